@@ -360,6 +360,7 @@ impl BackendDevice for CudaDevice {
                 let data = self.alloc_zeros::<F8E4M3>(elem_count)?;
                 CudaStorageSlice::F8E4M3(data)
             }
+            DType::F8E5M2 => Err(CudaError::UnsupportedDtype { dtype, op: "zeros" }).w()?,
             DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
                 return Err(
                     CudaError::InternalError("Dummy types not supported in CUDA backend").into(),
@@ -399,13 +400,16 @@ impl BackendDevice for CudaDevice {
                 curand.0.fill_with_uniform(&mut data).w()?;
                 CudaStorageSlice::F64(data)
             }
-            DType::F8E4M3 | DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
-                Err(CudaError::UnsupportedDtype {
-                    dtype,
-                    op: "rand_uniform",
-                })
-                .w()?
-            }
+            DType::F8E4M3
+            | DType::F8E5M2
+            | DType::F6E2M3
+            | DType::F6E3M2
+            | DType::F4
+            | DType::F8E8M0 => Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "rand_uniform",
+            })
+            .w()?,
         };
         let slice = if lo == 0. && up == 1.0 {
             slice
@@ -457,13 +461,16 @@ impl BackendDevice for CudaDevice {
                 curand.0.fill_with_normal(&mut data, mean, std).w()?;
                 CudaStorageSlice::F64(data)
             }
-            DType::F8E4M3 | DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
-                Err(CudaError::UnsupportedDtype {
-                    dtype,
-                    op: "rand_normal",
-                })
-                .w()?
-            }
+            DType::F8E4M3
+            | DType::F8E5M2
+            | DType::F6E2M3
+            | DType::F6E3M2
+            | DType::F4
+            | DType::F8E8M0 => Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "rand_normal",
+            })
+            .w()?,
         };
         Ok(CudaStorage {
             slice,
@@ -514,6 +521,11 @@ impl BackendDevice for CudaDevice {
                 let data = self.alloc::<F8E4M3>(elem_count)?;
                 CudaStorageSlice::F8E4M3(data)
             }
+            DType::F8E5M2 => Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "alloc_uninit",
+            })
+            .w()?,
             DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
                 return Err(
                     CudaError::InternalError("Dummy types not supported in CUDA backend").into(),
@@ -568,7 +580,8 @@ impl BackendDevice for CudaDevice {
                 let data = self.clone_htod(storage)?;
                 CudaStorageSlice::F8E4M3(data)
             }
-            CpuStorageRef::F4(_)
+            CpuStorageRef::F8E5M2(_)
+            | CpuStorageRef::F4(_)
             | CpuStorageRef::F6E2M3(_)
             | CpuStorageRef::F6E3M2(_)
             | CpuStorageRef::F8E8M0(_) => {
@@ -627,7 +640,8 @@ impl BackendDevice for CudaDevice {
                 let data = self.clone_htod(storage)?;
                 CudaStorageSlice::F8E4M3(data)
             }
-            CpuStorage::F4(_)
+            CpuStorage::F8E5M2(_)
+            | CpuStorage::F4(_)
             | CpuStorage::F6E2M3(_)
             | CpuStorage::F6E3M2(_)
             | CpuStorage::F8E8M0(_) => {
@@ -686,7 +700,8 @@ impl BackendDevice for CudaDevice {
                 let data = self.clone_htod(&storage)?;
                 CudaStorageSlice::F8E4M3(data)
             }
-            CpuStorage::F4(_)
+            CpuStorage::F8E5M2(_)
+            | CpuStorage::F4(_)
             | CpuStorage::F6E2M3(_)
             | CpuStorage::F6E3M2(_)
             | CpuStorage::F8E8M0(_) => {
@@ -48,6 +48,7 @@ impl crate::scalar::Scalar {
             Scalar::F16(v) => builder.arg(v),
             Scalar::BF16(v) => builder.arg(v),
             Scalar::F8E4M3(v) => builder.arg(v),
+            Scalar::F8E5M2(v) => builder.arg(v),
         };
     }
 }
@@ -1552,6 +1553,11 @@ impl BackendStorage for CudaStorage {
             DType::I16 | DType::I32 => {
                 return Err(CudaError::InternalError("i16,i32 dtypes are not supported").into())
             }
+            DType::F8E5M2 => Err(CudaError::UnsupportedDtype {
+                dtype,
+                op: "to_dtype",
+            })
+            .w()?,
             DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
                 return Err(
                     CudaError::InternalError("Dummy types not supported in CUDA backend").into(),
@@ -3,6 +3,7 @@
 #![allow(clippy::redundant_closure_call)]
 use crate::Tensor;
 use float8::F8E4M3 as f8e4m3;
+use float8::F8E5M2 as f8e5m2;
 use half::{bf16, f16};
 use num_traits::float::Float;
 
@@ -203,6 +204,10 @@ pub trait UnaryOpT {
     fn i32(v1: i32) -> i32;
     fn i64(v1: i64) -> i64;
     fn f8e4m3(v1: f8e4m3) -> f8e4m3;
+    // The f8e5m2 ops go through f32.
+    fn f8e5m2(v1: f8e5m2) -> f8e5m2 {
+        f8e5m2::from_f32(Self::f32(v1.to_f32()))
+    }
 
     // There is no very good way to represent optional function in traits so we go for an explicit
     // boolean flag to mark the function as existing.
@@ -230,6 +235,9 @@ pub trait BinaryOpT {
     fn i32(v1: i32, v2: i32) -> i32;
     fn i64(v1: i64, v2: i64) -> i64;
     fn f8e4m3(v1: f8e4m3, v2: f8e4m3) -> f8e4m3;
+    fn f8e5m2(v1: f8e5m2, v2: f8e5m2) -> f8e5m2 {
+        f8e5m2::from_f32(Self::f32(v1.to_f32(), v2.to_f32()))
+    }
 
     const BF16_VEC: bool = false;
     fn bf16_vec(_xs1: &[bf16], _xs2: &[bf16], _ys: &mut [bf16]) {}
@@ -1,5 +1,10 @@
+use super::iq_quants::{
+    IQ2S_GRID, IQ2XS_GRID, IQ2XXS_GRID, IQ3S_GRID, IQ3XXS_GRID, KSIGNS_IQ2XS, KVALUES_IQ4NL,
+};
 use super::k_quants::{
-    BlockQ2K, BlockQ3K, BlockQ4K, BlockQ4_0, BlockQ5K, BlockQ6K, BlockQ8K, BlockQ8_0, QK8_0, QK_K,
+    BlockIQ2S, BlockIQ2XS, BlockIQ2XXS, BlockIQ3S, BlockIQ3XXS, BlockIQ4NL, BlockIQ4XS, BlockQ2K,
+    BlockQ3K, BlockQ4K, BlockQ4_0, BlockQ5K, BlockQ6K, BlockQ8K, BlockQ8_0, BlockTQ1_0, BlockTQ2_0,
+    QK4_NL, QK8_0, QK_K,
 };
 use byteorder::{ByteOrder, LittleEndian};
 use half::f16;
@@ -667,3 +672,385 @@ pub(crate) fn vec_dot_q8k_q8k(n: usize, xs: &[BlockQ8K], ys: &[BlockQ8K]) -> f32
         hsum_float_8(acc)
     }
 }
+
+#[inline(always)]
+unsafe fn hsum_i32_8(a: __m256i) -> i32 {
+    let sum128 = _mm_add_epi32(_mm256_castsi256_si128(a), _mm256_extracti128_si256(a, 1));
+    let hi64 = _mm_unpackhi_epi64(sum128, sum128);
+    let sum64 = _mm_add_epi32(hi64, sum128);
+    let hi32 = _mm_shuffle_epi32(sum64, 0b10_11_00_01);
+    _mm_cvtsi128_si32(_mm_add_epi32(sum64, hi32))
+}
+
+// Expands 4 bytes of sign bits, one byte per group of 8 values, to a mask where the bytes of the
+// negative values are set to 0xff.
+#[inline(always)]
+unsafe fn sign_masks(signs: u32) -> __m256i {
+    let shuffle = _mm256_set_epi64x(
+        0x0303030303030303,
+        0x0202020202020202,
+        0x0101010101010101,
+        0,
+    );
+    let bits = _mm256_set1_epi64x(0x8040201008040201u64 as i64);
+    let signs = _mm256_shuffle_epi8(_mm256_set1_epi32(signs as i32), shuffle);
+    _mm256_cmpeq_epi8(_mm256_and_si256(signs, bits), bits)
+}
+
+// The pairwise sums of the products between the unsigned values `x` negated where `neg` is set
+// and `y`. The negation is applied to the products rather than to `y` as q8k values can be -128.
+#[inline(always)]
+unsafe fn signed_dot(x: __m256i, neg: __m256i, y: __m256i) -> __m256i {
+    let pos = _mm256_maddubs_epi16(_mm256_andnot_si256(neg, x), y);
+    let neg = _mm256_maddubs_epi16(_mm256_and_si256(neg, x), y);
+    _mm256_sub_epi16(pos, neg)
+}
+
+// Sums the i16 pairs of `dot`, the lower and upper 128 bits being multiplied by `ls1` and `ls2`.
+#[inline(always)]
+unsafe fn scaled_sum(dot: __m256i, ls1: i16, ls2: i16) -> __m256i {
+    let scales = mm256_set_m128i(_mm_set1_epi16(ls2), _mm_set1_epi16(ls1));
+    _mm256_madd_epi16(dot, scales)
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq2xxs_q8k(n: usize, xs: &[BlockIQ2XXS], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq2xxs_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = _mm256_setzero_si256();
+            for (qs, q8) in x.qs.chunks_exact(4).zip(y.qs.chunks_exact(32)) {
+                let aux0 = qs[0] as u32 | (qs[1] as u32) << 16;
+                let aux1 = qs[2] as u32 | (qs[3] as u32) << 16;
+                let grid = |l: usize| IQ2XXS_GRID[((aux0 >> (8 * l)) & 255) as usize] as i64;
+                let grid = _mm256_set_epi64x(grid(3), grid(2), grid(1), grid(0));
+                let signs = (0..4).fold(0u32, |acc, l| {
+                    acc | (KSIGNS_IQ2XS[((aux1 >> (7 * l)) & 127) as usize] as u32) << (8 * l)
+                });
+                let q8 = _mm256_loadu_si256(q8.as_ptr() as *const __m256i);
+                let dot = signed_dot(grid, sign_masks(signs), q8);
+                let ls = 2 * (aux1 >> 28) as i16 + 1;
+                sumi = _mm256_add_epi32(sumi, scaled_sum(dot, ls, ls));
+            }
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d);
+            acc = _mm256_fmadd_ps(d, _mm256_cvtepi32_ps(sumi), acc);
+        }
+        0.125 * hsum_float_8(acc)
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq2xs_q8k(n: usize, xs: &[BlockIQ2XS], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq2xs_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = _mm256_setzero_si256();
+            for (ib32, (qs, q8)) in x.qs.chunks_exact(4).zip(y.qs.chunks_exact(32)).enumerate() {
+                let grid = |l: usize| IQ2XS_GRID[(qs[l] & 511) as usize] as i64;
+                let grid = _mm256_set_epi64x(grid(3), grid(2), grid(1), grid(0));
+                let signs = (0..4).fold(0u32, |acc, l| {
+                    acc | (KSIGNS_IQ2XS[(qs[l] >> 9) as usize] as u32) << (8 * l)
+                });
+                let q8 = _mm256_loadu_si256(q8.as_ptr() as *const __m256i);
+                let dot = signed_dot(grid, sign_masks(signs), q8);
+                let ls1 = 2 * (x.scales[ib32] & 0xf) as i16 + 1;
+                let ls2 = 2 * (x.scales[ib32] >> 4) as i16 + 1;
+                sumi = _mm256_add_epi32(sumi, scaled_sum(dot, ls1, ls2));
+            }
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d);
+            acc = _mm256_fmadd_ps(d, _mm256_cvtepi32_ps(sumi), acc);
+        }
+        0.125 * hsum_float_8(acc)
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq2s_q8k(n: usize, xs: &[BlockIQ2S], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq2s_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (qs, signs) = x.qs.split_at(QK_K / 8);
+            let mut sumi = _mm256_setzero_si256();
+            for (ib32, q8) in y.qs.chunks_exact(32).enumerate() {
+                let qh = x.qh[ib32] as usize;
+                let grid = |l: usize| {
+                    let index = qs[4 * ib32 + l] as usize | (qh << (8 - 2 * l)) & 0x300;
+                    IQ2S_GRID[index] as i64
+                };
+                let grid = _mm256_set_epi64x(grid(3), grid(2), grid(1), grid(0));
+                let signs = LittleEndian::read_u32(&signs[4 * ib32..]);
+                let q8 = _mm256_loadu_si256(q8.as_ptr() as *const __m256i);
+                let dot = signed_dot(grid, sign_masks(signs), q8);
+                let ls1 = 2 * (x.scales[ib32] & 0xf) as i16 + 1;
+                let ls2 = 2 * (x.scales[ib32] >> 4) as i16 + 1;
+                sumi = _mm256_add_epi32(sumi, scaled_sum(dot, ls1, ls2));
+            }
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d);
+            acc = _mm256_fmadd_ps(d, _mm256_cvtepi32_ps(sumi), acc);
+        }
+        0.125 * hsum_float_8(acc)
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq3xxs_q8k(n: usize, xs: &[BlockIQ3XXS], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq3xxs_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (qs, scales_and_signs) = x.qs.split_at(QK_K / 4);
+            let mut sumi = _mm256_setzero_si256();
+            for (ib32, (qs, q8)) in qs.chunks_exact(8).zip(y.qs.chunks_exact(32)).enumerate() {
+                let aux = LittleEndian::read_u32(&scales_and_signs[4 * ib32..]);
+                let grid = |l: usize| IQ3XXS_GRID[qs[l] as usize] as i32;
+                let grid = _mm256_set_epi32(
+                    grid(7),
+                    grid(6),
+                    grid(5),
+                    grid(4),
+                    grid(3),
+                    grid(2),
+                    grid(1),
+                    grid(0),
+                );
+                let signs = (0..4).fold(0u32, |acc, l| {
+                    acc | (KSIGNS_IQ2XS[((aux >> (7 * l)) & 127) as usize] as u32) << (8 * l)
+                });
+                let q8 = _mm256_loadu_si256(q8.as_ptr() as *const __m256i);
+                let dot = signed_dot(grid, sign_masks(signs), q8);
+                let ls = 2 * (aux >> 28) as i16 + 1;
+                sumi = _mm256_add_epi32(sumi, scaled_sum(dot, ls, ls));
+            }
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d);
+            acc = _mm256_fmadd_ps(d, _mm256_cvtepi32_ps(sumi), acc);
+        }
+        0.25 * hsum_float_8(acc)
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq3s_q8k(n: usize, xs: &[BlockIQ3S], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq3s_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = _mm256_setzero_si256();
+            for (ib32, (qs, q8)) in x.qs.chunks_exact(8).zip(y.qs.chunks_exact(32)).enumerate() {
+                let qh = x.qh[ib32] as usize;
+                let grid = |k: usize| IQ3S_GRID[qs[k] as usize | (qh << (8 - k)) & 256] as i32;
+                let grid = _mm256_set_epi32(
+                    grid(7),
+                    grid(6),
+                    grid(5),
+                    grid(4),
+                    grid(3),
+                    grid(2),
+                    grid(1),
+                    grid(0),
+                );
+                let signs = LittleEndian::read_u32(&x.signs[4 * ib32..]);
+                let q8 = _mm256_loadu_si256(q8.as_ptr() as *const __m256i);
+                let dot = signed_dot(grid, sign_masks(signs), q8);
+                let ls = 2 * ((x.scales[ib32 / 2] >> (4 * (ib32 % 2))) & 0xf) as i16 + 1;
+                sumi = _mm256_add_epi32(sumi, scaled_sum(dot, ls, ls));
+            }
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d);
+            acc = _mm256_fmadd_ps(d, _mm256_cvtepi32_ps(sumi), acc);
+        }
+        hsum_float_8(acc)
+    }
+}
+
+// Maps the 32 nibbles of `qs`, low nibbles first, to the IQ4 non-linear values.
+#[inline(always)]
+unsafe fn iq4_values(qs: *const u8) -> __m256i {
+    let values = _mm_loadu_si128(KVALUES_IQ4NL.as_ptr() as *const __m128i);
+    let m4 = _mm_set1_epi8(0xf);
+    let q = _mm_loadu_si128(qs as *const __m128i);
+    let lo = _mm_shuffle_epi8(values, _mm_and_si128(q, m4));
+    let hi = _mm_shuffle_epi8(values, _mm_and_si128(_mm_srli_epi16(q, 4), m4));
+    mm256_set_m128i(hi, lo)
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4nl_q8_0(n: usize, xs: &[BlockIQ4NL], ys: &[BlockQ8_0]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK4_NL),
+        "vec_dot_iq4nl_q8_0: {n} is not divisible by {QK4_NL}"
+    );
+    unsafe {
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d.to_f32());
+            let bx = iq4_values(x.qs.as_ptr());
+            let by = _mm256_loadu_si256(y.qs.as_ptr() as *const __m256i);
+            acc = _mm256_fmadd_ps(d, mul_sum_i8_pairs_float(bx, by), acc);
+        }
+        hsum_float_8(acc)
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4xs_q8k(n: usize, xs: &[BlockIQ4XS], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq4xs_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut acc = _mm256_setzero_ps();
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = _mm256_setzero_si256();
+            for (ib, (qs, q8)) in x.qs.chunks_exact(16).zip(y.qs.chunks_exact(32)).enumerate() {
+                let values = iq4_values(qs.as_ptr());
+                let neg = _mm256_cmpgt_epi8(_mm256_setzero_si256(), values);
+                let q8 = _mm256_loadu_si256(q8.as_ptr() as *const __m256i);
+                let dot = signed_dot(_mm256_abs_epi8(values), neg, q8);
+                let ls = x.scale(ib) as i16 - 32;
+                sumi = _mm256_add_epi32(sumi, scaled_sum(dot, ls, ls));
+            }
+            let d = _mm256_set1_ps(x.d.to_f32() * y.d);
+            acc = _mm256_fmadd_ps(d, _mm256_cvtepi32_ps(sumi), acc);
+        }
+        hsum_float_8(acc)
+    }
+}
+
+// Extracts a trit from each byte of `q`, `pow3` selects the trit in each 16 bits lane, see
+// `BlockTQ1_0::trits`.
+#[inline(always)]
+unsafe fn tq1_trits(q: __m256i, pow3: __m256i) -> __m256i {
+    let low_mask = _mm256_set1_epi16(0xff);
+    let three = _mm256_set1_epi16(3);
+    let even = _mm256_and_si256(_mm256_mullo_epi16(q, pow3), low_mask);
+    let odd = _mm256_and_si256(_mm256_mullo_epi16(_mm256_srli_epi16(q, 8), pow3), low_mask);
+    let even = _mm256_srli_epi16(_mm256_mullo_epi16(even, three), 8);
+    let odd = _mm256_srli_epi16(_mm256_mullo_epi16(odd, three), 8);
+    _mm256_or_si256(even, _mm256_slli_epi16(odd, 8))
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_tq1_0_q8k(n: usize, xs: &[BlockTQ1_0], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_tq1_0_q8k: {n} is not divisible by {QK_K}"
+    );
+    const POW3: [u8; 5] = [1, 3, 9, 27, 81];
+    unsafe {
+        let ones = _mm256_set1_epi16(1);
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            // The trits are in {0, 1, 2}, the sum of the q8 values is subtracted at the end.
+            let mut sumi = _mm256_setzero_si256();
+            let mut dot = |trits: __m256i, q8: __m256i| {
+                let dot = _mm256_madd_epi16(_mm256_maddubs_epi16(trits, q8), ones);
+                sumi = _mm256_add_epi32(sumi, dot);
+            };
+            let q = _mm256_loadu_si256(x.qs.as_ptr() as *const __m256i);
+            for (n, &p) in POW3.iter().enumerate() {
+                let q8 = _mm256_loadu_si256(y.qs.as_ptr().add(32 * n) as *const __m256i);
+                dot(tq1_trits(q, _mm256_set1_epi16(p as i16)), q8);
+            }
+            // The 16 bytes of the second part hold two trits per 32 values.
+            let q = _mm_loadu_si128(x.qs.as_ptr().add(32) as *const __m128i);
+            let q = mm256_set_m128i(q, q);
+            for n in [0, 2] {
+                let pow3 = mm256_set_m128i(
+                    _mm_set1_epi16(POW3[n + 1] as i16),
+                    _mm_set1_epi16(POW3[n] as i16),
+                );
+                let q8 = _mm256_loadu_si256(y.qs.as_ptr().add(160 + 16 * n) as *const __m256i);
+                dot(tq1_trits(q, pow3), q8);
+            }
+            let q8 = _mm_loadu_si128(y.qs.as_ptr().add(224) as *const __m128i);
+            let q8 = mm256_set_m128i(_mm_setzero_si128(), q8);
+            dot(tq1_trits(q, _mm256_set1_epi16(POW3[4] as i16)), q8);
+            let mut sumi = hsum_i32_8(sumi);
+            for (n, &p) in POW3[..4].iter().enumerate() {
+                for (j, &q) in x.qh.iter().enumerate() {
+                    let trit = (q.wrapping_mul(p) as u16 * 3) >> 8;
+                    sumi += trit as i32 * y.qs[240 + 4 * n + j] as i32;
+                }
+            }
+            sumi -= y.bsums.iter().map(|&b| b as i32).sum::<i32>();
+            sumf += x.d.to_f32() * y.d * sumi as f32;
+        }
+        sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_tq2_0_q8k(n: usize, xs: &[BlockTQ2_0], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_tq2_0_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let ones = _mm256_set1_epi16(1);
+        let m3 = _mm256_set1_epi8(3);
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            // The values are stored as {0, 1, 2}, the sum of the q8 values is subtracted at the end.
+            let mut sumi = _mm256_setzero_si256();
+            for (qs, q8) in x.qs.chunks_exact(32).zip(y.qs.chunks_exact(128)) {
+                let q = _mm256_loadu_si256(qs.as_ptr() as *const __m256i);
+                for (l, q8) in q8.chunks_exact(32).enumerate() {
+                    let shift = _mm_cvtsi32_si128(2 * l as i32);
+                    let values = _mm256_and_si256(_mm256_srl_epi16(q, shift), m3);
+                    let q8 = _mm256_loadu_si256(q8.as_ptr() as *const __m256i);
+                    let dot = _mm256_madd_epi16(_mm256_maddubs_epi16(values, q8), ones);
+                    sumi = _mm256_add_epi32(sumi, dot);
+                }
+            }
+            let sumi = hsum_i32_8(sumi) - y.bsums.iter().map(|&b| b as i32).sum::<i32>();
+            sumf += x.d.to_f32() * y.d * sumi as f32;
+        }
+        sumf
+    }
+}
+
+// The dot product of int8 vectors accumulated in int32. The sign of `xs` is moved to `ys` so
+// that the unsigned x signed multiply-add instructions can be used, `ys` must not contain -128.
+#[inline(always)]
+pub(crate) fn vec_dot_i8(xs: &[i8], ys: &[i8]) -> i32 {
+    debug_assert_eq!(xs.len(), ys.len());
+    let n = xs.len() / 32 * 32;
+    let sumi = unsafe {
+        let mut acc = _mm256_setzero_si256();
+        for i in (0..n).step_by(32) {
+            let x = _mm256_loadu_si256(xs.as_ptr().add(i) as *const __m256i);
+            let y = _mm256_loadu_si256(ys.as_ptr().add(i) as *const __m256i);
+            let ax = _mm256_sign_epi8(x, x);
+            let sy = _mm256_sign_epi8(y, x);
+            #[cfg(target_feature = "avxvnni")]
+            {
+                acc = _mm256_dpbusd_avx_epi32(acc, ax, sy);
+            }
+            #[cfg(not(target_feature = "avxvnni"))]
+            {
+                let dot = _mm256_maddubs_epi16(ax, sy);
+                acc = _mm256_add_epi32(acc, _mm256_madd_epi16(_mm256_set1_epi16(1), dot));
+            }
+        }
+        hsum_i32_8(acc)
+    };
+    let tail = xs[n..].iter().zip(ys[n..].iter());
+    sumi + tail.map(|(&x, &y)| x as i32 * y as i32).sum::<i32>()
+}
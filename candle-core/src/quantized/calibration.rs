@@ -0,0 +1,155 @@
+//! Importance matrix calibration.
+//!
+//! A [`Calibration`] accumulates, for each hooked weight, the squared values of the input
+//! columns of the matmuls using it. Running a model over some calibration text then gives the
+//! importance matrix that [`super::QTensor::quantize_imatrix`] uses, and that can be written in
+//! the llama.cpp format with [`super::imatrix_file::save_imatrix`].
+//!
+//! ```ignore
+//! let calibration = Calibration::new();
+//! let model = calibration.load(|| ModelWeights::from_gguf(content, &mut file, &Device::Cpu))?;
+//! for chunk in chunks {
+//!     model.forward(&chunk, 0)?;
+//! }
+//! let imatrix = calibration.imatrix();
+//! ```
+//!
+//! The cpu tensors loaded from gguf files within [`Calibration::load`] get a [`Hook`] named
+//! after the tensor, and so do the `candle_nn::Linear` layers built there using their weight
+//! path. The `QMatMul` layers created from hooked tensors do not dequantize their weights so
+//! that the inputs get recorded, even the `f16` and `f32` ones. Hooks can also be attached
+//! explicitly with [`super::QTensor::with_calibration`].
+use super::imatrix_file::ImatrixEntry;
+use crate::{DType, Result, Tensor, D};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    static LOADING: RefCell<Option<Calibration>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+struct Stats {
+    ncall: usize,
+    n_rows: usize,
+    sums: Vec<f64>,
+}
+
+/// The statistics of a calibration run, shared by the hooks created from it.
+#[derive(Clone, Default)]
+pub struct Calibration {
+    stats: Arc<Mutex<HashMap<String, Stats>>>,
+}
+
+impl std::fmt::Debug for Calibration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let n_hooks = self.stats.lock().unwrap().len();
+        write!(f, "Calibration[hooks: {n_hooks}]")
+    }
+}
+
+impl Calibration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f` with this calibration hooking the weights loaded or built on the current thread.
+    pub fn load<T>(&self, f: impl FnOnce() -> T) -> T {
+        struct Restore(Option<Calibration>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                LOADING.with(|l| *l.borrow_mut() = self.0.take())
+            }
+        }
+        let _restore = Restore(LOADING.with(|l| l.borrow_mut().replace(self.clone())));
+        f()
+    }
+
+    /// Registers the weight `name` and returns the hook recording its inputs.
+    pub fn hook(&self, name: &str) -> Hook {
+        self.stats
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default();
+        Hook {
+            name: Arc::from(name),
+            calibration: self.clone(),
+        }
+    }
+
+    /// Returns the importance matrix collected so far, indexed by weight name. The weights
+    /// that have not been used are skipped.
+    pub fn imatrix(&self) -> BTreeMap<String, ImatrixEntry> {
+        self.stats
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, stats)| stats.ncall > 0)
+            .map(|(name, stats)| {
+                let n_rows = stats.n_rows.max(1) as f64;
+                let values = stats.sums.iter().map(|&v| (v / n_rows) as f32).collect();
+                let entry = ImatrixEntry {
+                    ncall: stats.ncall,
+                    values,
+                };
+                (name.clone(), entry)
+            })
+            .collect()
+    }
+}
+
+/// Returns a hook for the weight `name` if it is loaded within [`Calibration::load`].
+pub fn loading_hook(name: &str) -> Option<Hook> {
+    LOADING.with(|l| l.borrow().as_ref().map(|c| c.hook(name)))
+}
+
+/// Records the inputs of the matmuls with a given weight.
+#[derive(Clone)]
+pub struct Hook {
+    name: Arc<str>,
+    calibration: Calibration,
+}
+
+impl std::fmt::Debug for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Hook[{}]", self.name)
+    }
+}
+
+impl Hook {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Accumulates the squared values of the columns of `xs`, the input of a matmul with the
+    /// hooked weight.
+    pub fn record(&self, xs: &Tensor) -> Result<()> {
+        let n_cols = xs.dim(D::Minus1)?;
+        let n_rows = xs.elem_count() / n_cols.max(1);
+        let sums = xs
+            .to_dtype(DType::F32)?
+            .reshape((n_rows, n_cols))?
+            .sqr()?
+            .sum(0)?
+            .to_vec1::<f32>()?;
+        let mut stats = self.calibration.stats.lock().unwrap();
+        let stats = stats.entry(self.name.to_string()).or_default();
+        if stats.sums.is_empty() {
+            stats.sums = vec![0f64; n_cols]
+        } else if stats.sums.len() != n_cols {
+            crate::bail!(
+                "inconsistent input size for imatrix calibration of {}, {} vs {n_cols}",
+                self.name,
+                stats.sums.len()
+            )
+        }
+        for (acc, &v) in stats.sums.iter_mut().zip(sums.iter()) {
+            *acc += v as f64
+        }
+        stats.ncall += 1;
+        stats.n_rows += n_rows;
+        Ok(())
+    }
+}
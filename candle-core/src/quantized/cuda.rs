@@ -595,6 +595,17 @@ impl QCudaStorage {
             GgmlDType::Q5K => deq::<crate::quantized::BlockQ5K>(&buffer, block_len, &mut out),
             GgmlDType::Q6K => deq::<crate::quantized::BlockQ6K>(&buffer, block_len, &mut out),
             GgmlDType::Q8K => deq::<crate::quantized::BlockQ8K>(&buffer, block_len, &mut out),
+            GgmlDType::IQ2XXS => deq::<crate::quantized::BlockIQ2XXS>(&buffer, block_len, &mut out),
+            GgmlDType::IQ2XS => deq::<crate::quantized::BlockIQ2XS>(&buffer, block_len, &mut out),
+            GgmlDType::IQ3XXS => deq::<crate::quantized::BlockIQ3XXS>(&buffer, block_len, &mut out),
+            GgmlDType::IQ4NL => deq::<crate::quantized::BlockIQ4NL>(&buffer, block_len, &mut out),
+            GgmlDType::IQ3S => deq::<crate::quantized::BlockIQ3S>(&buffer, block_len, &mut out),
+            GgmlDType::IQ2S => deq::<crate::quantized::BlockIQ2S>(&buffer, block_len, &mut out),
+            GgmlDType::IQ4XS => deq::<crate::quantized::BlockIQ4XS>(&buffer, block_len, &mut out),
+            GgmlDType::IQ1S => deq::<crate::quantized::BlockIQ1S>(&buffer, block_len, &mut out),
+            GgmlDType::IQ1M => deq::<crate::quantized::BlockIQ1M>(&buffer, block_len, &mut out),
+            GgmlDType::TQ1_0 => deq::<crate::quantized::BlockTQ1_0>(&buffer, block_len, &mut out),
+            GgmlDType::TQ2_0 => deq::<crate::quantized::BlockTQ2_0>(&buffer, block_len, &mut out),
         }
 
         self.device
@@ -667,7 +678,7 @@ impl QCudaStorage {
         let mut qcpu_storage = crate::Device::Cpu.qzeros(src_len, self.dtype)?;
 
         if let QStorage::Cpu(storage) = &mut qcpu_storage {
-            storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix_weights, n_per_row);
+            storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix_weights, n_per_row)?;
         } else {
             unreachable!()
         }
@@ -691,7 +702,7 @@ impl QCudaStorage {
         let mut qcpu_storage = crate::Device::Cpu.qzeros(src_len, self.dtype)?;
 
         if let QStorage::Cpu(storage) = &mut qcpu_storage {
-            storage.from_float(src.as_slice::<f32>()?);
+            storage.from_float(src.as_slice::<f32>()?)?;
         } else {
             unreachable!()
         }
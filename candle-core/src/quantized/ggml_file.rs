@@ -184,7 +184,45 @@ pub fn qtensor_from_ggml(
         GgmlDType::Q6K => {
             from_raw_data::<k_quants::BlockQ6K>(raw_data, size_in_bytes, dims, device)
         }
-        _ => crate::bail!("quantized type {ggml_dtype:?} is not supported yet"),
+        GgmlDType::Q8_1 => {
+            from_raw_data::<k_quants::BlockQ8_1>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::Q8K => {
+            from_raw_data::<k_quants::BlockQ8K>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ2XXS => {
+            from_raw_data::<k_quants::BlockIQ2XXS>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ2XS => {
+            from_raw_data::<k_quants::BlockIQ2XS>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ2S => {
+            from_raw_data::<k_quants::BlockIQ2S>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ3XXS => {
+            from_raw_data::<k_quants::BlockIQ3XXS>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ3S => {
+            from_raw_data::<k_quants::BlockIQ3S>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ4NL => {
+            from_raw_data::<k_quants::BlockIQ4NL>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ4XS => {
+            from_raw_data::<k_quants::BlockIQ4XS>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ1S => {
+            from_raw_data::<k_quants::BlockIQ1S>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::IQ1M => {
+            from_raw_data::<k_quants::BlockIQ1M>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::TQ1_0 => {
+            from_raw_data::<k_quants::BlockTQ1_0>(raw_data, size_in_bytes, dims, device)
+        }
+        GgmlDType::TQ2_0 => {
+            from_raw_data::<k_quants::BlockTQ2_0>(raw_data, size_in_bytes, dims, device)
+        }
     }
 }
 
@@ -2,13 +2,16 @@
 //!
 //! Spec: https://github.com/ggml-org/ggml/blob/master/docs/gguf.md  
 
-use super::{GgmlDType, QTensor};
-use crate::{Context, Device, Result};
+use super::{GgmlDType, QStorage, QTensor};
+use crate::{Context, Device, Error, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::collections::HashMap;
 
 pub const DEFAULT_ALIGNMENT: u64 = 32;
 
+// The largest alignment of the block types, the memory mapped tensors have to be aligned on it.
+const MMAP_ALIGNMENT: usize = 8;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Magic {
     Gguf,
@@ -55,12 +58,7 @@ pub struct TensorInfo {
 }
 
 impl TensorInfo {
-    pub fn read<R: std::io::Seek + std::io::Read>(
-        &self,
-        reader: &mut R,
-        tensor_data_offset: u64,
-        device: &Device,
-    ) -> Result<QTensor> {
+    fn size_in_bytes(&self) -> Result<usize> {
         let tensor_elems = self.shape.elem_count();
         let block_size = self.ggml_dtype.block_size();
         if !tensor_elems.is_multiple_of(block_size) {
@@ -68,7 +66,16 @@ impl TensorInfo {
             "the number of elements {tensor_elems} is not divisible by the block size {block_size}"
         )
         }
-        let size_in_bytes = tensor_elems / block_size * self.ggml_dtype.type_size();
+        Ok(tensor_elems / block_size * self.ggml_dtype.type_size())
+    }
+
+    pub fn read<R: std::io::Seek + std::io::Read>(
+        &self,
+        reader: &mut R,
+        tensor_data_offset: u64,
+        device: &Device,
+    ) -> Result<QTensor> {
+        let size_in_bytes = self.size_in_bytes()?;
         let mut raw_data = vec![0u8; size_in_bytes];
         reader.seek(std::io::SeekFrom::Start(tensor_data_offset + self.offset))?;
         reader.read_exact(&mut raw_data)?;
@@ -477,7 +484,91 @@ impl Content {
             Some(tensor_info) => tensor_info,
             None => crate::bail!("cannot find tensor info for {name}"),
         };
-        tensor_info.read(reader, self.tensor_data_offset, device)
+        let tensor = tensor_info.read(reader, self.tensor_data_offset, device)?;
+        Ok(hook_loaded(name, tensor))
+    }
+}
+
+/// A memory mapped gguf file.
+///
+/// The tensors loaded on the cpu borrow their data from the mapping rather than copying it, so
+/// loading is close to instant and the pages are shared between the processes using the same
+/// file. The tensors loaded on other devices are uploaded directly from the mapping.
+pub struct MmapedFile {
+    content: Content,
+    mmap: std::sync::Arc<memmap2::Mmap>,
+}
+
+impl MmapedFile {
+    /// Maps the file and reads its metadata and tensor infos.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`memmap2::MmapOptions`].
+    pub unsafe fn new<P: AsRef<std::path::Path>>(p: P) -> Result<Self> {
+        let p = p.as_ref();
+        let file = std::fs::File::open(p).map_err(|e| Error::from(e).with_path(p))?;
+        let mmap = memmap2::MmapOptions::new()
+            .map(&file)
+            .map_err(|e| Error::from(e).with_path(p))?;
+        let content =
+            Content::read(&mut std::io::Cursor::new(&mmap[..])).map_err(|e| e.with_path(p))?;
+        Ok(Self {
+            content,
+            mmap: std::sync::Arc::new(mmap),
+        })
+    }
+
+    pub fn content(&self) -> &Content {
+        &self.content
+    }
+
+    /// A reader over the whole file, for the model loaders taking a [`Content`] and a reader.
+    /// The tensors read this way are copied out of the mapping.
+    pub fn reader(&self) -> std::io::Cursor<&[u8]> {
+        std::io::Cursor::new(&self.mmap[..])
+    }
+
+    pub fn tensor(&self, name: &str, device: &Device) -> Result<QTensor> {
+        let tensor_info = match self.content.tensor_infos.get(name) {
+            Some(tensor_info) => tensor_info,
+            None => crate::bail!("cannot find tensor info for {name}"),
+        };
+        let size_in_bytes = tensor_info.size_in_bytes()?;
+        let offset = (self.content.tensor_data_offset + tensor_info.offset) as usize;
+        if offset + size_in_bytes > self.mmap.len() {
+            crate::bail!("tensor {name} is out of the file bounds")
+        }
+        // The gguf alignment is a multiple of the block alignment, unless the file sets a small
+        // `general.alignment` in which case the data is copied.
+        let aligned = (self.mmap.as_ptr() as usize + offset).is_multiple_of(MMAP_ALIGNMENT);
+        let tensor = if aligned {
+            let dtype = tensor_info.ggml_dtype;
+            let storage = match device {
+                Device::Cpu => {
+                    QStorage::Cpu(dtype.cpu_mmaped(self.mmap.clone(), offset, size_in_bytes)?)
+                }
+                device => {
+                    let data = &self.mmap[offset..offset + size_in_bytes];
+                    QStorage::from_data(std::borrow::Cow::Borrowed(data), device, dtype)?
+                }
+            };
+            QTensor::new(storage, tensor_info.shape.clone())?
+        } else {
+            tensor_info.read(&mut self.reader(), self.content.tensor_data_offset, device)?
+        };
+        Ok(hook_loaded(name, tensor))
+    }
+}
+
+// Attaches the hook of the calibration the tensor is loaded for, if any.
+fn hook_loaded(name: &str, tensor: QTensor) -> QTensor {
+    if !tensor.device().is_cpu() {
+        return tensor;
+    }
+    match super::calibration::loading_hook(name) {
+        Some(hook) => tensor.with_calibration(hook),
+        None => tensor,
     }
 }
 
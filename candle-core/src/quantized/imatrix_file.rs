@@ -1,9 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{Cursor, Read};
+use std::io::{BufWriter, Cursor, Read, Write};
 use std::path::Path;
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::Result;
 
@@ -83,3 +83,46 @@ pub fn load_imatrix<P: AsRef<Path>>(fname: P) -> Result<HashMap<String, Vec<f32>
 
     Ok(all_data)
 }
+
+/// An importance matrix entry, `values` holds the mean squared activation of each column and
+/// `ncall` the number of matmul calls these statistics were gathered over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImatrixEntry {
+    pub ncall: usize,
+    pub values: Vec<f32>,
+}
+
+/// Writes an imatrix file using the llama.cpp format, this can be read back by [`load_imatrix`].
+///
+/// `n_chunks` is the number of chunks of the calibration `dataset` that have been processed.
+pub fn save_imatrix<P: AsRef<Path>>(
+    fname: P,
+    entries: &BTreeMap<String, ImatrixEntry>,
+    n_chunks: usize,
+    dataset: &str,
+) -> Result<()> {
+    if entries.is_empty() {
+        crate::bail!(
+            "no imatrix entries to write to {}",
+            fname.as_ref().display()
+        );
+    }
+    let mut w = BufWriter::new(File::create(fname.as_ref())?);
+    w.write_i32::<LittleEndian>(entries.len() as i32)?;
+    for (name, entry) in entries.iter() {
+        w.write_i32::<LittleEndian>(name.len() as i32)?;
+        w.write_all(name.as_bytes())?;
+        w.write_i32::<LittleEndian>(entry.ncall as i32)?;
+        w.write_i32::<LittleEndian>(entry.values.len() as i32)?;
+        // The file stores the sums over the calls, these get divided by ncall when loading.
+        let ncall = entry.ncall.max(1) as f32;
+        for &v in entry.values.iter() {
+            w.write_f32::<LittleEndian>(v * ncall)?;
+        }
+    }
+    w.write_i32::<LittleEndian>(n_chunks as i32)?;
+    w.write_i32::<LittleEndian>(dataset.len() as i32)?;
+    w.write_all(dataset.as_bytes())?;
+    w.flush()?;
+    Ok(())
+}
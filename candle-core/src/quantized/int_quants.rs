@@ -0,0 +1,317 @@
+//! Per-channel and per-group integer quantization.
+//!
+//! The ggml block formats use a fixed block size which suits the weights of large language
+//! models. The linear and convolution layers of encoders and CNNs are rather quantized with one
+//! scale per output channel, or per group of input channels, and optionally a zero point. The
+//! first dimension of a weight is the output channel dimension, the other dimensions are
+//! flattened as the input channel dimension so that convolution kernels can be quantized too.
+//!
+//! The matmul quantizes the activations dynamically to int8, with one scale per row, and
+//! accumulates the int8 products in int32.
+use crate::{DType, Device, Result, Shape, Tensor};
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntBits {
+    Int8,
+    /// Two values per byte, the low nibble first.
+    Int4,
+}
+
+impl IntBits {
+    // The range of the quantized values, the symmetric int8 range excludes -128 so that the
+    // dot products can use the sign trick of the simd implementations.
+    fn range(&self, symmetric: bool) -> (i32, i32) {
+        match (self, symmetric) {
+            (Self::Int8, true) => (-127, 127),
+            (Self::Int8, false) => (-128, 127),
+            (Self::Int4, true) => (-7, 7),
+            (Self::Int4, false) => (-8, 7),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntQuantConfig {
+    pub bits: IntBits,
+    /// The number of input channels sharing a scale, `None` for one scale per output channel.
+    pub group_size: Option<usize>,
+    /// Symmetric quantization does not use zero points.
+    pub symmetric: bool,
+}
+
+impl IntQuantConfig {
+    /// Symmetric per-channel int8, the usual scheme for CNN and encoder weights.
+    pub fn int8_per_channel() -> Self {
+        Self {
+            bits: IntBits::Int8,
+            group_size: None,
+            symmetric: true,
+        }
+    }
+
+    /// Symmetric int4 with one scale per group of `group_size` input channels.
+    pub fn int4_per_group(group_size: usize) -> Self {
+        Self {
+            bits: IntBits::Int4,
+            group_size: Some(group_size),
+            symmetric: true,
+        }
+    }
+}
+
+/// A weight quantized per channel or per group, stored on the cpu.
+#[derive(Debug, Clone)]
+pub struct IntQTensor {
+    // The int8 values, or the packed int4 values.
+    data: Vec<u8>,
+    // One scale, and zero point for asymmetric quantization, per output channel and group.
+    scales: Vec<f32>,
+    zero_points: Option<Vec<i8>>,
+    config: IntQuantConfig,
+    shape: Shape,
+}
+
+// The dot product of int8 vectors accumulated in int32, `ys` must not contain -128.
+#[allow(unreachable_code)]
+#[inline(always)]
+fn vec_dot_i8(xs: &[i8], ys: &[i8]) -> i32 {
+    #[cfg(target_feature = "avx2")]
+    return super::avx::vec_dot_i8(xs, ys);
+
+    #[cfg(target_feature = "neon")]
+    return super::neon::vec_dot_i8(xs, ys);
+
+    let products = xs.iter().zip(ys.iter()).map(|(&x, &y)| x as i32 * y as i32);
+    products.sum()
+}
+
+fn unpack_int4(packed: &[u8], dst: &mut [i8]) {
+    for (dst, &p) in dst.chunks_exact_mut(2).zip(packed.iter()) {
+        // Sign extend the nibbles.
+        dst[0] = ((p << 4) as i8) >> 4;
+        dst[1] = (p as i8) >> 4;
+    }
+}
+
+impl IntQTensor {
+    /// Quantizes a weight whose first dimension is the output channel dimension.
+    pub fn quantize(w: &Tensor, config: IntQuantConfig) -> Result<Self> {
+        let shape = w.shape().clone();
+        if shape.rank() < 2 {
+            crate::bail!("cannot quantize a tensor with shape {shape:?} per channel")
+        }
+        let n = shape.dims()[0];
+        let k = shape.elem_count() / n;
+        let group_size = config.group_size.unwrap_or(k);
+        if group_size == 0 || !k.is_multiple_of(group_size) {
+            crate::bail!("the group size {group_size} does not divide the {k} input channels")
+        }
+        if config.bits == IntBits::Int4 && !group_size.is_multiple_of(2) {
+            crate::bail!("int4 quantization requires an even group size, got {group_size}")
+        }
+        let w = w
+            .to_device(&Device::Cpu)?
+            .to_dtype(DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        let (qmin, qmax) = config.bits.range(config.symmetric);
+        let n_groups = n * k / group_size;
+        let mut q = vec![0i8; n * k];
+        let mut scales = Vec::with_capacity(n_groups);
+        let mut zero_points = Vec::with_capacity(n_groups);
+        for (w, q) in w
+            .chunks_exact(group_size)
+            .zip(q.chunks_exact_mut(group_size))
+        {
+            let (scale, zero) = if config.symmetric {
+                let amax = w.iter().fold(0f32, |m, v| m.max(v.abs()));
+                (amax / qmax as f32, 0)
+            } else {
+                // The range includes zero so that it is exactly representable.
+                let min = w.iter().fold(0f32, |m, &v| m.min(v));
+                let max = w.iter().fold(0f32, |m, &v| m.max(v));
+                let scale = (max - min) / (qmax - qmin) as f32;
+                let zero = if scale == 0. {
+                    0
+                } else {
+                    (qmin as f32 - min / scale).round() as i32
+                };
+                (scale, zero.clamp(qmin, qmax))
+            };
+            let inv_scale = if scale == 0. { 0. } else { 1. / scale };
+            for (q, &w) in q.iter_mut().zip(w.iter()) {
+                *q = ((w * inv_scale).round() as i32 + zero).clamp(qmin, qmax) as i8
+            }
+            scales.push(scale);
+            zero_points.push(zero as i8);
+        }
+        let data = match config.bits {
+            IntBits::Int8 => q.into_iter().map(|q| q as u8).collect(),
+            IntBits::Int4 => q
+                .chunks_exact(2)
+                .map(|q| (q[0] as u8 & 0x0f) | ((q[1] as u8) << 4))
+                .collect(),
+        };
+        let zero_points = if config.symmetric {
+            None
+        } else {
+            Some(zero_points)
+        };
+        Ok(Self {
+            data,
+            scales,
+            zero_points,
+            config,
+            shape,
+        })
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    pub fn config(&self) -> IntQuantConfig {
+        self.config
+    }
+
+    /// The output and input channel dimensions.
+    pub fn matrix_dims(&self) -> (usize, usize) {
+        let n = self.shape.dims()[0];
+        (n, self.shape.elem_count() / n)
+    }
+
+    fn group_size(&self) -> usize {
+        self.config.group_size.unwrap_or(self.matrix_dims().1)
+    }
+
+    pub fn storage_size_in_bytes(&self) -> usize {
+        let zero_points = self.zero_points.as_ref().map_or(0, |z| z.len());
+        self.data.len() + self.scales.len() * 4 + zero_points
+    }
+
+    // The quantized values of an output channel.
+    fn row(&self, o: usize, dst: &mut [i8]) {
+        let k = dst.len();
+        match self.config.bits {
+            IntBits::Int8 => {
+                let src = &self.data[o * k..(o + 1) * k];
+                for (d, &s) in dst.iter_mut().zip(src.iter()) {
+                    *d = s as i8
+                }
+            }
+            IntBits::Int4 => unpack_int4(&self.data[o * k / 2..(o + 1) * k / 2], dst),
+        }
+    }
+
+    pub fn dequantize(&self, device: &Device) -> Result<Tensor> {
+        let (n, k) = self.matrix_dims();
+        let group_size = self.group_size();
+        let mut q = vec![0i8; k];
+        let mut ys = Vec::with_capacity(n * k);
+        for o in 0..n {
+            self.row(o, &mut q);
+            for (g, q) in q.chunks_exact(group_size).enumerate() {
+                let idx = o * k / group_size + g;
+                let scale = self.scales[idx];
+                let zero = self.zero_points.as_ref().map_or(0, |z| z[idx] as i32);
+                ys.extend(q.iter().map(|&q| scale * (q as i32 - zero) as f32))
+            }
+        }
+        Tensor::from_vec(ys, self.shape.clone(), device)
+    }
+
+    // Computes `dst = lhs . self^T` where `lhs` has `m` rows.
+    fn matmul_t_cpu(&self, m: usize, lhs: &[f32], dst: &mut [f32]) {
+        let (n, k) = self.matrix_dims();
+        let group_size = self.group_size();
+        let n_groups = k / group_size;
+        // Dynamic quantization of the activations, one scale per row.
+        let mut lhs_q = vec![0i8; m * k];
+        let mut lhs_scales = vec![0f32; m];
+        for ((x, q), s) in lhs
+            .chunks_exact(k)
+            .zip(lhs_q.chunks_exact_mut(k))
+            .zip(lhs_scales.iter_mut())
+        {
+            let amax = x.iter().fold(0f32, |m, v| m.max(v.abs()));
+            *s = amax / 127.;
+            let inv_scale = if amax == 0. { 0. } else { 127. / amax };
+            for (q, &x) in q.iter_mut().zip(x.iter()) {
+                *q = (x * inv_scale).round().clamp(-127., 127.) as i8
+            }
+        }
+        // The sums of the quantized activations of each group, for the zero point correction.
+        let lhs_sums = self.zero_points.as_ref().map(|_| {
+            lhs_q
+                .chunks_exact(group_size)
+                .map(|q| q.iter().map(|&q| q as i32).sum::<i32>())
+                .collect::<Vec<_>>()
+        });
+
+        let mut dst_t = vec![0f32; n * m];
+        dst_t
+            .par_chunks_exact_mut(m)
+            .enumerate()
+            .for_each(|(o, dst_t)| {
+                let mut w = vec![0i8; k];
+                self.row(o, &mut w);
+                let scales = &self.scales[o * n_groups..(o + 1) * n_groups];
+                let zeros = self
+                    .zero_points
+                    .as_ref()
+                    .map(|z| &z[o * n_groups..(o + 1) * n_groups]);
+                for (row, dst) in dst_t.iter_mut().enumerate() {
+                    let x = &lhs_q[row * k..(row + 1) * k];
+                    let mut acc = 0f32;
+                    for g in 0..n_groups {
+                        let range = g * group_size..(g + 1) * group_size;
+                        let mut sumi = vec_dot_i8(&w[range.clone()], &x[range]);
+                        if let (Some(zeros), Some(sums)) = (zeros, lhs_sums.as_ref()) {
+                            sumi -= zeros[g] as i32 * sums[row * n_groups + g]
+                        }
+                        acc += scales[g] * sumi as f32
+                    }
+                    *dst = acc * lhs_scales[row]
+                }
+            });
+        for (o, dst_t) in dst_t.chunks_exact(m).enumerate() {
+            for (row, &v) in dst_t.iter().enumerate() {
+                dst[row * n + o] = v
+            }
+        }
+    }
+
+    /// Computes `xs . w^T` where `xs` has shape `(..., k)`, the result has shape `(..., n)`. The
+    /// cpu implementation uses int8 products, on other devices the weights are dequantized.
+    pub fn matmul_t(&self, xs: &Tensor) -> Result<Tensor> {
+        let (n, k) = self.matrix_dims();
+        let mut dims = xs.dims().to_vec();
+        if dims.last() != Some(&k) {
+            crate::bail!(
+                "input tensor {:?} incompatible with {:?}",
+                xs.shape(),
+                self.shape
+            )
+        }
+        if !xs.device().is_cpu() {
+            let w = self
+                .dequantize(xs.device())?
+                .to_dtype(xs.dtype())?
+                .reshape((n, k))?;
+            return xs.broadcast_matmul(&w.t()?);
+        }
+        let lhs = xs.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>()?;
+        let m = lhs.len() / k;
+        let mut dst = vec![0f32; m * n];
+        self.matmul_t_cpu(m, &lhs, &mut dst);
+        *dims.last_mut().unwrap() = n;
+        Tensor::from_vec(dst, dims, &Device::Cpu)?.to_dtype(xs.dtype())
+    }
+}
+
+impl crate::Module for IntQTensor {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        self.matmul_t(xs)
+    }
+}
@@ -1,3 +1,10 @@
+use super::iq_quants::{
+    best_index_int8, dequantize_grid, even_signs, grid_dot, grid_pair, iq1_split, iq1_values,
+    iq1s_grid, iq1s_point, iq2s_grid, iq2xs_grid, iq2xxs_grid, iq3s_grid, iq3xxs_grid,
+    quant_weights, signs, Requantize, ScaleSearch, GROUP_MAX_EPS, GROUP_MAX_EPS_IQ1M,
+    GROUP_MAX_EPS_IQ1S, GROUP_MAX_EPS_IQ2S, GROUP_MAX_EPS_IQ3XXS, IQ1M_DELTA, IQ1S_DELTA,
+    IQ2S_GRID, IQ2XS_GRID, IQ2XXS_GRID, IQ3S_GRID, IQ3XXS_GRID, KSIGNS_IQ2XS, KVALUES_IQ4NL,
+};
 use super::utils::{
     get_scale_min_k4, group_for_dequantization, group_for_quantization, make_q3_quants,
     make_qkx1_quants, make_qx_quants, nearest_int,
@@ -19,6 +26,7 @@ pub const QK5_0: usize = 32;
 pub const QK5_1: usize = 32;
 pub const QK8_0: usize = 32;
 pub const QK8_1: usize = 32;
+pub const QK4_NL: usize = 32;
 
 pub trait GgmlType: Sized + Clone + Send + Sync {
     const DTYPE: GgmlDType;
@@ -169,6 +177,109 @@ pub struct BlockQ8K {
 }
 const _: () = assert!(4 + QK_K + QK_K / 16 * 2 == std::mem::size_of::<BlockQ8K>());
 
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ2XXS {
+    pub(crate) d: f16,
+    pub(crate) qs: [u16; QK_K / 8],
+}
+const _: () = assert!(2 + QK_K / 4 == std::mem::size_of::<BlockIQ2XXS>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ2XS {
+    pub(crate) d: f16,
+    pub(crate) qs: [u16; QK_K / 8],
+    pub(crate) scales: [u8; QK_K / 32],
+}
+const _: () = assert!(2 + QK_K / 4 + QK_K / 32 == std::mem::size_of::<BlockIQ2XS>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ2S {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK_K / 4],
+    pub(crate) qh: [u8; QK_K / 32],
+    pub(crate) scales: [u8; QK_K / 32],
+}
+const _: () = assert!(2 + QK_K / 4 + QK_K / 16 == std::mem::size_of::<BlockIQ2S>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ3XXS {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; 3 * QK_K / 8],
+}
+const _: () = assert!(2 + 3 * QK_K / 8 == std::mem::size_of::<BlockIQ3XXS>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ3S {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK_K / 4],
+    pub(crate) qh: [u8; QK_K / 32],
+    pub(crate) signs: [u8; QK_K / 8],
+    pub(crate) scales: [u8; QK_K / 64],
+}
+const _: () =
+    assert!(2 + QK_K / 4 + QK_K / 32 + QK_K / 8 + QK_K / 64 == std::mem::size_of::<BlockIQ3S>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ4NL {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK4_NL / 2],
+}
+const _: () = assert!(std::mem::size_of::<BlockIQ4NL>() == 18);
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ4XS {
+    pub(crate) d: f16,
+    pub(crate) scales_h: u16,
+    pub(crate) scales_l: [u8; QK_K / 64],
+    pub(crate) qs: [u8; QK_K / 2],
+}
+const _: () = assert!(2 + 2 + QK_K / 64 + QK_K / 2 == std::mem::size_of::<BlockIQ4XS>());
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ1S {
+    pub(crate) d: f16,
+    pub(crate) qs: [u8; QK_K / 8],
+    pub(crate) qh: [u16; QK_K / 32],
+}
+const _: () = assert!(2 + QK_K / 8 + QK_K / 16 == std::mem::size_of::<BlockIQ1S>());
+
+// The block scale is stored in the top 4 bits of each of the 4 u16 making up `scales`.
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockIQ1M {
+    pub(crate) qs: [u8; QK_K / 8],
+    pub(crate) qh: [u8; QK_K / 16],
+    pub(crate) scales: [u8; QK_K / 32],
+}
+const _: () = assert!(QK_K / 8 + QK_K / 16 + QK_K / 32 == std::mem::size_of::<BlockIQ1M>());
+
+// Ternary quantization, 5 values are packed per byte in base 3 except for the last 16 values
+// that are packed 4 per byte in `qh`.
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockTQ1_0 {
+    pub(crate) qs: [u8; (QK_K - 4 * QK_K / 64) / 5],
+    pub(crate) qh: [u8; QK_K / 64],
+    pub(crate) d: f16,
+}
+const _: () = assert!(std::mem::size_of::<BlockTQ1_0>() == 54);
+
+#[derive(Debug, Clone, PartialEq)]
+#[repr(C)]
+pub struct BlockTQ2_0 {
+    pub(crate) qs: [u8; QK_K / 4],
+    pub(crate) d: f16,
+}
+const _: () = assert!(QK_K / 4 + 2 == std::mem::size_of::<BlockTQ2_0>());
+
 impl GgmlType for BlockQ4_0 {
     const DTYPE: GgmlDType = GgmlDType::Q4_0;
     const BLCK_SIZE: usize = QK4_0;
@@ -2264,6 +2375,1484 @@ impl GgmlType for BlockQ8K {
     }
 }
 
+// The imatrix weights of a block, the imatrix holds one weight per column of the matrix.
+fn imatrix_block(
+    imatrix: Option<(&[f32], usize)>,
+    block_idx: usize,
+    block_size: usize,
+) -> Option<&[f32]> {
+    imatrix.map(|(imatrix, n_per_row)| {
+        let row = block_idx % (n_per_row / block_size);
+        &imatrix[row * block_size..(row + 1) * block_size]
+    })
+}
+
+// The 2 and 3 bits i-quants share the same structure: the values of each sub-block are grouped by
+// 8 or 4 and each group is stored as the index of a grid point together with the sign bits.
+// https://github.com/ggml-org/llama.cpp/blob/master/ggml/src/ggml-quants.c
+impl BlockIQ2XXS {
+    fn quantize(xs: &[f32], ys: &mut [Self], imatrix: Option<(&[f32], usize)>) {
+        let grid = iq2xxs_grid();
+        let search = ScaleSearch {
+            n_levels: 3,
+            steps: -6..=6,
+            step: 0.1,
+            requantize: Requantize::All,
+        };
+        let mut weights = [0f32; 32];
+        let mut waux = [0f32; 32];
+        let mut xval = [0f32; 32];
+        let mut levels = [0i8; 32];
+        for (block_idx, (block, x)) in group_for_quantization(xs, ys).into_iter().enumerate() {
+            let qw = imatrix_block(imatrix, block_idx, QK_K);
+            let sigma2 = x.iter().map(|x| x * x).sum::<f32>() / QK_K as f32;
+            let mut scales = [0f32; QK_K / 32];
+            let mut q2 = [0u32; QK_K / 16];
+            for (ib, xb) in x.chunks_exact(32).enumerate() {
+                quant_weights(xb, qw.map(|qw| &qw[32 * ib..]), sigma2, &mut weights);
+                for (a, &w) in waux.iter_mut().zip(weights.iter()) {
+                    *a = w.sqrt()
+                }
+                let mut block_signs = [0u8; 4];
+                for (k, s) in block_signs.iter_mut().enumerate() {
+                    let r = 8 * k..8 * (k + 1);
+                    *s = even_signs(&xb[r.clone()], &weights[r.clone()], &mut xval[r]);
+                }
+                let max = xval.iter().copied().fold(f32::MIN, f32::max);
+                if max < GROUP_MAX_EPS {
+                    continue;
+                }
+                let mut scale = search.run(grid, &xval, &weights, &waux, &mut levels);
+                if scale < 0. {
+                    // Flip the scale so that it is positive and the signs accordingly.
+                    scale = -scale;
+                    for s in block_signs.iter_mut() {
+                        *s = !*s & 127
+                    }
+                }
+                for (k, &s) in block_signs.iter().enumerate() {
+                    let index = grid.index(&levels[8 * k..8 * (k + 1)]).unwrap_or(0);
+                    q2[2 * ib] |= (index as u32) << (8 * k);
+                    q2[2 * ib + 1] |= (s as u32) << (7 * k);
+                }
+                scales[ib] = scale;
+            }
+            let max_scale = scales.iter().copied().fold(0f32, f32::max);
+            if max_scale == 0. {
+                block.d = f16::ZERO;
+                block.qs.fill(0);
+                continue;
+            }
+            let d = max_scale / 31.;
+            block.d = f16::from_f32(d);
+            let id = 1. / d;
+            for (ib, &scale) in scales.iter().enumerate() {
+                let l = nearest_int(0.5 * (id * scale - 1.)).clamp(0, 15);
+                q2[2 * ib + 1] |= (l as u32) << 28;
+            }
+            for (qs, q2) in block.qs.chunks_exact_mut(2).zip(q2.iter()) {
+                qs[0] = *q2 as u16;
+                qs[1] = (*q2 >> 16) as u16;
+            }
+        }
+    }
+}
+
+impl GgmlType for BlockIQ2XXS {
+    const DTYPE: GgmlDType = GgmlDType::IQ2XXS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "avx2")]
+        return super::avx::vec_dot_iq2xxs_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq2xxs_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_iq2xxs_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for (qs, q8) in x.qs.chunks_exact(4).zip(y.qs.chunks_exact(32)) {
+                let aux0 = qs[0] as u32 | (qs[1] as u32) << 16;
+                let aux1 = qs[2] as u32 | (qs[3] as u32) << 16;
+                let ls = 2 * (aux1 >> 28) as i32 + 1;
+                let mut sumi = 0i32;
+                for (l, q8) in q8.chunks_exact(8).enumerate() {
+                    let grid = IQ2XXS_GRID[((aux0 >> (8 * l)) & 255) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[((aux1 >> (7 * l)) & 127) as usize];
+                    sumi += grid_dot(&grid, signs, q8);
+                }
+                bsum += sumi * ls;
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        0.125 * sumf
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        Self::quantize(xs, ys, None)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix_weights: &[f32], n_per_row: usize) {
+        Self::quantize(xs, ys, Some((imatrix_weights, n_per_row)))
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            for (qs, ys) in x.qs.chunks_exact(4).zip(ys.chunks_exact_mut(32)) {
+                let aux0 = qs[0] as u32 | (qs[1] as u32) << 16;
+                let aux1 = qs[2] as u32 | (qs[3] as u32) << 16;
+                let db = d * (0.5 + (aux1 >> 28) as f32) * 0.25;
+                for (l, ys) in ys.chunks_exact_mut(8).enumerate() {
+                    let grid = IQ2XXS_GRID[((aux0 >> (8 * l)) & 255) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[((aux1 >> (7 * l)) & 127) as usize];
+                    dequantize_grid(ys, db, &grid, signs);
+                }
+            }
+        }
+    }
+}
+
+impl BlockIQ2XS {
+    fn quantize(xs: &[f32], ys: &mut [Self], imatrix: Option<(&[f32], usize)>) {
+        let grid = iq2xs_grid();
+        let search = ScaleSearch {
+            n_levels: 3,
+            steps: -9..=9,
+            step: 0.1,
+            requantize: Requantize::OffGrid,
+        };
+        let mut weights = [0f32; 16];
+        let mut waux = [0f32; 16];
+        let mut xval = [0f32; 16];
+        let mut levels = [0i8; 16];
+        for (block_idx, (block, x)) in group_for_quantization(xs, ys).into_iter().enumerate() {
+            let qw = imatrix_block(imatrix, block_idx, QK_K);
+            let sigma2 = x.iter().map(|x| x * x).sum::<f32>() / QK_K as f32;
+            let mut scales = [0f32; QK_K / 16];
+            block.qs.fill(0);
+            block.scales.fill(0);
+            for (ib, xb) in x.chunks_exact(16).enumerate() {
+                quant_weights(xb, qw.map(|qw| &qw[16 * ib..]), sigma2, &mut weights);
+                for (a, &w) in waux.iter_mut().zip(weights.iter()) {
+                    *a = w.sqrt()
+                }
+                let mut block_signs = [0u8; 2];
+                for (k, s) in block_signs.iter_mut().enumerate() {
+                    let r = 8 * k..8 * (k + 1);
+                    *s = even_signs(&xb[r.clone()], &weights[r.clone()], &mut xval[r]);
+                }
+                let max = xval.iter().copied().fold(f32::MIN, f32::max);
+                if max < GROUP_MAX_EPS {
+                    continue;
+                }
+                let mut scale = search.run(grid, &xval, &weights, &waux, &mut levels);
+                if scale < 0. {
+                    scale = -scale;
+                    for s in block_signs.iter_mut() {
+                        *s = !*s & 127
+                    }
+                }
+                for (k, &s) in block_signs.iter().enumerate() {
+                    let index = grid.index(&levels[8 * k..8 * (k + 1)]).unwrap_or(0);
+                    block.qs[2 * ib + k] = index as u16 | (s as u16) << 9;
+                }
+                scales[ib] = scale;
+            }
+            let max_scale = scales.iter().copied().fold(0f32, f32::max);
+            if max_scale == 0. {
+                block.d = f16::ZERO;
+                block.qs.fill(0);
+                continue;
+            }
+            let d = max_scale / 31.;
+            block.d = f16::from_f32(d);
+            let id = 1. / d;
+            for (ib, &scale) in scales.iter().enumerate() {
+                let l = nearest_int(0.5 * (id * scale - 1.)).clamp(0, 15) as u8;
+                block.scales[ib / 2] |= l << (4 * (ib % 2));
+            }
+        }
+    }
+}
+
+impl GgmlType for BlockIQ2XS {
+    const DTYPE: GgmlDType = GgmlDType::IQ2XS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "avx2")]
+        return super::avx::vec_dot_iq2xs_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq2xs_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_iq2xs_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for (ib32, (qs, q8)) in x.qs.chunks_exact(4).zip(y.qs.chunks_exact(32)).enumerate() {
+                let ls1 = 2 * (x.scales[ib32] & 0xf) as i32 + 1;
+                let ls2 = 2 * (x.scales[ib32] >> 4) as i32 + 1;
+                let mut sumi = [0i32; 2];
+                for (l, (&q, q8)) in qs.iter().zip(q8.chunks_exact(8)).enumerate() {
+                    let grid = IQ2XS_GRID[(q & 511) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[(q >> 9) as usize];
+                    sumi[l / 2] += grid_dot(&grid, signs, q8);
+                }
+                bsum += sumi[0] * ls1 + sumi[1] * ls2;
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        0.125 * sumf
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        Self::quantize(xs, ys, None)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix_weights: &[f32], n_per_row: usize) {
+        Self::quantize(xs, ys, Some((imatrix_weights, n_per_row)))
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            for (ib32, (qs, ys)) in
+                x.qs.chunks_exact(4)
+                    .zip(ys.chunks_exact_mut(32))
+                    .enumerate()
+            {
+                let db = [
+                    d * (0.5 + (x.scales[ib32] & 0xf) as f32) * 0.25,
+                    d * (0.5 + (x.scales[ib32] >> 4) as f32) * 0.25,
+                ];
+                for (l, (&q, ys)) in qs.iter().zip(ys.chunks_exact_mut(8)).enumerate() {
+                    let grid = IQ2XS_GRID[(q & 511) as usize].to_le_bytes();
+                    let signs = KSIGNS_IQ2XS[(q >> 9) as usize];
+                    dequantize_grid(ys, db[l / 2], &grid, signs);
+                }
+            }
+        }
+    }
+}
+
+impl BlockIQ2S {
+    fn quantize(xs: &[f32], ys: &mut [Self], imatrix: Option<(&[f32], usize)>) {
+        let grid = iq2s_grid();
+        let search = ScaleSearch {
+            n_levels: 3,
+            steps: -9..=9,
+            step: 0.1,
+            requantize: Requantize::OffGrid,
+        };
+        let mut weights = [0f32; 16];
+        let mut waux = [0f32; 16];
+        let mut xval = [0f32; 16];
+        let mut levels = [0i8; 16];
+        for (block_idx, (block, x)) in group_for_quantization(xs, ys).into_iter().enumerate() {
+            let qw = imatrix_block(imatrix, block_idx, QK_K);
+            let sigma2 = 2. * x.iter().map(|x| x * x).sum::<f32>() / QK_K as f32;
+            let mut scales = [0f32; QK_K / 16];
+            block.qs.fill(0);
+            block.qh.fill(0);
+            block.scales.fill(0);
+            for (ib, xb) in x.chunks_exact(16).enumerate() {
+                match qw {
+                    Some(qw) => quant_weights(xb, Some(&qw[16 * ib..]), sigma2, &mut weights),
+                    None => {
+                        for (w, &x) in weights.iter_mut().zip(xb.iter()) {
+                            *w = 0.25 * sigma2 + x * x
+                        }
+                    }
+                }
+                for (a, &w) in waux.iter_mut().zip(weights.iter()) {
+                    *a = w.sqrt()
+                }
+                let mut block_signs = [0u8; 2];
+                for (k, s) in block_signs.iter_mut().enumerate() {
+                    let r = 8 * k..8 * (k + 1);
+                    *s = signs(&xb[r.clone()], &mut xval[r]);
+                }
+                let max = xval.iter().copied().fold(f32::MIN, f32::max);
+                if max < GROUP_MAX_EPS_IQ2S {
+                    continue;
+                }
+                let scale = search.run(grid, &xval, &weights, &waux, &mut levels);
+                for (k, &s) in block_signs.iter().enumerate() {
+                    let index = grid.index(&levels[8 * k..8 * (k + 1)]).unwrap_or(0);
+                    let i8 = 2 * ib + k;
+                    block.qs[i8] = index as u8;
+                    block.qh[i8 / 4] |= ((index >> 8) << (2 * (i8 % 4))) as u8;
+                    block.qs[QK_K / 8 + i8] = s;
+                }
+                scales[ib] = scale;
+            }
+            let max_scale = scales.iter().copied().fold(0f32, f32::max);
+            if max_scale == 0. {
+                block.d = f16::ZERO;
+                continue;
+            }
+            let d = max_scale / 31.;
+            block.d = f16::from_f32(d * 0.9875);
+            let id = 1. / d;
+            for (ib, &scale) in scales.iter().enumerate() {
+                let l = nearest_int(0.5 * (id * scale - 1.)).clamp(0, 15) as u8;
+                block.scales[ib / 2] |= l << (4 * (ib % 2));
+            }
+        }
+    }
+}
+
+impl GgmlType for BlockIQ2S {
+    const DTYPE: GgmlDType = GgmlDType::IQ2S;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "avx2")]
+        return super::avx::vec_dot_iq2s_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq2s_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_iq2s_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (qs, signs) = x.qs.split_at(QK_K / 8);
+            let mut bsum = 0i32;
+            for (ib32, q8) in y.qs.chunks_exact(32).enumerate() {
+                let ls1 = 2 * (x.scales[ib32] & 0xf) as i32 + 1;
+                let ls2 = 2 * (x.scales[ib32] >> 4) as i32 + 1;
+                let mut sumi = [0i32; 2];
+                for (l, q8) in q8.chunks_exact(8).enumerate() {
+                    let index =
+                        qs[4 * ib32 + l] as usize | ((x.qh[ib32] as usize) << (8 - 2 * l)) & 0x300;
+                    let grid = IQ2S_GRID[index].to_le_bytes();
+                    sumi[l / 2] += grid_dot(&grid, signs[4 * ib32 + l], q8);
+                }
+                bsum += sumi[0] * ls1 + sumi[1] * ls2;
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        0.125 * sumf
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        Self::quantize(xs, ys, None)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix_weights: &[f32], n_per_row: usize) {
+        Self::quantize(xs, ys, Some((imatrix_weights, n_per_row)))
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            let (qs, signs) = x.qs.split_at(QK_K / 8);
+            for (ib32, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let db = [
+                    d * (0.5 + (x.scales[ib32] & 0xf) as f32) * 0.25,
+                    d * (0.5 + (x.scales[ib32] >> 4) as f32) * 0.25,
+                ];
+                for (l, ys) in ys.chunks_exact_mut(8).enumerate() {
+                    let index =
+                        qs[4 * ib32 + l] as usize | ((x.qh[ib32] as usize) << (8 - 2 * l)) & 0x300;
+                    let grid = IQ2S_GRID[index].to_le_bytes();
+                    dequantize_grid(ys, db[l / 2], &grid, signs[4 * ib32 + l]);
+                }
+            }
+        }
+    }
+}
+
+impl BlockIQ3XXS {
+    fn quantize(xs: &[f32], ys: &mut [Self], imatrix: Option<(&[f32], usize)>) {
+        let grid = iq3xxs_grid();
+        let search = ScaleSearch {
+            n_levels: 8,
+            steps: -15..=15,
+            step: 0.2,
+            requantize: Requantize::OffGrid,
+        };
+        let mut weights = [0f32; 32];
+        let mut waux = [0f32; 32];
+        let mut xval = [0f32; 32];
+        let mut levels = [0i8; 32];
+        for (block_idx, (block, x)) in group_for_quantization(xs, ys).into_iter().enumerate() {
+            let qw = imatrix_block(imatrix, block_idx, QK_K);
+            let sigma2 = 2. * x.iter().map(|x| x * x).sum::<f32>() / QK_K as f32;
+            let mut scales = [0f32; QK_K / 32];
+            let mut scales_and_signs = [0u32; QK_K / 32];
+            block.qs.fill(0);
+            for (ib, xb) in x.chunks_exact(32).enumerate() {
+                quant_weights(xb, qw.map(|qw| &qw[32 * ib..]), sigma2, &mut weights);
+                for (a, &w) in waux.iter_mut().zip(weights.iter()) {
+                    *a = w.sqrt()
+                }
+                let mut block_signs = [0u8; 4];
+                for (k, s) in block_signs.iter_mut().enumerate() {
+                    let r = 8 * k..8 * (k + 1);
+                    *s = even_signs(&xb[r.clone()], &weights[r.clone()], &mut xval[r]);
+                }
+                let max = xval.iter().copied().fold(f32::MIN, f32::max);
+                if max < GROUP_MAX_EPS_IQ3XXS {
+                    continue;
+                }
+                let mut scale = search.run(grid, &xval, &weights, &waux, &mut levels);
+                if scale < 0. {
+                    scale = -scale;
+                    for s in block_signs.iter_mut() {
+                        *s = !*s & 127
+                    }
+                }
+                for k in 0..8 {
+                    let index = grid.index(&levels[4 * k..4 * (k + 1)]).unwrap_or(0);
+                    block.qs[8 * ib + k] = index as u8;
+                }
+                scales_and_signs[ib] = block_signs
+                    .iter()
+                    .enumerate()
+                    .fold(0, |acc, (k, &s)| acc | (s as u32) << (7 * k));
+                scales[ib] = scale;
+            }
+            let max_scale = scales.iter().copied().fold(0f32, f32::max);
+            if max_scale == 0. {
+                block.d = f16::ZERO;
+                block.qs.fill(0);
+                continue;
+            }
+            let d = max_scale / 31.;
+            block.d = f16::from_f32(d * 1.0125);
+            let id = 1. / d;
+            for (ib, &scale) in scales.iter().enumerate() {
+                let l = nearest_int(0.5 * (id * scale - 1.)).clamp(0, 15);
+                let aux = scales_and_signs[ib] | (l as u32) << 28;
+                LittleEndian::write_u32(&mut block.qs[QK_K / 4 + 4 * ib..], aux);
+            }
+        }
+    }
+}
+
+impl GgmlType for BlockIQ3XXS {
+    const DTYPE: GgmlDType = GgmlDType::IQ3XXS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "avx2")]
+        return super::avx::vec_dot_iq3xxs_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq3xxs_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_iq3xxs_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (qs, scales_and_signs) = x.qs.split_at(QK_K / 4);
+            let mut bsum = 0i32;
+            for (ib32, q8) in y.qs.chunks_exact(32).enumerate() {
+                let aux = LittleEndian::read_u32(&scales_and_signs[4 * ib32..]);
+                let ls = 2 * (aux >> 28) as i32 + 1;
+                let mut sumi = 0i32;
+                for (l, q8) in q8.chunks_exact(8).enumerate() {
+                    let q = &qs[8 * ib32 + 2 * l..];
+                    let grid = grid_pair(&IQ3XXS_GRID, q[0] as usize, q[1] as usize);
+                    let signs = KSIGNS_IQ2XS[((aux >> (7 * l)) & 127) as usize];
+                    sumi += grid_dot(&grid, signs, q8);
+                }
+                bsum += sumi * ls;
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        0.25 * sumf
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        Self::quantize(xs, ys, None)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix_weights: &[f32], n_per_row: usize) {
+        Self::quantize(xs, ys, Some((imatrix_weights, n_per_row)))
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            let (qs, scales_and_signs) = x.qs.split_at(QK_K / 4);
+            for (ib32, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let aux = LittleEndian::read_u32(&scales_and_signs[4 * ib32..]);
+                let db = d * (0.5 + (aux >> 28) as f32) * 0.5;
+                for (l, ys) in ys.chunks_exact_mut(8).enumerate() {
+                    let q = &qs[8 * ib32 + 2 * l..];
+                    let grid = grid_pair(&IQ3XXS_GRID, q[0] as usize, q[1] as usize);
+                    let signs = KSIGNS_IQ2XS[((aux >> (7 * l)) & 127) as usize];
+                    dequantize_grid(ys, db, &grid, signs);
+                }
+            }
+        }
+    }
+}
+
+impl BlockIQ3S {
+    fn quantize(xs: &[f32], ys: &mut [Self], imatrix: Option<(&[f32], usize)>) {
+        let grid = iq3s_grid();
+        let search = ScaleSearch {
+            n_levels: 8,
+            steps: -9..=9,
+            step: 0.2,
+            requantize: Requantize::AllIfOffGrid,
+        };
+        let mut weights = [0f32; 32];
+        let mut waux = [0f32; 32];
+        let mut xval = [0f32; 32];
+        let mut levels = [0i8; 32];
+        for (block_idx, (block, x)) in group_for_quantization(xs, ys).into_iter().enumerate() {
+            let qw = imatrix_block(imatrix, block_idx, QK_K);
+            let sigma2 = 2. * x.iter().map(|x| x * x).sum::<f32>() / QK_K as f32;
+            let mut scales = [0f32; QK_K / 32];
+            block.qs.fill(0);
+            block.qh.fill(0);
+            block.signs.fill(0);
+            block.scales.fill(0);
+            for (ib, xb) in x.chunks_exact(32).enumerate() {
+                quant_weights(xb, qw.map(|qw| &qw[32 * ib..]), sigma2, &mut weights);
+                for (a, &w) in waux.iter_mut().zip(weights.iter()) {
+                    *a = w.sqrt()
+                }
+                for k in 0..4 {
+                    let r = 8 * k..8 * (k + 1);
+                    block.signs[4 * ib + k] = signs(&xb[r.clone()], &mut xval[r]);
+                }
+                let max = xval.iter().copied().fold(f32::MIN, f32::max);
+                if max == 0. {
+                    continue;
+                }
+                let scale = search.run(grid, &xval, &weights, &waux, &mut levels);
+                for k in 0..8 {
+                    let index = grid.index(&levels[4 * k..4 * (k + 1)]).unwrap_or(0);
+                    block.qs[8 * ib + k] = index as u8;
+                    block.qh[ib] |= ((index >> 8) << k) as u8;
+                }
+                scales[ib] = scale;
+            }
+            let max_scale = scales.iter().copied().fold(0f32, f32::max);
+            if max_scale == 0. {
+                block.d = f16::ZERO;
+                continue;
+            }
+            let d = max_scale / 31.;
+            block.d = f16::from_f32(d * 1.033);
+            let id = 1. / d;
+            for (ib, &scale) in scales.iter().enumerate() {
+                let l = nearest_int(0.5 * (id * scale - 1.)).clamp(0, 15) as u8;
+                block.scales[ib / 2] |= l << (4 * (ib % 2));
+            }
+        }
+    }
+}
+
+impl GgmlType for BlockIQ3S {
+    const DTYPE: GgmlDType = GgmlDType::IQ3S;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "avx2")]
+        return super::avx::vec_dot_iq3s_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq3s_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_iq3s_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for (ib32, q8) in y.qs.chunks_exact(32).enumerate() {
+                let ls = 2 * ((x.scales[ib32 / 2] >> (4 * (ib32 % 2))) & 0xf) as i32 + 1;
+                let qh = x.qh[ib32] as usize;
+                let mut sumi = 0i32;
+                for (l, q8) in q8.chunks_exact(8).enumerate() {
+                    let q = &x.qs[8 * ib32 + 2 * l..];
+                    let i1 = q[0] as usize | (qh << (8 - 2 * l)) & 256;
+                    let i2 = q[1] as usize | (qh << (7 - 2 * l)) & 256;
+                    let grid = grid_pair(&IQ3S_GRID, i1, i2);
+                    sumi += grid_dot(&grid, x.signs[4 * ib32 + l], q8);
+                }
+                bsum += sumi * ls;
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        sumf
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        Self::quantize(xs, ys, None)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix_weights: &[f32], n_per_row: usize) {
+        Self::quantize(xs, ys, Some((imatrix_weights, n_per_row)))
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            for (ib32, ys) in ys.chunks_exact_mut(32).enumerate() {
+                let ls = (x.scales[ib32 / 2] >> (4 * (ib32 % 2))) & 0xf;
+                let db = d * (1 + 2 * ls) as f32;
+                let qh = x.qh[ib32] as usize;
+                for (l, ys) in ys.chunks_exact_mut(8).enumerate() {
+                    let q = &x.qs[8 * ib32 + 2 * l..];
+                    let i1 = q[0] as usize | (qh << (8 - 2 * l)) & 256;
+                    let i2 = q[1] as usize | (qh << (7 - 2 * l)) & 256;
+                    let grid = grid_pair(&IQ3S_GRID, i1, i2);
+                    dequantize_grid(ys, db, &grid, x.signs[4 * ib32 + l]);
+                }
+            }
+        }
+    }
+}
+
+// Quantizes `xs` to the non-linear IQ4 values, `l` receives the index of each value. The scale is
+// returned, when there are several sub-blocks `ls` receives the 6 bits sub-block scales offset by
+// 32.
+// https://github.com/ggml-org/llama.cpp/blob/master/ggml/src/ggml-quants.c quantize_row_iq4_nl_impl
+fn quantize_iq4(
+    xs: &[f32],
+    imatrix: Option<&[f32]>,
+    block_size: usize,
+    l: &mut [u8],
+    ls: &mut [u8],
+) -> f32 {
+    const NTRY: i32 = 7;
+    let values = &KVALUES_IQ4NL;
+    let v0 = values[0] as f32;
+    let sigma2 = 2. * xs.iter().map(|x| x * x).sum::<f32>() / xs.len() as f32;
+    let mut weights = [0f32; 32];
+    let mut scales = [0f32; QK_K / 32];
+    let mut max_scale = 0f32;
+    let mut amax_scale = 0f32;
+    for (ib, xb) in xs.chunks_exact(block_size).enumerate() {
+        let weights = &mut weights[..block_size];
+        quant_weights(
+            xb,
+            imatrix.map(|qw| &qw[block_size * ib..]),
+            sigma2,
+            weights,
+        );
+        let (mut amax, mut max) = (0f32, 0f32);
+        for &x in xb.iter() {
+            if x.abs() > amax {
+                amax = x.abs();
+                max = x;
+            }
+        }
+        if amax < GROUP_MAX_EPS {
+            continue;
+        }
+        let sums = |id: f32| {
+            let (mut sumqx, mut sumq2) = (0f32, 0f32);
+            for (&x, &w) in xb.iter().zip(weights.iter()) {
+                let q = values[best_index_int8(values, id * x)] as f32;
+                sumqx += w * q * x;
+                sumq2 += w * q * q;
+            }
+            (sumqx, sumq2)
+        };
+        let mut d = -max / v0;
+        let (sumqx, sumq2) = sums(1. / d);
+        if sumq2 > 0. {
+            d = sumqx / sumq2;
+        }
+        let mut best = d * sumqx;
+        for itry in -NTRY..=NTRY {
+            let (sumqx, sumq2) = sums((itry as f32 + v0) / max);
+            if sumq2 > 0. && sumqx * sumqx > best * sumq2 {
+                d = sumqx / sumq2;
+                best = d * sumqx;
+            }
+        }
+        scales[ib] = d;
+        if d.abs() > amax_scale {
+            amax_scale = d.abs();
+            max_scale = d;
+        }
+    }
+
+    if xs.len() > block_size {
+        let d = -max_scale / 32.;
+        let id = if d != 0. { 1. / d } else { 0. };
+        for (ib, xb) in xs.chunks_exact(block_size).enumerate() {
+            let s = nearest_int(id * scales[ib]).clamp(-32, 31);
+            let dl = d * s as f32;
+            let idl = if dl != 0. { 1. / dl } else { 0. };
+            for (l, &x) in l[block_size * ib..].iter_mut().zip(xb.iter()) {
+                *l = best_index_int8(values, idl * x) as u8
+            }
+            ls[ib] = (s + 32) as u8;
+        }
+        d
+    } else {
+        let d = scales[0];
+        let id = if d != 0. { 1. / d } else { 0. };
+        for (l, &x) in l.iter_mut().zip(xs.iter()) {
+            *l = best_index_int8(values, id * x) as u8
+        }
+        d
+    }
+}
+
+impl GgmlType for BlockIQ4NL {
+    const DTYPE: GgmlDType = GgmlDType::IQ4NL;
+    const BLCK_SIZE: usize = QK4_NL;
+    type VecDotType = BlockQ8_0;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "avx2")]
+        return super::avx::vec_dot_iq4nl_q8_0(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq4nl_q8_0(n, xs, ys);
+
+        #[cfg(target_feature = "simd128")]
+        return super::simd128::vec_dot_iq4nl_q8_0(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK4_NL),
+            "vec_dot_iq4nl_q8_0: {n} is not divisible by {QK4_NL}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = 0i32;
+            for (j, &q) in x.qs.iter().enumerate() {
+                sumi += KVALUES_IQ4NL[(q & 0xf) as usize] as i32 * y.qs[j] as i32;
+                sumi += KVALUES_IQ4NL[(q >> 4) as usize] as i32 * y.qs[j + QK4_NL / 2] as i32;
+            }
+            sumf += x.d.to_f32() * y.d.to_f32() * sumi as f32;
+        }
+        sumf
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        Self::from_float_imatrix_opt(xs, ys, None)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix_weights: &[f32], n_per_row: usize) {
+        Self::from_float_imatrix_opt(xs, ys, Some((imatrix_weights, n_per_row)))
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            for (j, &q) in x.qs.iter().enumerate() {
+                ys[j] = d * KVALUES_IQ4NL[(q & 0xf) as usize] as f32;
+                ys[j + QK4_NL / 2] = d * KVALUES_IQ4NL[(q >> 4) as usize] as f32;
+            }
+        }
+    }
+}
+
+impl BlockIQ4NL {
+    fn from_float_imatrix_opt(xs: &[f32], ys: &mut [Self], imatrix: Option<(&[f32], usize)>) {
+        let mut l = [0u8; QK4_NL];
+        for (block_idx, (block, x)) in group_for_quantization(xs, ys).into_iter().enumerate() {
+            let qw = imatrix_block(imatrix, block_idx, QK4_NL);
+            let d = quantize_iq4(x, qw, QK4_NL, &mut l, &mut []);
+            block.d = f16::from_f32(d);
+            for (j, q) in block.qs.iter_mut().enumerate() {
+                *q = l[j] | (l[j + QK4_NL / 2] << 4);
+            }
+        }
+    }
+}
+
+impl GgmlType for BlockIQ4XS {
+    const DTYPE: GgmlDType = GgmlDType::IQ4XS;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "avx2")]
+        return super::avx::vec_dot_iq4xs_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq4xs_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "simd128")]
+        return super::simd128::vec_dot_iq4xs_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_iq4xs_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for (ib, (qs, q8)) in x.qs.chunks_exact(16).zip(y.qs.chunks_exact(32)).enumerate() {
+                let mut sumi = 0i32;
+                for (j, &q) in qs.iter().enumerate() {
+                    sumi += KVALUES_IQ4NL[(q & 0xf) as usize] as i32 * q8[j] as i32;
+                    sumi += KVALUES_IQ4NL[(q >> 4) as usize] as i32 * q8[j + 16] as i32;
+                }
+                bsum += sumi * (x.scale(ib) as i32 - 32);
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        sumf
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        Self::from_float_imatrix_opt(xs, ys, None)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix_weights: &[f32], n_per_row: usize) {
+        Self::from_float_imatrix_opt(xs, ys, Some((imatrix_weights, n_per_row)))
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            for (ib, (qs, ys)) in
+                x.qs.chunks_exact(16)
+                    .zip(ys.chunks_exact_mut(32))
+                    .enumerate()
+            {
+                let dl = d * (x.scale(ib) as f32 - 32.);
+                for (j, &q) in qs.iter().enumerate() {
+                    ys[j] = dl * KVALUES_IQ4NL[(q & 0xf) as usize] as f32;
+                    ys[j + 16] = dl * KVALUES_IQ4NL[(q >> 4) as usize] as f32;
+                }
+            }
+        }
+    }
+}
+
+impl BlockIQ4XS {
+    // The 6 bits scale of the `ib`-th sub-block of 32 values.
+    pub(crate) fn scale(&self, ib: usize) -> u8 {
+        let low = (self.scales_l[ib / 2] >> (4 * (ib % 2))) & 0xf;
+        let high = ((self.scales_h >> (2 * ib)) & 3) as u8;
+        low | (high << 4)
+    }
+
+    fn from_float_imatrix_opt(xs: &[f32], ys: &mut [Self], imatrix: Option<(&[f32], usize)>) {
+        let mut l = [0u8; QK_K];
+        let mut ls = [0u8; QK_K / 32];
+        for (block_idx, (block, x)) in group_for_quantization(xs, ys).into_iter().enumerate() {
+            let qw = imatrix_block(imatrix, block_idx, QK_K);
+            let d = quantize_iq4(x, qw, 32, &mut l, &mut ls);
+            block.d = f16::from_f32(d);
+            block.scales_h = 0;
+            block.scales_l.fill(0);
+            for (ib, &s) in ls.iter().enumerate() {
+                block.scales_l[ib / 2] |= (s & 0xf) << (4 * (ib % 2));
+                block.scales_h |= ((s >> 4) as u16) << (2 * ib);
+            }
+            for (qs, l) in block.qs.chunks_exact_mut(16).zip(l.chunks_exact(32)) {
+                for (j, q) in qs.iter_mut().enumerate() {
+                    *q = l[j] | (l[j + 16] << 4);
+                }
+            }
+        }
+    }
+}
+
+// The 1 bit i-quants store groups of 8 values as points of a grid with coordinates in {-1, 0, 1},
+// shifted by a small delta whose sign is stored per sub-block (IQ1S) or per group (IQ1M).
+impl BlockIQ1S {
+    fn quantize(xs: &[f32], ys: &mut [Self], imatrix: Option<(&[f32], usize)>) {
+        let grid = iq1s_grid();
+        let mut weights = [0f32; 32];
+        let mut levels = [0i8; 32];
+        for (block_idx, (block, x)) in group_for_quantization(xs, ys).into_iter().enumerate() {
+            let qw = imatrix_block(imatrix, block_idx, QK_K);
+            let sigma2 = 2. * x.iter().map(|x| x * x).sum::<f32>() / QK_K as f32;
+            block.d = f16::ZERO;
+            block.qs.fill(0);
+            block.qh.fill(0);
+            let mut scales = [0f32; QK_K / 32];
+            let mut shifts = [false; QK_K / 32];
+            for (ib, xb) in x.chunks_exact(32).enumerate() {
+                quant_weights(xb, qw.map(|qw| &qw[32 * ib..]), sigma2, &mut weights);
+                let max = xb.iter().fold(0f32, |acc, x| acc.max(x.abs()));
+                if max < GROUP_MAX_EPS_IQ1S {
+                    continue;
+                }
+                let (mut scale, shift) = iq1_split(
+                    xb,
+                    &weights,
+                    IQ1S_DELTA,
+                    &[(false, false), (true, true)],
+                    &mut levels,
+                );
+                let values = iq1_values(IQ1S_DELTA, shift.0);
+                let mut on_grid = true;
+                let mut qh = 0u16;
+                for k in 0..4 {
+                    let r = 8 * k..8 * (k + 1);
+                    on_grid &= grid.snap_by(
+                        &mut levels[r.clone()],
+                        &xb[r.clone()],
+                        &weights[r.clone()],
+                        |l| scale * values[l as usize],
+                    );
+                    let index = grid.index(&levels[r]).unwrap_or(0);
+                    block.qs[4 * ib + k] = index as u8;
+                    qh |= ((index >> 8) as u16) << (3 * k);
+                }
+                if !on_grid {
+                    let (sumqx, sumq2) = iq1_weighted_sums(xb, &weights, &levels, &values);
+                    if sumqx > 0. && sumq2 > 0. {
+                        scale = sumqx / sumq2;
+                    }
+                }
+                block.qh[ib] = qh;
+                scales[ib] = scale;
+                shifts[ib] = shift.0;
+            }
+            let max_scale = scales.iter().copied().fold(0f32, f32::max);
+            if max_scale == 0. {
+                continue;
+            }
+            let d = max_scale / 15.;
+            // ggml applies this fudge factor to the block scale.
+            block.d = f16::from_f32(d * 1.125);
+            let id = 1. / d;
+            for (ib, (&scale, &shift)) in scales.iter().zip(shifts.iter()).enumerate() {
+                let mut l = nearest_int(0.5 * (id * scale - 1.)).clamp(0, 7) as u16;
+                if shift {
+                    l |= 8
+                }
+                block.qh[ib] |= l << 12;
+            }
+        }
+    }
+}
+
+fn iq1_weighted_sums(xb: &[f32], weights: &[f32], levels: &[i8], values: &[f32; 3]) -> (f32, f32) {
+    let mut sumqx = 0f32;
+    let mut sumq2 = 0f32;
+    for ((&x, &w), &l) in xb.iter().zip(weights.iter()).zip(levels.iter()) {
+        let q = values[l as usize];
+        sumqx += w * q * x;
+        sumq2 += w * q * q;
+    }
+    (sumqx, sumq2)
+}
+
+impl GgmlType for BlockIQ1S {
+    const DTYPE: GgmlDType = GgmlDType::IQ1S;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq1s_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_iq1s_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = 0i32;
+            // The dot product with the shifts, the q8 block sums are used for these.
+            let mut sumi1 = 0i32;
+            let groups = x.qs.chunks_exact(4).zip(y.qs.chunks_exact(32));
+            for (ib, ((qs, q8), &qh)) in groups.zip(x.qh.iter()).enumerate() {
+                let ls = 2 * ((qh >> 12) & 7) as i32 + 1;
+                let delta = if qh & 0x8000 != 0 { -1 } else { 1 };
+                let mut lsum = 0i32;
+                for (l, (&q, q8)) in qs.iter().zip(q8.chunks_exact(8)).enumerate() {
+                    let point = iq1s_point(q as usize | ((qh as usize >> (3 * l)) & 7) << 8);
+                    lsum += point
+                        .iter()
+                        .zip(q8.iter())
+                        .map(|(&g, &q)| g as i32 * q as i32)
+                        .sum::<i32>();
+                }
+                let bsum = y.bsums[2 * ib] as i32 + y.bsums[2 * ib + 1] as i32;
+                sumi += ls * lsum;
+                sumi1 += ls * delta * bsum;
+            }
+            sumf += x.d.to_f32() * y.d * (sumi as f32 + IQ1S_DELTA * sumi1 as f32);
+        }
+        sumf
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        Self::quantize(xs, ys, None)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix_weights: &[f32], n_per_row: usize) {
+        Self::quantize(xs, ys, Some((imatrix_weights, n_per_row)))
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            let groups = x.qs.chunks_exact(4).zip(ys.chunks_exact_mut(32));
+            for ((qs, ys), &qh) in groups.zip(x.qh.iter()) {
+                let dl = d * (2 * ((qh >> 12) & 7) + 1) as f32;
+                let delta = if qh & 0x8000 != 0 {
+                    -IQ1S_DELTA
+                } else {
+                    IQ1S_DELTA
+                };
+                for (l, (&q, ys)) in qs.iter().zip(ys.chunks_exact_mut(8)).enumerate() {
+                    let point = iq1s_point(q as usize | ((qh as usize >> (3 * l)) & 7) << 8);
+                    for (y, &g) in ys.iter_mut().zip(point.iter()) {
+                        *y = dl * (g as f32 + delta)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl BlockIQ1M {
+    // The 3 bits sub-block scales and the block scale.
+    pub(crate) fn scales(&self) -> ([u16; 4], f32) {
+        let mut sc = [0u16; 4];
+        for (sc, s) in sc.iter_mut().zip(self.scales.chunks_exact(2)) {
+            *sc = u16::from_le_bytes([s[0], s[1]])
+        }
+        let d =
+            (sc[0] >> 12) | ((sc[1] >> 8) & 0x00f0) | ((sc[2] >> 4) & 0x0f00) | (sc[3] & 0xf000);
+        (sc, f16::from_bits(d).to_f32())
+    }
+
+    // The grid index of the group `k` of the sub-block `ib` and whether its shift is negative.
+    pub(crate) fn group(&self, ib: usize, k: usize) -> (usize, bool) {
+        let qh = self.qh[ib] as usize >> (4 * k);
+        let index = self.qs[2 * ib + k] as usize | (qh & 7) << 8;
+        (index, qh & 8 != 0)
+    }
+
+    fn quantize(xs: &[f32], ys: &mut [Self], imatrix: Option<(&[f32], usize)>) {
+        let grid = iq1s_grid();
+        let all_shifts = [(false, false), (false, true), (true, false), (true, true)];
+        let mut weights = [0f32; 16];
+        let mut levels = [0i8; 16];
+        for (block_idx, (block, x)) in group_for_quantization(xs, ys).into_iter().enumerate() {
+            let qw = imatrix_block(imatrix, block_idx, QK_K);
+            let sigma2 = 2. * x.iter().map(|x| x * x).sum::<f32>() / QK_K as f32;
+            block.qs.fill(0);
+            block.qh.fill(0);
+            block.scales.fill(0);
+            let mut scales = [0f32; QK_K / 16];
+            let mut shifts = [(false, false); QK_K / 16];
+            for (ib, xb) in x.chunks_exact(16).enumerate() {
+                quant_weights(xb, qw.map(|qw| &qw[16 * ib..]), sigma2, &mut weights);
+                let max = xb.iter().fold(0f32, |acc, x| acc.max(x.abs()));
+                if max < GROUP_MAX_EPS_IQ1M {
+                    continue;
+                }
+                let (mut scale, shift) =
+                    iq1_split(xb, &weights, IQ1M_DELTA, &all_shifts, &mut levels);
+                let values = [
+                    iq1_values(IQ1M_DELTA, shift.0),
+                    iq1_values(IQ1M_DELTA, shift.1),
+                ];
+                let mut on_grid = true;
+                let mut qh = 0u8;
+                for (k, values) in values.iter().enumerate() {
+                    let r = 8 * k..8 * (k + 1);
+                    on_grid &= grid.snap_by(
+                        &mut levels[r.clone()],
+                        &xb[r.clone()],
+                        &weights[r.clone()],
+                        |l| scale * values[l as usize],
+                    );
+                    let index = grid.index(&levels[r]).unwrap_or(0);
+                    block.qs[2 * ib + k] = index as u8;
+                    qh |= ((index >> 8) as u8) << (4 * k);
+                }
+                if !on_grid {
+                    let mut sumqx = 0f32;
+                    let mut sumq2 = 0f32;
+                    for (k, values) in values.iter().enumerate() {
+                        let r = 8 * k..8 * (k + 1);
+                        let (qx, q2) = iq1_weighted_sums(
+                            &xb[r.clone()],
+                            &weights[r.clone()],
+                            &levels[r],
+                            values,
+                        );
+                        sumqx += qx;
+                        sumq2 += q2;
+                    }
+                    if sumqx > 0. && sumq2 > 0. {
+                        scale = sumqx / sumq2;
+                    }
+                }
+                block.qh[ib] = qh;
+                scales[ib] = scale;
+                shifts[ib] = shift;
+            }
+            let max_scale = scales.iter().copied().fold(0f32, f32::max);
+            if max_scale == 0. {
+                continue;
+            }
+            let mut sc = [0u16; 4];
+            let mut d = max_scale / 15.;
+            let id = 1. / d;
+            // The block scale is refitted once the sub-block scales are rounded.
+            let mut sumqx = 0f32;
+            let mut sumq2 = 0f32;
+            for (ib, xb) in x.chunks_exact(16).enumerate() {
+                let l = nearest_int(0.5 * (id * scales[ib] - 1.)).clamp(0, 7);
+                sc[ib / 4] |= (l as u16) << (3 * (ib % 4));
+                let (s0, s1) = shifts[ib];
+                block.qh[ib] |= (u8::from(s0) << 3) | (u8::from(s1) << 7);
+                quant_weights(xb, qw.map(|qw| &qw[16 * ib..]), sigma2, &mut weights);
+                for k in 0..2 {
+                    let (index, neg) = block.group(ib, k);
+                    let values = iq1_values(IQ1M_DELTA, neg);
+                    let point = iq1s_point(index);
+                    for j in 0..8 {
+                        let w = weights[8 * k + j];
+                        let q = values[(point[j] + 1) as usize] * (2 * l + 1) as f32;
+                        sumqx += w * q * xb[8 * k + j];
+                        sumq2 += w * q * q;
+                    }
+                }
+            }
+            if sumq2 > 0. {
+                d = sumqx / sumq2;
+            }
+            // ggml applies this fudge factor to the block scale.
+            let s = f16::from_f32(d * 1.1125).to_bits();
+            sc[0] |= (s & 0x000f) << 12;
+            sc[1] |= (s & 0x00f0) << 8;
+            sc[2] |= (s & 0x0f00) << 4;
+            sc[3] |= s & 0xf000;
+            for (s, sc) in block.scales.chunks_exact_mut(2).zip(sc.iter()) {
+                s.copy_from_slice(&sc.to_le_bytes())
+            }
+        }
+    }
+}
+
+impl GgmlType for BlockIQ1M {
+    const DTYPE: GgmlDType = GgmlDType::IQ1M;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_iq1m_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_iq1m_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (sc, d) = x.scales();
+            let mut sumi1 = 0i32;
+            let mut sumi2 = 0i32;
+            for (ib, q8) in y.qs.chunks_exact(16).enumerate() {
+                let ls = 2 * ((sc[ib / 4] >> (3 * (ib % 4))) & 7) as i32 + 1;
+                let mut lsum1 = 0i32;
+                let mut lsum2 = 0i32;
+                for (k, q8) in q8.chunks_exact(8).enumerate() {
+                    let (index, neg) = x.group(ib, k);
+                    let point = iq1s_point(index);
+                    lsum1 += point
+                        .iter()
+                        .zip(q8.iter())
+                        .map(|(&g, &q)| g as i32 * q as i32)
+                        .sum::<i32>();
+                    let sum = q8.iter().map(|&q| q as i32).sum::<i32>();
+                    lsum2 += if neg { -sum } else { sum };
+                }
+                sumi1 += ls * lsum1;
+                sumi2 += ls * lsum2;
+            }
+            sumf += d * y.d * (sumi1 as f32 + IQ1M_DELTA * sumi2 as f32);
+        }
+        sumf
+    }
+
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        Self::quantize(xs, ys, None)
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], imatrix_weights: &[f32], n_per_row: usize) {
+        Self::quantize(xs, ys, Some((imatrix_weights, n_per_row)))
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let (sc, d) = x.scales();
+            for (ib, ys) in ys.chunks_exact_mut(16).enumerate() {
+                let dl = d * (2 * ((sc[ib / 4] >> (3 * (ib % 4))) & 7) + 1) as f32;
+                for (k, ys) in ys.chunks_exact_mut(8).enumerate() {
+                    let (index, neg) = x.group(ib, k);
+                    let delta = if neg { -IQ1M_DELTA } else { IQ1M_DELTA };
+                    for (y, &g) in ys.iter_mut().zip(iq1s_point(index).iter()) {
+                        *y = dl * (g as f32 + delta)
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl BlockTQ1_0 {
+    // The values of the block in {-1, 0, 1}. Each byte stores its trits as a fixed point
+    // fraction, multiplying by a power of 3 brings the trit to extract in the top position.
+    pub(crate) fn trits(&self) -> [i8; QK_K] {
+        const POW3: [u8; 5] = [1, 3, 9, 27, 81];
+        let trit = |q: u8, n: usize| ((q.wrapping_mul(POW3[n]) as u16 * 3) >> 8) as i8 - 1;
+        let mut trits = [0i8; QK_K];
+        for n in 0..5 {
+            for (m, &q) in self.qs[..32].iter().enumerate() {
+                trits[32 * n + m] = trit(q, n)
+            }
+            for (m, &q) in self.qs[32..].iter().enumerate() {
+                trits[160 + 16 * n + m] = trit(q, n)
+            }
+        }
+        for n in 0..4 {
+            for (j, &q) in self.qh.iter().enumerate() {
+                trits[240 + 4 * n + j] = trit(q, n)
+            }
+        }
+        trits
+    }
+}
+
+impl GgmlType for BlockTQ1_0 {
+    const DTYPE: GgmlDType = GgmlDType::TQ1_0;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "avx2")]
+        return super::avx::vec_dot_tq1_0_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_tq1_0_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_tq1_0_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let sumi = x
+                .trits()
+                .iter()
+                .zip(y.qs.iter())
+                .map(|(&t, &q)| t as i32 * q as i32)
+                .sum::<i32>();
+            sumf += x.d.to_f32() * y.d * sumi as f32;
+        }
+        sumf
+    }
+
+    // https://github.com/ggml-org/llama.cpp/blob/master/ggml/src/ggml-quants.c quantize_row_tq1_0_ref
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        for (block, x) in group_for_quantization(xs, ys) {
+            let amax = x.iter().fold(0f32, |acc, x| acc.max(x.abs()));
+            let id = if amax != 0. { 1. / amax } else { 0. };
+            block.d = f16::from_f32(amax);
+            // Packs the trits of the values `stride` apart, the first value ends up in the most
+            // significant trit. The ceiling division turns the base 3 number into a fraction of 256.
+            let pack = |x: &[f32], stride: usize, n: usize| {
+                let mut q = 0u16;
+                for i in 0..n {
+                    q = q * 3 + (nearest_int(x[i * stride] * id) + 1) as u16;
+                }
+                for _ in n..5 {
+                    q *= 3
+                }
+                (q * 256).div_ceil(243) as u8
+            };
+            for (m, q) in block.qs[..32].iter_mut().enumerate() {
+                *q = pack(&x[m..], 32, 5)
+            }
+            for (m, q) in block.qs[32..].iter_mut().enumerate() {
+                *q = pack(&x[160 + m..], 16, 5)
+            }
+            for (j, q) in block.qh.iter_mut().enumerate() {
+                *q = pack(&x[240 + j..], 4, 4)
+            }
+        }
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], _: &[f32], _: usize) {
+        // Ternary values are rounded to the closest of -d, 0 and d, the importance weights
+        // cannot change the result.
+        Self::from_float(xs, ys)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            for (y, &t) in ys.iter_mut().zip(x.trits().iter()) {
+                *y = d * t as f32
+            }
+        }
+    }
+}
+
+impl BlockTQ2_0 {
+    // The values of the block in {-1, 0, 1}.
+    pub(crate) fn trits(&self) -> [i8; QK_K] {
+        let mut trits = [0i8; QK_K];
+        for (j, qs) in self.qs.chunks_exact(32).enumerate() {
+            for l in 0..4 {
+                for (m, &q) in qs.iter().enumerate() {
+                    trits[128 * j + 32 * l + m] = ((q >> (2 * l)) & 3) as i8 - 1
+                }
+            }
+        }
+        trits
+    }
+}
+
+impl GgmlType for BlockTQ2_0 {
+    const DTYPE: GgmlDType = GgmlDType::TQ2_0;
+    const BLCK_SIZE: usize = QK_K;
+    type VecDotType = BlockQ8K;
+
+    #[allow(unreachable_code)]
+    fn vec_dot(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        #[cfg(target_feature = "avx2")]
+        return super::avx::vec_dot_tq2_0_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "neon")]
+        return super::neon::vec_dot_tq2_0_q8k(n, xs, ys);
+
+        #[cfg(target_feature = "simd128")]
+        return super::simd128::vec_dot_tq2_0_q8k(n, xs, ys);
+
+        Self::vec_dot_unopt(n, xs, ys)
+    }
+
+    fn vec_dot_unopt(n: usize, xs: &[Self], ys: &[Self::VecDotType]) -> f32 {
+        debug_assert!(
+            n.is_multiple_of(QK_K),
+            "vec_dot_tq2_0_q8k: {n} is not divisible by {QK_K}"
+        );
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let sumi = x
+                .trits()
+                .iter()
+                .zip(y.qs.iter())
+                .map(|(&t, &q)| t as i32 * q as i32)
+                .sum::<i32>();
+            sumf += x.d.to_f32() * y.d * sumi as f32;
+        }
+        sumf
+    }
+
+    // https://github.com/ggml-org/llama.cpp/blob/master/ggml/src/ggml-quants.c quantize_row_tq2_0_ref
+    fn from_float(xs: &[f32], ys: &mut [Self]) {
+        for (block, x) in group_for_quantization(xs, ys) {
+            let amax = x.iter().fold(0f32, |acc, x| acc.max(x.abs()));
+            let id = if amax != 0. { 1. / amax } else { 0. };
+            block.d = f16::from_f32(amax);
+            for (j, qs) in block.qs.chunks_exact_mut(32).enumerate() {
+                let x = &x[128 * j..];
+                for (m, q) in qs.iter_mut().enumerate() {
+                    *q = 0;
+                    for l in 0..4 {
+                        let xi = (nearest_int(x[m + 32 * l] * id) + 1) as u8;
+                        *q |= (xi & 3) << (2 * l);
+                    }
+                }
+            }
+        }
+    }
+
+    fn from_float_imatrix(xs: &[f32], ys: &mut [Self], _: &[f32], _: usize) {
+        Self::from_float(xs, ys)
+    }
+
+    fn to_float(xs: &[Self], ys: &mut [f32]) {
+        for (x, ys) in group_for_dequantization(xs, ys) {
+            let d = x.d.to_f32();
+            for (y, &t) in ys.iter_mut().zip(x.trits().iter()) {
+                *y = d * t as f32
+            }
+        }
+    }
+}
+
 // https://github.com/ggml-org/llama.cpp/blob/aa3ee0eb0b80efca126cedf9bcb4fb5864b46ce3/ggml/src/ggml-cpu/ggml-cpu.c#L1205
 pub fn matmul<T: GgmlType>(
     (m, k, n): (usize, usize, usize),
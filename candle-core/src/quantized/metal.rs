@@ -105,6 +105,50 @@ impl QMetalStorage {
                 let vec: Vec<crate::quantized::BlockQ8K> = read_to_vec(&buffer, block_len);
                 crate::quantized::BlockQ8K::to_float(&vec, &mut out);
             }
+            GgmlDType::IQ2XXS => {
+                let vec: Vec<crate::quantized::BlockIQ2XXS> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ2XXS::to_float(&vec, &mut out);
+            }
+            GgmlDType::IQ2XS => {
+                let vec: Vec<crate::quantized::BlockIQ2XS> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ2XS::to_float(&vec, &mut out);
+            }
+            GgmlDType::IQ3XXS => {
+                let vec: Vec<crate::quantized::BlockIQ3XXS> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ3XXS::to_float(&vec, &mut out);
+            }
+            GgmlDType::IQ4NL => {
+                let vec: Vec<crate::quantized::BlockIQ4NL> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ4NL::to_float(&vec, &mut out);
+            }
+            GgmlDType::IQ3S => {
+                let vec: Vec<crate::quantized::BlockIQ3S> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ3S::to_float(&vec, &mut out);
+            }
+            GgmlDType::IQ2S => {
+                let vec: Vec<crate::quantized::BlockIQ2S> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ2S::to_float(&vec, &mut out);
+            }
+            GgmlDType::IQ4XS => {
+                let vec: Vec<crate::quantized::BlockIQ4XS> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ4XS::to_float(&vec, &mut out);
+            }
+            GgmlDType::IQ1S => {
+                let vec: Vec<crate::quantized::BlockIQ1S> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ1S::to_float(&vec, &mut out);
+            }
+            GgmlDType::IQ1M => {
+                let vec: Vec<crate::quantized::BlockIQ1M> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockIQ1M::to_float(&vec, &mut out);
+            }
+            GgmlDType::TQ1_0 => {
+                let vec: Vec<crate::quantized::BlockTQ1_0> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockTQ1_0::to_float(&vec, &mut out);
+            }
+            GgmlDType::TQ2_0 => {
+                let vec: Vec<crate::quantized::BlockTQ2_0> = read_to_vec(&buffer, block_len);
+                crate::quantized::BlockTQ2_0::to_float(&vec, &mut out);
+            }
         }
 
         let buffer = self.device.new_buffer_with_data(&out)?;
@@ -156,7 +200,7 @@ impl QMetalStorage {
         let mut qcpu_storage = crate::Device::Cpu.qzeros(elem_count, self.dtype)?;
 
         if let QStorage::Cpu(storage) = &mut qcpu_storage {
-            storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix_weights, n_per_row);
+            storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix_weights, n_per_row)?;
         } else {
             unreachable!()
         }
@@ -172,7 +216,7 @@ impl QMetalStorage {
         let mut qcpu_storage = crate::Device::Cpu.qzeros(elem_count, self.dtype)?;
 
         if let QStorage::Cpu(storage) = &mut qcpu_storage {
-            storage.from_float(src.as_slice::<f32>()?);
+            storage.from_float(src.as_slice::<f32>()?)?;
         } else {
             unreachable!()
         }
@@ -229,7 +273,7 @@ impl QMetalStorage {
                 device.device(),
                 &encoder,
                 device.kernels(),
-                self.dtype.into(),
+                self.dtype.try_into()?,
                 (1, 1, n, k),
                 storage.buffer(),
                 (layout.start_offset() + batch_id * k) * storage.dtype().size_in_bytes(),
@@ -313,7 +357,7 @@ impl QMetalStorage {
             device.device(),
             &encoder,
             device.kernels(),
-            self.dtype.into(),
+            self.dtype.try_into()?,
             src0_l.dims(),
             &src0_stride,
             &self.buffer,
@@ -368,9 +412,11 @@ fn read_to_vec<T: Clone>(buffer: &Buffer, n: usize) -> Vec<T> {
     slice.to_vec()
 }
 
-impl From<GgmlDType> for candle_metal_kernels::GgmlDType {
-    fn from(value: GgmlDType) -> Self {
-        match value {
+impl TryFrom<GgmlDType> for candle_metal_kernels::GgmlDType {
+    type Error = crate::Error;
+
+    fn try_from(value: GgmlDType) -> Result<Self> {
+        let dtype = match value {
             GgmlDType::Q4_0 => candle_metal_kernels::GgmlDType::Q4_0,
             GgmlDType::Q4_1 => candle_metal_kernels::GgmlDType::Q4_1,
             GgmlDType::Q5_0 => candle_metal_kernels::GgmlDType::Q5_0,
@@ -386,6 +432,18 @@ impl From<GgmlDType> for candle_metal_kernels::GgmlDType {
             GgmlDType::F16 => candle_metal_kernels::GgmlDType::F16,
             GgmlDType::F32 => candle_metal_kernels::GgmlDType::F32,
             GgmlDType::BF16 => candle_metal_kernels::GgmlDType::F16,
-        }
+            GgmlDType::IQ2XXS
+            | GgmlDType::IQ2XS
+            | GgmlDType::IQ2S
+            | GgmlDType::IQ3XXS
+            | GgmlDType::IQ3S
+            | GgmlDType::IQ4NL
+            | GgmlDType::IQ4XS
+            | GgmlDType::IQ1S
+            | GgmlDType::IQ1M
+            | GgmlDType::TQ1_0
+            | GgmlDType::TQ2_0 => crate::bail!("no metal kernels for {value:?}"),
+        };
+        Ok(dtype)
     }
 }
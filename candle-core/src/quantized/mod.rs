@@ -6,11 +6,14 @@ use std::borrow::Cow;
 
 #[cfg(target_feature = "avx2")]
 pub mod avx;
+pub mod calibration;
 mod dummy_cuda;
 mod dummy_metal;
 pub mod ggml_file;
 pub mod gguf_file;
 pub mod imatrix_file;
+pub mod int_quants;
+mod iq_quants;
 pub mod k_quants;
 #[cfg(feature = "metal")]
 pub mod metal;
@@ -24,6 +27,7 @@ pub mod cuda;
 mod cuda {
     pub use super::dummy_cuda::*;
 }
+pub mod mx;
 
 #[cfg(target_feature = "neon")]
 pub mod neon;
@@ -53,6 +57,7 @@ fn as_t_slice<T>(data: Cow<'_, [u8]>) -> &[T] {
 pub struct QTensor {
     storage: QStorage,
     shape: Shape,
+    calibration: Option<calibration::Hook>,
 }
 
 impl Device {
@@ -99,6 +104,17 @@ impl QStorage {
                 GgmlDType::Q5K => metal::load_quantized(d, as_t_slice::<BlockQ5K>(data)),
                 GgmlDType::Q6K => metal::load_quantized(d, as_t_slice::<BlockQ6K>(data)),
                 GgmlDType::Q8K => metal::load_quantized(d, as_t_slice::<BlockQ8K>(data)),
+                GgmlDType::IQ2XXS => metal::load_quantized(d, as_t_slice::<BlockIQ2XXS>(data)),
+                GgmlDType::IQ2XS => metal::load_quantized(d, as_t_slice::<BlockIQ2XS>(data)),
+                GgmlDType::IQ3XXS => metal::load_quantized(d, as_t_slice::<BlockIQ3XXS>(data)),
+                GgmlDType::IQ4NL => metal::load_quantized(d, as_t_slice::<BlockIQ4NL>(data)),
+                GgmlDType::IQ3S => metal::load_quantized(d, as_t_slice::<BlockIQ3S>(data)),
+                GgmlDType::IQ2S => metal::load_quantized(d, as_t_slice::<BlockIQ2S>(data)),
+                GgmlDType::IQ4XS => metal::load_quantized(d, as_t_slice::<BlockIQ4XS>(data)),
+                GgmlDType::IQ1S => metal::load_quantized(d, as_t_slice::<BlockIQ1S>(data)),
+                GgmlDType::IQ1M => metal::load_quantized(d, as_t_slice::<BlockIQ1M>(data)),
+                GgmlDType::TQ1_0 => metal::load_quantized(d, as_t_slice::<BlockTQ1_0>(data)),
+                GgmlDType::TQ2_0 => metal::load_quantized(d, as_t_slice::<BlockTQ2_0>(data)),
                 GgmlDType::BF16 => metal::load_quantized(d, as_t_slice::<bf16>(data)),
             },
             Device::Cuda(d) => match dtype {
@@ -116,6 +132,17 @@ impl QStorage {
                 GgmlDType::Q5K => cuda::load_quantized(d, as_t_slice::<BlockQ5K>(data)),
                 GgmlDType::Q6K => cuda::load_quantized(d, as_t_slice::<BlockQ6K>(data)),
                 GgmlDType::Q8K => cuda::load_quantized(d, as_t_slice::<BlockQ8K>(data)),
+                GgmlDType::IQ2XXS => cuda::load_quantized(d, as_t_slice::<BlockIQ2XXS>(data)),
+                GgmlDType::IQ2XS => cuda::load_quantized(d, as_t_slice::<BlockIQ2XS>(data)),
+                GgmlDType::IQ3XXS => cuda::load_quantized(d, as_t_slice::<BlockIQ3XXS>(data)),
+                GgmlDType::IQ4NL => cuda::load_quantized(d, as_t_slice::<BlockIQ4NL>(data)),
+                GgmlDType::IQ3S => cuda::load_quantized(d, as_t_slice::<BlockIQ3S>(data)),
+                GgmlDType::IQ2S => cuda::load_quantized(d, as_t_slice::<BlockIQ2S>(data)),
+                GgmlDType::IQ4XS => cuda::load_quantized(d, as_t_slice::<BlockIQ4XS>(data)),
+                GgmlDType::IQ1S => cuda::load_quantized(d, as_t_slice::<BlockIQ1S>(data)),
+                GgmlDType::IQ1M => cuda::load_quantized(d, as_t_slice::<BlockIQ1M>(data)),
+                GgmlDType::TQ1_0 => cuda::load_quantized(d, as_t_slice::<BlockTQ1_0>(data)),
+                GgmlDType::TQ2_0 => cuda::load_quantized(d, as_t_slice::<BlockTQ2_0>(data)),
                 GgmlDType::BF16 => cuda::load_quantized(d, as_t_slice::<bf16>(data)),
             },
         }
@@ -156,7 +183,7 @@ impl QStorage {
     fn quantize(&mut self, src: &Storage) -> Result<()> {
         match (self, src) {
             (QStorage::Cpu(storage), Storage::Cpu(src)) => {
-                storage.from_float(src.as_slice::<f32>()?);
+                storage.from_float(src.as_slice::<f32>()?)?;
             }
             (QStorage::Metal(storage), Storage::Metal(src)) => storage.quantize(src)?,
             (QStorage::Cuda(storage), Storage::Cuda(src)) => storage.quantize(src)?,
@@ -173,7 +200,7 @@ impl QStorage {
     ) -> Result<()> {
         match (self, src) {
             (QStorage::Cpu(storage), Storage::Cpu(src)) => {
-                storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix_weights, n_per_row);
+                storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix_weights, n_per_row)?;
             }
             (QStorage::Metal(storage), Storage::Metal(src)) => {
                 storage.quantize_imatrix(src, imatrix_weights, n_per_row)?
@@ -189,7 +216,7 @@ impl QStorage {
     fn quantize_onto(&mut self, src: &Storage) -> Result<()> {
         match (self, src) {
             (QStorage::Cpu(storage), Storage::Cpu(src)) => {
-                storage.from_float(src.as_slice::<f32>()?);
+                storage.from_float(src.as_slice::<f32>()?)?;
             }
             (QStorage::Metal(storage), Storage::Cpu(src)) => storage.quantize_onto(src)?,
             (QStorage::Cuda(storage), Storage::Cpu(src)) => storage.quantize_onto(src)?,
@@ -206,7 +233,7 @@ impl QStorage {
     ) -> Result<()> {
         match (self, src) {
             (QStorage::Cpu(storage), Storage::Cpu(src)) => {
-                storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix_weights, n_per_row);
+                storage.from_float_imatrix(src.as_slice::<f32>()?, imatrix_weights, n_per_row)?;
             }
             (QStorage::Metal(storage), Storage::Cpu(src)) => {
                 storage.quantize_imatrix_onto(src, imatrix_weights, n_per_row)?
@@ -267,6 +294,17 @@ pub enum GgmlDType {
     Q5K,
     Q6K,
     Q8K,
+    IQ2XXS,
+    IQ2XS,
+    IQ3XXS,
+    IQ4NL,
+    IQ3S,
+    IQ2S,
+    IQ4XS,
+    IQ1S,
+    IQ1M,
+    TQ1_0,
+    TQ2_0,
 }
 
 impl GgmlDType {
@@ -286,8 +324,19 @@ impl GgmlDType {
             13 => Self::Q5K,
             14 => Self::Q6K,
             15 => Self::Q8K,
+            16 => Self::IQ2XXS,
+            17 => Self::IQ2XS,
+            18 => Self::IQ3XXS,
+            19 => Self::IQ1S,
+            20 => Self::IQ4NL,
+            21 => Self::IQ3S,
+            22 => Self::IQ2S,
+            23 => Self::IQ4XS,
+            29 => Self::IQ1M,
             // https://github.com/ggerganov/ggml/blob/29d87fc6676e7ed0cdfdec0804b06001d9c2bb44/include/ggml.h#L389
             30 => Self::BF16,
+            34 => Self::TQ1_0,
+            35 => Self::TQ2_0,
             _ => crate::bail!("unknown dtype for tensor {u}"),
         };
         Ok(dtype)
@@ -309,8 +358,19 @@ impl GgmlDType {
             Self::Q5K => 13,
             Self::Q6K => 14,
             Self::Q8K => 15,
+            Self::IQ2XXS => 16,
+            Self::IQ2XS => 17,
+            Self::IQ3XXS => 18,
+            Self::IQ1S => 19,
+            Self::IQ4NL => 20,
+            Self::IQ3S => 21,
+            Self::IQ2S => 22,
+            Self::IQ4XS => 23,
+            Self::IQ1M => 29,
             // https://github.com/ggerganov/ggml/blob/29d87fc6676e7ed0cdfdec0804b06001d9c2bb44/include/ggml.h#L389
             Self::BF16 => 30,
+            Self::TQ1_0 => 34,
+            Self::TQ2_0 => 35,
         }
     }
 
@@ -331,6 +391,38 @@ impl GgmlDType {
             Self::Q5K => Box::new(vec![BlockQ5K::zeros(); elem_count / BlockQ5K::BLCK_SIZE]),
             Self::Q6K => Box::new(vec![BlockQ6K::zeros(); elem_count / BlockQ6K::BLCK_SIZE]),
             Self::Q8K => Box::new(vec![BlockQ8K::zeros(); elem_count / BlockQ8K::BLCK_SIZE]),
+            Self::IQ2XXS => Box::new(vec![
+                BlockIQ2XXS::zeros();
+                elem_count / BlockIQ2XXS::BLCK_SIZE
+            ]),
+            Self::IQ2XS => Box::new(vec![
+                BlockIQ2XS::zeros();
+                elem_count / BlockIQ2XS::BLCK_SIZE
+            ]),
+            Self::IQ3XXS => Box::new(vec![
+                BlockIQ3XXS::zeros();
+                elem_count / BlockIQ3XXS::BLCK_SIZE
+            ]),
+            Self::IQ4NL => Box::new(vec![
+                BlockIQ4NL::zeros();
+                elem_count / BlockIQ4NL::BLCK_SIZE
+            ]),
+            Self::IQ3S => Box::new(vec![BlockIQ3S::zeros(); elem_count / BlockIQ3S::BLCK_SIZE]),
+            Self::IQ2S => Box::new(vec![BlockIQ2S::zeros(); elem_count / BlockIQ2S::BLCK_SIZE]),
+            Self::IQ4XS => Box::new(vec![
+                BlockIQ4XS::zeros();
+                elem_count / BlockIQ4XS::BLCK_SIZE
+            ]),
+            Self::IQ1S => Box::new(vec![BlockIQ1S::zeros(); elem_count / BlockIQ1S::BLCK_SIZE]),
+            Self::IQ1M => Box::new(vec![BlockIQ1M::zeros(); elem_count / BlockIQ1M::BLCK_SIZE]),
+            Self::TQ1_0 => Box::new(vec![
+                BlockTQ1_0::zeros();
+                elem_count / BlockTQ1_0::BLCK_SIZE
+            ]),
+            Self::TQ2_0 => Box::new(vec![
+                BlockTQ2_0::zeros();
+                elem_count / BlockTQ2_0::BLCK_SIZE
+            ]),
             Self::BF16 => Box::new(vec![bf16::zeros(); elem_count]),
         }
     }
@@ -351,10 +443,88 @@ impl GgmlDType {
             Self::Q5K => Box::new(as_t_slice::<BlockQ5K>(data).to_vec()),
             Self::Q6K => Box::new(as_t_slice::<BlockQ6K>(data).to_vec()),
             Self::Q8K => Box::new(as_t_slice::<BlockQ8K>(data).to_vec()),
+            Self::IQ2XXS => Box::new(as_t_slice::<BlockIQ2XXS>(data).to_vec()),
+            Self::IQ2XS => Box::new(as_t_slice::<BlockIQ2XS>(data).to_vec()),
+            Self::IQ3XXS => Box::new(as_t_slice::<BlockIQ3XXS>(data).to_vec()),
+            Self::IQ4NL => Box::new(as_t_slice::<BlockIQ4NL>(data).to_vec()),
+            Self::IQ3S => Box::new(as_t_slice::<BlockIQ3S>(data).to_vec()),
+            Self::IQ2S => Box::new(as_t_slice::<BlockIQ2S>(data).to_vec()),
+            Self::IQ4XS => Box::new(as_t_slice::<BlockIQ4XS>(data).to_vec()),
+            Self::IQ1S => Box::new(as_t_slice::<BlockIQ1S>(data).to_vec()),
+            Self::IQ1M => Box::new(as_t_slice::<BlockIQ1M>(data).to_vec()),
+            Self::TQ1_0 => Box::new(as_t_slice::<BlockTQ1_0>(data).to_vec()),
+            Self::TQ2_0 => Box::new(as_t_slice::<BlockTQ2_0>(data).to_vec()),
             Self::BF16 => Box::new(as_t_slice::<bf16>(data).to_vec()),
         }
     }
 
+    /// Cpu storage borrowing `size_in_bytes` bytes at `offset` from a memory mapped file, the
+    /// offset has to be aligned for the block type.
+    pub(crate) fn cpu_mmaped(
+        &self,
+        mmap: std::sync::Arc<memmap2::Mmap>,
+        offset: usize,
+        size_in_bytes: usize,
+    ) -> Result<Box<dyn QuantizedType>> {
+        let storage: Box<dyn QuantizedType> = match self {
+            Self::F32 => Box::new(MmapedBlocks::<f32>::new(mmap, offset, size_in_bytes)?),
+            Self::F16 => Box::new(MmapedBlocks::<f16>::new(mmap, offset, size_in_bytes)?),
+            Self::Q4_0 => Box::new(MmapedBlocks::<BlockQ4_0>::new(mmap, offset, size_in_bytes)?),
+            Self::Q4_1 => Box::new(MmapedBlocks::<BlockQ4_1>::new(mmap, offset, size_in_bytes)?),
+            Self::Q5_0 => Box::new(MmapedBlocks::<BlockQ5_0>::new(mmap, offset, size_in_bytes)?),
+            Self::Q5_1 => Box::new(MmapedBlocks::<BlockQ5_1>::new(mmap, offset, size_in_bytes)?),
+            Self::Q8_0 => Box::new(MmapedBlocks::<BlockQ8_0>::new(mmap, offset, size_in_bytes)?),
+            Self::Q8_1 => Box::new(MmapedBlocks::<BlockQ8_1>::new(mmap, offset, size_in_bytes)?),
+            Self::Q2K => Box::new(MmapedBlocks::<BlockQ2K>::new(mmap, offset, size_in_bytes)?),
+            Self::Q3K => Box::new(MmapedBlocks::<BlockQ3K>::new(mmap, offset, size_in_bytes)?),
+            Self::Q4K => Box::new(MmapedBlocks::<BlockQ4K>::new(mmap, offset, size_in_bytes)?),
+            Self::Q5K => Box::new(MmapedBlocks::<BlockQ5K>::new(mmap, offset, size_in_bytes)?),
+            Self::Q6K => Box::new(MmapedBlocks::<BlockQ6K>::new(mmap, offset, size_in_bytes)?),
+            Self::Q8K => Box::new(MmapedBlocks::<BlockQ8K>::new(mmap, offset, size_in_bytes)?),
+            Self::IQ2XXS => Box::new(MmapedBlocks::<BlockIQ2XXS>::new(
+                mmap,
+                offset,
+                size_in_bytes,
+            )?),
+            Self::IQ2XS => Box::new(MmapedBlocks::<BlockIQ2XS>::new(
+                mmap,
+                offset,
+                size_in_bytes,
+            )?),
+            Self::IQ3XXS => Box::new(MmapedBlocks::<BlockIQ3XXS>::new(
+                mmap,
+                offset,
+                size_in_bytes,
+            )?),
+            Self::IQ4NL => Box::new(MmapedBlocks::<BlockIQ4NL>::new(
+                mmap,
+                offset,
+                size_in_bytes,
+            )?),
+            Self::IQ3S => Box::new(MmapedBlocks::<BlockIQ3S>::new(mmap, offset, size_in_bytes)?),
+            Self::IQ2S => Box::new(MmapedBlocks::<BlockIQ2S>::new(mmap, offset, size_in_bytes)?),
+            Self::IQ4XS => Box::new(MmapedBlocks::<BlockIQ4XS>::new(
+                mmap,
+                offset,
+                size_in_bytes,
+            )?),
+            Self::IQ1S => Box::new(MmapedBlocks::<BlockIQ1S>::new(mmap, offset, size_in_bytes)?),
+            Self::IQ1M => Box::new(MmapedBlocks::<BlockIQ1M>::new(mmap, offset, size_in_bytes)?),
+            Self::TQ1_0 => Box::new(MmapedBlocks::<BlockTQ1_0>::new(
+                mmap,
+                offset,
+                size_in_bytes,
+            )?),
+            Self::TQ2_0 => Box::new(MmapedBlocks::<BlockTQ2_0>::new(
+                mmap,
+                offset,
+                size_in_bytes,
+            )?),
+            Self::BF16 => Box::new(MmapedBlocks::<bf16>::new(mmap, offset, size_in_bytes)?),
+        };
+        Ok(storage)
+    }
+
     /// The type size for blocks in bytes.
     pub fn type_size(&self) -> usize {
         use k_quants::*;
@@ -374,6 +544,17 @@ impl GgmlDType {
             Self::Q5K => std::mem::size_of::<BlockQ5K>(),
             Self::Q6K => std::mem::size_of::<BlockQ6K>(),
             Self::Q8K => std::mem::size_of::<BlockQ8K>(),
+            Self::IQ2XXS => std::mem::size_of::<BlockIQ2XXS>(),
+            Self::IQ2XS => std::mem::size_of::<BlockIQ2XS>(),
+            Self::IQ3XXS => std::mem::size_of::<BlockIQ3XXS>(),
+            Self::IQ4NL => std::mem::size_of::<BlockIQ4NL>(),
+            Self::IQ3S => std::mem::size_of::<BlockIQ3S>(),
+            Self::IQ2S => std::mem::size_of::<BlockIQ2S>(),
+            Self::IQ4XS => std::mem::size_of::<BlockIQ4XS>(),
+            Self::IQ1S => std::mem::size_of::<BlockIQ1S>(),
+            Self::IQ1M => std::mem::size_of::<BlockIQ1M>(),
+            Self::TQ1_0 => std::mem::size_of::<BlockTQ1_0>(),
+            Self::TQ2_0 => std::mem::size_of::<BlockTQ2_0>(),
         }
     }
 
@@ -389,8 +570,59 @@ impl GgmlDType {
             Self::Q8_0 => k_quants::QK8_0,
             Self::Q8_1 => k_quants::QK8_1,
             Self::Q2K | Self::Q3K | Self::Q4K | Self::Q5K | Self::Q6K | Self::Q8K => k_quants::QK_K,
+            Self::IQ4NL => k_quants::QK4_NL,
+            Self::IQ2XXS
+            | Self::IQ2XS
+            | Self::IQ2S
+            | Self::IQ3XXS
+            | Self::IQ3S
+            | Self::IQ4XS
+            | Self::IQ1S
+            | Self::IQ1M
+            | Self::TQ1_0
+            | Self::TQ2_0 => k_quants::QK_K,
         }
     }
+
+    /// Returns true if [`QTensor::quantize_imatrix`] can use an importance matrix with this
+    /// type, i.e. for the k-quants and i-quants. The ternary types round each value to the
+    /// closest of `-d`, `0` and `d` so an importance matrix has no effect on them.
+    pub fn supports_imatrix(&self) -> bool {
+        matches!(
+            self,
+            Self::Q2K
+                | Self::Q3K
+                | Self::Q4K
+                | Self::Q5K
+                | Self::Q6K
+                | Self::IQ2XXS
+                | Self::IQ2XS
+                | Self::IQ2S
+                | Self::IQ3XXS
+                | Self::IQ3S
+                | Self::IQ4NL
+                | Self::IQ4XS
+                | Self::IQ1S
+                | Self::IQ1M
+        )
+    }
+
+    /// The i-quants and ternary types only have cpu kernels, on other devices they have to be
+    /// dequantized before being used in a matmul.
+    pub fn is_cpu_only(&self) -> bool {
+        matches!(
+            self,
+            Self::IQ2XXS
+                | Self::IQ2XS
+                | Self::IQ2S
+                | Self::IQ3XXS
+                | Self::IQ3S
+                | Self::IQ4NL
+                | Self::IQ4XS
+                | Self::IQ1S
+                | Self::IQ1M
+        )
+    }
 }
 
 // A version of GgmlType without `vec_dot` so that it can be dyn boxed.
@@ -403,9 +635,14 @@ pub trait QuantizedType: Send + Sync {
     fn as_ptr(&self) -> *const u8;
     fn block_size(&self) -> usize;
     #[allow(clippy::wrong_self_convention)]
-    fn from_float(&mut self, xs: &[f32]);
+    fn from_float(&mut self, xs: &[f32]) -> Result<()>;
     #[allow(clippy::wrong_self_convention)]
-    fn from_float_imatrix(&mut self, xs: &[f32], imatrix_weights: &[f32], n_per_row: usize);
+    fn from_float_imatrix(
+        &mut self,
+        xs: &[f32],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()>;
     fn size(&self) -> usize;
 }
 
@@ -421,12 +658,19 @@ impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for Vec<T> {
         self.len() * core::mem::size_of::<T>()
     }
 
-    fn from_float(&mut self, xs: &[f32]) {
-        T::from_float(xs, self)
+    fn from_float(&mut self, xs: &[f32]) -> Result<()> {
+        T::from_float(xs, self);
+        Ok(())
     }
 
-    fn from_float_imatrix(&mut self, xs: &[f32], imatrix_weights: &[f32], n_per_row: usize) {
-        T::from_float_imatrix(xs, self, imatrix_weights, n_per_row)
+    fn from_float_imatrix(
+        &mut self,
+        xs: &[f32],
+        imatrix_weights: &[f32],
+        n_per_row: usize,
+    ) -> Result<()> {
+        T::from_float_imatrix(xs, self, imatrix_weights, n_per_row);
+        Ok(())
     }
 
     fn dtype(&self) -> GgmlDType {
@@ -452,6 +696,104 @@ impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for Vec<T> {
     }
 }
 
+/// Quantized blocks borrowed from a read-only memory mapping, see
+/// [`gguf_file::MmapedFile`]. The pages are shared with the page cache and so with the other
+/// processes using the same file.
+pub struct MmapedBlocks<T> {
+    mmap: std::sync::Arc<memmap2::Mmap>,
+    offset: usize,
+    len: usize,
+    phantom: std::marker::PhantomData<T>,
+}
+
+impl<T> MmapedBlocks<T> {
+    fn new(
+        mmap: std::sync::Arc<memmap2::Mmap>,
+        offset: usize,
+        size_in_bytes: usize,
+    ) -> Result<Self> {
+        let size = std::mem::size_of::<T>();
+        let align = std::mem::align_of::<T>();
+        match offset.checked_add(size_in_bytes) {
+            Some(end) if end <= mmap.len() => {}
+            _ => crate::bail!(
+                "mapped range {offset}+{size_in_bytes} is out of bounds for a file of {} bytes",
+                mmap.len()
+            ),
+        }
+        if !size_in_bytes.is_multiple_of(size) {
+            crate::bail!("mapped size {size_in_bytes} is not a multiple of the block size {size}")
+        }
+        if !(mmap.as_ptr() as usize + offset).is_multiple_of(align) {
+            crate::bail!("mapped offset {offset} is not aligned on {align} bytes")
+        }
+        Ok(Self {
+            mmap,
+            offset,
+            len: size_in_bytes / size,
+            phantom: std::marker::PhantomData,
+        })
+    }
+
+    fn as_slice(&self) -> &[T] {
+        // Safety: the bounds and alignment are checked on creation and the mapping is kept
+        // alive by the arc.
+        unsafe {
+            let ptr = self.mmap.as_ptr().add(self.offset) as *const T;
+            std::slice::from_raw_parts(ptr, self.len)
+        }
+    }
+}
+
+impl<T: k_quants::GgmlType + Send + Sync> QuantizedType for MmapedBlocks<T> {
+    fn matmul_t(&self, mkn: (usize, usize, usize), lhs: &[f32], dst: &mut [f32]) -> Result<()> {
+        k_quants::matmul(mkn, lhs, self.as_slice(), dst)
+    }
+    fn matmul_t_f16(&self, mkn: (usize, usize, usize), lhs: &[f16], dst: &mut [f16]) -> Result<()> {
+        k_quants::matmul_f16(mkn, lhs, self.as_slice(), dst)
+    }
+
+    fn size(&self) -> usize {
+        self.len * core::mem::size_of::<T>()
+    }
+
+    // The mapped blocks are read-only, quantizing always creates a new storage.
+    fn from_float(&mut self, _xs: &[f32]) -> Result<()> {
+        crate::bail!("cannot quantize into a memory mapped storage")
+    }
+
+    fn from_float_imatrix(
+        &mut self,
+        _xs: &[f32],
+        _imatrix_weights: &[f32],
+        _n_per_row: usize,
+    ) -> Result<()> {
+        crate::bail!("cannot quantize into a memory mapped storage")
+    }
+
+    fn dtype(&self) -> GgmlDType {
+        T::DTYPE
+    }
+
+    fn block_size(&self) -> usize {
+        T::BLCK_SIZE
+    }
+
+    fn dequantize(&self, elem_count: usize) -> Result<CpuStorage> {
+        let mut ys = vec![0.0f32; elem_count];
+        T::to_float(self.as_slice(), &mut ys);
+        Ok(CpuStorage::F32(ys))
+    }
+
+    fn storage_size_in_bytes(&self) -> usize {
+        self.len * std::mem::size_of::<T>()
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        self.as_slice().as_ptr() as *const u8
+    }
+}
+
 impl std::fmt::Debug for QTensor {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "QTensor[{:?}; {:?}]", self.shape, self.dtype())
@@ -476,7 +818,11 @@ impl QTensor {
     pub fn new<S: Into<Shape>>(storage: QStorage, shape: S) -> Result<Self> {
         let shape = shape.into();
         check_shape(&shape, storage.block_size())?;
-        Ok(Self { storage, shape })
+        Ok(Self {
+            storage,
+            shape,
+            calibration: None,
+        })
     }
 
     pub fn quantize(src: &Tensor, dtype: GgmlDType) -> Result<Self> {
@@ -496,6 +842,7 @@ impl QTensor {
         Ok(Self {
             storage,
             shape: shape.clone(),
+            calibration: None,
         })
     }
 
@@ -504,6 +851,9 @@ impl QTensor {
         imatrix_weights: &[f32],
         dtype: GgmlDType,
     ) -> Result<Self> {
+        if !dtype.supports_imatrix() {
+            crate::bail!("quantization with an imatrix is not supported for {dtype:?}")
+        }
         // (n_per_row/QK_K-1)*QK_K+(QK_K/32-1)*32+32=n_per_row
         // Size of imatrix == last dim of tensor
         let n_per_row = src.dim(D::Minus1)?;
@@ -531,6 +881,7 @@ impl QTensor {
         Ok(Self {
             storage,
             shape: shape.clone(),
+            calibration: None,
         })
     }
 
@@ -547,6 +898,9 @@ impl QTensor {
                 src.device()
             )
         }
+        if !dtype.supports_imatrix() {
+            crate::bail!("quantization with an imatrix is not supported for {dtype:?}")
+        }
         // (n_per_row/QK_K-1)*QK_K+(QK_K/32-1)*32+32=n_per_row
         // Size of imatrix == last dim of tensor
         let n_per_row = src.dim(D::Minus1)?;
@@ -574,6 +928,7 @@ impl QTensor {
         Ok(Self {
             storage,
             shape: shape.clone(),
+            calibration: None,
         })
     }
 
@@ -602,6 +957,7 @@ impl QTensor {
         Ok(Self {
             storage,
             shape: shape.clone(),
+            calibration: None,
         })
     }
 
@@ -621,6 +977,17 @@ impl QTensor {
         &self.shape
     }
 
+    /// Attaches a calibration hook, the [`QMatMul`] layers using this tensor record their
+    /// inputs with it.
+    pub fn with_calibration(mut self, hook: calibration::Hook) -> Self {
+        self.calibration = Some(hook);
+        self
+    }
+
+    pub fn calibration(&self) -> Option<&calibration::Hook> {
+        self.calibration.as_ref()
+    }
+
     pub fn dequantize(&self, device: &Device) -> Result<Tensor> {
         let storage = self.storage.dequantize(self.shape.elem_count())?;
         let none = crate::op::BackpropOp::none();
@@ -695,6 +1062,10 @@ pub enum QMatMul {
     QTensor(std::sync::Arc<QTensor>),
     Tensor(Tensor),
     TensorF16(Tensor),
+    /// Per-channel or per-group integer weights, see [`int_quants`].
+    Int(std::sync::Arc<int_quants::IntQTensor>),
+    /// Weights in one of the microscaling formats, see [`mx`].
+    Mx(std::sync::Arc<mx::MxTensor>),
 }
 
 thread_local! {
@@ -721,8 +1092,13 @@ thread_local! {
 
 impl QMatMul {
     pub fn from_arc(qtensor: std::sync::Arc<QTensor>) -> Result<Self> {
+        // Keep the hooked weights quantized so that the matmul inputs get recorded.
+        if qtensor.calibration.is_some() && qtensor.device().is_cpu() {
+            return Ok(Self::QTensor(qtensor));
+        }
         let dequantize = match qtensor.dtype() {
             GgmlDType::F32 | GgmlDType::F16 | GgmlDType::BF16 => true,
+            dtype if dtype.is_cpu_only() && !qtensor.device().is_cpu() => true,
             _ => DEQUANTIZE_ALL.with(|b| *b),
         };
         let t = if dequantize {
@@ -741,11 +1117,24 @@ impl QMatMul {
         Self::from_arc(std::sync::Arc::new(qtensor))
     }
 
+    pub fn from_int(qtensor: int_quants::IntQTensor) -> Self {
+        Self::Int(std::sync::Arc::new(qtensor))
+    }
+
+    pub fn from_mx(qtensor: mx::MxTensor) -> Self {
+        Self::Mx(std::sync::Arc::new(qtensor))
+    }
+
     pub fn dequantize_f16(&self) -> Result<Tensor> {
         match self {
             Self::QTensor(t) => t.dequantize_f16(&t.device()),
             Self::Tensor(t) => t.to_dtype(DType::F16),
             Self::TensorF16(t) => Ok(t.clone()),
+            Self::Int(t) => t
+                .dequantize(&Device::Cpu)?
+                .reshape(t.matrix_dims())?
+                .to_dtype(DType::F16),
+            Self::Mx(t) => t.dequantize(&Device::Cpu)?.to_dtype(DType::F16),
         }
     }
 
@@ -839,6 +1228,9 @@ impl crate::CustomOp1 for QTensor {
             QStorage::Metal(metal) => metal,
             _ => unreachable!("Cannot call metal matmul on non metal QTensor"),
         };
+        if self.dtype().is_cpu_only() {
+            crate::bail!("no metal matmul kernel for {:?}", self.dtype())
+        }
         self_storage.fwd(&self.shape, storage, layout)
     }
 
@@ -853,12 +1245,70 @@ impl crate::CustomOp1 for QTensor {
         };
         self_storage.fwd(&self.shape, storage, layout)
     }
+
+    // The weights are frozen, only the input gets a gradient. This uses the dequantized weights
+    // as there is no quantized kernel for the transposed matmul.
+    fn bwd(&self, _arg: &Tensor, _res: &Tensor, grad_res: &Tensor) -> Result<Option<Tensor>> {
+        let w = self
+            .dequantize(grad_res.device())?
+            .to_dtype(grad_res.dtype())?;
+        Ok(Some(grad_res.broadcast_matmul(&w)?))
+    }
+}
+
+// The quantized matmul op as applied by [`QMatMul`], sharing the weights with the module so
+// that the backward pass can use them.
+struct QMatMulOp(std::sync::Arc<QTensor>);
+
+impl crate::CustomOp1 for QMatMulOp {
+    fn name(&self) -> &'static str {
+        crate::CustomOp1::name(self.0.as_ref())
+    }
+
+    fn cpu_fwd(
+        &self,
+        storage: &crate::CpuStorage,
+        layout: &crate::Layout,
+    ) -> Result<(crate::CpuStorage, Shape)> {
+        crate::CustomOp1::cpu_fwd(self.0.as_ref(), storage, layout)
+    }
+
+    fn metal_fwd(
+        &self,
+        storage: &crate::MetalStorage,
+        layout: &crate::Layout,
+    ) -> Result<(crate::MetalStorage, Shape)> {
+        crate::CustomOp1::metal_fwd(self.0.as_ref(), storage, layout)
+    }
+
+    fn cuda_fwd(
+        &self,
+        storage: &crate::CudaStorage,
+        layout: &crate::Layout,
+    ) -> Result<(crate::CudaStorage, Shape)> {
+        crate::CustomOp1::cuda_fwd(self.0.as_ref(), storage, layout)
+    }
+
+    fn bwd(&self, arg: &Tensor, res: &Tensor, grad_res: &Tensor) -> Result<Option<Tensor>> {
+        crate::CustomOp1::bwd(self.0.as_ref(), arg, res, grad_res)
+    }
 }
 
 impl crate::Module for QMatMul {
     fn forward(&self, xs: &Tensor) -> Result<Tensor> {
         match self {
-            Self::QTensor(t) => xs.apply_op1_no_bwd(t.as_ref()),
+            Self::QTensor(t) => {
+                if let Some(hook) = &t.calibration {
+                    hook.record(xs)?
+                }
+                if xs.track_op() {
+                    // Gradients flow back to the input through the frozen weights, e.g. when
+                    // training adapters on top of a quantized model.
+                    xs.apply_op1(QMatMulOp(t.clone()))
+                } else {
+                    xs.apply_op1_no_bwd(t.as_ref())
+                }
+            }
             Self::Tensor(w) => {
                 let w = match *xs.dims() {
                     [b1, b2, _, _] => w.broadcast_left((b1, b2))?.t()?,
@@ -876,6 +1326,8 @@ impl crate::Module for QMatMul {
                 };
                 xs.to_dtype(DType::F16)?.matmul(&w)?.to_dtype(in_dtype)
             }
+            Self::Int(t) => t.matmul_t(xs),
+            Self::Mx(t) => t.matmul_t(xs),
         }
     }
 }
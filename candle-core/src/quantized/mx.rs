@@ -0,0 +1,411 @@
+//! Low precision floats and the OCP microscaling (MX) formats.
+//!
+//! The MX formats split the last dimension of a tensor in blocks of 32 elements sharing a power
+//! of two scale stored as an E8M0 exponent, the elements use one of the fp8, fp6 or fp4 float
+//! formats. The fp4 elements are packed two per byte, the low nibble first, the others use one
+//! byte per element.
+//!
+//! The element codecs are also used by the cpu conversions of the `F6E2M3`, `F6E3M2`, `F4` and
+//! `F8E8M0` dtypes.
+use crate::{DType, Device, Result, Shape, Tensor};
+use rayon::prelude::*;
+use std::sync::OnceLock;
+
+/// The number of elements sharing a scale.
+pub const MX_BLOCK_SIZE: usize = 32;
+
+/// The element formats of the MX types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MxElement {
+    /// 8-bit float with 4 exponent bits and 3 mantissa bits, no infinities.
+    Fp8E4M3,
+    /// 8-bit float with 5 exponent bits and 2 mantissa bits.
+    Fp8E5M2,
+    /// 6-bit float with 2 exponent bits and 3 mantissa bits.
+    Fp6E2M3,
+    /// 6-bit float with 3 exponent bits and 2 mantissa bits.
+    Fp6E3M2,
+    /// 4-bit float with 2 exponent bits and 1 mantissa bit.
+    Fp4E2M1,
+}
+
+struct Codec {
+    // The values of all the codes, NaN for the codes that do not encode a finite value.
+    values: Vec<f32>,
+    // The finite non-negative values in increasing order with their codes.
+    positives: Vec<(f32, u8)>,
+}
+
+impl MxElement {
+    fn exponent_mantissa_bits(&self) -> (u32, u32) {
+        match self {
+            Self::Fp8E4M3 => (4, 3),
+            Self::Fp8E5M2 => (5, 2),
+            Self::Fp6E2M3 => (2, 3),
+            Self::Fp6E3M2 => (3, 2),
+            Self::Fp4E2M1 => (2, 1),
+        }
+    }
+
+    /// The number of bits per element.
+    pub fn bits(&self) -> u32 {
+        let (e, m) = self.exponent_mantissa_bits();
+        1 + e + m
+    }
+
+    fn decode_slow(&self, code: u8) -> f32 {
+        let (e_bits, m_bits) = self.exponent_mantissa_bits();
+        let bias = (1 << (e_bits - 1)) - 1;
+        let sign = if (code >> (e_bits + m_bits)) & 1 == 1 {
+            -1f32
+        } else {
+            1f32
+        };
+        let exp = ((code >> m_bits) & ((1 << e_bits) - 1)) as i32;
+        let man = (code & ((1 << m_bits) - 1)) as f32 / (1 << m_bits) as f32;
+        let max_exp = (1 << e_bits) - 1;
+        match self {
+            Self::Fp8E4M3 if exp == max_exp && man == 7. / 8. => return f32::NAN,
+            Self::Fp8E5M2 if exp == max_exp && man == 0. => return sign * f32::INFINITY,
+            Self::Fp8E5M2 if exp == max_exp => return f32::NAN,
+            _ => {}
+        }
+        if exp == 0 {
+            sign * man * 2f32.powi(1 - bias)
+        } else {
+            sign * (1. + man) * 2f32.powi(exp - bias)
+        }
+    }
+
+    fn codec(&self) -> &'static Codec {
+        static CODECS: [OnceLock<Codec>; 5] = [
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+        ];
+        let idx = match self {
+            Self::Fp8E4M3 => 0,
+            Self::Fp8E5M2 => 1,
+            Self::Fp6E2M3 => 2,
+            Self::Fp6E3M2 => 3,
+            Self::Fp4E2M1 => 4,
+        };
+        CODECS[idx].get_or_init(|| {
+            let n_codes = 1usize << self.bits();
+            let values = (0..n_codes)
+                .map(|c| self.decode_slow(c as u8))
+                .collect::<Vec<_>>();
+            // The positive codes are ordered like their values.
+            let positives = values[..n_codes / 2]
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| v.is_finite())
+                .map(|(c, &v)| (v, c as u8))
+                .collect();
+            Codec { values, positives }
+        })
+    }
+
+    /// The value of a code.
+    pub fn decode(&self, code: u8) -> f32 {
+        self.codec().values[code as usize]
+    }
+
+    /// The largest finite value.
+    pub fn max_value(&self) -> f32 {
+        self.codec().positives.last().map_or(0., |v| v.0)
+    }
+
+    /// The code of the nearest value, ties to even, the values out of range saturate.
+    pub fn encode(&self, v: f32) -> u8 {
+        let codec = self.codec();
+        let sign_bit = 1u8 << (self.bits() - 1);
+        if v.is_nan() {
+            return match self {
+                Self::Fp8E4M3 => 0x7f,
+                Self::Fp8E5M2 => 0x7e,
+                // The fp6 and fp4 formats have no NaN.
+                _ => 0,
+            };
+        }
+        let sign = if v.is_sign_negative() { sign_bit } else { 0 };
+        let a = v.abs();
+        let positives = &codec.positives;
+        let idx = positives.partition_point(|&(p, _)| p < a);
+        let code = if idx == 0 {
+            positives[0].1
+        } else if idx == positives.len() {
+            positives[idx - 1].1
+        } else {
+            let (hi, hi_code) = positives[idx];
+            let (lo, lo_code) = positives[idx - 1];
+            if a - lo < hi - a || (a - lo == hi - a && lo_code.is_multiple_of(2)) {
+                lo_code
+            } else {
+                hi_code
+            }
+        };
+        code | sign
+    }
+}
+
+/// The value of an E8M0 scale, a biased power of two exponent.
+pub fn e8m0_to_f32(e: u8) -> f32 {
+    if e == 0xff {
+        f32::NAN
+    } else {
+        2f32.powi(e as i32 - 127)
+    }
+}
+
+/// The E8M0 encoding of the power of two at or below `v`.
+pub fn f32_to_e8m0(v: f32) -> u8 {
+    if v.is_nan() {
+        return 0xff;
+    }
+    if v <= 0. {
+        return 0;
+    }
+    (v.log2().floor() as i32 + 127).clamp(0, 254) as u8
+}
+
+// Unpacks the elements stored in `data` starting at element `start`.
+pub(crate) fn decode_packed(element: MxElement, data: &[u8], start: usize, dst: &mut [f32]) {
+    match element.bits() {
+        8 => {
+            for (d, &c) in dst.iter_mut().zip(data[start..].iter()) {
+                *d = element.decode(c)
+            }
+        }
+        4 => {
+            for (i, d) in dst.iter_mut().enumerate() {
+                let i = start + i;
+                *d = element.decode((data[i / 2] >> (4 * (i % 2))) & 0x0f)
+            }
+        }
+        // Four 6-bit values per three bytes, in little endian bit order.
+        _ => {
+            for (i, d) in dst.iter_mut().enumerate() {
+                let bit = (start + i) * 6;
+                let (byte, shift) = (bit / 8, bit % 8);
+                let mut code = (data[byte] >> shift) as u16;
+                if shift > 2 {
+                    code |= (data[byte + 1] as u16) << (8 - shift)
+                }
+                *d = element.decode(code as u8 & 0x3f)
+            }
+        }
+    }
+}
+
+pub(crate) fn encode_packed(element: MxElement, src: &[f32]) -> Vec<u8> {
+    match element.bits() {
+        8 => src.iter().map(|&v| element.encode(v)).collect(),
+        4 => src
+            .chunks(2)
+            .map(|v| element.encode(v[0]) | (v.get(1).map_or(0, |&v| element.encode(v)) << 4))
+            .collect(),
+        _ => {
+            let mut data = vec![0u8; (src.len() * 6).div_ceil(8)];
+            for (i, &v) in src.iter().enumerate() {
+                let code = element.encode(v) as u16;
+                let bit = i * 6;
+                let (byte, shift) = (bit / 8, bit % 8);
+                data[byte] |= (code << shift) as u8;
+                if shift > 2 {
+                    data[byte + 1] |= (code >> (8 - shift)) as u8
+                }
+            }
+            data
+        }
+    }
+}
+
+/// The element format of the low precision dtypes stored as packed codes.
+pub(crate) fn dtype_element(dtype: DType) -> Option<MxElement> {
+    match dtype {
+        DType::F6E2M3 => Some(MxElement::Fp6E2M3),
+        DType::F6E3M2 => Some(MxElement::Fp6E3M2),
+        DType::F4 => Some(MxElement::Fp4E2M1),
+        _ => None,
+    }
+}
+
+/// A tensor in one of the MX formats, stored on the cpu.
+#[derive(Debug, Clone)]
+pub struct MxTensor {
+    element: MxElement,
+    data: Vec<u8>,
+    // One E8M0 scale per block of the last dimension.
+    scales: Vec<u8>,
+    shape: Shape,
+}
+
+impl MxTensor {
+    /// Quantizes a tensor whose last dimension is a multiple of the block size.
+    pub fn quantize(xs: &Tensor, element: MxElement) -> Result<Self> {
+        let shape = xs.shape().clone();
+        let k = shape.dims().last().copied().unwrap_or(0);
+        if !k.is_multiple_of(MX_BLOCK_SIZE) || k == 0 {
+            crate::bail!("the last dimension of {shape:?} is not a multiple of {MX_BLOCK_SIZE}")
+        }
+        let xs = xs
+            .to_device(&Device::Cpu)?
+            .to_dtype(DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        let mut scales = Vec::with_capacity(xs.len() / MX_BLOCK_SIZE);
+        let mut scaled = Vec::with_capacity(xs.len());
+        for block in xs.chunks_exact(MX_BLOCK_SIZE) {
+            let amax = block.iter().fold(0f32, |m, v| m.max(v.abs()));
+            // The OCP specification derives the shared exponent from the exponent of the largest
+            // value, which saturates the values above the largest element. Rounding the scale up
+            // avoids the saturation.
+            let scale = if amax == 0. {
+                0
+            } else {
+                let e = (amax / element.max_value()).log2().ceil() as i32;
+                (e + 127).clamp(0, 254) as u8
+            };
+            let inv_scale = 1. / e8m0_to_f32(scale);
+            scaled.extend(block.iter().map(|v| v * inv_scale));
+            scales.push(scale);
+        }
+        Ok(Self {
+            element,
+            data: encode_packed(element, &scaled),
+            scales,
+            shape,
+        })
+    }
+
+    /// Builds a MXFP4 tensor from the packed blocks and scales of a gpt-oss style checkpoint,
+    /// `blocks` has shape `(..., n_blocks, 16)` and `scales` has shape `(..., n_blocks)`, both
+    /// using the u8 dtype.
+    pub fn from_mxfp4_blocks(blocks: &Tensor, scales: &Tensor) -> Result<Self> {
+        let dims = blocks.dims();
+        if dims.len() < 2 || dims[dims.len() - 1] != MX_BLOCK_SIZE / 2 {
+            crate::bail!("unexpected shape for the mxfp4 blocks {:?}", blocks.shape())
+        }
+        if scales.dims() != &dims[..dims.len() - 1] {
+            crate::bail!(
+                "mxfp4 scales {:?} incompatible with the blocks {:?}",
+                scales.shape(),
+                blocks.shape()
+            )
+        }
+        let mut shape = dims[..dims.len() - 1].to_vec();
+        *shape.last_mut().unwrap() *= MX_BLOCK_SIZE;
+        let to_bytes = |xs: &Tensor| -> Result<Vec<u8>> {
+            xs.to_device(&Device::Cpu)?.flatten_all()?.to_vec1::<u8>()
+        };
+        Ok(Self {
+            element: MxElement::Fp4E2M1,
+            data: to_bytes(blocks)?,
+            scales: to_bytes(scales)?,
+            shape: shape.into(),
+        })
+    }
+
+    pub fn element(&self) -> MxElement {
+        self.element
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    pub fn storage_size_in_bytes(&self) -> usize {
+        self.data.len() + self.scales.len()
+    }
+
+    // Decodes `dst.len()` elements starting at the block aligned element `start`.
+    fn decode_range(&self, start: usize, dst: &mut [f32]) {
+        decode_packed(self.element, &self.data, start, dst);
+        let scales = &self.scales[start / MX_BLOCK_SIZE..];
+        for (block, &scale) in dst.chunks_mut(MX_BLOCK_SIZE).zip(scales.iter()) {
+            let scale = e8m0_to_f32(scale);
+            block.iter_mut().for_each(|v| *v *= scale)
+        }
+    }
+
+    pub fn dequantize(&self, device: &Device) -> Result<Tensor> {
+        let mut ys = vec![0f32; self.shape.elem_count()];
+        self.decode_range(0, &mut ys);
+        Tensor::from_vec(ys, self.shape.clone(), device)
+    }
+
+    /// Computes `xs . w^T` for a 2d tensor `w` of shape `(n, k)`, `xs` has shape `(..., k)`. The
+    /// cpu implementation decodes the rows of the weights on the fly.
+    pub fn matmul_t(&self, xs: &Tensor) -> Result<Tensor> {
+        let (n, k) = self.shape.dims2()?;
+        let mut dims = xs.dims().to_vec();
+        if dims.last() != Some(&k) {
+            crate::bail!(
+                "input tensor {:?} incompatible with {:?}",
+                xs.shape(),
+                self.shape
+            )
+        }
+        if !xs.device().is_cpu() {
+            let w = self.dequantize(xs.device())?.to_dtype(xs.dtype())?;
+            return xs.broadcast_matmul(&w.t()?);
+        }
+        let lhs = xs.to_dtype(DType::F32)?.flatten_all()?.to_vec1::<f32>()?;
+        let m = lhs.len() / k;
+        let mut dst_t = vec![0f32; n * m];
+        dst_t
+            .par_chunks_exact_mut(m)
+            .enumerate()
+            .for_each(|(o, dst_t)| {
+                let mut w = vec![0f32; k];
+                self.decode_range(o * k, &mut w);
+                for (dst, x) in dst_t.iter_mut().zip(lhs.chunks_exact(k)) {
+                    unsafe { crate::cpu::vec_dot_f32(w.as_ptr(), x.as_ptr(), dst, k) }
+                }
+            });
+        let mut dst = vec![0f32; m * n];
+        for (o, dst_t) in dst_t.chunks_exact(m).enumerate() {
+            for (row, &v) in dst_t.iter().enumerate() {
+                dst[row * n + o] = v
+            }
+        }
+        *dims.last_mut().unwrap() = n;
+        Tensor::from_vec(dst, dims, &Device::Cpu)?.to_dtype(xs.dtype())
+    }
+}
+
+impl crate::Module for MxTensor {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        self.matmul_t(xs)
+    }
+}
+
+/// Dequantizes a fp8 weight with one scale per block of `block_size` rows and columns, as used
+/// by the DeepSeek checkpoints where the scales are stored as `weight_scale_inv`.
+pub fn dequantize_fp8_blockwise(
+    w: &Tensor,
+    scale_inv: &Tensor,
+    block_size: (usize, usize),
+) -> Result<Tensor> {
+    let (n, k) = w.dims2()?;
+    let (bn, bk) = block_size;
+    let (sn, sk) = scale_inv.dims2()?;
+    if sn != n.div_ceil(bn) || sk != k.div_ceil(bk) {
+        crate::bail!(
+            "fp8 scales {:?} incompatible with the weight {:?} and blocks {block_size:?}",
+            scale_inv.shape(),
+            w.shape()
+        )
+    }
+    let scale = scale_inv
+        .to_dtype(DType::F32)?
+        .reshape((sn, 1, sk, 1))?
+        .broadcast_as((sn, bn, sk, bk))?
+        .reshape((sn * bn, sk * bk))?
+        .narrow(0, 0, n)?
+        .narrow(1, 0, k)?;
+    w.to_dtype(DType::F32)?.mul(&scale)
+}
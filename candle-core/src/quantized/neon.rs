@@ -1,5 +1,11 @@
+use super::iq_quants::{
+    IQ1M_DELTA, IQ1S_DELTA, IQ1S_GRID, IQ2S_GRID, IQ2XS_GRID, IQ2XXS_GRID, IQ3S_GRID, IQ3XXS_GRID,
+    KSIGNS_IQ2XS, KVALUES_IQ4NL,
+};
 use super::k_quants::{
-    BlockQ2K, BlockQ3K, BlockQ4K, BlockQ4_0, BlockQ5K, BlockQ6K, BlockQ8K, BlockQ8_0, QK8_0, QK_K,
+    BlockIQ1M, BlockIQ1S, BlockIQ2S, BlockIQ2XS, BlockIQ2XXS, BlockIQ3S, BlockIQ3XXS, BlockIQ4NL,
+    BlockIQ4XS, BlockQ2K, BlockQ3K, BlockQ4K, BlockQ4_0, BlockQ5K, BlockQ6K, BlockQ8K, BlockQ8_0,
+    BlockTQ1_0, BlockTQ2_0, QK4_NL, QK8_0, QK_K,
 };
 use byteorder::{ByteOrder, LittleEndian};
 
@@ -11,9 +17,26 @@ use core::arch::arm::*;
 #[cfg(target_arch = "aarch64")]
 use core::arch::aarch64::*;
 
+// The products of `a` and `b` summed in 4 int32 lanes, only the sum of the lanes is meaningful as
+// the lanes differ between the two implementations.
+#[cfg(target_feature = "dotprod")]
+#[inline(always)]
+unsafe fn vdotq_s32(a: int8x16_t, b: int8x16_t) -> int32x4_t {
+    // The SDOT intrinsic is not stable yet so the instruction is emitted directly.
+    let mut acc = vdupq_n_s32(0);
+    std::arch::asm!(
+        "sdot {acc:v}.4s, {a:v}.16b, {b:v}.16b",
+        acc = inout(vreg) acc,
+        a = in(vreg) a,
+        b = in(vreg) b,
+        options(pure, nomem, nostack, preserves_flags),
+    );
+    acc
+}
+
+#[cfg(not(target_feature = "dotprod"))]
 #[inline(always)]
 unsafe fn vdotq_s32(a: int8x16_t, b: int8x16_t) -> int32x4_t {
-    // TODO: dotprod
     let p0 = vmull_s8(vget_low_s8(a), vget_low_s8(b));
     let p1 = vmull_s8(vget_high_s8(a), vget_high_s8(b));
     vaddq_s32(vpaddlq_s16(p0), vpaddlq_s16(p1))
@@ -562,7 +585,6 @@ pub(crate) fn vec_dot_q2k_q8k(n: usize, xs: &[BlockQ2K], ys: &[BlockQ8K]) -> f32
             let mut isum = 0i32;
             let mut is = 0usize;
 
-            // TODO: dotprod
             for _j in 0..QK_K / 128 {
                 let q2bits = vld1q_u8_x2(q2);
                 q2 = q2.add(32);
@@ -613,3 +635,387 @@ unsafe fn multiply_accum_with_scale(
     let p2 = vdotq_s32(q2bytes.1, q8bytes.1);
     vaddvq_s32(p1) * aux[is + index] as i32 + vaddvq_s32(p2) * aux[is + 1 + index] as i32
 }
+
+// Two grid points of 8 values each.
+#[inline(always)]
+unsafe fn grid_points(g0: u64, g1: u64) -> uint8x16_t {
+    vcombine_u8(vcreate_u8(g0), vcreate_u8(g1))
+}
+
+// Negates the unsigned grid values `x` that have their bit set in `s0` for the first 8 values and
+// in `s1` for the last 8 values.
+#[inline(always)]
+unsafe fn apply_signs(x: uint8x16_t, s0: u8, s1: u8) -> int8x16_t {
+    let bits = vreinterpretq_u8_u64(vdupq_n_u64(0x8040201008040201));
+    let neg = vtstq_u8(vcombine_u8(vdup_n_u8(s0), vdup_n_u8(s1)), bits);
+    let x = vreinterpretq_s8_u8(x);
+    vbslq_s8(neg, vnegq_s8(x), x)
+}
+
+// The dot product of 16 int8 values with the 16 values at `q8`.
+#[inline(always)]
+unsafe fn dot16(x: int8x16_t, q8: *const i8) -> i32 {
+    vaddvq_s32(vdotq_s32(x, vld1q_s8(q8)))
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq2xxs_q8k(n: usize, xs: &[BlockIQ2XXS], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq2xxs_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for (qs, q8) in x.qs.chunks_exact(4).zip(y.qs.chunks_exact(32)) {
+                let aux0 = qs[0] as u32 | (qs[1] as u32) << 16;
+                let aux1 = qs[2] as u32 | (qs[3] as u32) << 16;
+                let grid = |l: usize| IQ2XXS_GRID[((aux0 >> (8 * l)) & 255) as usize];
+                let signs = |l: usize| KSIGNS_IQ2XS[((aux1 >> (7 * l)) & 127) as usize];
+                let q0 = apply_signs(grid_points(grid(0), grid(1)), signs(0), signs(1));
+                let q1 = apply_signs(grid_points(grid(2), grid(3)), signs(2), signs(3));
+                let sumi = dot16(q0, q8.as_ptr()) + dot16(q1, q8.as_ptr().add(16));
+                bsum += (2 * (aux1 >> 28) as i32 + 1) * sumi;
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        0.125 * sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq2xs_q8k(n: usize, xs: &[BlockIQ2XS], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq2xs_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for (ib32, (qs, q8)) in x.qs.chunks_exact(4).zip(y.qs.chunks_exact(32)).enumerate() {
+                let grid = |l: usize| IQ2XS_GRID[(qs[l] & 511) as usize];
+                let signs = |l: usize| KSIGNS_IQ2XS[(qs[l] >> 9) as usize];
+                let q0 = apply_signs(grid_points(grid(0), grid(1)), signs(0), signs(1));
+                let q1 = apply_signs(grid_points(grid(2), grid(3)), signs(2), signs(3));
+                let ls1 = 2 * (x.scales[ib32] & 0xf) as i32 + 1;
+                let ls2 = 2 * (x.scales[ib32] >> 4) as i32 + 1;
+                bsum += ls1 * dot16(q0, q8.as_ptr()) + ls2 * dot16(q1, q8.as_ptr().add(16));
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        0.125 * sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq2s_q8k(n: usize, xs: &[BlockIQ2S], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq2s_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (qs, signs) = x.qs.split_at(QK_K / 8);
+            let mut bsum = 0i32;
+            for (ib32, q8) in y.qs.chunks_exact(32).enumerate() {
+                let qh = x.qh[ib32] as usize;
+                let grid = |l: usize| {
+                    let index = qs[4 * ib32 + l] as usize | (qh << (8 - 2 * l)) & 0x300;
+                    IQ2S_GRID[index]
+                };
+                let signs = &signs[4 * ib32..];
+                let q0 = apply_signs(grid_points(grid(0), grid(1)), signs[0], signs[1]);
+                let q1 = apply_signs(grid_points(grid(2), grid(3)), signs[2], signs[3]);
+                let ls1 = 2 * (x.scales[ib32] & 0xf) as i32 + 1;
+                let ls2 = 2 * (x.scales[ib32] >> 4) as i32 + 1;
+                bsum += ls1 * dot16(q0, q8.as_ptr()) + ls2 * dot16(q1, q8.as_ptr().add(16));
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        0.125 * sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq3xxs_q8k(n: usize, xs: &[BlockIQ3XXS], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq3xxs_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (qs, scales_and_signs) = x.qs.split_at(QK_K / 4);
+            let mut bsum = 0i32;
+            for (ib32, (qs, q8)) in qs.chunks_exact(8).zip(y.qs.chunks_exact(32)).enumerate() {
+                let aux = LittleEndian::read_u32(&scales_and_signs[4 * ib32..]);
+                // Each point of the grid has 4 values, two points make up a group of 8 values.
+                let grid = |l: usize| {
+                    IQ3XXS_GRID[qs[2 * l] as usize] as u64
+                        | (IQ3XXS_GRID[qs[2 * l + 1] as usize] as u64) << 32
+                };
+                let signs = |l: usize| KSIGNS_IQ2XS[((aux >> (7 * l)) & 127) as usize];
+                let q0 = apply_signs(grid_points(grid(0), grid(1)), signs(0), signs(1));
+                let q1 = apply_signs(grid_points(grid(2), grid(3)), signs(2), signs(3));
+                let sumi = dot16(q0, q8.as_ptr()) + dot16(q1, q8.as_ptr().add(16));
+                bsum += (2 * (aux >> 28) as i32 + 1) * sumi;
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        0.25 * sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq3s_q8k(n: usize, xs: &[BlockIQ3S], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq3s_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for (ib32, (qs, q8)) in x.qs.chunks_exact(8).zip(y.qs.chunks_exact(32)).enumerate() {
+                let qh = x.qh[ib32] as usize;
+                let point = |k: usize| IQ3S_GRID[qs[k] as usize | (qh << (8 - k)) & 256] as u64;
+                let grid = |l: usize| point(2 * l) | point(2 * l + 1) << 32;
+                let signs = &x.signs[4 * ib32..];
+                let q0 = apply_signs(grid_points(grid(0), grid(1)), signs[0], signs[1]);
+                let q1 = apply_signs(grid_points(grid(2), grid(3)), signs[2], signs[3]);
+                let sumi = dot16(q0, q8.as_ptr()) + dot16(q1, q8.as_ptr().add(16));
+                let ls = 2 * ((x.scales[ib32 / 2] >> (4 * (ib32 % 2))) & 0xf) as i32 + 1;
+                bsum += ls * sumi;
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        sumf
+    }
+}
+
+// Two points of the IQ1 grid, the values are in {-1, 0, 1}. Each byte of a grid entry holds the
+// value `j` in its low nibble and the value `j + 4` in its high nibble, both shifted up by 1.
+#[inline(always)]
+unsafe fn iq1s_points(i0: usize, i1: usize) -> int8x16_t {
+    let point = |i: usize| {
+        let g = IQ1S_GRID[i];
+        (g & 0x0f0f0f0f) as u64 | (((g >> 4) & 0x0f0f0f0f) as u64) << 32
+    };
+    let points = vreinterpretq_s8_u8(grid_points(point(i0), point(i1)));
+    vsubq_s8(points, vdupq_n_s8(1))
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq1s_q8k(n: usize, xs: &[BlockIQ1S], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq1s_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = 0i32;
+            // The dot product with the shifts, the q8 block sums are used for these.
+            let mut sumi1 = 0i32;
+            let groups = x.qs.chunks_exact(4).zip(y.qs.chunks_exact(32));
+            for (ib, ((qs, q8), &qh)) in groups.zip(x.qh.iter()).enumerate() {
+                let index = |l: usize| qs[l] as usize | ((qh as usize >> (3 * l)) & 7) << 8;
+                let q0 = iq1s_points(index(0), index(1));
+                let q1 = iq1s_points(index(2), index(3));
+                let lsum = dot16(q0, q8.as_ptr()) + dot16(q1, q8.as_ptr().add(16));
+                let ls = 2 * ((qh >> 12) & 7) as i32 + 1;
+                let delta = if qh & 0x8000 != 0 { -1 } else { 1 };
+                let bsum = y.bsums[2 * ib] as i32 + y.bsums[2 * ib + 1] as i32;
+                sumi += ls * lsum;
+                sumi1 += ls * delta * bsum;
+            }
+            sumf += x.d.to_f32() * y.d * (sumi as f32 + IQ1S_DELTA * sumi1 as f32);
+        }
+        sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq1m_q8k(n: usize, xs: &[BlockIQ1M], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq1m_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (sc, d) = x.scales();
+            let mut sumi1 = 0i32;
+            let mut sumi2 = 0i32;
+            for (ib, q8) in y.qs.chunks_exact(16).enumerate() {
+                let (i0, neg0) = x.group(ib, 0);
+                let (i1, neg1) = x.group(ib, 1);
+                let delta = |neg: bool| vdup_n_s8(if neg { -1 } else { 1 });
+                let deltas = vcombine_s8(delta(neg0), delta(neg1));
+                let ls = 2 * ((sc[ib / 4] >> (3 * (ib % 4))) & 7) as i32 + 1;
+                sumi1 += ls * dot16(iq1s_points(i0, i1), q8.as_ptr());
+                sumi2 += ls * dot16(deltas, q8.as_ptr());
+            }
+            sumf += d * y.d * (sumi1 as f32 + IQ1M_DELTA * sumi2 as f32);
+        }
+        sumf
+    }
+}
+
+// Maps the 32 nibbles of `qs`, low nibbles first, to the IQ4 non-linear values.
+#[inline(always)]
+unsafe fn iq4_values(qs: *const u8) -> (int8x16_t, int8x16_t) {
+    let values = vld1q_s8(KVALUES_IQ4NL.as_ptr());
+    let q = vld1q_u8(qs);
+    let lo = vqtbl1q_s8(values, vandq_u8(q, vdupq_n_u8(0x0F)));
+    let hi = vqtbl1q_s8(values, vshrq_n_u8(q, 4));
+    (lo, hi)
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4nl_q8_0(n: usize, xs: &[BlockIQ4NL], ys: &[BlockQ8_0]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK4_NL),
+        "vec_dot_iq4nl_q8_0: {n} is not divisible by {QK4_NL}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (lo, hi) = iq4_values(x.qs.as_ptr());
+            let y0 = vld1q_s8(y.qs.as_ptr());
+            let y1 = vld1q_s8(y.qs.as_ptr().add(16));
+            let sumi = vaddvq_s32(vaddq_s32(vdotq_s32(lo, y0), vdotq_s32(hi, y1)));
+            sumf += x.d.to_f32() * y.d.to_f32() * sumi as f32;
+        }
+        sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4xs_q8k(n: usize, xs: &[BlockIQ4XS], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq4xs_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for (ib, (qs, q8)) in x.qs.chunks_exact(16).zip(y.qs.chunks_exact(32)).enumerate() {
+                let (lo, hi) = iq4_values(qs.as_ptr());
+                let y0 = vld1q_s8(q8.as_ptr());
+                let y1 = vld1q_s8(q8.as_ptr().add(16));
+                let sumi = vaddvq_s32(vaddq_s32(vdotq_s32(lo, y0), vdotq_s32(hi, y1)));
+                bsum += sumi * (x.scale(ib) as i32 - 32);
+            }
+            sumf += x.d.to_f32() * y.d * bsum as f32;
+        }
+        sumf
+    }
+}
+
+// Extracts a trit from each byte of `q`, `pow3` selects the trit, see `BlockTQ1_0::trits`.
+#[inline(always)]
+unsafe fn tq1_trits(q: uint8x16_t, pow3: u8) -> int8x16_t {
+    let q = vmulq_u8(q, vdupq_n_u8(pow3));
+    let three = vdup_n_u8(3);
+    let lo = vshrn_n_u16::<8>(vmull_u8(vget_low_u8(q), three));
+    let hi = vshrn_n_u16::<8>(vmull_u8(vget_high_u8(q), three));
+    vreinterpretq_s8_u8(vcombine_u8(lo, hi))
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_tq1_0_q8k(n: usize, xs: &[BlockTQ1_0], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_tq1_0_q8k: {n} is not divisible by {QK_K}"
+    );
+    const POW3: [u8; 5] = [1, 3, 9, 27, 81];
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            // The trits are in {0, 1, 2}, the sum of the q8 values is subtracted at the end.
+            let mut sumi = vdupq_n_s32(0);
+            let q0 = vld1q_u8(x.qs.as_ptr());
+            let q1 = vld1q_u8(x.qs.as_ptr().add(16));
+            let q2 = vld1q_u8(x.qs.as_ptr().add(32));
+            for (n, &p) in POW3.iter().enumerate() {
+                let q8 = y.qs.as_ptr();
+                let y0 = vld1q_s8(q8.add(32 * n));
+                let y1 = vld1q_s8(q8.add(32 * n + 16));
+                let y2 = vld1q_s8(q8.add(160 + 16 * n));
+                sumi = vaddq_s32(sumi, vdotq_s32(tq1_trits(q0, p), y0));
+                sumi = vaddq_s32(sumi, vdotq_s32(tq1_trits(q1, p), y1));
+                sumi = vaddq_s32(sumi, vdotq_s32(tq1_trits(q2, p), y2));
+            }
+            let mut sumi = vaddvq_s32(sumi);
+            for (n, &p) in POW3[..4].iter().enumerate() {
+                for (j, &q) in x.qh.iter().enumerate() {
+                    let trit = (q.wrapping_mul(p) as u16 * 3) >> 8;
+                    sumi += trit as i32 * y.qs[240 + 4 * n + j] as i32;
+                }
+            }
+            sumi -= y.bsums.iter().map(|&b| b as i32).sum::<i32>();
+            sumf += x.d.to_f32() * y.d * sumi as f32;
+        }
+        sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_tq2_0_q8k(n: usize, xs: &[BlockTQ2_0], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_tq2_0_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let m3 = vdupq_n_u8(3);
+        // The values are stored as {0, 1, 2}, the sum of the q8 values is subtracted at the end.
+        let planes = |q: uint8x16_t| {
+            [
+                vandq_u8(q, m3),
+                vandq_u8(vshrq_n_u8(q, 2), m3),
+                vandq_u8(vshrq_n_u8(q, 4), m3),
+                vshrq_n_u8(q, 6),
+            ]
+        };
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut sumi = vdupq_n_s32(0);
+            for (qs, q8) in x.qs.chunks_exact(32).zip(y.qs.chunks_exact(128)) {
+                let q0 = planes(vld1q_u8(qs.as_ptr()));
+                let q1 = planes(vld1q_u8(qs.as_ptr().add(16)));
+                for (l, q8) in q8.chunks_exact(32).enumerate() {
+                    let y0 = vld1q_s8(q8.as_ptr());
+                    let y1 = vld1q_s8(q8.as_ptr().add(16));
+                    sumi = vaddq_s32(sumi, vdotq_s32(vreinterpretq_s8_u8(q0[l]), y0));
+                    sumi = vaddq_s32(sumi, vdotq_s32(vreinterpretq_s8_u8(q1[l]), y1));
+                }
+            }
+            let sumi = vaddvq_s32(sumi) - y.bsums.iter().map(|&b| b as i32).sum::<i32>();
+            sumf += x.d.to_f32() * y.d * sumi as f32;
+        }
+        sumf
+    }
+}
+
+// The dot product of int8 vectors accumulated in int32.
+#[inline(always)]
+pub(crate) fn vec_dot_i8(xs: &[i8], ys: &[i8]) -> i32 {
+    debug_assert_eq!(xs.len(), ys.len());
+    let n = xs.len() / 16 * 16;
+    let sumi = unsafe {
+        let mut acc = vdupq_n_s32(0);
+        for i in (0..n).step_by(16) {
+            let x = vld1q_s8(xs.as_ptr().add(i));
+            let y = vld1q_s8(ys.as_ptr().add(i));
+            acc = vaddq_s32(acc, vdotq_s32(x, y));
+        }
+        vaddvq_s32(acc)
+    };
+    let tail = xs[n..].iter().zip(ys[n..].iter());
+    sumi + tail.map(|(&x, &y)| x as i32 * y as i32).sum::<i32>()
+}
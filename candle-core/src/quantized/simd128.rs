@@ -1,4 +1,8 @@
-use super::k_quants::{BlockQ2K, BlockQ4K, BlockQ4_0, BlockQ6K, BlockQ8K, BlockQ8_0, QK8_0, QK_K};
+use super::iq_quants::KVALUES_IQ4NL;
+use super::k_quants::{
+    BlockIQ4NL, BlockIQ4XS, BlockQ2K, BlockQ4K, BlockQ4_0, BlockQ6K, BlockQ8K, BlockQ8_0,
+    BlockTQ2_0, QK4_NL, QK8_0, QK_K,
+};
 use byteorder::{ByteOrder, LittleEndian};
 use half::f16;
 
@@ -416,3 +420,103 @@ pub(crate) fn vec_dot_q8k_q8k(n: usize, xs: &[BlockQ8K], ys: &[BlockQ8K]) -> f32
         res
     }
 }
+
+// The dot products of 16 i8 values with the 16 values at `y`, summed in 4 lanes.
+#[inline(always)]
+unsafe fn dot_i8x16(x: v128, y: *const i8) -> v128 {
+    let lo = i32x4_dot_i16x8(i16x8_extend_low_i8x16(x), i16x8_load_extend_i8x8(y));
+    let hi = i32x4_dot_i16x8(i16x8_extend_high_i8x16(x), i16x8_load_extend_i8x8(y.add(8)));
+    i32x4_add(lo, hi)
+}
+
+#[inline(always)]
+unsafe fn hsum_i32x4(x: v128) -> i32 {
+    i32x4_extract_lane::<0>(x)
+        + i32x4_extract_lane::<1>(x)
+        + i32x4_extract_lane::<2>(x)
+        + i32x4_extract_lane::<3>(x)
+}
+
+// Maps the 32 nibbles of `qs`, low nibbles first, to the IQ4 non-linear values.
+#[inline(always)]
+unsafe fn iq4_values(qs: *const u8) -> (v128, v128) {
+    let values = v128_load(KVALUES_IQ4NL.as_ptr() as *const v128);
+    let q = v128_load(qs as *const v128);
+    let lo = i8x16_swizzle(values, v128_and(q, u8x16_splat(0x0F)));
+    let hi = i8x16_swizzle(values, u8x16_shr(q, 4));
+    (lo, hi)
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4nl_q8_0(n: usize, xs: &[BlockIQ4NL], ys: &[BlockQ8_0]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK4_NL),
+        "vec_dot_iq4nl_q8_0: {n} is not divisible by {QK4_NL}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let (lo, hi) = iq4_values(x.qs.as_ptr());
+            let sumi = i32x4_add(
+                dot_i8x16(lo, y.qs.as_ptr()),
+                dot_i8x16(hi, y.qs.as_ptr().add(16)),
+            );
+            sumf += f16::to_f32(x.d) * f16::to_f32(y.d) * hsum_i32x4(sumi) as f32;
+        }
+        sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_iq4xs_q8k(n: usize, xs: &[BlockIQ4XS], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_iq4xs_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            let mut bsum = 0i32;
+            for (ib, (qs, q8)) in x.qs.chunks_exact(16).zip(y.qs.chunks_exact(32)).enumerate() {
+                let (lo, hi) = iq4_values(qs.as_ptr());
+                let sumi = i32x4_add(
+                    dot_i8x16(lo, q8.as_ptr()),
+                    dot_i8x16(hi, q8.as_ptr().add(16)),
+                );
+                bsum += hsum_i32x4(sumi) * (x.scale(ib) as i32 - 32);
+            }
+            sumf += f16::to_f32(x.d) * y.d * bsum as f32;
+        }
+        sumf
+    }
+}
+
+#[inline(always)]
+pub(crate) fn vec_dot_tq2_0_q8k(n: usize, xs: &[BlockTQ2_0], ys: &[BlockQ8K]) -> f32 {
+    debug_assert!(
+        n.is_multiple_of(QK_K),
+        "vec_dot_tq2_0_q8k: {n} is not divisible by {QK_K}"
+    );
+    unsafe {
+        let m3 = u8x16_splat(3);
+        let mut sumf = 0f32;
+        for (x, y) in xs.iter().zip(ys.iter()) {
+            // The values are stored as {0, 1, 2}, the sum of the q8 values is subtracted at the end.
+            let mut sumi = i32x4_splat(0);
+            for (qs, q8) in x.qs.chunks_exact(32).zip(y.qs.chunks_exact(128)) {
+                let q0 = v128_load(qs.as_ptr() as *const v128);
+                let q1 = v128_load(qs.as_ptr().add(16) as *const v128);
+                for (l, q8) in q8.chunks_exact(32).enumerate() {
+                    let shift = 2 * l as u32;
+                    let v0 = v128_and(u8x16_shr(q0, shift), m3);
+                    let v1 = v128_and(u8x16_shr(q1, shift), m3);
+                    sumi = i32x4_add(sumi, dot_i8x16(v0, q8.as_ptr()));
+                    sumi = i32x4_add(sumi, dot_i8x16(v1, q8.as_ptr().add(16)));
+                }
+            }
+            let sumi = hsum_i32x4(sumi) - y.bsums.iter().map(|&b| b as i32).sum::<i32>();
+            sumf += f16::to_f32(x.d) * y.d * sumi as f32;
+        }
+        sumf
+    }
+}
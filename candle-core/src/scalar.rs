@@ -2,6 +2,7 @@
 //!
 use crate::{DType, Result, Tensor, WithDType};
 use float8::F8E4M3 as f8e4m3;
+use float8::F8E5M2 as f8e5m2;
 use half::{bf16, f16};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -16,6 +17,7 @@ pub enum Scalar {
     F32(f32),
     F64(f64),
     F8E4M3(f8e4m3),
+    F8E5M2(f8e5m2),
 }
 
 impl<T: WithDType> From<T> for Scalar {
@@ -37,6 +39,7 @@ impl Scalar {
             DType::F32 => Scalar::F32(0.0),
             DType::F64 => Scalar::F64(0.0),
             DType::F8E4M3 => Scalar::F8E4M3(f8e4m3::ZERO),
+            DType::F8E5M2 => Scalar::F8E5M2(f8e5m2::ZERO),
             DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
                 panic!("Cannot create zero scalar for dummy type {dtype:?}")
             }
@@ -55,6 +58,7 @@ impl Scalar {
             DType::F32 => Scalar::F32(1.0),
             DType::F64 => Scalar::F64(1.0),
             DType::F8E4M3 => Scalar::F8E4M3(f8e4m3::ONE),
+            DType::F8E5M2 => Scalar::F8E5M2(f8e5m2::ONE),
             DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
                 panic!("Cannot create one scalar for dummy type {dtype:?}")
             }
@@ -73,6 +77,7 @@ impl Scalar {
             Scalar::F32(_) => DType::F32,
             Scalar::F64(_) => DType::F64,
             Scalar::F8E4M3(_) => DType::F8E4M3,
+            Scalar::F8E5M2(_) => DType::F8E5M2,
         }
     }
 
@@ -88,6 +93,7 @@ impl Scalar {
             Scalar::F32(v) => *v as f64,
             Scalar::F64(v) => *v,
             Scalar::F8E4M3(v) => v.to_f64(),
+            Scalar::F8E5M2(v) => v.to_f64(),
         }
     }
 }
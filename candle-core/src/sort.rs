@@ -124,6 +124,7 @@ impl crate::CustomOp1 for ArgSort {
             crate::CpuStorage::F32(vs) => self.asort(vs, layout),
             crate::CpuStorage::F64(vs) => self.asort(vs, layout),
             crate::CpuStorage::F8E4M3(vs) => self.asort(vs, layout),
+            crate::CpuStorage::F8E5M2(vs) => self.asort(vs, layout),
             // Dummy types don't support sorting
             crate::CpuStorage::F6E2M3(_) => {
                 return Err(
@@ -187,6 +188,7 @@ impl crate::CustomOp1 for ArgSort {
                     DType::I32 => "asort_asc_i32",
                     DType::I64 => "asort_asc_i64",
                     DType::F8E4M3 => crate::bail!("Metal device does not yet support F8E4M3."),
+                    DType::F8E5M2 => crate::bail!("Metal device does not yet support F8E5M2."),
                     DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
                         return Err(
                             crate::Error::UnsupportedDTypeForOp(storage.dtype(), "argsort").bt(),
@@ -205,6 +207,7 @@ impl crate::CustomOp1 for ArgSort {
                     DType::I32 => "asort_desc_i32",
                     DType::I64 => "asort_desc_i64",
                     DType::F8E4M3 => crate::bail!("Metal device does not yet support F8E4M3."),
+                    DType::F8E5M2 => crate::bail!("Metal device does not yet support F8E5M2."),
                     DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
                         return Err(
                             crate::Error::UnsupportedDTypeForOp(storage.dtype(), "argsort").bt(),
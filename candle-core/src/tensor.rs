@@ -2080,7 +2080,10 @@ impl Tensor {
         self.is_variable
     }
 
-    pub(crate) fn op(&self) -> &Option<Op> {
+    /// The operation that produced this tensor. This is only recorded when the tensor depends on
+    /// some variable, see [`Tensor::track_op`].
+    #[doc(hidden)]
+    pub fn op(&self) -> &Option<Op> {
         &self.op
     }
 
@@ -0,0 +1,79 @@
+use candle_core::quantized::{calibration, gguf_file, imatrix_file, GgmlDType, QMatMul, QTensor};
+use candle_core::{Device, Module, Result, Tensor};
+
+#[test]
+fn imatrix_calibration() -> Result<()> {
+    let dev = Device::Cpu;
+    let w = Tensor::arange(0f32, 32. * 64., &dev)?
+        .affine(1e-3, -1.)?
+        .reshape((32, 64))?;
+    let up = QTensor::quantize(&w, GgmlDType::F32)?;
+    let down = QTensor::quantize(&w.t()?.contiguous()?, GgmlDType::Q8_0)?;
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    gguf_file::write(
+        &mut buffer,
+        &[],
+        &[
+            ("blk.0.ffn_up.weight", &up),
+            ("blk.0.ffn_down.weight", &down),
+        ],
+    )?;
+
+    let calibration = calibration::Calibration::new();
+    let mut reader = std::io::Cursor::new(buffer.into_inner());
+    let content = gguf_file::Content::read(&mut reader)?;
+    let (up, down) = calibration.load(|| {
+        let up = content.tensor(&mut reader, "blk.0.ffn_up.weight", &dev)?;
+        let down = content.tensor(&mut reader, "blk.0.ffn_down.weight", &dev)?;
+        Ok::<_, candle_core::Error>((QMatMul::from_qtensor(up)?, QMatMul::from_qtensor(down)?))
+    })?;
+    // The hooked f32 weights are not dequantized.
+    assert!(matches!(up, QMatMul::QTensor(_)));
+    // Weights loaded outside of the calibration are not recorded.
+    let other = content.tensor(&mut reader, "blk.0.ffn_up.weight", &dev)?;
+    assert!(other.calibration().is_none());
+    let other = QMatMul::from_qtensor(other)?;
+    // Neither are the ones of an unrelated calibration.
+    let unrelated = calibration::Calibration::new();
+    let hooked = QTensor::quantize(&w, GgmlDType::Q8_0)?.with_calibration(unrelated.hook("x"));
+    let hooked = QMatMul::from_qtensor(hooked)?;
+
+    let xs1 = Tensor::arange(0f32, 2. * 64., &dev)?
+        .affine(0.01, -0.5)?
+        .reshape((1, 2, 64))?;
+    let xs2 = Tensor::ones((1, 64), candle_core::DType::F32, &dev)?;
+    for xs in [&xs1, &xs2] {
+        let ys = up.forward(xs)?;
+        down.forward(&ys)?;
+        other.forward(xs)?;
+        hooked.forward(xs)?;
+    }
+    let imatrix = calibration.imatrix();
+    assert_eq!(unrelated.imatrix().len(), 1);
+    assert_eq!(
+        imatrix.keys().collect::<Vec<_>>(),
+        ["blk.0.ffn_down.weight", "blk.0.ffn_up.weight"]
+    );
+
+    let entry = &imatrix["blk.0.ffn_up.weight"];
+    assert_eq!(entry.ncall, 2);
+    let xs = Tensor::cat(&[xs1.reshape((2, 64))?, xs2], 0)?;
+    let expected = xs.sqr()?.mean(0)?.to_vec1::<f32>()?;
+    for (v, e) in entry.values.iter().zip(expected.iter()) {
+        assert!((v - e).abs() < 1e-5, "{v} {e}")
+    }
+    assert_eq!(imatrix["blk.0.ffn_down.weight"].values.len(), 32);
+
+    let file = std::env::temp_dir().join(format!("candle-{}.imatrix", std::process::id()));
+    imatrix_file::save_imatrix(&file, &imatrix, 2, "test")?;
+    let loaded = imatrix_file::load_imatrix(&file);
+    std::fs::remove_file(&file)?;
+    let loaded = loaded?;
+    assert_eq!(loaded.len(), 2);
+    for (name, entry) in imatrix.iter() {
+        for (v, e) in loaded[name].iter().zip(entry.values.iter()) {
+            assert!((v - e).abs() <= 1e-5 * e.abs().max(1.), "{name} {v} {e}")
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,270 @@
+# Generates ggml_golden.gguf: a few blocks for each i-quant and ternary type together
+# with their dequantized values. The dequantization below is a transcription of
+# dequantize_row_* from ggml-quants.c, the grid tables are parsed from the ggml metal
+# sources vendored in candle-metal-kernels.
+import os
+import re
+import struct
+
+QK_K = 256
+N_BLOCKS = 2
+HERE = os.path.dirname(os.path.abspath(__file__))
+METAL = os.path.join(HERE, "../../candle-metal-kernels/src/metal_src/quantized.metal")
+
+
+def load_tables():
+    src = open(METAL).read()
+    tables = {}
+    for m in re.finditer(r"GGML_TABLE_BEGIN\((\w+), (\w+), \w+\)(.*?)GGML_TABLE_END\(\)", src, re.S):
+        tables[m.group(2)] = [int(v, 0) for v in re.findall(r"0x[0-9a-fA-F]+|\b\d+\b", m.group(3))]
+    return tables
+
+
+T = load_tables()
+KVALUES_IQ4NL = [-127, -104, -83, -65, -49, -35, -22, -10, 1, 13, 25, 38, 53, 69, 89, 113]
+IQ1S_DELTA = 0.125
+IQ1M_DELTA = 0.125
+
+
+def grid_bytes(table, index, n):
+    v = T[table][index]
+    return [(v >> (8 * i)) & 0xFF for i in range(n)]
+
+
+def sign(signs, j):
+    return -1.0 if signs & T["kmask_iq2xs"][j] else 1.0
+
+
+def f16(b, o):
+    return struct.unpack_from("<e", b, o)[0]
+
+
+def u16(b, o):
+    return struct.unpack_from("<H", b, o)[0]
+
+
+def u32(b, o):
+    return struct.unpack_from("<I", b, o)[0]
+
+
+def iq2_xxs(b):
+    d, y = f16(b, 0), []
+    for ib32 in range(QK_K // 32):
+        aux_g, aux_s = u32(b, 2 + 8 * ib32), u32(b, 6 + 8 * ib32)
+        db = d * (0.5 + (aux_s >> 28)) * 0.25
+        for l in range(4):
+            grid = grid_bytes("iq2xxs_grid", (aux_g >> (8 * l)) & 0xFF, 8)
+            signs = T["ksigns_iq2xs"][(aux_s >> (7 * l)) & 127]
+            y += [db * grid[j] * sign(signs, j) for j in range(8)]
+    return y
+
+
+def iq2_xs(b):
+    d, y = f16(b, 0), []
+    for ib32 in range(QK_K // 32):
+        sc = b[2 + QK_K // 4 + ib32]
+        for l in range(4):
+            db = d * (0.5 + ((sc >> (4 * (l // 2))) & 0xF)) * 0.25
+            q = u16(b, 2 + 2 * (4 * ib32 + l))
+            grid = grid_bytes("iq2xs_grid", q & 511, 8)
+            signs = T["ksigns_iq2xs"][q >> 9]
+            y += [db * grid[j] * sign(signs, j) for j in range(8)]
+    return y
+
+
+def iq2_s(b):
+    d, y = f16(b, 0), []
+    qs, qh, scales = b[2:66], b[66:74], b[74:82]
+    signs = qs[QK_K // 8:]
+    for ib32 in range(QK_K // 32):
+        for l in range(4):
+            db = d * (0.5 + ((scales[ib32] >> (4 * (l // 2))) & 0xF)) * 0.25
+            index = qs[4 * ib32 + l] | ((qh[ib32] << (8 - 2 * l)) & 0x300)
+            grid = grid_bytes("iq2s_grid", index, 8)
+            y += [db * grid[j] * sign(signs[4 * ib32 + l], j) for j in range(8)]
+    return y
+
+
+def iq3_xxs(b):
+    d, y = f16(b, 0), []
+    for ib32 in range(QK_K // 32):
+        aux = u32(b, 2 + QK_K // 4 + 4 * ib32)
+        db = d * (0.5 + (aux >> 28)) * 0.5
+        for l in range(4):
+            signs = T["ksigns_iq2xs"][(aux >> (7 * l)) & 127]
+            grid1 = grid_bytes("iq3xxs_grid", b[2 + 8 * ib32 + 2 * l], 4)
+            grid2 = grid_bytes("iq3xxs_grid", b[2 + 8 * ib32 + 2 * l + 1], 4)
+            y += [db * grid1[j] * sign(signs, j) for j in range(4)]
+            y += [db * grid2[j] * sign(signs, j + 4) for j in range(4)]
+    return y
+
+
+def iq3_s(b):
+    d, y = f16(b, 0), []
+    qs, qh, signs, scales = b[2:66], b[66:74], b[74:106], b[106:110]
+    for ib32 in range(QK_K // 32):
+        db = d * (1 + 2 * ((scales[ib32 // 2] >> (4 * (ib32 % 2))) & 0xF))
+        for l in range(4):
+            grid1 = grid_bytes("iq3s_grid", qs[8 * ib32 + 2 * l] | ((qh[ib32] << (8 - 2 * l)) & 256), 4)
+            grid2 = grid_bytes("iq3s_grid", qs[8 * ib32 + 2 * l + 1] | ((qh[ib32] << (7 - 2 * l)) & 256), 4)
+            s = signs[4 * ib32 + l]
+            y += [db * grid1[j] * sign(s, j) for j in range(4)]
+            y += [db * grid2[j] * sign(s, j + 4) for j in range(4)]
+    return y
+
+
+def iq1_s(b):
+    d, y = f16(b, 0), []
+    qs = b[2:34]
+    for ib in range(QK_K // 32):
+        qh = u16(b, 34 + 2 * ib)
+        dl = d * (2 * ((qh >> 12) & 7) + 1)
+        delta = -IQ1S_DELTA if qh & 0x8000 else IQ1S_DELTA
+        for l in range(4):
+            grid = grid_bytes("iq1s_grid_gpu", qs[4 * ib + l] | (((qh >> (3 * l)) & 7) << 8), 4)
+            values = [g & 0xF for g in grid] + [g >> 4 for g in grid]
+            y += [dl * (v - 1 + delta) for v in values]
+    return y
+
+
+def iq1_m(b):
+    qs, qh = b[0:32], b[32:48]
+    sc = [u16(b, 48 + 2 * i) for i in range(4)]
+    scale = (sc[0] >> 12) | ((sc[1] >> 8) & 0x00F0) | ((sc[2] >> 4) & 0x0F00) | (sc[3] & 0xF000)
+    d = struct.unpack("<e", struct.pack("<H", scale))[0]
+    y = []
+    for ib in range(QK_K // 32):
+        dl1 = d * (2 * ((sc[ib // 2] >> (6 * (ib % 2) + 0)) & 7) + 1)
+        dl2 = d * (2 * ((sc[ib // 2] >> (6 * (ib % 2) + 3)) & 7) + 1)
+        idx = [
+            qs[4 * ib + 0] | ((qh[2 * ib + 0] << 8) & 0x700),
+            qs[4 * ib + 1] | ((qh[2 * ib + 0] << 4) & 0x700),
+            qs[4 * ib + 2] | ((qh[2 * ib + 1] << 8) & 0x700),
+            qs[4 * ib + 3] | ((qh[2 * ib + 1] << 4) & 0x700),
+        ]
+        delta = [
+            -IQ1M_DELTA if qh[2 * ib + 0] & 0x08 else IQ1M_DELTA,
+            -IQ1M_DELTA if qh[2 * ib + 0] & 0x80 else IQ1M_DELTA,
+            -IQ1M_DELTA if qh[2 * ib + 1] & 0x08 else IQ1M_DELTA,
+            -IQ1M_DELTA if qh[2 * ib + 1] & 0x80 else IQ1M_DELTA,
+        ]
+        for l in range(4):
+            dl = dl1 if l < 2 else dl2
+            grid = grid_bytes("iq1s_grid_gpu", idx[l], 4)
+            values = [g & 0xF for g in grid] + [g >> 4 for g in grid]
+            y += [dl * (v - 1 + delta[l]) for v in values]
+    return y
+
+
+def iq4_nl(b):
+    d, qs = f16(b, 0), b[2:18]
+    return [d * KVALUES_IQ4NL[q & 0xF] for q in qs] + [d * KVALUES_IQ4NL[q >> 4] for q in qs]
+
+
+def iq4_xs(b):
+    d, scales_h, scales_l, qs = f16(b, 0), u16(b, 2), b[4:8], b[8:136]
+    y = []
+    for ib in range(QK_K // 32):
+        ls = ((scales_l[ib // 2] >> (4 * (ib % 2))) & 0xF) | (((scales_h >> (2 * ib)) & 3) << 4)
+        dl = d * (ls - 32)
+        q = qs[16 * ib:16 * ib + 16]
+        y += [dl * KVALUES_IQ4NL[v & 0xF] for v in q] + [dl * KVALUES_IQ4NL[v >> 4] for v in q]
+    return y
+
+
+def tq1_0(b):
+    qs, qh, d = b[0:48], b[48:52], f16(b, 52)
+    pow3 = [1, 3, 9, 27, 81, 243]
+    y = []
+
+    def trit(q, n):
+        q = (q * pow3[n]) & 0xFF
+        return ((q * 3) >> 8) - 1
+
+    for n in range(5):
+        y += [trit(qs[m], n) * d for m in range(32)]
+    for n in range(5):
+        y += [trit(qs[32 + m], n) * d for m in range(16)]
+    for n in range(4):
+        y += [trit(qh[j], n) * d for j in range(4)]
+    return y
+
+
+def tq2_0(b):
+    qs, d = b[0:64], f16(b, 64)
+    y = []
+    for j in range(0, 64, 32):
+        for l in range(4):
+            y += [(((qs[j + m] >> (2 * l)) & 3) - 1) * d for m in range(32)]
+    return y
+
+
+# name, ggml type id, block size, type size, dequantize, fixed fields (offset, little-endian u16)
+TYPES = [
+    ("iq2_xxs", 16, QK_K, 66, iq2_xxs, [(0, 0x2C00)]),
+    ("iq2_xs", 17, QK_K, 74, iq2_xs, [(0, 0x2C00)]),
+    ("iq3_xxs", 18, QK_K, 98, iq3_xxs, [(0, 0x2C00)]),
+    ("iq1_s", 19, QK_K, 50, iq1_s, [(0, 0x2C00)]),
+    ("iq4_nl", 20, 32, 18, iq4_nl, [(0, 0x2000)]),
+    ("iq3_s", 21, QK_K, 110, iq3_s, [(0, 0x2C00)]),
+    ("iq2_s", 22, QK_K, 82, iq2_s, [(0, 0x2C00)]),
+    ("iq4_xs", 23, QK_K, 136, iq4_xs, [(0, 0x2000)]),
+    # The f16 super-block scale of iq1_m is spread over the top nibbles of its scales.
+    ("iq1_m", 29, QK_K, 56, iq1_m, []),
+    ("tq1_0", 34, QK_K, 54, tq1_0, [(52, 0x3800)]),
+    ("tq2_0", 35, QK_K, 66, tq2_0, [(64, 0x3800)]),
+]
+
+state = 0x2545F491
+
+
+def random_bytes(n):
+    global state
+    out = bytearray()
+    for _ in range(n):
+        state = (state * 1103515245 + 12345) & 0x7FFFFFFF
+        out.append(state >> 16 & 0xFF)
+    return out
+
+
+def make_block(name, type_size, fixed):
+    b = random_bytes(type_size)
+    for offset, value in fixed:
+        struct.pack_into("<H", b, offset, value)
+    if name == "iq1_m":
+        # d = 0.25 (0x3400)
+        for i, nibble in enumerate([0x0, 0x0, 0x4, 0x3]):
+            o = 48 + 2 * i
+            struct.pack_into("<H", b, o, (u16(b, o) & 0x0FFF) | (nibble << 12))
+    return bytes(b)
+
+
+tensors = []
+for name, ggml_type, block_size, type_size, dequantize, fixed in TYPES:
+    blocks = [make_block(name, type_size, fixed) for _ in range(N_BLOCKS)]
+    values = [v for block in blocks for v in dequantize(block)]
+    assert len(values) == N_BLOCKS * block_size
+    n = N_BLOCKS * block_size
+    tensors.append((name, ggml_type, n, b"".join(blocks)))
+    tensors.append((name + "_dequant", 0, n, struct.pack("<%df" % n, *values)))
+
+ALIGNMENT = 32
+
+
+def pad(n):
+    return (ALIGNMENT - n % ALIGNMENT) % ALIGNMENT
+
+
+header = bytearray(b"GGUF")
+header += struct.pack("<IQQ", 3, len(tensors), 0)
+offset = 0
+for name, ggml_type, n, data in tensors:
+    encoded = name.encode()
+    header += struct.pack("<Q", len(encoded)) + encoded
+    header += struct.pack("<IQIQ", 1, n, ggml_type, offset)
+    offset += len(data) + pad(len(data))
+header += bytes(pad(len(header)))
+with open(os.path.join(HERE, "ggml_golden.gguf"), "wb") as f:
+    f.write(header)
+    for _, _, _, data in tensors:
+        f.write(data + bytes(pad(len(data))))
@@ -14,6 +14,10 @@ const GGML_MAX_QUANTIZATION_TOTAL_ERROR: f32 = 0.002;
 const GGML_MAX_QUANTIZATION_TOTAL_ERROR_2BITS: f32 = 0.0075;
 const GGML_MAX_QUANTIZATION_TOTAL_ERROR_3BITS: f32 = 0.0040;
 const GGML_MAX_DOT_PRODUCT_ERROR: f32 = 0.02;
+const GGML_MAX_DOT_PRODUCT_ERROR_LOWBIT: f32 = 0.04;
+const GGML_MAX_DOT_PRODUCT_ERROR_TERNARY: f32 = 0.15;
+// ggml does not test the IQ1 types as they can only be quantized with an imatrix there.
+const MAX_DOT_PRODUCT_ERROR_IQ1: f32 = 0.25;
 
 fn test_matmul(
     device: &Device,
@@ -1036,6 +1040,17 @@ fn ggml_reference_matmul_error(dtype: GgmlDType) -> Result<f32> {
 
         // Not from the ggml repo.
         GgmlDType::Q8K => 0.00065,
+        GgmlDType::IQ2XXS => 0.0362,
+        GgmlDType::IQ2XS => 0.0303,
+        GgmlDType::IQ2S => 0.0333,
+        GgmlDType::IQ3XXS => 0.0146,
+        GgmlDType::IQ3S => 0.0138,
+        GgmlDType::IQ4NL => 0.0028,
+        GgmlDType::IQ4XS => 0.0020,
+        GgmlDType::IQ1S => 0.2044,
+        GgmlDType::IQ1M => 0.1646,
+        GgmlDType::TQ1_0 => 0.1417,
+        GgmlDType::TQ2_0 => 0.1417,
     };
     Ok(err)
 }
@@ -1086,11 +1101,22 @@ fn ggml_matmul_error_test_<T: GgmlType>(a: &[f32], b: &[f32], err_m: f32) -> Res
 
     let reference_result = vec_dot_reference(a, b);
 
+    // https://github.com/ggml-org/llama.cpp/blob/master/tests/test-quantize-fns.cpp
+    let max_error = match T::DTYPE {
+        GgmlDType::IQ2XXS
+        | GgmlDType::IQ2XS
+        | GgmlDType::IQ2S
+        | GgmlDType::IQ3XXS
+        | GgmlDType::IQ3S => GGML_MAX_DOT_PRODUCT_ERROR_LOWBIT,
+        GgmlDType::IQ1S | GgmlDType::IQ1M => MAX_DOT_PRODUCT_ERROR_IQ1,
+        GgmlDType::TQ1_0 | GgmlDType::TQ2_0 => GGML_MAX_DOT_PRODUCT_ERROR_TERNARY,
+        _ => GGML_MAX_DOT_PRODUCT_ERROR,
+    };
     let verify_result = |result: f32, source: &str| {
         let error = (result - reference_result).abs() / length as f32;
         let ggml_error = ggml_reference_matmul_error(T::DTYPE)? * err_m;
-        if !error.is_finite() || error > GGML_MAX_DOT_PRODUCT_ERROR {
-            bail!("Dot product with dtype {:?} error {error} exceeds max error {GGML_MAX_DOT_PRODUCT_ERROR}. Source: {source}", T::DTYPE);
+        if !error.is_finite() || error > max_error {
+            bail!("Dot product with dtype {:?} error {error} exceeds max error {max_error}. Source: {source}", T::DTYPE);
         }
         // We diverge slightly due to different rounding behavior / f16 to f32 conversions in GGML
         // => we use a slightly higher error threshold
@@ -1123,6 +1149,120 @@ fn quantized_mm() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn quantized_mm_iq() -> Result<()> {
+    ggml_matmul_error_test::<k_quants::BlockIQ2XXS>()?;
+    ggml_matmul_error_test::<k_quants::BlockIQ2XS>()?;
+    ggml_matmul_error_test::<k_quants::BlockIQ2S>()?;
+    ggml_matmul_error_test::<k_quants::BlockIQ3XXS>()?;
+    ggml_matmul_error_test::<k_quants::BlockIQ3S>()?;
+    ggml_matmul_error_test::<k_quants::BlockIQ4NL>()?;
+    ggml_matmul_error_test::<k_quants::BlockIQ4XS>()?;
+    ggml_matmul_error_test::<k_quants::BlockIQ1S>()?;
+    ggml_matmul_error_test::<k_quants::BlockIQ1M>()?;
+    ggml_matmul_error_test::<k_quants::BlockTQ1_0>()?;
+    ggml_matmul_error_test::<k_quants::BlockTQ2_0>()?;
+    Ok(())
+}
+
+#[test]
+fn quantize_iq() -> Result<()> {
+    let cpu = &Device::Cpu;
+    ggml_quantization_error_test(GgmlDType::IQ2XXS, cpu, 0.01)?;
+    ggml_quantization_error_test(GgmlDType::IQ2XS, cpu, 0.01)?;
+    ggml_quantization_error_test(GgmlDType::IQ2S, cpu, 0.01)?;
+    ggml_quantization_error_test(
+        GgmlDType::IQ3XXS,
+        cpu,
+        GGML_MAX_QUANTIZATION_TOTAL_ERROR_2BITS,
+    )?;
+    ggml_quantization_error_test(
+        GgmlDType::IQ3S,
+        cpu,
+        GGML_MAX_QUANTIZATION_TOTAL_ERROR_3BITS,
+    )?;
+    ggml_quantization_error_test(GgmlDType::IQ4NL, cpu, GGML_MAX_QUANTIZATION_TOTAL_ERROR)?;
+    ggml_quantization_error_test(GgmlDType::IQ4XS, cpu, GGML_MAX_QUANTIZATION_TOTAL_ERROR)?;
+    ggml_quantization_error_test(GgmlDType::IQ1S, cpu, 0.015)?;
+    ggml_quantization_error_test(GgmlDType::IQ1M, cpu, 0.015)?;
+
+    // Ternary values are represented exactly.
+    let src = (0..512).map(|i| (i % 3) as f32 - 1.).collect::<Vec<_>>();
+    let src = Tensor::new(src.as_slice(), cpu)?;
+    for dtype in [GgmlDType::TQ1_0, GgmlDType::TQ2_0] {
+        let quant = quantized::QTensor::quantize(&src, dtype)?;
+        let dst = quant.dequantize(cpu)?;
+        assert_eq!(dst.to_vec1::<f32>()?, src.to_vec1::<f32>()?, "{dtype:?}");
+    }
+    Ok(())
+}
+
+#[test]
+fn imatrix_quantize_iq() -> Result<()> {
+    let cpu = &Device::Cpu;
+    // Inputs with a few larger columns, the imatrix puts more weight on these.
+    let col_scales = (0..512)
+        .map(|i| if i % 7 == 0 { 4f32 } else { 1f32 })
+        .collect::<Vec<_>>();
+    let col_scales = Tensor::new(col_scales.as_slice(), cpu)?;
+    let lhs = Tensor::randn(0f32, 1f32, (1024, 512), cpu)?.broadcast_mul(&col_scales)?;
+    let imatrix = lhs.sqr()?.mean(0)?.to_vec1::<f32>()?;
+    let xs = Tensor::randn(0f32, 1f32, (256, 512), cpu)?;
+    let expected = lhs.matmul(&xs.t()?)?;
+    for dtype in [
+        GgmlDType::IQ2XXS,
+        GgmlDType::IQ2XS,
+        GgmlDType::IQ2S,
+        GgmlDType::IQ3XXS,
+        GgmlDType::IQ3S,
+        GgmlDType::IQ4NL,
+        GgmlDType::IQ4XS,
+        GgmlDType::IQ1S,
+        GgmlDType::IQ1M,
+    ] {
+        let quant1 = quantized::QTensor::quantize(&xs, dtype)?;
+        let quant2 = quantized::QTensor::quantize_imatrix(&xs, &imatrix, dtype)?;
+        let mm1 = lhs.matmul(&quant1.dequantize(cpu)?.t()?)?;
+        let mm2 = lhs.matmul(&quant2.dequantize(cpu)?.t()?)?;
+        let err1 = (mm1 - &expected)?.sqr()?.mean_all()?.to_scalar::<f32>()?;
+        let err2 = (mm2 - &expected)?.sqr()?.mean_all()?.to_scalar::<f32>()?;
+        assert!(err2 < err1, "{dtype:?}: err2 {err2} > err1 {err1}");
+    }
+    // The legacy and ternary types do not use an imatrix.
+    for dtype in [GgmlDType::Q8_0, GgmlDType::TQ1_0, GgmlDType::TQ2_0] {
+        assert!(!dtype.supports_imatrix(), "{dtype:?}");
+        assert!(quantized::QTensor::quantize_imatrix(&xs, &imatrix, dtype).is_err());
+    }
+    Ok(())
+}
+
+/// Checks the dequantization of raw blocks against ggml, the file is generated with
+/// ggml_golden.py.
+#[test]
+fn ggml_golden_blocks() -> Result<()> {
+    let dev = &Device::Cpu;
+    let mut file = std::fs::File::open("tests/ggml_golden.gguf")?;
+    let content = quantized::gguf_file::Content::read(&mut file)?;
+    let mut names = content
+        .tensor_infos
+        .keys()
+        .filter(|name| !name.ends_with("_dequant"))
+        .cloned()
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names.len(), 11);
+    for name in names {
+        let qtensor = content.tensor(&mut file, &name, dev)?;
+        let expected = content
+            .tensor(&mut file, &format!("{name}_dequant"), dev)?
+            .dequantize(dev)?;
+        let dequant = qtensor.dequantize(dev)?;
+        let diff = (dequant - &expected)?.abs()?.max(0)?.to_scalar::<f32>()?;
+        assert!(diff < 1e-5, "{name} {:?}: {diff}", qtensor.dtype());
+    }
+    Ok(())
+}
+
 /// generates random tensors of size `m x k` and `n x k` and calculates their expected matrix multiplication result.
 fn get_random_tensors(
     m: usize,
@@ -1400,3 +1540,277 @@ fn quantized_matmul_q8k() -> Result<()> {
     ggml_matmul_error_test::<BlockQ8K>()?;
     Ok(())
 }
+
+// Deterministic weights of shape `(n, k)` with values in [-1, 1].
+fn sin_weights(n: usize, k: usize, dev: &Device) -> Result<Tensor> {
+    Tensor::arange(0f32, (n * k) as f32, dev)?
+        .affine(0.37, 0.)?
+        .sin()?
+        .reshape((n, k))
+}
+
+#[test]
+fn mmaped_gguf() -> Result<()> {
+    use quantized::{gguf_file, QMatMul, QTensor};
+    let dev = Device::Cpu;
+    let w = sin_weights(16, 256, &dev)?;
+    let dtypes = [
+        GgmlDType::F32,
+        GgmlDType::F16,
+        GgmlDType::Q4_0,
+        GgmlDType::Q8_0,
+        GgmlDType::Q4K,
+        GgmlDType::Q6K,
+        GgmlDType::IQ4XS,
+    ];
+    let qtensors = dtypes
+        .iter()
+        .map(|dtype| Ok((format!("{dtype:?}"), QTensor::quantize(&w, *dtype)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let file = std::env::temp_dir().join(format!("candle-mmap-{}.gguf", std::process::id()));
+    let mut out = std::fs::File::create(&file)?;
+    let tensors = qtensors
+        .iter()
+        .map(|(name, t)| (name.as_str(), t))
+        .collect::<Vec<_>>();
+    gguf_file::write(&mut out, &[], &tensors)?;
+    drop(out);
+
+    let mmaped = unsafe { gguf_file::MmapedFile::new(&file) };
+    std::fs::remove_file(&file)?;
+    let mmaped = mmaped?;
+    assert_eq!(mmaped.content().tensor_infos.len(), dtypes.len());
+    let xs = Tensor::arange(0f32, 3. * 256., &dev)?
+        .affine(0.011, -1.)?
+        .reshape((3, 256))?;
+    for (name, expected) in qtensors {
+        let tensor = mmaped.tensor(&name, &dev)?;
+        assert_eq!(tensor.dtype(), expected.dtype());
+        assert_eq!(tensor.shape(), expected.shape());
+        let diff = (tensor.dequantize(&dev)? - expected.dequantize(&dev)?)?
+            .abs()?
+            .flatten_all()?
+            .max(0)?;
+        assert_eq!(diff.to_scalar::<f32>()?, 0.);
+        // The copying reader gives the same tensors.
+        let copied = mmaped.content().tensor(&mut mmaped.reader(), &name, &dev)?;
+        let ys = QMatMul::from_qtensor(tensor)?.forward(&xs)?;
+        let expected = QMatMul::from_qtensor(copied)?.forward(&xs)?;
+        let diff = (ys - expected)?.abs()?.flatten_all()?.max(0)?;
+        assert_eq!(diff.to_scalar::<f32>()?, 0., "{name}");
+    }
+    assert!(mmaped.tensor("missing", &dev).is_err());
+    Ok(())
+}
+
+#[test]
+fn qmatmul_backward() -> Result<()> {
+    use quantized::{QMatMul, QTensor};
+    let dev = Device::Cpu;
+    let w = Tensor::arange(0f32, 32. * 64., &dev)?
+        .affine(0.13, 0.)?
+        .sin()?
+        .reshape((32, 64))?;
+    let xs = Tensor::arange(0f32, 2. * 3. * 64., &dev)?
+        .affine(0.07, 0.)?
+        .cos()?
+        .reshape((2, 3, 64))?;
+    for dtype in [GgmlDType::Q4_0, GgmlDType::Q8_0] {
+        let qtensor = QTensor::quantize(&w, dtype)?;
+        let w = qtensor.dequantize(&dev)?;
+        let mm = QMatMul::from_qtensor(qtensor)?;
+        assert!(matches!(mm, QMatMul::QTensor(_)));
+        // A trainable adapter on top of the frozen quantized weights.
+        let xs = Var::from_tensor(&xs)?;
+        let adapter = Var::from_tensor(&Tensor::full(0.01f32, (64, 32), &dev)?)?;
+        let ys = (mm.forward(&xs)? + xs.broadcast_matmul(&adapter)?)?;
+        let grads = ys.sqr()?.sum_all()?.backward()?;
+
+        let expected = (xs.broadcast_matmul(&w.t()?)? + xs.broadcast_matmul(&adapter)?)?;
+        let expected_grads = expected.sqr()?.sum_all()?.backward()?;
+        for var in [xs.as_tensor(), adapter.as_tensor()] {
+            let grad = grads.get(var).unwrap();
+            let expected = expected_grads.get(var).unwrap();
+            let diff = (grad - expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+            let scale = expected.abs()?.max_all()?.to_scalar::<f32>()?;
+            assert!(diff <= 1e-2 * scale, "{dtype:?} {diff} {scale}");
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn int_quants() -> Result<()> {
+    use quantized::int_quants::{IntBits, IntQTensor, IntQuantConfig};
+    use quantized::QMatMul;
+    let dev = Device::Cpu;
+    // An odd number of input channels exercises the non-simd tail of the dot products.
+    let (n, k) = (24, 104);
+    let w = sin_weights(n, k, &dev)?;
+    let xs = Tensor::arange(0f32, (3 * k) as f32, &dev)?
+        .affine(0.05, -2.)?
+        .cos()?
+        .reshape((1, 3, k))?;
+    let configs = [
+        (IntQuantConfig::int8_per_channel(), 0.01),
+        (IntQuantConfig::int4_per_group(8), 0.15),
+        (
+            IntQuantConfig {
+                bits: IntBits::Int8,
+                group_size: Some(52),
+                symmetric: false,
+            },
+            0.01,
+        ),
+        (
+            IntQuantConfig {
+                bits: IntBits::Int4,
+                group_size: None,
+                symmetric: false,
+            },
+            0.15,
+        ),
+    ];
+    for (config, tolerance) in configs {
+        let qw = IntQTensor::quantize(&w, config)?;
+        let dw = qw.dequantize(&dev)?;
+        let err = (&w - &dw)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(err < tolerance, "{config:?} {err}");
+
+        // The int8 matmul matches the matmul with the dequantized weights.
+        let expected = xs.broadcast_matmul(&dw.t()?)?;
+        let ys = QMatMul::from_int(qw).forward(&xs)?;
+        assert_eq!(ys.dims(), [1, 3, n]);
+        let diff = (ys - &expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        let scale = expected.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(diff < 0.02 * scale, "{config:?} {diff} {scale}");
+    }
+    assert!(IntQTensor::quantize(&w, IntQuantConfig::int4_per_group(5)).is_err());
+    Ok(())
+}
+
+#[test]
+fn mx_elements() -> Result<()> {
+    use quantized::mx::{self, MxElement};
+    let fp4 = (0..8u8)
+        .map(|c| MxElement::Fp4E2M1.decode(c))
+        .collect::<Vec<_>>();
+    assert_eq!(fp4, [0., 0.5, 1., 1.5, 2., 3., 4., 6.]);
+    assert_eq!(MxElement::Fp4E2M1.decode(0b1111), -6.);
+    assert_eq!(MxElement::Fp6E2M3.max_value(), 7.5);
+    assert_eq!(MxElement::Fp6E3M2.max_value(), 28.);
+    assert_eq!(MxElement::Fp8E4M3.max_value(), 448.);
+    assert_eq!(MxElement::Fp8E5M2.max_value(), 57344.);
+    assert!(MxElement::Fp8E4M3.decode(0x7f).is_nan());
+    assert_eq!(MxElement::Fp8E5M2.decode(0x7c), f32::INFINITY);
+    // Ties round to even and the values out of range saturate.
+    assert_eq!(MxElement::Fp4E2M1.encode(2.5), 4);
+    assert_eq!(MxElement::Fp4E2M1.encode(-5.), 0b1110);
+    assert_eq!(MxElement::Fp4E2M1.encode(100.), 7);
+    assert_eq!(mx::e8m0_to_f32(127), 1.);
+    assert_eq!(mx::f32_to_e8m0(0.3), 125);
+
+    // The fp8 e4m3 codec matches the F8E4M3 dtype.
+    let dev = Device::Cpu;
+    let xs = Tensor::arange(-500f32, 500., &dev)?.affine(0.37, 0.)?;
+    let expected = xs.to_dtype(DType::F8E4M3)?.to_dtype(DType::F32)?;
+    let ys = xs
+        .to_vec1::<f32>()?
+        .iter()
+        .map(|&v| MxElement::Fp8E4M3.decode(MxElement::Fp8E4M3.encode(v)))
+        .collect::<Vec<_>>();
+    assert_eq!(ys, expected.to_vec1::<f32>()?);
+    Ok(())
+}
+
+#[test]
+fn low_precision_dtypes() -> Result<()> {
+    let dev = Device::Cpu;
+    let xs = Tensor::new(&[[0f32, 0.5, -1.5, 3.], [6., -4., 1., 2.]], &dev)?;
+    for dtype in [DType::F4, DType::F6E2M3, DType::F6E3M2] {
+        let ys = xs.to_dtype(dtype)?;
+        assert_eq!(ys.dtype(), dtype);
+        let ys = ys.to_dtype(DType::F32)?;
+        assert_eq!(ys.to_vec2::<f32>()?, xs.to_vec2::<f32>()?, "{dtype:?}");
+        // Non-zero offsets into the packed storage.
+        let ys = xs.to_dtype(dtype)?.flatten_all()?.narrow(0, 1, 5)?;
+        let ys = ys.to_dtype(DType::BF16)?.to_dtype(DType::F32)?;
+        assert_eq!(ys.to_vec1::<f32>()?, [0.5, -1.5, 3., 6., -4.]);
+    }
+    let ys = Tensor::new(&[1f32, 0.3, 1024.], &dev)?
+        .to_dtype(DType::F8E8M0)?
+        .to_dtype(DType::F32)?;
+    assert_eq!(ys.to_vec1::<f32>()?, [1., 0.25, 1024.]);
+    Ok(())
+}
+
+#[test]
+fn mx_matmul() -> Result<()> {
+    use quantized::mx::{MxElement, MxTensor};
+    use quantized::QMatMul;
+    let dev = Device::Cpu;
+    let (n, k) = (24, 96);
+    let w = sin_weights(n, k, &dev)?;
+    let xs = Tensor::arange(0f32, (3 * k) as f32, &dev)?
+        .affine(0.05, -2.)?
+        .cos()?
+        .reshape((1, 3, k))?;
+    let elements = [
+        (MxElement::Fp8E4M3, 0.07),
+        (MxElement::Fp8E5M2, 0.13),
+        (MxElement::Fp6E2M3, 0.07),
+        (MxElement::Fp6E3M2, 0.13),
+        (MxElement::Fp4E2M1, 0.26),
+    ];
+    for (element, tolerance) in elements {
+        let qw = MxTensor::quantize(&w, element)?;
+        let dw = qw.dequantize(&dev)?;
+        let err = (&w - &dw)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(err < tolerance, "{element:?} {err}");
+
+        let expected = xs.broadcast_matmul(&dw.t()?)?;
+        let ys = QMatMul::from_mx(qw).forward(&xs)?;
+        assert_eq!(ys.dims(), [1, 3, n]);
+        let diff = (ys - &expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+        assert!(diff < 1e-4, "{element:?} {diff}");
+    }
+    assert!(MxTensor::quantize(&w.narrow(1, 0, 40)?, MxElement::Fp4E2M1).is_err());
+    Ok(())
+}
+
+#[test]
+fn mxfp4_blocks() -> Result<()> {
+    use quantized::mx::MxTensor;
+    let dev = Device::Cpu;
+    // Two rows of one block each, the low nibbles hold the even elements.
+    let mut blocks = vec![0u8; 32];
+    blocks[0] = 0x21;
+    blocks[16] = 0xf7;
+    let blocks = Tensor::from_vec(blocks, (2, 1, 16), &dev)?;
+    let scales = Tensor::new(&[[127u8], [128]], &dev)?;
+    let w = MxTensor::from_mxfp4_blocks(&blocks, &scales)?;
+    assert_eq!(w.shape().dims(), [2, 32]);
+    let w = w.dequantize(&dev)?;
+    assert_eq!(
+        w.narrow(1, 0, 3)?.to_vec2::<f32>()?,
+        [[0.5, 1., 0.], [12., -12., 0.]]
+    );
+    Ok(())
+}
+
+#[test]
+fn fp8_blockwise() -> Result<()> {
+    let dev = Device::Cpu;
+    let w = Tensor::ones((3, 5), DType::F32, &dev)?.to_dtype(DType::F8E4M3)?;
+    let scale_inv = Tensor::new(&[[1f32, 2.], [3., 4.]], &dev)?;
+    let w = quantized::mx::dequantize_fp8_blockwise(&w, &scale_inv, (2, 4))?;
+    assert_eq!(
+        w.to_vec2::<f32>()?,
+        [
+            [1., 1., 1., 1., 2.],
+            [1., 1., 1., 1., 2.],
+            [3., 3., 3., 3., 4.]
+        ]
+    );
+    Ok(())
+}
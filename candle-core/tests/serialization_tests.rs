@@ -69,3 +69,19 @@ fn safetensors() -> Result<()> {
     assert_eq!(diff, 0f32);
     Ok(())
 }
+
+#[test]
+fn safetensors_f8e5m2() -> Result<()> {
+    let tmp_file = TmpFile::create("st_f8e5m2");
+    let t = Tensor::new(&[0.5f32, -2., 3., 57344.], &candle_core::Device::Cpu)?
+        .to_dtype(DType::F8E5M2)?;
+    t.save_safetensors("t", &tmp_file)?;
+    let st = candle_core::safetensors::load(&tmp_file, &candle_core::Device::Cpu)?;
+    let t2 = st.get("t").unwrap();
+    assert_eq!(t2.dtype(), DType::F8E5M2);
+    assert_eq!(
+        t2.to_dtype(DType::F32)?.to_vec1::<f32>()?,
+        [0.5, -2., 3., 57344.]
+    );
+    Ok(())
+}
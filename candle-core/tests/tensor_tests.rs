@@ -1998,3 +1998,23 @@ fn tensor_norm() -> Result<()> {
     assert_eq!(norm.to_scalar::<f64>()?, 5.);
     Ok(())
 }
+
+#[test]
+fn f8e5m2_roundtrip() -> Result<()> {
+    // These values are exactly representable with 5 exponent bits and 2 mantissa bits, 57344 is
+    // the largest finite value.
+    let values = [0.5f32, -2., 3., 0.25, -1.75, 57344.];
+    let t = Tensor::new(&values, &Device::Cpu)?;
+    let f8 = t.to_dtype(DType::F8E5M2)?;
+    assert_eq!(f8.dtype(), DType::F8E5M2);
+    assert_eq!(f8.to_dtype(DType::F32)?.to_vec1::<f32>()?, values);
+    let bf16 = t.to_dtype(DType::BF16)?.to_dtype(DType::F8E5M2)?;
+    assert_eq!(
+        bf16.to_dtype(DType::BF16)?.to_vec1::<half::bf16>()?,
+        values.map(half::bf16::from_f32)
+    );
+    // Values are rounded to the nearest representable one.
+    let t = Tensor::new(&[1.1f32, 100.], &Device::Cpu)?.to_dtype(DType::F8E5M2)?;
+    assert_eq!(t.to_dtype(DType::F32)?.to_vec1::<f32>()?, [1., 96.]);
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Collating functions turn a list of samples into a batch.
+use candle::{Result, Tensor};
+
+pub trait Collate<T>: Send + Sync {
+    type Output: Send;
+
+    fn collate(&self, items: Vec<T>) -> Result<Self::Output>;
+}
+
+impl<T, O, F> Collate<T> for F
+where
+    F: Fn(Vec<T>) -> Result<O> + Send + Sync,
+    O: Send,
+{
+    type Output = O;
+
+    fn collate(&self, items: Vec<T>) -> Result<O> {
+        self(items)
+    }
+}
+
+/// Stacks the samples along a new first dimension, all the samples must have the same shape.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stack;
+
+impl Collate<Tensor> for Stack {
+    type Output = Tensor;
+
+    fn collate(&self, items: Vec<Tensor>) -> Result<Tensor> {
+        Tensor::stack(&items, 0)
+    }
+}
+
+impl Collate<(Tensor, Tensor)> for Stack {
+    type Output = (Tensor, Tensor);
+
+    fn collate(&self, items: Vec<(Tensor, Tensor)>) -> Result<(Tensor, Tensor)> {
+        let (xs, ys): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+        Ok((Tensor::stack(&xs, 0)?, Tensor::stack(&ys, 0)?))
+    }
+}
+
+/// Pads the samples with `value` along `dim` to the length of the longest one before stacking
+/// them, e.g. for token sequences of different lengths.
+///
+/// The samples that all have the same shape, e.g. the labels, are stacked without padding.
+#[derive(Debug, Clone, Copy)]
+pub struct Pad {
+    value: f64,
+    dim: usize,
+}
+
+impl Pad {
+    pub fn new(value: f64) -> Self {
+        Self { value, dim: 0 }
+    }
+
+    /// The dimension to pad, in the samples before stacking. Defaults to 0.
+    pub fn dim(mut self, dim: usize) -> Self {
+        self.dim = dim;
+        self
+    }
+
+    fn pad_and_stack(&self, items: &[Tensor]) -> Result<Tensor> {
+        let first = match items.first() {
+            None => candle::bail!("cannot collate an empty batch"),
+            Some(first) => first,
+        };
+        if items.iter().all(|t| t.dims() == first.dims()) {
+            return Tensor::stack(items, 0);
+        }
+        let mut max_len = 0;
+        for t in items.iter() {
+            max_len = usize::max(max_len, t.dim(self.dim)?)
+        }
+        let items = items
+            .iter()
+            .map(|t| {
+                let len = t.dim(self.dim)?;
+                if len == max_len {
+                    return Ok(t.clone());
+                }
+                let mut dims = t.dims().to_vec();
+                dims[self.dim] = max_len - len;
+                let pad = Tensor::full(self.value, dims, t.device())?.to_dtype(t.dtype())?;
+                Tensor::cat(&[t, &pad], self.dim)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Tensor::stack(&items, 0)
+    }
+}
+
+impl Collate<Tensor> for Pad {
+    type Output = Tensor;
+
+    fn collate(&self, items: Vec<Tensor>) -> Result<Tensor> {
+        self.pad_and_stack(&items)
+    }
+}
+
+impl Collate<(Tensor, Tensor)> for Pad {
+    type Output = (Tensor, Tensor);
+
+    fn collate(&self, items: Vec<(Tensor, Tensor)>) -> Result<(Tensor, Tensor)> {
+        let (xs, ys): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+        Ok((self.pad_and_stack(&xs)?, self.pad_and_stack(&ys)?))
+    }
+}
@@ -0,0 +1,256 @@
+//! Batched iteration over a [`Dataset`], with the samples fetched by worker threads.
+//!
+//! ```no_run
+//! use candle_datasets::{collate::Pad, DataLoader};
+//! # fn main() -> candle::Result<()> {
+//! # let (inputs, labels): (Vec<candle::Tensor>, Vec<candle::Tensor>) = (vec![], vec![]);
+//! let mut loader = DataLoader::new((inputs, labels))
+//!     .batch_size(32)
+//!     .shuffle(42)
+//!     .collate(Pad::new(0.))
+//!     .num_workers(4);
+//! for _epoch in 0..10 {
+//!     for batch in loader.iter()? {
+//!         let (xs, ys) = batch?;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::collate::{Collate, Stack};
+use crate::dataset::Dataset;
+use crate::sampler::{RandomSampler, Sampler, SequentialSampler};
+use candle::{Error, Result};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+pub struct DataLoader<D, C = Stack> {
+    dataset: Arc<D>,
+    collate: Arc<C>,
+    sampler: Arc<dyn Sampler>,
+    batch_size: usize,
+    drop_last: bool,
+    num_workers: usize,
+    prefetch: usize,
+    epoch: usize,
+}
+
+impl<D: Dataset> DataLoader<D, Stack> {
+    /// A data loader that visits the samples in order in batches of 16, the samples are stacked
+    /// and fetched on the calling thread.
+    pub fn new(dataset: D) -> Self {
+        Self {
+            dataset: Arc::new(dataset),
+            collate: Arc::new(Stack),
+            sampler: Arc::new(SequentialSampler),
+            batch_size: 16,
+            drop_last: false,
+            num_workers: 0,
+            prefetch: 2,
+            epoch: 0,
+        }
+    }
+}
+
+impl<D: Dataset, C> DataLoader<D, C> {
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Whether to skip the last batch when it has less than `batch_size` samples.
+    pub fn drop_last(mut self, drop_last: bool) -> Self {
+        self.drop_last = drop_last;
+        self
+    }
+
+    /// Visits the samples in a different random order at each epoch, see [`RandomSampler`].
+    pub fn shuffle(self, seed: u64) -> Self {
+        self.sampler(RandomSampler::new(seed))
+    }
+
+    pub fn sampler<S: Sampler + 'static>(mut self, sampler: S) -> Self {
+        self.sampler = Arc::new(sampler);
+        self
+    }
+
+    pub fn collate<C2: Collate<D::Item>>(self, collate: C2) -> DataLoader<D, C2> {
+        DataLoader {
+            dataset: self.dataset,
+            collate: Arc::new(collate),
+            sampler: self.sampler,
+            batch_size: self.batch_size,
+            drop_last: self.drop_last,
+            num_workers: self.num_workers,
+            prefetch: self.prefetch,
+            epoch: self.epoch,
+        }
+    }
+
+    /// The number of threads used to fetch and collate the batches, with 0 the batches are
+    /// prepared on the calling thread when iterating.
+    pub fn num_workers(mut self, num_workers: usize) -> Self {
+        self.num_workers = num_workers;
+        self
+    }
+
+    /// The number of batches that each worker can prepare in advance.
+    pub fn prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    pub fn dataset(&self) -> &D {
+        self.dataset.as_ref()
+    }
+
+    /// The epoch used by the sampler for the next call to [`DataLoader::iter`], this is
+    /// incremented after each call.
+    pub fn epoch(&self) -> usize {
+        self.epoch
+    }
+
+    /// Sets the epoch, e.g. when resuming a training run.
+    pub fn set_epoch(&mut self, epoch: usize) {
+        self.epoch = epoch
+    }
+
+    fn batches(&self) -> Result<Vec<Vec<usize>>> {
+        let indices = self.sampler.indices(self.dataset.len(), self.epoch)?;
+        let batches = indices
+            .chunks(self.batch_size)
+            .filter(|b| !self.drop_last || b.len() == self.batch_size)
+            .map(|b| b.to_vec())
+            .collect();
+        Ok(batches)
+    }
+
+    /// The number of batches in the current epoch.
+    pub fn num_batches(&self) -> Result<usize> {
+        Ok(self.batches()?.len())
+    }
+}
+
+fn fetch<D: Dataset, C: Collate<D::Item>>(
+    dataset: &D,
+    collate: &C,
+    indices: &[usize],
+) -> Result<C::Output> {
+    let items = indices
+        .iter()
+        .map(|&i| dataset.get(i))
+        .collect::<Result<Vec<_>>>()?;
+    collate.collate(items)
+}
+
+impl<D, C> DataLoader<D, C>
+where
+    D: Dataset + 'static,
+    C: Collate<D::Item> + 'static,
+{
+    /// Iterates over the batches of the current epoch and moves to the next epoch.
+    pub fn iter(&mut self) -> Result<DataLoaderIter<C::Output>> {
+        let batches = self.batches()?;
+        self.epoch += 1;
+        let num_batches = batches.len();
+        let num_workers = usize::min(self.num_workers, num_batches);
+        if num_workers == 0 {
+            let dataset = self.dataset.clone();
+            let collate = self.collate.clone();
+            let iter = batches
+                .into_iter()
+                .map(move |b| fetch(dataset.as_ref(), collate.as_ref(), &b));
+            return Ok(DataLoaderIter {
+                inner: Inner::Local(Box::new(iter)),
+                num_batches,
+                index: 0,
+            });
+        }
+        // The batches are assigned to the workers in a round-robin way and each worker has its
+        // own bounded channel, so that the batches can be returned in order.
+        let batches = Arc::new(batches);
+        let mut receivers = Vec::with_capacity(num_workers);
+        let mut handles = Vec::with_capacity(num_workers);
+        for worker in 0..num_workers {
+            let (tx, rx) = sync_channel(self.prefetch);
+            let dataset = self.dataset.clone();
+            let collate = self.collate.clone();
+            let batches = batches.clone();
+            let handle = std::thread::Builder::new()
+                .name(format!("data-loader-{worker}"))
+                .spawn(move || {
+                    for b in batches.iter().skip(worker).step_by(num_workers) {
+                        let batch = fetch(dataset.as_ref(), collate.as_ref(), b);
+                        // The iterator has been dropped.
+                        if tx.send(batch).is_err() {
+                            break;
+                        }
+                    }
+                })?;
+            receivers.push(rx);
+            handles.push(handle);
+        }
+        Ok(DataLoaderIter {
+            inner: Inner::Workers { receivers, handles },
+            num_batches,
+            index: 0,
+        })
+    }
+}
+
+enum Inner<O> {
+    Local(Box<dyn Iterator<Item = Result<O>> + Send>),
+    Workers {
+        receivers: Vec<Receiver<Result<O>>>,
+        handles: Vec<JoinHandle<()>>,
+    },
+}
+
+/// The batches of an epoch, see [`DataLoader::iter`].
+pub struct DataLoaderIter<O> {
+    inner: Inner<O>,
+    num_batches: usize,
+    index: usize,
+}
+
+impl<O> Iterator for DataLoaderIter<O> {
+    type Item = Result<O>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.num_batches {
+            return None;
+        }
+        let batch = match &mut self.inner {
+            Inner::Local(iter) => iter.next()?,
+            Inner::Workers { receivers, .. } => {
+                let rx = &receivers[self.index % receivers.len()];
+                match rx.recv() {
+                    Ok(batch) => batch,
+                    Err(_) => Err(Error::msg("data loader worker panicked")),
+                }
+            }
+        };
+        self.index += 1;
+        Some(batch)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.num_batches - self.index;
+        (len, Some(len))
+    }
+}
+
+impl<O> ExactSizeIterator for DataLoaderIter<O> {}
+
+impl<O> Drop for DataLoaderIter<O> {
+    fn drop(&mut self) {
+        if let Inner::Workers { receivers, handles } = &mut self.inner {
+            // Dropping the receivers makes the workers that are blocked on sending exit.
+            receivers.clear();
+            for handle in handles.drain(..) {
+                let _ = handle.join();
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Indexable datasets.
+//!
+//! A [`Dataset`] gives random access to its samples, this is what a
+//! [`DataLoader`](crate::DataLoader) needs to shuffle them and to fetch them from multiple
+//! threads.
+use candle::{Result, Tensor};
+use std::sync::Arc;
+
+/// A collection of samples that can be accessed by index.
+pub trait Dataset: Send + Sync {
+    type Item: Send;
+
+    /// The number of samples.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the sample at `index`, `index` has to be smaller than `len()`.
+    fn get(&self, index: usize) -> Result<Self::Item>;
+}
+
+/// The samples of a tensor are indexed along its first dimension.
+impl Dataset for Tensor {
+    type Item = Tensor;
+
+    fn len(&self) -> usize {
+        self.dims().first().copied().unwrap_or(0)
+    }
+
+    fn get(&self, index: usize) -> Result<Tensor> {
+        Tensor::get(self, index)
+    }
+}
+
+impl<T: Clone + Send + Sync> Dataset for Vec<T> {
+    type Item = T;
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn get(&self, index: usize) -> Result<T> {
+        match self.as_slice().get(index) {
+            Some(v) => Ok(v.clone()),
+            None => candle::bail!(
+                "index {index} out of range for dataset of len {}",
+                self.len()
+            ),
+        }
+    }
+}
+
+/// Zips two datasets, e.g. `(images, labels)`.
+impl<A: Dataset, B: Dataset> Dataset for (A, B) {
+    type Item = (A::Item, B::Item);
+
+    fn len(&self) -> usize {
+        usize::min(self.0.len(), self.1.len())
+    }
+
+    fn get(&self, index: usize) -> Result<Self::Item> {
+        Ok((self.0.get(index)?, self.1.get(index)?))
+    }
+}
+
+impl<D: Dataset + ?Sized> Dataset for Arc<D> {
+    type Item = D::Item;
+
+    fn len(&self) -> usize {
+        self.as_ref().len()
+    }
+
+    fn get(&self, index: usize) -> Result<Self::Item> {
+        self.as_ref().get(index)
+    }
+}
+
+/// The samples of a dataset at the given indexes, e.g. for a train/validation split.
+pub struct Subset<D> {
+    dataset: D,
+    indices: Vec<usize>,
+}
+
+impl<D: Dataset> Subset<D> {
+    pub fn new(dataset: D, indices: Vec<usize>) -> Result<Self> {
+        let len = dataset.len();
+        if let Some(index) = indices.iter().find(|&&i| i >= len) {
+            candle::bail!("index {index} out of range for dataset of len {len}")
+        }
+        Ok(Self { dataset, indices })
+    }
+}
+
+impl<D: Dataset> Dataset for Subset<D> {
+    type Item = D::Item;
+
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn get(&self, index: usize) -> Result<Self::Item> {
+        match self.indices.as_slice().get(index) {
+            Some(&i) => self.dataset.get(i),
+            None => candle::bail!(
+                "index {index} out of range for subset of len {}",
+                self.len()
+            ),
+        }
+    }
+}
+
+/// Applies a function to the samples of a dataset when they are fetched, e.g. to decode or
+/// augment them. The function runs in the data loader worker threads.
+pub struct Map<D, F> {
+    dataset: D,
+    f: F,
+}
+
+impl<D, F> Map<D, F> {
+    pub fn new(dataset: D, f: F) -> Self {
+        Self { dataset, f }
+    }
+}
+
+impl<D, F, T> Dataset for Map<D, F>
+where
+    D: Dataset,
+    F: Fn(D::Item) -> Result<T> + Send + Sync,
+    T: Send,
+{
+    type Item = T;
+
+    fn len(&self) -> usize {
+        self.dataset.len()
+    }
+
+    fn get(&self, index: usize) -> Result<T> {
+        (self.f)(self.dataset.get(index)?)
+    }
+}
@@ -1,7 +1,13 @@
 //! Datasets & Dataloaders for Candle
 pub mod batcher;
+pub mod collate;
+pub mod data_loader;
+pub mod dataset;
 pub mod hub;
 pub mod nlp;
+pub mod sampler;
 pub mod vision;
 
 pub use batcher::Batcher;
+pub use data_loader::DataLoader;
+pub use dataset::Dataset;
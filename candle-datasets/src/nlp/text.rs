@@ -0,0 +1,881 @@
+//! Local text datasets read from Parquet, JSONL or CSV files.
+//!
+//! A column of the file is tokenized with a [`Tokenizer`] and the tokens are cached on disk, the
+//! records are read and tokenized by chunks that are appended to the cache files, and the cache
+//! files are memory-mapped so that the datasets can be larger than the available memory.
+//! The column can contain plain text or chat records, i.e. lists of `{"role", "content"}`
+//! messages that are formatted with a [`ChatFormat`] and get a loss mask so that only the
+//! tokens of the assistant answers are trained on.
+//!
+//! The tokenized documents can then be used for training in two ways:
+//! - [`PackedDataset`] concatenates the documents separated with an EOS token and splits the
+//!   result in blocks of `seq_len` tokens.
+//! - [`SequenceDataset`] returns each document as a sample, [`PadBatch`] pads them into batches
+//!   with an attention mask.
+//!
+//! ```no_run
+//! use candle_datasets::nlp::text::{PackedDataset, PadBatch, TextFile};
+//! use candle_datasets::DataLoader;
+//! # fn main() -> candle::Result<()> {
+//! let tokenizer = tokenizers::Tokenizer::from_file("tokenizer.json").map_err(candle::Error::msg)?;
+//! let text = TextFile::new("train.jsonl").column("text").tokenize(&tokenizer)?;
+//! let dataset = PackedDataset::new(std::sync::Arc::new(text), 1024, 2);
+//! let mut loader = DataLoader::new(dataset)
+//!     .batch_size(8)
+//!     .shuffle(42)
+//!     .collate(PadBatch::new(0));
+//! for batch in loader.iter()? {
+//!     let batch = batch?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::collate::Collate;
+use crate::dataset::Dataset;
+use candle::{Device, Error, Result, Tensor};
+use parquet::file::reader::SerializedFileReader;
+use parquet::record::Field;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
+// Bumped when the layout of the cache files changes.
+const CACHE_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Text(String),
+    Chat(Vec<ChatMessage>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Parquet,
+    /// One json object per line.
+    Jsonl,
+    /// Comma separated values with a header line.
+    Csv,
+}
+
+impl Format {
+    /// The format corresponding to the extension of `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match ext.to_lowercase().as_str() {
+            "parquet" => Ok(Self::Parquet),
+            "jsonl" | "json" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => candle::bail!("cannot infer the format of {path:?}, expected parquet/jsonl/csv"),
+        }
+    }
+}
+
+fn json_messages(value: &serde_json::Value) -> Result<Vec<ChatMessage>> {
+    let messages = match value.as_array() {
+        Some(messages) => messages,
+        None => candle::bail!("expected a list of messages, got {value}"),
+    };
+    messages
+        .iter()
+        .map(|m| {
+            let field = |name: &str| match m.get(name).and_then(|v| v.as_str()) {
+                Some(v) => Ok(v.to_string()),
+                None => candle::bail!("missing {name} in message {m}"),
+            };
+            Ok(ChatMessage {
+                role: field("role")?,
+                content: field("content")?,
+            })
+        })
+        .collect()
+}
+
+fn string_record(s: &str, chat: bool) -> Result<Record> {
+    if chat {
+        let value: serde_json::Value = serde_json::from_str(s).map_err(Error::wrap)?;
+        Ok(Record::Chat(json_messages(&value)?))
+    } else {
+        Ok(Record::Text(s.to_string()))
+    }
+}
+
+fn json_record(value: &serde_json::Value, chat: bool) -> Result<Record> {
+    match value {
+        serde_json::Value::String(s) => string_record(s, chat),
+        serde_json::Value::Array(_) if chat => Ok(Record::Chat(json_messages(value)?)),
+        _ => candle::bail!("unexpected value {value}"),
+    }
+}
+
+fn parquet_record(field: &Field, chat: bool) -> Result<Record> {
+    match field {
+        Field::Str(s) => string_record(s, chat),
+        Field::ListInternal(list) if chat => {
+            let messages = list
+                .elements()
+                .iter()
+                .map(|m| {
+                    let row = match m {
+                        Field::Group(row) => row,
+                        _ => candle::bail!("expected a message struct, got {m}"),
+                    };
+                    let field = |name: &str| {
+                        let v = row.get_column_iter().find(|(n, _)| n.as_str() == name);
+                        match v {
+                            Some((_, Field::Str(v))) => Ok(v.clone()),
+                            _ => candle::bail!("missing {name} in message {m}"),
+                        }
+                    };
+                    Ok(ChatMessage {
+                        role: field("role")?,
+                        content: field("content")?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Record::Chat(messages))
+        }
+        _ => candle::bail!("unexpected value {field}"),
+    }
+}
+
+// Splits csv content in rows of fields, the fields can be quoted with `"` in which case they can
+// contain commas, newlines and `""` escaped quotes. The content is read line by line, a row spans
+// multiple lines when a quoted field contains newlines.
+struct CsvReader<R> {
+    reader: R,
+    line: String,
+    row: Vec<String>,
+    field: String,
+    in_quotes: bool,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::new(),
+            row: vec![],
+            field: String::new(),
+            in_quotes: false,
+        }
+    }
+
+    fn next_row(&mut self) -> Result<Option<Vec<String>>> {
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                if self.in_quotes {
+                    candle::bail!("unterminated quoted field in csv")
+                }
+                if self.field.is_empty() && self.row.is_empty() {
+                    return Ok(None);
+                }
+                self.row.push(std::mem::take(&mut self.field));
+                return Ok(Some(std::mem::take(&mut self.row)));
+            }
+            // The lookahead characters are always on the same line.
+            let mut chars = self.line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (c, self.in_quotes) {
+                    ('"', true) if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        self.field.push('"')
+                    }
+                    ('"', true) => self.in_quotes = false,
+                    ('"', false) if self.field.is_empty() => self.in_quotes = true,
+                    (',', false) => self.row.push(std::mem::take(&mut self.field)),
+                    ('\r', false) if chars.peek() == Some(&'\n') => {}
+                    ('\n', false) => {
+                        self.row.push(std::mem::take(&mut self.field));
+                        return Ok(Some(std::mem::take(&mut self.row)));
+                    }
+                    (c, _) => self.field.push(c),
+                }
+            }
+        }
+    }
+}
+
+// Calls `f` on the values of `column` in a file, one record at a time.
+fn for_each_record(
+    path: &Path,
+    format: Format,
+    column: &str,
+    chat: bool,
+    mut f: impl FnMut(Record) -> Result<()>,
+) -> Result<()> {
+    match format {
+        Format::Parquet => {
+            let reader =
+                SerializedFileReader::new(std::fs::File::open(path)?).map_err(Error::wrap)?;
+            for row in reader.into_iter() {
+                let row = row.map_err(Error::wrap)?;
+                let field = row.get_column_iter().find(|(n, _)| n.as_str() == column);
+                match field {
+                    Some((_, field)) => f(parquet_record(field, chat)?)?,
+                    None => candle::bail!("no column {column} in {path:?}"),
+                }
+            }
+        }
+        Format::Jsonl => {
+            let reader = BufReader::new(std::fs::File::open(path)?);
+            for (i, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let value: serde_json::Value = serde_json::from_str(&line)
+                    .map_err(|e| Error::msg(format!("{path:?}:{}: {e}", i + 1)))?;
+                match value.get(column) {
+                    Some(value) => f(json_record(value, chat)?)?,
+                    None => candle::bail!("{path:?}:{}: no column {column}", i + 1),
+                }
+            }
+        }
+        Format::Csv => {
+            let mut rows = CsvReader::new(BufReader::new(std::fs::File::open(path)?));
+            let header = rows.next_row()?.unwrap_or_default();
+            let index = match header.iter().position(|h| h == column) {
+                Some(index) => index,
+                None => candle::bail!("no column {column} in {path:?}"),
+            };
+            while let Some(row) = rows.next_row()? {
+                match row.as_slice().get(index) {
+                    Some(value) => f(string_record(value, chat)?)?,
+                    None => candle::bail!("missing column {column} in {row:?}"),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the values of `column` in a file, when `chat` is set the values are expected to be
+/// lists of messages, possibly encoded as json strings.
+pub fn read_column<P: AsRef<Path>>(
+    path: P,
+    format: Format,
+    column: &str,
+    chat: bool,
+) -> Result<Vec<Record>> {
+    let mut records = vec![];
+    for_each_record(path.as_ref(), format, column, chat, |record| {
+        records.push(record);
+        Ok(())
+    })?;
+    Ok(records)
+}
+
+/// How the messages of chat records are turned into text.
+///
+/// Each message is formatted as `prefix`, `content` then `suffix`, the `{role}` placeholder in
+/// the prefix is replaced with the message role. The loss mask covers the content and suffix of
+/// the messages whose role is in `train_roles`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatFormat {
+    prefix: String,
+    suffix: String,
+    train_roles: Vec<String>,
+}
+
+impl ChatFormat {
+    pub fn new(prefix: &str, suffix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+            train_roles: vec!["assistant".to_string()],
+        }
+    }
+
+    /// The ChatML format, `<|im_start|>{role}\n{content}<|im_end|>\n`.
+    pub fn chatml() -> Self {
+        Self::new("<|im_start|>{role}\n", "<|im_end|>\n")
+    }
+
+    /// The roles whose messages are trained on, defaults to `["assistant"]`.
+    pub fn train_roles(mut self, roles: &[&str]) -> Self {
+        self.train_roles = roles.iter().map(|r| r.to_string()).collect();
+        self
+    }
+}
+
+// FNV-1a, used rather than the std hasher for the cache keys to be stable across rust versions.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes.iter() {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3)
+        }
+        // Separates the successive fields.
+        self.0 ^= 0xff;
+        self.0 = self.0.wrapping_mul(0x100000001b3)
+    }
+}
+
+/// A text file to tokenize, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct TextFile {
+    path: PathBuf,
+    format: Option<Format>,
+    column: String,
+    chat: Option<ChatFormat>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl TextFile {
+    /// Reads the `text` column of `path`, the format is inferred from the file extension.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            format: None,
+            column: "text".to_string(),
+            chat: None,
+            cache_dir: None,
+        }
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn column(mut self, column: &str) -> Self {
+        self.column = column.to_string();
+        self
+    }
+
+    /// Reads the column as chat records formatted with `chat_format`.
+    pub fn chat(mut self, chat_format: ChatFormat) -> Self {
+        self.chat = Some(chat_format);
+        self
+    }
+
+    /// Where the tokenized data is cached, defaults to the directory of the file.
+    pub fn cache_dir<P: AsRef<Path>>(mut self, cache_dir: P) -> Self {
+        self.cache_dir = Some(cache_dir.as_ref().to_path_buf());
+        self
+    }
+
+    // The cache files depend on the content of the file through its size and modification time,
+    // and on the options and the tokenizer.
+    fn cache_prefix(&self, tokenizer: &Tokenizer) -> Result<PathBuf> {
+        let metadata = std::fs::metadata(&self.path)?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(Error::wrap)?;
+        let mut hasher = Fnv::new();
+        hasher.write(&CACHE_VERSION.to_le_bytes());
+        hasher.write(self.path.to_string_lossy().as_bytes());
+        hasher.write(&metadata.len().to_le_bytes());
+        hasher.write(&mtime.as_nanos().to_le_bytes());
+        hasher.write(self.column.as_bytes());
+        if let Some(chat) = &self.chat {
+            hasher.write(chat.prefix.as_bytes());
+            hasher.write(chat.suffix.as_bytes());
+            hasher.write(chat.train_roles.join("\n").as_bytes());
+        }
+        hasher.write(tokenizer.to_string(false).map_err(Error::msg)?.as_bytes());
+        let dir = match &self.cache_dir {
+            Some(dir) => dir.clone(),
+            None => self.path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        };
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        Ok(dir.join(format!("{name}.{:016x}", hasher.0)))
+    }
+
+    fn tokenize_chunk(
+        &self,
+        tokenizer: &Tokenizer,
+        chunk: &[Record],
+        cache: &mut CacheWriter,
+    ) -> Result<()> {
+        match &self.chat {
+            None => {
+                let texts = chunk
+                    .iter()
+                    .map(|r| match r {
+                        Record::Text(t) => Ok(t.as_str()),
+                        Record::Chat(_) => candle::bail!("unexpected chat record"),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let encodings = tokenizer.encode_batch(texts, false).map_err(Error::msg)?;
+                for encoding in encodings.iter() {
+                    cache.push_document(encoding.get_ids(), None)?
+                }
+            }
+            Some(chat_format) => {
+                for record in chunk.iter() {
+                    let messages = match record {
+                        Record::Chat(messages) => messages,
+                        Record::Text(_) => candle::bail!("unexpected text record"),
+                    };
+                    let (ids, mask) = tokenize_chat(tokenizer, chat_format, messages)?;
+                    cache.push_document(&ids, Some(&mask))?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The records are tokenized by chunks of 1024 that are written to the cache files as they
+    // come so that the file does not have to fit in memory.
+    fn tokenize_records(&self, tokenizer: &Tokenizer, cache: &mut CacheWriter) -> Result<()> {
+        const CHUNK_SIZE: usize = 1024;
+        let format = match self.format {
+            Some(format) => format,
+            None => Format::from_path(&self.path)?,
+        };
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        let chat = self.chat.is_some();
+        for_each_record(&self.path, format, &self.column, chat, |record| {
+            chunk.push(record);
+            if chunk.len() == CHUNK_SIZE {
+                self.tokenize_chunk(tokenizer, &chunk, cache)?;
+                chunk.clear()
+            }
+            Ok(())
+        })?;
+        self.tokenize_chunk(tokenizer, &chunk, cache)
+    }
+
+    /// Tokenizes the column, or loads the tokens from the cache when the file has already been
+    /// tokenized with the same options.
+    pub fn tokenize(&self, tokenizer: &Tokenizer) -> Result<TokenizedText> {
+        let prefix = self.cache_prefix(tokenizer)?;
+        let with_ext = |ext: &str| {
+            let mut p = prefix.clone().into_os_string();
+            p.push(ext);
+            PathBuf::from(p)
+        };
+        let tokens = with_ext(".tokens");
+        let offsets = with_ext(".offsets");
+        let mask = with_ext(".mask");
+        let has_mask = self.chat.is_some();
+        if !tokens.exists() || !offsets.exists() || (has_mask && !mask.exists()) {
+            if let Some(dir) = prefix.parent() {
+                std::fs::create_dir_all(dir)?
+            }
+            let mut cache = CacheWriter::new(&tokens, &offsets, has_mask.then_some(&mask))?;
+            self.tokenize_records(tokenizer, &mut cache)?;
+            cache.finish()?;
+        }
+        let loss_mask = if has_mask {
+            Some(mmap_file(&mask)?)
+        } else {
+            None
+        };
+        TokenizedText::new(mmap_file(&tokens)?, mmap_file(&offsets)?, loss_mask)
+    }
+}
+
+fn tokenize_chat(
+    tokenizer: &Tokenizer,
+    chat_format: &ChatFormat,
+    messages: &[ChatMessage],
+) -> Result<(Vec<u32>, Vec<u8>)> {
+    let mut ids = vec![];
+    let mut mask = vec![];
+    // The prefixes and contents are tokenized separately so that the masks are aligned with the
+    // token boundaries.
+    for message in messages.iter() {
+        let prefix = chat_format.prefix.replace("{role}", &message.role);
+        let content = format!("{}{}", message.content, chat_format.suffix);
+        let trained = chat_format.train_roles.contains(&message.role) as u8;
+        for (text, m) in [(prefix, 0), (content, trained)] {
+            let encoding = tokenizer.encode(text, false).map_err(Error::msg)?;
+            ids.extend_from_slice(encoding.get_ids());
+            mask.resize(ids.len(), m)
+        }
+    }
+    Ok((ids, mask))
+}
+
+// A file written under a temporary name and renamed to `path` once complete, the temporary
+// name is unique so that several processes can build the same cache concurrently, e.g. the
+// ranks of a distributed run. The temporary file is removed if it is not persisted.
+struct AtomicFile {
+    path: PathBuf,
+    tmp: PathBuf,
+    file: Option<std::io::BufWriter<std::fs::File>>,
+}
+
+impl AtomicFile {
+    fn create(path: &Path) -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let mut tmp = path.as_os_str().to_owned();
+        let counter = COUNTER.fetch_add(1, Ordering::Relaxed);
+        tmp.push(format!(".{}.{counter}.tmp", std::process::id()));
+        let tmp = PathBuf::from(tmp);
+        let file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp,
+            file: Some(file),
+        })
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(bytes)?
+        }
+        Ok(())
+    }
+
+    fn persist(mut self) -> Result<()> {
+        if let Some(file) = self.file.take() {
+            file.into_inner().map_err(Error::wrap)?.sync_all()?;
+            std::fs::rename(&self.tmp, &self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+// Appends the tokenized documents to the cache files.
+struct CacheWriter {
+    tokens: AtomicFile,
+    offsets: AtomicFile,
+    loss_mask: Option<AtomicFile>,
+    num_tokens: u64,
+}
+
+impl CacheWriter {
+    fn new(tokens: &Path, offsets: &Path, loss_mask: Option<&PathBuf>) -> Result<Self> {
+        let mut offsets = AtomicFile::create(offsets)?;
+        offsets.write_all(&0u64.to_le_bytes())?;
+        Ok(Self {
+            tokens: AtomicFile::create(tokens)?,
+            offsets,
+            loss_mask: loss_mask.map(|p| AtomicFile::create(p)).transpose()?,
+            num_tokens: 0,
+        })
+    }
+
+    fn push_document(&mut self, ids: &[u32], mask: Option<&[u8]>) -> Result<()> {
+        for id in ids.iter() {
+            self.tokens.write_all(&id.to_le_bytes())?
+        }
+        if let (Some(file), Some(mask)) = (self.loss_mask.as_mut(), mask) {
+            file.write_all(mask)?
+        }
+        self.num_tokens += ids.len() as u64;
+        self.offsets.write_all(&self.num_tokens.to_le_bytes())
+    }
+
+    fn finish(self) -> Result<()> {
+        // The tokens are renamed last as their presence marks a complete cache.
+        self.offsets.persist()?;
+        if let Some(loss_mask) = self.loss_mask {
+            loss_mask.persist()?;
+        }
+        self.tokens.persist()
+    }
+}
+
+fn mmap_file(p: &Path) -> Result<memmap2::Mmap> {
+    let file = std::fs::File::open(p)?;
+    let mmap = unsafe { memmap2::MmapOptions::new().map(&file)? };
+    Ok(mmap)
+}
+
+/// The memory-mapped tokens of the documents of a [`TextFile`].
+pub struct TokenizedText {
+    // u32 little endian tokens.
+    tokens: memmap2::Mmap,
+    // u64 little endian offsets of the documents in the tokens, with a final offset for the end.
+    offsets: memmap2::Mmap,
+    // One byte per token for the chat records.
+    loss_mask: Option<memmap2::Mmap>,
+}
+
+impl TokenizedText {
+    fn new(
+        tokens: memmap2::Mmap,
+        offsets: memmap2::Mmap,
+        loss_mask: Option<memmap2::Mmap>,
+    ) -> Result<Self> {
+        let text = Self {
+            tokens,
+            offsets,
+            loss_mask,
+        };
+        let num_tokens = text.tokens.len() / 4;
+        let valid = text.tokens.len().is_multiple_of(4)
+            && text.offsets.len().is_multiple_of(8)
+            && (text.num_documents() == 0 || text.offset(text.num_documents()) == num_tokens)
+            && text
+                .loss_mask
+                .as_ref()
+                .is_none_or(|m| m.len() == num_tokens);
+        if !valid {
+            candle::bail!("inconsistent tokenized text cache files")
+        }
+        Ok(text)
+    }
+
+    fn offset(&self, i: usize) -> usize {
+        let bytes = &self.offsets[i * 8..(i + 1) * 8];
+        u64::from_le_bytes(bytes.try_into().unwrap()) as usize
+    }
+
+    pub fn num_documents(&self) -> usize {
+        (self.offsets.len() / 8).saturating_sub(1)
+    }
+
+    pub fn num_tokens(&self) -> usize {
+        self.tokens.len() / 4
+    }
+
+    pub fn has_loss_mask(&self) -> bool {
+        self.loss_mask.is_some()
+    }
+
+    /// The range of the tokens of document `i`.
+    fn range(&self, i: usize) -> Result<std::ops::Range<usize>> {
+        if i >= self.num_documents() {
+            candle::bail!(
+                "document {i} out of range for {} documents",
+                self.num_documents()
+            )
+        }
+        Ok(self.offset(i)..self.offset(i + 1))
+    }
+
+    fn tokens(&self, range: std::ops::Range<usize>) -> Vec<u32> {
+        self.tokens[range.start * 4..range.end * 4]
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            .collect()
+    }
+
+    // The loss mask is all ones for the plain text records.
+    fn loss_mask(&self, range: std::ops::Range<usize>) -> Vec<u8> {
+        match &self.loss_mask {
+            Some(mask) => mask[range].to_vec(),
+            None => vec![1; range.len()],
+        }
+    }
+
+    /// The tokens of document `i`.
+    pub fn document(&self, i: usize) -> Result<Vec<u32>> {
+        Ok(self.tokens(self.range(i)?))
+    }
+
+    /// The loss mask of document `i`, 1 for the tokens that should be trained on.
+    pub fn document_loss_mask(&self, i: usize) -> Result<Vec<u8>> {
+        Ok(self.loss_mask(self.range(i)?))
+    }
+}
+
+/// A training sample, the targets are the inputs shifted by one token and the loss mask applies
+/// to the targets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextSample {
+    pub input_ids: Vec<u32>,
+    pub target_ids: Vec<u32>,
+    pub loss_mask: Vec<u8>,
+}
+
+impl TextSample {
+    // Builds the sample for a sequence of tokens and their loss mask.
+    fn shifted(tokens: Vec<u32>, mask: Vec<u8>) -> Self {
+        let n = tokens.len().saturating_sub(1);
+        Self {
+            input_ids: tokens[..n].to_vec(),
+            target_ids: tokens.as_slice().get(1..).unwrap_or_default().to_vec(),
+            loss_mask: mask.as_slice().get(1..).unwrap_or_default().to_vec(),
+        }
+    }
+}
+
+/// The documents concatenated with an EOS token after each of them, and split in blocks of
+/// `seq_len` tokens. The EOS tokens are trained on.
+pub struct PackedDataset {
+    text: Arc<TokenizedText>,
+    seq_len: usize,
+    eos: u32,
+}
+
+impl PackedDataset {
+    pub fn new(text: Arc<TokenizedText>, seq_len: usize, eos: u32) -> Self {
+        Self {
+            text,
+            seq_len: seq_len.max(1),
+            eos,
+        }
+    }
+
+    // The position of document `i` in the concatenation.
+    fn document_start(&self, i: usize) -> usize {
+        self.text.offset(i) + i
+    }
+}
+
+impl Dataset for PackedDataset {
+    type Item = TextSample;
+
+    fn len(&self) -> usize {
+        let total = self.text.num_tokens() + self.text.num_documents();
+        total.saturating_sub(1) / self.seq_len
+    }
+
+    fn get(&self, index: usize) -> Result<TextSample> {
+        if index >= self.len() {
+            candle::bail!(
+                "index {index} out of range for dataset of len {}",
+                self.len()
+            )
+        }
+        // The block overlaps the next one by a token so that all the tokens are used as targets.
+        let mut pos = index * self.seq_len;
+        let end = pos + self.seq_len + 1;
+        let num_documents = self.text.num_documents();
+        // Binary search for the last document starting before `pos`.
+        let (mut doc, mut hi) = (0, num_documents);
+        while doc + 1 < hi {
+            let mid = (doc + hi) / 2;
+            if self.document_start(mid) <= pos {
+                doc = mid
+            } else {
+                hi = mid
+            }
+        }
+        let mut tokens = Vec::with_capacity(end - pos);
+        let mut mask = Vec::with_capacity(end - pos);
+        while pos < end && doc < num_documents {
+            let range = self.text.range(doc)?;
+            let start = self.document_start(doc);
+            let in_doc = pos - start;
+            if in_doc < range.len() {
+                let len = usize::min(range.len() - in_doc, end - pos);
+                let range = range.start + in_doc..range.start + in_doc + len;
+                tokens.extend(self.text.tokens(range.clone()));
+                mask.extend(self.text.loss_mask(range));
+                pos += len
+            } else {
+                tokens.push(self.eos);
+                mask.push(1);
+                pos += 1;
+                doc += 1
+            }
+        }
+        Ok(TextSample::shifted(tokens, mask))
+    }
+}
+
+/// Each document is a sample, truncated to `max_len` tokens and optionally followed by an EOS
+/// token. The samples have different lengths and are padded by [`PadBatch`].
+pub struct SequenceDataset {
+    text: Arc<TokenizedText>,
+    max_len: usize,
+    eos: Option<u32>,
+}
+
+impl SequenceDataset {
+    pub fn new(text: Arc<TokenizedText>, max_len: usize) -> Self {
+        Self {
+            text,
+            max_len,
+            eos: None,
+        }
+    }
+
+    pub fn eos(mut self, eos: u32) -> Self {
+        self.eos = Some(eos);
+        self
+    }
+}
+
+impl Dataset for SequenceDataset {
+    type Item = TextSample;
+
+    fn len(&self) -> usize {
+        self.text.num_documents()
+    }
+
+    fn get(&self, index: usize) -> Result<TextSample> {
+        let range = self.text.range(index)?;
+        let mut tokens = self.text.tokens(range.clone());
+        let mut mask = self.text.loss_mask(range);
+        if let Some(eos) = self.eos {
+            tokens.push(eos);
+            mask.push(1)
+        }
+        tokens.truncate(self.max_len + 1);
+        mask.truncate(self.max_len + 1);
+        Ok(TextSample::shifted(tokens, mask))
+    }
+}
+
+/// A batch of samples, all the tensors have shape `(batch, seq_len)`. The ids are u32 and the
+/// masks are u8 with 1 for the tokens to attend to, or to compute the loss on.
+#[derive(Debug, Clone)]
+pub struct TextBatch {
+    pub input_ids: Tensor,
+    pub target_ids: Tensor,
+    pub attention_mask: Tensor,
+    pub loss_mask: Tensor,
+}
+
+/// Pads the samples on the right with `pad_id` to the length of the longest one.
+#[derive(Debug, Clone, Copy)]
+pub struct PadBatch {
+    pad_id: u32,
+}
+
+impl PadBatch {
+    pub fn new(pad_id: u32) -> Self {
+        Self { pad_id }
+    }
+}
+
+impl Collate<TextSample> for PadBatch {
+    type Output = TextBatch;
+
+    fn collate(&self, items: Vec<TextSample>) -> Result<TextBatch> {
+        let b = items.len();
+        let len = items.iter().map(|s| s.input_ids.len()).max().unwrap_or(0);
+        let mut input_ids = vec![self.pad_id; b * len];
+        let mut target_ids = vec![self.pad_id; b * len];
+        let mut attention_mask = vec![0u8; b * len];
+        let mut loss_mask = vec![0u8; b * len];
+        for (i, s) in items.iter().enumerate() {
+            let n = s.input_ids.len();
+            let row = i * len..i * len + n;
+            input_ids[row.clone()].copy_from_slice(&s.input_ids);
+            target_ids[row.clone()].copy_from_slice(&s.target_ids);
+            attention_mask[row.clone()].fill(1);
+            loss_mask[row].copy_from_slice(&s.loss_mask);
+        }
+        let dev = &Device::Cpu;
+        Ok(TextBatch {
+            input_ids: Tensor::from_vec(input_ids, (b, len), dev)?,
+            target_ids: Tensor::from_vec(target_ids, (b, len), dev)?,
+            attention_mask: Tensor::from_vec(attention_mask, (b, len), dev)?,
+            loss_mask: Tensor::from_vec(loss_mask, (b, len), dev)?,
+        })
+    }
+}
@@ -0,0 +1,187 @@
+//! Samplers decide in which order the samples of a dataset are visited at each epoch.
+//!
+//! The random samplers are seeded and derive their rng from the seed and the epoch, so that the
+//! order changes between epochs but is reproducible, and is the same on all the processes of a
+//! distributed run.
+use candle::{Error, Result};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+pub trait Sampler: Send + Sync {
+    /// The indexes of the samples to visit during `epoch`, for a dataset with `len` samples.
+    fn indices(&self, len: usize, epoch: usize) -> Result<Vec<usize>>;
+}
+
+// The epoch is mixed in with a multiplication by the golden ratio so that adjacent seeds do not
+// give the same orders shifted by one epoch.
+fn epoch_rng(seed: u64, epoch: usize) -> StdRng {
+    StdRng::seed_from_u64(seed ^ (epoch as u64).wrapping_mul(0x9E3779B97F4A7C15))
+}
+
+/// Visits all the samples in order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SequentialSampler;
+
+impl Sampler for SequentialSampler {
+    fn indices(&self, len: usize, _epoch: usize) -> Result<Vec<usize>> {
+        Ok((0..len).collect())
+    }
+}
+
+/// Visits all the samples in a random order.
+#[derive(Debug, Clone, Copy)]
+pub struct RandomSampler {
+    seed: u64,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn indices(&self, len: usize, epoch: usize) -> Result<Vec<usize>> {
+        let mut indices = (0..len).collect::<Vec<_>>();
+        indices.shuffle(&mut epoch_rng(self.seed, epoch));
+        Ok(indices)
+    }
+}
+
+/// Draws `num_samples` samples with a probability proportional to their weight, e.g. to
+/// rebalance the classes of a dataset.
+#[derive(Debug, Clone)]
+pub struct WeightedRandomSampler {
+    weights: Vec<f64>,
+    num_samples: usize,
+    replacement: bool,
+    seed: u64,
+}
+
+impl WeightedRandomSampler {
+    pub fn new(
+        weights: Vec<f64>,
+        num_samples: usize,
+        replacement: bool,
+        seed: u64,
+    ) -> Result<Self> {
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.) {
+            candle::bail!("weights have to be finite and non-negative")
+        }
+        let non_zero = weights.iter().filter(|w| **w > 0.).count();
+        if non_zero == 0 {
+            candle::bail!("at least one weight has to be positive")
+        }
+        if !replacement && num_samples > non_zero {
+            candle::bail!(
+                "cannot draw {num_samples} samples without replacement from {non_zero} samples with a positive weight"
+            )
+        }
+        Ok(Self {
+            weights,
+            num_samples,
+            replacement,
+            seed,
+        })
+    }
+}
+
+impl Sampler for WeightedRandomSampler {
+    fn indices(&self, len: usize, epoch: usize) -> Result<Vec<usize>> {
+        if self.weights.len() != len {
+            candle::bail!(
+                "got {} weights for a dataset of len {len}",
+                self.weights.len()
+            )
+        }
+        let mut rng = epoch_rng(self.seed, epoch);
+        if self.replacement {
+            let dist =
+                rand::distr::weighted::WeightedIndex::new(&self.weights).map_err(Error::wrap)?;
+            Ok((&mut rng)
+                .sample_iter(dist)
+                .take(self.num_samples)
+                .collect())
+        } else {
+            // Weighted sampling without replacement (Efraimidis & Spirakis): the samples with the
+            // largest u^(1/w) keys are kept.
+            let mut keys = self
+                .weights
+                .iter()
+                .enumerate()
+                .filter(|(_, w)| **w > 0.)
+                .map(|(i, w)| (rng.random::<f64>().powf(1. / w), i))
+                .collect::<Vec<_>>();
+            keys.sort_by(|a, b| b.0.total_cmp(&a.0));
+            Ok(keys
+                .into_iter()
+                .take(self.num_samples)
+                .map(|(_, i)| i)
+                .collect())
+        }
+    }
+}
+
+/// Splits the samples between the `num_replicas` processes of a distributed run, each process
+/// only visits the indexes for its `rank`.
+///
+/// The indexes are padded by repeating the first ones so that all the processes get the same
+/// number of samples, or truncated when `drop_last` is set.
+#[derive(Debug, Clone, Copy)]
+pub struct DistributedSampler {
+    num_replicas: usize,
+    rank: usize,
+    shuffle: Option<u64>,
+    drop_last: bool,
+}
+
+impl DistributedSampler {
+    pub fn new(num_replicas: usize, rank: usize) -> Result<Self> {
+        if rank >= num_replicas {
+            candle::bail!("rank {rank} has to be smaller than num_replicas {num_replicas}")
+        }
+        Ok(Self {
+            num_replicas,
+            rank,
+            shuffle: None,
+            drop_last: false,
+        })
+    }
+
+    /// Shuffles the indexes before splitting them, all the processes have to use the same seed.
+    pub fn shuffle(mut self, seed: u64) -> Self {
+        self.shuffle = Some(seed);
+        self
+    }
+
+    pub fn drop_last(mut self, drop_last: bool) -> Self {
+        self.drop_last = drop_last;
+        self
+    }
+}
+
+impl Sampler for DistributedSampler {
+    fn indices(&self, len: usize, epoch: usize) -> Result<Vec<usize>> {
+        let mut indices = (0..len).collect::<Vec<_>>();
+        if let Some(seed) = self.shuffle {
+            indices.shuffle(&mut epoch_rng(seed, epoch));
+        }
+        let total = if self.drop_last {
+            len / self.num_replicas * self.num_replicas
+        } else {
+            len.div_ceil(self.num_replicas) * self.num_replicas
+        };
+        if len > 0 {
+            let mut i = 0;
+            while indices.len() < total {
+                indices.push(indices[i % len]);
+                i += 1
+            }
+        }
+        indices.truncate(total);
+        Ok(indices
+            .into_iter()
+            .skip(self.rank)
+            .step_by(self.num_replicas)
+            .collect())
+    }
+}
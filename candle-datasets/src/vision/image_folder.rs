@@ -0,0 +1,108 @@
+//! A dataset of images stored with one subdirectory per class, e.g.
+//! `root/cat/001.jpg`, `root/dog/001.png`.
+use crate::dataset::Dataset;
+use crate::vision::transforms::Compose;
+use candle::{Device, Result, Tensor};
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+fn is_image(p: &Path) -> bool {
+    match p.extension().and_then(|e| e.to_str()) {
+        Some(e) => EXTENSIONS.iter().any(|v| e.eq_ignore_ascii_case(v)),
+        None => false,
+    }
+}
+
+fn collect_images(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        let p = entry.path();
+        if p.is_dir() {
+            collect_images(&p, files)?
+        } else if is_image(&p) {
+            files.push(p)
+        }
+    }
+    Ok(())
+}
+
+/// The samples are `(image, label)` pairs, the images are loaded and converted to tensors with
+/// the transform when they are fetched, and the labels are u32 scalars indexing
+/// [`ImageFolder::classes`].
+pub struct ImageFolder {
+    samples: Vec<(PathBuf, u32)>,
+    classes: Vec<String>,
+    transform: Compose,
+}
+
+impl ImageFolder {
+    /// Lists the images in the subdirectories of `root`, the classes are the subdirectory names
+    /// in alphabetical order. The subdirectories are scanned recursively.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self> {
+        let root = root.as_ref();
+        let mut dirs = std::fs::read_dir(root)?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_dir())
+            .collect::<Vec<_>>();
+        dirs.sort();
+        let mut samples = vec![];
+        let mut classes = vec![];
+        for dir in dirs {
+            let mut files = vec![];
+            collect_images(&dir, &mut files)?;
+            files.sort();
+            let label = classes.len() as u32;
+            samples.extend(files.into_iter().map(|f| (f, label)));
+            classes.push(
+                dir.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+            );
+        }
+        if samples.is_empty() {
+            candle::bail!("no images found in {root:?}")
+        }
+        Ok(Self {
+            samples,
+            classes,
+            transform: Compose::new(),
+        })
+    }
+
+    /// The transform applied to the images, by default they are only converted to tensors.
+    pub fn transform(mut self, transform: Compose) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
+
+    /// The image paths and their labels.
+    pub fn samples(&self) -> &[(PathBuf, u32)] {
+        &self.samples
+    }
+}
+
+impl Dataset for ImageFolder {
+    type Item = (Tensor, Tensor);
+
+    fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    fn get(&self, index: usize) -> Result<Self::Item> {
+        let (path, label) = match self.samples.as_slice().get(index) {
+            Some(sample) => sample,
+            None => candle::bail!(
+                "index {index} out of range for dataset of len {}",
+                self.len()
+            ),
+        };
+        let image = self.transform.load(path)?;
+        Ok((image, Tensor::new(*label, &Device::Cpu)?))
+    }
+}
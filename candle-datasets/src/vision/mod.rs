@@ -10,4 +10,8 @@ pub struct Dataset {
 
 pub mod cifar;
 pub mod fashion_mnist;
+pub mod image_folder;
 pub mod mnist;
+pub mod transforms;
+
+pub use image_folder::ImageFolder;
@@ -0,0 +1,606 @@
+//! Image transforms for preprocessing and data augmentation.
+//!
+//! The transforms operate on [`RgbImage`] and are chained with [`Compose`], which converts the
+//! result to a `(3, height, width)` f32 tensor with values in `[0, 1]` and optionally normalizes
+//! it.
+//!
+//! ```no_run
+//! use candle_datasets::vision::transforms::{CenterCrop, Compose, RandomHorizontalFlip, Resize};
+//! # fn main() -> candle::Result<()> {
+//! let transform = Compose::new()
+//!     .add(Resize::new(256))
+//!     .add(CenterCrop::new(224))
+//!     .add(RandomHorizontalFlip::new(0.5))
+//!     .normalize([0.485, 0.456, 0.406], [0.229, 0.224, 0.225]);
+//! let image = transform.load("image.jpg")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`MixUp`] and [`CutMix`] mix the samples of a batch, so they are applied when collating the
+//! batches of a [`DataLoader`](crate::DataLoader).
+use crate::collate::{Collate, Stack};
+use candle::{DType, Device, Result, Tensor};
+use image::{imageops, imageops::FilterType, Rgb, RgbImage};
+use rand::Rng;
+use rand_distr::Distribution;
+
+pub trait Transform: Send + Sync {
+    fn apply(&self, image: RgbImage) -> Result<RgbImage>;
+}
+
+impl<F: Fn(RgbImage) -> Result<RgbImage> + Send + Sync> Transform for F {
+    fn apply(&self, image: RgbImage) -> Result<RgbImage> {
+        self(image)
+    }
+}
+
+/// Converts an image to a `(3, height, width)` f32 tensor with values in `[0, 1]`.
+pub fn to_tensor(image: &RgbImage) -> Result<Tensor> {
+    let (width, height) = image.dimensions();
+    let data = image.as_raw().clone();
+    let data = Tensor::from_vec(data, (height as usize, width as usize, 3), &Device::Cpu)?;
+    data.permute((2, 0, 1))?.to_dtype(DType::F32)? / 255.
+}
+
+/// Normalizes the channels of a `(3, height, width)` tensor, or of a batch of such tensors.
+#[derive(Debug, Clone, Copy)]
+pub struct Normalize {
+    mean: [f32; 3],
+    std: [f32; 3],
+}
+
+impl Normalize {
+    pub fn new(mean: [f32; 3], std: [f32; 3]) -> Self {
+        Self { mean, std }
+    }
+
+    pub fn apply(&self, xs: &Tensor) -> Result<Tensor> {
+        let mean = Tensor::new(&self.mean, xs.device())?.reshape((3, 1, 1))?;
+        let std = Tensor::new(&self.std, xs.device())?.reshape((3, 1, 1))?;
+        let mean = mean.to_dtype(xs.dtype())?;
+        let std = std.to_dtype(xs.dtype())?;
+        xs.broadcast_sub(&mean)?.broadcast_div(&std)
+    }
+}
+
+/// A sequence of transforms followed by the conversion to a tensor.
+#[derive(Default)]
+pub struct Compose {
+    transforms: Vec<Box<dyn Transform>>,
+    normalize: Option<Normalize>,
+}
+
+impl Compose {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a transform after all the current transforms.
+    #[allow(clippy::should_implement_trait)]
+    pub fn add<T: Transform + 'static>(mut self, transform: T) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Normalizes the resulting tensor with the per-channel `mean` and `std`.
+    pub fn normalize(mut self, mean: [f32; 3], std: [f32; 3]) -> Self {
+        self.normalize = Some(Normalize::new(mean, std));
+        self
+    }
+
+    pub fn apply(&self, image: RgbImage) -> Result<Tensor> {
+        let mut image = image;
+        for transform in self.transforms.iter() {
+            image = transform.apply(image)?;
+        }
+        let xs = to_tensor(&image)?;
+        match &self.normalize {
+            Some(normalize) => normalize.apply(&xs),
+            None => Ok(xs),
+        }
+    }
+
+    /// Loads an image from disk and applies the transforms to it.
+    pub fn load<P: AsRef<std::path::Path>>(&self, p: P) -> Result<Tensor> {
+        let image = image::ImageReader::open(p)?
+            .decode()
+            .map_err(candle::Error::wrap)?
+            .to_rgb8();
+        self.apply(image)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Size {
+    Shorter(u32),
+    Exact(u32, u32),
+}
+
+/// Resizes an image.
+#[derive(Debug, Clone, Copy)]
+pub struct Resize {
+    size: Size,
+    filter: FilterType,
+}
+
+impl Resize {
+    /// Resizes the shorter side of the image to `size`, keeping the aspect ratio.
+    pub fn new(size: u32) -> Self {
+        Self {
+            size: Size::Shorter(size),
+            filter: FilterType::Triangle,
+        }
+    }
+
+    pub fn exact(width: u32, height: u32) -> Self {
+        Self {
+            size: Size::Exact(width, height),
+            filter: FilterType::Triangle,
+        }
+    }
+
+    pub fn filter(mut self, filter: FilterType) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+impl Transform for Resize {
+    fn apply(&self, image: RgbImage) -> Result<RgbImage> {
+        let (w, h) = image.dimensions();
+        let (new_w, new_h) = match self.size {
+            Size::Exact(w, h) => (w, h),
+            Size::Shorter(s) if w <= h => (s, (h as u64 * s as u64 / w.max(1) as u64) as u32),
+            Size::Shorter(s) => ((w as u64 * s as u64 / h.max(1) as u64) as u32, s),
+        };
+        if (new_w, new_h) == (w, h) {
+            return Ok(image);
+        }
+        Ok(imageops::resize(&image, new_w, new_h, self.filter))
+    }
+}
+
+// Crops a `width` x `height` region at `(x, y)`, the parts outside of the image are black.
+fn crop(image: &RgbImage, x: i64, y: i64, width: u32, height: u32) -> RgbImage {
+    let (w, h) = image.dimensions();
+    if x >= 0 && y >= 0 && x + width as i64 <= w as i64 && y + height as i64 <= h as i64 {
+        return imageops::crop_imm(image, x as u32, y as u32, width, height).to_image();
+    }
+    let mut out = RgbImage::new(width, height);
+    imageops::overlay(&mut out, image, -x, -y);
+    out
+}
+
+/// Crops the center of an image, images smaller than the crop are padded with black.
+#[derive(Debug, Clone, Copy)]
+pub struct CenterCrop {
+    size: u32,
+}
+
+impl CenterCrop {
+    pub fn new(size: u32) -> Self {
+        Self { size }
+    }
+}
+
+impl Transform for CenterCrop {
+    fn apply(&self, image: RgbImage) -> Result<RgbImage> {
+        let (w, h) = image.dimensions();
+        let x = (w as i64 - self.size as i64) / 2;
+        let y = (h as i64 - self.size as i64) / 2;
+        Ok(crop(&image, x, y, self.size, self.size))
+    }
+}
+
+/// Crops an image at a random position, after padding it with `padding` black pixels on each
+/// side.
+#[derive(Debug, Clone, Copy)]
+pub struct RandomCrop {
+    size: u32,
+    padding: u32,
+}
+
+impl RandomCrop {
+    pub fn new(size: u32) -> Self {
+        Self { size, padding: 0 }
+    }
+
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+}
+
+impl Transform for RandomCrop {
+    fn apply(&self, image: RgbImage) -> Result<RgbImage> {
+        let (w, h) = image.dimensions();
+        let p = self.padding as i64;
+        let mut rng = rand::rng();
+        let max_x = (w as i64 + p - self.size as i64).max(-p);
+        let max_y = (h as i64 + p - self.size as i64).max(-p);
+        let x = rng.random_range(-p..=max_x);
+        let y = rng.random_range(-p..=max_y);
+        Ok(crop(&image, x, y, self.size, self.size))
+    }
+}
+
+/// Flips an image horizontally with probability `p`.
+#[derive(Debug, Clone, Copy)]
+pub struct RandomHorizontalFlip {
+    p: f64,
+}
+
+impl RandomHorizontalFlip {
+    pub fn new(p: f64) -> Self {
+        Self { p }
+    }
+}
+
+impl Transform for RandomHorizontalFlip {
+    fn apply(&self, mut image: RgbImage) -> Result<RgbImage> {
+        if rand::rng().random_bool(self.p.clamp(0., 1.)) {
+            imageops::flip_horizontal_in_place(&mut image)
+        }
+        Ok(image)
+    }
+}
+
+// The image interpolated with `degenerate` as in PIL's `ImageEnhance`, a factor of 0 returns
+// `degenerate` and 1 returns `image`.
+fn blend(image: &RgbImage, degenerate: &RgbImage, factor: f32) -> RgbImage {
+    let mut out = image.clone();
+    for (o, d) in out.pixels_mut().zip(degenerate.pixels()) {
+        for c in 0..3 {
+            let v = d[c] as f32 + factor * (o[c] as f32 - d[c] as f32);
+            o[c] = v.round().clamp(0., 255.) as u8
+        }
+    }
+    out
+}
+
+fn grayscale(image: &RgbImage) -> RgbImage {
+    let gray = imageops::grayscale(image);
+    let (w, h) = image.dimensions();
+    RgbImage::from_fn(w, h, |x, y| {
+        let v = gray.get_pixel(x, y)[0];
+        Rgb([v, v, v])
+    })
+}
+
+fn adjust_brightness(image: &RgbImage, factor: f32) -> RgbImage {
+    let (w, h) = image.dimensions();
+    blend(image, &RgbImage::new(w, h), factor)
+}
+
+fn adjust_contrast(image: &RgbImage, factor: f32) -> RgbImage {
+    let gray = imageops::grayscale(image);
+    let n = gray.as_raw().len().max(1);
+    let mean = gray.as_raw().iter().map(|&v| v as f32).sum::<f32>() / n as f32;
+    let mean = mean.round() as u8;
+    let (w, h) = image.dimensions();
+    blend(image, &RgbImage::from_pixel(w, h, Rgb([mean; 3])), factor)
+}
+
+fn adjust_saturation(image: &RgbImage, factor: f32) -> RgbImage {
+    blend(image, &grayscale(image), factor)
+}
+
+fn adjust_sharpness(image: &RgbImage, factor: f32) -> RgbImage {
+    let kernel = [1., 1., 1., 1., 5., 1., 1., 1., 1.].map(|v: f32| v / 13.);
+    blend(image, &imageops::filter3x3(image, &kernel), factor)
+}
+
+/// Randomly changes the brightness, contrast, saturation and hue of an image.
+///
+/// The brightness, contrast and saturation factors are sampled uniformly in
+/// `[1 - value, 1 + value]`, the hue is rotated by a fraction of a turn sampled in
+/// `[-hue, hue]`, `hue` must be at most 0.5.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ColorJitter {
+    brightness: f32,
+    contrast: f32,
+    saturation: f32,
+    hue: f32,
+}
+
+impl ColorJitter {
+    pub fn new(brightness: f32, contrast: f32, saturation: f32, hue: f32) -> Self {
+        Self {
+            brightness,
+            contrast,
+            saturation,
+            hue: hue.clamp(0., 0.5),
+        }
+    }
+}
+
+impl Transform for ColorJitter {
+    fn apply(&self, image: RgbImage) -> Result<RgbImage> {
+        let mut rng = rand::rng();
+        let mut factor = |v: f32| 1. + rng.random_range(-v..=v);
+        let mut image = image;
+        if self.brightness > 0. {
+            image = adjust_brightness(&image, factor(self.brightness))
+        }
+        if self.contrast > 0. {
+            image = adjust_contrast(&image, factor(self.contrast))
+        }
+        if self.saturation > 0. {
+            image = adjust_saturation(&image, factor(self.saturation))
+        }
+        if self.hue > 0. {
+            let hue = rng.random_range(-self.hue..=self.hue);
+            image = imageops::huerotate(&image, (hue * 360.).round() as i32)
+        }
+        Ok(image)
+    }
+}
+
+// Applies the affine transform mapping each output pixel to the input pixel
+// `(m[0] x + m[1] y + m[2], m[3] x + m[4] y + m[5])` relative to the center of the image, using
+// nearest neighbor sampling and black outside of the image.
+fn affine(image: &RgbImage, m: [f32; 6]) -> RgbImage {
+    let (w, h) = image.dimensions();
+    let (cx, cy) = (w as f32 * 0.5, h as f32 * 0.5);
+    RgbImage::from_fn(w, h, |x, y| {
+        let (x, y) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let src_x = (m[0] * x + m[1] * y + m[2] + cx).floor();
+        let src_y = (m[3] * x + m[4] * y + m[5] + cy).floor();
+        if src_x < 0. || src_y < 0. || src_x >= w as f32 || src_y >= h as f32 {
+            Rgb([0; 3])
+        } else {
+            *image.get_pixel(src_x as u32, src_y as u32)
+        }
+    })
+}
+
+fn autocontrast(image: &RgbImage) -> RgbImage {
+    let mut lo = [255u8; 3];
+    let mut hi = [0u8; 3];
+    for p in image.pixels() {
+        for c in 0..3 {
+            lo[c] = lo[c].min(p[c]);
+            hi[c] = hi[c].max(p[c]);
+        }
+    }
+    let mut out = image.clone();
+    for p in out.pixels_mut() {
+        for c in 0..3 {
+            if hi[c] > lo[c] {
+                let scale = 255. / (hi[c] - lo[c]) as f32;
+                p[c] = ((p[c] - lo[c]) as f32 * scale).round().min(255.) as u8
+            }
+        }
+    }
+    out
+}
+
+// Histogram equalization of each channel, following PIL's `ImageOps.equalize`.
+fn equalize(image: &RgbImage) -> RgbImage {
+    let mut luts = [[0u8; 256]; 3];
+    for (c, lut) in luts.iter_mut().enumerate() {
+        let mut hist = [0usize; 256];
+        for p in image.pixels() {
+            hist[p[c] as usize] += 1
+        }
+        let last = hist.iter().rev().find(|&&v| v > 0).copied().unwrap_or(0);
+        let total = hist.iter().sum::<usize>();
+        let step = (total - last) / 255;
+        if step == 0 {
+            for (i, v) in lut.iter_mut().enumerate() {
+                *v = i as u8
+            }
+            continue;
+        }
+        let mut n = step / 2;
+        for (i, v) in lut.iter_mut().enumerate() {
+            *v = (n / step).min(255) as u8;
+            n += hist[i]
+        }
+    }
+    let mut out = image.clone();
+    for p in out.pixels_mut() {
+        for (c, lut) in luts.iter().enumerate() {
+            p[c] = lut[p[c] as usize]
+        }
+    }
+    out
+}
+
+fn posterize(image: &RgbImage, bits: u8) -> RgbImage {
+    let mask = !(0xffu8.checked_shr(bits as u32).unwrap_or(0));
+    let mut out = image.clone();
+    for p in out.pixels_mut() {
+        p.0 = p.0.map(|v| v & mask)
+    }
+    out
+}
+
+fn solarize(image: &RgbImage, threshold: f32) -> RgbImage {
+    let mut out = image.clone();
+    for p in out.pixels_mut() {
+        p.0 = p.0.map(|v| if v as f32 >= threshold { 255 - v } else { v })
+    }
+    out
+}
+
+/// RandAugment, <https://arxiv.org/abs/1909.13719>: applies `num_ops` operations picked at
+/// random among geometric and color transforms, with a strength given by `magnitude` which
+/// ranges from 0 to `num_bins - 1`.
+///
+/// The magnitudes follow the torchvision implementation.
+#[derive(Debug, Clone, Copy)]
+pub struct RandAugment {
+    num_ops: usize,
+    magnitude: usize,
+    num_bins: usize,
+}
+
+impl Default for RandAugment {
+    fn default() -> Self {
+        Self {
+            num_ops: 2,
+            magnitude: 9,
+            num_bins: 31,
+        }
+    }
+}
+
+impl RandAugment {
+    pub fn new(num_ops: usize, magnitude: usize) -> Self {
+        Self {
+            num_ops,
+            magnitude,
+            ..Default::default()
+        }
+    }
+
+    pub fn num_bins(mut self, num_bins: usize) -> Self {
+        self.num_bins = num_bins.max(2);
+        self
+    }
+}
+
+impl Transform for RandAugment {
+    fn apply(&self, image: RgbImage) -> Result<RgbImage> {
+        const NUM_OPS: usize = 14;
+        let mut rng = rand::rng();
+        let f = (self.magnitude as f32 / (self.num_bins - 1) as f32).clamp(0., 1.);
+        let (w, h) = image.dimensions();
+        let mut image = image;
+        for _ in 0..self.num_ops {
+            let sign = if rng.random_bool(0.5) { 1. } else { -1. };
+            image = match rng.random_range(0..NUM_OPS) {
+                0 => image,
+                1 => affine(&image, [1., 0.3 * f * sign, 0., 0., 1., 0.]),
+                2 => affine(&image, [1., 0., 0., 0.3 * f * sign, 1., 0.]),
+                3 => {
+                    let t = 150. / 331. * w as f32 * f * sign;
+                    affine(&image, [1., 0., -t, 0., 1., 0.])
+                }
+                4 => {
+                    let t = 150. / 331. * h as f32 * f * sign;
+                    affine(&image, [1., 0., 0., 0., 1., -t])
+                }
+                5 => {
+                    let (sin, cos) = (30. * f * sign).to_radians().sin_cos();
+                    affine(&image, [cos, sin, 0., -sin, cos, 0.])
+                }
+                6 => adjust_brightness(&image, 1. + 0.9 * f * sign),
+                7 => adjust_saturation(&image, 1. + 0.9 * f * sign),
+                8 => adjust_contrast(&image, 1. + 0.9 * f * sign),
+                9 => adjust_sharpness(&image, 1. + 0.9 * f * sign),
+                10 => posterize(&image, 8 - (4. * f).round() as u8),
+                11 => solarize(&image, 255. * (1. - f)),
+                12 => autocontrast(&image),
+                _ => equalize(&image),
+            }
+        }
+        Ok(image)
+    }
+}
+
+fn sample_beta(alpha: f64) -> Result<f64> {
+    let beta = rand_distr::Beta::new(alpha, alpha).map_err(candle::Error::wrap)?;
+    Ok(beta.sample(&mut rand::rng()))
+}
+
+// The one-hot encoding of the labels, the labels that have already been encoded are returned
+// as is.
+fn one_hot(labels: &Tensor, num_classes: usize) -> Result<Tensor> {
+    if labels.rank() == 2 {
+        return labels.to_dtype(DType::F32);
+    }
+    let labels = labels.to_device(&Device::Cpu)?;
+    let labels = match labels.dtype() {
+        DType::U8 | DType::U32 | DType::I64 => labels,
+        _ => labels.to_dtype(DType::I64)?,
+    };
+    candle_nn::encoding::one_hot(labels, num_classes, 1f32, 0f32)
+}
+
+/// MixUp, <https://arxiv.org/abs/1710.09412>: mixes each sample of a batch with the next one,
+/// using a ratio sampled from a `Beta(alpha, alpha)` distribution.
+///
+/// The labels are returned as one-hot f32 tensors mixed with the same ratio.
+#[derive(Debug, Clone, Copy)]
+pub struct MixUp {
+    alpha: f64,
+    num_classes: usize,
+}
+
+impl MixUp {
+    pub fn new(alpha: f64, num_classes: usize) -> Self {
+        Self { alpha, num_classes }
+    }
+
+    /// Mixes a batch of images with shape `(batch, channels, height, width)`.
+    pub fn apply(&self, xs: &Tensor, labels: &Tensor) -> Result<(Tensor, Tensor)> {
+        let lambda = sample_beta(self.alpha)?;
+        let ys = one_hot(labels, self.num_classes)?.to_device(xs.device())?;
+        let xs = ((xs * lambda)? + (xs.roll(1, 0)? * (1. - lambda))?)?;
+        let ys = ((&ys * lambda)? + (ys.roll(1, 0)? * (1. - lambda))?)?;
+        Ok((xs, ys))
+    }
+}
+
+impl Collate<(Tensor, Tensor)> for MixUp {
+    type Output = (Tensor, Tensor);
+
+    fn collate(&self, items: Vec<(Tensor, Tensor)>) -> Result<(Tensor, Tensor)> {
+        let (xs, ys) = Stack.collate(items)?;
+        self.apply(&xs, &ys)
+    }
+}
+
+/// CutMix, <https://arxiv.org/abs/1905.04899>: replaces a random box of each image of a batch
+/// with the same region of the next image. The box area is sampled using a
+/// `Beta(alpha, alpha)` distribution.
+///
+/// The labels are returned as one-hot f32 tensors mixed according to the box area.
+#[derive(Debug, Clone, Copy)]
+pub struct CutMix {
+    alpha: f64,
+    num_classes: usize,
+}
+
+impl CutMix {
+    pub fn new(alpha: f64, num_classes: usize) -> Self {
+        Self { alpha, num_classes }
+    }
+
+    /// Mixes a batch of images with shape `(batch, channels, height, width)`.
+    pub fn apply(&self, xs: &Tensor, labels: &Tensor) -> Result<(Tensor, Tensor)> {
+        let (_b, _c, h, w) = xs.dims4()?;
+        let lambda = sample_beta(self.alpha)?;
+        let ratio = (1. - lambda).sqrt();
+        let (cut_w, cut_h) = (w as f64 * ratio, h as f64 * ratio);
+        let mut rng = rand::rng();
+        let cx = rng.random_range(0..w) as f64;
+        let cy = rng.random_range(0..h) as f64;
+        let clamp = |v: f64, max: usize| v.round().clamp(0., max as f64) as usize;
+        let (x1, x2) = (clamp(cx - cut_w / 2., w), clamp(cx + cut_w / 2., w));
+        let (y1, y2) = (clamp(cy - cut_h / 2., h), clamp(cy + cut_h / 2., h));
+        let mut mask = vec![0f32; h * w];
+        for y in y1..y2 {
+            mask[y * w + x1..y * w + x2].fill(1.)
+        }
+        let mask = Tensor::from_vec(mask, (1, 1, h, w), xs.device())?.to_dtype(xs.dtype())?;
+        let xs = (xs.broadcast_mul(&(1. - &mask)?)? + xs.roll(1, 0)?.broadcast_mul(&mask)?)?;
+        // The ratio is adjusted to the area of the box after clamping.
+        let lambda = 1. - ((x2 - x1) * (y2 - y1)) as f64 / (w * h) as f64;
+        let ys = one_hot(labels, self.num_classes)?.to_device(xs.device())?;
+        let ys = ((&ys * lambda)? + (ys.roll(1, 0)? * (1. - lambda))?)?;
+        Ok((xs, ys))
+    }
+}
+
+impl Collate<(Tensor, Tensor)> for CutMix {
+    type Output = (Tensor, Tensor);
+
+    fn collate(&self, items: Vec<(Tensor, Tensor)>) -> Result<(Tensor, Tensor)> {
+        let (xs, ys) = Stack.collate(items)?;
+        self.apply(&xs, &ys)
+    }
+}
@@ -0,0 +1,136 @@
+use candle::{Device, Result, Tensor};
+use candle_datasets::collate::Pad;
+use candle_datasets::dataset::Map;
+use candle_datasets::sampler::{DistributedSampler, RandomSampler, Sampler, WeightedRandomSampler};
+use candle_datasets::DataLoader;
+
+fn dataset(len: u32) -> Result<(Tensor, Tensor)> {
+    let xs = Tensor::arange(0u32, 2 * len, &Device::Cpu)?.reshape((len as usize, 2))?;
+    let ys = Tensor::arange(0u32, len, &Device::Cpu)?;
+    Ok((xs, ys))
+}
+
+fn labels<I: Iterator<Item = Result<(Tensor, Tensor)>>>(iter: I) -> Result<Vec<Vec<u32>>> {
+    iter.map(|b| b?.1.to_vec1::<u32>()).collect()
+}
+
+#[test]
+fn data_loader_sequential() -> Result<()> {
+    let mut loader = DataLoader::new(dataset(10)?).batch_size(4);
+    assert_eq!(loader.num_batches()?, 3);
+    let mut iter = loader.iter()?;
+    let (xs, ys) = iter.next().unwrap()?;
+    assert_eq!(xs.dims(), [4, 2]);
+    assert_eq!(ys.to_vec1::<u32>()?, [0, 1, 2, 3]);
+    assert_eq!(iter.len(), 2);
+    assert_eq!(labels(iter)?, [vec![4, 5, 6, 7], vec![8, 9]]);
+
+    let mut loader = loader.drop_last(true);
+    assert_eq!(labels(loader.iter()?)?, [[0, 1, 2, 3], [4, 5, 6, 7]]);
+    Ok(())
+}
+
+#[test]
+fn data_loader_shuffle_workers() -> Result<()> {
+    let mut loader = DataLoader::new(dataset(50)?).batch_size(8).shuffle(42);
+    let epoch0 = labels(loader.iter()?)?;
+    let epoch1 = labels(loader.iter()?)?;
+    assert_ne!(epoch0, epoch1);
+    let mut all = epoch0.concat();
+    all.sort();
+    assert_eq!(all, (0..50).collect::<Vec<_>>());
+
+    // The worker threads return the same batches in the same order.
+    let mut loader = loader.num_workers(3).prefetch(1);
+    loader.set_epoch(0);
+    assert_eq!(labels(loader.iter()?)?, epoch0);
+    assert_eq!(labels(loader.iter()?)?, epoch1);
+
+    // Dropping an iterator before the end stops the workers.
+    let mut iter = loader.iter()?;
+    assert!(iter.next().is_some());
+    drop(iter);
+    Ok(())
+}
+
+#[test]
+fn data_loader_errors() -> Result<()> {
+    let (xs, ys) = dataset(10)?;
+    let ds = Map::new((xs, ys), |(x, y): (Tensor, Tensor)| {
+        if y.to_scalar::<u32>()? == 5 {
+            candle::bail!("bad sample")
+        }
+        Ok((x, y))
+    });
+    let mut loader = DataLoader::new(ds).batch_size(3).num_workers(2);
+    let results = loader.iter()?.map(|b| b.is_ok()).collect::<Vec<_>>();
+    assert_eq!(results, [true, false, true, true]);
+    Ok(())
+}
+
+#[test]
+fn pad_collate() -> Result<()> {
+    let dev = &Device::Cpu;
+    let seqs = vec![
+        (Tensor::new(&[1u32, 2, 3], dev)?, Tensor::new(0u32, dev)?),
+        (Tensor::new(&[4u32], dev)?, Tensor::new(1u32, dev)?),
+        (Tensor::new(&[5u32, 6], dev)?, Tensor::new(2u32, dev)?),
+    ];
+    let mut loader = DataLoader::new(seqs).batch_size(3).collate(Pad::new(9.));
+    let (xs, ys) = loader.iter()?.next().unwrap()?;
+    assert_eq!(xs.to_vec2::<u32>()?, [[1, 2, 3], [4, 9, 9], [5, 6, 9]]);
+    assert_eq!(ys.to_vec1::<u32>()?, [0, 1, 2]);
+    Ok(())
+}
+
+#[test]
+fn samplers() -> Result<()> {
+    let weights = vec![0., 1., 0., 3.];
+    let sampler = WeightedRandomSampler::new(weights.clone(), 100, true, 0)?;
+    let indices = sampler.indices(4, 0)?;
+    assert_eq!(indices.len(), 100);
+    assert!(indices.iter().all(|&i| i == 1 || i == 3));
+    assert!(indices.iter().filter(|&&i| i == 3).count() > 50);
+
+    let sampler = WeightedRandomSampler::new(weights.clone(), 2, false, 0)?;
+    let mut indices = sampler.indices(4, 0)?;
+    indices.sort();
+    assert_eq!(indices, [1, 3]);
+    assert!(WeightedRandomSampler::new(weights, 3, false, 0).is_err());
+
+    let ranks = (0..3)
+        .map(|rank| DistributedSampler::new(3, rank)?.indices(7, 0))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(ranks, [vec![0, 3, 6], vec![1, 4, 0], vec![2, 5, 1]]);
+    let sampler = DistributedSampler::new(3, 1)?.drop_last(true).shuffle(1);
+    let mut indices = (0..3)
+        .flat_map(|rank| {
+            let sampler = DistributedSampler::new(3, rank)
+                .unwrap()
+                .drop_last(true)
+                .shuffle(1);
+            sampler.indices(7, 0).unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(sampler.indices(7, 0)?.len(), 2);
+    indices.sort();
+    indices.dedup();
+    assert_eq!(indices.len(), 6);
+    Ok(())
+}
+
+#[test]
+fn sampler_seeds() -> Result<()> {
+    // Adjacent seeds do not give the same orders shifted by one epoch.
+    let orders = |seed| -> Result<Vec<Vec<usize>>> {
+        let sampler = RandomSampler::new(seed);
+        (0..4).map(|epoch| sampler.indices(100, epoch)).collect()
+    };
+    let (orders0, orders1) = (orders(0)?, orders(1)?);
+    for o0 in orders0.iter() {
+        assert!(orders1.iter().all(|o1| o0 != o1));
+    }
+    assert_ne!(orders0[0], orders0[1]);
+    assert_eq!(orders0, orders(0)?);
+    Ok(())
+}
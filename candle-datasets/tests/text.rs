@@ -0,0 +1,289 @@
+use candle::Result;
+use candle_datasets::collate::Collate;
+use candle_datasets::nlp::text::{
+    read_column, ChatFormat, ChatMessage, Format, PackedDataset, PadBatch, Record, SequenceDataset,
+    TextFile,
+};
+use candle_datasets::Dataset;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
+const WORDS: [&str; 12] = [
+    "[UNK]",
+    "</s>",
+    "user",
+    "assistant",
+    ":",
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "hi",
+];
+
+// A word level tokenizer with one token per word of `WORDS`.
+fn tokenizer() -> Result<Tokenizer> {
+    let vocab = WORDS
+        .iter()
+        .enumerate()
+        .map(|(i, w)| format!("{w:?}: {i}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let json = format!(
+        r#"{{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [{{
+                "id": 1, "content": "</s>", "single_word": false, "lstrip": false,
+                "rstrip": false, "normalized": false, "special": true
+            }}],
+            "normalizer": null,
+            "pre_tokenizer": {{"type": "Whitespace"}},
+            "post_processor": null,
+            "decoder": null,
+            "model": {{"type": "WordLevel", "vocab": {{{vocab}}}, "unk_token": "[UNK]"}}
+        }}"#
+    );
+    json.parse().map_err(candle::Error::msg)
+}
+
+fn ids(words: &str) -> Vec<u32> {
+    words
+        .split(' ')
+        .map(|w| WORDS.iter().position(|v| *v == w).unwrap() as u32)
+        .collect()
+}
+
+fn temp_dir(name: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("candle-text-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+#[test]
+fn packed_jsonl() -> Result<()> {
+    let dir = temp_dir("jsonl")?;
+    let path = dir.join("data.jsonl");
+    std::fs::write(
+        &path,
+        "{\"text\": \"a b c\", \"id\": 0}\n{\"text\": \"d e\"}\n\n{\"text\": \"f\"}\n",
+    )?;
+    let tokenizer = tokenizer()?;
+    let cache_dir = dir.join("cache");
+    let file = TextFile::new(&path).cache_dir(&cache_dir);
+    let text = file.tokenize(&tokenizer)?;
+    assert_eq!(text.num_documents(), 3);
+    assert_eq!(text.num_tokens(), 6);
+    assert_eq!(text.document(1)?, ids("d e"));
+    assert!(!text.has_loss_mask());
+
+    // The second call loads the cached tokens.
+    let num_files = std::fs::read_dir(&cache_dir)?.count();
+    let text = file.tokenize(&tokenizer)?;
+    assert_eq!(std::fs::read_dir(&cache_dir)?.count(), num_files);
+    assert_eq!(text.document(0)?, ids("a b c"));
+
+    // a b c </s> d e </s> f </s>, in blocks of 3 tokens.
+    let dataset = PackedDataset::new(Arc::new(text), 3, 1);
+    assert_eq!(dataset.len(), 2);
+    let sample = dataset.get(0)?;
+    assert_eq!(sample.input_ids, ids("a b c"));
+    assert_eq!(sample.target_ids, ids("b c </s>"));
+    assert_eq!(sample.loss_mask, [1, 1, 1]);
+    let sample = dataset.get(1)?;
+    assert_eq!(sample.input_ids, ids("</s> d e"));
+    assert_eq!(sample.target_ids, ids("d e </s>"));
+    assert!(dataset.get(2).is_err());
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn csv_and_parquet() -> Result<()> {
+    use parquet::data_type::{ByteArray, ByteArrayType};
+    use parquet::file::writer::SerializedFileWriter;
+
+    let dir = temp_dir("csv")?;
+    let path = dir.join("data.csv");
+    std::fs::write(&path, "id,text\r\n0,\"a, \"\"b\"\"\nc\"\r\n1,d\n2,\"\"\n")?;
+    let records = read_column(&path, Format::from_path(&path)?, "text", false)?;
+    let texts = ["a, \"b\"\nc", "d", ""].map(|t| Record::Text(t.to_string()));
+    assert_eq!(records, texts);
+    assert!(read_column(&path, Format::Csv, "missing", false).is_err());
+
+    let path = dir.join("data.parquet");
+    let schema = "message schema { REQUIRED BYTE_ARRAY text (UTF8); REQUIRED INT64 id; }";
+    let schema = Arc::new(parquet::schema::parser::parse_message_type(schema).unwrap());
+    let file = std::fs::File::create(&path)?;
+    let mut writer = SerializedFileWriter::new(file, schema, Default::default()).unwrap();
+    let mut row_group = writer.next_row_group().unwrap();
+    let mut column = row_group.next_column().unwrap().unwrap();
+    let values = ["a b", "c d e"].map(ByteArray::from);
+    column
+        .typed::<ByteArrayType>()
+        .write_batch(&values, None, None)
+        .unwrap();
+    column.close().unwrap();
+    let mut column = row_group.next_column().unwrap().unwrap();
+    column
+        .typed::<parquet::data_type::Int64Type>()
+        .write_batch(&[0, 1], None, None)
+        .unwrap();
+    column.close().unwrap();
+    row_group.close().unwrap();
+    writer.close().unwrap();
+
+    let text = TextFile::new(&path).tokenize(&tokenizer()?)?;
+    assert_eq!(text.num_documents(), 2);
+    assert_eq!(text.document(1)?, ids("c d e"));
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn chat_padded_batches() -> Result<()> {
+    let dir = temp_dir("chat")?;
+    let path = dir.join("chat.jsonl");
+    std::fs::write(
+        &path,
+        concat!(
+            r#"{"messages": [{"role": "user", "content": "a b"}, {"role": "assistant", "content": "c"}]}"#,
+            "\n",
+            r#"{"messages": "[{\"role\": \"user\", \"content\": \"hi\"}]"}"#,
+            "\n"
+        ),
+    )?;
+    let records = read_column(&path, Format::Jsonl, "messages", true)?;
+    let message = |role: &str, content: &str| ChatMessage {
+        role: role.to_string(),
+        content: content.to_string(),
+    };
+    assert_eq!(records[1], Record::Chat(vec![message("user", "hi")]),);
+
+    let chat_format = ChatFormat::new("{role} : ", " </s>");
+    let text = TextFile::new(&path)
+        .column("messages")
+        .chat(chat_format)
+        .tokenize(&tokenizer()?)?;
+    assert!(text.has_loss_mask());
+    assert_eq!(text.document(0)?, ids("user : a b </s> assistant : c </s>"));
+    assert_eq!(text.document_loss_mask(0)?, [0, 0, 0, 0, 0, 0, 0, 1, 1]);
+
+    let dataset = SequenceDataset::new(Arc::new(text), 6);
+    let samples = (0..dataset.len())
+        .map(|i| dataset.get(i))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(samples[0].input_ids, ids("user : a b </s> assistant"));
+    assert_eq!(samples[0].loss_mask, [0, 0, 0, 0, 0, 0]);
+    assert_eq!(samples[1].target_ids, ids(": hi </s>"));
+
+    let batch = PadBatch::new(0).collate(samples)?;
+    assert_eq!(batch.input_ids.dims(), [2, 6]);
+    assert_eq!(
+        batch.attention_mask.to_vec2::<u8>()?,
+        [[1, 1, 1, 1, 1, 1], [1, 1, 1, 0, 0, 0]]
+    );
+    assert_eq!(
+        batch.target_ids.to_vec2::<u32>()?,
+        [
+            ids(": a b </s> assistant :"),
+            ids(": hi </s> [UNK] [UNK] [UNK]")
+        ]
+    );
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn chat_parquet() -> Result<()> {
+    use parquet::data_type::{ByteArray, ByteArrayType};
+    use parquet::file::writer::SerializedFileWriter;
+
+    let dir = temp_dir("chat-parquet")?;
+    let path = dir.join("chat.parquet");
+    let schema = "message schema {
+        REQUIRED GROUP messages (LIST) {
+            REPEATED GROUP list {
+                REQUIRED GROUP element {
+                    REQUIRED BYTE_ARRAY role (UTF8);
+                    REQUIRED BYTE_ARRAY content (UTF8);
+                }
+            }
+        }
+    }";
+    let schema = Arc::new(parquet::schema::parser::parse_message_type(schema).unwrap());
+    let file = std::fs::File::create(&path)?;
+    let mut writer = SerializedFileWriter::new(file, schema, Default::default()).unwrap();
+    let mut row_group = writer.next_row_group().unwrap();
+    // Two rows, the first one with two messages and the second one with a single message.
+    let columns = [["user", "assistant", "user"], ["a b", "c", "hi"]];
+    for values in columns {
+        let mut column = row_group.next_column().unwrap().unwrap();
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(
+                &values.map(ByteArray::from),
+                Some(&[1, 1, 1]),
+                Some(&[0, 1, 0]),
+            )
+            .unwrap();
+        column.close().unwrap();
+    }
+    row_group.close().unwrap();
+    writer.close().unwrap();
+
+    let records = read_column(&path, Format::Parquet, "messages", true)?;
+    let message = |role: &str, content: &str| ChatMessage {
+        role: role.to_string(),
+        content: content.to_string(),
+    };
+    let expected = [
+        Record::Chat(vec![message("user", "a b"), message("assistant", "c")]),
+        Record::Chat(vec![message("user", "hi")]),
+    ];
+    assert_eq!(records, expected);
+    assert!(read_column(&path, Format::Parquet, "messages", false).is_err());
+
+    let text = TextFile::new(&path)
+        .column("messages")
+        .chat(ChatFormat::new("{role} : ", " </s>"))
+        .tokenize(&tokenizer()?)?;
+    assert_eq!(text.document(0)?, ids("user : a b </s> assistant : c </s>"));
+    assert_eq!(text.document_loss_mask(0)?, [0, 0, 0, 0, 0, 0, 0, 1, 1]);
+    assert_eq!(text.document_loss_mask(1)?, [0, 0, 0, 0]);
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn tokenize_chunks() -> Result<()> {
+    let dir = temp_dir("chunks")?;
+    let path = dir.join("data.csv");
+    // More records than fit in a tokenization chunk.
+    let mut csv = "text\n".to_string();
+    for i in 0..2500 {
+        csv.push_str(["a b", "c", "\"d,\ne\""][i % 3]);
+        csv.push('\n')
+    }
+    std::fs::write(&path, csv)?;
+    let text = TextFile::new(&path).tokenize(&tokenizer()?)?;
+    assert_eq!(text.num_documents(), 2500);
+    // The comma is an unknown token.
+    assert_eq!(text.num_tokens(), 2500 / 3 * 6 + 2);
+    assert_eq!(text.document(2)?, ids("d [UNK] e"));
+    assert_eq!(text.document(1024)?, ids("c"));
+    assert_eq!(text.document(2499)?, ids("a b"));
+    // Only the cache files are left next to the data.
+    let mut files = std::fs::read_dir(&dir)?
+        .map(|e| Ok(e?.file_name().to_string_lossy().to_string()))
+        .collect::<Result<Vec<_>>>()?;
+    files.sort();
+    assert_eq!(files.len(), 3);
+    assert!(files.iter().all(|f| !f.ends_with(".tmp")));
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use candle::{Device, Result, Tensor};
+use candle_datasets::vision::transforms::{
+    CenterCrop, ColorJitter, Compose, CutMix, MixUp, RandAugment, RandomCrop, RandomHorizontalFlip,
+    Resize, Transform,
+};
+use candle_datasets::vision::ImageFolder;
+use candle_datasets::{DataLoader, Dataset};
+use image::{Rgb, RgbImage};
+
+// An image whose red channel is the x coordinate and green channel the y coordinate.
+fn gradient(width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 100]))
+}
+
+#[test]
+fn resize_and_crop() -> Result<()> {
+    let image = Resize::new(16).apply(gradient(40, 20))?;
+    assert_eq!(image.dimensions(), (32, 16));
+    let image = Resize::exact(7, 5).apply(image)?;
+    assert_eq!(image.dimensions(), (7, 5));
+
+    let image = CenterCrop::new(4).apply(gradient(8, 6))?;
+    assert_eq!(image.dimensions(), (4, 4));
+    assert_eq!(image.get_pixel(0, 0), &Rgb([2, 1, 100]));
+
+    // The crops larger than the image are padded with black.
+    let image = CenterCrop::new(4).apply(gradient(2, 2))?;
+    assert_eq!(image.get_pixel(0, 0), &Rgb([0, 0, 0]));
+    assert_eq!(image.get_pixel(1, 1), &Rgb([0, 0, 100]));
+
+    for _ in 0..10 {
+        let image = RandomCrop::new(6).padding(2).apply(gradient(6, 6))?;
+        assert_eq!(image.dimensions(), (6, 6));
+    }
+    Ok(())
+}
+
+#[test]
+fn flip_and_color() -> Result<()> {
+    let image = RandomHorizontalFlip::new(1.).apply(gradient(5, 3))?;
+    assert_eq!(image.get_pixel(0, 2), &Rgb([4, 2, 100]));
+    let image = RandomHorizontalFlip::new(0.).apply(gradient(5, 3))?;
+    assert_eq!(image.get_pixel(0, 2), &Rgb([0, 2, 100]));
+
+    // Without jitter, the image is unchanged.
+    let image = ColorJitter::default().apply(gradient(5, 3))?;
+    assert_eq!(image, gradient(5, 3));
+    let image = ColorJitter::new(0.4, 0.4, 0.4, 0.1).apply(gradient(5, 3))?;
+    assert_eq!(image.dimensions(), (5, 3));
+
+    for _ in 0..20 {
+        let image = RandAugment::new(3, 15).apply(gradient(12, 10))?;
+        assert_eq!(image.dimensions(), (12, 10));
+    }
+    Ok(())
+}
+
+#[test]
+fn compose() -> Result<()> {
+    let transform = Compose::new()
+        .add(Resize::new(8))
+        .add(CenterCrop::new(8))
+        .normalize([0.5, 0.5, 0.5], [0.5, 0.5, 0.5]);
+    let xs = transform.apply(RgbImage::from_pixel(16, 12, Rgb([255, 0, 51])))?;
+    assert_eq!(xs.dims(), [3, 8, 8]);
+    let xs = xs.mean((1, 2))?.to_vec1::<f32>()?;
+    for (x, expected) in xs.iter().zip([1., -1., -0.6]) {
+        assert!((x - expected).abs() < 1e-5, "{xs:?}")
+    }
+    Ok(())
+}
+
+#[test]
+fn mixup_cutmix() -> Result<()> {
+    let dev = &Device::Cpu;
+    let xs = Tensor::stack(
+        &[
+            Tensor::zeros((3, 8, 8), candle::DType::F32, dev)?,
+            Tensor::ones((3, 8, 8), candle::DType::F32, dev)?,
+        ],
+        0,
+    )?;
+    let labels = Tensor::new(&[0u32, 2], dev)?;
+    for (mixed, ys) in [
+        MixUp::new(0.4, 3).apply(&xs, &labels)?,
+        CutMix::new(1.0, 3).apply(&xs, &labels)?,
+    ] {
+        assert_eq!(mixed.dims(), [2, 3, 8, 8]);
+        assert_eq!(ys.dims(), [2, 3]);
+        let sums = ys.sum(1)?.to_vec1::<f32>()?;
+        assert!(sums.iter().all(|s| (s - 1.).abs() < 1e-5));
+        // The ratio of the first sample from the second one is its mean pixel value.
+        let ratio = mixed.get(0)?.mean_all()?.to_scalar::<f32>()?;
+        let y = ys.get(0)?.to_vec1::<f32>()?;
+        assert!((y[2] - ratio).abs() < 1e-5, "{y:?} {ratio}");
+    }
+    Ok(())
+}
+
+#[test]
+fn image_folder() -> Result<()> {
+    let root = std::env::temp_dir().join(format!("candle-image-folder-{}", std::process::id()));
+    for (class, n) in [("dog", 2), ("cat", 3)] {
+        let dir = root.join(class);
+        std::fs::create_dir_all(&dir)?;
+        for i in 0..n {
+            gradient(10, 12)
+                .save(dir.join(format!("{i}.png")))
+                .map_err(candle::Error::wrap)?;
+        }
+    }
+    std::fs::write(root.join("cat").join("notes.txt"), "not an image")?;
+
+    let transform = Compose::new().add(Resize::exact(6, 4));
+    let dataset = ImageFolder::new(&root)?.transform(transform);
+    assert_eq!(dataset.classes(), ["cat", "dog"]);
+    assert_eq!(dataset.len(), 5);
+    let (image, label) = dataset.get(4)?;
+    assert_eq!(image.dims(), [3, 4, 6]);
+    assert_eq!(label.to_scalar::<u32>()?, 1);
+
+    let mut loader = DataLoader::new(dataset).batch_size(5).num_workers(2);
+    let (xs, ys) = loader.iter()?.next().unwrap()?;
+    assert_eq!(xs.dims(), [5, 3, 4, 6]);
+    assert_eq!(ys.to_vec1::<u32>()?, [0, 0, 0, 1, 1]);
+    std::fs::remove_dir_all(&root)?;
+    Ok(())
+}
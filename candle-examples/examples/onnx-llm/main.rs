@@ -89,7 +89,7 @@ pub fn main() -> Result<()> {
     let tokens: Vec<i64> = tokens_u32.iter().map(|&t| t as i64).collect();
 
     println!("Loading ONNX model from {:?}", model_path);
-    let model = candle_onnx::read_file(model_path)?;
+    let model = candle_onnx::Model::read(model_path)?;
 
     let mut generated_tokens = tokens.clone();
     print!("{}", args.prompt);
@@ -170,7 +170,7 @@ pub fn main() -> Result<()> {
             }
         }
 
-        let outputs = candle_onnx::simple_eval(&model, inputs)?;
+        let outputs = model.simple_eval(inputs)?;
 
         let logits = outputs.get("logits").unwrap();
 
@@ -26,6 +26,10 @@ struct Args {
     /// The model to be used.
     #[arg(value_enum, long, default_value_t = Which::SqueezeNet)]
     which: Which,
+
+    /// Print the inferred shapes, dtypes and unsupported ops of the model before running it.
+    #[arg(long)]
+    inspect: bool,
 }
 
 pub fn main() -> anyhow::Result<()> {
@@ -65,6 +69,9 @@ pub fn main() -> anyhow::Result<()> {
     };
 
     let model = candle_onnx::read_file(model)?;
+    if args.inspect {
+        print!("{}", candle_onnx::inspect(&model)?);
+    }
     let graph = model.graph.as_ref().unwrap();
     let mut inputs = std::collections::HashMap::new();
     inputs.insert(graph.input[0].name.to_string(), image.unsqueeze(0)?);
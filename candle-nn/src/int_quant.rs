@@ -0,0 +1,143 @@
+//! Linear and convolution layers with per-channel or per-group integer weights.
+//!
+//! The weights are quantized with [`candle::quantized::int_quants`], the cpu forward pass uses
+//! int8 products with dynamically quantized activations.
+use crate::{Conv2d, Conv2dConfig, Linear};
+use candle::quantized::int_quants::{IntQTensor, IntQuantConfig};
+use candle::{Result, Tensor};
+
+#[derive(Clone, Debug)]
+pub struct QLinear {
+    weight: IntQTensor,
+    bias: Option<Tensor>,
+}
+
+impl QLinear {
+    pub fn new(weight: IntQTensor, bias: Option<Tensor>) -> Self {
+        Self { weight, bias }
+    }
+
+    pub fn weight(&self) -> &IntQTensor {
+        &self.weight
+    }
+
+    pub fn bias(&self) -> Option<&Tensor> {
+        self.bias.as_ref()
+    }
+}
+
+impl crate::Module for QLinear {
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let x = self.weight.matmul_t(x)?;
+        match &self.bias {
+            None => Ok(x),
+            Some(bias) => x.broadcast_add(bias),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct QConv2d {
+    weight: IntQTensor,
+    bias: Option<Tensor>,
+    config: Conv2dConfig,
+}
+
+impl QConv2d {
+    pub fn new(weight: IntQTensor, bias: Option<Tensor>, config: Conv2dConfig) -> Self {
+        Self {
+            weight,
+            bias,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &Conv2dConfig {
+        &self.config
+    }
+
+    pub fn weight(&self) -> &IntQTensor {
+        &self.weight
+    }
+
+    pub fn bias(&self) -> Option<&Tensor> {
+        self.bias.as_ref()
+    }
+
+    // Unfolds the patches of the input so that the convolution is a matmul with the weights:
+    // (b, c, h, w) -> (b, out_h * out_w, c * k_h * k_w).
+    fn im2col(&self, x: &Tensor, k_h: usize, k_w: usize) -> Result<(Tensor, usize, usize)> {
+        let Conv2dConfig {
+            padding: p,
+            stride: s,
+            dilation: d,
+            ..
+        } = self.config;
+        let (b, c, h, w) = x.dims4()?;
+        let (h, w) = (h + 2 * p, w + 2 * p);
+        if h < d * (k_h - 1) + 1 || w < d * (k_w - 1) + 1 {
+            candle::bail!("input {:?} is too small for the kernel", x.shape())
+        }
+        let out_h = (h - d * (k_h - 1) - 1) / s + 1;
+        let out_w = (w - d * (k_w - 1) - 1) / s + 1;
+        let mut indexes = Vec::with_capacity(out_h * out_w * k_h * k_w);
+        for oy in 0..out_h {
+            for ox in 0..out_w {
+                for ky in 0..k_h {
+                    for kx in 0..k_w {
+                        indexes.push(((oy * s + ky * d) * w + ox * s + kx * d) as u32)
+                    }
+                }
+            }
+        }
+        let indexes = Tensor::new(indexes, x.device())?;
+        let x = x
+            .pad_with_zeros(2, p, p)?
+            .pad_with_zeros(3, p, p)?
+            .reshape((b, c, h * w))?
+            .index_select(&indexes, 2)?
+            .reshape((b, c, out_h * out_w, k_h * k_w))?
+            .transpose(1, 2)?
+            .reshape((b, out_h * out_w, c * k_h * k_w))?;
+        Ok((x, out_h, out_w))
+    }
+}
+
+impl crate::Module for QConv2d {
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        let (out_c, _in_c, k_h, k_w) = self.weight.shape().dims4()?;
+        let x = if self.config.groups == 1 {
+            let (b, _c, _h, _w) = x.dims4()?;
+            let (x, out_h, out_w) = self.im2col(x, k_h, k_w)?;
+            self.weight
+                .matmul_t(&x)?
+                .transpose(1, 2)?
+                .reshape((b, out_c, out_h, out_w))?
+        } else {
+            // Grouped convolutions use the dequantized weights.
+            let w = self.weight.dequantize(x.device())?.to_dtype(x.dtype())?;
+            let c = self.config;
+            x.conv2d(&w, c.padding, c.stride, c.dilation, c.groups)?
+        };
+        match &self.bias {
+            None => Ok(x),
+            Some(bias) => {
+                let b = bias.dims1()?;
+                x.broadcast_add(&bias.reshape((1, b, 1, 1))?)
+            }
+        }
+    }
+}
+
+/// Quantizes the weights of a linear layer, the bias is kept as is.
+pub fn quantize_linear(linear: &Linear, config: IntQuantConfig) -> Result<QLinear> {
+    let weight = IntQTensor::quantize(linear.weight(), config)?;
+    Ok(QLinear::new(weight, linear.bias().cloned()))
+}
+
+/// Quantizes the weights of a convolution layer per output channel, the group size applies to
+/// the flattened input channels and kernel positions.
+pub fn quantize_conv2d(conv: &Conv2d, config: IntQuantConfig) -> Result<QConv2d> {
+    let weight = IntQTensor::quantize(conv.weight(), config)?;
+    Ok(QConv2d::new(weight, conv.bias().cloned(), *conv.config()))
+}
@@ -55,6 +55,11 @@ impl Cache {
         self.all_data = None;
     }
 
+    /// Drops every element past `seq_len`, this is a no-op if the cache is already shorter.
+    pub fn truncate(&mut self, seq_len: usize) {
+        self.current_seq_len = self.current_seq_len.min(seq_len)
+    }
+
     pub fn append(&mut self, src: &Tensor) -> Result<()> {
         let seq_len = src.dim(self.dim)?;
         // This doesn't seem very idiomatic but because the creation can fail, it's tricky to use
@@ -148,6 +153,12 @@ impl KvCache {
         self.k.reset();
         self.v.reset();
     }
+
+    /// Rolls the cache back to its first `seq_len` positions.
+    pub fn truncate(&mut self, seq_len: usize) {
+        self.k.truncate(seq_len);
+        self.v.truncate(seq_len);
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -443,6 +454,17 @@ impl ScatteredKvCache {
     pub fn v(&self) -> &Tensor {
         &self.v
     }
+
+    /// Returns the cache for the batch element `batch_index`. The returned cache shares its
+    /// storage with this one so appending to it updates this cache, e.g. to process a prompt for
+    /// a single batch element.
+    pub fn batch_element(&self, batch_index: usize) -> Result<Self> {
+        Ok(Self {
+            k: self.k.narrow(0, batch_index, 1)?,
+            v: self.v.narrow(0, batch_index, 1)?,
+            context: self.context,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -499,7 +521,6 @@ impl ScatteredCacheBuilder {
         self.indices[batch_index] = 0;
     }
 
-    #[allow(clippy::needless_range_loop)]
     pub fn indices_and_mask(
         &mut self,
         seq_len: usize,
@@ -519,51 +540,7 @@ impl ScatteredCacheBuilder {
                 attention_masks.push(masks);
                 cache_indices.push(indices);
             } else {
-                let start_index = self.indices[batch_i];
-                let start_pos = self.positions[batch_i];
-                let mut masks: Vec<Vec<f32>> = Vec::with_capacity(seq_len);
-                let mut indices = Vec::with_capacity(seq_len);
-                let mut all_pos = vec![usize::MAX; context];
-                if start_pos < context {
-                    for i in 0..start_pos {
-                        all_pos[i] = i;
-                    }
-                } else {
-                    let offset = start_pos - start_index;
-                    for i in 0..context {
-                        all_pos[i] = if i < start_index {
-                            i + offset
-                        } else {
-                            i + offset - context
-                        };
-                    }
-                }
-                for seq_i in 0..seq_len {
-                    let index = self.indices[batch_i];
-                    all_pos[index] = seq_i + start_pos;
-                    indices.push(index as u32);
-                    self.indices[batch_i] += 1;
-                    self.positions[batch_i] += 1;
-                    if self.indices[batch_i] >= self.context {
-                        self.indices[batch_i] = 0;
-                    }
-                }
-
-                for seq_i in 0..seq_len {
-                    let my_pos = seq_i + start_pos;
-                    let mask = all_pos
-                        .iter()
-                        .map(|&pos| {
-                            if pos <= my_pos {
-                                0.0
-                            } else {
-                                f32::NEG_INFINITY
-                            }
-                        })
-                        .collect::<Vec<f32>>();
-                    masks.push(mask);
-                }
-
+                let (indices, masks) = self.advance(batch_i, seq_len);
                 attention_masks.push(masks);
                 cache_indices.push(indices);
             }
@@ -580,11 +557,105 @@ impl ScatteredCacheBuilder {
         Ok(IndicesAndMask { indices, mask })
     }
 
+    /// Same as `indices_and_mask` for a batch holding only the element `batch_index`, this is to
+    /// be used with the caches returned by [`ScatteredKvCache::batch_element`].
+    pub fn indices_and_mask_for(
+        &mut self,
+        batch_index: usize,
+        seq_len: usize,
+    ) -> Result<IndicesAndMask> {
+        if batch_index >= self.batch_size() {
+            candle::bail!(
+                "batch index {batch_index} out of range for batch size {}",
+                self.batch_size()
+            )
+        }
+        if self.context <= seq_len {
+            let mask = self.get_mask_abs(seq_len, seq_len)?;
+            let indices = self.advance_abs(batch_index, seq_len);
+            let indices = Tensor::new(vec![indices], self.device())?;
+            return Ok(IndicesAndMask { indices, mask });
+        }
+        let (indices, masks) = self.advance(batch_index, seq_len);
+        let masks = masks.into_iter().flatten().collect::<Vec<f32>>();
+        let mask = Tensor::from_vec(masks, (1, 1, seq_len, self.context), self.device())?
+            .to_dtype(self.dtype)?;
+        let indices = Tensor::new(vec![indices], self.device())?;
+        Ok(IndicesAndMask { indices, mask })
+    }
+
+    // Moves the batch element `batch_i` forward by `seq_len` positions, returns the cache indices
+    // for the new elements and their attention masks.
+    #[allow(clippy::needless_range_loop)]
+    fn advance(&mut self, batch_i: usize, seq_len: usize) -> (Vec<u32>, Vec<Vec<f32>>) {
+        let context = self.context;
+        let start_index = self.indices[batch_i];
+        let start_pos = self.positions[batch_i];
+        let mut masks: Vec<Vec<f32>> = Vec::with_capacity(seq_len);
+        let mut indices = Vec::with_capacity(seq_len);
+        let mut all_pos = vec![usize::MAX; context];
+        if start_pos < context {
+            for i in 0..start_pos {
+                all_pos[i] = i;
+            }
+        } else {
+            let offset = start_pos - start_index;
+            for i in 0..context {
+                all_pos[i] = if i < start_index {
+                    i + offset
+                } else {
+                    i + offset - context
+                };
+            }
+        }
+        for seq_i in 0..seq_len {
+            let index = self.indices[batch_i];
+            all_pos[index] = seq_i + start_pos;
+            indices.push(index as u32);
+            self.indices[batch_i] += 1;
+            self.positions[batch_i] += 1;
+            if self.indices[batch_i] >= self.context {
+                self.indices[batch_i] = 0;
+            }
+        }
+
+        for seq_i in 0..seq_len {
+            let my_pos = seq_i + start_pos;
+            let mask = all_pos
+                .iter()
+                .map(|&pos| {
+                    if pos <= my_pos {
+                        0.0
+                    } else {
+                        f32::NEG_INFINITY
+                    }
+                })
+                .collect::<Vec<f32>>();
+            masks.push(mask);
+        }
+        (indices, masks)
+    }
+
+    // Same as `advance` when the sequence does not fit in the context, the cache is not used in
+    // this case.
+    fn advance_abs(&mut self, batch_i: usize, seq_len: usize) -> Vec<u32> {
+        let mut indices = Vec::with_capacity(seq_len);
+        for _ in 0..seq_len {
+            let index = self.indices[batch_i];
+            indices.push(index as u32);
+            self.indices[batch_i] += 1;
+            self.positions[batch_i] += 1;
+            if self.indices[batch_i] >= self.context {
+                self.indices[batch_i] = 0;
+            }
+        }
+        indices
+    }
+
     pub fn device(&self) -> &Device {
         &self.device
     }
 
-    #[allow(clippy::needless_range_loop)]
     fn indices_and_mask_abs(
         &mut self,
         seq_len: usize,
@@ -597,17 +668,7 @@ impl ScatteredCacheBuilder {
                 let indices = vec![self.indices[batch_i] as u32; seq_len];
                 cache_indices.push(indices);
             } else {
-                let mut indices = Vec::with_capacity(seq_len);
-                for _ in 0..seq_len {
-                    let index = self.indices[batch_i];
-                    indices.push(index as u32);
-                    self.indices[batch_i] += 1;
-                    self.positions[batch_i] += 1;
-                    if self.indices[batch_i] >= self.context {
-                        self.indices[batch_i] = 0;
-                    }
-                }
-                cache_indices.push(indices);
+                cache_indices.push(self.advance_abs(batch_i, seq_len));
             }
         }
         let indices = Tensor::new(cache_indices, self.device())?;
@@ -760,6 +821,28 @@ impl ConcatKvCache {
         self.v = None;
     }
 
+    /// Truncate the cache to its first `seq_len` positions
+    ///
+    /// This is used to roll back entries that were appended speculatively, e.g. draft tokens
+    /// rejected during speculative decoding. Truncating to 0 resets the cache and truncating
+    /// past the current length is a no-op.
+    pub fn truncate(&mut self, seq_len: usize) -> Result<()> {
+        if seq_len == 0 {
+            self.reset();
+            return Ok(());
+        }
+        if seq_len >= self.current_seq_len() {
+            return Ok(());
+        }
+        if let Some(k) = self.k.as_mut() {
+            *k = k.narrow(self.dim, 0, seq_len)?;
+        }
+        if let Some(v) = self.v.as_mut() {
+            *v = v.narrow(self.dim, 0, seq_len)?;
+        }
+        Ok(())
+    }
+
     /// Get reference to current K cache data
     ///
     /// Returns `None` if the cache is empty.
@@ -983,4 +1066,35 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_concat_cache_truncate() -> Result<()> {
+        let device = Device::Cpu;
+        let mut cache = ConcatKvCache::new(2);
+
+        let k1 = Tensor::arange(0f32, 6., &device)?.reshape((1, 1, 6, 1))?;
+        let v1 = (&k1 + 10.)?;
+        cache.append(&k1, &v1)?;
+
+        // Roll back the last two positions, e.g. rejected draft tokens.
+        cache.truncate(4)?;
+        assert_eq!(cache.current_seq_len(), 4);
+
+        let k2 = Tensor::new(&[[[[42f32]]]], &device)?;
+        let v2 = (&k2 + 10.)?;
+        let (k, v) = cache.append(&k2, &v2)?;
+        assert_eq!(k.flatten_all()?.to_vec1::<f32>()?, [0., 1., 2., 3., 42.]);
+        assert_eq!(
+            v.flatten_all()?.to_vec1::<f32>()?,
+            [10., 11., 12., 13., 52.]
+        );
+
+        // Truncating past the end is a no-op, truncating to zero resets.
+        cache.truncate(10)?;
+        assert_eq!(cache.current_seq_len(), 5);
+        cache.truncate(0)?;
+        assert!(cache.is_empty());
+
+        Ok(())
+    }
 }
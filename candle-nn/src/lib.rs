@@ -24,6 +24,7 @@ pub mod encoding;
 pub mod func;
 pub mod group_norm;
 pub mod init;
+pub mod int_quant;
 pub mod kv_cache;
 pub mod layer_norm;
 pub mod linear;
@@ -49,6 +50,7 @@ pub use embedding::{embedding, Embedding};
 pub use func::{func, func_t, Func, FuncT};
 pub use group_norm::{group_norm, GroupNorm};
 pub use init::Init;
+pub use int_quant::{quantize_conv2d, quantize_linear, QConv2d, QLinear};
 pub use layer_norm::{
     layer_norm, layer_norm_no_bias, rms_norm, LayerNorm, LayerNormConfig, RmsNorm,
 };
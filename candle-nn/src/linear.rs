@@ -17,17 +17,30 @@
 //! assert_eq!(ys.to_vec2::<f32>()?, &[[210.0, 430.0, 650.0]]);
 //! # Ok(()) }
 //! ```
+use candle::quantized::calibration;
 use candle::{Result, Tensor};
 
 #[derive(Clone, Debug)]
 pub struct Linear {
     weight: Tensor,
     bias: Option<Tensor>,
+    calibration: Option<calibration::Hook>,
 }
 
 impl Linear {
     pub fn new(weight: Tensor, bias: Option<Tensor>) -> Self {
-        Self { weight, bias }
+        Self {
+            weight,
+            bias,
+            calibration: None,
+        }
+    }
+
+    /// Attaches a calibration hook recording the inputs of the layer, see
+    /// [`candle::quantized::calibration`].
+    pub fn with_calibration(mut self, hook: calibration::Hook) -> Self {
+        self.calibration = Some(hook);
+        self
     }
 
     pub fn weight(&self) -> &Tensor {
@@ -41,6 +54,9 @@ impl Linear {
 
 impl super::Module for Linear {
     fn forward(&self, x: &Tensor) -> candle::Result<Tensor> {
+        if let Some(hook) = &self.calibration {
+            hook.record(x)?
+        }
         // When possible, we avoid using a broadcasted matmul as it is much slower
         // than the standard matmul for the cuda and cpu backends.
         let x = match *x.dims() {
@@ -90,14 +106,26 @@ pub fn linear(in_dim: usize, out_dim: usize, vb: crate::VarBuilder) -> Result<Li
         up: bound,
     };
     let bs = vb.get_with_hints(out_dim, "bias", init_bs)?;
-    Ok(Linear::new(ws, Some(bs)))
+    Ok(hook_loaded(Linear::new(ws, Some(bs)), &vb))
 }
 
 /// Create or initialize a new linear layer without biases.
 pub fn linear_no_bias(in_dim: usize, out_dim: usize, vb: crate::VarBuilder) -> Result<Linear> {
     let init_ws = crate::init::DEFAULT_KAIMING_NORMAL;
     let ws = vb.get_with_hints((out_dim, in_dim), "weight", init_ws)?;
-    Ok(Linear::new(ws, None))
+    Ok(hook_loaded(Linear::new(ws, None), &vb))
+}
+
+// Hooks the layer when built within a calibration, see [`calibration::Calibration::load`].
+fn hook_loaded(linear: Linear, vb: &crate::VarBuilder) -> Linear {
+    let name = match vb.prefix().as_str() {
+        "" => "weight".to_string(),
+        prefix => format!("{prefix}.weight"),
+    };
+    match calibration::loading_hook(&name) {
+        Some(hook) => linear.with_calibration(hook),
+        None => linear,
+    }
 }
 
 pub fn linear_b(
@@ -0,0 +1,42 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::quantized::calibration::Calibration;
+use candle::{DType, Device, Module, Tensor};
+use candle_nn::{VarBuilder, VarMap};
+
+#[test]
+fn linear_calibration() -> Result<()> {
+    let device = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, device);
+    let calibration = Calibration::new();
+    let (up, down) = calibration.load(|| {
+        let up = candle_nn::linear(8, 16, vb.pp("mlp.up"))?;
+        let down = candle_nn::linear_no_bias(16, 8, vb.pp("mlp.down"))?;
+        candle::Result::Ok((up, down))
+    })?;
+    // Layers built outside of the calibration are not hooked.
+    let other = candle_nn::linear(8, 16, vb.pp("other"))?;
+
+    let xs = Tensor::arange(0f32, 24., device)?.reshape((1, 3, 8))?;
+    down.forward(&up.forward(&xs)?)?;
+    other.forward(&xs)?;
+    let imatrix = calibration.imatrix();
+    assert_eq!(
+        imatrix.keys().collect::<Vec<_>>(),
+        ["mlp.down.weight", "mlp.up.weight"]
+    );
+    let entry = &imatrix["mlp.up.weight"];
+    assert_eq!(entry.ncall, 1);
+    let expected = xs.reshape((3, 8))?.sqr()?.mean(0)?.to_vec1::<f32>()?;
+    for (v, e) in entry.values.iter().zip(expected.iter()) {
+        assert!((v - e).abs() < 1e-4, "{v} {e}")
+    }
+    assert_eq!(imatrix["mlp.down.weight"].values.len(), 16);
+    Ok(())
+}
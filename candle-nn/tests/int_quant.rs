@@ -0,0 +1,87 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::Result;
+use candle::quantized::int_quants::IntQuantConfig;
+use candle::{Device, Module, Shape, Tensor};
+use candle_nn::{Conv2d, Conv2dConfig, Linear};
+
+fn max_rel_diff(xs: &Tensor, ys: &Tensor) -> Result<f32> {
+    let diff = (xs - ys)?.abs()?.max_all()?.to_scalar::<f32>()?;
+    let scale = ys.abs()?.max_all()?.to_scalar::<f32>()?;
+    Ok(diff / scale)
+}
+
+// Deterministic weights with values in [-1, 1].
+fn sin_weights<S: Into<Shape>>(shape: S, device: &Device) -> Result<Tensor> {
+    let shape = shape.into();
+    let w = Tensor::arange(0f32, shape.elem_count() as f32, device)?
+        .affine(0.37, 0.)?
+        .sin()?
+        .reshape(shape)?;
+    Ok(w)
+}
+
+#[test]
+fn quantized_linear() -> Result<()> {
+    let device = &Device::Cpu;
+    let w = sin_weights((16, 64), device)?;
+    let b = Tensor::arange(0f32, 16., device)?;
+    let linear = Linear::new(w, Some(b));
+    let xs = Tensor::arange(0f32, 2. * 5. * 64., device)?
+        .affine(0.03, -1.)?
+        .cos()?
+        .reshape((2, 5, 64))?;
+    let expected = linear.forward(&xs)?;
+    for config in [
+        IntQuantConfig::int8_per_channel(),
+        IntQuantConfig::int4_per_group(16),
+    ] {
+        let qlinear = candle_nn::quantize_linear(&linear, config)?;
+        let ys = qlinear.forward(&xs)?;
+        assert_eq!(ys.dims(), [2, 5, 16]);
+        assert!(max_rel_diff(&ys, &expected)? < 0.05, "{config:?}");
+    }
+    Ok(())
+}
+
+#[test]
+fn quantized_conv2d() -> Result<()> {
+    let device = &Device::Cpu;
+    let w = sin_weights((8, 4, 3, 3), device)?;
+    let b = Tensor::arange(0f32, 8., device)?.affine(0.1, 0.)?;
+    let xs = Tensor::arange(0f32, 2. * 4. * 9. * 7., device)?
+        .affine(0.05, 0.)?
+        .cos()?
+        .reshape((2, 4, 9, 7))?;
+    let configs = [
+        Conv2dConfig::default(),
+        Conv2dConfig {
+            padding: 1,
+            stride: 2,
+            dilation: 2,
+            ..Default::default()
+        },
+    ];
+    for config in configs {
+        let conv = Conv2d::new(w.clone(), Some(b.clone()), config);
+        let expected = conv.forward(&xs)?;
+        let qconv = candle_nn::quantize_conv2d(&conv, IntQuantConfig::int8_per_channel())?;
+        let ys = qconv.forward(&xs)?;
+        assert_eq!(ys.dims(), expected.dims());
+        assert!(max_rel_diff(&ys, &expected)? < 0.02, "{config:?}");
+    }
+    // Grouped convolutions fall back to the dequantized weights.
+    let config = Conv2dConfig {
+        groups: 2,
+        ..Default::default()
+    };
+    let conv = Conv2d::new(w.narrow(1, 0, 2)?, None, config);
+    let expected = conv.forward(&xs)?;
+    let qconv = candle_nn::quantize_conv2d(&conv, IntQuantConfig::int8_per_channel())?;
+    assert!(max_rel_diff(&qconv.forward(&xs)?, &expected)? < 0.02);
+    Ok(())
+}
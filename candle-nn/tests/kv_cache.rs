@@ -26,6 +26,11 @@ fn kv_cache() -> Result<()> {
         let data = cache.current_data()?.unwrap();
         assert_eq!(data.to_vec1::<f32>()?, [1., 2., 3., 4., 0., 5., 6., 7.]);
         assert_eq!(cache.current_seq_len(), 8);
+        cache.truncate(5);
+        let t = Tensor::new(&[8f32], &Device::Cpu)?;
+        cache.append(&t)?;
+        let data = cache.current_data()?.unwrap();
+        assert_eq!(data.to_vec1::<f32>()?, [1., 2., 3., 4., 0., 8.]);
         cache.reset();
     }
     Ok(())
@@ -121,3 +126,34 @@ fn rotating_kv_cache() -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn scattered_kv_cache_batch_element() -> Result<()> {
+    use candle_nn::kv_cache::ScatteredCacheBuilder;
+    let dev = &Device::Cpu;
+    let mut builder = ScatteredCacheBuilder::new(2, 4, candle::DType::F32, dev)?;
+    let mut cache = builder.make_cache(1, 1)?;
+
+    // Appending to the cache of the second batch element updates the full cache.
+    let mut element = cache.batch_element(1)?;
+    let iam = builder.indices_and_mask_for(1, 2)?;
+    let kv = Tensor::new(&[1f32, 2.], dev)?.reshape((1, 1, 2, 1))?;
+    let (k, _) = element.append(&kv, &kv, &iam)?;
+    assert_eq!(k.flatten_all()?.to_vec1::<f32>()?, [1., 2., 0., 0.]);
+    assert_eq!(builder.positions(), &[0, 2]);
+    let inf = f32::NEG_INFINITY;
+    let mask = iam.mask().flatten_all()?.to_vec1::<f32>()?;
+    assert_eq!(mask, [0., inf, inf, inf, 0., 0., inf, inf]);
+
+    // A batched step then sees the values of the second element.
+    let iam = builder.indices_and_mask(1, &[true, true])?;
+    let kv = Tensor::new(&[3f32, 4.], dev)?.reshape((2, 1, 1, 1))?;
+    let (k, _) = cache.append(&kv, &kv, &iam)?;
+    assert_eq!(
+        k.flatten_all()?.to_vec1::<f32>()?,
+        [3., 0., 0., 0., 1., 2., 4., 0.]
+    );
+    assert_eq!(builder.positions(), &[1, 3]);
+    assert!(builder.indices_and_mask_for(2, 1).is_err());
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use crate::external_data::ExternalData;
 use crate::onnx::attribute_proto::AttributeType;
 use crate::onnx::tensor_proto::DataType;
 use crate::onnx::{self, GraphProto};
@@ -90,6 +91,9 @@ impl AttrOwned for Tensor {
                 attr.name
             ),
         };
+        if tensor_proto.data_location == onnx::tensor_proto::DataLocation::External as i32 {
+            bail!("attribute {} has its data stored externally", attr.name)
+        }
 
         let data_type = match DataType::try_from(tensor_proto.data_type) {
             Ok(value) => value,
@@ -190,20 +194,41 @@ fn get_attr_opt_owned<T: AttrOwned>(node: &onnx::NodeProto, name: &str) -> Resul
 }
 
 pub fn get_tensor(t: &onnx::TensorProto, name: &str) -> Result<Tensor> {
+    get_tensor_(t, name, &ExternalData::default())
+}
+
+/// Converts `t` to a tensor, the data of the external tensors is read from `external_data`.
+pub(crate) fn get_tensor_(
+    t: &onnx::TensorProto,
+    name: &str,
+    external_data: &ExternalData,
+) -> Result<Tensor> {
+    let raw_data = external_data.raw_data(t)?;
     let dims: Vec<usize> = t.dims.iter().map(|&x| x as usize).collect();
     match DataType::try_from(t.data_type) {
         Ok(DataType::Int32) => {
             if t.int32_data.is_empty() {
-                let len = t.raw_data.len() / 4;
-                let data: &[i32] =
-                    unsafe { std::slice::from_raw_parts(t.raw_data.as_ptr() as *const i32, len) };
-                let data = data.iter().map(|v| *v as i64).collect::<Vec<_>>();
+                // The external data is not necessarily aligned.
+                let data = raw_data
+                    .chunks_exact(4)
+                    .map(|v| i32::from_le_bytes([v[0], v[1], v[2], v[3]]) as i64)
+                    .collect::<Vec<_>>();
+                let len = data.len();
                 Tensor::from_vec(data, len, &Device::Cpu)
             } else {
                 let data = t.int32_data.iter().map(|v| *v as i64).collect::<Vec<_>>();
                 Tensor::from_vec(data, t.int32_data.len(), &Device::Cpu)
             }
         }
+        // There is no int8 dtype, the values are widened to i64 similar to int32.
+        Ok(DataType::Int8) => {
+            let data = if t.int32_data.is_empty() {
+                raw_data.iter().map(|&v| v as i8 as i64).collect::<Vec<_>>()
+            } else {
+                t.int32_data.iter().map(|&v| v as i64).collect::<Vec<_>>()
+            };
+            Tensor::from_vec(data, dims.as_slice(), &Device::Cpu)
+        }
         Ok(dt) => match dtype(dt) {
             Some(dt) => {
                 if dt == DType::F32 && !t.float_data.is_empty() {
@@ -213,12 +238,7 @@ pub fn get_tensor(t: &onnx::TensorProto, name: &str) -> Result<Tensor> {
                 } else if dt == DType::I64 && !t.int64_data.is_empty() {
                     Tensor::from_slice(&t.int64_data, dims.as_slice(), &Device::Cpu)
                 } else {
-                    Tensor::from_raw_buffer(
-                        t.raw_data.as_slice(),
-                        dt,
-                        dims.as_slice(),
-                        &Device::Cpu,
-                    )
+                    Tensor::from_raw_buffer(&raw_data, dt, dims.as_slice(), &Device::Cpu)
                 }
             }
             None => {
@@ -231,2331 +251,3150 @@ pub fn get_tensor(t: &onnx::TensorProto, name: &str) -> Result<Tensor> {
     }
 }
 
-// This function provides a direct evaluation of the proto.
-// Longer-term, we should first convert the proto to an intermediate representation of the compute
-// graph so as to make multiple evaluations more efficient.
-// An example upside of this would be to remove intermediary values when they are not needed
-// anymore.
+// This function provides a direct evaluation of the proto, all the intermediary values are kept
+// until the end of the evaluation. Use `OnnxSession` to evaluate a graph multiple times, it
+// converts the proto once and drops the intermediary values when they are not needed anymore.
+// The models with external data are evaluated through `Model::simple_eval`.
 pub fn simple_eval(
     model: &onnx::ModelProto,
     mut inputs: HashMap<String, Value>,
+) -> Result<HashMap<String, Value>> {
+    simple_eval_(model, &ExternalData::default(), &mut inputs)
+}
+
+pub(crate) fn simple_eval_(
+    model: &onnx::ModelProto,
+    external_data: &ExternalData,
+    values: &mut HashMap<String, Value>,
 ) -> Result<HashMap<String, Value>> {
     let graph = match &model.graph {
         None => bail!("no graph defined in proto"),
         Some(graph) => graph,
     };
-    simple_eval_(graph, &mut inputs)
+    let mut sequences = HashMap::new();
+    eval_graph(graph, values, &mut sequences, external_data)?;
+    graph
+        .output
+        .iter()
+        .map(|output| match values.remove(&output.name) {
+            None if sequences.contains_key(&output.name) => {
+                bail!("unsupported sequence output {}", output.name)
+            }
+            None => bail!("cannot find output {}", output.name),
+            Some(value) => Ok((output.name.clone(), value)),
+        })
+        .collect()
 }
 
-fn simple_eval_(
+/// Evaluates the nodes of a graph or of a sub-graph, all the values are left in `values` and
+/// `sequences` so that the sub-graphs can access the values of the outer scopes.
+fn eval_graph(
     graph: &onnx::GraphProto,
     values: &mut HashMap<String, Value>,
-) -> Result<HashMap<String, Value>> {
+    sequences: &mut HashMap<String, Vec<Value>>,
+    external_data: &ExternalData,
+) -> Result<()> {
     for t in graph.initializer.iter() {
-        let tensor = get_tensor(t, t.name.as_str())?;
+        let tensor = get_tensor_(t, t.name.as_str(), external_data)?;
         values.insert(t.name.to_string(), tensor);
     }
     for input in graph.input.iter() {
-        let input_type = match &input.r#type {
-            Some(input_type) => input_type,
-            None => continue,
-        };
-        let input_type = match &input_type.value {
-            Some(input_type) => input_type,
-            None => continue,
-        };
-        let tensor_type = match input_type {
-            onnx::type_proto::Value::TensorType(tt) => tt,
-            _ => continue,
-        };
-
-        let tensor = match values.get(&input.name) {
-            None => bail!("missing input {}", input.name),
-            Some(tensor) => tensor,
-        };
-        let dt = match DataType::try_from(tensor_type.elem_type) {
-            Ok(dt) => match dtype(dt) {
-                Some(dt) => dt,
-                None => {
-                    bail!("unsupported 'value' data-type {dt:?} for {}", input.name)
-                }
-            },
-            type_ => bail!("unsupported input type {type_:?}"),
-        };
-        match &tensor_type.shape {
-            None => continue,
-            Some(shape) => {
-                if shape.dim.len() != tensor.rank() {
-                    bail!(
-                        "unexpected rank for {}, got {:?}, expected {:?}",
-                        input.name,
-                        shape.dim,
-                        tensor.shape()
-                    )
-                }
-                for (idx, (d, &dim)) in shape.dim.iter().zip(tensor.dims().iter()).enumerate() {
-                    match &d.value {
-                        Some(onnx::tensor_shape_proto::dimension::Value::DimValue(v)) => {
-                            if *v as usize != dim {
-                                bail!(
-                                    "unexpected dim {idx} for {}, got {:?}, expected {:?}",
-                                    input.name,
-                                    shape.dim,
-                                    tensor.shape()
-                                )
-                            }
-                        }
-                        // We do not check equality constraints for the DimParam dimensions for now.
-                        Some(onnx::tensor_shape_proto::dimension::Value::DimParam(_)) | None => (),
-                    }
-                }
-            }
-        };
-        if dt != tensor.dtype() {
-            bail!(
-                "unexpected dtype for {}, got {:?}, expected {dt:?}",
-                input.name,
-                tensor.dtype()
-            )
-        }
+        check_input(input, values)?
     }
     // The nodes are topologically sorted so we can just process them in order.
     for node in graph.node.iter() {
-        let get = |input_name: &str| match values.get(input_name) {
-            Some(value) => Ok(value),
-            None => bail!("cannot find {input_name} for op '{}'", node.name),
-        };
-        let get_opt = |i: usize| {
-            node.input
-                .get(i)
-                .filter(|s: &&String| !s.is_empty())
-                .map(|s| get(s))
-        };
+        eval_node(node, values, sequences, external_data)?;
+    }
+    Ok(())
+}
 
-        // TODO: Validate node.input for each operator.
-        match node.op_type.as_str() {
-            "Add" => {
-                let input0 = get(&node.input[0])?;
-                let input1 = get(&node.input[1])?;
-                let output = input0.broadcast_add(input1)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "Sub" => {
-                let input0 = get(&node.input[0])?;
-                let input1 = get(&node.input[1])?;
-                let output = input0.broadcast_sub(input1)?;
-                values.insert(node.output[0].clone(), output);
+/// A tensor or a sequence of tensors, the inputs and outputs of sub-graphs can be either.
+pub(crate) enum Item {
+    Tensor(Value),
+    Sequence(Vec<Value>),
+}
+
+impl Item {
+    pub(crate) fn get(
+        name: &str,
+        values: &HashMap<String, Value>,
+        sequences: &HashMap<String, Vec<Value>>,
+    ) -> Result<Self> {
+        if let Some(value) = values.get(name) {
+            return Ok(Self::Tensor(value.clone()));
+        }
+        match sequences.get(name) {
+            Some(sequence) => Ok(Self::Sequence(sequence.clone())),
+            None => bail!("cannot find {name}"),
+        }
+    }
+
+    pub(crate) fn insert(
+        self,
+        name: &str,
+        values: &mut HashMap<String, Value>,
+        sequences: &mut HashMap<String, Vec<Value>>,
+    ) {
+        match self {
+            Self::Tensor(value) => {
+                sequences.remove(name);
+                values.insert(name.to_string(), value);
+            }
+            Self::Sequence(sequence) => {
+                values.remove(name);
+                sequences.insert(name.to_string(), sequence);
             }
-            "Mul" => {
-                let input0 = get(&node.input[0])?;
-                let input1 = get(&node.input[1])?;
-                let output = input0.broadcast_mul(input1)?;
-                values.insert(node.output[0].clone(), output);
+        }
+    }
+
+    fn tensor(self) -> Result<Value> {
+        match self {
+            Self::Tensor(value) => Ok(value),
+            Self::Sequence(_) => bail!("expected a tensor, got a sequence"),
+        }
+    }
+}
+
+/// Checks the dtype and shape of the value provided for a graph input with a tensor type.
+pub(crate) fn check_input(
+    input: &onnx::ValueInfoProto,
+    values: &HashMap<String, Value>,
+) -> Result<()> {
+    let input_type = match &input.r#type {
+        Some(input_type) => input_type,
+        None => return Ok(()),
+    };
+    let input_type = match &input_type.value {
+        Some(input_type) => input_type,
+        None => return Ok(()),
+    };
+    let tensor_type = match input_type {
+        onnx::type_proto::Value::TensorType(tt) => tt,
+        _ => return Ok(()),
+    };
+
+    let tensor = match values.get(&input.name) {
+        None => bail!("missing input {}", input.name),
+        Some(tensor) => tensor,
+    };
+    let dt = match DataType::try_from(tensor_type.elem_type) {
+        Ok(dt) => match dtype(dt) {
+            Some(dt) => dt,
+            None => {
+                bail!("unsupported 'value' data-type {dt:?} for {}", input.name)
             }
-            "Div" => {
-                let input0 = get(&node.input[0])?;
-                let input1 = get(&node.input[1])?;
-                let output = input0.broadcast_div(input1)?;
-                values.insert(node.output[0].clone(), output);
+        },
+        type_ => bail!("unsupported input type {type_:?}"),
+    };
+    match &tensor_type.shape {
+        None => return Ok(()),
+        Some(shape) => {
+            if shape.dim.len() != tensor.rank() {
+                bail!(
+                    "unexpected rank for {}, got {:?}, expected {:?}",
+                    input.name,
+                    shape.dim,
+                    tensor.shape()
+                )
             }
-            "Pow" => {
-                let input0 = get(&node.input[0])?;
-                let input1 = get(&node.input[1])?;
-                // HACK: current implementation of broadcast_pow cannot handle negative base,
-                // so we use powf where we can, which *does* correctly handle negative base.
-                if let Ok(exp) = to_scalar_flexible::<f64>(&input1.to_dtype(DType::F64)?) {
-                    let output = input0.powf(exp)?;
-                    values.insert(node.output[0].clone(), output);
-                } else {
-                    let output = input0.broadcast_pow(input1)?;
-                    values.insert(node.output[0].clone(), output);
+            for (idx, (d, &dim)) in shape.dim.iter().zip(tensor.dims().iter()).enumerate() {
+                match &d.value {
+                    Some(onnx::tensor_shape_proto::dimension::Value::DimValue(v)) => {
+                        if *v as usize != dim {
+                            bail!(
+                                "unexpected dim {idx} for {}, got {:?}, expected {:?}",
+                                input.name,
+                                shape.dim,
+                                tensor.shape()
+                            )
+                        }
+                    }
+                    // We do not check equality constraints for the DimParam dimensions for now.
+                    Some(onnx::tensor_shape_proto::dimension::Value::DimParam(_)) | None => (),
                 }
             }
-            "Exp" => {
-                let xs = get(&node.input[0])?;
-                let output = xs.exp()?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "Equal" => {
-                let input0 = get(&node.input[0])?;
-                let input1 = get(&node.input[1])?;
-                let output = input0.broadcast_eq(input1)?;
+        }
+    };
+    if dt != tensor.dtype() {
+        bail!(
+            "unexpected dtype for {}, got {:?}, expected {dt:?}",
+            input.name,
+            tensor.dtype()
+        )
+    }
+    Ok(())
+}
+
+/// Evaluates a single node, reading its inputs from `values` and `sequences` and inserting its
+/// outputs.
+pub(crate) fn eval_node(
+    node: &onnx::NodeProto,
+    values: &mut HashMap<String, Value>,
+    sequences: &mut HashMap<String, Vec<Value>>,
+    external_data: &ExternalData,
+) -> Result<()> {
+    let get = |input_name: &str| match values.get(input_name) {
+        Some(value) => Ok(value),
+        None => bail!("cannot find {input_name} for op '{}'", node.name),
+    };
+    let get_seq = |input_name: &str| match sequences.get(input_name) {
+        Some(sequence) => Ok(sequence),
+        None => bail!("cannot find sequence {input_name} for op '{}'", node.name),
+    };
+    let get_opt = |i: usize| {
+        node.input
+            .get(i)
+            .filter(|s: &&String| !s.is_empty())
+            .map(|s| get(s))
+    };
+
+    // TODO: Validate node.input for each operator.
+    match node.op_type.as_str() {
+        "Add" => {
+            let input0 = get(&node.input[0])?;
+            let input1 = get(&node.input[1])?;
+            let output = input0.broadcast_add(input1)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Sub" => {
+            let input0 = get(&node.input[0])?;
+            let input1 = get(&node.input[1])?;
+            let output = input0.broadcast_sub(input1)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Mul" => {
+            let input0 = get(&node.input[0])?;
+            let input1 = get(&node.input[1])?;
+            let output = input0.broadcast_mul(input1)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Div" => {
+            let input0 = get(&node.input[0])?;
+            let input1 = get(&node.input[1])?;
+            let output = input0.broadcast_div(input1)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Pow" => {
+            let input0 = get(&node.input[0])?;
+            let input1 = get(&node.input[1])?;
+            // HACK: current implementation of broadcast_pow cannot handle negative base,
+            // so we use powf where we can, which *does* correctly handle negative base.
+            if let Ok(exp) = to_scalar_flexible::<f64>(&input1.to_dtype(DType::F64)?) {
+                let output = input0.powf(exp)?;
                 values.insert(node.output[0].clone(), output);
-            }
-            "Not" => {
-                let xs = get(&node.input[0])?;
-                let xs = xs.eq(&xs.zeros_like()?)?;
-                values.insert(node.output[0].clone(), xs);
-            }
-            "MatMul" => {
-                let input0 = get(&node.input[0])?;
-                let input1 = get(&node.input[1])?;
-                let output = input0.broadcast_matmul(input1)?;
+            } else {
+                let output = input0.broadcast_pow(input1)?;
                 values.insert(node.output[0].clone(), output);
             }
-            "Reshape" => {
-                let input0 = get(&node.input[0])?;
-                let input1 = get(&node.input[1])?.to_vec1::<i64>()?;
-                // TODO: Check that there is at most a single -1 or 0, handle other neg values.
-                let mut other_than_minus1 = 1usize;
-                for &v in input1.iter() {
-                    if v != -1 && v != 0 {
-                        other_than_minus1 *= v as usize
-                    }
-                }
-                let input1 = input1
+        }
+        "Exp" => {
+            let xs = get(&node.input[0])?;
+            let output = xs.exp()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Equal" => {
+            let input0 = get(&node.input[0])?;
+            let input1 = get(&node.input[1])?;
+            let output = input0.broadcast_eq(input1)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Not" => {
+            let xs = get(&node.input[0])?;
+            let xs = xs.eq(&xs.zeros_like()?)?;
+            values.insert(node.output[0].clone(), xs);
+        }
+        "MatMul" => {
+            let input0 = get(&node.input[0])?;
+            let input1 = get(&node.input[1])?;
+            let output = input0.broadcast_matmul(input1)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Reshape" => {
+            let input0 = get(&node.input[0])?;
+            let input1 = get(&node.input[1])?.to_vec1::<i64>()?;
+            // TODO: Check that there is at most a single -1 or 0, handle other neg values.
+            let mut other_than_minus1 = 1usize;
+            for &v in input1.iter() {
+                if v != -1 && v != 0 {
+                    other_than_minus1 *= v as usize
+                }
+            }
+            let input1 = input1
+                .iter()
+                .enumerate()
+                .map(|(idx, &v)| match v {
+                    -1 => Ok(input0.elem_count() / other_than_minus1),
+                    0 => input0.dim(idx),
+                    _ => Ok(v as usize),
+                })
+                .collect::<Result<Vec<usize>>>()?;
+            let output = input0.reshape(input1)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "LogSoftmax" => {
+            let input = get(&node.input[0])?;
+            let output = match get_attr_opt::<i64>(node, "axis")? {
+                None => candle_nn::ops::softmax_last_dim(input)?,
+                Some(&axis) => {
+                    let axis = input.normalize_axis(axis)?;
+                    candle_nn::ops::log_softmax(input, axis)?
+                }
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        "Softmax" => {
+            let input = get(&node.input[0])?;
+            let output = match get_attr_opt::<i64>(node, "axis")? {
+                None => candle_nn::ops::softmax_last_dim(input)?,
+                Some(&axis) => {
+                    let axis = input.normalize_axis(axis)?;
+                    candle_nn::ops::softmax(input, axis)?
+                }
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        "Transpose" => {
+            let input = get(&node.input[0])?;
+            let output = match get_attr_opt::<[i64]>(node, "perm")? {
+                None => input.t()?,
+                Some(perm) => {
+                    let perm = perm.iter().map(|&v| v as usize).collect::<Vec<_>>();
+                    input.permute(perm)?.contiguous()?
+                }
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        "Dropout" => {
+            let input = get(&node.input[0])?;
+            // Do not apply dropout at the moment, consider that we're only doing inference.
+            values.insert(node.output[0].clone(), input.clone());
+        }
+        "MaxPool" => {
+            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#MaxPool
+            let dilations = get_attr_opt::<[i64]>(node, "dilations")?;
+            let kernel_shape = get_attr::<[i64]>(node, "kernel_shape")?;
+            let pads = get_attr_opt::<[i64]>(node, "pads")?;
+            let strides = get_attr_opt::<[i64]>(node, "strides")?;
+            let auto_pad = get_attr_opt::<str>(node, "auto_pad")?;
+            match auto_pad {
+                None | Some("NOTSET") => (),
+                Some(s) => bail!("unsupported auto_pad {s}"),
+            };
+            if let Some(d) = dilations {
+                if d.iter().any(|&v| v != 1) {
+                    bail!("MaxPool with dilation != 1, {dilations:?}")
+                }
+            }
+            if let Some(d) = pads {
+                if d.iter().any(|&v| v != 0) {
+                    bail!("MaxPool with pads != 0, {pads:?}")
+                }
+            }
+            let xs = get(&node.input[0])?;
+            let (k1, k2) = match kernel_shape {
+                [k1, k2] => (*k1 as usize, *k2 as usize),
+                _ => bail!("only 2d MaxPool is supported, kernel shape {kernel_shape:?}"),
+            };
+            let ys = match strides {
+                None => xs.max_pool2d((k1, k2))?,
+                Some([s1, s2]) => {
+                    xs.max_pool2d_with_stride((k1, k2), (*s1 as usize, *s2 as usize))?
+                }
+                Some(strides) => bail!("only 2d MaxPool is supported, strides {strides:?}"),
+            };
+            values.insert(node.output[0].clone(), ys);
+        }
+        "AveragePool" => {
+            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#AveragePool
+            let dilations = get_attr_opt::<[i64]>(node, "dilations")?;
+            let kernel_shape = get_attr::<[i64]>(node, "kernel_shape")?;
+            let pads = get_attr_opt::<[i64]>(node, "pads")?;
+            let strides = get_attr_opt::<[i64]>(node, "strides")?;
+            let auto_pad = get_attr_opt::<str>(node, "auto_pad")?;
+            match auto_pad {
+                None | Some("NOTSET") => (),
+                Some(s) => bail!("unsupported auto_pad {s}"),
+            };
+            if let Some(d) = dilations {
+                if d.iter().any(|&v| v != 1) {
+                    bail!("AvgPool with dilation != 1, {dilations:?}")
+                }
+            }
+            if let Some(d) = pads {
+                if d.iter().any(|&v| v != 0) {
+                    bail!("AvgPool with pads != 0, {pads:?}")
+                }
+            }
+            let xs = get(&node.input[0])?;
+            let (k1, k2) = match kernel_shape {
+                [k1, k2] => (*k1 as usize, *k2 as usize),
+                _ => bail!("only 2d AvgPool is supported, kernel shape {kernel_shape:?}"),
+            };
+            let ys = match strides {
+                None => xs.avg_pool2d((k1, k2))?,
+                Some([s1, s2]) => {
+                    xs.avg_pool2d_with_stride((k1, k2), (*s1 as usize, *s2 as usize))?
+                }
+                Some(strides) => bail!("only 2d AvgPool is supported, strides {strides:?}"),
+            };
+            values.insert(node.output[0].clone(), ys);
+        }
+        "BatchNormalization" => {
+            let training_mode = get_attr_opt::<i64>(node, "training_mode")?;
+            if training_mode.copied().unwrap_or(0) != 0 {
+                bail!("training mode is not supported for BatchNorm")
+            }
+            let eps = get_attr_opt::<f32>(node, "epsilon")?
+                .copied()
+                .unwrap_or(1e-5);
+            let xs = get(&node.input[0])?;
+            let weight = get(&node.input[1])?;
+            let bias = get(&node.input[2])?;
+            let running_mean = get(&node.input[3])?;
+            let running_var = get(&node.input[4])?;
+            let target_shape: Vec<usize> = xs
+                .dims()
+                .iter()
+                .enumerate()
+                .map(|(idx, v)| if idx == 1 { *v } else { 1 })
+                .collect();
+            let target_shape = target_shape.as_slice();
+            let xs = xs
+                .broadcast_sub(&running_mean.reshape(target_shape)?)?
+                .broadcast_div(&(running_var.reshape(target_shape)? + eps as f64)?.sqrt()?)?;
+            let weight = weight.reshape(target_shape)?;
+            let bias = bias.reshape(target_shape)?;
+            let xs = xs.broadcast_mul(&weight)?.broadcast_add(&bias)?;
+            values.insert(node.output[0].clone(), xs);
+        }
+        "Squeeze" => {
+            let xs = get(&node.input[0])?;
+            let mut axes = if node.input.len() <= 1 {
+                // contract all the dimensions with size 1 except the batch dim.
+                xs.dims()
                     .iter()
                     .enumerate()
-                    .map(|(idx, &v)| match v {
-                        -1 => Ok(input0.elem_count() / other_than_minus1),
-                        0 => input0.dim(idx),
-                        _ => Ok(v as usize),
-                    })
-                    .collect::<Result<Vec<usize>>>()?;
-                let output = input0.reshape(input1)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "LogSoftmax" => {
-                let input = get(&node.input[0])?;
-                let output = match get_attr_opt::<i64>(node, "axis")? {
-                    None => candle_nn::ops::softmax_last_dim(input)?,
-                    Some(&axis) => {
-                        let axis = input.normalize_axis(axis)?;
-                        candle_nn::ops::log_softmax(input, axis)?
-                    }
-                };
-                values.insert(node.output[0].clone(), output);
-            }
-            "Softmax" => {
-                let input = get(&node.input[0])?;
-                let output = match get_attr_opt::<i64>(node, "axis")? {
-                    None => candle_nn::ops::softmax_last_dim(input)?,
-                    Some(&axis) => {
-                        let axis = input.normalize_axis(axis)?;
-                        candle_nn::ops::softmax(input, axis)?
-                    }
-                };
-                values.insert(node.output[0].clone(), output);
-            }
-            "Transpose" => {
-                let input = get(&node.input[0])?;
-                let output = match get_attr_opt::<[i64]>(node, "perm")? {
-                    None => input.t()?,
-                    Some(perm) => {
-                        let perm = perm.iter().map(|&v| v as usize).collect::<Vec<_>>();
-                        input.permute(perm)?.contiguous()?
-                    }
-                };
-                values.insert(node.output[0].clone(), output);
-            }
-            "Dropout" => {
-                let input = get(&node.input[0])?;
-                // Do not apply dropout at the moment, consider that we're only doing inference.
-                values.insert(node.output[0].clone(), input.clone());
-            }
-            "MaxPool" => {
-                // https://github.com/onnx/onnx/blob/main/docs/Operators.md#MaxPool
-                let dilations = get_attr_opt::<[i64]>(node, "dilations")?;
-                let kernel_shape = get_attr::<[i64]>(node, "kernel_shape")?;
-                let pads = get_attr_opt::<[i64]>(node, "pads")?;
-                let strides = get_attr_opt::<[i64]>(node, "strides")?;
-                let auto_pad = get_attr_opt::<str>(node, "auto_pad")?;
-                match auto_pad {
-                    None | Some("NOTSET") => (),
-                    Some(s) => bail!("unsupported auto_pad {s}"),
-                };
-                if let Some(d) = dilations {
-                    if d.iter().any(|&v| v != 1) {
-                        bail!("MaxPool with dilation != 1, {dilations:?}")
-                    }
-                }
-                if let Some(d) = pads {
-                    if d.iter().any(|&v| v != 0) {
-                        bail!("MaxPool with pads != 0, {pads:?}")
-                    }
-                }
-                let xs = get(&node.input[0])?;
-                let (k1, k2) = match kernel_shape {
-                    [k1, k2] => (*k1 as usize, *k2 as usize),
-                    _ => bail!("only 2d MaxPool is supported, kernel shape {kernel_shape:?}"),
-                };
-                let ys = match strides {
-                    None => xs.max_pool2d((k1, k2))?,
-                    Some([s1, s2]) => {
-                        xs.max_pool2d_with_stride((k1, k2), (*s1 as usize, *s2 as usize))?
-                    }
-                    Some(strides) => bail!("only 2d MaxPool is supported, strides {strides:?}"),
-                };
-                values.insert(node.output[0].clone(), ys);
-            }
-            "AveragePool" => {
-                // https://github.com/onnx/onnx/blob/main/docs/Operators.md#AveragePool
-                let dilations = get_attr_opt::<[i64]>(node, "dilations")?;
-                let kernel_shape = get_attr::<[i64]>(node, "kernel_shape")?;
-                let pads = get_attr_opt::<[i64]>(node, "pads")?;
-                let strides = get_attr_opt::<[i64]>(node, "strides")?;
-                let auto_pad = get_attr_opt::<str>(node, "auto_pad")?;
-                match auto_pad {
-                    None | Some("NOTSET") => (),
-                    Some(s) => bail!("unsupported auto_pad {s}"),
-                };
-                if let Some(d) = dilations {
-                    if d.iter().any(|&v| v != 1) {
-                        bail!("AvgPool with dilation != 1, {dilations:?}")
+                    .flat_map(|(idx, &s)| if s == 1 && idx > 0 { Some(idx) } else { None })
+                    .collect()
+            } else {
+                get(&node.input[1])?
+                    .to_vec1::<i64>()?
+                    .iter()
+                    .map(|&i| xs.normalize_axis(i))
+                    .collect::<Result<Vec<_>>>()?
+            };
+            axes.sort();
+            let mut xs = xs.clone();
+            for &axis in axes.iter().rev() {
+                xs = xs.squeeze(axis)?
+            }
+            values.insert(node.output[0].clone(), xs);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#ConstantOfShape
+        "ConstantOfShape" => {
+            let input = get(&node.input[0])?;
+            let value = get_attr_opt_owned::<Tensor>(node, "value")?.unwrap_or(Tensor::zeros(
+                (),
+                DType::F32,
+                &Device::Cpu,
+            )?);
+
+            let shape_vec: Vec<usize> = input
+                .to_vec1::<i64>()?
+                .iter()
+                .map(|&x| x as usize)
+                .collect();
+
+            let xs =
+                Tensor::ones(shape_vec, value.dtype(), input.device())?.broadcast_mul(&value)?;
+            values.insert(node.output[0].clone(), xs);
+        }
+        "Unsqueeze" => {
+            let xs = get(&node.input[0])?;
+            let axes = match get_attr_opt::<[i64]>(node, "axes")? {
+                Some(axis) => axis.to_vec(),
+                None => get(&node.input[1])?.to_vec1::<i64>()?,
+            };
+            let mut axes = axes
+                .iter()
+                .map(|&i| {
+                    if i == xs.rank() as i64 {
+                        Ok(xs.rank())
+                    } else if i < 0 {
+                        // normalize_axis doesn't work correctly here
+                        // because we actually want normalized with respect
+                        // to the final size, not the current (off by one)
+                        Ok(xs.rank() - (-i as usize) + 1)
+                    } else {
+                        xs.normalize_axis(i)
                     }
-                }
-                if let Some(d) = pads {
-                    if d.iter().any(|&v| v != 0) {
-                        bail!("AvgPool with pads != 0, {pads:?}")
+                })
+                .collect::<Result<Vec<_>>>()?;
+            axes.sort();
+            let mut xs = xs.clone();
+            for &axis in axes.iter().rev() {
+                xs = xs.unsqueeze(axis)?
+            }
+            values.insert(node.output[0].clone(), xs);
+        }
+        "Clip" => {
+            let xs = get(&node.input[0])?;
+            let xs = if let Some(mins) = get_opt(1) {
+                xs.broadcast_maximum(mins?)?
+            } else {
+                xs.clone()
+            };
+            let xs = if let Some(maxs) = get_opt(2) {
+                xs.broadcast_minimum(maxs?)?
+            } else {
+                xs.clone()
+            };
+            values.insert(node.output[0].clone(), xs);
+        }
+        "Gather" => {
+            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Gather
+            let xs = get(&node.input[0])?;
+            let indices = get(&node.input[1])?;
+            let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(0);
+            let axis = xs.normalize_axis(axis)?;
+
+            // index_select does not support negative indices, so normalize them
+            // to positive indices.
+            let indices = &{
+                let zeros = Tensor::zeros(indices.shape(), indices.dtype(), indices.device())?;
+                let max = Tensor::new(xs.dims()[axis] as i64, indices.device())?
+                    .to_dtype(indices.dtype())?;
+                let mask = indices.lt(&zeros)?;
+                mask.to_dtype(indices.dtype())?
+                    .broadcast_mul(&max)?
+                    .add(indices)?
+            };
+
+            // In Pytorch or Numpy this can be done by indexing the xs tensor using the indices
+            // tensor directly, but candle does not support tensor indexing at the moment, so
+            // some workarounds must be done.
+            let xs = match indices.dims() {
+                [] => {
+                    let index = indices.to_vec0::<i64>()? as usize;
+                    xs.narrow(axis, index, 1)?.squeeze(axis)?
+                }
+                [_] => xs.index_select(indices, axis)?,
+                [first, _] => {
+                    let mut v = Vec::with_capacity(*first);
+                    for i in 0..*first {
+                        v.push(xs.index_select(&indices.get(i)?, axis)?)
                     }
+                    Tensor::stack(&v, axis)?
                 }
-                let xs = get(&node.input[0])?;
-                let (k1, k2) = match kernel_shape {
-                    [k1, k2] => (*k1 as usize, *k2 as usize),
-                    _ => bail!("only 2d AvgPool is supported, kernel shape {kernel_shape:?}"),
-                };
-                let ys = match strides {
-                    None => xs.avg_pool2d((k1, k2))?,
-                    Some([s1, s2]) => {
-                        xs.avg_pool2d_with_stride((k1, k2), (*s1 as usize, *s2 as usize))?
-                    }
-                    Some(strides) => bail!("only 2d AvgPool is supported, strides {strides:?}"),
-                };
-                values.insert(node.output[0].clone(), ys);
-            }
-            "BatchNormalization" => {
-                let training_mode = get_attr_opt::<i64>(node, "training_mode")?;
-                if training_mode.copied().unwrap_or(0) != 0 {
-                    bail!("training mode is not supported for BatchNorm")
-                }
-                let eps = get_attr_opt::<f32>(node, "epsilon")?
-                    .copied()
-                    .unwrap_or(1e-5);
-                let xs = get(&node.input[0])?;
-                let weight = get(&node.input[1])?;
-                let bias = get(&node.input[2])?;
-                let running_mean = get(&node.input[3])?;
-                let running_var = get(&node.input[4])?;
-                let target_shape: Vec<usize> = xs
-                    .dims()
-                    .iter()
-                    .enumerate()
-                    .map(|(idx, v)| if idx == 1 { *v } else { 1 })
-                    .collect();
-                let target_shape = target_shape.as_slice();
-                let xs = xs
-                    .broadcast_sub(&running_mean.reshape(target_shape)?)?
-                    .broadcast_div(&(running_var.reshape(target_shape)? + eps as f64)?.sqrt()?)?;
-                let weight = weight.reshape(target_shape)?;
-                let bias = bias.reshape(target_shape)?;
-                let xs = xs.broadcast_mul(&weight)?.broadcast_add(&bias)?;
-                values.insert(node.output[0].clone(), xs);
-            }
-            "Squeeze" => {
-                let xs = get(&node.input[0])?;
-                let mut axes = if node.input.len() <= 1 {
-                    // contract all the dimensions with size 1 except the batch dim.
-                    xs.dims()
-                        .iter()
-                        .enumerate()
-                        .flat_map(|(idx, &s)| if s == 1 && idx > 0 { Some(idx) } else { None })
-                        .collect()
-                } else {
-                    get(&node.input[1])?
-                        .to_vec1::<i64>()?
-                        .iter()
-                        .map(|&i| xs.normalize_axis(i))
-                        .collect::<Result<Vec<_>>>()?
-                };
-                axes.sort();
-                let mut xs = xs.clone();
-                for &axis in axes.iter().rev() {
-                    xs = xs.squeeze(axis)?
+                _ => {
+                    // TODO: Provide an op to handle the ONNX generalized gather op ideally in a
+                    // differentiable way.
+                    todo!("implement gather for {xs:?} {indices:?} axis {axis}")
                 }
-                values.insert(node.output[0].clone(), xs);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#ConstantOfShape
-            "ConstantOfShape" => {
-                let input = get(&node.input[0])?;
-                let value = get_attr_opt_owned::<Tensor>(node, "value")?.unwrap_or(Tensor::zeros(
-                    (),
-                    DType::F32,
-                    &Device::Cpu,
-                )?);
+            };
+            values.insert(node.output[0].clone(), xs);
+        }
+        // https://onnx.ai/onnx/operators/onnx__GatherElements.html#gatherelements
+        // A Note to fellow lurkers:
+        // The numpy based `gather_elements` implementation in `onnx` tests [here](https://github.com/onnx/onnx/blob/main/onnx/backend/test/case/node/gatherelements.py)
+        // and examples is incorrect.
+        // Use `torch.gather` for the validating/ verifying against the proper behaviour
+        "GatherElements" => {
+            let data = get(&node.input[0])?;
+            let indices = get(&node.input[1])?;
 
-                let shape_vec: Vec<usize> = input
-                    .to_vec1::<i64>()?
-                    .iter()
-                    .map(|&x| x as usize)
-                    .collect();
-
-                let xs = Tensor::ones(shape_vec, value.dtype(), input.device())?
-                    .broadcast_mul(&value)?;
-                values.insert(node.output[0].clone(), xs);
-            }
-            "Unsqueeze" => {
-                let xs = get(&node.input[0])?;
-                let axes = match get_attr_opt::<[i64]>(node, "axes")? {
-                    Some(axis) => axis.to_vec(),
-                    None => get(&node.input[1])?.to_vec1::<i64>()?,
-                };
-                let mut axes = axes
-                    .iter()
-                    .map(|&i| {
-                        if i == xs.rank() as i64 {
-                            Ok(xs.rank())
-                        } else if i < 0 {
-                            // normalize_axis doesn't work correctly here
-                            // because we actually want normalized with respect
-                            // to the final size, not the current (off by one)
-                            Ok(xs.rank() - (-i as usize) + 1)
-                        } else {
-                            xs.normalize_axis(i)
-                        }
-                    })
-                    .collect::<Result<Vec<_>>>()?;
-                axes.sort();
-                let mut xs = xs.clone();
-                for &axis in axes.iter().rev() {
-                    xs = xs.unsqueeze(axis)?
-                }
-                values.insert(node.output[0].clone(), xs);
+            let rank = data.rank();
+            if rank != indices.rank() {
+                bail!("indices must have same rank as input data. Data rank [{}] != indices rank [{}]", data.rank(), indices.rank());
             }
-            "Clip" => {
-                let xs = get(&node.input[0])?;
-                let xs = if let Some(mins) = get_opt(1) {
-                    xs.broadcast_maximum(mins?)?
-                } else {
-                    xs.clone()
-                };
-                let xs = if let Some(maxs) = get_opt(2) {
-                    xs.broadcast_minimum(maxs?)?
-                } else {
-                    xs.clone()
-                };
-                values.insert(node.output[0].clone(), xs);
-            }
-            "Gather" => {
-                // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Gather
-                let xs = get(&node.input[0])?;
-                let indices = get(&node.input[1])?;
-                let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(0);
-                let axis = xs.normalize_axis(axis)?;
-
-                // index_select does not support negative indices, so normalize them
-                // to positive indices.
-                let indices = &{
-                    let zeros = Tensor::zeros(indices.shape(), indices.dtype(), indices.device())?;
-                    let max = Tensor::new(xs.dims()[axis] as i64, indices.device())?
-                        .to_dtype(indices.dtype())?;
-                    let mask = indices.lt(&zeros)?;
-                    mask.to_dtype(indices.dtype())?
-                        .broadcast_mul(&max)?
-                        .add(indices)?
-                };
 
-                // In Pytorch or Numpy this can be done by indexing the xs tensor using the indices
-                // tensor directly, but candle does not support tensor indexing at the moment, so
-                // some workarounds must be done.
-                let xs = match indices.dims() {
-                    [] => {
-                        let index = indices.to_vec0::<i64>()? as usize;
-                        xs.narrow(axis, index, 1)?.squeeze(axis)?
-                    }
-                    [_] => xs.index_select(indices, axis)?,
-                    [first, _] => {
-                        let mut v = Vec::with_capacity(*first);
-                        for i in 0..*first {
-                            v.push(xs.index_select(&indices.get(i)?, axis)?)
-                        }
-                        Tensor::stack(&v, axis)?
-                    }
-                    _ => {
-                        // TODO: Provide an op to handle the ONNX generalized gather op ideally in a
-                        // differentiable way.
-                        todo!("implement gather for {xs:?} {indices:?} axis {axis}")
-                    }
-                };
-                values.insert(node.output[0].clone(), xs);
-            }
-            // https://onnx.ai/onnx/operators/onnx__GatherElements.html#gatherelements
-            // A Note to fellow lurkers:
-            // The numpy based `gather_elements` implementation in `onnx` tests [here](https://github.com/onnx/onnx/blob/main/onnx/backend/test/case/node/gatherelements.py)
-            // and examples is incorrect.
-            // Use `torch.gather` for the validating/ verifying against the proper behaviour
-            "GatherElements" => {
-                let data = get(&node.input[0])?;
-                let indices = get(&node.input[1])?;
+            let axis = {
+                let axis_i64 = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(0);
+                let axis = data.normalize_axis(axis_i64)?;
 
-                let rank = data.rank();
-                if rank != indices.rank() {
-                    bail!("indices must have same rank as input data. Data rank [{}] != indices rank [{}]", data.rank(), indices.rank());
+                if axis >= rank {
+                    bail!(
+                        "axis ({}) out of accepted range [-rank, rank-1] which was [-{rank}, {}]",
+                        axis_i64,
+                        rank - 1
+                    )
                 }
 
-                let axis = {
-                    let axis_i64 = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(0);
-                    let axis = data.normalize_axis(axis_i64)?;
-
-                    if axis >= rank {
-                        bail!(
-                            "axis ({}) out of accepted range [-rank, rank-1] which was [-{rank}, {}]",
-                            axis_i64,
-                            rank - 1
-                        )
-                    }
+                axis
+            };
 
-                    axis
-                };
+            // index_select does not support negative indices, so normalize them
+            // to positive indices.
+            let indices = &{
+                let zeros = Tensor::zeros(indices.shape(), indices.dtype(), indices.device())?;
+                let max = Tensor::new(data.dims()[axis] as i64, indices.device())?
+                    .to_dtype(indices.dtype())?;
+                let mask = indices.lt(&zeros)?;
+                mask.to_dtype(indices.dtype())?
+                    .broadcast_mul(&max)?
+                    .add(indices)?
+            };
 
-                // index_select does not support negative indices, so normalize them
-                // to positive indices.
-                let indices = &{
-                    let zeros = Tensor::zeros(indices.shape(), indices.dtype(), indices.device())?;
-                    let max = Tensor::new(data.dims()[axis] as i64, indices.device())?
-                        .to_dtype(indices.dtype())?;
-                    let mask = indices.lt(&zeros)?;
-                    mask.to_dtype(indices.dtype())?
-                        .broadcast_mul(&max)?
-                        .add(indices)?
+            values.insert(node.output[0].clone(), data.gather(indices, axis)?);
+        }
+        "Shape" => {
+            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Shape
+            let xs = get(&node.input[0])?;
+            let start = get_attr_opt::<i64>(node, "start")?.copied().unwrap_or(0);
+            let end = get_attr_opt::<i64>(node, "end")?.copied().unwrap_or(-1);
+            let start = xs.normalize_axis(start)?;
+            let end = xs.normalize_axis(end)?;
+            let mut dims = vec![];
+            for idx in start..=end {
+                dims.push(xs.dim(idx)? as i64)
+            }
+            let dims = Tensor::from_vec(dims, xs.rank(), xs.device())?;
+            values.insert(node.output[0].clone(), dims);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Size
+        "Size" => {
+            let data = get(&node.input[0])?;
+            let size: usize = data.dims().iter().product();
+            let output = Tensor::from_slice(&[size as i64], (), data.device())?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Sqrt
+        "Sqrt" => {
+            let xs = get(&node.input[0])?;
+            let output = xs.sqrt()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Range
+        "Range" => {
+            let start = get(&node.input[0])?;
+            let limit = get(&node.input[1])?;
+            let delta = get(&node.input[2])?;
+
+            macro_rules! arange_step {
+                ($t: ty) => {
+                    Tensor::arange_step(
+                        to_vec0_flexible::<$t>(start)?,
+                        to_vec0_flexible::<$t>(limit)?,
+                        to_vec0_flexible::<$t>(delta)?,
+                        &Device::Cpu,
+                    )?
                 };
-
-                values.insert(node.output[0].clone(), data.gather(indices, axis)?);
-            }
-            "Shape" => {
-                // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Shape
-                let xs = get(&node.input[0])?;
-                let start = get_attr_opt::<i64>(node, "start")?.copied().unwrap_or(0);
-                let end = get_attr_opt::<i64>(node, "end")?.copied().unwrap_or(-1);
-                let start = xs.normalize_axis(start)?;
-                let end = xs.normalize_axis(end)?;
-                let mut dims = vec![];
-                for idx in start..=end {
-                    dims.push(xs.dim(idx)? as i64)
-                }
-                let dims = Tensor::from_vec(dims, xs.rank(), xs.device())?;
-                values.insert(node.output[0].clone(), dims);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Size
-            "Size" => {
-                let data = get(&node.input[0])?;
-                let size: usize = data.dims().iter().product();
-                let output = Tensor::from_slice(&[size as i64], (), data.device())?;
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Sqrt
-            "Sqrt" => {
-                let xs = get(&node.input[0])?;
-                let output = xs.sqrt()?;
-                values.insert(node.output[0].clone(), output);
             }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Range
-            "Range" => {
-                let start = get(&node.input[0])?;
-                let limit = get(&node.input[1])?;
-                let delta = get(&node.input[2])?;
-
-                macro_rules! arange_step {
-                    ($t: ty) => {
-                        Tensor::arange_step(
-                            to_vec0_flexible::<$t>(start)?,
-                            to_vec0_flexible::<$t>(limit)?,
-                            to_vec0_flexible::<$t>(delta)?,
-                            &Device::Cpu,
-                        )?
-                    };
-                }
 
-                let output = match start.dtype() {
-                    DType::U8 => arange_step!(u8),
-                    DType::U32 => arange_step!(u32),
-                    DType::I64 => arange_step!(i64),
-                    DType::BF16 => arange_step!(f32),
-                    DType::F16 => arange_step!(f32),
-                    DType::F32 => arange_step!(f32),
-                    DType::F64 => arange_step!(f64),
-                    DType::F8E4M3 => arange_step!(f32),
-                    DType::I32
-                    | DType::I16
-                    | DType::F6E2M3
-                    | DType::F6E3M2
-                    | DType::F4
-                    | DType::F8E8M0 => {
-                        bail!("unsupported Range type i32/i16/f6e2m3/f6e3m2/f4/f8e8m0")
-                    }
-                };
+            let output = match start.dtype() {
+                DType::U8 => arange_step!(u8),
+                DType::U32 => arange_step!(u32),
+                DType::I64 => arange_step!(i64),
+                DType::BF16 => arange_step!(f32),
+                DType::F16 => arange_step!(f32),
+                DType::F32 => arange_step!(f32),
+                DType::F64 => arange_step!(f64),
+                DType::F8E4M3 => arange_step!(f32),
+                DType::F8E5M2 => arange_step!(f32),
+                DType::I32
+                | DType::I16
+                | DType::F6E2M3
+                | DType::F6E3M2
+                | DType::F4
+                | DType::F8E8M0 => {
+                    bail!("unsupported Range type i32/i16/f6e2m3/f6e3m2/f4/f8e8m0")
+                }
+            };
+
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Greater
+        "Greater" => {
+            let a = get(&node.input[0])?;
+            let b = get(&node.input[1])?;
 
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Greater
-            "Greater" => {
-                let a = get(&node.input[0])?;
-                let b = get(&node.input[1])?;
+            let output = a.broadcast_gt(b)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Less
+        "Less" => {
+            let a = get(&node.input[0])?;
+            let b = get(&node.input[1])?;
 
-                let output = a.broadcast_gt(b)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Less
-            "Less" => {
-                let a = get(&node.input[0])?;
-                let b = get(&node.input[1])?;
+            let output = a.broadcast_lt(b)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#LessOrEqual
+        "LessOrEqual" => {
+            let a = get(&node.input[0])?;
+            let b = get(&node.input[1])?;
 
-                let output = a.broadcast_lt(b)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#LessOrEqual
-            "LessOrEqual" => {
-                let a = get(&node.input[0])?;
-                let b = get(&node.input[1])?;
+            let output = a.broadcast_le(b)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#GreaterOrEqual
+        "GreaterOrEqual" => {
+            let a = get(&node.input[0])?;
+            let b = get(&node.input[1])?;
 
-                let output = a.broadcast_le(b)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#GreaterOrEqual
-            "GreaterOrEqual" => {
-                let a = get(&node.input[0])?;
-                let b = get(&node.input[1])?;
+            let output = a.broadcast_ge(b)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Log
+        "Log" => {
+            let a = get(&node.input[0])?;
 
-                let output = a.broadcast_ge(b)?;
-                values.insert(node.output[0].clone(), output);
+            let output = a.log()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Min
+        "Min" => {
+            let mut output = get(&node.input[0])?.clone();
+            for input in node.input.iter() {
+                let input = get(input)?;
+                output = output.broadcast_minimum(input)?
             }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Log
-            "Log" => {
-                let a = get(&node.input[0])?;
 
-                let output = a.log()?;
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Min
-            "Min" => {
-                let mut output = get(&node.input[0])?.clone();
-                for input in node.input.iter() {
-                    let input = get(input)?;
-                    output = output.broadcast_minimum(input)?
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Where
+        "Where" => {
+            let cond = get(&node.input[0])?;
+            let a = get(&node.input[1])?;
+            let b = get(&node.input[2])?;
+
+            // where_cond requires that all inputs are the same shape.
+            // In contrast, the Where op in ONNX only requires that they are broadcastable.
+            let shape = broadcast_shape_from_many(&[cond.dims(), a.dims(), b.dims()])?;
+            let cond = cond.broadcast_as(shape.clone())?;
+            let a = a.broadcast_as(shape.clone())?;
+            let b = b.broadcast_as(shape)?;
+            let output = cond.where_cond(&a, &b)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Conv" => {
+            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Conv
+            let xs = get(&node.input[0])?;
+            let ws = get(&node.input[1])?;
+            let ys = conv(node, xs, ws)?;
+            let ys = if node.input.len() > 2 {
+                let bs = get(&node.input[2])?;
+                let mut bs_shape = vec![1; ys.rank()];
+                bs_shape[1] = bs.elem_count();
+                ys.broadcast_add(&bs.reshape(bs_shape)?)?
+            } else {
+                ys
+            };
+            values.insert(node.output[0].clone(), ys);
+        }
+        "Concat" => {
+            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Concat
+            let inputs = node
+                .input
+                .iter()
+                .map(|n| Ok(get(n.as_str())?.clone()))
+                .collect::<Result<Vec<Value>>>()?;
+            let axis: i64 = *get_attr(node, "axis")?;
+            if inputs.is_empty() {
+                bail!("empty concat")
+            };
+            // Find minimum rank among inputs and squeeze trailing singleton dims to match
+            let min_rank = inputs.iter().map(|t| t.rank()).min().unwrap();
+            let inputs: Vec<_> = inputs
+                .into_iter()
+                .map(|t| {
+                    let mut t = t;
+                    while t.rank() > min_rank {
+                        let last_dim = t.rank() - 1;
+                        if t.dims()[last_dim] == 1 {
+                            t = t.squeeze(last_dim).unwrap_or(t);
+                        } else {
+                            break;
+                        }
+                    }
+                    t
+                })
+                .collect();
+            let axis = inputs[0].normalize_axis(axis)?;
+            let output = Tensor::cat(&inputs, axis).map_err(|e| {
+                let shapes: Vec<_> = inputs.iter().map(|t| format!("{:?}", t.dims())).collect();
+                candle::Error::Msg(format!(
+                    "Concat failed for node '{}': {} (input shapes: {:?})",
+                    node.name, e, shapes
+                ))
+            })?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Abs" => {
+            let input = get(&node.input[0])?;
+            let output = input.abs()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Cos" => {
+            let input = get(&node.input[0])?;
+            let output = input.cos()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Sin" => {
+            let input = get(&node.input[0])?;
+            let output = input.sin()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Neg" => {
+            let input = get(&node.input[0])?;
+            // neg() not implemented for i64, work around with multiply by -1
+            let output = if input.dtype() == DType::I64 {
+                let minus_one =
+                    Tensor::new(&[-1i64], input.device())?.broadcast_as(input.shape())?;
+                input.mul(&minus_one)?
+            } else {
+                input.neg()?
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        "Erf" => {
+            let input = get(&node.input[0])?;
+            let output = input.erf()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Tanh" => {
+            let input = get(&node.input[0])?;
+            let output = input.tanh()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Sigmoid" => {
+            let input = get(&node.input[0])?;
+            let output = candle_nn::ops::sigmoid(input)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Gelu" => {
+            let input = get(&node.input[0])?;
+            let output = match get_attr_opt::<str>(node, "approximate")?.unwrap_or("none") {
+                "none" => input.gelu_erf()?,
+                "tanh" => input.gelu()?,
+                approximate => bail!("unsupported approximate {approximate} for gelu"),
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        "Relu" => {
+            let input = get(&node.input[0])?;
+            let output = input.relu()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "PRelu" => {
+            // https://onnx.ai/onnx/operators/onnx__PRelu.html
+            let input = get(&node.input[0])?;
+            let slope = get(&node.input[1])?;
+
+            let output = PReLU::new(slope.clone(), false).forward(input)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Ceil" => {
+            let input = get(&node.input[0])?;
+            let output = input.ceil()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "Floor" => {
+            let input = get(&node.input[0])?;
+            let output = input.floor()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Constant
+        "Constant" => {
+            let value = match node.attribute.iter().find(|attr| attr.name == "value") {
+                None => {
+                    // TODO: support sparse_value etc.
+                    bail!("cannot find 'value' attr in 'Constant' for {}", node.name)
+                }
+                Some(value) => value,
+            };
+            let output = match value.r#type() {
+                AttributeType::Tensor => {
+                    let t = value.t.as_ref().unwrap();
+                    get_tensor_(t, &node.name, external_data)?
                 }
+                rtype => bail!("unsupported 'value' type {rtype:?} for {}", node.name),
+            };
 
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Where
-            "Where" => {
-                let cond = get(&node.input[0])?;
-                let a = get(&node.input[1])?;
-                let b = get(&node.input[2])?;
-
-                // where_cond requires that all inputs are the same shape.
-                // In contrast, the Where op in ONNX only requires that they are broadcastable.
-                let shape = broadcast_shape_from_many(&[cond.dims(), a.dims(), b.dims()])?;
-                let cond = cond.broadcast_as(shape.clone())?;
-                let a = a.broadcast_as(shape.clone())?;
-                let b = b.broadcast_as(shape)?;
-                let output = cond.where_cond(&a, &b)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "Conv" => {
-                // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Conv
-                let dilations = get_attr_opt::<[i64]>(node, "dilations")?;
-                let groups = get_attr_opt::<i64>(node, "group")?.copied().unwrap_or(1);
-                let _kernel_shape = get_attr_opt::<[i64]>(node, "kernel_shape")?;
-                let pads = get_attr_opt::<[i64]>(node, "pads")?;
-                let strides = get_attr_opt::<[i64]>(node, "strides")?;
-                let auto_pad = get_attr_opt::<str>(node, "auto_pad")?;
-                match auto_pad {
-                    None | Some("NOTSET") => (),
-                    Some(s) => bail!("unsupported auto_pad {s}"),
-                };
-                let xs = get(&node.input[0])?;
-                let ws = get(&node.input[1])?;
-                let ys = match ws.rank() {
-                    3 => {
-                        let (pads, xs) = match pads {
-                            None => (0, xs.clone()),
-                            Some([p]) => (*p as usize, xs.clone()),
-                            Some([p1, p2]) => {
-                                if p1 != p2 {
-                                    (0usize, xs.pad_with_zeros(2, *p1 as usize, *p2 as usize)?)
-                                } else {
-                                    (*p1 as usize, xs.clone())
-                                }
-                            }
-                            Some(pads) => {
-                                bail!("more pads than expected in conv1d {pads:?} {}", node.name)
-                            }
-                        };
-                        let strides = match strides {
-                            None => 1,
-                            Some([p]) => *p as usize,
-                            Some(s) => {
-                                bail!("more strides than expected in conv1d {s:?} {}", node.name)
-                            }
-                        };
-                        let dilations = match dilations {
-                            None => 1,
-                            Some([p]) => *p as usize,
-                            Some(s) => {
-                                bail!("more dilations than expected in conv1d {s:?} {}", node.name)
-                            }
-                        };
-                        xs.conv1d(ws, pads, strides, dilations, groups as usize)?
-                    }
-                    4 => {
-                        let (pads, xs) = match pads {
-                            None => (0, xs.clone()),
-                            Some([p]) => (*p as usize, xs.clone()),
-                            Some(&[p1, p2, p3, p4]) => {
-                                let p1 = p1 as usize;
-                                let p2 = p2 as usize;
-                                let p3 = p3 as usize;
-                                let p4 = p4 as usize;
-                                if p1 != p2 || p1 != p3 || p1 != p4 {
-                                    (0, xs.pad_with_zeros(2, p1, p3)?.pad_with_zeros(3, p2, p4)?)
-                                } else {
-                                    (p1, xs.clone())
-                                }
-                            }
-                            Some(pads) => {
-                                bail!("more pads than expected in conv2d {pads:?} {}", node.name)
-                            }
-                        };
-                        let strides = match strides {
-                            None => 1,
-                            Some([p]) => *p as usize,
-                            Some([p1, p2]) => {
-                                if p1 != p2 {
-                                    bail!(
-                                        "strides have to be the same on both axis {pads:?} {}",
-                                        node.name
-                                    )
-                                }
-                                *p1 as usize
-                            }
-                            Some(s) => {
-                                bail!("more strides than expected in conv2d {s:?} {}", node.name)
-                            }
-                        };
-                        let dilations = match dilations {
-                            None => 1,
-                            Some([p]) => *p as usize,
-                            Some([p1, p2]) => {
-                                if p1 != p2 {
-                                    bail!(
-                                        "dilations have to be the same on both axis {pads:?} {}",
-                                        node.name
-                                    )
-                                }
-                                *p1 as usize
-                            }
-                            Some(s) => {
-                                bail!("more dilations than expected in conv2d {s:?} {}", node.name)
-                            }
-                        };
-                        xs.conv2d(ws, pads, strides, dilations, groups as usize)?
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Cast
+        "Cast" => {
+            let input = get(&node.input[0])?;
+            let dt: i64 = *get_attr(node, "to")?;
+            let dtype = match DataType::try_from(dt as i32) {
+                Ok(DataType::Int32) => DType::I64,
+                Ok(dt) => match dtype(dt) {
+                    Some(dt) => dt,
+                    None => {
+                        bail!("unsupported 'to' value {dt:?} for cast {}", node.name)
                     }
-                    rank => bail!(
-                        "unsupported rank for weight matrix {rank} in conv {}",
-                        node.name
-                    ),
-                };
-                let ys = if node.input.len() > 2 {
-                    let bs = get(&node.input[2])?;
-                    let mut bs_shape = vec![1; ys.rank()];
-                    bs_shape[1] = bs.elem_count();
-                    ys.broadcast_add(&bs.reshape(bs_shape)?)?
-                } else {
-                    ys
-                };
-                values.insert(node.output[0].clone(), ys);
-            }
-            "Concat" => {
-                // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Concat
-                let inputs = node
-                    .input
-                    .iter()
-                    .map(|n| Ok(get(n.as_str())?.clone()))
-                    .collect::<Result<Vec<Value>>>()?;
-                let axis: i64 = *get_attr(node, "axis")?;
-                if inputs.is_empty() {
-                    bail!("empty concat")
-                };
-                // Find minimum rank among inputs and squeeze trailing singleton dims to match
-                let min_rank = inputs.iter().map(|t| t.rank()).min().unwrap();
-                let inputs: Vec<_> = inputs
-                    .into_iter()
-                    .map(|t| {
-                        let mut t = t;
-                        while t.rank() > min_rank {
-                            let last_dim = t.rank() - 1;
-                            if t.dims()[last_dim] == 1 {
-                                t = t.squeeze(last_dim).unwrap_or(t);
-                            } else {
-                                break;
-                            }
-                        }
-                        t
-                    })
-                    .collect();
-                let axis = inputs[0].normalize_axis(axis)?;
-                let output = Tensor::cat(&inputs, axis).map_err(|e| {
-                    let shapes: Vec<_> = inputs.iter().map(|t| format!("{:?}", t.dims())).collect();
-                    candle::Error::Msg(format!(
-                        "Concat failed for node '{}': {} (input shapes: {:?})",
-                        node.name, e, shapes
-                    ))
-                })?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "Abs" => {
-                let input = get(&node.input[0])?;
-                let output = input.abs()?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "Cos" => {
-                let input = get(&node.input[0])?;
-                let output = input.cos()?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "Sin" => {
-                let input = get(&node.input[0])?;
-                let output = input.sin()?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "Neg" => {
-                let input = get(&node.input[0])?;
-                // neg() not implemented for i64, work around with multiply by -1
-                let output = if input.dtype() == DType::I64 {
-                    let minus_one =
-                        Tensor::new(&[-1i64], input.device())?.broadcast_as(input.shape())?;
-                    input.mul(&minus_one)?
-                } else {
-                    input.neg()?
-                };
-                values.insert(node.output[0].clone(), output);
-            }
-            "Erf" => {
-                let input = get(&node.input[0])?;
-                let output = input.erf()?;
-                values.insert(node.output[0].clone(), output);
+                },
+                Err(_) => {
+                    bail!("unsupported 'to' value {dt:?} for cast {}", node.name)
+                }
+            };
+            let output = input.to_dtype(dtype)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#CumSum
+        "CumSum" => {
+            let exclusive = get_attr_opt::<i64>(node, "exclusive")?
+                .copied()
+                .unwrap_or(0);
+            let reverse = get_attr_opt::<i64>(node, "reverse")?.copied().unwrap_or(0);
+            if exclusive != 0 {
+                bail!("only exclusive == 0 is supported in CumSum")
+            }
+            if reverse != 0 {
+                bail!("only reverse == 0 is supported in CumSum")
+            }
+            let input = get(&node.input[0])?;
+            let axis = to_vec0_flexible::<u32>(&get(&node.input[1])?.to_dtype(DType::U32)?)?;
+            let output = input.cumsum(axis as usize)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        //  https://github.com/onnx/onnx/blob/main/docs/Operators.md#flatten
+        "Flatten" => {
+            let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(1) as usize;
+            let input = get(&node.input[0])?;
+            let first_part: usize = input.shape().dims().iter().take(axis).product();
+            let end_index = input.shape().dims().iter().product::<usize>();
+            let new_shape = (first_part, end_index / first_part);
+            let output = input.reshape(new_shape)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#identity
+        "Identity" => {
+            let input = get(&node.input[0])?;
+            values.insert(node.output[0].clone(), input.clone());
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#if
+        "If" => {
+            // protobuf encodes boolean false as 0 and true as 1
+            let cond = to_scalar_flexible::<u8>(&get(&node.input[0])?.get(0)?)?;
+            let attr_name = if cond != 0 {
+                "then_branch"
+            } else {
+                "else_branch"
+            };
+            let sub_graph = get_attr::<GraphProto>(node, attr_name)?;
+            if sub_graph.output.len() != node.output.len() {
+                bail!(
+                    "If node {:?} is malformed: branch outputs ({}) don't match node outputs ({})",
+                    node.name,
+                    sub_graph.output.len(),
+                    node.output.len()
+                );
             }
-            "Tanh" => {
-                let input = get(&node.input[0])?;
-                let output = input.tanh()?;
-                values.insert(node.output[0].clone(), output);
+            eval_graph(sub_graph, values, sequences, external_data)?;
+            for (output, sub_output) in node.output.iter().zip(sub_graph.output.iter()) {
+                Item::get(&sub_output.name, values, sequences)?.insert(output, values, sequences)
             }
-            "Sigmoid" => {
-                let input = get(&node.input[0])?;
-                let output = candle_nn::ops::sigmoid(input)?;
-                values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#pad
+        "Pad" => {
+            let mode = get_attr_opt(node, "mode")?.unwrap_or("constant");
+            let data = get(&node.input[0])?;
+            let pads = get(&node.input[1])?;
+            if node.input.len() > 2 {
+                bail!(
+                    "unsupported number of inputs {} for Pad node {:?}, expected 2",
+                    node.input.len(),
+                    node.name
+                );
             }
-            "Gelu" => {
-                let input = get(&node.input[0])?;
-                let output = input.gelu_erf()?;
-                values.insert(node.output[0].clone(), output);
+            if pads.rank() != 1 {
+                bail!("Pad expects 'pads' input to be 1D vector: {pads:?}");
             }
-            "Relu" => {
-                let input = get(&node.input[0])?;
-                let output = input.relu()?;
-                values.insert(node.output[0].clone(), output);
+            if pads.dim(0).unwrap() != 2 * data.rank() {
+                bail!("Pad expects 'pads' input len to be 2 * rank of 'data' input: pads: {}, data rank: {}", pads, data.rank());
             }
-            "PRelu" => {
-                // https://onnx.ai/onnx/operators/onnx__PRelu.html
-                let input = get(&node.input[0])?;
-                let slope = get(&node.input[1])?;
 
-                let output = PReLU::new(slope.clone(), false).forward(input)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "Ceil" => {
-                let input = get(&node.input[0])?;
-                let output = input.ceil()?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "Floor" => {
-                let input = get(&node.input[0])?;
-                let output = input.floor()?;
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Constant
-            "Constant" => {
-                let value = match node.attribute.iter().find(|attr| attr.name == "value") {
-                    None => {
-                        // TODO: support sparse_value etc.
-                        bail!("cannot find 'value' attr in 'Constant' for {}", node.name)
-                    }
-                    Some(value) => value,
-                };
-                let output = match value.r#type() {
-                    AttributeType::Tensor => {
-                        let t = value.t.as_ref().unwrap();
-                        get_tensor(t, &node.name)?
-                    }
-                    rtype => bail!("unsupported 'value' type {rtype:?} for {}", node.name),
-                };
+            let pads = pads.to_vec1::<i64>()?;
+            let (pads_pre, pads_post) = pads.split_at(pads.len() / 2);
 
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Cast
-            "Cast" => {
-                let input = get(&node.input[0])?;
-                let dt: i64 = *get_attr(node, "to")?;
-                let dtype = match DataType::try_from(dt as i32) {
-                    Ok(DataType::Int32) => DType::I64,
-                    Ok(dt) => match dtype(dt) {
-                        Some(dt) => dt,
-                        None => {
-                            bail!("unsupported 'to' value {dt:?} for cast {}", node.name)
+            match mode {
+                "reflect" => {
+                    let mut out = data.clone();
+                    for (i, &dim) in data.dims().iter().enumerate().rev() {
+                        if pads_pre[i] == 0 && pads_post[i] == 0 {
+                            continue;
                         }
-                    },
-                    Err(_) => {
-                        bail!("unsupported 'to' value {dt:?} for cast {}", node.name)
-                    }
-                };
-                let output = input.to_dtype(dtype)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#CumSum
-            "CumSum" => {
-                let exclusive = get_attr_opt::<i64>(node, "exclusive")?
-                    .copied()
-                    .unwrap_or(0);
-                let reverse = get_attr_opt::<i64>(node, "reverse")?.copied().unwrap_or(0);
-                if exclusive != 0 {
-                    bail!("only exclusive == 0 is supported in CumSum")
-                }
-                if reverse != 0 {
-                    bail!("only reverse == 0 is supported in CumSum")
-                }
-                let input = get(&node.input[0])?;
-                let axis = to_vec0_flexible::<u32>(&get(&node.input[1])?.to_dtype(DType::U32)?)?;
-                let output = input.cumsum(axis as usize)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            //  https://github.com/onnx/onnx/blob/main/docs/Operators.md#flatten
-            "Flatten" => {
-                let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(1) as usize;
-                let input = get(&node.input[0])?;
-                let first_part: usize = input.shape().dims().iter().take(axis).product();
-                let end_index = input.shape().dims().iter().product::<usize>();
-                let new_shape = (first_part, end_index / first_part);
-                let output = input.reshape(new_shape)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#identity
-            "Identity" => {
-                let input = get(&node.input[0])?;
-                values.insert(node.output[0].clone(), input.clone());
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#if
-            "If" => {
-                // protobuf encodes boolean false as 0 and true as 1
-                let cond = to_scalar_flexible::<u8>(&get(&node.input[0])?.get(0)?)?;
-                let attr_name = if cond != 0 {
-                    "then_branch"
-                } else {
-                    "else_branch"
-                };
-                let sub_graph = get_attr::<GraphProto>(node, attr_name)?;
-                if sub_graph.output.len() != node.output.len() {
-                    bail!(
-                        "If node {:?} is malformed: branch outputs ({}) don't match node outputs ({})",
-                        node.name,
-                        sub_graph.output.len(),
-                        node.output.len()
-                    );
-                }
-                let branch_out = simple_eval_(sub_graph, values)?;
-                for (i, out) in node.output.iter().enumerate() {
-                    values.insert(
-                        out.clone(),
-                        branch_out.get(&sub_graph.output[i].name).unwrap().clone(),
-                    );
-                }
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#pad
-            "Pad" => {
-                let mode = get_attr_opt(node, "mode")?.unwrap_or("constant");
-                let data = get(&node.input[0])?;
-                let pads = get(&node.input[1])?;
-                if node.input.len() > 2 {
-                    bail!(
-                        "unsupported number of inputs {} for Pad node {:?}, expected 2",
-                        node.input.len(),
-                        node.name
-                    );
-                }
-                if pads.rank() != 1 {
-                    bail!("Pad expects 'pads' input to be 1D vector: {pads:?}");
-                }
-                if pads.dim(0).unwrap() != 2 * data.rank() {
-                    bail!("Pad expects 'pads' input len to be 2 * rank of 'data' input: pads: {}, data rank: {}", pads, data.rank());
-                }
-
-                let pads = pads.to_vec1::<i64>()?;
-                let (pads_pre, pads_post) = pads.split_at(pads.len() / 2);
-
-                match mode {
-                    "reflect" => {
-                        let mut out = data.clone();
-                        for (i, &dim) in data.dims().iter().enumerate().rev() {
-                            if pads_pre[i] == 0 && pads_post[i] == 0 {
-                                continue;
-                            }
-                            fn zigzag(min: i64, max: i64) -> impl Iterator<Item = i64> {
-                                std::iter::repeat((min..max).chain((min + 1..=max).rev())).flatten()
-                            }
-                            let idx = if dim > 1 {
-                                let cycle_len = dim * 2 - 2;
-                                let skip = cycle_len - ((pads_pre[i] as usize) % cycle_len);
-                                let idx = zigzag(0, (dim - 1) as i64)
-                                    .skip(skip)
-                                    .take((pads_pre[i] as usize) + dim + (pads_post[i] as usize));
-                                Tensor::from_iter(idx, out.device())?
-                            } else {
-                                Tensor::full(0i64, (dim,), out.device())?
-                            };
-
-                            out = out.index_select(&idx, i)?;
+                        fn zigzag(min: i64, max: i64) -> impl Iterator<Item = i64> {
+                            std::iter::repeat((min..max).chain((min + 1..=max).rev())).flatten()
                         }
+                        let idx = if dim > 1 {
+                            let cycle_len = dim * 2 - 2;
+                            let skip = cycle_len - ((pads_pre[i] as usize) % cycle_len);
+                            let idx = zigzag(0, (dim - 1) as i64)
+                                .skip(skip)
+                                .take((pads_pre[i] as usize) + dim + (pads_post[i] as usize));
+                            Tensor::from_iter(idx, out.device())?
+                        } else {
+                            Tensor::full(0i64, (dim,), out.device())?
+                        };
 
-                        values.insert(node.output[0].clone(), out);
-                    }
-                    _ => bail!(
-                        "unsupported 'mode' value {mode:?} for Pad node {:?}",
-                        node.name
-                    ),
-                }
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#slice
-            "Slice" => {
-                let data = get(&node.input[0])?;
-                let starts = get(&node.input[1])?;
-                let ends = get(&node.input[2])?;
-                let default_axes;
-                let default_steps;
-                let axes: &Tensor;
-                let steps: &Tensor;
-                // If axes are omitted, they are set to [0, ..., r-1]. If steps are omitted,
-                // they are set to [1, ..., 1] of length len(starts)
-                match node.input.len() {
-                    3 => {
-                        let len = starts.dims()[0];
-                        default_axes = Some(Tensor::arange(0, len as i64, starts.device())?);
-                        axes = default_axes.as_ref().unwrap();
-                        default_steps = Some(Tensor::ones((len,), DType::I64, starts.device())?);
-                        steps = default_steps.as_ref().unwrap();
-                    }
-                    4 => {
-                        let len = starts.dims()[0];
-                        axes = get(&node.input[3])?;
-                        default_steps = Some(Tensor::ones((len,), DType::I64, starts.device())?);
-                        steps = default_steps.as_ref().unwrap();
-                    }
-                    5 => {
-                        steps = get(&node.input[4])?;
-                        axes = get(&node.input[3])?;
-                    }
-                    _ => bail!(
-                        "Slice node is invalid, expected 3-5 inputs, got {}: {:?}",
-                        node.input.len(),
-                        node
-                    ),
-                }
-
-                let mut out = data.clone();
-                for (i, axis) in axes.to_vec1::<i64>()?.into_iter().enumerate() {
-                    // All negative elements of axes are made non-negative by
-                    // adding r to them, where r = rank(input).
-                    let axis = if axis < 0 {
-                        axis + data.rank() as i64
-                    } else {
-                        axis
-                    } as usize;
-
-                    let data_dim = data.dims()[axis] as i64;
-                    let mut s = to_scalar_flexible::<i64>(&starts.get(i)?)?;
-                    let mut e = to_scalar_flexible::<i64>(&ends.get(i)?)?;
-                    // All negative values in starts[i] and ends[i] have
-                    // dims[axes[i]] added to them, where dims are the
-                    // dimensions of input.
-                    if s < 0 {
-                        s += data_dim;
-                    }
-                    if e < 0 {
-                        e += data_dim;
-                    }
-
-                    let p = to_scalar_flexible::<i64>(&steps.get(i)?)?;
-                    // starts[i] is clamped into the range [0, dims[axes[i]]]
-                    // for positive stepping and [0, dims[axes[i]]-1] for
-                    // negative stepping.
-                    // for positive stepping ends[axes[i]] is clamped to
-                    // [0, dims[axes[i]]], while for negative stepping it is
-                    // clamped to [-1, dims[axes[i]]-1].
-                    if p >= 0 {
-                        s = s.clamp(0, data_dim);
-                        e = e.clamp(0, data_dim);
-                    } else {
-                        s = s.clamp(0, data_dim - 1);
-                        e = e.clamp(-1, data_dim - 1);
+                        out = out.index_select(&idx, i)?;
                     }
 
-                    let indexes = Tensor::arange_step(s, e, p, data.device())?;
-                    out = out.contiguous()?.index_select(&indexes, axis)?
+                    values.insert(node.output[0].clone(), out);
                 }
-                values.insert(node.output[0].clone(), out);
+                _ => bail!(
+                    "unsupported 'mode' value {mode:?} for Pad node {:?}",
+                    node.name
+                ),
             }
-            // https://onnx.ai/onnx/operators/onnx__ReduceMax.html#reducemax
-            "ReduceMax" => {
-                let input = get(&node.input[0])?;
-                let axes = get_opt(1);
-                let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1) == 1;
-
-                let axes = if let Some(Ok(axes)) = axes {
-                    // Satisfies version 18+
-                    axes.to_vec1::<i64>().ok()
-                } else if let Ok(Some(axes)) = get_attr_opt::<[i64]>(node, "axes") {
-                    // Backward compatibility with version 13 and below
-                    Some(axes.to_vec())
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#slice
+        "Slice" => {
+            let data = get(&node.input[0])?;
+            let starts = get(&node.input[1])?;
+            let ends = get(&node.input[2])?;
+            let default_axes;
+            let default_steps;
+            let axes: &Tensor;
+            let steps: &Tensor;
+            // If axes are omitted, they are set to [0, ..., r-1]. If steps are omitted,
+            // they are set to [1, ..., 1] of length len(starts)
+            match node.input.len() {
+                3 => {
+                    let len = starts.dims()[0];
+                    default_axes = Some(Tensor::arange(0, len as i64, starts.device())?);
+                    axes = default_axes.as_ref().unwrap();
+                    default_steps = Some(Tensor::ones((len,), DType::I64, starts.device())?);
+                    steps = default_steps.as_ref().unwrap();
+                }
+                4 => {
+                    let len = starts.dims()[0];
+                    axes = get(&node.input[3])?;
+                    default_steps = Some(Tensor::ones((len,), DType::I64, starts.device())?);
+                    steps = default_steps.as_ref().unwrap();
+                }
+                5 => {
+                    steps = get(&node.input[4])?;
+                    axes = get(&node.input[3])?;
+                }
+                _ => bail!(
+                    "Slice node is invalid, expected 3-5 inputs, got {}: {:?}",
+                    node.input.len(),
+                    node
+                ),
+            }
+
+            let mut out = data.clone();
+            for (i, axis) in axes.to_vec1::<i64>()?.into_iter().enumerate() {
+                // All negative elements of axes are made non-negative by
+                // adding r to them, where r = rank(input).
+                let axis = if axis < 0 {
+                    axis + data.rank() as i64
                 } else {
-                    None
-                };
+                    axis
+                } as usize;
+
+                let data_dim = data.dims()[axis] as i64;
+                let mut s = to_scalar_flexible::<i64>(&starts.get(i)?)?;
+                let mut e = to_scalar_flexible::<i64>(&ends.get(i)?)?;
+                // All negative values in starts[i] and ends[i] have
+                // dims[axes[i]] added to them, where dims are the
+                // dimensions of input.
+                if s < 0 {
+                    s += data_dim;
+                }
+                if e < 0 {
+                    e += data_dim;
+                }
+
+                let p = to_scalar_flexible::<i64>(&steps.get(i)?)?;
+                // starts[i] is clamped into the range [0, dims[axes[i]]]
+                // for positive stepping and [0, dims[axes[i]]-1] for
+                // negative stepping.
+                // for positive stepping ends[axes[i]] is clamped to
+                // [0, dims[axes[i]]], while for negative stepping it is
+                // clamped to [-1, dims[axes[i]]-1].
+                if p >= 0 {
+                    s = s.clamp(0, data_dim);
+                    e = e.clamp(0, data_dim);
+                } else {
+                    s = s.clamp(0, data_dim - 1);
+                    e = e.clamp(-1, data_dim - 1);
+                }
 
-                let axes = if let Some(axes) = axes {
-                    let rank = input.rank();
-                    let mut axes_set = HashSet::new();
+                let indexes = Tensor::arange_step(s, e, p, data.device())?;
+                out = out.contiguous()?.index_select(&indexes, axis)?
+            }
+            values.insert(node.output[0].clone(), out);
+        }
+        // https://onnx.ai/onnx/operators/onnx__ReduceMax.html#reducemax
+        "ReduceMax" => {
+            let input = get(&node.input[0])?;
+            let axes = get_opt(1);
+            let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1) == 1;
+
+            let axes = if let Some(Ok(axes)) = axes {
+                // Satisfies version 18+
+                axes.to_vec1::<i64>().ok()
+            } else if let Ok(Some(axes)) = get_attr_opt::<[i64]>(node, "axes") {
+                // Backward compatibility with version 13 and below
+                Some(axes.to_vec())
+            } else {
+                None
+            };
 
-                    let mut axes = axes
-                        .iter()
-                        .map(|a| {
-                            let axis = if *a < 0 {
-                                (rank as i64 + *a) as usize
-                            } else {
-                                *a as usize
-                            };
-
-                            axes_set.insert(axis);
-                            axis
-                        })
-                        .collect::<Vec<_>>();
-
-                    if axes_set.len() < axes.len() {
-                        bail!("Duplicate value in 'axes'");
-                    }
+            let axes = if let Some(axes) = axes {
+                let rank = input.rank();
+                let mut axes_set = HashSet::new();
 
-                    if axes.len() > 1 {
-                        axes.sort();
-                    }
+                let mut axes = axes
+                    .iter()
+                    .map(|a| {
+                        let axis = if *a < 0 {
+                            (rank as i64 + *a) as usize
+                        } else {
+                            *a as usize
+                        };
 
-                    Some(axes)
-                } else {
-                    None
-                };
+                        axes_set.insert(axis);
+                        axis
+                    })
+                    .collect::<Vec<_>>();
 
-                // TODO: Handle empty set
-                // Definition:
-                // "Reduction over an empty set of values yields minus infinity (if supported by the datatype) or the minimum value of the data type otherwise"
-                // For now, this will throw an error
-                if input.elem_count() == 0 {
-                    bail!("reduction over zero-size tensor not supported");
+                if axes_set.len() < axes.len() {
+                    bail!("Duplicate value in 'axes'");
                 }
 
-                let output = if let Some(axes) = axes {
-                    let mut result = input.clone();
-                    for &axis in axes.iter().rev() {
-                        result = if keepdims {
-                            result.max_keepdim(axis)?
-                        } else {
-                            result.max(axis)?
-                        }
+                if axes.len() > 1 {
+                    axes.sort();
+                }
+
+                Some(axes)
+            } else {
+                None
+            };
+
+            // TODO: Handle empty set
+            // Definition:
+            // "Reduction over an empty set of values yields minus infinity (if supported by the datatype) or the minimum value of the data type otherwise"
+            // For now, this will throw an error
+            if input.elem_count() == 0 {
+                bail!("reduction over zero-size tensor not supported");
+            }
+
+            let output = if let Some(axes) = axes {
+                let mut result = input.clone();
+                for &axis in axes.iter().rev() {
+                    result = if keepdims {
+                        result.max_keepdim(axis)?
+                    } else {
+                        result.max(axis)?
                     }
+                }
 
-                    result
+                result
+            } else {
+                // If `axes` is empty and `noop_with_empty_axes` is set to `true (1)`
+                // ""input tensor will not be reduced,and the output tensor would be equivalent to input tensor.""
+                if get_attr_opt::<i64>(node, "noop_with_empty_axes")?.copied() == Some(1) {
+                    input.clone()
                 } else {
-                    // If `axes` is empty and `noop_with_empty_axes` is set to `true (1)`
-                    // ""input tensor will not be reduced,and the output tensor would be equivalent to input tensor.""
-                    if get_attr_opt::<i64>(node, "noop_with_empty_axes")?.copied() == Some(1) {
-                        input.clone()
+                    let mut result = input.flatten_all()?;
+                    if keepdims {
+                        result = result.max_keepdim(0)?;
+                        // If keepdims is true, reshape to match input dimensions
+                        let shape = vec![1; input.rank()];
+                        result.reshape(shape)?
                     } else {
-                        let mut result = input.flatten_all()?;
-                        if keepdims {
-                            result = result.max_keepdim(0)?;
-                            // If keepdims is true, reshape to match input dimensions
-                            let shape = vec![1; input.rank()];
-                            result.reshape(shape)?
-                        } else {
-                            result.max(0)?
-                        }
+                        result.max(0)?
                     }
-                };
+                }
+            };
 
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://onnx.ai/onnx/operators/onnx__ReduceMean.html#reducemean-13
-            // TODO: This version is only compatible with ReduceMean V13 and below.
-            "ReduceMean" => {
-                let input = get(&node.input[0])?;
-                let axes = get_attr_opt::<[i64]>(node, "axes")?;
-                let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1);
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__ReduceMean.html#reducemean-13
+        // TODO: This version is only compatible with ReduceMean V13 and below.
+        "ReduceMean" => {
+            let input = get(&node.input[0])?;
+            let axes = get_attr_opt::<[i64]>(node, "axes")?;
+            let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1);
+
+            let n_dims = input.dims().len();
+
+            let axes: Vec<usize> = if let Some(axes) = axes {
+                axes.iter()
+                    .map(|e| (if e < &0 { (n_dims as i64) + *e } else { *e }) as usize)
+                    .collect()
+            } else {
+                (0..n_dims).collect()
+            };
+            let output = if keepdims == 1 {
+                input.mean_keepdim(axes)?
+            } else {
+                input.mean(axes)?
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__ReduceMin.html#reducemin
+        "ReduceMin" => {
+            let input = get(&node.input[0])?;
+            let axes = get_opt(1);
+            let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1) == 1;
+
+            let axes = if let Some(Ok(axes)) = axes {
+                // Satisfies version 18+
+                axes.to_vec1::<i64>().ok()
+            } else if let Ok(Some(axes)) = get_attr_opt::<[i64]>(node, "axes") {
+                // Backward compatibility with version 13 and below
+                Some(axes.to_vec())
+            } else {
+                None
+            };
 
-                let n_dims = input.dims().len();
+            let axes = if let Some(axes) = axes {
+                let rank = input.rank();
+                let mut axes_set = HashSet::new();
 
-                let axes: Vec<usize> = if let Some(axes) = axes {
-                    axes.iter()
-                        .map(|e| (if e < &0 { (n_dims as i64) + *e } else { *e }) as usize)
-                        .collect()
-                } else {
-                    (0..n_dims).collect()
-                };
-                let output = if keepdims == 1 {
-                    input.mean_keepdim(axes)?
-                } else {
-                    input.mean(axes)?
-                };
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://onnx.ai/onnx/operators/onnx__ReduceMin.html#reducemin
-            "ReduceMin" => {
-                let input = get(&node.input[0])?;
-                let axes = get_opt(1);
-                let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1) == 1;
-
-                let axes = if let Some(Ok(axes)) = axes {
-                    // Satisfies version 18+
-                    axes.to_vec1::<i64>().ok()
-                } else if let Ok(Some(axes)) = get_attr_opt::<[i64]>(node, "axes") {
-                    // Backward compatibility with version 13 and below
-                    Some(axes.to_vec())
-                } else {
-                    None
-                };
+                let mut axes = axes
+                    .iter()
+                    .map(|a| {
+                        let axis = if *a < 0 {
+                            (rank as i64 + *a) as usize
+                        } else {
+                            *a as usize
+                        };
 
-                let axes = if let Some(axes) = axes {
-                    let rank = input.rank();
-                    let mut axes_set = HashSet::new();
+                        axes_set.insert(axis);
+                        axis
+                    })
+                    .collect::<Vec<_>>();
 
-                    let mut axes = axes
-                        .iter()
-                        .map(|a| {
-                            let axis = if *a < 0 {
-                                (rank as i64 + *a) as usize
-                            } else {
-                                *a as usize
-                            };
-
-                            axes_set.insert(axis);
-                            axis
-                        })
-                        .collect::<Vec<_>>();
-
-                    if axes_set.len() < axes.len() {
-                        bail!("Duplicate value in 'axes'");
-                    }
+                if axes_set.len() < axes.len() {
+                    bail!("Duplicate value in 'axes'");
+                }
 
-                    if axes.len() > 1 {
-                        axes.sort();
-                    }
+                if axes.len() > 1 {
+                    axes.sort();
+                }
 
-                    Some(axes)
-                } else {
-                    None
-                };
+                Some(axes)
+            } else {
+                None
+            };
 
-                // TODO: Handle empty set
-                // Definition:
-                // "Reduction over an empty set of values yields positive infinity (if supported by the datatype) or the max value of the data type otherwise"
-                // For now, this will throw an error
-                if input.elem_count() == 0 {
-                    bail!("reduction over zero-size tensor not supported");
-                }
+            // TODO: Handle empty set
+            // Definition:
+            // "Reduction over an empty set of values yields positive infinity (if supported by the datatype) or the max value of the data type otherwise"
+            // For now, this will throw an error
+            if input.elem_count() == 0 {
+                bail!("reduction over zero-size tensor not supported");
+            }
 
-                let output = if let Some(axes) = axes {
-                    let mut result = input.clone();
-                    for &axis in axes.iter().rev() {
-                        result = if keepdims {
-                            result.min_keepdim(axis)?
-                        } else {
-                            result.min(axis)?
-                        }
+            let output = if let Some(axes) = axes {
+                let mut result = input.clone();
+                for &axis in axes.iter().rev() {
+                    result = if keepdims {
+                        result.min_keepdim(axis)?
+                    } else {
+                        result.min(axis)?
                     }
+                }
 
-                    result
+                result
+            } else {
+                // If `axes` is empty and `noop_with_empty_axes` is set to `true (1)`
+                // ""input tensor will not be reduced,and the output tensor would be equivalent to input tensor.""
+                if get_attr_opt::<i64>(node, "noop_with_empty_axes")?.copied() == Some(1) {
+                    input.clone()
                 } else {
-                    // If `axes` is empty and `noop_with_empty_axes` is set to `true (1)`
-                    // ""input tensor will not be reduced,and the output tensor would be equivalent to input tensor.""
-                    if get_attr_opt::<i64>(node, "noop_with_empty_axes")?.copied() == Some(1) {
-                        input.clone()
+                    let mut result = input.flatten_all()?;
+                    if keepdims {
+                        result = result.min_keepdim(0)?;
+                        // If keepdims is true, reshape to match input dimensions
+                        let shape = vec![1; input.rank()];
+                        result.reshape(shape)?
                     } else {
-                        let mut result = input.flatten_all()?;
-                        if keepdims {
-                            result = result.min_keepdim(0)?;
-                            // If keepdims is true, reshape to match input dimensions
-                            let shape = vec![1; input.rank()];
-                            result.reshape(shape)?
-                        } else {
-                            result.min(0)?
-                        }
+                        result.min(0)?
                     }
-                };
+                }
+            };
 
-                values.insert(node.output[0].clone(), output);
-            }
-            //https://github.com/onnx/onnx/blob/main/docs/Operators.md#Split
-            // Version 18 impl
-            "Split" => {
-                let input_tensor = get(&node.input[0])?;
-                let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(0);
-                let axis = input_tensor.normalize_axis(axis)?;
-
-                // Determine split sizes
-                let splits = if node.input.len() > 1 {
-                    // If the split tensor is provided, use it to determine sizes
-                    let split_tensor = get(&node.input[1])?.to_vec1::<i64>()?;
-                    split_tensor.iter().map(|&x| x as usize).collect::<Vec<_>>()
-                } else {
-                    let num_outputs = if let Some(&num_outputs_attrib) =
-                        get_attr_opt::<i64>(node, "num_outputs")?
-                    {
+            values.insert(node.output[0].clone(), output);
+        }
+        //https://github.com/onnx/onnx/blob/main/docs/Operators.md#Split
+        // Version 18 impl
+        "Split" => {
+            let input_tensor = get(&node.input[0])?;
+            let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(0);
+            let axis = input_tensor.normalize_axis(axis)?;
+
+            // Determine split sizes
+            let splits = if node.input.len() > 1 {
+                // If the split tensor is provided, use it to determine sizes
+                let split_tensor = get(&node.input[1])?.to_vec1::<i64>()?;
+                split_tensor.iter().map(|&x| x as usize).collect::<Vec<_>>()
+            } else {
+                let num_outputs =
+                    if let Some(&num_outputs_attrib) = get_attr_opt::<i64>(node, "num_outputs")? {
                         num_outputs_attrib as usize
                     } else {
                         node.output.len()
                     };
 
-                    let input_dim = input_tensor.dim(axis)?;
+                let input_dim = input_tensor.dim(axis)?;
 
-                    let mut split_sizes =
-                        vec![input_dim / num_outputs as usize; num_outputs as usize];
-                    let remainder = input_dim % num_outputs as usize;
-                    if remainder > 0 {
-                        // If there's a remainder, add it to the last split size
-                        split_sizes[num_outputs as usize - 1] += remainder;
-                    }
+                let mut split_sizes = vec![input_dim / num_outputs as usize; num_outputs as usize];
+                let remainder = input_dim % num_outputs as usize;
+                if remainder > 0 {
+                    // If there's a remainder, add it to the last split size
+                    split_sizes[num_outputs as usize - 1] += remainder;
+                }
 
-                    split_sizes
-                };
+                split_sizes
+            };
 
-                // Perform the split operation
-                let mut outputs = vec![];
-                let mut start = 0;
-                for &size in &splits {
-                    let end = start + size;
-                    let slice = input_tensor.narrow(axis, start, size)?;
-                    outputs.push(slice);
-                    start = end;
-                }
+            // Perform the split operation
+            let mut outputs = vec![];
+            let mut start = 0;
+            for &size in &splits {
+                let end = start + size;
+                let slice = input_tensor.narrow(axis, start, size)?;
+                outputs.push(slice);
+                start = end;
+            }
 
-                // Insert the split outputs into the values map
-                for (output, slice) in node.output.iter().zip(outputs.into_iter()) {
-                    values.insert(output.clone(), slice);
-                }
+            // Insert the split outputs into the values map
+            for (output, slice) in node.output.iter().zip(outputs.into_iter()) {
+                values.insert(output.clone(), slice);
+            }
+        }
+        //https://github.com/onnx/onnx/blob/main/docs/Operators.md#Expand
+        // Version 13 impl
+        "Expand" => {
+            // unlike broadcast_to, expand allows for the output shape to
+            // be different from the specified shape.
+            let input_tensor = get(&node.input[0])?;
+            let input_shape = get(&node.input[1])?;
+
+            // Check that the shape tensor is 1D
+            if input_shape.rank() != 1 {
+                bail!(
+                    "Expand expects 'shape' input to be 1D tensor: {:?}",
+                    input_shape
+                );
             }
-            //https://github.com/onnx/onnx/blob/main/docs/Operators.md#Expand
-            // Version 13 impl
-            "Expand" => {
-                // unlike broadcast_to, expand allows for the output shape to
-                // be different from the specified shape.
-                let input_tensor = get(&node.input[0])?;
-                let input_shape = get(&node.input[1])?;
+            let input_tensor_dims = input_tensor.dims();
+            let input_shape_dims = input_shape
+                .to_vec1::<i64>()?
+                .into_iter()
+                .map(|x| x as usize)
+                .collect::<Vec<_>>();
 
-                // Check that the shape tensor is 1D
-                if input_shape.rank() != 1 {
-                    bail!(
-                        "Expand expects 'shape' input to be 1D tensor: {:?}",
-                        input_shape
-                    );
-                }
-                let input_tensor_dims = input_tensor.dims();
-                let input_shape_dims = input_shape
-                    .to_vec1::<i64>()?
-                    .into_iter()
-                    .map(|x| x as usize)
-                    .collect::<Vec<_>>();
+            let target_shape = broadcast_shape(input_tensor_dims, input_shape_dims.as_slice())?;
 
-                let target_shape = broadcast_shape(input_tensor_dims, input_shape_dims.as_slice())?;
+            let expanded_tensor = input_tensor.broadcast_as(target_shape)?;
 
-                let expanded_tensor = input_tensor.broadcast_as(target_shape)?;
+            values.insert(node.output[0].clone(), expanded_tensor);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Tile
+        "Tile" => {
+            let input = get(&node.input[0])?;
+            let repeats = get(&node.input[1])?.to_vec1::<i64>()?;
 
-                values.insert(node.output[0].clone(), expanded_tensor);
+            let mut result = input.clone();
+            for (dim, &repeat) in repeats.iter().enumerate() {
+                if repeat > 1 {
+                    let repeat = repeat as usize;
+                    let tensors: Vec<_> = (0..repeat).map(|_| result.clone()).collect();
+                    result = Tensor::cat(&tensors, dim)?;
+                }
             }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Tile
-            "Tile" => {
-                let input = get(&node.input[0])?;
-                let repeats = get(&node.input[1])?.to_vec1::<i64>()?;
-
-                let mut result = input.clone();
-                for (dim, &repeat) in repeats.iter().enumerate() {
-                    if repeat > 1 {
-                        let repeat = repeat as usize;
-                        let tensors: Vec<_> = (0..repeat).map(|_| result.clone()).collect();
-                        result = Tensor::cat(&tensors, dim)?;
+            values.insert(node.output[0].clone(), result);
+        }
+        //https://github.com/onnx/onnx/blob/main/docs/Operators.md#ReduceSum
+        // Version 13 impl
+        "ReduceSum" => {
+            let input = get(&node.input[0])?;
+            let axes = get_opt(1);
+            let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1);
+            let noop_with_empty_axes = get_attr_opt::<i64>(node, "noop_with_empty_axes")?
+                .copied()
+                .unwrap_or(0);
+
+            let axes = match axes {
+                Some(Ok(axes)) => axes
+                    .to_vec1::<i64>()?
+                    .into_iter()
+                    .map(|x| x as usize)
+                    .collect::<Vec<_>>(),
+                Some(Err(_)) | None => {
+                    if noop_with_empty_axes == 1 {
+                        vec![]
+                    } else {
+                        (0..input.rank()).collect()
                     }
                 }
-                values.insert(node.output[0].clone(), result);
-            }
-            //https://github.com/onnx/onnx/blob/main/docs/Operators.md#ReduceSum
-            // Version 13 impl
-            "ReduceSum" => {
-                let input = get(&node.input[0])?;
-                let axes = get_opt(1);
-                let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1);
-                let noop_with_empty_axes = get_attr_opt::<i64>(node, "noop_with_empty_axes")?
-                    .copied()
-                    .unwrap_or(0);
-
-                let axes = match axes {
-                    Some(Ok(axes)) => axes
-                        .to_vec1::<i64>()?
-                        .into_iter()
-                        .map(|x| x as usize)
-                        .collect::<Vec<_>>(),
-                    Some(Err(_)) | None => {
-                        if noop_with_empty_axes == 1 {
-                            vec![]
-                        } else {
-                            (0..input.rank()).collect()
-                        }
-                    }
-                };
+            };
 
-                let output = if keepdims == 1 {
-                    input.sum_keepdim(axes)?
-                } else {
-                    input.sum(axes)?
-                };
+            let output = if keepdims == 1 {
+                input.sum_keepdim(axes)?
+            } else {
+                input.sum(axes)?
+            };
 
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#ReduceL2
-            // Version 18 impl
-            "ReduceL2" => {
-                let input = get(&node.input[0])?;
-                let axes = get_opt(1);
-                let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1);
-                let noop_with_empty_axes = get_attr_opt::<i64>(node, "noop_with_empty_axes")?
-                    .copied()
-                    .unwrap_or(0);
-
-                let input_sq = input.sqr()?;
-
-                let axes = match axes {
-                    Some(axes) => axes?
-                        .to_vec1::<i64>()?
-                        .into_iter()
-                        .map(|x| x as usize)
-                        .collect::<Vec<_>>(),
-                    None => {
-                        if noop_with_empty_axes == 1 {
-                            vec![]
-                        } else {
-                            (0..input_sq.rank()).collect()
-                        }
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#ReduceL2
+        // Version 18 impl
+        "ReduceL2" => {
+            let input = get(&node.input[0])?;
+            let axes = get_opt(1);
+            let keepdims = get_attr_opt::<i64>(node, "keepdims")?.copied().unwrap_or(1);
+            let noop_with_empty_axes = get_attr_opt::<i64>(node, "noop_with_empty_axes")?
+                .copied()
+                .unwrap_or(0);
+
+            let input_sq = input.sqr()?;
+
+            let axes = match axes {
+                Some(axes) => axes?
+                    .to_vec1::<i64>()?
+                    .into_iter()
+                    .map(|x| x as usize)
+                    .collect::<Vec<_>>(),
+                None => {
+                    if noop_with_empty_axes == 1 {
+                        vec![]
+                    } else {
+                        (0..input_sq.rank()).collect()
                     }
-                };
+                }
+            };
 
-                let output = if keepdims == 1 {
-                    input_sq.sum_keepdim(axes)?.sqrt()?
-                } else {
-                    input_sq.sum(axes)?.sqrt()?
-                };
+            let output = if keepdims == 1 {
+                input_sq.sum_keepdim(axes)?.sqrt()?
+            } else {
+                input_sq.sum(axes)?.sqrt()?
+            };
 
-                values.insert(node.output[0].clone(), output);
-            }
-            random_type @ ("RandomUniform" | "RandomNormal") => {
-                let dt: i64 = get_attr_opt(node, "dtype")?.copied().unwrap_or(1); // 1 is float
-                                                                                  // type by
-                                                                                  // default
-                let dtype = match DataType::try_from(dt as i32) {
-                    Ok(dt) => match dtype(dt) {
-                        Some(DType::U8 | DType::U32 | DType::I64) => {
-                            bail!(
-                                "unsupported 'dtype' value {dt:?}, only floats are allowed, for {random_type} {}",
-                                node.name
-                            )
-                        }
-                        Some(dt) => dt,
-                        None => {
-                            bail!(
-                                "unsupported 'dtype' value {dt:?} for {random_type} {}",
-                                node.name
-                            )
-                        }
-                    },
-                    Err(_) => {
+            values.insert(node.output[0].clone(), output);
+        }
+        random_type @ ("RandomUniform" | "RandomNormal") => {
+            let dt: i64 = get_attr_opt(node, "dtype")?.copied().unwrap_or(1); // 1 is float
+                                                                              // type by
+                                                                              // default
+            let dtype = match DataType::try_from(dt as i32) {
+                Ok(dt) => match dtype(dt) {
+                    Some(DType::U8 | DType::U32 | DType::I64) => {
+                        bail!(
+                            "unsupported 'dtype' value {dt:?}, only floats are allowed, for {random_type} {}",
+                            node.name
+                        )
+                    }
+                    Some(dt) => dt,
+                    None => {
                         bail!(
                             "unsupported 'dtype' value {dt:?} for {random_type} {}",
                             node.name
                         )
                     }
-                };
-                let seed: Option<f32> = get_attr_opt(node, "seed")?.copied();
-                if seed.is_some() {
-                    bail!("seed for {random_type} is currently not supported")
-                };
-                let shape: Vec<usize> = get_attr::<[i64]>(node, "shape")?
-                    .iter()
-                    .map(|x| *x as usize)
-                    .collect();
-                let output = if random_type == "RandomUniform" {
-                    let low: f32 = get_attr_opt(node, "low")?.copied().unwrap_or(0.0);
-                    let high: f32 = get_attr_opt(node, "high")?.copied().unwrap_or(1.0);
-                    Tensor::rand(low, high, shape, &Device::Cpu)?.to_dtype(dtype)?
-                } else {
-                    let mean: f32 = get_attr_opt(node, "mean")?.copied().unwrap_or(0.0);
-                    let scale: f32 = get_attr_opt(node, "scale")?.copied().unwrap_or(1.0);
-                    Tensor::randn(mean, scale, shape, &Device::Cpu)?.to_dtype(dtype)?
-                };
-                values.insert(node.output[0].clone(), output);
-            }
-            "ArgMin" => {
-                let input = get(&node.input[0])?;
-                let axis_i64: i64 = get_attr_opt(node, "axis")?.copied().unwrap_or(0);
-                let rank_i64: i64 = input.rank().try_into().unwrap();
-                if axis_i64 < -rank_i64 || axis_i64 >= rank_i64 {
+                },
+                Err(_) => {
                     bail!(
-                        "axis ({}) out of accepted range [-rank, rank-1] which was [{}, {}]",
-                        axis_i64,
-                        -rank_i64,
-                        rank_i64 - 1
+                        "unsupported 'dtype' value {dt:?} for {random_type} {}",
+                        node.name
                     )
                 }
-                let axis = input.normalize_axis(axis_i64)?;
-                let keepdims: i64 = get_attr_opt(node, "keepdims")?.copied().unwrap_or(1);
-                let select_last_index: i64 = get_attr_opt(node, "select_last_index")?
-                    .copied()
-                    .unwrap_or(0);
-                if select_last_index == 1 {
-                    bail!("select_last_index for ArgMin is currently not supported")
-                }
-                let output = if keepdims == 1 {
-                    input.argmin_keepdim(axis)?
-                } else {
-                    input.argmin(axis)?
-                }
-                .to_dtype(DType::I64)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "ArgMax" => {
-                let input = get(&node.input[0])?;
-                let axis_i64: i64 = get_attr_opt(node, "axis")?.copied().unwrap_or(0);
-                let rank_i64: i64 = input.rank().try_into().unwrap();
-                if axis_i64 < -rank_i64 || axis_i64 >= rank_i64 {
+            };
+            let seed: Option<f32> = get_attr_opt(node, "seed")?.copied();
+            if seed.is_some() {
+                bail!("seed for {random_type} is currently not supported")
+            };
+            let shape: Vec<usize> = get_attr::<[i64]>(node, "shape")?
+                .iter()
+                .map(|x| *x as usize)
+                .collect();
+            let output = if random_type == "RandomUniform" {
+                let low: f32 = get_attr_opt(node, "low")?.copied().unwrap_or(0.0);
+                let high: f32 = get_attr_opt(node, "high")?.copied().unwrap_or(1.0);
+                Tensor::rand(low, high, shape, &Device::Cpu)?.to_dtype(dtype)?
+            } else {
+                let mean: f32 = get_attr_opt(node, "mean")?.copied().unwrap_or(0.0);
+                let scale: f32 = get_attr_opt(node, "scale")?.copied().unwrap_or(1.0);
+                Tensor::randn(mean, scale, shape, &Device::Cpu)?.to_dtype(dtype)?
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        "ArgMin" => {
+            let input = get(&node.input[0])?;
+            let axis_i64: i64 = get_attr_opt(node, "axis")?.copied().unwrap_or(0);
+            let rank_i64: i64 = input.rank().try_into().unwrap();
+            if axis_i64 < -rank_i64 || axis_i64 >= rank_i64 {
+                bail!(
+                    "axis ({}) out of accepted range [-rank, rank-1] which was [{}, {}]",
+                    axis_i64,
+                    -rank_i64,
+                    rank_i64 - 1
+                )
+            }
+            let axis = input.normalize_axis(axis_i64)?;
+            let keepdims: i64 = get_attr_opt(node, "keepdims")?.copied().unwrap_or(1);
+            let select_last_index: i64 = get_attr_opt(node, "select_last_index")?
+                .copied()
+                .unwrap_or(0);
+            if select_last_index == 1 {
+                bail!("select_last_index for ArgMin is currently not supported")
+            }
+            let output = if keepdims == 1 {
+                input.argmin_keepdim(axis)?
+            } else {
+                input.argmin(axis)?
+            }
+            .to_dtype(DType::I64)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "ArgMax" => {
+            let input = get(&node.input[0])?;
+            let axis_i64: i64 = get_attr_opt(node, "axis")?.copied().unwrap_or(0);
+            let rank_i64: i64 = input.rank().try_into().unwrap();
+            if axis_i64 < -rank_i64 || axis_i64 >= rank_i64 {
+                bail!(
+                    "axis ({}) out of accepted range [-rank, rank-1] which was [{}, {}]",
+                    axis_i64,
+                    -rank_i64,
+                    rank_i64 - 1
+                )
+            }
+            let axis = input.normalize_axis(axis_i64)?;
+            let keepdims: i64 = get_attr_opt(node, "keepdims")?.copied().unwrap_or(1);
+            let select_last_index: i64 = get_attr_opt(node, "select_last_index")?
+                .copied()
+                .unwrap_or(0);
+            if select_last_index == 1 {
+                bail!("select_last_index for ArgMin is currently not supported")
+            }
+            let output = if keepdims == 1 {
+                input.argmax_keepdim(axis)?
+            } else {
+                input.argmax(axis)?
+            }
+            .to_dtype(DType::I64)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "LeakyRelu" => {
+            let input = get(&node.input[0])?;
+            let dt = input.dtype();
+            match dt {
+                DType::U8
+                | DType::U32
+                | DType::I64
+                | DType::I32
+                | DType::I16
+                | DType::F6E2M3
+                | DType::F6E3M2
+                | DType::F4
+                | DType::F8E8M0 => {
                     bail!(
-                        "axis ({}) out of accepted range [-rank, rank-1] which was [{}, {}]",
-                        axis_i64,
-                        -rank_i64,
-                        rank_i64 - 1
+                        "unsupported dtype {}, only float types are allowed for LeakyRelu",
+                        dt.as_str()
                     )
                 }
-                let axis = input.normalize_axis(axis_i64)?;
-                let keepdims: i64 = get_attr_opt(node, "keepdims")?.copied().unwrap_or(1);
-                let select_last_index: i64 = get_attr_opt(node, "select_last_index")?
-                    .copied()
-                    .unwrap_or(0);
-                if select_last_index == 1 {
-                    bail!("select_last_index for ArgMin is currently not supported")
-                }
-                let output = if keepdims == 1 {
-                    input.argmax_keepdim(axis)?
-                } else {
-                    input.argmax(axis)?
-                }
-                .to_dtype(DType::I64)?;
-                values.insert(node.output[0].clone(), output);
-            }
-            "LeakyRelu" => {
-                let input = get(&node.input[0])?;
-                let dt = input.dtype();
-                match dt {
-                    DType::U8
-                    | DType::U32
-                    | DType::I64
-                    | DType::I32
-                    | DType::I16
-                    | DType::F6E2M3
-                    | DType::F6E3M2
-                    | DType::F4
-                    | DType::F8E8M0 => {
-                        bail!(
-                            "unsupported dtype {}, only float types are allowed for LeakyRelu",
-                            dt.as_str()
-                        )
-                    }
-                    DType::BF16 | DType::F16 | DType::F32 | DType::F64 | DType::F8E4M3 => {}
-                }
-                let alpha = get_attr_opt::<f32>(node, "alpha")?.copied().unwrap_or(0.01);
-                let output = candle_nn::ops::leaky_relu(input, alpha.into())?;
-                values.insert(node.output[0].clone(), output);
+                DType::BF16
+                | DType::F16
+                | DType::F32
+                | DType::F64
+                | DType::F8E4M3
+                | DType::F8E5M2 => {}
             }
-            // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Gemm
-            "Gemm" => {
-                let a = get(&node.input[0])?;
-                let b = get(&node.input[1])?;
-                let c = get(&node.input[2])?;
+            let alpha = get_attr_opt::<f32>(node, "alpha")?.copied().unwrap_or(0.01);
+            let output = candle_nn::ops::leaky_relu(input, alpha.into())?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://github.com/onnx/onnx/blob/main/docs/Operators.md#Gemm
+        "Gemm" => {
+            let a = get(&node.input[0])?;
+            let b = get(&node.input[1])?;
+            let c = get(&node.input[2])?;
 
-                let alpha = get_attr_opt::<f32>(node, "alpha")?.copied().unwrap_or(1.0);
-                let beta = get_attr_opt::<f32>(node, "beta")?.copied().unwrap_or(1.0);
+            let alpha = get_attr_opt::<f32>(node, "alpha")?.copied().unwrap_or(1.0);
+            let beta = get_attr_opt::<f32>(node, "beta")?.copied().unwrap_or(1.0);
 
-                let alpha = Tensor::full(alpha, a.shape(), &Device::Cpu)?;
-                let beta = Tensor::full(beta, c.shape(), &Device::Cpu)?;
+            let alpha = Tensor::full(alpha, a.shape(), &Device::Cpu)?;
+            let beta = Tensor::full(beta, c.shape(), &Device::Cpu)?;
 
-                let trans_a = get_attr_opt::<i64>(node, "transA")?.copied().unwrap_or(0);
-                let trans_b = get_attr_opt::<i64>(node, "transB")?.copied().unwrap_or(0);
+            let trans_a = get_attr_opt::<i64>(node, "transA")?.copied().unwrap_or(0);
+            let trans_b = get_attr_opt::<i64>(node, "transB")?.copied().unwrap_or(0);
 
-                let a = if trans_a == 0 { a.clone() } else { a.t()? };
-                let b = if trans_b == 0 { b.clone() } else { b.t()? };
+            let a = if trans_a == 0 { a.clone() } else { a.t()? };
+            let b = if trans_b == 0 { b.clone() } else { b.t()? };
 
-                let output = a
-                    .broadcast_mul(&alpha)?
-                    .broadcast_matmul(&b)?
-                    .broadcast_add(&c.broadcast_mul(&beta)?)?;
-                values.insert(node.output[0].clone(), output);
+            let output = a
+                .broadcast_mul(&alpha)?
+                .broadcast_matmul(&b)?
+                .broadcast_add(&c.broadcast_mul(&beta)?)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "LSTM" => {
+            let direction = get_attr_opt(node, "direction")?.unwrap_or("forward");
+            if direction != "forward" {
+                bail!("LSTM currently only supports direction == \"forward\"");
+            }
+            let num_directions = if direction == "bidirectional" { 2 } else { 1 };
+            let hidden_size: i64 = get_attr(node, "hidden_size").copied()?;
+            let input_forget = get_attr_opt(node, "input_forget")?.copied().unwrap_or(0);
+            if input_forget != 0 {
+                bail!("LSTM currently only supports input_forget == 0");
+            }
+            let activations_default = vec![
+                "Sigmoid".to_string(),
+                "Tanh".to_string(),
+                "Tanh".to_string(),
+            ];
+            let activations = get_attr_opt_owned::<Vec<String>>(node, "activations")?
+                .unwrap_or(activations_default.clone());
+            if activations != activations_default {
+                bail!("LSTM currently only supports default activations ({activations_default:?})");
+            }
+            // activation_alpha and activation_beta don't apply to (Sigmoid, Tanh, Tanh) so ignoring them is okay
+            if get_attr_opt::<f32>(node, "clip")?.is_some() {
+                bail!("LSTM does not currently support clip attribute");
+            }
+
+            // The shape format of inputs X, initial_h and outputs Y, Y_h.
+            // If 0, the following shapes are expected:
+            //     X.shape = [seq_length, batch_size, input_size],
+            //     Y.shape = [seq_length, num_directions, batch_size, hidden_size],
+            //     initial_h.shape = Y_h.shape = [num_directions, batch_size, hidden_size].
+            // If 1, the following shapes are expected:
+            //     X.shape = [batch_size, seq_length, input_size],
+            //     Y.shape = [batch_size, seq_length, num_directions, hidden_size],
+            //     initial_h.shape = Y_h.shape = [batch_size, num_directions, hidden_size].
+            let layout = get_attr_opt(node, "layout")?.copied().unwrap_or(0);
+            if layout != 0 {
+                bail!("LSTM currently only supports layout == 0");
+            }
+
+            // The input sequences packed (and potentially padded) into one 3-D tensor
+            // with the shape of `[seq_length, batch_size, input_size]`.
+            let x = get(&node.input[0])?;
+            // XXX: depends on layout
+            let (seq_length, batch_size, input_size) = x.dims3()?;
+            // The weight tensor for the gates.
+            // Concatenation of `W[iofc]` and `WB[iofc]` (if bidirectional) along dimension 0.
+            // The tensor has shape `[num_directions, 4*hidden_size, input_size]`.
+            let w = get(&node.input[1])?;
+            // The recurrence weight tensor.
+            // Concatenation of `R[iofc]` and `RB[iofc]` (if bidirectional) along dimension 0.
+            // This tensor has shape `[num_directions, 4*hidden_size, hidden_size]`.
+            let r = get(&node.input[2])?;
+
+            // The bias tensor for input gate.
+            // Concatenation of `[Wb[iofc], Rb[iofc]]`, and `[WBb[iofc], RBb[iofc]]` (if bidirectional) along dimension 0.
+            // This tensor has shape `[num_directions, 8*hidden_size]`.
+            // Optional: If not specified - assumed to be 0.
+            let b_default: Tensor;
+            let b = match get_opt(3) {
+                Some(n) => n?,
+                None => {
+                    b_default = Tensor::zeros(
+                        (num_directions, 8 * hidden_size as usize),
+                        DType::F32,
+                        x.device(),
+                    )?;
+                    &b_default
+                }
+            };
+
+            // Optional tensor specifying lengths of the sequences in a batch.
+            // If not specified - assumed all sequences in the batch to have length `seq_length`.
+            // It has shape `[batch_size]`.
+            let seq_lens_default: Tensor;
+            let seq_lens = match get_opt(4) {
+                Some(n) => n?,
+                None => {
+                    seq_lens_default = Tensor::full(seq_length as i64, (batch_size,), x.device())?;
+                    &seq_lens_default
+                }
+            };
+            let seq_lens_is_default =
+                (seq_lens.to_vec1::<i64>()?.iter()).all(|e| *e as usize == seq_length);
+            if !seq_lens_is_default {
+                bail!("LSTM currently only supports default value of seq_lens");
+            }
+
+            // Optional initial value of the hidden. If not specified - assumed to be 0.
+            // It has shape `[num_directions, batch_size, hidden_size]`.
+            let initial_h_default: Tensor;
+            let initial_h = match get_opt(5) {
+                Some(n) => n?,
+                _ => {
+                    initial_h_default = Tensor::zeros(
+                        (num_directions, batch_size, hidden_size as usize),
+                        DType::F32,
+                        x.device(),
+                    )?;
+                    &initial_h_default
+                }
+            };
+
+            // Optional initial value of the cell.
+            // If not specified - assumed to be 0.
+            // It has shape `[num_directions, batch_size, hidden_size]`.
+            let initial_c_default: Tensor;
+            let initial_c = match node.input.get(6) {
+                Some(n) if !n.is_empty() => get(n)?,
+                _ => {
+                    initial_c_default = Tensor::zeros(
+                        (num_directions, batch_size, hidden_size as usize),
+                        DType::F32,
+                        x.device(),
+                    )?;
+                    &initial_c_default
+                }
+            };
+
+            // The weight tensor for peepholes.
+            // Concatenation of `P[iof]` and `PB[iof]` (if bidirectional) along dimension 0.
+            // It has shape `[num_directions, 3*hidde_size]`. Optional: If not specified - assumed to be 0.
+            let p_default = Tensor::zeros(
+                (num_directions, 3 * hidden_size as usize),
+                DType::F32,
+                x.device(),
+            )?;
+            let p = get_opt(7).unwrap_or(Ok(&p_default))?;
+            let p_is_zeros = (p.to_vec2::<f32>()?.iter()).all(|v| v.iter().all(|e| *e == 0.0));
+            if !p_is_zeros {
+                bail!("LSTM currently only supports default value of p (a Tensor of all zeroes)");
+            }
+
+            // these all have [num_directions, ...] shapes
+            let w = w.get(0)?; // w[iofc] has shape [4*hidden_size, input_size]
+            let r = r.get(0)?; // r[iofc] has shape [4*hidden_size, hidden_size]
+            let b = b.get(0)?; // concat of [wb[iofc],rb[iofc]] has shape [8*hidden_size]
+            let idx_wb = Tensor::arange(0, 4 * hidden_size, x.device())?;
+            let idx_rb = Tensor::arange(4 * hidden_size, 8 * hidden_size, x.device())?;
+            let wb = b.index_select(&idx_wb, 0)?;
+            let rb = b.index_select(&idx_rb, 0)?;
+            let c = initial_c.get(0)?;
+            let h = initial_h.get(0)?;
+
+            // w, r, wb, rb are all iofc but lstm expects ifco
+            // so we need to move some stuff around
+            let idx_i = Tensor::arange(0, hidden_size, x.device())?;
+            let idx_o = Tensor::arange(hidden_size, 2 * hidden_size, x.device())?;
+            let idx_f = Tensor::arange(2 * hidden_size, 3 * hidden_size, x.device())?;
+            let idx_c = Tensor::arange(3 * hidden_size, 4 * hidden_size, x.device())?;
+            let idx_ifco = Tensor::cat(&[&idx_i, &idx_f, &idx_c, &idx_o], 0)?;
+            let w = w.index_select(&idx_ifco, 0)?;
+            let r = r.index_select(&idx_ifco, 0)?;
+            let wb = wb.index_select(&idx_ifco, 0)?;
+            let rb = rb.index_select(&idx_ifco, 0)?;
+            let vmap = candle_nn::VarMap::new();
+            vmap.data().lock().unwrap().extend([
+                ("weight_ih_l0".to_string(), candle::Var::from_tensor(&w)?),
+                ("weight_hh_l0".to_string(), candle::Var::from_tensor(&r)?),
+                ("bias_ih_l0".to_string(), candle::Var::from_tensor(&wb)?),
+                ("bias_hh_l0".to_string(), candle::Var::from_tensor(&rb)?),
+            ]);
+            use candle_nn::rnn::RNN as _;
+            let lstm = candle_nn::rnn::lstm(
+                input_size,
+                hidden_size as usize,
+                candle_nn::rnn::LSTMConfig::default(),
+                candle_nn::VarBuilder::from_varmap(&vmap, w.dtype(), w.device()),
+            )?;
+
+            let mut lstm_state = candle_nn::rnn::LSTMState::new(h, c);
+            let mut h_acc = if node.output.first().map(String::as_str).unwrap_or("") != "" {
+                Some(vec![])
+            } else {
+                None
+            };
+            for t in 0..seq_length {
+                let x = x.get(t)?;
+                lstm_state = lstm.step(&x, &lstm_state)?;
+                if let Some(h_acc) = &mut h_acc {
+                    h_acc.push(lstm_state.clone());
+                }
+            }
+
+            assert_eq!(num_directions, 1, "if support for bidirectional is ever added, outputs will have to be concatenated, not simply reshaped");
+            if let Some(name) = node.output.first() {
+                let h_acc = h_acc.as_ref().unwrap();
+                let h_acc = lstm.states_to_tensor(h_acc)?;
+                let h_acc = h_acc.reshape((
+                    seq_length,
+                    num_directions,
+                    batch_size,
+                    hidden_size as usize,
+                ))?;
+                values.insert(name.clone(), h_acc);
             }
-            "LSTM" => {
-                let direction = get_attr_opt(node, "direction")?.unwrap_or("forward");
-                if direction != "forward" {
-                    bail!("LSTM currently only supports direction == \"forward\"");
-                }
-                let num_directions = if direction == "bidirectional" { 2 } else { 1 };
-                let hidden_size: i64 = get_attr(node, "hidden_size").copied()?;
-                let input_forget = get_attr_opt(node, "input_forget")?.copied().unwrap_or(0);
-                if input_forget != 0 {
-                    bail!("LSTM currently only supports input_forget == 0");
-                }
-                let activations_default = vec![
-                    "Sigmoid".to_string(),
-                    "Tanh".to_string(),
-                    "Tanh".to_string(),
-                ];
-                let activations = get_attr_opt_owned::<Vec<String>>(node, "activations")?
-                    .unwrap_or(activations_default.clone());
-                if activations != activations_default {
-                    bail!("LSTM currently only supports default activations ({activations_default:?})");
-                }
-                // activation_alpha and activation_beta don't apply to (Sigmoid, Tanh, Tanh) so ignoring them is okay
-                if get_attr_opt::<f32>(node, "clip")?.is_some() {
-                    bail!("LSTM does not currently support clip attribute");
-                }
-
-                // The shape format of inputs X, initial_h and outputs Y, Y_h.
-                // If 0, the following shapes are expected:
-                //     X.shape = [seq_length, batch_size, input_size],
-                //     Y.shape = [seq_length, num_directions, batch_size, hidden_size],
-                //     initial_h.shape = Y_h.shape = [num_directions, batch_size, hidden_size].
-                // If 1, the following shapes are expected:
-                //     X.shape = [batch_size, seq_length, input_size],
-                //     Y.shape = [batch_size, seq_length, num_directions, hidden_size],
-                //     initial_h.shape = Y_h.shape = [batch_size, num_directions, hidden_size].
-                let layout = get_attr_opt(node, "layout")?.copied().unwrap_or(0);
-                if layout != 0 {
-                    bail!("LSTM currently only supports layout == 0");
-                }
-
-                // The input sequences packed (and potentially padded) into one 3-D tensor
-                // with the shape of `[seq_length, batch_size, input_size]`.
-                let x = get(&node.input[0])?;
-                // XXX: depends on layout
-                let (seq_length, batch_size, input_size) = x.dims3()?;
-                // The weight tensor for the gates.
-                // Concatenation of `W[iofc]` and `WB[iofc]` (if bidirectional) along dimension 0.
-                // The tensor has shape `[num_directions, 4*hidden_size, input_size]`.
-                let w = get(&node.input[1])?;
-                // The recurrence weight tensor.
-                // Concatenation of `R[iofc]` and `RB[iofc]` (if bidirectional) along dimension 0.
-                // This tensor has shape `[num_directions, 4*hidden_size, hidden_size]`.
-                let r = get(&node.input[2])?;
-
-                // The bias tensor for input gate.
-                // Concatenation of `[Wb[iofc], Rb[iofc]]`, and `[WBb[iofc], RBb[iofc]]` (if bidirectional) along dimension 0.
-                // This tensor has shape `[num_directions, 8*hidden_size]`.
-                // Optional: If not specified - assumed to be 0.
-                let b_default: Tensor;
-                let b = match get_opt(3) {
-                    Some(n) => n?,
-                    None => {
-                        b_default = Tensor::zeros(
-                            (num_directions, 8 * hidden_size as usize),
-                            DType::F32,
-                            x.device(),
-                        )?;
-                        &b_default
-                    }
-                };
+            if let Some(name) = node.output.get(1) {
+                values.insert(
+                    name.clone(),
+                    lstm_state
+                        .h()
+                        .reshape((num_directions, batch_size, hidden_size as usize))?,
+                );
+            }
+            if let Some(name) = node.output.get(2) {
+                values.insert(
+                    name.clone(),
+                    lstm_state
+                        .c()
+                        .reshape((num_directions, batch_size, hidden_size as usize))?,
+                );
+            }
+        }
+        "RNN" => {
+            // activation_alpha and activation_beta don't apply to (Tanh, Tanh) so ignoring them is okay
+            let activations_default = vec!["Tanh".to_string(), "Tanh".to_string()];
+            let activations = get_attr_opt_owned::<Vec<String>>(node, "activations")?
+                .unwrap_or(activations_default.clone());
+            let clip = get_attr_opt::<f32>(node, "clip")?.copied();
+            if clip.is_some() {
+                bail!("RNN does not currently support clip attribute");
+            }
+            let direction = get_attr_opt(node, "direction")?.unwrap_or("forward");
+            if direction != "forward" {
+                bail!("RNN currently only supports direction == \"forward\"");
+            }
+            let num_directions = if direction == "bidirectional" { 2 } else { 1 };
+            let hidden_size: i64 = get_attr(node, "hidden_size").copied()?;
+
+            // The shape format of inputs X, initial_h and outputs Y, Y_h.
+            // If 0, the following shapes are expected:
+            //    X.shape = [seq_length, batch_size, input_size],
+            //    Y.shape = [seq_length, num_directions, batch_size, hidden_size],
+            //    initial_h.shape = Y_h.shape = [num_directions, batch_size, hidden_size].
+            // If 1, the following shapes are expected:
+            //    X.shape = [batch_size, seq_length, input_size],
+            //    Y.shape = [batch_size, seq_length, num_directions, hidden_size],
+            //    initial_h.shape = Y_h.shape = [batch_size, num_directions, hidden_size].
+            let layout = get_attr_opt(node, "layout")?.copied().unwrap_or(0);
+            if layout != 0 {
+                bail!("RNN currently only supports layout == 0");
+            }
+
+            // The input sequences packed (and potentially padded) into one 3-D tensor
+            // with the shape of `[seq_length, batch_size, input_size]`.
+            let x = get(&node.input[0])?;
+            // XXX: depends on layout
+            let (seq_length, batch_size, _) = x.dims3()?;
+            // The weight tensor for the input gate.
+            // Concatenation of `Wi` and `WBi` (if bidirectional).
+            // The tensor has shape `[num_directions, hidden_size, input_size]`.
+            let w = get(&node.input[1])?;
+            // The recurrence weight tensor.
+            // Concatenation of `Ri` and `RBi` (if bidirectional).
+            // This tensor has shape `[num_directions, hidden_size, hidden_size]`.
+            let r = get(&node.input[2])?;
+
+            // The bias tensor for input gate.
+            // Concatenation of `[Wbi, Rbi]` and `[WBbi, RBbi]` (if bidirectional).
+            // This tensor has shape `[num_directions, 2*hidden_size]`.
+            // Optional: If not specified - assumed to be 0.
+            let b_default: Tensor;
+            let b = match get_opt(3) {
+                Some(n) => n?,
+                None => {
+                    b_default = Tensor::zeros(
+                        (num_directions, 2 * hidden_size as usize),
+                        DType::F32,
+                        x.device(),
+                    )?;
+                    &b_default
+                }
+            };
+
+            // Optional tensor specifying lengths of the sequences in a batch.
+            // If not specified - assumed all sequences in the batch to have length `seq_length`.
+            // It has shape `[batch_size]`.
+            let seq_lens_default: Tensor;
+            let seq_lens = match get_opt(4) {
+                Some(n) => n?,
+                None => {
+                    seq_lens_default = Tensor::full(seq_length as i64, (batch_size,), x.device())?;
+                    &seq_lens_default
+                }
+            };
+            let seq_lens_is_default =
+                (seq_lens.to_vec1::<i64>()?.iter()).all(|e| *e as usize == seq_length);
+            if !seq_lens_is_default {
+                bail!("RNN currently does not support variable-length sequences. All sequences must use the full sequence length of {}", seq_length);
+            }
+
+            // Optional initial value of the hidden. If not specified - assumed to be 0.
+            // It has shape `[num_directions, batch_size, hidden_size]`.
+            let initial_h_default: Tensor;
+            let initial_h = match get_opt(5) {
+                Some(n) => n?,
+                _ => {
+                    initial_h_default = Tensor::zeros(
+                        (num_directions, batch_size, hidden_size as usize),
+                        DType::F32,
+                        x.device(),
+                    )?;
+                    &initial_h_default
+                }
+            };
+
+            fn choose_activation(activation: &str, x: &Tensor) -> Result<Tensor> {
+                match activation {
+                    "Tanh" => x.tanh(),
+                    _ => bail!("unsupported activation {activation}"),
+                }
+            }
+
+            // these all have [num_directions, ...] shapes
+            let w = w.get(0)?;
+            let r = r.get(0)?;
+            let b = b.get(0)?;
+            let idx_wb = Tensor::arange(0, hidden_size, x.device())?;
+            let idx_rb = Tensor::arange(hidden_size, 2 * hidden_size, x.device())?;
+            let wb = b.index_select(&idx_wb, 0)?;
+            let rb = b.index_select(&idx_rb, 0)?;
+            let mut h_t = initial_h.get(0)?;
+            let mut h_list: Vec<Tensor> = vec![];
+            for i in 0..seq_length {
+                let xs = x.get(i)?;
+                let h = xs
+                    .matmul(&w.t()?)?
+                    .add(&h_t.matmul(&r.t()?)?)?
+                    .add(&wb.unsqueeze(0)?)?
+                    .add(&rb.unsqueeze(0)?)?;
+                let h = choose_activation(&activations[0], &h)?;
+                h_list.push(h.to_owned());
+                h_t = h;
+            }
+            let h = Tensor::stack(&h_list, 0)?;
+            let h = h.reshape((seq_length, num_directions, batch_size, hidden_size as usize))?;
+            values.insert(node.output[0].clone(), h);
+            values.insert(
+                node.output[1].clone(),
+                h_t.reshape((num_directions, batch_size, hidden_size as usize))?,
+            );
+        }
+        // https://onnx.ai/onnx/operators/onnx__Xor.html
+        "Xor" => {
+            // Since we don't have a `DType::Bool` yet, this ensures that we are working with `0`(False) & `1`(True)
+            let a = get(&node.input[0])?.gt(0_u8)?;
+            let b = get(&node.input[1])?.gt(0_u8)?;
 
-                // Optional tensor specifying lengths of the sequences in a batch.
-                // If not specified - assumed all sequences in the batch to have length `seq_length`.
-                // It has shape `[batch_size]`.
-                let seq_lens_default: Tensor;
-                let seq_lens = match get_opt(4) {
-                    Some(n) => n?,
-                    None => {
-                        seq_lens_default =
-                            Tensor::full(seq_length as i64, (batch_size,), x.device())?;
-                        &seq_lens_default
-                    }
-                };
-                let seq_lens_is_default =
-                    (seq_lens.to_vec1::<i64>()?.iter()).all(|e| *e as usize == seq_length);
-                if !seq_lens_is_default {
-                    bail!("LSTM currently only supports default value of seq_lens");
-                }
-
-                // Optional initial value of the hidden. If not specified - assumed to be 0.
-                // It has shape `[num_directions, batch_size, hidden_size]`.
-                let initial_h_default: Tensor;
-                let initial_h = match get_opt(5) {
-                    Some(n) => n?,
-                    _ => {
-                        initial_h_default = Tensor::zeros(
-                            (num_directions, batch_size, hidden_size as usize),
-                            DType::F32,
-                            x.device(),
-                        )?;
-                        &initial_h_default
-                    }
-                };
+            let out = a.broadcast_add(&b)?.eq(1_u8)?;
 
-                // Optional initial value of the cell.
-                // If not specified - assumed to be 0.
-                // It has shape `[num_directions, batch_size, hidden_size]`.
-                let initial_c_default: Tensor;
-                let initial_c = match node.input.get(6) {
-                    Some(n) if !n.is_empty() => get(n)?,
-                    _ => {
-                        initial_c_default = Tensor::zeros(
-                            (num_directions, batch_size, hidden_size as usize),
-                            DType::F32,
-                            x.device(),
-                        )?;
-                        &initial_c_default
-                    }
-                };
+            values.insert(node.output[0].clone(), out);
+        }
+        // https://onnx.ai/onnx/operators/onnx__And.html
+        "And" => {
+            let a = get(&node.input[0])?.gt(0_u8)?;
+            let b = get(&node.input[1])?.gt(0_u8)?;
 
-                // The weight tensor for peepholes.
-                // Concatenation of `P[iof]` and `PB[iof]` (if bidirectional) along dimension 0.
-                // It has shape `[num_directions, 3*hidde_size]`. Optional: If not specified - assumed to be 0.
-                let p_default = Tensor::zeros(
-                    (num_directions, 3 * hidden_size as usize),
-                    DType::F32,
-                    x.device(),
-                )?;
-                let p = get_opt(7).unwrap_or(Ok(&p_default))?;
-                let p_is_zeros = (p.to_vec2::<f32>()?.iter()).all(|v| v.iter().all(|e| *e == 0.0));
-                if !p_is_zeros {
-                    bail!(
-                        "LSTM currently only supports default value of p (a Tensor of all zeroes)"
-                    );
-                }
-
-                // these all have [num_directions, ...] shapes
-                let w = w.get(0)?; // w[iofc] has shape [4*hidden_size, input_size]
-                let r = r.get(0)?; // r[iofc] has shape [4*hidden_size, hidden_size]
-                let b = b.get(0)?; // concat of [wb[iofc],rb[iofc]] has shape [8*hidden_size]
-                let idx_wb = Tensor::arange(0, 4 * hidden_size, x.device())?;
-                let idx_rb = Tensor::arange(4 * hidden_size, 8 * hidden_size, x.device())?;
-                let wb = b.index_select(&idx_wb, 0)?;
-                let rb = b.index_select(&idx_rb, 0)?;
-                let c = initial_c.get(0)?;
-                let h = initial_h.get(0)?;
-
-                // w, r, wb, rb are all iofc but lstm expects ifco
-                // so we need to move some stuff around
-                let idx_i = Tensor::arange(0, hidden_size, x.device())?;
-                let idx_o = Tensor::arange(hidden_size, 2 * hidden_size, x.device())?;
-                let idx_f = Tensor::arange(2 * hidden_size, 3 * hidden_size, x.device())?;
-                let idx_c = Tensor::arange(3 * hidden_size, 4 * hidden_size, x.device())?;
-                let idx_ifco = Tensor::cat(&[&idx_i, &idx_f, &idx_c, &idx_o], 0)?;
-                let w = w.index_select(&idx_ifco, 0)?;
-                let r = r.index_select(&idx_ifco, 0)?;
-                let wb = wb.index_select(&idx_ifco, 0)?;
-                let rb = rb.index_select(&idx_ifco, 0)?;
-                let vmap = candle_nn::VarMap::new();
-                vmap.data().lock().unwrap().extend([
-                    ("weight_ih_l0".to_string(), candle::Var::from_tensor(&w)?),
-                    ("weight_hh_l0".to_string(), candle::Var::from_tensor(&r)?),
-                    ("bias_ih_l0".to_string(), candle::Var::from_tensor(&wb)?),
-                    ("bias_hh_l0".to_string(), candle::Var::from_tensor(&rb)?),
-                ]);
-                use candle_nn::rnn::RNN as _;
-                let lstm = candle_nn::rnn::lstm(
-                    input_size,
-                    hidden_size as usize,
-                    candle_nn::rnn::LSTMConfig::default(),
-                    candle_nn::VarBuilder::from_varmap(&vmap, w.dtype(), w.device()),
-                )?;
+            let out = a.broadcast_mul(&b)?;
 
-                let mut lstm_state = candle_nn::rnn::LSTMState::new(h, c);
-                let mut h_acc = if node.output.first().map(String::as_str).unwrap_or("") != "" {
-                    Some(vec![])
-                } else {
-                    None
-                };
-                for t in 0..seq_length {
-                    let x = x.get(t)?;
-                    lstm_state = lstm.step(&x, &lstm_state)?;
-                    if let Some(h_acc) = &mut h_acc {
-                        h_acc.push(lstm_state.clone());
-                    }
-                }
+            values.insert(node.output[0].clone(), out);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Or.html
+        "Or" => {
+            let a = get(&node.input[0])?.gt(0_u8)?;
+            let b = get(&node.input[1])?.gt(0_u8)?;
 
-                assert_eq!(num_directions, 1, "if support for bidirectional is ever added, outputs will have to be concatenated, not simply reshaped");
-                if let Some(name) = node.output.first() {
-                    let h_acc = h_acc.as_ref().unwrap();
-                    let h_acc = lstm.states_to_tensor(h_acc)?;
-                    let h_acc = h_acc.reshape((
-                        seq_length,
-                        num_directions,
-                        batch_size,
-                        hidden_size as usize,
-                    ))?;
-                    values.insert(name.clone(), h_acc);
-                }
-                if let Some(name) = node.output.get(1) {
-                    values.insert(
-                        name.clone(),
-                        lstm_state.h().reshape((
-                            num_directions,
-                            batch_size,
-                            hidden_size as usize,
-                        ))?,
-                    );
-                }
-                if let Some(name) = node.output.get(2) {
-                    values.insert(
-                        name.clone(),
-                        lstm_state.c().reshape((
-                            num_directions,
-                            batch_size,
-                            hidden_size as usize,
-                        ))?,
-                    );
-                }
-            }
-            "RNN" => {
-                // activation_alpha and activation_beta don't apply to (Tanh, Tanh) so ignoring them is okay
-                let activations_default = vec!["Tanh".to_string(), "Tanh".to_string()];
-                let activations = get_attr_opt_owned::<Vec<String>>(node, "activations")?
-                    .unwrap_or(activations_default.clone());
-                let clip = get_attr_opt::<f32>(node, "clip")?.copied();
-                if clip.is_some() {
-                    bail!("RNN does not currently support clip attribute");
-                }
-                let direction = get_attr_opt(node, "direction")?.unwrap_or("forward");
-                if direction != "forward" {
-                    bail!("RNN currently only supports direction == \"forward\"");
-                }
-                let num_directions = if direction == "bidirectional" { 2 } else { 1 };
-                let hidden_size: i64 = get_attr(node, "hidden_size").copied()?;
-
-                // The shape format of inputs X, initial_h and outputs Y, Y_h.
-                // If 0, the following shapes are expected:
-                //    X.shape = [seq_length, batch_size, input_size],
-                //    Y.shape = [seq_length, num_directions, batch_size, hidden_size],
-                //    initial_h.shape = Y_h.shape = [num_directions, batch_size, hidden_size].
-                // If 1, the following shapes are expected:
-                //    X.shape = [batch_size, seq_length, input_size],
-                //    Y.shape = [batch_size, seq_length, num_directions, hidden_size],
-                //    initial_h.shape = Y_h.shape = [batch_size, num_directions, hidden_size].
-                let layout = get_attr_opt(node, "layout")?.copied().unwrap_or(0);
-                if layout != 0 {
-                    bail!("RNN currently only supports layout == 0");
-                }
-
-                // The input sequences packed (and potentially padded) into one 3-D tensor
-                // with the shape of `[seq_length, batch_size, input_size]`.
-                let x = get(&node.input[0])?;
-                // XXX: depends on layout
-                let (seq_length, batch_size, _) = x.dims3()?;
-                // The weight tensor for the input gate.
-                // Concatenation of `Wi` and `WBi` (if bidirectional).
-                // The tensor has shape `[num_directions, hidden_size, input_size]`.
-                let w = get(&node.input[1])?;
-                // The recurrence weight tensor.
-                // Concatenation of `Ri` and `RBi` (if bidirectional).
-                // This tensor has shape `[num_directions, hidden_size, hidden_size]`.
-                let r = get(&node.input[2])?;
-
-                // The bias tensor for input gate.
-                // Concatenation of `[Wbi, Rbi]` and `[WBbi, RBbi]` (if bidirectional).
-                // This tensor has shape `[num_directions, 2*hidden_size]`.
-                // Optional: If not specified - assumed to be 0.
-                let b_default: Tensor;
-                let b = match get_opt(3) {
-                    Some(n) => n?,
-                    None => {
-                        b_default = Tensor::zeros(
-                            (num_directions, 2 * hidden_size as usize),
-                            DType::F32,
-                            x.device(),
-                        )?;
-                        &b_default
-                    }
-                };
+            let out = a.broadcast_add(&b)?.gt(0_u8)?;
 
-                // Optional tensor specifying lengths of the sequences in a batch.
-                // If not specified - assumed all sequences in the batch to have length `seq_length`.
-                // It has shape `[batch_size]`.
-                let seq_lens_default: Tensor;
-                let seq_lens = match get_opt(4) {
-                    Some(n) => n?,
-                    None => {
-                        seq_lens_default =
-                            Tensor::full(seq_length as i64, (batch_size,), x.device())?;
-                        &seq_lens_default
-                    }
-                };
-                let seq_lens_is_default =
-                    (seq_lens.to_vec1::<i64>()?.iter()).all(|e| *e as usize == seq_length);
-                if !seq_lens_is_default {
-                    bail!("RNN currently does not support variable-length sequences. All sequences must use the full sequence length of {}", seq_length);
-                }
-
-                // Optional initial value of the hidden. If not specified - assumed to be 0.
-                // It has shape `[num_directions, batch_size, hidden_size]`.
-                let initial_h_default: Tensor;
-                let initial_h = match get_opt(5) {
-                    Some(n) => n?,
-                    _ => {
-                        initial_h_default = Tensor::zeros(
-                            (num_directions, batch_size, hidden_size as usize),
-                            DType::F32,
-                            x.device(),
-                        )?;
-                        &initial_h_default
-                    }
-                };
+            values.insert(node.output[0].clone(), out);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Sign.html
+        "Sign" => {
+            let input = get(&node.input[0])?;
+            let output = input.sign()?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Selu.html
+        "Selu" => {
+            let input = get(&node.input[0])?;
+            let alpha = get_attr_opt::<f32>(node, "alpha")?
+                .copied()
+                .unwrap_or(1.6732632);
+            let gamma = get_attr_opt::<f32>(node, "gamma")?
+                .copied()
+                .unwrap_or(1.050701);
+            let out = candle_nn::ops::selu(input, alpha as f32, gamma as f32)?;
+            values.insert(node.output[0].clone(), out);
+        }
 
-                fn choose_activation(activation: &str, x: &Tensor) -> Result<Tensor> {
-                    match activation {
-                        "Tanh" => x.tanh(),
-                        _ => bail!("unsupported activation {activation}"),
-                    }
+        // https://onnx.ai/onnx/operators/onnx__OneHot.html
+        "OneHot" => {
+            let indices = get(&node.input[0])?;
+            let orig_shape = get(&node.input[0])?.dims().to_vec();
+            let depth_tensor = get(&node.input[1])?;
+            let values_tensor = get(&node.input[2])?;
+
+            let depth = to_scalar_flexible::<i64>(depth_tensor)? as usize;
+            let values_vec = values_tensor.to_vec1::<f32>()?;
+            if values_vec.len() != 2 {
+                return Err(candle::Error::Msg(
+                    "OneHot: expected 2-element values tensor".to_string(),
+                ));
+            }
+            let off_value = values_vec[0];
+            let on_value = values_vec[1];
+
+            let mut axis = node
+                .attribute
+                .iter()
+                .find(|attr| attr.name == "axis")
+                .map(|attr| attr.i)
+                .unwrap_or(-1);
+
+            let rank = indices.rank();
+            if axis < -((rank as i64) + 1) || axis > (rank as i64) {
+                return Err(candle::Error::Msg(format!(
+                    "OneHot: invalid axis {axis} for rank {rank}"
+                )));
+            }
+            if axis < 0 {
+                axis += rank as i64 + 1;
+            }
+
+            let indices = indices.flatten_all()?;
+            let indices_vec = indices.to_vec1::<i64>()?;
+            let mut out = vec![off_value; depth * indices.elem_count()];
+            for (i, &index) in indices_vec.iter().enumerate() {
+                let idx = if index < 0 {
+                    (index + depth as i64) as usize
+                } else {
+                    index as usize
+                };
+                if idx >= depth {
+                    continue;
                 }
-
-                // these all have [num_directions, ...] shapes
-                let w = w.get(0)?;
-                let r = r.get(0)?;
-                let b = b.get(0)?;
-                let idx_wb = Tensor::arange(0, hidden_size, x.device())?;
-                let idx_rb = Tensor::arange(hidden_size, 2 * hidden_size, x.device())?;
-                let wb = b.index_select(&idx_wb, 0)?;
-                let rb = b.index_select(&idx_rb, 0)?;
-                let mut h_t = initial_h.get(0)?;
-                let mut h_list: Vec<Tensor> = vec![];
-                for i in 0..seq_length {
-                    let xs = x.get(i)?;
-                    let h = xs
-                        .matmul(&w.t()?)?
-                        .add(&h_t.matmul(&r.t()?)?)?
-                        .add(&wb.unsqueeze(0)?)?
-                        .add(&rb.unsqueeze(0)?)?;
-                    let h = choose_activation(&activations[0], &h)?;
-                    h_list.push(h.to_owned());
-                    h_t = h;
-                }
-                let h = Tensor::stack(&h_list, 0)?;
-                let h =
-                    h.reshape((seq_length, num_directions, batch_size, hidden_size as usize))?;
-                values.insert(node.output[0].clone(), h);
-                values.insert(
-                    node.output[1].clone(),
-                    h_t.reshape((num_directions, batch_size, hidden_size as usize))?,
-                );
+                out[i * depth + idx] = on_value;
             }
-            // https://onnx.ai/onnx/operators/onnx__Xor.html
-            "Xor" => {
-                // Since we don't have a `DType::Bool` yet, this ensures that we are working with `0`(False) & `1`(True)
-                let a = get(&node.input[0])?.gt(0_u8)?;
-                let b = get(&node.input[1])?.gt(0_u8)?;
 
-                let out = a.broadcast_add(&b)?.eq(1_u8)?;
+            let mut target_shape = orig_shape;
+            target_shape.push(depth);
+            let output = Tensor::from_vec(out, target_shape, indices.device())?;
 
-                values.insert(node.output[0].clone(), out);
-            }
-            // https://onnx.ai/onnx/operators/onnx__And.html
-            "And" => {
-                let a = get(&node.input[0])?.gt(0_u8)?;
-                let b = get(&node.input[1])?.gt(0_u8)?;
+            let final_output = if axis as usize == output.rank() - 1 {
+                output
+            } else {
+                fn move_axis_to(rank: usize, from: usize, to: usize) -> Vec<usize> {
+                    let mut dims: Vec<usize> = (0..rank).collect();
+                    let axis = dims.remove(from);
+                    dims.insert(to, axis);
+                    dims
+                }
 
-                let out = a.broadcast_mul(&b)?;
+                let perm = move_axis_to(output.rank(), output.rank() - 1, axis as usize);
+                output.permute(&*perm)?
+            };
+            values.insert(node.output[0].clone(), final_output);
+        }
+        "HardSwish" => {
+            let input = get(&node.input[0])?;
+            let hard_sigmoid = candle_nn::ops::hard_sigmoid(&input)?;
+            let output = input * hard_sigmoid;
+            values.insert(node.output[0].clone(), output?);
+        }
+        "Resize" => {
+            let input = get(&node.input[0])?;
 
-                values.insert(node.output[0].clone(), out);
+            if input.rank() != 4 {
+                bail!("Unsupported rank for nearest resize: {}", input.rank());
             }
-            // https://onnx.ai/onnx/operators/onnx__Or.html
-            "Or" => {
-                let a = get(&node.input[0])?.gt(0_u8)?;
-                let b = get(&node.input[1])?.gt(0_u8)?;
 
-                let out = a.broadcast_add(&b)?.gt(0_u8)?;
+            let scales = if node.input.len() > 2 && !node.input[2].is_empty() {
+                Some(get(&node.input[2])?)
+            } else {
+                None
+            };
 
-                values.insert(node.output[0].clone(), out);
-            }
-            // https://onnx.ai/onnx/operators/onnx__Sign.html
-            "Sign" => {
-                let input = get(&node.input[0])?;
-                let output = input.sign()?;
-                values.insert(node.output[0].clone(), output);
-            }
-            // https://onnx.ai/onnx/operators/onnx__Selu.html
-            "Selu" => {
-                let input = get(&node.input[0])?;
-                let alpha = get_attr_opt::<f32>(node, "alpha")?
-                    .copied()
-                    .unwrap_or(1.6732632);
-                let gamma = get_attr_opt::<f32>(node, "gamma")?
-                    .copied()
-                    .unwrap_or(1.050701);
-                let out = candle_nn::ops::selu(input, alpha as f32, gamma as f32)?;
-                values.insert(node.output[0].clone(), out);
-            }
-
-            // https://onnx.ai/onnx/operators/onnx__OneHot.html
-            "OneHot" => {
-                let indices = get(&node.input[0])?;
-                let orig_shape = get(&node.input[0])?.dims().to_vec();
-                let depth_tensor = get(&node.input[1])?;
-                let values_tensor = get(&node.input[2])?;
-
-                let depth = to_scalar_flexible::<i64>(depth_tensor)? as usize;
-                let values_vec = values_tensor.to_vec1::<f32>()?;
-                if values_vec.len() != 2 {
-                    return Err(candle::Error::Msg(
-                        "OneHot: expected 2-element values tensor".to_string(),
-                    ));
-                }
-                let off_value = values_vec[0];
-                let on_value = values_vec[1];
-
-                let mut axis = node
-                    .attribute
-                    .iter()
-                    .find(|attr| attr.name == "axis")
-                    .map(|attr| attr.i)
-                    .unwrap_or(-1);
-
-                let rank = indices.rank();
-                if axis < -((rank as i64) + 1) || axis > (rank as i64) {
-                    return Err(candle::Error::Msg(format!(
-                        "OneHot: invalid axis {axis} for rank {rank}"
-                    )));
-                }
-                if axis < 0 {
-                    axis += rank as i64 + 1;
-                }
-
-                let indices = indices.flatten_all()?;
-                let indices_vec = indices.to_vec1::<i64>()?;
-                let mut out = vec![off_value; depth * indices.elem_count()];
-                for (i, &index) in indices_vec.iter().enumerate() {
-                    let idx = if index < 0 {
-                        (index + depth as i64) as usize
-                    } else {
-                        index as usize
-                    };
-                    if idx >= depth {
-                        continue;
-                    }
-                    out[i * depth + idx] = on_value;
-                }
+            let sizes = if node.input.len() > 3 && !node.input[3].is_empty() {
+                Some(get(&node.input[3])?)
+            } else {
+                None
+            };
 
-                let mut target_shape = orig_shape;
-                target_shape.push(depth);
-                let output = Tensor::from_vec(out, target_shape, indices.device())?;
+            let output_dims = match (scales, sizes) {
+                (Some(_), Some(_)) => {
+                    bail!("Scales and sizes cannot both be set for Resize operation")
+                }
+                (Some(scales_tensor), None) => {
+                    let scale_values = scales_tensor.to_vec1::<f32>()?;
+                    input
+                        .dims()
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &d)| (d as f32 * scale_values[i]) as usize)
+                        .collect::<Vec<_>>()
+                }
+                (None, Some(sizes_tensor)) => sizes_tensor
+                    .to_vec1::<i64>()?
+                    .iter()
+                    .map(|&d| d as usize)
+                    .collect::<Vec<_>>(),
+                (None, None) => bail!("Either scales or sizes should be present"),
+            };
 
-                let final_output = if axis as usize == output.rank() - 1 {
-                    output
-                } else {
-                    fn move_axis_to(rank: usize, from: usize, to: usize) -> Vec<usize> {
-                        let mut dims: Vec<usize> = (0..rank).collect();
-                        let axis = dims.remove(from);
-                        dims.insert(to, axis);
-                        dims
-                    }
+            let coordinate_transformation_mode =
+                get_attr_opt::<str>(node, "coordinate_transformation_mode")?
+                    .unwrap_or("half_pixel");
+            // Interpolation mode: nearest, linear, or cubic.
+            let mode = get_attr_opt::<str>(node, "mode")?.unwrap_or("nearest");
+            // How to determine the "nearest" pixel in nearest interpolation mode.
+            let nearest_mode =
+                get_attr_opt::<str>(node, "nearest_mode")?.unwrap_or("round_prefer_floor");
 
-                    let perm = move_axis_to(output.rank(), output.rank() - 1, axis as usize);
-                    output.permute(&*perm)?
-                };
-                values.insert(node.output[0].clone(), final_output);
+            if mode != "nearest" {
+                bail!("Unsupported resize mode: {}", mode);
             }
-            "HardSwish" => {
-                let input = get(&node.input[0])?;
-                let hard_sigmoid = candle_nn::ops::hard_sigmoid(&input)?;
-                let output = input * hard_sigmoid;
-                values.insert(node.output[0].clone(), output?);
+
+            if nearest_mode != "floor" {
+                bail!("Unsupported nearest_mode for resize: {}", nearest_mode);
             }
-            "Resize" => {
-                let input = get(&node.input[0])?;
 
-                if input.rank() != 4 {
-                    bail!("Unsupported rank for nearest resize: {}", input.rank());
-                }
+            if coordinate_transformation_mode != "asymmetric" {
+                bail!(
+                    "Unsupported coordinate_transformation_mode for resize: {}",
+                    coordinate_transformation_mode
+                );
+            }
 
-                let scales = if node.input.len() > 2 && !node.input[2].is_empty() {
-                    Some(get(&node.input[2])?)
-                } else {
-                    None
-                };
+            let h = output_dims[2];
+            let w = output_dims[3];
+            let output = input.upsample_nearest2d(h, w)?;
 
-                let sizes = if node.input.len() > 3 && !node.input[3].is_empty() {
-                    Some(get(&node.input[3])?)
-                } else {
-                    None
-                };
+            values.insert(node.output[0].clone(), output);
+        }
+        "Trilu" => {
+            let input = get(&node.input[0])?;
 
-                let output_dims = match (scales, sizes) {
-                    (Some(_), Some(_)) => {
-                        bail!("Scales and sizes cannot both be set for Resize operation")
-                    }
-                    (Some(scales_tensor), None) => {
-                        let scale_values = scales_tensor.to_vec1::<f32>()?;
-                        input
-                            .dims()
-                            .iter()
-                            .enumerate()
-                            .map(|(i, &d)| (d as f32 * scale_values[i]) as usize)
-                            .collect::<Vec<_>>()
+            // Get the diagonal offset 'k' from the second input if provided
+            let k = if node.input.len() > 1 && !node.input[1].is_empty() {
+                to_vec0_flexible::<i64>(get(&node.input[1])?)?
+            } else {
+                0
+            };
+
+            // Get the 'upper' attribute
+            let upper = get_attr_opt::<i64>(node, "upper")?.copied().unwrap_or(1);
+
+            // For batched inputs, we need to handle each matrix separately
+            let dims = input.dims();
+            if dims.len() < 2 {
+                bail!("Trilu expects input with at least 2 dimensions: {:?}", dims);
+            }
+
+            // Get the last two dimensions which represent the matrix
+            let n = dims[dims.len() - 2];
+            let m = dims[dims.len() - 1];
+            let max_dim = std::cmp::max(n, m);
+
+            // Handle the diagonal offset k
+            let mask = if k != 0 {
+                let mut data = vec![0u32; n * m];
+                for i in 0..n {
+                    for j in 0..m {
+                        if (upper != 0 && (j as i64) >= (i as i64) + k)
+                            || (upper == 0 && (j as i64) <= (i as i64) + k)
+                        {
+                            data[i * m + j] = 1u32;
+                        }
                     }
-                    (None, Some(sizes_tensor)) => sizes_tensor
-                        .to_vec1::<i64>()?
-                        .iter()
-                        .map(|&d| d as usize)
-                        .collect::<Vec<_>>(),
-                    (None, None) => bail!("Either scales or sizes should be present"),
-                };
-
-                let coordinate_transformation_mode =
-                    get_attr_opt::<str>(node, "coordinate_transformation_mode")?
-                        .unwrap_or("half_pixel");
-                // Interpolation mode: nearest, linear, or cubic.
-                let mode = get_attr_opt::<str>(node, "mode")?.unwrap_or("nearest");
-                // How to determine the "nearest" pixel in nearest interpolation mode.
-                let nearest_mode =
-                    get_attr_opt::<str>(node, "nearest_mode")?.unwrap_or("round_prefer_floor");
-
-                if mode != "nearest" {
-                    bail!("Unsupported resize mode: {}", mode);
                 }
+                Tensor::from_vec(data, (n, m), input.device())?.to_dtype(input.dtype())?
+            } else if upper == 0 {
+                Tensor::tril2(max_dim, input.dtype(), input.device())?
+            } else {
+                Tensor::triu2(max_dim, input.dtype(), input.device())?
+            };
 
-                if nearest_mode != "floor" {
-                    bail!("Unsupported nearest_mode for resize: {}", nearest_mode);
-                }
+            let final_mask = if n != m {
+                mask.narrow(0, 0, n)?.narrow(1, 0, m)?
+            } else {
+                mask
+            };
 
-                if coordinate_transformation_mode != "asymmetric" {
-                    bail!(
-                        "Unsupported coordinate_transformation_mode for resize: {}",
-                        coordinate_transformation_mode
-                    );
-                }
+            let output = (input * &final_mask)?;
 
-                let h = output_dims[2];
-                let w = output_dims[3];
-                let output = input.upsample_nearest2d(h, w)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "ScatterND" => {
+            let data = get(&node.input[0])?;
 
-                values.insert(node.output[0].clone(), output);
-            }
-            "Trilu" => {
-                let input = get(&node.input[0])?;
+            let indices = get(&node.input[1])?;
+            let indices = indices.to_dtype(DType::I64)?;
 
-                // Get the diagonal offset 'k' from the second input if provided
-                let k = if node.input.len() > 1 && !node.input[1].is_empty() {
-                    to_vec0_flexible::<i64>(get(&node.input[1])?)?
-                } else {
-                    0
-                };
+            let updates = get(&node.input[2])?;
 
-                // Get the 'upper' attribute
-                let upper = get_attr_opt::<i64>(node, "upper")?.copied().unwrap_or(1);
-
-                // For batched inputs, we need to handle each matrix separately
-                let dims = input.dims();
-                if dims.len() < 2 {
-                    bail!("Trilu expects input with at least 2 dimensions: {:?}", dims);
-                }
-
-                // Get the last two dimensions which represent the matrix
-                let n = dims[dims.len() - 2];
-                let m = dims[dims.len() - 1];
-                let max_dim = std::cmp::max(n, m);
-
-                // Handle the diagonal offset k
-                let mask = if k != 0 {
-                    let mut data = vec![0u32; n * m];
-                    for i in 0..n {
-                        for j in 0..m {
-                            if (upper != 0 && (j as i64) >= (i as i64) + k)
-                                || (upper == 0 && (j as i64) <= (i as i64) + k)
-                            {
-                                data[i * m + j] = 1u32;
-                            }
-                        }
-                    }
-                    Tensor::from_vec(data, (n, m), input.device())?.to_dtype(input.dtype())?
-                } else if upper == 0 {
-                    Tensor::tril2(max_dim, input.dtype(), input.device())?
-                } else {
-                    Tensor::triu2(max_dim, input.dtype(), input.device())?
-                };
+            let reduction = get_attr_opt::<str>(node, "reduction")?.unwrap_or("none");
 
-                let final_mask = if n != m {
-                    mask.narrow(0, 0, n)?.narrow(1, 0, m)?
-                } else {
-                    mask
-                };
+            let indices_shape = indices.dims();
+            let data_shape = data.dims();
+            let _updates_shape = updates.dims();
 
-                let output = (input * &final_mask)?;
+            // Last dimension of indices represents the depth of indexing
+            let k = indices_shape.last().unwrap().clone();
 
-                values.insert(node.output[0].clone(), output);
+            if k > data.rank() {
+                bail!("ScatterND expects k (indices.shape[-1]) to be at most the rank of data");
             }
-            "ScatterND" => {
-                let data = get(&node.input[0])?;
-
-                let indices = get(&node.input[1])?;
-                let indices = indices.to_dtype(DType::I64)?;
-
-                let updates = get(&node.input[2])?;
 
-                let reduction = get_attr_opt::<str>(node, "reduction")?.unwrap_or("none");
+            let num_updates = indices_shape[..indices_shape.len() - 1]
+                .iter()
+                .product::<usize>();
 
-                let indices_shape = indices.dims();
-                let data_shape = data.dims();
-                let _updates_shape = updates.dims();
-
-                // Last dimension of indices represents the depth of indexing
-                let k = indices_shape.last().unwrap().clone();
+            let flat_indices = if indices.rank() == 1 && k == 1 {
+                indices.unsqueeze(0)?
+            } else {
+                indices.reshape((num_updates, k))?
+            };
 
-                if k > data.rank() {
-                    bail!("ScatterND expects k (indices.shape[-1]) to be at most the rank of data");
+            // Calculate the shape of each update element
+            let update_element_shape = if k < data_shape.len() {
+                data_shape[k..].to_vec()
+            } else {
+                vec![]
+            };
+
+            // Expected shape for updates based on indices and target tensor
+            let expected_updates_shape = {
+                let mut shape = indices_shape[..indices_shape.len() - 1].to_vec();
+                shape.extend(&update_element_shape);
+                shape
+            };
+
+            // Validate or reshape updates to expected shape
+            let updates = if updates.dims() != expected_updates_shape {
+                if updates.rank() == 0 {
+                    // Handle scalar updates
+                    let mut target_shape = vec![num_updates];
+                    target_shape.extend(&update_element_shape);
+                    updates.broadcast_as(target_shape)?
+                } else {
+                    // Try to broadcast or reshape updates to expected shape
+                    let flat_shape =
+                        vec![num_updates, update_element_shape.iter().product::<usize>()];
+                    let flattened = updates.reshape(flat_shape)?;
+                    flattened.reshape(expected_updates_shape)?
                 }
+            } else {
+                updates.clone()
+            };
 
-                let num_updates = indices_shape[..indices_shape.len() - 1]
-                    .iter()
-                    .product::<usize>();
+            let mut output = data.clone();
 
-                let flat_indices = if indices.rank() == 1 && k == 1 {
-                    indices.unsqueeze(0)?
-                } else {
-                    indices.reshape((num_updates, k))?
-                };
+            // convert indices to flat indices
+            let mut flat_output = output.flatten_all()?;
+            let flat_updates = if update_element_shape.is_empty() {
+                updates.reshape(num_updates)?
+            } else {
+                let product = update_element_shape.iter().product::<usize>();
+                updates.reshape((num_updates, product))?
+            };
 
-                // Calculate the shape of each update element
-                let update_element_shape = if k < data_shape.len() {
-                    data_shape[k..].to_vec()
-                } else {
-                    vec![]
-                };
+            // Calculate strides for the output tensor
+            let mut strides: Vec<usize> = vec![1];
+            for i in (0..data_shape.len() - 1).rev() {
+                strides.push(strides.last().unwrap() * data_shape[i + 1]);
+            }
+            strides.reverse();
 
-                // Expected shape for updates based on indices and target tensor
-                let expected_updates_shape = {
-                    let mut shape = indices_shape[..indices_shape.len() - 1].to_vec();
-                    shape.extend(&update_element_shape);
-                    shape
-                };
+            // Process each update
+            for i in 0..num_updates {
+                let index_slice = flat_indices.narrow(0, i, 1)?;
+                let indices_vec = index_slice.squeeze(0)?.to_vec1::<i64>()?;
 
-                // Validate or reshape updates to expected shape
-                let updates = if updates.dims() != expected_updates_shape {
-                    if updates.rank() == 0 {
-                        // Handle scalar updates
-                        let mut target_shape = vec![num_updates];
-                        target_shape.extend(&update_element_shape);
-                        updates.broadcast_as(target_shape)?
-                    } else {
-                        // Try to broadcast or reshape updates to expected shape
-                        let flat_shape =
-                            vec![num_updates, update_element_shape.iter().product::<usize>()];
-                        let flattened = updates.reshape(flat_shape)?;
-                        flattened.reshape(expected_updates_shape)?
+                // Convert multi-dimensional indices to flat index
+                let mut flat_idx: usize = 0;
+                for (dim, &idx) in indices_vec.iter().enumerate() {
+                    let dim_size = data_shape[dim] as i64;
+                    let norm_idx = if idx < 0 { dim_size + idx } else { idx };
+
+                    if norm_idx < 0 || norm_idx >= dim_size {
+                        bail!(
+                            "Index {} out of bounds for dimension {} with size {}",
+                            idx,
+                            dim,
+                            dim_size
+                        );
                     }
-                } else {
-                    updates.clone()
-                };
 
-                let mut output = data.clone();
+                    flat_idx += (norm_idx as usize) * strides[dim];
+                }
 
-                // convert indices to flat indices
-                let mut flat_output = output.flatten_all()?;
-                let flat_updates = if update_element_shape.is_empty() {
-                    updates.reshape(num_updates)?
+                // Extract current update
+                let update_slice = if update_element_shape.is_empty() {
+                    flat_updates.narrow(0, i, 1)?.squeeze(0)?
                 } else {
-                    let product = update_element_shape.iter().product::<usize>();
-                    updates.reshape((num_updates, product))?
+                    flat_updates.narrow(0, i, 1)?
                 };
 
-                // Calculate strides for the output tensor
-                let mut strides: Vec<usize> = vec![1];
-                for i in (0..data_shape.len() - 1).rev() {
-                    strides.push(strides.last().unwrap() * data_shape[i + 1]);
-                }
-                strides.reverse();
-
-                // Process each update
-                for i in 0..num_updates {
-                    let index_slice = flat_indices.narrow(0, i, 1)?;
-                    let indices_vec = index_slice.squeeze(0)?.to_vec1::<i64>()?;
-
-                    // Convert multi-dimensional indices to flat index
-                    let mut flat_idx: usize = 0;
-                    for (dim, &idx) in indices_vec.iter().enumerate() {
-                        let dim_size = data_shape[dim] as i64;
-                        let norm_idx = if idx < 0 { dim_size + idx } else { idx };
-
-                        if norm_idx < 0 || norm_idx >= dim_size {
-                            bail!(
-                                "Index {} out of bounds for dimension {} with size {}",
-                                idx,
-                                dim,
-                                dim_size
-                            );
+                match reduction {
+                    "add" => {
+                        if update_element_shape.is_empty() {
+                            let existing = flat_output.narrow(0, flat_idx, 1)?;
+                            let new_value = existing.add(&update_slice.unsqueeze(0)?)?;
+                            flat_output = flat_output.slice_scatter(&new_value, 0, flat_idx)?;
+                        } else {
+                            let slice_size = update_element_shape.iter().product::<usize>();
+                            let existing = flat_output.narrow(0, flat_idx, slice_size)?;
+                            let new_value = existing.add(&update_slice)?;
+                            flat_output = flat_output.slice_scatter(&new_value, 0, flat_idx)?;
                         }
-
-                        flat_idx += (norm_idx as usize) * strides[dim];
                     }
-
-                    // Extract current update
-                    let update_slice = if update_element_shape.is_empty() {
-                        flat_updates.narrow(0, i, 1)?.squeeze(0)?
-                    } else {
-                        flat_updates.narrow(0, i, 1)?
-                    };
-
-                    match reduction {
-                        "add" => {
-                            if update_element_shape.is_empty() {
-                                let existing = flat_output.narrow(0, flat_idx, 1)?;
-                                let new_value = existing.add(&update_slice.unsqueeze(0)?)?;
-                                flat_output = flat_output.slice_scatter(&new_value, 0, flat_idx)?;
-                            } else {
-                                let slice_size = update_element_shape.iter().product::<usize>();
-                                let existing = flat_output.narrow(0, flat_idx, slice_size)?;
-                                let new_value = existing.add(&update_slice)?;
-                                flat_output = flat_output.slice_scatter(&new_value, 0, flat_idx)?;
-                            }
-                        }
-                        "none" | _ => {
-                            if update_element_shape.is_empty() {
-                                flat_output = flat_output.slice_scatter(
-                                    &update_slice.unsqueeze(0)?,
-                                    0,
-                                    flat_idx,
-                                )?;
-                            } else {
-                                flat_output =
-                                    flat_output.slice_scatter(&update_slice, 0, flat_idx)?;
-                            }
+                    "none" | _ => {
+                        if update_element_shape.is_empty() {
+                            flat_output = flat_output.slice_scatter(
+                                &update_slice.unsqueeze(0)?,
+                                0,
+                                flat_idx,
+                            )?;
+                        } else {
+                            flat_output = flat_output.slice_scatter(&update_slice, 0, flat_idx)?;
                         }
                     }
                 }
+            }
 
-                // Reshape flat output back to original shape
-                output = flat_output.reshape(data_shape.to_vec())?;
+            // Reshape flat output back to original shape
+            output = flat_output.reshape(data_shape.to_vec())?;
 
-                values.insert(node.output[0].clone(), output);
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__LayerNormalization.html
+        "LayerNormalization" => {
+            let xs = get(&node.input[0])?;
+            let scale = get(&node.input[1])?;
+            let bias = get_opt(2).transpose()?;
+            let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(-1);
+            let axis = xs.normalize_axis(axis)?;
+            let eps = get_attr_opt::<f32>(node, "epsilon")?
+                .copied()
+                .unwrap_or(1e-5);
+            let (ys, mean, inv_std) = layer_norm(xs, axis, eps as f64, scale, bias)?;
+            values.insert(node.output[0].clone(), ys);
+            for (name, value) in node.output.iter().skip(1).zip([mean, inv_std]) {
+                if !name.is_empty() {
+                    values.insert(name.clone(), value);
+                }
+            }
+        }
+        // https://onnx.ai/onnx/operators/onnx__InstanceNormalization.html
+        "InstanceNormalization" => {
+            let xs = get(&node.input[0])?;
+            let scale = get(&node.input[1])?;
+            let bias = get(&node.input[2])?;
+            let eps = get_attr_opt::<f32>(node, "epsilon")?
+                .copied()
+                .unwrap_or(1e-5);
+            if xs.rank() < 3 {
+                bail!(
+                    "InstanceNormalization expects at least 3 dims, got {:?}",
+                    xs.shape()
+                )
+            }
+            // The scale and bias apply to the channel dimension.
+            let mut shape = vec![1; xs.rank()];
+            shape[1] = xs.dim(1)?;
+            let scale = scale.reshape(shape.as_slice())?;
+            let bias = bias.reshape(shape.as_slice())?;
+            let (ys, _, _) = layer_norm(xs, 2, eps as f64, &scale, Some(&bias))?;
+            values.insert(node.output[0].clone(), ys);
+        }
+        // https://onnx.ai/onnx/operators/onnx__GlobalAveragePool.html
+        "GlobalAveragePool" => {
+            let xs = get(&node.input[0])?;
+            let output = xs.mean_keepdim((2..xs.rank()).collect::<Vec<_>>())?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Einsum.html
+        "Einsum" => {
+            let equation = get_attr::<str>(node, "equation")?;
+            let operands = node
+                .input
+                .iter()
+                .map(|name| get(name).cloned())
+                .collect::<Result<Vec<_>>>()?;
+            let output = einsum(equation, &operands)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__TopK.html
+        "TopK" => {
+            let xs = get(&node.input[0])?;
+            let k = to_scalar_flexible::<i64>(get(&node.input[1])?)?;
+            let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(-1);
+            let axis = xs.normalize_axis(axis)?;
+            let largest = get_attr_opt::<i64>(node, "largest")?.copied().unwrap_or(1) == 1;
+            if k < 0 || k as usize > xs.dim(axis)? {
+                bail!("TopK: invalid k {k} for dim {axis} of {:?}", xs.shape())
+            }
+            // The results are always sorted, which is also valid when `sorted` is 0. The sort is
+            // stable so the lower indices come first on ties.
+            let last = xs.rank() - 1;
+            let xs = xs.transpose(axis, last)?.contiguous()?;
+            let indices = xs
+                .arg_sort_last_dim(!largest)?
+                .narrow(last, 0, k as usize)?
+                .contiguous()?;
+            let topk = xs.gather(&indices, last)?.transpose(axis, last)?;
+            let indices = indices.transpose(axis, last)?.to_dtype(DType::I64)?;
+            values.insert(node.output[0].clone(), topk.contiguous()?);
+            values.insert(node.output[1].clone(), indices.contiguous()?);
+        }
+        // https://onnx.ai/onnx/operators/onnx__NonZero.html
+        "NonZero" => {
+            let xs = get(&node.input[0])?;
+            let dims = xs.dims().to_vec();
+            let xs = xs.flatten_all()?.to_dtype(DType::F64)?.to_vec1::<f64>()?;
+            let nonzero = xs
+                .iter()
+                .enumerate()
+                .filter(|(_, &v)| v != 0.)
+                .map(|(idx, _)| idx)
+                .collect::<Vec<_>>();
+            let n = nonzero.len();
+            let mut output = vec![0i64; dims.len() * n];
+            for (i, &idx) in nonzero.iter().enumerate() {
+                let mut idx = idx;
+                for (d, &dim) in dims.iter().enumerate().rev() {
+                    output[d * n + i] = (idx % dim) as i64;
+                    idx /= dim
+                }
+            }
+            let output = Tensor::from_vec(output, (dims.len(), n), &Device::Cpu)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__ScatterElements.html
+        "ScatterElements" => {
+            let data = get(&node.input[0])?;
+            let indices = get(&node.input[1])?;
+            let updates = get(&node.input[2])?;
+            let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(0);
+            let axis = data.normalize_axis(axis)?;
+            let reduction = get_attr_opt::<str>(node, "reduction")?.unwrap_or("none");
+            if indices.rank() != data.rank() || indices.dims() != updates.dims() {
+                bail!(
+                    "ScatterElements: incompatible shapes, data {:?}, indices {:?}, updates {:?}",
+                    data.shape(),
+                    indices.shape(),
+                    updates.shape()
+                )
+            }
+            let output = match data.dtype() {
+                DType::U8 => scatter_elements::<u8>(data, indices, updates, axis, reduction)?,
+                DType::U32 => scatter_elements::<u32>(data, indices, updates, axis, reduction)?,
+                DType::I64 => scatter_elements::<i64>(data, indices, updates, axis, reduction)?,
+                DType::F64 => scatter_elements::<f64>(data, indices, updates, axis, reduction)?,
+                dtype @ (DType::I32 | DType::I16) => scatter_elements::<i64>(
+                    &data.to_dtype(DType::I64)?,
+                    indices,
+                    &updates.to_dtype(DType::I64)?,
+                    axis,
+                    reduction,
+                )?
+                .to_dtype(dtype)?,
+                dtype => scatter_elements::<f32>(
+                    &data.to_dtype(DType::F32)?,
+                    indices,
+                    &updates.to_dtype(DType::F32)?,
+                    axis,
+                    reduction,
+                )?
+                .to_dtype(dtype)?,
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Max.html
+        "Max" => {
+            let mut output = get(&node.input[0])?.clone();
+            for input in node.input.iter() {
+                let input = get(input)?;
+                output = output.broadcast_maximum(input)?
+            }
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Mean.html
+        "Mean" => {
+            let mut output = get(&node.input[0])?.clone();
+            for input in node.input.iter().skip(1) {
+                output = output.broadcast_add(get(input)?)?
+            }
+            let output = (output / node.input.len() as f64)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Mod.html
+        "Mod" => {
+            let a = get(&node.input[0])?;
+            let b = get(&node.input[1])?;
+            let fmod = get_attr_opt::<i64>(node, "fmod")?.copied().unwrap_or(0) == 1;
+            let shape = broadcast_shape(a.dims(), b.dims())?;
+            let a = a.broadcast_as(shape.as_slice())?;
+            let b = b.broadcast_as(shape.as_slice())?;
+            // The remainder of the truncated division, it has the sign of the dividend.
+            let rem = if a.dtype().is_float() {
+                let q = (&a / &b)?;
+                let q = (q.abs()?.floor()? * q.sign()?)?;
+                (&a - (q * &b)?)?
+            } else {
+                (&a - ((&a / &b)? * &b)?)?
+            };
+            let output = if fmod {
+                rem
+            } else {
+                // The remainder of the floored division has the sign of the divisor.
+                let zeros = rem.zeros_like()?;
+                let adjust = rem
+                    .ne(&zeros)?
+                    .mul(&rem.lt(&zeros)?.ne(&b.lt(&zeros)?)?)?
+                    .to_dtype(rem.dtype())?;
+                (&rem + (b * adjust)?)?
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Reciprocal.html
+        "Reciprocal" => {
+            let xs = get(&node.input[0])?;
+            values.insert(node.output[0].clone(), xs.recip()?);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Softplus.html
+        "Softplus" => {
+            let xs = get(&node.input[0])?;
+            // log(1 + exp(x)) computed as max(x, 0) + log(1 + exp(-|x|)) to avoid overflows.
+            let output = (xs.relu()? + (xs.abs()?.neg()?.exp()? + 1.)?.log()?)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__HardSigmoid.html
+        "HardSigmoid" => {
+            let xs = get(&node.input[0])?;
+            let alpha = get_attr_opt::<f32>(node, "alpha")?.copied().unwrap_or(0.2);
+            let beta = get_attr_opt::<f32>(node, "beta")?.copied().unwrap_or(0.5);
+            let output = xs.affine(alpha as f64, beta as f64)?.clamp(0f32, 1f32)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Elu.html
+        "Elu" => {
+            let xs = get(&node.input[0])?;
+            let alpha = get_attr_opt::<f32>(node, "alpha")?.copied().unwrap_or(1.0);
+            values.insert(node.output[0].clone(), xs.elu(alpha as f64)?);
+        }
+        // The com.microsoft contrib ops that onnxruntime optimized exports use, see
+        // https://github.com/microsoft/onnxruntime/blob/main/docs/ContribOperators.md
+        "SkipLayerNormalization" => {
+            let xs = get(&node.input[0])?;
+            let skip = get(&node.input[1])?;
+            let gamma = get(&node.input[2])?;
+            let beta = get_opt(3).transpose()?;
+            let bias = get_opt(4).transpose()?;
+            let eps = get_attr_opt::<f32>(node, "epsilon")?
+                .copied()
+                .unwrap_or(1e-12);
+            let xs = xs.broadcast_add(skip)?;
+            let xs = match bias {
+                Some(bias) => xs.broadcast_add(bias)?,
+                None => xs,
+            };
+            let (ys, mean, inv_std) = layer_norm(&xs, xs.rank() - 1, eps as f64, gamma, beta)?;
+            values.insert(node.output[0].clone(), ys);
+            for (name, value) in node.output.iter().skip(1).zip([mean, inv_std, xs]) {
+                if !name.is_empty() {
+                    values.insert(name.clone(), value);
+                }
+            }
+        }
+        "Attention" => {
+            let xs = get(&node.input[0])?;
+            let weights = get(&node.input[1])?;
+            let bias = get_opt(2).transpose()?;
+            let mask_index = get_opt(3).transpose()?;
+            if get_opt(4).is_some() {
+                bail!("Attention: the past input is not supported")
+            }
+            let attention_bias = get_opt(5).transpose()?;
+            let num_heads = *get_attr::<i64>(node, "num_heads")? as usize;
+            let unidirectional = get_attr_opt::<i64>(node, "unidirectional")?
+                .copied()
+                .unwrap_or(0)
+                == 1;
+            let mask_filter_value = get_attr_opt::<f32>(node, "mask_filter_value")?
+                .copied()
+                .unwrap_or(-10000.) as f64;
+
+            let (b_sz, seq_len, _) = xs.dims3()?;
+            let qkv = xs.broadcast_matmul(weights)?;
+            let qkv = match bias {
+                Some(bias) => qkv.broadcast_add(bias)?,
+                None => qkv,
+            };
+            let hidden = qkv.dim(2)?;
+            let (q_size, k_size, v_size) = match get_attr_opt::<[i64]>(node, "qkv_hidden_sizes")? {
+                Some(&[q, k, v]) => (q as usize, k as usize, v as usize),
+                Some(sizes) => bail!("Attention: unexpected qkv_hidden_sizes {sizes:?}"),
+                None => (hidden / 3, hidden / 3, hidden / 3),
+            };
+            if q_size != k_size
+                || q_size + k_size + v_size != hidden
+                || q_size % num_heads != 0
+                || v_size % num_heads != 0
+            {
+                bail!("Attention: incompatible sizes q {q_size}, k {k_size}, v {v_size}, hidden {hidden}, heads {num_heads}")
+            }
+            let heads = |xs: Tensor| {
+                xs.reshape((b_sz, seq_len, num_heads, ()))?
+                    .transpose(1, 2)?
+                    .contiguous()
+            };
+            let q = heads(qkv.narrow(2, 0, q_size)?)?;
+            let k = heads(qkv.narrow(2, q_size, k_size)?)?;
+            let v = heads(qkv.narrow(2, q_size + k_size, v_size)?)?;
+            let scale = get_attr_opt::<f32>(node, "scale")?
+                .copied()
+                .filter(|&scale| scale != 0.)
+                .map_or(1. / ((q_size / num_heads) as f64).sqrt(), |scale| {
+                    scale as f64
+                });
+
+            let mut scores = (q.matmul(&k.t()?)? * scale)?;
+            if let Some(mask) = mask_index {
+                let mask = match mask.rank() {
+                    // The number of keys to attend to for each batch element, the padding is at
+                    // the end.
+                    1 if mask.dim(0)? == b_sz => {
+                        let lens = mask.to_dtype(DType::I64)?.to_vec1::<i64>()?;
+                        let mask = lens
+                            .iter()
+                            .flat_map(|&len| (0..seq_len).map(move |j| u8::from((j as i64) < len)))
+                            .collect::<Vec<_>>();
+                        Tensor::from_vec(mask, (b_sz, seq_len), xs.device())?
+                    }
+                    // 1 for the keys to attend to and 0 for the masked ones.
+                    2 => mask.clone(),
+                    _ => bail!("Attention: unsupported mask_index shape {:?}", mask.shape()),
+                };
+                let mask = mask
+                    .to_dtype(scores.dtype())?
+                    .affine(-mask_filter_value, mask_filter_value)?
+                    .reshape((b_sz, 1, 1, seq_len))?;
+                scores = scores.broadcast_add(&mask)?
+            }
+            if unidirectional {
+                let mask = (0..seq_len)
+                    .flat_map(|i| {
+                        (0..seq_len).map(move |j| if j > i { mask_filter_value } else { 0. })
+                    })
+                    .collect::<Vec<_>>();
+                let mask = Tensor::from_vec(mask, (seq_len, seq_len), xs.device())?
+                    .to_dtype(scores.dtype())?;
+                scores = scores.broadcast_add(&mask)?
+            }
+            if let Some(attention_bias) = attention_bias {
+                scores = scores.broadcast_add(attention_bias)?
+            }
+            let probs = candle_nn::ops::softmax_last_dim(&scores)?;
+            let output = probs
+                .matmul(&v)?
+                .transpose(1, 2)?
+                .reshape((b_sz, seq_len, v_size))?;
+            values.insert(node.output[0].clone(), output);
+        }
+        "FastGelu" => {
+            let xs = get(&node.input[0])?;
+            let xs = match get_opt(1).transpose()? {
+                Some(bias) => xs.broadcast_add(bias)?,
+                None => xs.clone(),
+            };
+            values.insert(node.output[0].clone(), xs.gelu()?);
+        }
+        "BiasGelu" => {
+            let xs = get(&node.input[0])?;
+            let bias = get(&node.input[1])?;
+            values.insert(node.output[0].clone(), xs.broadcast_add(bias)?.gelu_erf()?);
+        }
+        // https://onnx.ai/onnx/operators/onnx__QuantizeLinear.html
+        "QuantizeLinear" => {
+            let xs = get(&node.input[0])?;
+            let scale = get(&node.input[1])?;
+            let zero_point = get_opt(2).transpose()?;
+            let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(1);
+            let output_dtype = get_attr_opt::<i64>(node, "output_dtype")?
+                .copied()
+                .filter(|&dt| dt != 0);
+            if get_attr_opt::<i64>(node, "block_size")?.is_some_and(|&v| v != 0) {
+                bail!("QuantizeLinear: blocked quantization is not supported")
+            }
+            let signed = match (output_dtype, zero_point.map(|zp| zp.dtype())) {
+                (Some(dt), _) => match DataType::try_from(dt as i32) {
+                    Ok(DataType::Uint8) => false,
+                    Ok(DataType::Int8) => true,
+                    dt => bail!("QuantizeLinear: unsupported output_dtype {dt:?}"),
+                },
+                (None, None | Some(DType::U8)) => false,
+                (None, Some(DType::I64)) => true,
+                (None, Some(dtype)) => bail!("QuantizeLinear: unsupported zero point {dtype:?}"),
+            };
+            let output = quantize_linear(xs, scale, zero_point, axis, signed)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__DequantizeLinear.html
+        "DequantizeLinear" => {
+            let xs = get(&node.input[0])?;
+            let scale = get(&node.input[1])?;
+            let zero_point = get_opt(2).transpose()?;
+            let axis = get_attr_opt::<i64>(node, "axis")?.copied().unwrap_or(1);
+            if get_attr_opt::<i64>(node, "block_size")?.is_some_and(|&v| v != 0) {
+                bail!("DequantizeLinear: blocked quantization is not supported")
+            }
+            let xs = xs.to_dtype(DType::F32)?;
+            let xs = match zero_point {
+                Some(zp) => xs.broadcast_sub(&per_axis(zp, &xs, axis)?.to_dtype(DType::F32)?)?,
+                None => xs,
+            };
+            let output = xs
+                .broadcast_mul(&per_axis(scale, &xs, axis)?.to_dtype(DType::F32)?)?
+                .to_dtype(scale.dtype())?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__DynamicQuantizeLinear.html
+        "DynamicQuantizeLinear" => {
+            let xs = get(&node.input[0])?.to_dtype(DType::F32)?;
+            let flat = xs.flatten_all()?;
+            // The range always includes 0 so that it is exactly representable.
+            let min = flat.min(0)?.to_scalar::<f32>()?.min(0.);
+            let max = flat.max(0)?.to_scalar::<f32>()?.max(0.);
+            let scale = (max - min) / 255.;
+            let (ys, zero_point) = if scale == 0. {
+                (xs.zeros_like()?.to_dtype(DType::U8)?, 0u8)
+            } else {
+                let zero_point = (-min / scale).round_ties_even().clamp(0., 255.) as u8;
+                let zp = Tensor::new(zero_point, xs.device())?;
+                let scale = Tensor::new(scale, xs.device())?;
+                let ys = quantize_linear(&xs, &scale, Some(&zp), 1, false)?;
+                (ys, zero_point)
+            };
+            values.insert(node.output[0].clone(), ys);
+            values.insert(node.output[1].clone(), Tensor::new(scale, xs.device())?);
+            values.insert(
+                node.output[2].clone(),
+                Tensor::new(zero_point, xs.device())?,
+            );
+        }
+        // https://onnx.ai/onnx/operators/onnx__MatMulInteger.html
+        "MatMulInteger" => {
+            let a = get(&node.input[0])?;
+            let b = get(&node.input[1])?;
+            let a_zero_point = get_opt(2).transpose()?;
+            let b_zero_point = get_opt(3).transpose()?;
+            let output = matmul_integer(a, a_zero_point, b, b_zero_point)?.to_dtype(DType::I64)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__QLinearMatMul.html
+        "QLinearMatMul" => {
+            let a = get(&node.input[0])?;
+            let a_scale = get(&node.input[1])?;
+            let a_zero_point = get(&node.input[2])?;
+            let b = get(&node.input[3])?;
+            let b_scale = get(&node.input[4])?;
+            let b_zero_point = get(&node.input[5])?;
+            let y_scale = get(&node.input[6])?;
+            let y_zero_point = get_opt(7).transpose()?;
+            let ys = matmul_integer(a, Some(a_zero_point), b, Some(b_zero_point))?
+                .broadcast_mul(&per_row(a_scale)?.to_dtype(DType::F64)?)?
+                .broadcast_mul(&b_scale.to_dtype(DType::F64)?)?;
+            let signed = match y_zero_point.map(|zp| zp.dtype()) {
+                None | Some(DType::U8) => false,
+                Some(DType::I64) => true,
+                Some(dtype) => bail!("QLinearMatMul: unsupported zero point {dtype:?}"),
+            };
+            let output = quantize_linear(&ys, y_scale, y_zero_point, 1, signed)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__ConvInteger.html
+        "ConvInteger" => {
+            let xs = get(&node.input[0])?.to_dtype(DType::F64)?;
+            let ws = get(&node.input[1])?.to_dtype(DType::F64)?;
+            // The zero point of the input is a scalar, the one of the weights can be per output
+            // channel.
+            let xs = match get_opt(2).transpose()? {
+                Some(zp) => xs.broadcast_sub(&zp.to_dtype(DType::F64)?)?,
+                None => xs,
+            };
+            let ws = match get_opt(3).transpose()? {
+                Some(zp) => ws.broadcast_sub(&per_axis(zp, &ws, 0)?.to_dtype(DType::F64)?)?,
+                None => ws,
+            };
+            let output = conv(node, &xs, &ws)?.round()?.to_dtype(DType::I64)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__Loop.html
+        "Loop" => {
+            let body = get_attr::<GraphProto>(node, "body")?;
+            let max_trip_count = match get_opt(0) {
+                Some(max) => Some(to_scalar_flexible::<i64>(max?)?),
+                None => None,
+            };
+            let mut cond = match get_opt(1) {
+                Some(cond) => to_scalar_flexible::<u8>(cond?)? != 0,
+                None => true,
+            };
+            let n_carried = node.input.len().saturating_sub(2);
+            if body.input.len() != n_carried + 2
+                || body.output.len() < n_carried + 1
+                || node.output.len() != body.output.len() - 1
+            {
+                bail!(
+                    "Loop node {:?} is malformed: {} inputs and {} outputs for a body with {} inputs and {} outputs",
+                    node.name,
+                    node.input.len(),
+                    node.output.len(),
+                    body.input.len(),
+                    body.output.len()
+                )
+            }
+            let mut carried = node.input[2..]
+                .iter()
+                .map(|name| Item::get(name, values, sequences))
+                .collect::<Result<Vec<_>>>()?;
+            let mut scan_outputs = vec![vec![]; body.output.len() - 1 - n_carried];
+            let mut iteration = 0i64;
+            while cond && max_trip_count.is_none_or(|max| iteration < max) {
+                let iteration_num = Tensor::new(iteration, &Device::Cpu)?;
+                values.insert(body.input[0].name.clone(), iteration_num);
+                let cond_in = Tensor::new(u8::from(cond), &Device::Cpu)?;
+                values.insert(body.input[1].name.clone(), cond_in);
+                for (input, item) in body.input[2..].iter().zip(carried) {
+                    item.insert(&input.name, values, sequences)
+                }
+                eval_graph(body, values, sequences, external_data)?;
+                let cond_out = Item::get(&body.output[0].name, values, sequences)?.tensor()?;
+                cond = to_scalar_flexible::<u8>(&cond_out)? != 0;
+                carried = body.output[1..=n_carried]
+                    .iter()
+                    .map(|output| Item::get(&output.name, values, sequences))
+                    .collect::<Result<Vec<_>>>()?;
+                for (scan, output) in scan_outputs
+                    .iter_mut()
+                    .zip(body.output[n_carried + 1..].iter())
+                {
+                    scan.push(Item::get(&output.name, values, sequences)?.tensor()?)
+                }
+                iteration += 1;
+            }
+            for (output, item) in node.output.iter().zip(carried) {
+                item.insert(output, values, sequences)
+            }
+            // The scan outputs are the values of each iteration stacked on a new first axis.
+            for (output, scan) in node.output[n_carried..].iter().zip(scan_outputs) {
+                if scan.is_empty() {
+                    bail!("Loop: no iteration, the shape of the scan output {output} is unknown")
+                }
+                values.insert(output.clone(), Tensor::stack(&scan, 0)?);
             }
-            op_type => bail!("unsupported op_type {op_type} for op {node:?}"),
         }
+        // https://onnx.ai/onnx/operators/onnx__Scan.html
+        "Scan" => {
+            let body = get_attr::<GraphProto>(node, "body")?;
+            let n_scan_inputs = *get_attr::<i64>(node, "num_scan_inputs")? as usize;
+            if node.input.len() < n_scan_inputs || n_scan_inputs == 0 {
+                bail!(
+                    "Scan node {:?} is malformed: {} inputs",
+                    node.name,
+                    node.input.len()
+                )
+            }
+            let n_states = node.input.len() - n_scan_inputs;
+            if body.input.len() != node.input.len()
+                || body.output.len() != node.output.len()
+                || body.output.len() < n_states
+            {
+                bail!(
+                    "Scan node {:?} is malformed: {} inputs and {} outputs for a body with {} inputs and {} outputs",
+                    node.name,
+                    node.input.len(),
+                    node.output.len(),
+                    body.input.len(),
+                    body.output.len()
+                )
+            }
+            let n_scan_outputs = body.output.len() - n_states;
+            let attr_or_zeros = |name: &str, n: usize| -> Result<Vec<i64>> {
+                match get_attr_opt::<[i64]>(node, name)? {
+                    None => Ok(vec![0; n]),
+                    Some(v) if v.len() == n => Ok(v.to_vec()),
+                    Some(v) => bail!("Scan: expected {n} values for {name}, got {v:?}"),
+                }
+            };
+            let input_axes = attr_or_zeros("scan_input_axes", n_scan_inputs)?;
+            let input_directions = attr_or_zeros("scan_input_directions", n_scan_inputs)?;
+            let output_axes = attr_or_zeros("scan_output_axes", n_scan_outputs)?;
+            let output_directions = attr_or_zeros("scan_output_directions", n_scan_outputs)?;
+
+            let mut states = node.input[..n_states]
+                .iter()
+                .map(|name| get(name).cloned())
+                .collect::<Result<Vec<_>>>()?;
+            let mut scan_inputs = vec![];
+            for (name, &axis) in node.input[n_states..].iter().zip(input_axes.iter()) {
+                let input = get(name)?;
+                let axis = input.normalize_axis(axis)?;
+                scan_inputs.push((input.clone(), axis))
+            }
+            let seq_len = scan_inputs[0].0.dim(scan_inputs[0].1)?;
+            let mut scan_outputs = vec![vec![]; n_scan_outputs];
+            for step in 0..seq_len {
+                for (input, state) in body.input.iter().zip(states) {
+                    values.insert(input.name.clone(), state);
+                }
+                for (i, (xs, axis)) in scan_inputs.iter().enumerate() {
+                    if xs.dim(*axis)? != seq_len {
+                        bail!("Scan: the scan inputs have different lengths")
+                    }
+                    let idx = if input_directions[i] == 1 {
+                        seq_len - 1 - step
+                    } else {
+                        step
+                    };
+                    let xs = xs.narrow(*axis, idx, 1)?.squeeze(*axis)?;
+                    values.insert(body.input[n_states + i].name.clone(), xs);
+                }
+                eval_graph(body, values, sequences, external_data)?;
+                let mut outputs = vec![];
+                for output in body.output.iter() {
+                    outputs.push(Item::get(&output.name, values, sequences)?.tensor()?)
+                }
+                let scans = outputs.split_off(n_states);
+                states = outputs;
+                for (scan, output) in scan_outputs.iter_mut().zip(scans) {
+                    scan.push(output)
+                }
+            }
+            for (output, state) in node.output.iter().zip(states) {
+                values.insert(output.clone(), state);
+            }
+            for (i, (output, mut scan)) in
+                node.output[n_states..].iter().zip(scan_outputs).enumerate()
+            {
+                if scan.is_empty() {
+                    bail!("Scan: empty scan inputs, the shape of the output {output} is unknown")
+                }
+                if output_directions[i] == 1 {
+                    scan.reverse()
+                }
+                let rank = scan[0].rank() as i64 + 1;
+                let axis = output_axes[i];
+                let axis = if axis < 0 { axis + rank } else { axis };
+                if axis < 0 || axis >= rank {
+                    bail!("Scan: invalid scan output axis {}", output_axes[i])
+                }
+                values.insert(output.clone(), Tensor::stack(&scan, axis as usize)?);
+            }
+        }
+        // https://onnx.ai/onnx/operators/onnx__SequenceEmpty.html
+        "SequenceEmpty" => {
+            sequences.insert(node.output[0].clone(), vec![]);
+        }
+        // https://onnx.ai/onnx/operators/onnx__SequenceConstruct.html
+        "SequenceConstruct" => {
+            let sequence = node
+                .input
+                .iter()
+                .map(|name| get(name).cloned())
+                .collect::<Result<Vec<_>>>()?;
+            sequences.insert(node.output[0].clone(), sequence);
+        }
+        // https://onnx.ai/onnx/operators/onnx__SequenceLength.html
+        "SequenceLength" => {
+            let sequence = get_seq(&node.input[0])?;
+            let output = Tensor::new(sequence.len() as i64, &Device::Cpu)?;
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__SequenceAt.html
+        "SequenceAt" => {
+            let sequence = get_seq(&node.input[0])?;
+            let position = to_scalar_flexible::<i64>(get(&node.input[1])?)?;
+            let idx = sequence_position(position, sequence.len(), false)?;
+            let output = sequence[idx].clone();
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__SequenceInsert.html
+        "SequenceInsert" => {
+            let mut sequence = get_seq(&node.input[0])?.clone();
+            let tensor = get(&node.input[1])?.clone();
+            let idx = match get_opt(2) {
+                Some(position) => {
+                    let position = to_scalar_flexible::<i64>(position?)?;
+                    sequence_position(position, sequence.len(), true)?
+                }
+                None => sequence.len(),
+            };
+            sequence.insert(idx, tensor);
+            sequences.insert(node.output[0].clone(), sequence);
+        }
+        // https://onnx.ai/onnx/operators/onnx__SequenceErase.html
+        "SequenceErase" => {
+            let mut sequence = get_seq(&node.input[0])?.clone();
+            let position = match get_opt(1) {
+                Some(position) => to_scalar_flexible::<i64>(position?)?,
+                None => -1,
+            };
+            let idx = sequence_position(position, sequence.len(), false)?;
+            sequence.remove(idx);
+            sequences.insert(node.output[0].clone(), sequence);
+        }
+        // https://onnx.ai/onnx/operators/onnx__ConcatFromSequence.html
+        "ConcatFromSequence" => {
+            let sequence = get_seq(&node.input[0])?;
+            let axis = *get_attr::<i64>(node, "axis")?;
+            let new_axis = get_attr_opt::<i64>(node, "new_axis")?.copied().unwrap_or(0) == 1;
+            if sequence.is_empty() {
+                bail!("ConcatFromSequence: empty sequence {}", node.input[0])
+            }
+            let output = if new_axis {
+                let rank = sequence[0].rank() as i64 + 1;
+                let axis = if axis < 0 { axis + rank } else { axis };
+                if axis < 0 || axis >= rank {
+                    bail!("ConcatFromSequence: invalid axis {axis} for rank {rank}")
+                }
+                Tensor::stack(sequence, axis as usize)?
+            } else {
+                let axis = sequence[0].normalize_axis(axis)?;
+                Tensor::cat(sequence, axis)?
+            };
+            values.insert(node.output[0].clone(), output);
+        }
+        // https://onnx.ai/onnx/operators/onnx__GRU.html
+        "GRU" => {
+            let activations = get_attr_opt_owned::<Vec<String>>(node, "activations")?;
+            if get_attr_opt::<f32>(node, "clip")?.is_some() {
+                bail!("GRU does not currently support clip attribute");
+            }
+            let direction = get_attr_opt(node, "direction")?.unwrap_or("forward");
+            let num_directions = match direction {
+                "forward" | "reverse" => 1,
+                "bidirectional" => 2,
+                _ => bail!("GRU: unsupported direction {direction}"),
+            };
+            let activations = match activations {
+                None => ["Sigmoid", "Tanh"]
+                    .repeat(num_directions)
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect(),
+                Some(a) if a.len() == 2 * num_directions => a,
+                Some(a) => bail!(
+                    "GRU: expected {} activations, got {a:?}",
+                    2 * num_directions
+                ),
+            };
+            let hidden_size = *get_attr::<i64>(node, "hidden_size")? as usize;
+            let linear_before_reset = get_attr_opt::<i64>(node, "linear_before_reset")?
+                .copied()
+                .unwrap_or(0)
+                == 1;
+            if get_attr_opt::<i64>(node, "layout")?.copied().unwrap_or(0) != 0 {
+                bail!("GRU currently only supports layout == 0");
+            }
+
+            // X has shape [seq_length, batch_size, input_size].
+            let x = get(&node.input[0])?;
+            let (seq_length, batch_size, _) = x.dims3()?;
+            // W has shape [num_directions, 3*hidden_size, input_size] and R has shape
+            // [num_directions, 3*hidden_size, hidden_size], the gates are in the z, r, h order.
+            let w = get(&node.input[1])?;
+            let r = get(&node.input[2])?;
+            // B is the concatenation of Wb and Rb, shape [num_directions, 6*hidden_size].
+            let b = match get_opt(3) {
+                Some(b) => b?.clone(),
+                None => Tensor::zeros((num_directions, 6 * hidden_size), x.dtype(), x.device())?,
+            };
+            if let Some(seq_lens) = get_opt(4) {
+                let seq_lens = seq_lens?.to_dtype(DType::I64)?.to_vec1::<i64>()?;
+                if seq_lens.iter().any(|&l| l as usize != seq_length) {
+                    bail!("GRU currently does not support variable-length sequences. All sequences must use the full sequence length of {}", seq_length);
+                }
+            }
+            let initial_h = match get_opt(5) {
+                Some(h) => h?.clone(),
+                None => Tensor::zeros(
+                    (num_directions, batch_size, hidden_size),
+                    x.dtype(),
+                    x.device(),
+                )?,
+            };
+
+            let mut ys = vec![];
+            let mut ys_h = vec![];
+            for dir in 0..num_directions {
+                let (f, g) = (&activations[2 * dir], &activations[2 * dir + 1]);
+                let w = w.get(dir)?;
+                let r = r.get(dir)?;
+                let b = b.get(dir)?;
+                let (wb, rb) = (
+                    b.narrow(0, 0, 3 * hidden_size)?,
+                    b.narrow(0, 3 * hidden_size, 3 * hidden_size)?,
+                );
+                let r_zr = r.narrow(0, 0, 2 * hidden_size)?.t()?;
+                let rb_zr = rb.narrow(0, 0, 2 * hidden_size)?;
+                let r_h = r.narrow(0, 2 * hidden_size, hidden_size)?.t()?;
+                let rb_h = rb.narrow(0, 2 * hidden_size, hidden_size)?;
+                // The input projections for all the time steps at once.
+                let x_proj = x.broadcast_matmul(&w.t()?)?.broadcast_add(&wb)?;
+                let reverse = direction == "reverse" || dir == 1;
+                let mut h_t = initial_h.get(dir)?;
+                let mut h_list = vec![h_t.clone(); seq_length];
+                for step in 0..seq_length {
+                    let t = if reverse { seq_length - 1 - step } else { step };
+                    let gx = x_proj.get(t)?;
+                    let gh = h_t.matmul(&r_zr)?.broadcast_add(&rb_zr)?;
+                    let z = gru_activation(
+                        f,
+                        &(gx.narrow(1, 0, hidden_size)? + gh.narrow(1, 0, hidden_size)?)?,
+                    )?;
+                    let r_gate = gru_activation(
+                        f,
+                        &(gx.narrow(1, hidden_size, hidden_size)?
+                            + gh.narrow(1, hidden_size, hidden_size)?)?,
+                    )?;
+                    let gx_h = gx.narrow(1, 2 * hidden_size, hidden_size)?;
+                    let h_pre = if linear_before_reset {
+                        let rh = h_t.matmul(&r_h)?.broadcast_add(&rb_h)?;
+                        (gx_h + (r_gate * rh)?)?
+                    } else {
+                        let rh = (r_gate * &h_t)?.matmul(&r_h)?.broadcast_add(&rb_h)?;
+                        (gx_h + rh)?
+                    };
+                    let h_tilde = gru_activation(g, &h_pre)?;
+                    // (1 - z) * h_tilde + z * h_t
+                    h_t = (&h_tilde + (z * (&h_t - &h_tilde)?)?)?;
+                    h_list[t] = h_t.clone();
+                }
+                ys.push(Tensor::stack(&h_list, 0)?);
+                ys_h.push(h_t);
+            }
+            // Y has shape [seq_length, num_directions, batch_size, hidden_size] and Y_h has
+            // shape [num_directions, batch_size, hidden_size].
+            let y = Tensor::stack(&ys, 1)?;
+            let y_h = Tensor::stack(&ys_h, 0)?;
+            if let Some(name) = node.output.first().filter(|v| !v.is_empty()) {
+                values.insert(name.clone(), y);
+            }
+            if let Some(name) = node.output.get(1).filter(|v| !v.is_empty()) {
+                values.insert(name.clone(), y_h);
+            }
+        }
+        // https://onnx.ai/onnx/operators/onnx__ConvTranspose.html
+        "ConvTranspose" => {
+            let xs = get(&node.input[0])?;
+            let ws = get(&node.input[1])?;
+            let ys = conv_transpose(node, xs, ws)?;
+            let ys = match get_opt(2) {
+                Some(bs) => {
+                    let bs = bs?;
+                    let mut bs_shape = vec![1; ys.rank()];
+                    bs_shape[1] = bs.elem_count();
+                    ys.broadcast_add(&bs.reshape(bs_shape)?)?
+                }
+                None => ys,
+            };
+            values.insert(node.output[0].clone(), ys);
+        }
+        op_type => bail!("unsupported op_type {op_type} for op {node:?}"),
     }
-    graph
-        .output
-        .iter()
-        .map(|output| match values.remove(&output.name) {
-            None => bail!("cannot find output {}", output.name),
-            Some(value) => Ok((output.name.clone(), value)),
-        })
-        .collect()
+    Ok(())
 }
 
 fn broadcast_shape(shape_a: &[usize], shape_b: &[usize]) -> Result<Vec<usize>> {
@@ -2610,3 +3449,626 @@ fn to_vec0_flexible<T: candle::WithDType>(t: &Tensor) -> Result<T> {
         t.to_vec0::<T>()
     }
 }
+
+/// Applies the 1d or 2d convolution of a Conv node, the bias is not added.
+fn conv(node: &onnx::NodeProto, xs: &Tensor, ws: &Tensor) -> Result<Tensor> {
+    let dilations = get_attr_opt::<[i64]>(node, "dilations")?;
+    let groups = get_attr_opt::<i64>(node, "group")?.copied().unwrap_or(1);
+    let _kernel_shape = get_attr_opt::<[i64]>(node, "kernel_shape")?;
+    let pads = get_attr_opt::<[i64]>(node, "pads")?;
+    let strides = get_attr_opt::<[i64]>(node, "strides")?;
+    let auto_pad = get_attr_opt::<str>(node, "auto_pad")?;
+    match auto_pad {
+        None | Some("NOTSET") => (),
+        Some(s) => bail!("unsupported auto_pad {s}"),
+    };
+    let ys = match ws.rank() {
+        3 => {
+            let (pads, xs) = match pads {
+                None => (0, xs.clone()),
+                Some([p]) => (*p as usize, xs.clone()),
+                Some([p1, p2]) => {
+                    if p1 != p2 {
+                        (0usize, xs.pad_with_zeros(2, *p1 as usize, *p2 as usize)?)
+                    } else {
+                        (*p1 as usize, xs.clone())
+                    }
+                }
+                Some(pads) => {
+                    bail!("more pads than expected in conv1d {pads:?} {}", node.name)
+                }
+            };
+            let strides = match strides {
+                None => 1,
+                Some([p]) => *p as usize,
+                Some(s) => {
+                    bail!("more strides than expected in conv1d {s:?} {}", node.name)
+                }
+            };
+            let dilations = match dilations {
+                None => 1,
+                Some([p]) => *p as usize,
+                Some(s) => {
+                    bail!("more dilations than expected in conv1d {s:?} {}", node.name)
+                }
+            };
+            xs.conv1d(ws, pads, strides, dilations, groups as usize)?
+        }
+        4 => {
+            let (pads, xs) = match pads {
+                None => (0, xs.clone()),
+                Some([p]) => (*p as usize, xs.clone()),
+                Some(&[p1, p2, p3, p4]) => {
+                    let p1 = p1 as usize;
+                    let p2 = p2 as usize;
+                    let p3 = p3 as usize;
+                    let p4 = p4 as usize;
+                    if p1 != p2 || p1 != p3 || p1 != p4 {
+                        (0, xs.pad_with_zeros(2, p1, p3)?.pad_with_zeros(3, p2, p4)?)
+                    } else {
+                        (p1, xs.clone())
+                    }
+                }
+                Some(pads) => {
+                    bail!("more pads than expected in conv2d {pads:?} {}", node.name)
+                }
+            };
+            let strides = match strides {
+                None => 1,
+                Some([p]) => *p as usize,
+                Some([p1, p2]) => {
+                    if p1 != p2 {
+                        bail!(
+                            "strides have to be the same on both axis {pads:?} {}",
+                            node.name
+                        )
+                    }
+                    *p1 as usize
+                }
+                Some(s) => {
+                    bail!("more strides than expected in conv2d {s:?} {}", node.name)
+                }
+            };
+            let dilations = match dilations {
+                None => 1,
+                Some([p]) => *p as usize,
+                Some([p1, p2]) => {
+                    if p1 != p2 {
+                        bail!(
+                            "dilations have to be the same on both axis {pads:?} {}",
+                            node.name
+                        )
+                    }
+                    *p1 as usize
+                }
+                Some(s) => {
+                    bail!("more dilations than expected in conv2d {s:?} {}", node.name)
+                }
+            };
+            xs.conv2d(ws, pads, strides, dilations, groups as usize)?
+        }
+        rank => bail!(
+            "unsupported rank for weight matrix {rank} in conv {}",
+            node.name
+        ),
+    };
+    Ok(ys)
+}
+
+/// Normalizes `xs` over the dimensions starting at `axis` and applies the scale and bias, returns
+/// the normalized values together with the mean and the inverse standard deviation.
+fn layer_norm(
+    xs: &Tensor,
+    axis: usize,
+    eps: f64,
+    scale: &Tensor,
+    bias: Option<&Tensor>,
+) -> Result<(Tensor, Tensor, Tensor)> {
+    let dtype = xs.dtype();
+    // The statistics are computed in f32 for the half precision types.
+    let internal_dtype = match dtype {
+        DType::F16 | DType::BF16 => DType::F32,
+        dtype => dtype,
+    };
+    let dims = (axis..xs.rank()).collect::<Vec<_>>();
+    let xs = xs.to_dtype(internal_dtype)?;
+    let mean = xs.mean_keepdim(dims.as_slice())?;
+    let xs = xs.broadcast_sub(&mean)?;
+    let var = xs.sqr()?.mean_keepdim(dims.as_slice())?;
+    let inv_std = (var + eps)?.sqrt()?.recip()?;
+    let ys = xs.broadcast_mul(&inv_std)?.to_dtype(dtype)?;
+    let ys = ys.broadcast_mul(scale)?;
+    let ys = match bias {
+        Some(bias) => ys.broadcast_add(bias)?,
+        None => ys,
+    };
+    Ok((ys, mean, inv_std))
+}
+
+/// Evaluates an einsum equation by contracting the operands two at a time with batched matmuls.
+/// The repeated labels within a single operand, i.e. diagonals, are not supported.
+fn einsum(equation: &str, operands: &[Tensor]) -> Result<Tensor> {
+    let equation = equation
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    let (lhs, rhs) = match equation.split_once("->") {
+        Some((lhs, rhs)) => (lhs, Some(rhs)),
+        None => (equation.as_str(), None),
+    };
+    let terms = lhs.split(',').collect::<Vec<_>>();
+    if terms.len() != operands.len() {
+        bail!(
+            "Einsum: {} operands for equation {equation}",
+            operands.len()
+        )
+    }
+    // The dimensions covered by an ellipsis are right aligned and broadcast together, they get
+    // digit labels that cannot appear in a valid equation.
+    let mut n_ellipsis = 0;
+    for (term, operand) in terms.iter().zip(operands.iter()) {
+        if let Some((pre, post)) = term.split_once("...") {
+            let n_labels = pre.len() + post.len();
+            if operand.rank() < n_labels {
+                bail!("Einsum: {term} does not match shape {:?}", operand.shape())
+            }
+            n_ellipsis = usize::max(n_ellipsis, operand.rank() - n_labels)
+        }
+    }
+    if n_ellipsis > 10 {
+        bail!("Einsum: too many dimensions for the ellipsis in {equation}")
+    }
+    let ellipsis = (0..n_ellipsis)
+        .map(|i| (b'0' + i as u8) as char)
+        .collect::<Vec<_>>();
+    let expand = |term: &str, n: usize| -> Vec<char> {
+        match term.split_once("...") {
+            None => term.chars().collect(),
+            Some((pre, post)) => pre
+                .chars()
+                .chain(ellipsis[n_ellipsis - n..].iter().copied())
+                .chain(post.chars())
+                .collect(),
+        }
+    };
+    let mut inputs = vec![];
+    for (term, operand) in terms.iter().zip(operands.iter()) {
+        let n = operand.rank().saturating_sub(term.len().saturating_sub(3));
+        let labels = expand(term, n);
+        if labels.len() != operand.rank() {
+            bail!("Einsum: {term} does not match shape {:?}", operand.shape())
+        }
+        if labels
+            .iter()
+            .enumerate()
+            .any(|(i, l)| labels[..i].contains(l))
+        {
+            bail!("Einsum: repeated labels in {term} are not supported")
+        }
+        inputs.push((operand.clone(), labels))
+    }
+    let output_labels = match rhs {
+        Some(rhs) => expand(rhs, n_ellipsis),
+        None => {
+            // The implicit output has the ellipsis dimensions followed by the labels that appear
+            // exactly once, in alphabetical order.
+            let all = inputs
+                .iter()
+                .flat_map(|(_, l)| l.iter())
+                .collect::<Vec<_>>();
+            let mut once = all
+                .iter()
+                .filter(|l| l.is_alphabetic() && all.iter().filter(|v| *v == *l).count() == 1)
+                .map(|l| **l)
+                .collect::<Vec<_>>();
+            once.sort();
+            ellipsis.iter().copied().chain(once).collect()
+        }
+    };
+
+    // Sums over the labels that are not needed anymore.
+    let reduce = |xs: Tensor, labels: Vec<char>, keep: &dyn Fn(char) -> bool| {
+        let dims = (0..labels.len())
+            .filter(|&i| !keep(labels[i]))
+            .collect::<Vec<_>>();
+        let labels = labels.into_iter().filter(|&l| keep(l)).collect::<Vec<_>>();
+        let xs = if dims.is_empty() { xs } else { xs.sum(dims)? };
+        Ok::<_, candle::Error>((xs, labels))
+    };
+    let mut inputs = inputs.into_iter();
+    let (mut xs, mut labels) = match inputs.next() {
+        None => bail!("Einsum: no operands"),
+        Some(input) => input,
+    };
+    let rest = inputs.collect::<Vec<_>>();
+    for (idx, (ys, ys_labels)) in rest.iter().enumerate() {
+        let keep = |l: char| {
+            output_labels.contains(&l) || rest[idx + 1..].iter().any(|(_, v)| v.contains(&l))
+        };
+        let (lhs, lhs_labels) = reduce(xs, labels, &|l| keep(l) || ys_labels.contains(&l))?;
+        let (rhs, rhs_labels) = reduce(ys.clone(), ys_labels.clone(), &|l| {
+            keep(l) || lhs_labels.contains(&l)
+        })?;
+        (xs, labels) = einsum_contract(&lhs, &lhs_labels, &rhs, &rhs_labels, &keep)?;
+    }
+    let (xs, labels) = reduce(xs, labels, &|l| output_labels.contains(&l))?;
+    let mut perm = vec![];
+    for l in output_labels.iter() {
+        match labels.iter().position(|v| v == l) {
+            Some(idx) => perm.push(idx),
+            None => bail!("Einsum: unknown output label {l} in {equation}"),
+        }
+    }
+    xs.permute(perm)
+}
+
+/// Contracts two operands with a batched matmul, the labels that appear in both operands are
+/// batch dimensions when kept and summed over otherwise.
+fn einsum_contract(
+    lhs: &Tensor,
+    lhs_labels: &[char],
+    rhs: &Tensor,
+    rhs_labels: &[char],
+    keep: &dyn Fn(char) -> bool,
+) -> Result<(Tensor, Vec<char>)> {
+    let dim = |xs: &Tensor, labels: &[char], l: char| match labels.iter().position(|&v| v == l) {
+        Some(idx) => (idx, xs.dims()[idx]),
+        None => (0, 1),
+    };
+    let shared = lhs_labels
+        .iter()
+        .filter(|l| rhs_labels.contains(l))
+        .copied()
+        .collect::<Vec<_>>();
+    let batch = shared
+        .iter()
+        .filter(|&&l| keep(l))
+        .copied()
+        .collect::<Vec<_>>();
+    let summed = shared
+        .iter()
+        .filter(|&&l| !keep(l))
+        .copied()
+        .collect::<Vec<_>>();
+    let left = lhs_labels
+        .iter()
+        .filter(|l| !shared.contains(l))
+        .copied()
+        .collect::<Vec<_>>();
+    let right = rhs_labels
+        .iter()
+        .filter(|l| !shared.contains(l))
+        .copied()
+        .collect::<Vec<_>>();
+
+    let sizes = |labels: &[char]| -> Result<Vec<usize>> {
+        labels
+            .iter()
+            .map(|&l| {
+                let (_, d1) = dim(lhs, lhs_labels, l);
+                let (_, d2) = dim(rhs, rhs_labels, l);
+                if d1 != d2 && d1 != 1 && d2 != 1 {
+                    bail!("Einsum: incompatible sizes {d1} and {d2} for label {l}")
+                }
+                Ok(usize::max(d1, d2))
+            })
+            .collect()
+    };
+    let batch_dims = sizes(&batch)?;
+    let left_dims = sizes(&left)?;
+    let summed_dims = sizes(&summed)?;
+    let right_dims = sizes(&right)?;
+    let (nb, nl, ns, nr) = (
+        batch_dims.iter().product::<usize>(),
+        left_dims.iter().product::<usize>(),
+        summed_dims.iter().product::<usize>(),
+        right_dims.iter().product::<usize>(),
+    );
+
+    let lhs_perm = [&batch[..], &left, &summed]
+        .concat()
+        .iter()
+        .map(|&l| dim(lhs, lhs_labels, l).0)
+        .collect::<Vec<_>>();
+    let lhs = lhs
+        .permute(lhs_perm)?
+        .broadcast_as([&batch_dims[..], &left_dims, &summed_dims].concat())?
+        .contiguous()?
+        .reshape((nb, nl, ns))?;
+    let rhs_perm = [&batch[..], &summed, &right]
+        .concat()
+        .iter()
+        .map(|&l| dim(rhs, rhs_labels, l).0)
+        .collect::<Vec<_>>();
+    let rhs = rhs
+        .permute(rhs_perm)?
+        .broadcast_as([&batch_dims[..], &summed_dims, &right_dims].concat())?
+        .contiguous()?
+        .reshape((nb, ns, nr))?;
+    let xs = lhs
+        .matmul(&rhs)?
+        .reshape([&batch_dims[..], &left_dims, &right_dims].concat())?;
+    Ok((xs, [batch, left, right].concat()))
+}
+
+/// Writes the updates at the positions given by the indices along `axis`, combining them with the
+/// existing values according to `reduction`.
+fn scatter_elements<T: candle::WithDType>(
+    data: &Tensor,
+    indices: &Tensor,
+    updates: &Tensor,
+    axis: usize,
+    reduction: &str,
+) -> Result<Tensor> {
+    let reduce = match reduction {
+        "none" => |_: T, u: T| u,
+        "add" => |v: T, u: T| v + u,
+        "mul" => |v: T, u: T| v * u,
+        "max" => |v: T, u: T| if u > v { u } else { v },
+        "min" => |v: T, u: T| if u < v { u } else { v },
+        reduction => bail!("ScatterElements: unsupported reduction {reduction}"),
+    };
+    let dims = data.dims();
+    let mut strides = vec![1; dims.len()];
+    for d in (0..dims.len().saturating_sub(1)).rev() {
+        strides[d] = strides[d + 1] * dims[d + 1]
+    }
+    let mut output = data.flatten_all()?.to_vec1::<T>()?;
+    let index_dims = indices.dims();
+    let indices = indices
+        .flatten_all()?
+        .to_dtype(DType::I64)?
+        .to_vec1::<i64>()?;
+    let updates = updates.flatten_all()?.to_vec1::<T>()?;
+    for (i, (&index, &update)) in indices.iter().zip(updates.iter()).enumerate() {
+        let mut rem = i;
+        let mut offset = 0;
+        for d in (0..index_dims.len()).rev() {
+            let mut pos = rem % index_dims[d];
+            rem /= index_dims[d];
+            if d == axis {
+                let index = if index < 0 {
+                    index + dims[d] as i64
+                } else {
+                    index
+                };
+                if index < 0 || index >= dims[d] as i64 {
+                    bail!("ScatterElements: index {index} out of bounds for dim {d} of {dims:?}")
+                }
+                pos = index as usize
+            } else if pos >= dims[d] {
+                bail!("ScatterElements: indices shape {index_dims:?} larger than {dims:?}")
+            }
+            offset += pos * strides[d]
+        }
+        output[offset] = reduce(output[offset], update)
+    }
+    Tensor::from_vec(output, dims, data.device())
+}
+
+/// Reshapes a quantization parameter so that it broadcasts with `xs`, the parameter is either a
+/// scalar for per-tensor quantization or a 1d tensor for per-axis quantization.
+fn per_axis(param: &Tensor, xs: &Tensor, axis: i64) -> Result<Tensor> {
+    match param.rank() {
+        0 => Ok(param.clone()),
+        1 if param.elem_count() == 1 => param.reshape(()),
+        1 => {
+            let axis = xs.normalize_axis(axis)?;
+            let mut shape = vec![1; xs.rank()];
+            shape[axis] = param.elem_count();
+            param.reshape(shape)
+        }
+        _ => bail!(
+            "unsupported quantization parameter shape {:?}",
+            param.shape()
+        ),
+    }
+}
+
+/// Reshapes a 1d per-row quantization parameter of a matmul lhs to a column.
+fn per_row(param: &Tensor) -> Result<Tensor> {
+    if param.rank() == 1 && param.elem_count() > 1 {
+        param.reshape((param.elem_count(), 1))
+    } else {
+        Ok(param.clone())
+    }
+}
+
+/// Quantizes to uint8, or to int8 when `signed` is set. The int8 values are stored as i64.
+fn quantize_linear(
+    xs: &Tensor,
+    scale: &Tensor,
+    zero_point: Option<&Tensor>,
+    axis: i64,
+    signed: bool,
+) -> Result<Tensor> {
+    let xs = xs.to_dtype(DType::F32)?;
+    let scale = per_axis(scale, &xs, axis)?.to_dtype(DType::F32)?;
+    let ys = round_half_to_even(&xs.broadcast_div(&scale)?)?;
+    let ys = match zero_point {
+        Some(zp) => ys.broadcast_add(&per_axis(zp, &xs, axis)?.to_dtype(DType::F32)?)?,
+        None => ys,
+    };
+    if signed {
+        ys.clamp(-128f32, 127f32)?.to_dtype(DType::I64)
+    } else {
+        ys.clamp(0f32, 255f32)?.to_dtype(DType::U8)
+    }
+}
+
+fn round_half_to_even(xs: &Tensor) -> Result<Tensor> {
+    // `round` rounds the ties away from zero, move the odd results of ties back towards zero.
+    let rounded = xs.round()?;
+    let tie = (xs - &rounded)?.abs()?.eq(0.5)?;
+    let half = (&rounded * 0.5)?;
+    let odd = (&half - half.floor()?)?.ne(0.)?;
+    let adjust = tie.mul(&odd)?.to_dtype(xs.dtype())?.mul(&xs.sign()?)?;
+    rounded - adjust
+}
+
+/// Multiplies integer matrices after removing their zero points, the zero point of the lhs can
+/// be per row and the one of the rhs per column. This is lowered to a f64 matmul which is exact
+/// for the int32 accumulators of the onnx integer ops, the result has dtype f64.
+fn matmul_integer(
+    a: &Tensor,
+    a_zero_point: Option<&Tensor>,
+    b: &Tensor,
+    b_zero_point: Option<&Tensor>,
+) -> Result<Tensor> {
+    let a = a.to_dtype(DType::F64)?;
+    let a = match a_zero_point {
+        Some(zp) => a.broadcast_sub(&per_row(zp)?.to_dtype(DType::F64)?)?,
+        None => a,
+    };
+    let b = b.to_dtype(DType::F64)?;
+    let b = match b_zero_point {
+        Some(zp) => b.broadcast_sub(&zp.to_dtype(DType::F64)?)?,
+        None => b,
+    };
+    a.broadcast_matmul(&b)
+}
+
+/// Normalizes a position in a sequence of length `len`, inserting allows the position past the
+/// last element.
+fn sequence_position(position: i64, len: usize, insert: bool) -> Result<usize> {
+    let bound = len as i64 + i64::from(insert);
+    let idx = if position < 0 {
+        position + len as i64
+    } else {
+        position
+    };
+    if idx < 0 || idx >= bound {
+        bail!("position {position} out of range for a sequence of length {len}")
+    }
+    Ok(idx as usize)
+}
+
+fn gru_activation(activation: &str, xs: &Tensor) -> Result<Tensor> {
+    match activation {
+        "Sigmoid" => candle_nn::ops::sigmoid(xs),
+        "Tanh" => xs.tanh(),
+        "Relu" => xs.relu(),
+        _ => bail!("unsupported activation {activation}"),
+    }
+}
+
+/// Applies the 1d or 2d transposed convolution of a ConvTranspose node, the bias is not added.
+fn conv_transpose(node: &onnx::NodeProto, xs: &Tensor, ws: &Tensor) -> Result<Tensor> {
+    let n_spatial = match ws.rank() {
+        3 => 1,
+        4 => 2,
+        rank => bail!(
+            "unsupported rank for weight matrix {rank} in conv transpose {}",
+            node.name
+        ),
+    };
+    let attr = |name: &str, default: i64| -> Result<Vec<i64>> {
+        match get_attr_opt::<[i64]>(node, name)? {
+            None => Ok(vec![default; n_spatial]),
+            Some(v) if v.len() == n_spatial => Ok(v.to_vec()),
+            Some(v) => bail!("unexpected {name} {v:?} in conv transpose {}", node.name),
+        }
+    };
+    let groups = get_attr_opt::<i64>(node, "group")?.copied().unwrap_or(1) as usize;
+    let strides = attr("strides", 1)?;
+    let dilations = attr("dilations", 1)?;
+    let output_padding = attr("output_padding", 0)?;
+    // The candle kernels use the same stride and dilation for all the spatial dimensions.
+    if strides.iter().any(|&s| s != strides[0]) || dilations.iter().any(|&d| d != dilations[0]) {
+        bail!(
+            "strides and dilations have to be the same on all axis in conv transpose {}",
+            node.name
+        )
+    }
+    let (stride, dilation) = (strides[0] as usize, dilations[0] as usize);
+
+    // The output of the transposed convolution without padding, the padding and the output
+    // padding are then applied by cropping or extending this output.
+    let in_channels = xs.dim(1)?;
+    if in_channels % groups != 0 {
+        bail!("in channels {in_channels} are not divisible by the groups {groups}")
+    }
+    let group_size = in_channels / groups;
+    let ys = (0..groups)
+        .map(|g| {
+            let xs = xs.narrow(1, g * group_size, group_size)?;
+            let ws = ws.narrow(0, g * group_size, group_size)?;
+            if n_spatial == 1 {
+                xs.conv_transpose1d(&ws, 0, 0, stride, dilation, 1)
+            } else {
+                xs.conv_transpose2d(&ws, 0, 0, stride, dilation)
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut ys = Tensor::cat(&ys, 1)?;
+
+    let in_dims = &xs.dims()[2..];
+    let kernel_dims = &ws.dims()[2..];
+    let full = (0..n_spatial)
+        .map(|i| (in_dims[i] - 1) * stride + dilation * (kernel_dims[i] - 1) + 1)
+        .collect::<Vec<_>>();
+    let auto_pad = get_attr_opt::<str>(node, "auto_pad")?.unwrap_or("NOTSET");
+    let output_shape = match get_attr_opt::<[i64]>(node, "output_shape")? {
+        Some(shape) => Some(shape[shape.len().saturating_sub(n_spatial)..].to_vec()),
+        None if auto_pad == "SAME_UPPER" || auto_pad == "SAME_LOWER" => Some(
+            in_dims
+                .iter()
+                .map(|&d| (d * stride) as i64)
+                .collect::<Vec<_>>(),
+        ),
+        None => None,
+    };
+    let pads = match output_shape {
+        Some(output_shape) => {
+            if output_shape.len() != n_spatial {
+                bail!(
+                    "unexpected output_shape {output_shape:?} in conv transpose {}",
+                    node.name
+                )
+            }
+            let mut pads = vec![0; 2 * n_spatial];
+            for i in 0..n_spatial {
+                let total = (full[i] as i64 + output_padding[i]) - output_shape[i];
+                if total < 0 {
+                    bail!(
+                        "output_shape {output_shape:?} is too large in conv transpose {}",
+                        node.name
+                    )
+                }
+                let (begin, end) = if auto_pad == "SAME_UPPER" {
+                    (total / 2, total - total / 2)
+                } else {
+                    (total - total / 2, total / 2)
+                };
+                pads[i] = begin;
+                pads[n_spatial + i] = end;
+            }
+            pads
+        }
+        None => match (auto_pad, get_attr_opt::<[i64]>(node, "pads")?) {
+            ("VALID", _) | (_, None) => vec![0; 2 * n_spatial],
+            ("NOTSET", Some(pads)) if pads.len() == 2 * n_spatial => pads.to_vec(),
+            (auto_pad, pads) => bail!(
+                "unsupported auto_pad {auto_pad} and pads {pads:?} in conv transpose {}",
+                node.name
+            ),
+        },
+    };
+    for i in 0..n_spatial {
+        let dim = 2 + i;
+        let begin = pads[i] as usize;
+        let end = full[i] as i64 + output_padding[i] - pads[n_spatial + i];
+        if end <= begin as i64 {
+            bail!(
+                "the padding {pads:?} is too large in conv transpose {}",
+                node.name
+            )
+        }
+        let end = end as usize;
+        if end > full[i] {
+            ys = ys.pad_with_zeros(dim, 0, end - full[i])?
+        }
+        ys = ys.narrow(dim, begin, end - begin)?
+    }
+    Ok(ys)
+}
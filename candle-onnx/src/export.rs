@@ -0,0 +1,616 @@
+//! Exports candle computations as onnx models.
+//!
+//! The graph is traced from the backprop ops that candle records for the tensors depending on
+//! some variable, the exported inputs are hence turned into variables before running the
+//! computation. The traced graph is specialized for the shapes used when tracing, the
+//! variables of the [`VarMap`] become named initializers and all the other constants are
+//! exported as anonymous initializers.
+//!
+//! The fused kernels that do not support backprop, e.g. `candle_nn::ops::softmax_last_dim`,
+//! `rms_norm` or `layer_norm`, are not recorded and cannot be exported, their unfused versions
+//! can be used instead.
+//!
+//! ```no_run
+//! use candle::{DType, Device, Module, Tensor};
+//! use candle_nn::{VarBuilder, VarMap};
+//! # fn main() -> candle::Result<()> {
+//! let varmap = VarMap::new();
+//! let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+//! let model = candle_nn::linear(4, 2, vb.pp("linear"))?;
+//! let input = Tensor::zeros((1, 4), DType::F32, &Device::Cpu)?;
+//! let proto = candle_onnx::export::export_module(&model, &varmap, &input)?;
+//! candle_onnx::write_file(&proto, "linear.onnx")?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::onnx::{self, attribute_proto::AttributeType, tensor_proto::DataType};
+use candle::op::{BinaryOp, Op, ReduceOp, UnaryOp};
+use candle::{bail, DType, Module, Result, Tensor, TensorId, Var};
+use candle_nn::VarMap;
+use std::collections::HashMap;
+
+// The opset for which the exported nodes are specified, e.g. ReduceMax takes its axes as an input
+// starting from version 18.
+const OPSET_VERSION: i64 = 18;
+
+fn data_type(dtype: DType) -> Result<DataType> {
+    let dt = match dtype {
+        DType::U8 => DataType::Uint8,
+        DType::U32 => DataType::Uint32,
+        DType::I64 => DataType::Int64,
+        DType::F16 => DataType::Float16,
+        DType::F32 => DataType::Float,
+        DType::F64 => DataType::Double,
+        dtype => bail!("unsupported dtype {dtype:?} for onnx export"),
+    };
+    Ok(dt)
+}
+
+fn tensor_proto(name: &str, t: &Tensor) -> Result<onnx::TensorProto> {
+    let mut raw_data = vec![];
+    t.write_bytes(&mut raw_data)?;
+    Ok(onnx::TensorProto {
+        name: name.to_string(),
+        dims: t.dims().iter().map(|&d| d as i64).collect(),
+        data_type: data_type(t.dtype())?.into(),
+        raw_data,
+        ..Default::default()
+    })
+}
+
+fn value_info(name: &str, t: &Tensor) -> Result<onnx::ValueInfoProto> {
+    use onnx::tensor_shape_proto::{dimension, Dimension};
+    let dim = t
+        .dims()
+        .iter()
+        .map(|&d| Dimension {
+            value: Some(dimension::Value::DimValue(d as i64)),
+            ..Default::default()
+        })
+        .collect();
+    let tensor_type = onnx::type_proto::Tensor {
+        elem_type: data_type(t.dtype())?.into(),
+        shape: Some(onnx::TensorShapeProto { dim }),
+    };
+    Ok(onnx::ValueInfoProto {
+        name: name.to_string(),
+        r#type: Some(onnx::TypeProto {
+            value: Some(onnx::type_proto::Value::TensorType(tensor_type)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    })
+}
+
+fn int_attr(name: &str, i: i64) -> onnx::AttributeProto {
+    onnx::AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::Int.into(),
+        i,
+        ..Default::default()
+    }
+}
+
+fn ints_attr(name: &str, ints: &[usize]) -> onnx::AttributeProto {
+    onnx::AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::Ints.into(),
+        ints: ints.iter().map(|&v| v as i64).collect(),
+        ..Default::default()
+    }
+}
+
+fn float_attr(name: &str, f: f64) -> onnx::AttributeProto {
+    onnx::AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::Float.into(),
+        f: f as f32,
+        ..Default::default()
+    }
+}
+
+fn string_attr(name: &str, s: &str) -> onnx::AttributeProto {
+    onnx::AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::String.into(),
+        s: s.as_bytes().to_vec(),
+        ..Default::default()
+    }
+}
+
+#[derive(Default)]
+struct Tracer {
+    names: HashMap<TensorId, String>,
+    nodes: Vec<onnx::NodeProto>,
+    initializers: Vec<onnx::TensorProto>,
+    n_values: usize,
+}
+
+impl Tracer {
+    fn fresh(&mut self, prefix: &str) -> String {
+        self.n_values += 1;
+        format!("{prefix}_{}", self.n_values)
+    }
+
+    fn node(&mut self, op_type: &str, inputs: &[&str], attrs: Vec<onnx::AttributeProto>) -> String {
+        let output = self.fresh(op_type);
+        self.nodes.push(onnx::NodeProto {
+            op_type: op_type.to_string(),
+            name: output.clone(),
+            input: inputs.iter().map(|v| v.to_string()).collect(),
+            output: vec![output.clone()],
+            attribute: attrs,
+            ..Default::default()
+        });
+        output
+    }
+
+    fn constant(&mut self, t: &Tensor) -> Result<String> {
+        let name = self.fresh("const");
+        self.initializers.push(tensor_proto(&name, t)?);
+        Ok(name)
+    }
+
+    fn ints(&mut self, values: &[usize]) -> Result<String> {
+        let values = values.iter().map(|&v| v as i64).collect::<Vec<_>>();
+        self.constant(&Tensor::new(values.as_slice(), &candle::Device::Cpu)?)
+    }
+
+    fn scalar(&mut self, v: f64, dtype: DType) -> Result<String> {
+        self.constant(&Tensor::new(v, &candle::Device::Cpu)?.to_dtype(dtype)?)
+    }
+
+    fn cast(&mut self, input: &str, dt: DataType) -> String {
+        self.node("Cast", &[input], vec![int_attr("to", dt as i64)])
+    }
+
+    /// Returns the name of the onnx value for `t`, adding the nodes computing it if needed.
+    fn value(&mut self, t: &Tensor) -> Result<String> {
+        if let Some(name) = self.names.get(&t.id()) {
+            return Ok(name.clone());
+        }
+        let name = match t.op() {
+            None => self.constant(t)?,
+            Some(op) => self.op(t, op)?,
+        };
+        self.names.insert(t.id(), name.clone());
+        Ok(name)
+    }
+
+    fn op(&mut self, t: &Tensor, op: &Op) -> Result<String> {
+        let name = match op {
+            Op::Binary(lhs, rhs, op) => {
+                let (lhs, rhs) = (self.value(lhs)?, self.value(rhs)?);
+                let op_type = match op {
+                    BinaryOp::Add => "Add",
+                    BinaryOp::Mul => "Mul",
+                    BinaryOp::Sub => "Sub",
+                    BinaryOp::Div => "Div",
+                    BinaryOp::Maximum => "Max",
+                    BinaryOp::Minimum => "Min",
+                };
+                self.node(op_type, &[&lhs, &rhs], vec![])
+            }
+            Op::Unary(arg, op) => {
+                let arg = self.value(arg)?;
+                match op {
+                    UnaryOp::Exp => self.node("Exp", &[&arg], vec![]),
+                    UnaryOp::Log => self.node("Log", &[&arg], vec![]),
+                    UnaryOp::Sin => self.node("Sin", &[&arg], vec![]),
+                    UnaryOp::Cos => self.node("Cos", &[&arg], vec![]),
+                    UnaryOp::Abs => self.node("Abs", &[&arg], vec![]),
+                    UnaryOp::Neg => self.node("Neg", &[&arg], vec![]),
+                    UnaryOp::Recip => self.node("Reciprocal", &[&arg], vec![]),
+                    UnaryOp::Sqr => self.node("Mul", &[&arg, &arg], vec![]),
+                    UnaryOp::Sqrt => self.node("Sqrt", &[&arg], vec![]),
+                    UnaryOp::Gelu => {
+                        self.node("Gelu", &[&arg], vec![string_attr("approximate", "tanh")])
+                    }
+                    UnaryOp::GeluErf => self.node("Gelu", &[&arg], vec![]),
+                    UnaryOp::Erf => self.node("Erf", &[&arg], vec![]),
+                    UnaryOp::Relu => self.node("Relu", &[&arg], vec![]),
+                    UnaryOp::Silu => {
+                        let sigmoid = self.node("Sigmoid", &[&arg], vec![]);
+                        self.node("Mul", &[&arg, &sigmoid], vec![])
+                    }
+                    UnaryOp::Tanh => self.node("Tanh", &[&arg], vec![]),
+                    UnaryOp::Floor => self.node("Floor", &[&arg], vec![]),
+                    UnaryOp::Ceil => self.node("Ceil", &[&arg], vec![]),
+                    UnaryOp::Sign => self.node("Sign", &[&arg], vec![]),
+                    UnaryOp::Round => bail!("onnx export is not supported for round"),
+                }
+            }
+            Op::Reduce(arg, op, _) => {
+                // The reduced dims are the ones that changed, the reduction keeps the dims.
+                let axes = arg
+                    .dims()
+                    .iter()
+                    .zip(t.dims())
+                    .enumerate()
+                    .filter(|(_, (a, b))| a != b)
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                let arg = self.value(arg)?;
+                if axes.is_empty() {
+                    self.node("Identity", &[&arg], vec![])
+                } else {
+                    let op_type = match op {
+                        ReduceOp::Sum => "ReduceSum",
+                        ReduceOp::Max => "ReduceMax",
+                        ReduceOp::Min => "ReduceMin",
+                        ReduceOp::ArgMax | ReduceOp::ArgMin => {
+                            bail!("onnx export is not supported for {op:?}")
+                        }
+                    };
+                    let axes = self.ints(&axes)?;
+                    self.node(op_type, &[&arg, &axes], vec![int_attr("keepdims", 1)])
+                }
+            }
+            Op::Matmul(lhs, rhs) => {
+                let (lhs, rhs) = (self.value(lhs)?, self.value(rhs)?);
+                self.node("MatMul", &[&lhs, &rhs], vec![])
+            }
+            Op::Gather(arg, ids, dim) => {
+                let arg = self.value(arg)?;
+                let ids = self.value(ids)?;
+                let ids = self.cast(&ids, DataType::Int64);
+                self.node(
+                    "GatherElements",
+                    &[&arg, &ids],
+                    vec![int_attr("axis", *dim as i64)],
+                )
+            }
+            Op::IndexSelect(arg, ids, dim) => {
+                let arg = self.value(arg)?;
+                let ids = self.value(ids)?;
+                let ids = self.cast(&ids, DataType::Int64);
+                self.node("Gather", &[&arg, &ids], vec![int_attr("axis", *dim as i64)])
+            }
+            Op::Scatter(arg, ids, src, dim) | Op::ScatterAdd(arg, ids, src, dim) => {
+                let reduction = match op {
+                    Op::ScatterAdd(..) => "add",
+                    _ => "none",
+                };
+                let arg = self.value(arg)?;
+                let ids = self.value(ids)?;
+                let ids = self.cast(&ids, DataType::Int64);
+                let src = self.value(src)?;
+                let attrs = vec![
+                    int_attr("axis", *dim as i64),
+                    string_attr("reduction", reduction),
+                ];
+                self.node("ScatterElements", &[&arg, &ids, &src], attrs)
+            }
+            Op::WhereCond(pred, on_true, on_false) => {
+                let pred = self.value(pred)?;
+                let pred = self.cast(&pred, DataType::Bool);
+                let on_true = self.value(on_true)?;
+                let on_false = self.value(on_false)?;
+                self.node("Where", &[&pred, &on_true, &on_false], vec![])
+            }
+            Op::Conv1D {
+                arg,
+                kernel,
+                padding,
+                stride,
+                dilation,
+            }
+            | Op::Conv2D {
+                arg,
+                kernel,
+                padding,
+                stride,
+                dilation,
+            } => {
+                let n_spatial = kernel.rank() - 2;
+                let arg = self.value(arg)?;
+                let kernel = self.value(kernel)?;
+                let attrs = vec![
+                    ints_attr("pads", &vec![*padding; 2 * n_spatial]),
+                    ints_attr("strides", &vec![*stride; n_spatial]),
+                    ints_attr("dilations", &vec![*dilation; n_spatial]),
+                ];
+                self.node("Conv", &[&arg, &kernel], attrs)
+            }
+            Op::ConvTranspose1D {
+                arg,
+                kernel,
+                padding,
+                output_padding,
+                stride,
+                dilation,
+            }
+            | Op::ConvTranspose2D {
+                arg,
+                kernel,
+                padding,
+                output_padding,
+                stride,
+                dilation,
+            } => {
+                let n_spatial = kernel.rank() - 2;
+                let arg = self.value(arg)?;
+                let kernel = self.value(kernel)?;
+                let attrs = vec![
+                    ints_attr("pads", &vec![*padding; 2 * n_spatial]),
+                    ints_attr("output_padding", &vec![*output_padding; n_spatial]),
+                    ints_attr("strides", &vec![*stride; n_spatial]),
+                    ints_attr("dilations", &vec![*dilation; n_spatial]),
+                ];
+                self.node("ConvTranspose", &[&arg, &kernel], attrs)
+            }
+            Op::AvgPool2D {
+                arg,
+                kernel_size,
+                stride,
+            }
+            | Op::MaxPool2D {
+                arg,
+                kernel_size,
+                stride,
+            } => {
+                let op_type = match op {
+                    Op::AvgPool2D { .. } => "AveragePool",
+                    _ => "MaxPool",
+                };
+                let arg = self.value(arg)?;
+                let attrs = vec![
+                    ints_attr("kernel_shape", &[kernel_size.0, kernel_size.1]),
+                    ints_attr("strides", &[stride.0, stride.1]),
+                ];
+                self.node(op_type, &[&arg], attrs)
+            }
+            Op::UpsampleNearest2D { arg, .. } => {
+                let arg = self.value(arg)?;
+                let sizes = self.ints(t.dims())?;
+                let attrs = vec![
+                    string_attr("mode", "nearest"),
+                    string_attr("coordinate_transformation_mode", "asymmetric"),
+                    string_attr("nearest_mode", "floor"),
+                ];
+                self.node("Resize", &[&arg, "", "", &sizes], attrs)
+            }
+            Op::Cat(args, dim) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.value(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                let args = args.iter().map(|v| v.as_str()).collect::<Vec<_>>();
+                self.node("Concat", &args, vec![int_attr("axis", *dim as i64)])
+            }
+            Op::Affine { arg, mul, add } => {
+                let dtype = arg.dtype();
+                let arg = self.value(arg)?;
+                let mul = self.scalar(*mul, dtype)?;
+                let add = self.scalar(*add, dtype)?;
+                let ys = self.node("Mul", &[&arg, &mul], vec![]);
+                self.node("Add", &[&ys, &add], vec![])
+            }
+            Op::ToDType(arg) => {
+                let arg = self.value(arg)?;
+                self.cast(&arg, data_type(t.dtype())?)
+            }
+            Op::Copy(arg) | Op::ToDevice(arg) => {
+                let arg = self.value(arg)?;
+                self.node("Identity", &[&arg], vec![])
+            }
+            Op::Broadcast(arg) => {
+                let arg = self.value(arg)?;
+                let shape = self.ints(t.dims())?;
+                self.node("Expand", &[&arg, &shape], vec![])
+            }
+            Op::Reshape(arg) => {
+                let arg = self.value(arg)?;
+                let shape = self.ints(t.dims())?;
+                self.node("Reshape", &[&arg, &shape], vec![])
+            }
+            Op::Narrow(arg, dim, start, len) => {
+                let arg = self.value(arg)?;
+                let starts = self.ints(&[*start])?;
+                let ends = self.ints(&[start + len])?;
+                let axes = self.ints(&[*dim])?;
+                self.node("Slice", &[&arg, &starts, &ends, &axes], vec![])
+            }
+            Op::Transpose(arg, dim1, dim2) => {
+                let mut perm = (0..arg.rank()).collect::<Vec<_>>();
+                perm.swap(*dim1, *dim2);
+                let arg = self.value(arg)?;
+                self.node("Transpose", &[&arg], vec![ints_attr("perm", &perm)])
+            }
+            Op::Permute(arg, perm) => {
+                let arg = self.value(arg)?;
+                self.node("Transpose", &[&arg], vec![ints_attr("perm", perm)])
+            }
+            Op::Elu(arg, alpha) => {
+                let arg = self.value(arg)?;
+                self.node("Elu", &[&arg], vec![float_attr("alpha", *alpha)])
+            }
+            Op::Powf(arg, e) => {
+                let exp = self.scalar(*e, arg.dtype())?;
+                let arg = self.value(arg)?;
+                self.node("Pow", &[&arg, &exp], vec![])
+            }
+            Op::CustomOp1(arg, c) => {
+                let arg = self.value(arg)?;
+                match c.name() {
+                    "sigmoid" => self.node("Sigmoid", &[&arg], vec![]),
+                    name => bail!("onnx export is not supported for the custom op {name}"),
+                }
+            }
+            Op::CustomOp2(_, _, c) => {
+                bail!(
+                    "onnx export is not supported for the custom op {}",
+                    c.name()
+                )
+            }
+            Op::CustomOp3(_, _, _, c) => {
+                bail!(
+                    "onnx export is not supported for the custom op {}",
+                    c.name()
+                )
+            }
+            Op::Cmp(..) => bail!("onnx export is not supported for cmp"),
+            Op::IndexAdd(..) => bail!("onnx export is not supported for index-add"),
+            Op::SliceScatter0(..) => bail!("onnx export is not supported for slice-scatter"),
+            Op::UpsampleNearest1D { .. } => {
+                bail!("onnx export is not supported for upsample-nearest1d")
+            }
+            Op::UpsampleBilinear2D { .. } => {
+                bail!("onnx export is not supported for upsample-bilinear2d")
+            }
+        };
+        Ok(name)
+    }
+}
+
+/// Exports the computation of `outputs` from `inputs` as an onnx model.
+///
+/// The inputs have to be variables so that the operations applied to them are recorded, e.g.
+/// using `Var::from_tensor`, and the variables from `varmap` are exported as initializers using
+/// their names in the var map.
+///
+/// The values computed by ops without backprop support are not recorded and get exported as
+/// constants, [`export_module`] checks the exported graph on another input to catch this.
+pub fn export_graph(
+    inputs: &[(&str, &Tensor)],
+    outputs: &[(&str, &Tensor)],
+    varmap: &VarMap,
+) -> Result<onnx::ModelProto> {
+    let mut tracer = Tracer::default();
+    let mut graph = onnx::GraphProto {
+        name: "candle".to_string(),
+        ..Default::default()
+    };
+    for (name, t) in inputs.iter() {
+        if !t.is_variable() {
+            bail!("the input {name} has to be a variable for its ops to be traced")
+        }
+        tracer.names.insert(t.id(), name.to_string());
+        graph.input.push(value_info(name, t)?);
+    }
+    {
+        let vars = varmap.data().lock().unwrap();
+        let mut vars = vars.iter().collect::<Vec<_>>();
+        vars.sort_by_key(|(name, _)| name.as_str());
+        for (name, var) in vars {
+            if tracer.names.contains_key(&var.id()) {
+                continue;
+            }
+            tracer.names.insert(var.id(), name.clone());
+            graph.initializer.push(tensor_proto(name, var.as_tensor())?);
+        }
+    }
+    for (name, t) in outputs.iter() {
+        let value = tracer.value(t)?;
+        // The output names are provided by the caller so the last node is renamed, or an
+        // identity node is added when the output is not computed by a node of its own.
+        match tracer.nodes.last_mut() {
+            Some(node)
+                if node.output[0] == value && graph.output.iter().all(|o| o.name != value) =>
+            {
+                node.output[0] = name.to_string()
+            }
+            _ => tracer.nodes.push(onnx::NodeProto {
+                op_type: "Identity".to_string(),
+                name: name.to_string(),
+                input: vec![value],
+                output: vec![name.to_string()],
+                ..Default::default()
+            }),
+        }
+        tracer.names.insert(t.id(), name.to_string());
+        graph.output.push(value_info(name, t)?);
+    }
+    let used = tracer
+        .nodes
+        .iter()
+        .flat_map(|node| node.input.iter())
+        .collect::<std::collections::HashSet<_>>();
+    // The ops that do not support backprop, e.g. `softmax_last_dim` or the fused layer norm,
+    // are not recorded so their outputs are traced as constants.
+    for input in graph.input.iter() {
+        if !used.contains(&input.name) {
+            bail!(
+                "the outputs do not depend on {}, some op is likely missing backprop support",
+                input.name
+            )
+        }
+    }
+    // The variables that are not used by the exported computation are dropped.
+    graph.initializer.retain(|t| used.contains(&t.name));
+    graph.initializer.extend(tracer.initializers);
+    graph.node = tracer.nodes;
+    Ok(onnx::ModelProto {
+        ir_version: onnx::Version::IrVersion as i64,
+        producer_name: "candle".to_string(),
+        opset_import: vec![onnx::OperatorSetIdProto {
+            domain: "".to_string(),
+            version: OPSET_VERSION,
+        }],
+        graph: Some(graph),
+        ..Default::default()
+    })
+}
+
+/// Exports the forward pass of `module` on `input` as an onnx model with a single input named
+/// `input` and a single output named `output`.
+pub fn export_module<M: Module>(
+    module: &M,
+    varmap: &VarMap,
+    input: &Tensor,
+) -> Result<onnx::ModelProto> {
+    let input = Var::from_tensor(input)?;
+    let output = module.forward(input.as_tensor())?;
+    let proto = export_graph(
+        &[("input", input.as_tensor())],
+        &[("output", &output)],
+        varmap,
+    )?;
+    // A traced graph can still depend on the input while some of its values were turned into
+    // constants, e.g. with a residual connection around an op without backprop support, so the
+    // exported graph is checked against the module on another input.
+    let input = perturb(input.as_tensor())?;
+    let expected = module.forward(&input)?;
+    let inputs = HashMap::from([("input".to_string(), input)]);
+    let mut outputs = crate::simple_eval(&proto, inputs)?;
+    let output = match outputs.remove("output") {
+        Some(output) => output,
+        None => bail!("the exported graph has no output"),
+    };
+    if output.shape() != expected.shape() {
+        bail!(
+            "the exported graph returns a shape {:?} instead of {:?}",
+            output.shape(),
+            expected.shape()
+        )
+    }
+    let tolerance = match expected.dtype() {
+        DType::F16 | DType::BF16 => 1e-2,
+        _ => 1e-4,
+    };
+    let expected = expected.to_dtype(DType::F64)?.flatten_all()?;
+    let output = output.to_dtype(DType::F64)?.flatten_all()?;
+    let max_abs = expected.abs()?.max(0)?.to_scalar::<f64>()?;
+    let diff = (output - &expected)?.abs()?.max(0)?.to_scalar::<f64>()?;
+    if diff > tolerance * (1. + max_abs) {
+        bail!(
+            "the exported graph differs from the module by {diff} on another input, some op is likely missing backprop support"
+        )
+    }
+    Ok(proto)
+}
+
+// Returns an input with the same shape and dtype as `t` but different values, the elements are
+// rotated and the float ones are also rescaled so that constant inputs change too.
+fn perturb(t: &Tensor) -> Result<Tensor> {
+    let p = if t.elem_count() == 0 {
+        t.clone()
+    } else {
+        t.flatten_all()?.roll(1, 0)?.reshape(t.shape())?
+    };
+    if t.dtype().is_float() {
+        p.affine(-1.5, 0.25)
+    } else {
+        Ok(p)
+    }
+}
@@ -0,0 +1,252 @@
+//! Loading of the tensors stored outside of the protobuf file.
+//!
+//! A protobuf message cannot be larger than 2GB so the weights of large models are saved in
+//! separate files, the tensors then have their `data_location` set to `EXTERNAL` and an
+//! `external_data` list giving the `location` of the file relative to the model directory as
+//! well as an optional `offset` and `length` in bytes. The external files are memory mapped and
+//! kept alive in an [`ExternalData`] handle, the tensors are built from the mapped bytes when
+//! they are materialized so the data is never copied into the proto.
+use crate::onnx::{self, tensor_proto::DataLocation};
+use candle::{bail, Result};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+enum ExternalFile {
+    Mmap(memmap2::Mmap),
+    // Used when the file cannot be memory mapped, e.g. on some network file systems, the bytes
+    // of each tensor are read when it is materialized.
+    File {
+        file: Mutex<std::fs::File>,
+        len: usize,
+    },
+}
+
+impl ExternalFile {
+    fn open(path: &Path) -> Result<Self> {
+        let file =
+            std::fs::File::open(path).map_err(|err| candle::Error::from(err).with_path(path))?;
+        // SAFETY: the file is only read, modifying it while the model is alive is undefined
+        // behavior similar to the safetensors mmap loading.
+        match unsafe { memmap2::MmapOptions::new().map(&file) } {
+            Ok(mmap) => Ok(Self::Mmap(mmap)),
+            Err(_) => {
+                let len = file
+                    .metadata()
+                    .map_err(|err| candle::Error::from(err).with_path(path))?
+                    .len() as usize;
+                Ok(Self::File {
+                    file: Mutex::new(file),
+                    len,
+                })
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Mmap(mmap) => mmap.len(),
+            Self::File { len, .. } => *len,
+        }
+    }
+
+    fn read(&self, start: usize, end: usize) -> Result<Cow<'_, [u8]>> {
+        match self {
+            Self::Mmap(mmap) => Ok(Cow::Borrowed(&mmap[start..end])),
+            Self::File { file, .. } => {
+                let mut file = file.lock().unwrap();
+                let mut data = vec![0u8; end - start];
+                file.seek(SeekFrom::Start(start as u64))?;
+                file.read_exact(&mut data)?;
+                Ok(Cow::Owned(data))
+            }
+        }
+    }
+}
+
+/// The `location`, `offset` and `length` of the external data of a tensor.
+struct Location<'a> {
+    location: &'a Path,
+    offset: usize,
+    length: Option<usize>,
+}
+
+impl<'a> Location<'a> {
+    fn of(t: &'a onnx::TensorProto) -> Result<Self> {
+        let mut location = None;
+        let mut offset = 0usize;
+        let mut length = None;
+        for entry in t.external_data.iter() {
+            let parse = |v: &str| {
+                v.parse::<usize>().map_err(|_| {
+                    candle::Error::msg(format!(
+                        "invalid external data {} '{v}' for tensor {}",
+                        entry.key, t.name
+                    ))
+                })
+            };
+            match entry.key.as_str() {
+                "location" => location = Some(entry.value.as_str()),
+                "offset" => offset = parse(&entry.value)?,
+                "length" => length = Some(parse(&entry.value)?),
+                // The checksum is optional and not verified.
+                _ => {}
+            }
+        }
+        let location = match location {
+            Some(location) => Path::new(location),
+            None => bail!("no location in the external data for tensor {}", t.name),
+        };
+        // The location has to stay within the model directory.
+        if !location
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            bail!(
+                "invalid external data location {location:?} for tensor {}",
+                t.name
+            )
+        }
+        Ok(Self {
+            location,
+            offset,
+            length,
+        })
+    }
+
+    fn range(&self, t: &onnx::TensorProto, file: &ExternalFile) -> Result<(usize, usize)> {
+        let offset = self.offset;
+        let end = match self.length {
+            Some(length) => offset.saturating_add(length),
+            None => file.len(),
+        };
+        if offset > end || end > file.len() {
+            bail!(
+                "external data {offset}..{end} of tensor {} exceeds the file size {}",
+                t.name,
+                file.len()
+            )
+        }
+        Ok((offset, end))
+    }
+}
+
+/// The memory mapped files holding the data of the external tensors of a model, this is
+/// cheap to clone and has to be kept alongside the model, see [`crate::Model`].
+#[derive(Clone, Default)]
+pub struct ExternalData {
+    // The files indexed by their location relative to the model directory.
+    files: Arc<HashMap<PathBuf, ExternalFile>>,
+}
+
+impl std::fmt::Debug for ExternalData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ExternalData[files: {}]", self.files.len())
+    }
+}
+
+impl ExternalData {
+    /// The bytes of `t`, either its `raw_data` or the range of the external file holding them.
+    pub fn raw_data<'a>(&'a self, t: &'a onnx::TensorProto) -> Result<Cow<'a, [u8]>> {
+        if t.data_location != DataLocation::External as i32 {
+            return Ok(Cow::Borrowed(t.raw_data.as_slice()));
+        }
+        let location = Location::of(t)?;
+        let file = match self.files.get(location.location) {
+            Some(file) => file,
+            None => bail!(
+                "the data for {} is stored externally, use Model::read to load it",
+                t.name
+            ),
+        };
+        let (start, end) = location.range(t, file)?;
+        file.read(start, end)
+    }
+}
+
+struct Loader<'a> {
+    base_dir: &'a Path,
+    files: HashMap<PathBuf, ExternalFile>,
+}
+
+impl Loader<'_> {
+    fn tensor(&mut self, t: &onnx::TensorProto) -> Result<()> {
+        if t.data_location != DataLocation::External as i32 {
+            return Ok(());
+        }
+        let location = Location::of(t)?;
+        let file = match self.files.entry(location.location.to_path_buf()) {
+            std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+            std::collections::hash_map::Entry::Vacant(e) => {
+                let file = ExternalFile::open(&self.base_dir.join(e.key()))?;
+                e.insert(file)
+            }
+        };
+        location.range(t, file)?;
+        Ok(())
+    }
+
+    fn sparse_tensor(&mut self, t: &onnx::SparseTensorProto) -> Result<()> {
+        for t in t.values.iter().chain(t.indices.iter()) {
+            self.tensor(t)?
+        }
+        Ok(())
+    }
+
+    fn node(&mut self, node: &onnx::NodeProto) -> Result<()> {
+        for attr in node.attribute.iter() {
+            for t in attr.t.iter().chain(attr.tensors.iter()) {
+                self.tensor(t)?
+            }
+            for t in attr.sparse_tensor.iter().chain(attr.sparse_tensors.iter()) {
+                self.sparse_tensor(t)?
+            }
+            for g in attr.g.iter().chain(attr.graphs.iter()) {
+                self.graph(g)?
+            }
+        }
+        Ok(())
+    }
+
+    fn graph(&mut self, graph: &onnx::GraphProto) -> Result<()> {
+        for t in graph.initializer.iter() {
+            self.tensor(t)?
+        }
+        for t in graph.sparse_initializer.iter() {
+            self.sparse_tensor(t)?
+        }
+        for node in graph.node.iter() {
+            self.node(node)?
+        }
+        Ok(())
+    }
+}
+
+/// Memory maps the files holding the data of the tensors of `model` that are stored externally,
+/// the locations are resolved relative to `base_dir`, usually the directory of the model file.
+/// The locations and the ranges of all the external tensors are checked here.
+///
+/// This is done by [`crate::Model::read`], it is only needed for the models obtained otherwise,
+/// e.g. with [`crate::read_file`].
+pub fn load_external_data<P: AsRef<Path>>(
+    model: &onnx::ModelProto,
+    base_dir: P,
+) -> Result<ExternalData> {
+    let mut loader = Loader {
+        base_dir: base_dir.as_ref(),
+        files: HashMap::new(),
+    };
+    if let Some(graph) = model.graph.as_ref() {
+        loader.graph(graph)?
+    }
+    for function in model.functions.iter() {
+        for node in function.node.iter() {
+            loader.node(node)?
+        }
+    }
+    Ok(ExternalData {
+        files: Arc::new(loader.files),
+    })
+}
@@ -0,0 +1,1366 @@
+//! Static inspection of onnx models.
+//!
+//! [`inspect`] walks the graph once and propagates the dtypes and shapes of the values without
+//! evaluating the model. The dimensions are either known, symbolic when they come from a named
+//! graph input dimension such as `batch_size`, or unknown when they depend on the data. The small
+//! constants are evaluated so that the shape computations that are common in exported models,
+//! e.g. `Shape -> Gather -> Concat -> Reshape`, can be followed.
+//!
+//! This makes it possible to find the unsupported ops and the shape mismatches up front, the
+//! resulting [`ModelInfo`] can be printed as a summary.
+use crate::eval::{dtype, eval_node, get_tensor, Value};
+use crate::external_data::ExternalData;
+use crate::onnx::{self, tensor_proto::DataType};
+use crate::session::NON_DETERMINISTIC_OPS;
+use candle::{DType, Result};
+use std::collections::{BTreeMap, HashMap};
+
+/// The op types that [`crate::simple_eval`] can evaluate.
+pub const SUPPORTED_OPS: &[&str] = &[
+    "Abs",
+    "Add",
+    "And",
+    "ArgMax",
+    "ArgMin",
+    "Attention",
+    "AveragePool",
+    "BatchNormalization",
+    "BiasGelu",
+    "Cast",
+    "Ceil",
+    "Clip",
+    "Concat",
+    "ConcatFromSequence",
+    "Constant",
+    "ConstantOfShape",
+    "Conv",
+    "ConvInteger",
+    "ConvTranspose",
+    "Cos",
+    "CumSum",
+    "DequantizeLinear",
+    "Div",
+    "Dropout",
+    "DynamicQuantizeLinear",
+    "Einsum",
+    "Elu",
+    "Equal",
+    "Erf",
+    "Exp",
+    "Expand",
+    "FastGelu",
+    "Flatten",
+    "Floor",
+    "GRU",
+    "Gather",
+    "GatherElements",
+    "Gelu",
+    "Gemm",
+    "GlobalAveragePool",
+    "Greater",
+    "GreaterOrEqual",
+    "HardSigmoid",
+    "HardSwish",
+    "Identity",
+    "If",
+    "InstanceNormalization",
+    "LSTM",
+    "LayerNormalization",
+    "LeakyRelu",
+    "Less",
+    "LessOrEqual",
+    "Log",
+    "LogSoftmax",
+    "Loop",
+    "MatMul",
+    "MatMulInteger",
+    "Max",
+    "MaxPool",
+    "Mean",
+    "Min",
+    "Mod",
+    "Mul",
+    "Neg",
+    "NonZero",
+    "Not",
+    "OneHot",
+    "Or",
+    "PRelu",
+    "Pad",
+    "Pow",
+    "QLinearMatMul",
+    "QuantizeLinear",
+    "RNN",
+    "RandomNormal",
+    "RandomUniform",
+    "Range",
+    "Reciprocal",
+    "ReduceMax",
+    "ReduceMean",
+    "ReduceMin",
+    "ReduceSum",
+    "Relu",
+    "Reshape",
+    "Resize",
+    "Scan",
+    "ScatterElements",
+    "ScatterND",
+    "Selu",
+    "SequenceAt",
+    "SequenceConstruct",
+    "SequenceEmpty",
+    "SequenceErase",
+    "SequenceInsert",
+    "SequenceLength",
+    "Shape",
+    "Sigmoid",
+    "Sign",
+    "Sin",
+    "Size",
+    "SkipLayerNormalization",
+    "Slice",
+    "Softmax",
+    "Softplus",
+    "Split",
+    "Sqrt",
+    "Squeeze",
+    "Sub",
+    "Tanh",
+    "Tile",
+    "TopK",
+    "Transpose",
+    "Trilu",
+    "Unsqueeze",
+    "Where",
+    "Xor",
+];
+
+// The ops that keep the shape and dtype of their first input.
+const ELEMENTWISE_OPS: &[&str] = &[
+    "Abs",
+    "BatchNormalization",
+    "BiasGelu",
+    "Ceil",
+    "Clip",
+    "Cos",
+    "CumSum",
+    "Elu",
+    "Erf",
+    "Exp",
+    "FastGelu",
+    "Floor",
+    "Gelu",
+    "HardSigmoid",
+    "HardSwish",
+    "Identity",
+    "InstanceNormalization",
+    "LeakyRelu",
+    "Log",
+    "LogSoftmax",
+    "Neg",
+    "Reciprocal",
+    "Relu",
+    "ScatterElements",
+    "ScatterND",
+    "Selu",
+    "Sigmoid",
+    "Sign",
+    "Sin",
+    "Softmax",
+    "Softplus",
+    "Sqrt",
+    "Tanh",
+    "Trilu",
+];
+
+// The ops whose output is the broadcast of their inputs, with the dtype of the first input.
+const BROADCAST_OPS: &[&str] = &[
+    "Add", "Div", "Max", "Mean", "Min", "Mod", "Mul", "PRelu", "Pow", "Sub",
+];
+
+// The broadcasting ops that return booleans.
+const CMP_OPS: &[&str] = &[
+    "And",
+    "Equal",
+    "Greater",
+    "GreaterOrEqual",
+    "Less",
+    "LessOrEqual",
+    "Or",
+    "Xor",
+];
+
+// The constants with more elements than this are not kept for the shape computations.
+const MAX_CONST_ELEMS: usize = 1024;
+
+/// A dimension of a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dim {
+    Known(usize),
+    /// A dimension named in the graph inputs or outputs, e.g. `batch_size`.
+    Symbolic(String),
+    Unknown,
+}
+
+impl std::fmt::Display for Dim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Known(d) => write!(f, "{d}"),
+            Self::Symbolic(s) => write!(f, "{s}"),
+            Self::Unknown => write!(f, "?"),
+        }
+    }
+}
+
+/// The dtype and shape of a value, `None` when it could not be inferred.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ValueInfo {
+    pub dtype: Option<DType>,
+    pub shape: Option<Vec<Dim>>,
+}
+
+impl ValueInfo {
+    fn new(dtype: Option<DType>, shape: Option<Vec<Dim>>) -> Self {
+        Self { dtype, shape }
+    }
+
+    fn known(dtype: DType, dims: &[usize]) -> Self {
+        Self::new(
+            Some(dtype),
+            Some(dims.iter().map(|&d| Dim::Known(d)).collect()),
+        )
+    }
+
+    fn from_proto(vi: &onnx::ValueInfoProto) -> Self {
+        use onnx::tensor_shape_proto::dimension;
+        let tt = match vi.r#type.as_ref().and_then(|t| t.value.as_ref()) {
+            Some(onnx::type_proto::Value::TensorType(tt)) => tt,
+            _ => return Self::default(),
+        };
+        let dtype = DataType::try_from(tt.elem_type).ok().and_then(onnx_dtype);
+        let shape = tt.shape.as_ref().map(|shape| {
+            shape
+                .dim
+                .iter()
+                .map(|dim| match &dim.value {
+                    Some(dimension::Value::DimValue(v)) if *v >= 0 => Dim::Known(*v as usize),
+                    Some(dimension::Value::DimParam(s)) if !s.is_empty() => {
+                        Dim::Symbolic(s.clone())
+                    }
+                    _ => Dim::Unknown,
+                })
+                .collect()
+        });
+        Self::new(dtype, shape)
+    }
+
+    pub fn rank(&self) -> Option<usize> {
+        self.shape.as_ref().map(|s| s.len())
+    }
+
+    /// The dimensions when they are all known.
+    pub fn dims(&self) -> Option<Vec<usize>> {
+        self.shape
+            .as_ref()?
+            .iter()
+            .map(|d| match d {
+                Dim::Known(d) => Some(*d),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether some dimensions are only known when evaluating the model.
+    pub fn is_dynamic(&self) -> bool {
+        self.dims().is_none()
+    }
+
+    fn with_dtype(&self, dtype: DType) -> Self {
+        Self::new(Some(dtype), self.shape.clone())
+    }
+}
+
+impl std::fmt::Display for ValueInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.dtype {
+            Some(dtype) => write!(f, "{}", dtype.as_str())?,
+            None => write!(f, "?")?,
+        }
+        match &self.shape {
+            Some(shape) => {
+                let shape = shape.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+                write!(f, "[{}]", shape.join(", "))
+            }
+            None => write!(f, "[..]"),
+        }
+    }
+}
+
+/// The result of the inspection of a node.
+#[derive(Debug, Clone)]
+pub struct NodeInfo {
+    pub name: String,
+    pub op_type: String,
+    pub supported: bool,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<(String, ValueInfo)>,
+    /// The issue found when propagating the shapes through this node, e.g. incompatible input
+    /// shapes or a failure when evaluating its constant inputs.
+    pub error: Option<String>,
+}
+
+/// The result of [`inspect`].
+#[derive(Debug, Clone)]
+pub struct ModelInfo {
+    pub inputs: Vec<(String, ValueInfo)>,
+    pub outputs: Vec<(String, ValueInfo)>,
+    pub nodes: Vec<NodeInfo>,
+    /// The inferred information for all the values, including the initializers.
+    pub values: HashMap<String, ValueInfo>,
+    /// The number of elements in the initializers.
+    pub num_parameters: usize,
+}
+
+impl ModelInfo {
+    /// The op types that cannot be evaluated, together with their number of occurrences.
+    pub fn unsupported_ops(&self) -> BTreeMap<&str, usize> {
+        let mut ops = BTreeMap::new();
+        for node in self.nodes.iter().filter(|n| !n.supported) {
+            *ops.entry(node.op_type.as_str()).or_default() += 1
+        }
+        ops
+    }
+
+    /// The graph inputs with some dimensions that are only known at runtime.
+    pub fn dynamic_inputs(&self) -> Vec<&(String, ValueInfo)> {
+        self.inputs.iter().filter(|(_, v)| v.is_dynamic()).collect()
+    }
+
+    /// The graph outputs with some dimensions that are only known at runtime.
+    pub fn dynamic_outputs(&self) -> Vec<&(String, ValueInfo)> {
+        self.outputs
+            .iter()
+            .filter(|(_, v)| v.is_dynamic())
+            .collect()
+    }
+
+    /// The nodes for which an issue was found.
+    pub fn errors(&self) -> impl Iterator<Item = &NodeInfo> {
+        self.nodes.iter().filter(|n| n.error.is_some())
+    }
+}
+
+impl std::fmt::Display for ModelInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dynamic = |v: &ValueInfo| if v.is_dynamic() { " (dynamic)" } else { "" };
+        writeln!(f, "inputs:")?;
+        for (name, v) in self.inputs.iter() {
+            writeln!(f, "  {name}: {v}{}", dynamic(v))?
+        }
+        writeln!(f, "outputs:")?;
+        for (name, v) in self.outputs.iter() {
+            writeln!(f, "  {name}: {v}{}", dynamic(v))?
+        }
+        writeln!(f, "parameters: {}", self.num_parameters)?;
+        let mut ops = BTreeMap::new();
+        for node in self.nodes.iter() {
+            *ops.entry(node.op_type.as_str()).or_insert(0usize) += 1
+        }
+        writeln!(f, "nodes: {}", self.nodes.len())?;
+        for (op_type, count) in ops {
+            writeln!(f, "  {op_type}: {count}")?
+        }
+        let unsupported = self.unsupported_ops();
+        if !unsupported.is_empty() {
+            writeln!(f, "unsupported ops:")?;
+            for (op_type, count) in unsupported {
+                writeln!(f, "  {op_type}: {count}")?
+            }
+        }
+        let mut errors = self.errors().peekable();
+        if errors.peek().is_some() {
+            writeln!(f, "errors:")?;
+            for node in errors {
+                let error = node.error.as_deref().unwrap_or_default();
+                writeln!(f, "  {} ({}): {error}", node.name, node.op_type)?
+            }
+        }
+        Ok(())
+    }
+}
+
+// Similar to `dtype` but using the dtypes produced by the evaluation for the integer types that
+// are widened.
+fn onnx_dtype(dt: DataType) -> Option<DType> {
+    match dt {
+        DataType::Int32 | DataType::Int8 => Some(DType::I64),
+        dt => dtype(dt),
+    }
+}
+
+// An element of a small integer tensor, typically a shape.
+#[derive(Debug, Clone, PartialEq)]
+enum SymInt {
+    Int(i64),
+    Sym(String),
+    Unknown,
+}
+
+impl SymInt {
+    fn from_dim(d: &Dim) -> Self {
+        match d {
+            Dim::Known(d) => Self::Int(*d as i64),
+            Dim::Symbolic(s) => Self::Sym(s.clone()),
+            Dim::Unknown => Self::Unknown,
+        }
+    }
+
+    fn to_dim(&self) -> Dim {
+        match self {
+            Self::Int(v) if *v >= 0 => Dim::Known(*v as usize),
+            Self::Sym(s) => Dim::Symbolic(s.clone()),
+            _ => Dim::Unknown,
+        }
+    }
+}
+
+type InferResult<T> = std::result::Result<T, String>;
+
+fn broadcast(lhs: &[Dim], rhs: &[Dim]) -> InferResult<Vec<Dim>> {
+    let rank = lhs.len().max(rhs.len());
+    let mut dims = Vec::with_capacity(rank);
+    for i in 0..rank {
+        let l = (i + lhs.len()).checked_sub(rank).map(|i| &lhs[i]);
+        let r = (i + rhs.len()).checked_sub(rank).map(|i| &rhs[i]);
+        let d = match (l, r) {
+            (None, Some(d)) | (Some(d), None) => d.clone(),
+            (Some(Dim::Known(1)), Some(d)) | (Some(d), Some(Dim::Known(1))) => d.clone(),
+            (Some(Dim::Known(l)), Some(Dim::Known(r))) => {
+                if l != r {
+                    return Err(format!("cannot broadcast {lhs:?} with {rhs:?}"));
+                }
+                Dim::Known(*l)
+            }
+            // The other dimension can only be 1 or equal to the known one.
+            (Some(Dim::Known(d)), Some(_)) | (Some(_), Some(Dim::Known(d))) => Dim::Known(*d),
+            (Some(l), Some(r)) if l == r => l.clone(),
+            (Some(_), Some(_)) => Dim::Unknown,
+            (None, None) => unreachable!(),
+        };
+        dims.push(d)
+    }
+    Ok(dims)
+}
+
+fn product(dims: &[Dim]) -> Dim {
+    let mut non_trivial = dims.iter().filter(|d| **d != Dim::Known(1));
+    match (non_trivial.next(), non_trivial.next()) {
+        (None, _) => Dim::Known(1),
+        (Some(d), None) => d.clone(),
+        _ => {
+            let mut n = 1;
+            for d in dims.iter() {
+                match d {
+                    Dim::Known(d) => n *= d,
+                    _ => return Dim::Unknown,
+                }
+            }
+            Dim::Known(n)
+        }
+    }
+}
+
+// The dimension `d` such that `total` has the same number of elements as `others` and `d`,
+// `None` if the known dimensions are not divisible.
+fn remaining_dim(total: &[Dim], others: &[Dim]) -> Option<Dim> {
+    let mut total = total.to_vec();
+    let mut others = others.to_vec();
+    // The symbolic dimensions that appear on both sides cancel out.
+    others.retain(|d| match d {
+        Dim::Symbolic(_) => match total.iter().position(|t| t == d) {
+            Some(i) => {
+                total.remove(i);
+                false
+            }
+            None => true,
+        },
+        _ => true,
+    });
+    let (n, m) = match (product(&total), product(&others)) {
+        (Dim::Known(n), Dim::Known(m)) => (n, m),
+        (d, Dim::Known(1)) => return Some(d),
+        _ => return Some(Dim::Unknown),
+    };
+    if m == 0 {
+        return Some(Dim::Unknown);
+    }
+    if n % m != 0 {
+        return None;
+    }
+    Some(Dim::Known(n / m))
+}
+
+fn normalize_axis(axis: i64, rank: usize) -> InferResult<usize> {
+    let a = if axis < 0 { axis + rank as i64 } else { axis };
+    if a < 0 || a >= rank as i64 {
+        return Err(format!("axis {axis} out of range for rank {rank}"));
+    }
+    Ok(a as usize)
+}
+
+fn attr<'a>(node: &'a onnx::NodeProto, name: &str) -> Option<&'a onnx::AttributeProto> {
+    node.attribute.iter().find(|a| a.name == name)
+}
+
+fn attr_int(node: &onnx::NodeProto, name: &str, default: i64) -> i64 {
+    attr(node, name).map_or(default, |a| a.i)
+}
+
+fn attr_ints<'a>(node: &'a onnx::NodeProto, name: &str) -> Option<&'a [i64]> {
+    attr(node, name).map(|a| a.ints.as_slice())
+}
+
+fn attr_str<'a>(node: &'a onnx::NodeProto, name: &str, default: &'a str) -> &'a str {
+    attr(node, name).map_or(default, |a| std::str::from_utf8(&a.s).unwrap_or(default))
+}
+
+#[derive(Default)]
+struct Inferer {
+    infos: HashMap<String, ValueInfo>,
+    // The small constants, used to resolve the shape inputs.
+    consts: HashMap<String, Value>,
+    // The small integer tensors whose values are known symbolically, e.g. the shape of an input.
+    syms: HashMap<String, Vec<SymInt>>,
+}
+
+impl Inferer {
+    fn info(&self, name: &str) -> ValueInfo {
+        self.infos.get(name).cloned().unwrap_or_default()
+    }
+
+    fn input(&self, node: &onnx::NodeProto, i: usize) -> ValueInfo {
+        match node.input.get(i) {
+            Some(name) => self.info(name),
+            None => ValueInfo::default(),
+        }
+    }
+
+    fn shape(&self, node: &onnx::NodeProto, i: usize) -> InferResult<Vec<Dim>> {
+        match self.input(node, i).shape {
+            Some(shape) => Ok(shape),
+            None => Err(String::new()),
+        }
+    }
+
+    /// The values of a small integer tensor, scalars are returned as a single element.
+    fn sym_values(&self, name: &str) -> Option<Vec<SymInt>> {
+        if let Some(v) = self.syms.get(name) {
+            return Some(v.clone());
+        }
+        let t = self.consts.get(name)?;
+        if t.rank() > 1 || !t.dtype().is_int() {
+            return None;
+        }
+        let v = t.flatten_all().ok()?.to_dtype(DType::I64).ok()?;
+        Some(
+            v.to_vec1::<i64>()
+                .ok()?
+                .into_iter()
+                .map(SymInt::Int)
+                .collect(),
+        )
+    }
+
+    fn ints(&self, node: &onnx::NodeProto, i: usize) -> Option<Vec<i64>> {
+        let name = node.input.get(i).filter(|n| !n.is_empty())?;
+        self.sym_values(name)?
+            .into_iter()
+            .map(|v| match v {
+                SymInt::Int(v) => Some(v),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The axes given either as an input (recent opsets) or as an attribute.
+    fn axes(&self, node: &onnx::NodeProto, input: usize) -> Option<Vec<i64>> {
+        match node.input.get(input).filter(|n| !n.is_empty()) {
+            Some(_) => self.ints(node, input),
+            None => attr_ints(node, "axes").map(|v| v.to_vec()),
+        }
+    }
+
+    fn fold(&mut self, node: &onnx::NodeProto) -> Result<bool> {
+        let inputs = node.input.iter().filter(|n| !n.is_empty());
+        let has_graph = node
+            .attribute
+            .iter()
+            .any(|a| a.g.is_some() || !a.graphs.is_empty());
+        if has_graph
+            || NON_DETERMINISTIC_OPS.contains(&node.op_type.as_str())
+            || !SUPPORTED_OPS.contains(&node.op_type.as_str())
+            || !inputs.clone().all(|n| self.consts.contains_key(n))
+        {
+            return Ok(false);
+        }
+        let mut values = inputs
+            .map(|n| (n.clone(), self.consts[n].clone()))
+            .collect::<HashMap<_, _>>();
+        let mut sequences = HashMap::new();
+        eval_node(node, &mut values, &mut sequences, &ExternalData::default())?;
+        for output in node.output.iter() {
+            if let Some(t) = values.remove(output) {
+                self.infos
+                    .insert(output.clone(), ValueInfo::known(t.dtype(), t.dims()));
+                if t.elem_count() <= MAX_CONST_ELEMS {
+                    self.consts.insert(output.clone(), t);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    // Tracks the integer values that are computed from shapes.
+    fn propagate_syms(&mut self, node: &onnx::NodeProto) {
+        let input = |i: usize| {
+            node.input
+                .get(i)
+                .filter(|n| !n.is_empty())
+                .and_then(|n| self.sym_values(n))
+        };
+        let values = match node.op_type.as_str() {
+            "Shape" => self.input(node, 0).shape.map(|shape| {
+                let rank = shape.len() as i64;
+                let clamp = |v: i64| (if v < 0 { v + rank } else { v }).clamp(0, rank) as usize;
+                let start = clamp(attr_int(node, "start", 0));
+                let end = clamp(attr_int(node, "end", rank));
+                shape[start..end.max(start)]
+                    .iter()
+                    .map(SymInt::from_dim)
+                    .collect()
+            }),
+            "Identity" | "Cast" | "Squeeze" | "Unsqueeze" => input(0),
+            "Gather" if attr_int(node, "axis", 0) == 0 => match (input(0), self.ints(node, 1)) {
+                (Some(data), Some(indices)) => indices
+                    .iter()
+                    .map(|&i| {
+                        let i = if i < 0 { i + data.len() as i64 } else { i };
+                        data.get(i as usize).cloned()
+                    })
+                    .collect(),
+                _ => None,
+            },
+            "Concat" => (0..node.input.len())
+                .map(input)
+                .collect::<Option<Vec<_>>>()
+                .map(|vs| vs.concat()),
+            "Slice" => match (input(0), self.ints(node, 1), self.ints(node, 2)) {
+                (Some(data), Some(starts), Some(ends)) if starts.len() == 1 => {
+                    let len = data.len() as i64;
+                    let clamp = |v: i64| (if v < 0 { v + len } else { v }).clamp(0, len) as usize;
+                    let (start, end) = (clamp(starts[0]), clamp(ends[0]));
+                    Some(data[start..end.max(start)].to_vec())
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        if let (Some(values), Some(output)) = (values, node.output.first()) {
+            if values.iter().any(|v| !matches!(v, SymInt::Int(_))) {
+                self.syms.insert(output.clone(), values);
+            }
+        }
+    }
+
+    fn pool(&self, node: &onnx::NodeProto, kernel: &[Dim], channels: Dim) -> InferResult<Vec<Dim>> {
+        let xs = self.shape(node, 0)?;
+        if xs.len() < 2 || kernel.len() != xs.len() - 2 {
+            return Err(format!("unexpected input shape {xs:?}"));
+        }
+        let n = kernel.len();
+        let ones = vec![1; n];
+        let pads = attr_ints(node, "pads").map_or(vec![0; 2 * n], |v| v.to_vec());
+        let strides = attr_ints(node, "strides").unwrap_or(&ones);
+        let dilations = attr_ints(node, "dilations").unwrap_or(&ones);
+        let auto_pad = attr_str(node, "auto_pad", "NOTSET");
+        let ceil_mode = attr_int(node, "ceil_mode", 0) == 1;
+        let mut dims = vec![xs[0].clone(), channels];
+        for i in 0..n {
+            let d = match (&xs[2 + i], &kernel[i]) {
+                (Dim::Known(x), Dim::Known(k)) => {
+                    let (x, k, s) = (*x as i64, *k as i64, strides[i]);
+                    let d = match auto_pad {
+                        "SAME_UPPER" | "SAME_LOWER" => (x + s - 1) / s,
+                        _ => {
+                            let (pb, pe) = match auto_pad {
+                                "VALID" => (0, 0),
+                                _ => (pads[i], pads[n + i]),
+                            };
+                            let span = x + pb + pe - dilations[i] * (k - 1) - 1;
+                            if span < 0 {
+                                return Err(format!("kernel larger than the input {xs:?}"));
+                            }
+                            if ceil_mode {
+                                (span + s - 1) / s + 1
+                            } else {
+                                span / s + 1
+                            }
+                        }
+                    };
+                    Dim::Known(d as usize)
+                }
+                _ => Dim::Unknown,
+            };
+            dims.push(d)
+        }
+        Ok(dims)
+    }
+
+    fn infer(&self, node: &onnx::NodeProto) -> InferResult<Vec<ValueInfo>> {
+        let x = self.input(node, 0);
+        let op_type = node.op_type.as_str();
+        let infos = match op_type {
+            _ if ELEMENTWISE_OPS.contains(&op_type) => vec![x],
+            _ if BROADCAST_OPS.contains(&op_type) || CMP_OPS.contains(&op_type) => {
+                let mut shape = Some(vec![]);
+                for i in 0..node.input.len() {
+                    shape = match (shape, self.input(node, i).shape) {
+                        (Some(s), Some(t)) => Some(broadcast(&s, &t)?),
+                        _ => None,
+                    }
+                }
+                let dtype = if CMP_OPS.contains(&op_type) {
+                    Some(DType::U8)
+                } else {
+                    x.dtype
+                };
+                vec![ValueInfo::new(dtype, shape)]
+            }
+            "Not" => vec![x.with_dtype(DType::U8)],
+            "Where" => {
+                let shape = match (
+                    x.shape,
+                    self.input(node, 1).shape,
+                    self.input(node, 2).shape,
+                ) {
+                    (Some(c), Some(a), Some(b)) => Some(broadcast(&broadcast(&c, &a)?, &b)?),
+                    _ => None,
+                };
+                vec![ValueInfo::new(self.input(node, 1).dtype, shape)]
+            }
+            "Dropout" => vec![x.clone(), x.with_dtype(DType::U8)],
+            "MatMul" | "MatMulInteger" | "QLinearMatMul" => {
+                let rhs_idx = if op_type == "QLinearMatMul" { 3 } else { 1 };
+                let dtype = match op_type {
+                    "MatMul" => x.dtype,
+                    "MatMulInteger" => Some(DType::I64),
+                    _ => self.input(node, 7).dtype,
+                };
+                let shape = match (x.shape, self.input(node, rhs_idx).shape) {
+                    (Some(mut a), Some(mut b)) => {
+                        let (a_vec, b_vec) = (a.len() == 1, b.len() == 1);
+                        if a_vec {
+                            a.insert(0, Dim::Known(1))
+                        }
+                        if b_vec {
+                            b.push(Dim::Known(1))
+                        }
+                        if a.len() < 2 || b.len() < 2 {
+                            return Err(format!("unexpected matmul shapes {a:?} {b:?}"));
+                        }
+                        let (k1, k2) = (&a[a.len() - 1], &b[b.len() - 2]);
+                        if matches!((k1, k2), (Dim::Known(k1), Dim::Known(k2)) if k1 != k2) {
+                            return Err(format!("incompatible matmul shapes {a:?} {b:?}"));
+                        }
+                        let mut dims = broadcast(&a[..a.len() - 2], &b[..b.len() - 2])?;
+                        if !a_vec {
+                            dims.push(a[a.len() - 2].clone())
+                        }
+                        if !b_vec {
+                            dims.push(b[b.len() - 1].clone())
+                        }
+                        Some(dims)
+                    }
+                    _ => None,
+                };
+                vec![ValueInfo::new(dtype, shape)]
+            }
+            "Gemm" => {
+                let (a, b) = (self.shape(node, 0)?, self.shape(node, 1)?);
+                if a.len() != 2 || b.len() != 2 {
+                    return Err(format!("unexpected gemm shapes {a:?} {b:?}"));
+                }
+                let m = a[attr_int(node, "transA", 0) as usize].clone();
+                let n = b[1 - attr_int(node, "transB", 0) as usize].clone();
+                vec![ValueInfo::new(x.dtype, Some(vec![m, n]))]
+            }
+            "Conv" | "ConvInteger" => {
+                let w = self.shape(node, 1)?;
+                let kernel = match attr_ints(node, "kernel_shape") {
+                    Some(k) => k.iter().map(|&k| Dim::Known(k as usize)).collect(),
+                    None => w.get(2..).unwrap_or_default().to_vec(),
+                };
+                let shape = self.pool(node, &kernel, w[0].clone())?;
+                let dtype = if op_type == "Conv" {
+                    x.dtype
+                } else {
+                    Some(DType::I64)
+                };
+                vec![ValueInfo::new(dtype, Some(shape))]
+            }
+            "MaxPool" | "AveragePool" => {
+                let kernel = attr_ints(node, "kernel_shape")
+                    .ok_or("missing kernel_shape")?
+                    .iter()
+                    .map(|&k| Dim::Known(k as usize))
+                    .collect::<Vec<_>>();
+                let channels = self.shape(node, 0)?.get(1).cloned().unwrap_or(Dim::Unknown);
+                vec![ValueInfo::new(
+                    x.dtype,
+                    Some(self.pool(node, &kernel, channels)?),
+                )]
+            }
+            "ConvTranspose" => {
+                let (xs, w) = (self.shape(node, 0)?, self.shape(node, 1)?);
+                let n = w.len().saturating_sub(2);
+                if xs.len() != n + 2 {
+                    return Err(format!("unexpected shapes {xs:?} {w:?}"));
+                }
+                let ones = vec![1; n];
+                let zeros = vec![0; 2 * n];
+                let strides = attr_ints(node, "strides").unwrap_or(&ones);
+                let dilations = attr_ints(node, "dilations").unwrap_or(&ones);
+                let output_padding = attr_ints(node, "output_padding").unwrap_or(&zeros);
+                let pads = attr_ints(node, "pads").unwrap_or(&zeros);
+                let group = attr_int(node, "group", 1) as usize;
+                let channels = match &w[1] {
+                    Dim::Known(c) => Dim::Known(c * group),
+                    _ => Dim::Unknown,
+                };
+                let mut dims = vec![xs[0].clone(), channels];
+                let output_shape = attr_ints(node, "output_shape");
+                for i in 0..n {
+                    let d = match (output_shape, &xs[2 + i], &w[2 + i]) {
+                        (Some(s), _, _) => Dim::Known(s[s.len() - n + i] as usize),
+                        (None, Dim::Known(x), Dim::Known(k)) => {
+                            let full = strides[i] * (*x as i64 - 1)
+                                + output_padding[i]
+                                + (*k as i64 - 1) * dilations[i]
+                                + 1;
+                            Dim::Known((full - pads[i] - pads[n + i]).max(0) as usize)
+                        }
+                        _ => Dim::Unknown,
+                    };
+                    dims.push(d)
+                }
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "GlobalAveragePool" => {
+                let xs = self.shape(node, 0)?;
+                let mut dims = xs.iter().take(2).cloned().collect::<Vec<_>>();
+                dims.resize(xs.len(), Dim::Known(1));
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "Transpose" => {
+                let xs = self.shape(node, 0)?;
+                let perm = match attr_ints(node, "perm") {
+                    Some(perm) => perm.iter().map(|&p| p as usize).collect(),
+                    None => (0..xs.len()).rev().collect::<Vec<_>>(),
+                };
+                let dims = perm
+                    .iter()
+                    .map(|&p| xs.get(p).cloned())
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(format!("invalid perm {perm:?} for {xs:?}"))?;
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "Reshape" => {
+                let shape = node.input.get(1).and_then(|n| self.sym_values(n));
+                let shape = match shape {
+                    Some(shape) => {
+                        let xs = x.shape.clone();
+                        let mut dims = shape
+                            .iter()
+                            .enumerate()
+                            .map(|(i, v)| match v {
+                                SymInt::Int(0) if attr_int(node, "allowzero", 0) == 0 => xs
+                                    .as_ref()
+                                    .and_then(|xs| xs.get(i).cloned())
+                                    .unwrap_or(Dim::Unknown),
+                                v => v.to_dim(),
+                            })
+                            .collect::<Vec<_>>();
+                        if let Some(i) = shape.iter().position(|v| *v == SymInt::Int(-1)) {
+                            let others = [&dims[..i], &dims[i + 1..]].concat();
+                            dims[i] = match xs.as_ref() {
+                                Some(xs) => remaining_dim(xs, &others)
+                                    .ok_or_else(|| format!("cannot reshape {xs:?} to {shape:?}"))?,
+                                None => Dim::Unknown,
+                            }
+                        }
+                        Some(dims)
+                    }
+                    None => match self.input(node, 1).dims() {
+                        Some(d) if d.len() == 1 => Some(vec![Dim::Unknown; d[0]]),
+                        _ => None,
+                    },
+                };
+                vec![ValueInfo::new(x.dtype, shape)]
+            }
+            "Flatten" => {
+                let xs = self.shape(node, 0)?;
+                let axis = attr_int(node, "axis", 1);
+                let axis = if axis < 0 {
+                    (axis + xs.len() as i64).max(0) as usize
+                } else {
+                    (axis as usize).min(xs.len())
+                };
+                let dims = vec![product(&xs[..axis]), product(&xs[axis..])];
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "Squeeze" => {
+                let xs = self.shape(node, 0)?;
+                let dims = match self.axes(node, 1) {
+                    Some(axes) => {
+                        let axes = axes
+                            .iter()
+                            .map(|&a| normalize_axis(a, xs.len()))
+                            .collect::<InferResult<Vec<_>>>()?;
+                        let dims = xs.iter().enumerate().filter(|(i, _)| !axes.contains(i));
+                        Some(dims.map(|(_, d)| d.clone()).collect())
+                    }
+                    None if xs.iter().all(|d| matches!(d, Dim::Known(_))) => {
+                        Some(xs.into_iter().filter(|d| *d != Dim::Known(1)).collect())
+                    }
+                    None => None,
+                };
+                vec![ValueInfo::new(x.dtype, dims)]
+            }
+            "Unsqueeze" => {
+                let xs = self.shape(node, 0)?;
+                let dims = match self.axes(node, 1) {
+                    Some(axes) => {
+                        let rank = xs.len() + axes.len();
+                        let mut axes = axes
+                            .iter()
+                            .map(|&a| normalize_axis(a, rank))
+                            .collect::<InferResult<Vec<_>>>()?;
+                        axes.sort();
+                        let mut dims = xs;
+                        for a in axes {
+                            dims.insert(a.min(dims.len()), Dim::Known(1))
+                        }
+                        Some(dims)
+                    }
+                    None => None,
+                };
+                vec![ValueInfo::new(x.dtype, dims)]
+            }
+            "Concat" => {
+                let axis = attr_int(node, "axis", 0);
+                let mut shape: Option<Vec<Dim>> = None;
+                for i in 0..node.input.len() {
+                    let s = self.shape(node, i)?;
+                    let a = normalize_axis(axis, s.len())?;
+                    shape = Some(match shape {
+                        None => s,
+                        Some(mut dims) => {
+                            if dims.len() != s.len() {
+                                return Err(format!("rank mismatch {dims:?} {s:?}"));
+                            }
+                            dims[a] = match (&dims[a], &s[a]) {
+                                (Dim::Known(x), Dim::Known(y)) => Dim::Known(x + y),
+                                _ => Dim::Unknown,
+                            };
+                            for (j, d) in dims.iter_mut().enumerate() {
+                                if j != a && !matches!(d, Dim::Known(_)) {
+                                    *d = s[j].clone()
+                                }
+                            }
+                            dims
+                        }
+                    })
+                }
+                vec![ValueInfo::new(x.dtype, shape)]
+            }
+            "Gather" => {
+                let (xs, ids) = (self.shape(node, 0)?, self.shape(node, 1)?);
+                let axis = normalize_axis(attr_int(node, "axis", 0), xs.len())?;
+                let dims = [&xs[..axis], &ids[..], &xs[axis + 1..]].concat();
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "GatherElements" => vec![ValueInfo::new(x.dtype, self.input(node, 1).shape)],
+            "Shape" => {
+                let rank = self.shape(node, 0)?.len() as i64;
+                let clamp = |v: i64| (if v < 0 { v + rank } else { v }).clamp(0, rank);
+                let start = clamp(attr_int(node, "start", 0));
+                let end = clamp(attr_int(node, "end", rank));
+                let len = (end - start).max(0) as usize;
+                vec![ValueInfo::known(DType::I64, &[len])]
+            }
+            "Size" => vec![ValueInfo::known(DType::I64, &[])],
+            "Cast" => {
+                let to = DataType::try_from(attr_int(node, "to", 0) as i32).ok();
+                vec![ValueInfo::new(to.and_then(onnx_dtype), x.shape)]
+            }
+            "Expand" => {
+                let shape = node.input.get(1).and_then(|n| self.sym_values(n));
+                let shape = match (x.shape.clone(), shape) {
+                    (Some(xs), Some(s)) => Some(broadcast(
+                        &xs,
+                        &s.iter().map(|v| v.to_dim()).collect::<Vec<_>>(),
+                    )?),
+                    _ => None,
+                };
+                vec![ValueInfo::new(x.dtype, shape)]
+            }
+            "ConstantOfShape" => {
+                let shape = node.input.first().and_then(|n| self.sym_values(n));
+                let shape = shape.map(|s| s.iter().map(|v| v.to_dim()).collect());
+                let dtype = match attr(node, "value").and_then(|a| a.t.as_ref()) {
+                    Some(t) => DataType::try_from(t.data_type).ok().and_then(onnx_dtype),
+                    None => Some(DType::F32),
+                };
+                vec![ValueInfo::new(dtype, shape)]
+            }
+            "Slice" => {
+                let xs = self.shape(node, 0)?;
+                let (starts, ends) = match (self.ints(node, 1), self.ints(node, 2)) {
+                    (Some(starts), Some(ends)) => (starts, ends),
+                    _ => {
+                        return Ok(vec![ValueInfo::new(
+                            x.dtype,
+                            Some(vec![Dim::Unknown; xs.len()]),
+                        )])
+                    }
+                };
+                let axes = match self.ints(node, 3) {
+                    Some(axes) => axes,
+                    None if node.input.get(3).is_some_and(|n| !n.is_empty()) => {
+                        return Ok(vec![ValueInfo::new(
+                            x.dtype,
+                            Some(vec![Dim::Unknown; xs.len()]),
+                        )])
+                    }
+                    None => (0..starts.len() as i64).collect(),
+                };
+                let steps = self.ints(node, 4).unwrap_or(vec![1; starts.len()]);
+                let mut dims = xs.clone();
+                for (i, &axis) in axes.iter().enumerate() {
+                    let a = normalize_axis(axis, xs.len())?;
+                    dims[a] = match &xs[a] {
+                        Dim::Known(d) => {
+                            let d = *d as i64;
+                            let step = steps.get(i).copied().unwrap_or(1);
+                            let (lo, hi) = if step > 0 { (0, d) } else { (-1, d - 1) };
+                            let clamp = |v: i64| (if v < 0 { v + d } else { v }).clamp(lo, hi);
+                            let (start, end) = (clamp(starts[i]), clamp(ends[i]));
+                            let len = if step > 0 {
+                                (end - start + step - 1) / step
+                            } else {
+                                (start - end - step - 1) / -step
+                            };
+                            Dim::Known(len.max(0) as usize)
+                        }
+                        _ => Dim::Unknown,
+                    }
+                }
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "ReduceMax" | "ReduceMin" | "ReduceMean" | "ReduceSum" | "ArgMax" | "ArgMin" => {
+                let xs = self.shape(node, 0)?;
+                let keepdims = attr_int(node, "keepdims", 1) == 1;
+                let (axes, dtype) = match op_type {
+                    "ArgMax" | "ArgMin" => {
+                        (Some(vec![attr_int(node, "axis", 0)]), Some(DType::I64))
+                    }
+                    _ => (self.axes(node, 1), x.dtype),
+                };
+                let has_axes_input = node.input.get(1).is_some_and(|n| !n.is_empty());
+                let axes = match axes {
+                    Some(axes) if !axes.is_empty() => axes
+                        .iter()
+                        .map(|&a| normalize_axis(a, xs.len()))
+                        .collect::<InferResult<Vec<_>>>()?,
+                    _ if has_axes_input => return Ok(vec![ValueInfo::new(dtype, None)]),
+                    _ if attr_int(node, "noop_with_empty_axes", 0) == 1 => vec![],
+                    _ => (0..xs.len()).collect(),
+                };
+                let mut dims = vec![];
+                for (i, d) in xs.into_iter().enumerate() {
+                    if !axes.contains(&i) {
+                        dims.push(d)
+                    } else if keepdims {
+                        dims.push(Dim::Known(1))
+                    }
+                }
+                vec![ValueInfo::new(dtype, Some(dims))]
+            }
+            "Split" => {
+                let xs = self.shape(node, 0)?;
+                let axis = normalize_axis(attr_int(node, "axis", 0), xs.len())?;
+                let n = node.output.len();
+                let split = match node.input.get(1).filter(|n| !n.is_empty()) {
+                    Some(_) => self.ints(node, 1),
+                    None => attr_ints(node, "split").map(|s| s.to_vec()),
+                };
+                let split = match (split, &xs[axis]) {
+                    (Some(split), _) => split.iter().map(|&s| Dim::Known(s as usize)).collect(),
+                    (None, Dim::Known(d)) => {
+                        let chunk = d.div_ceil(n);
+                        (0..n)
+                            .map(|i| Dim::Known(chunk.min(d.saturating_sub(i * chunk))))
+                            .collect()
+                    }
+                    (None, _) => vec![Dim::Unknown; n],
+                };
+                split
+                    .into_iter()
+                    .map(|d| {
+                        let mut dims = xs.clone();
+                        dims[axis] = d;
+                        ValueInfo::new(x.dtype, Some(dims))
+                    })
+                    .collect()
+            }
+            "Tile" => {
+                let xs = self.shape(node, 0)?;
+                let dims = match self.ints(node, 1) {
+                    Some(repeats) if repeats.len() == xs.len() => xs
+                        .iter()
+                        .zip(repeats)
+                        .map(|(d, r)| match (d, r) {
+                            (d, 1) => d.clone(),
+                            (Dim::Known(d), r) => Dim::Known(d * r as usize),
+                            _ => Dim::Unknown,
+                        })
+                        .collect(),
+                    _ => vec![Dim::Unknown; xs.len()],
+                };
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "TopK" => {
+                let xs = self.shape(node, 0)?;
+                let axis = normalize_axis(attr_int(node, "axis", -1), xs.len())?;
+                let mut dims = xs;
+                dims[axis] = match self.ints(node, 1).as_deref() {
+                    Some([k]) => Dim::Known(*k as usize),
+                    _ => Dim::Unknown,
+                };
+                vec![
+                    ValueInfo::new(x.dtype, Some(dims.clone())),
+                    ValueInfo::new(Some(DType::I64), Some(dims)),
+                ]
+            }
+            "NonZero" => {
+                let rank = self.shape(node, 0)?.len();
+                let dims = vec![Dim::Known(rank), Dim::Unknown];
+                vec![ValueInfo::new(Some(DType::I64), Some(dims))]
+            }
+            "Pad" => {
+                let xs = self.shape(node, 0)?;
+                let dims = match self.ints(node, 1) {
+                    Some(pads) if pads.len() == 2 * xs.len() => xs
+                        .iter()
+                        .enumerate()
+                        .map(|(i, d)| match d {
+                            Dim::Known(d) => {
+                                Dim::Known((*d as i64 + pads[i] + pads[xs.len() + i]) as usize)
+                            }
+                            d if pads[i] == 0 && pads[xs.len() + i] == 0 => d.clone(),
+                            _ => Dim::Unknown,
+                        })
+                        .collect(),
+                    _ => vec![Dim::Unknown; xs.len()],
+                };
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "Resize" => {
+                let xs = self.shape(node, 0)?;
+                let dims = match self.ints(node, 3) {
+                    Some(sizes) => sizes.iter().map(|&s| Dim::Known(s as usize)).collect(),
+                    None => vec![Dim::Unknown; xs.len()],
+                };
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "LayerNormalization" | "SkipLayerNormalization" => {
+                let xs = self.shape(node, 0)?;
+                let axis = match op_type {
+                    "LayerNormalization" => normalize_axis(attr_int(node, "axis", -1), xs.len())?,
+                    _ => xs.len().saturating_sub(1),
+                };
+                let mut stats = xs[..axis].to_vec();
+                stats.resize(xs.len(), Dim::Known(1));
+                let stats = ValueInfo::new(x.dtype, Some(stats));
+                vec![x.clone(), stats.clone(), stats, x]
+            }
+            "Attention" => {
+                let (xs, w) = (self.shape(node, 0)?, self.shape(node, 1)?);
+                let v_hidden = match attr_ints(node, "qkv_hidden_sizes") {
+                    Some([_, _, v]) => Dim::Known(*v as usize),
+                    _ => match w.get(1) {
+                        Some(Dim::Known(d)) => Dim::Known(d / 3),
+                        _ => Dim::Unknown,
+                    },
+                };
+                let mut dims = xs[..xs.len().saturating_sub(1)].to_vec();
+                dims.push(v_hidden);
+                vec![ValueInfo::new(x.dtype, Some(dims))]
+            }
+            "LSTM" | "GRU" | "RNN" => {
+                let xs = self.shape(node, 0)?;
+                if xs.len() != 3 {
+                    return Err(format!("unexpected input shape {xs:?}"));
+                }
+                let dirs = match attr_str(node, "direction", "forward") {
+                    "bidirectional" => 2,
+                    _ => 1,
+                };
+                let hidden = Dim::Known(attr_int(node, "hidden_size", 0) as usize);
+                let state = vec![Dim::Known(dirs), xs[1].clone(), hidden.clone()];
+                let ys = vec![xs[0].clone(), Dim::Known(dirs), xs[1].clone(), hidden];
+                let state = ValueInfo::new(x.dtype, Some(state));
+                vec![ValueInfo::new(x.dtype, Some(ys)), state.clone(), state]
+            }
+            "Einsum" => {
+                let equation = attr_str(node, "equation", "").replace(' ', "");
+                let shape = match equation.split_once("->") {
+                    Some((lhs, rhs)) if !equation.contains("...") => {
+                        let mut letters = HashMap::new();
+                        for (i, term) in lhs.split(',').enumerate() {
+                            let s = self.shape(node, i)?;
+                            for (c, d) in term.chars().zip(s) {
+                                letters.entry(c).or_insert(d);
+                            }
+                        }
+                        rhs.chars()
+                            .map(|c| letters.get(&c).cloned())
+                            .collect::<Option<Vec<_>>>()
+                    }
+                    _ => None,
+                };
+                vec![ValueInfo::new(x.dtype, shape)]
+            }
+            "QuantizeLinear" => {
+                let dtype = match self.input(node, 2).dtype {
+                    Some(dtype) => dtype,
+                    None => match DataType::try_from(attr_int(node, "output_dtype", 0) as i32) {
+                        Ok(DataType::Int8) => DType::I64,
+                        _ => DType::U8,
+                    },
+                };
+                vec![x.with_dtype(dtype)]
+            }
+            "DequantizeLinear" => vec![x.with_dtype(DType::F32)],
+            "DynamicQuantizeLinear" => vec![
+                x.with_dtype(DType::U8),
+                ValueInfo::known(DType::F32, &[]),
+                ValueInfo::known(DType::U8, &[]),
+            ],
+            _ => vec![],
+        };
+        Ok(infos)
+    }
+
+    fn node(&mut self, node: &onnx::NodeProto) -> NodeInfo {
+        let supported = SUPPORTED_OPS.contains(&node.op_type.as_str());
+        let mut error = None;
+        let folded = match self.fold(node) {
+            Ok(folded) => folded,
+            Err(err) => {
+                error = Some(err.to_string());
+                false
+            }
+        };
+        if !folded && error.is_none() {
+            match self.infer(node) {
+                Ok(infos) => {
+                    for (output, info) in node.output.iter().zip(infos) {
+                        self.infos.insert(output.clone(), info);
+                    }
+                }
+                // An empty message is used when some input shape is unknown.
+                Err(err) if err.is_empty() => {}
+                Err(err) => error = Some(err),
+            }
+            self.propagate_syms(node);
+        }
+        let outputs = node
+            .output
+            .iter()
+            .filter(|name| !name.is_empty())
+            .map(|name| (name.clone(), self.info(name)))
+            .collect();
+        NodeInfo {
+            name: node.name.clone(),
+            op_type: node.op_type.clone(),
+            supported,
+            inputs: node.input.clone(),
+            outputs,
+            error,
+        }
+    }
+}
+
+/// Propagates the dtypes and shapes through the graph of `model`, see the module documentation.
+///
+/// The nodes are expected to be topologically sorted, as required by the onnx format.
+pub fn inspect(model: &onnx::ModelProto) -> Result<ModelInfo> {
+    let graph = match &model.graph {
+        None => candle::bail!("no graph defined in proto"),
+        Some(graph) => graph,
+    };
+    let mut inferer = Inferer::default();
+    let mut num_parameters = 0;
+    for t in graph.initializer.iter() {
+        let dims = t.dims.iter().map(|&d| d as usize).collect::<Vec<_>>();
+        let elem_count = dims.iter().product::<usize>();
+        num_parameters += elem_count;
+        let dtype = DataType::try_from(t.data_type).ok().and_then(onnx_dtype);
+        let info = ValueInfo::new(dtype, Some(dims.iter().map(|&d| Dim::Known(d)).collect()));
+        inferer.infos.insert(t.name.clone(), info);
+        let external = t.data_location == onnx::tensor_proto::DataLocation::External as i32;
+        if elem_count <= MAX_CONST_ELEMS && !external {
+            if let Ok(value) = get_tensor(t, &t.name) {
+                inferer.consts.insert(t.name.clone(), value);
+            }
+        }
+    }
+    // The initializers that are also graph inputs can be overridden so they are not constant.
+    let mut inputs = vec![];
+    for input in graph.input.iter() {
+        let info = ValueInfo::from_proto(input);
+        if inferer.infos.contains_key(&input.name) {
+            inferer.consts.remove(&input.name);
+            continue;
+        }
+        inferer.infos.insert(input.name.clone(), info.clone());
+        inputs.push((input.name.clone(), info));
+    }
+    let nodes = graph.node.iter().map(|node| inferer.node(node)).collect();
+    let outputs = graph
+        .output
+        .iter()
+        .map(|output| {
+            // The declared output shapes are used when nothing could be inferred.
+            let info = match inferer.infos.get(&output.name) {
+                Some(info) if info.shape.is_some() => info.clone(),
+                info => {
+                    let declared = ValueInfo::from_proto(output);
+                    ValueInfo::new(
+                        declared.dtype.or(info.and_then(|i| i.dtype)),
+                        declared.shape,
+                    )
+                }
+            };
+            (output.name.clone(), info)
+        })
+        .collect();
+    Ok(ModelInfo {
+        inputs,
+        outputs,
+        nodes,
+        values: inferer.infos,
+        num_parameters,
+    })
+}
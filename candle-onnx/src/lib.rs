@@ -6,9 +6,63 @@ pub mod onnx {
 }
 
 pub mod eval;
+pub mod export;
+pub mod external_data;
+pub mod inspect;
+pub mod session;
 pub use eval::{dtype, simple_eval};
+pub use external_data::{load_external_data, ExternalData};
+pub use inspect::inspect;
+pub use session::OnnxSession;
 
+/// An onnx model together with the files holding the data of its external tensors.
+#[derive(Debug, Clone)]
+pub struct Model {
+    pub proto: onnx::ModelProto,
+    pub external_data: ExternalData,
+}
+
+impl Model {
+    /// Reads an onnx model, the files holding the tensors stored externally are memory mapped
+    /// from the directory containing the model.
+    pub fn read<P: AsRef<std::path::Path>>(p: P) -> Result<Self> {
+        let p = p.as_ref();
+        let proto = read_file(p)?;
+        let base_dir = p.parent().unwrap_or_else(|| std::path::Path::new(""));
+        let external_data = load_external_data(&proto, base_dir)?;
+        Ok(Self {
+            proto,
+            external_data,
+        })
+    }
+
+    /// Evaluates the model, see [`simple_eval`].
+    pub fn simple_eval(
+        &self,
+        mut inputs: std::collections::HashMap<String, eval::Value>,
+    ) -> Result<std::collections::HashMap<String, eval::Value>> {
+        eval::simple_eval_(&self.proto, &self.external_data, &mut inputs)
+    }
+
+    /// Converts `t`, one of the tensors of the model, see [`eval::get_tensor`].
+    pub fn get_tensor(&self, t: &onnx::TensorProto, name: &str) -> Result<candle::Tensor> {
+        eval::get_tensor_(t, name, &self.external_data)
+    }
+
+    /// Compiles the model, see [`OnnxSession::new`].
+    pub fn session(&self, device: &candle::Device) -> Result<OnnxSession> {
+        OnnxSession::new_(&self.proto, self.external_data.clone(), device)
+    }
+}
+
+/// Reads an onnx model, the tensors stored in external files are not loaded, [`Model::read`]
+/// should be used for the models with external data.
 pub fn read_file<P: AsRef<std::path::Path>>(p: P) -> Result<onnx::ModelProto> {
     let buf = std::fs::read(p)?;
     onnx::ModelProto::decode(buf.as_slice()).map_err(candle::Error::wrap)
 }
+
+pub fn write_file<P: AsRef<std::path::Path>>(model: &onnx::ModelProto, p: P) -> Result<()> {
+    std::fs::write(p, model.encode_to_vec())?;
+    Ok(())
+}
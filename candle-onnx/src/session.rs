@@ -0,0 +1,294 @@
+//! A compiled onnx graph that can be evaluated many times.
+//!
+//! [`crate::simple_eval`] walks the proto on each call, converting all the initializers and
+//! keeping all the intermediary values alive until the end of the evaluation. An
+//! [`OnnxSession`] does this work once:
+//! - the nodes are topologically sorted,
+//! - the initializers are converted and moved to the target device,
+//! - the nodes that only depend on constants are evaluated, their outputs become constants,
+//! - the last use of each value is computed so that the values can be dropped as soon as they
+//!   are not needed anymore.
+use crate::eval::{check_input, eval_node, get_tensor_, Value};
+use crate::external_data::ExternalData;
+use crate::onnx;
+use candle::{bail, Device, Result};
+use std::collections::{HashMap, HashSet};
+
+// The ops whose outputs change between evaluations even with constant inputs.
+pub(crate) const NON_DETERMINISTIC_OPS: [&str; 6] = [
+    "RandomUniform",
+    "RandomUniformLike",
+    "RandomNormal",
+    "RandomNormalLike",
+    "Bernoulli",
+    "Multinomial",
+];
+
+/// The names of the values that a node reads, including the values of the outer scopes that
+/// are captured by its sub-graphs.
+fn node_inputs(node: &onnx::NodeProto) -> Vec<&str> {
+    let mut inputs = node
+        .input
+        .iter()
+        .filter(|name| !name.is_empty())
+        .map(|name| name.as_str())
+        .collect::<Vec<_>>();
+    for attr in node.attribute.iter() {
+        let graphs = attr.g.iter().chain(attr.graphs.iter());
+        for graph in graphs {
+            inputs.extend(captured_values(graph))
+        }
+    }
+    inputs
+}
+
+/// The values used by a sub-graph that are not defined in the sub-graph itself.
+fn captured_values(graph: &onnx::GraphProto) -> Vec<&str> {
+    let mut defined = graph
+        .input
+        .iter()
+        .map(|v| v.name.as_str())
+        .chain(graph.initializer.iter().map(|t| t.name.as_str()))
+        .collect::<HashSet<_>>();
+    let mut captured = vec![];
+    for node in graph.node.iter() {
+        for input in node_inputs(node) {
+            if !defined.contains(input) && !captured.contains(&input) {
+                captured.push(input)
+            }
+        }
+        defined.extend(node.output.iter().map(|v| v.as_str()))
+    }
+    for output in graph.output.iter() {
+        let output = output.name.as_str();
+        if !defined.contains(output) && !captured.contains(&output) {
+            captured.push(output)
+        }
+    }
+    captured
+}
+
+/// Orders the nodes so that each node comes after the producers of its inputs, the original
+/// order is kept when possible.
+fn topological_sort(nodes: &[onnx::NodeProto], available: &HashSet<String>) -> Result<Vec<usize>> {
+    let mut producers = HashMap::new();
+    for (idx, node) in nodes.iter().enumerate() {
+        for output in node.output.iter().filter(|v| !v.is_empty()) {
+            producers.insert(output.as_str(), idx);
+        }
+    }
+    // The number of producer nodes that have not been scheduled for each node.
+    let mut pending = vec![0usize; nodes.len()];
+    let mut consumers = vec![vec![]; nodes.len()];
+    for (idx, node) in nodes.iter().enumerate() {
+        let mut deps = HashSet::new();
+        for input in node_inputs(node) {
+            match producers.get(input) {
+                Some(&producer) => {
+                    if deps.insert(producer) {
+                        consumers[producer].push(idx)
+                    }
+                }
+                None if available.contains(input) => {}
+                None => bail!("cannot find {input} for op '{}'", node.name),
+            }
+        }
+        pending[idx] = deps.len();
+    }
+    let mut ready = std::collections::BTreeSet::new();
+    ready.extend((0..nodes.len()).filter(|&idx| pending[idx] == 0));
+    let mut order = Vec::with_capacity(nodes.len());
+    while let Some(idx) = ready.pop_first() {
+        order.push(idx);
+        for &consumer in consumers[idx].iter() {
+            pending[consumer] -= 1;
+            if pending[consumer] == 0 {
+                ready.insert(consumer);
+            }
+        }
+    }
+    if order.len() != nodes.len() {
+        bail!("the onnx graph contains a cycle")
+    }
+    Ok(order)
+}
+
+/// A compiled onnx model, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct OnnxSession {
+    device: Device,
+    // The initializers and the folded values, on the target device.
+    constants: HashMap<String, Value>,
+    // The sequences produced by the folded nodes, on the target device.
+    constant_sequences: HashMap<String, Vec<Value>>,
+    // The graph inputs that are not initializers.
+    inputs: Vec<onnx::ValueInfoProto>,
+    outputs: Vec<String>,
+    // The nodes to evaluate in order, together with the values to drop after each of them.
+    nodes: Vec<(onnx::NodeProto, Vec<String>)>,
+    // Used by the constant nodes whose value is stored externally.
+    external_data: ExternalData,
+}
+
+impl OnnxSession {
+    /// Compiles the graph of `model`, the constants are stored on `device`. The models with
+    /// external data are compiled through [`crate::Model::session`].
+    pub fn new(model: &onnx::ModelProto, device: &Device) -> Result<Self> {
+        Self::new_(model, ExternalData::default(), device)
+    }
+
+    pub(crate) fn new_(
+        model: &onnx::ModelProto,
+        external_data: ExternalData,
+        device: &Device,
+    ) -> Result<Self> {
+        let graph = match &model.graph {
+            None => bail!("no graph defined in proto"),
+            Some(graph) => graph,
+        };
+        let mut constants = HashMap::new();
+        for t in graph.initializer.iter() {
+            let tensor = get_tensor_(t, t.name.as_str(), &external_data)?.to_device(device)?;
+            constants.insert(t.name.to_string(), tensor);
+        }
+        // Initializers can also be listed as graph inputs, in which case they provide a default
+        // value that is overridden by the provided inputs.
+        let inputs = graph
+            .input
+            .iter()
+            .filter(|input| !constants.contains_key(&input.name))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // The initializers listed as graph inputs are not folded as they can be overridden.
+        let overridable = graph
+            .input
+            .iter()
+            .filter(|input| constants.contains_key(&input.name))
+            .map(|input| input.name.as_str())
+            .collect::<HashSet<_>>();
+
+        let mut available = constants.keys().cloned().collect::<HashSet<_>>();
+        available.extend(inputs.iter().map(|input| input.name.clone()));
+        let order = topological_sort(&graph.node, &available)?;
+
+        // Constant folding, the nodes are evaluated on the target device.
+        let mut nodes = vec![];
+        let mut constant_sequences = HashMap::new();
+        for idx in order {
+            let node = &graph.node[idx];
+            let foldable = !NON_DETERMINISTIC_OPS.contains(&node.op_type.as_str())
+                && node_inputs(node).iter().all(|input| {
+                    (constants.contains_key(*input) || constant_sequences.contains_key(*input))
+                        && !overridable.contains(input)
+                });
+            if foldable {
+                eval_node(
+                    node,
+                    &mut constants,
+                    &mut constant_sequences,
+                    &external_data,
+                )?;
+                for output in node.output.iter() {
+                    if let Some(value) = constants.get_mut(output) {
+                        *value = value.to_device(device)?
+                    }
+                    if let Some(sequence) = constant_sequences.get_mut(output) {
+                        for value in sequence.iter_mut() {
+                            *value = value.to_device(device)?
+                        }
+                    }
+                }
+            } else {
+                nodes.push(node.clone())
+            }
+        }
+        let outputs = graph
+            .output
+            .iter()
+            .map(|output| output.name.clone())
+            .collect::<Vec<_>>();
+
+        // The values are dropped after their last use, except for the graph outputs.
+        let mut last_use = HashMap::new();
+        for (idx, node) in nodes.iter().enumerate() {
+            for output in node.output.iter() {
+                last_use.insert(output.as_str(), idx);
+            }
+            for input in node_inputs(node) {
+                last_use.insert(input, idx);
+            }
+        }
+        for output in outputs.iter() {
+            last_use.remove(output.as_str());
+        }
+        let mut frees = vec![vec![]; nodes.len()];
+        for (name, idx) in last_use {
+            frees[idx].push(name.to_string())
+        }
+        // Only keep the constants that are still used.
+        let used = nodes
+            .iter()
+            .flat_map(node_inputs)
+            .chain(outputs.iter().map(|v| v.as_str()))
+            .map(|v| v.to_string())
+            .collect::<HashSet<_>>();
+        constants.retain(|name, _| used.contains(name));
+        constant_sequences.retain(|name, _| used.contains(name));
+        let nodes = nodes.into_iter().zip(frees).collect();
+        Ok(Self {
+            device: device.clone(),
+            constants,
+            constant_sequences,
+            inputs,
+            outputs,
+            nodes,
+            external_data,
+        })
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// The inputs that have to be provided to [`Self::run`].
+    pub fn inputs(&self) -> &[onnx::ValueInfoProto] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[String] {
+        &self.outputs
+    }
+
+    /// The number of nodes left after constant folding.
+    pub fn num_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Evaluates the graph, the inputs are moved to the session device.
+    pub fn run(&self, inputs: HashMap<String, Value>) -> Result<HashMap<String, Value>> {
+        let mut values = self.constants.clone();
+        let mut sequences = self.constant_sequences.clone();
+        for (name, value) in inputs {
+            values.insert(name, value.to_device(&self.device)?);
+        }
+        for input in self.inputs.iter() {
+            check_input(input, &values)?
+        }
+        for (node, frees) in self.nodes.iter() {
+            eval_node(node, &mut values, &mut sequences, &self.external_data)?;
+            for name in frees.iter() {
+                values.remove(name);
+                sequences.remove(name);
+            }
+        }
+        self.outputs
+            .iter()
+            .map(|name| match values.remove(name) {
+                None if sequences.contains_key(name) => bail!("unsupported sequence output {name}"),
+                None => bail!("cannot find output {name}"),
+                Some(value) => Ok((name.clone(), value)),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,118 @@
+use candle::{DType, Device, Module, Result, Tensor, Var, D};
+use candle_nn::{VarBuilder, VarMap};
+use candle_onnx::export::{export_graph, export_module};
+use prost::Message;
+use std::collections::HashMap;
+
+fn max_abs_diff(a: &Tensor, b: &Tensor) -> Result<f32> {
+    (a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()
+}
+
+// Exports the module, goes through the protobuf encoding and evaluates the imported model.
+fn round_trip<M: Module>(module: &M, varmap: &VarMap, input: &Tensor) -> Result<f32> {
+    let model = export_module(module, varmap, input)?;
+    let model = candle_onnx::onnx::ModelProto::decode(model.encode_to_vec().as_slice())
+        .map_err(candle::Error::wrap)?;
+    let inputs = HashMap::from([("input".to_string(), input.clone())]);
+    let outputs = candle_onnx::simple_eval(&model, inputs)?;
+    max_abs_diff(&outputs["output"], &module.forward(input)?)
+}
+
+#[test]
+fn export_mlp() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let l1 = candle_nn::linear(4, 8, vb.pp("l1"))?;
+    let l2 = candle_nn::linear(8, 3, vb.pp("l2"))?;
+    let mlp = candle_nn::seq()
+        .add(l1)
+        .add(candle_nn::Activation::Gelu)
+        .add(l2)
+        .add_fn(|xs| candle_nn::ops::softmax(xs, D::Minus1));
+    let input = Tensor::randn(0f32, 1., (2, 4), dev)?;
+    assert!(round_trip(&mlp, &varmap, &input)? < 1e-5);
+
+    // The variables are exported as named initializers.
+    let model = export_module(&mlp, &varmap, &input)?;
+    let graph = model.graph.unwrap();
+    for name in ["l1.weight", "l1.bias", "l2.weight", "l2.bias"] {
+        assert!(graph.initializer.iter().any(|t| t.name == name), "{name}")
+    }
+    assert_eq!(graph.output[0].name, "output");
+
+    // The ops without backprop support cannot be traced.
+    let mlp = mlp.add_fn(candle_nn::ops::softmax_last_dim);
+    assert!(export_module(&mlp, &varmap, &input).is_err());
+
+    // A residual connection keeps the input used while the softmax becomes a constant.
+    let l3 = candle_nn::linear(4, 3, vb.pp("l3"))?;
+    let residual = candle_nn::seq()
+        .add(l3)
+        .add_fn(|xs| xs + candle_nn::ops::softmax_last_dim(xs)?);
+    assert!(export_module(&residual, &varmap, &input).is_err());
+    Ok(())
+}
+
+#[test]
+fn export_conv() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let cfg = candle_nn::Conv2dConfig {
+        padding: 1,
+        stride: 2,
+        ..Default::default()
+    };
+    let conv = candle_nn::conv2d(2, 4, 3, cfg, vb.pp("conv"))?;
+    let linear = candle_nn::linear(16, 2, vb.pp("linear"))?;
+    let model = candle_nn::seq()
+        .add(conv)
+        .add(candle_nn::Activation::Silu)
+        .add_fn(|xs| xs.max_pool2d(2)?.flatten_from(1))
+        .add(linear);
+    let input = Tensor::randn(0f32, 1., (2, 2, 8, 8), dev)?;
+    assert!(round_trip(&model, &varmap, &input)? < 1e-5);
+    Ok(())
+}
+
+#[test]
+fn export_ops() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let w = vb.get_with_hints((3, 4), "w", candle_nn::init::DEFAULT_KAIMING_NORMAL)?;
+    let f = |xs: &Tensor| -> Result<Tensor> {
+        let ys = xs.broadcast_mul(&w)?.affine(0.5, 1.)?;
+        let ys = Tensor::cat(&[&ys.narrow(1, 1, 2)?, &ys.sqr()?.exp()?], 1)?;
+        let ys = ys.t()?.contiguous()?.reshape((2, 9))?;
+        let max = ys.max_keepdim(D::Minus1)?;
+        let sum = ys.sum_keepdim(0)?;
+        let ys = ys.broadcast_sub(&max)?.broadcast_add(&sum)?.gelu()?;
+        let mask = Tensor::new(&[1u8, 0, 1, 0, 1, 0, 1, 0, 1], dev)?.broadcast_as((2, 9))?;
+        let ys = mask.where_cond(&ys.abs()?.powf(1.5)?, &ys.tanh()?)?;
+        ys.to_dtype(DType::F64)?.to_dtype(DType::F32)
+    };
+    let input = Tensor::randn(0f32, 1., (3, 4), dev)?;
+    let var = Var::from_tensor(&input)?;
+    let output = f(var.as_tensor())?;
+    let model = export_graph(&[("x", var.as_tensor())], &[("y", &output)], &varmap)?;
+    let outputs = candle_onnx::simple_eval(&model, HashMap::from([("x".to_string(), input)]))?;
+    assert!(max_abs_diff(&outputs["y"], &output)? < 1e-5);
+
+    // The inputs have to be variables for their ops to be recorded.
+    let input = Tensor::randn(0f32, 1., (3, 4), dev)?;
+    assert!(export_graph(&[("x", &input)], &[("y", &input)], &varmap).is_err());
+    Ok(())
+}
+
+#[test]
+fn export_embedding() -> Result<()> {
+    let dev = &Device::Cpu;
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, dev);
+    let embedding = candle_nn::embedding(10, 4, vb.pp("emb"))?;
+    let input = Tensor::new(&[[1u32, 3, 5], [7, 9, 0]], dev)?;
+    assert!(round_trip(&embedding, &varmap, &input)? < 1e-6);
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use candle::{Device, Result, Tensor};
+use candle_onnx::onnx::tensor_proto::DataLocation;
+use candle_onnx::onnx::{
+    GraphProto, ModelProto, NodeProto, StringStringEntryProto, TensorProto, ValueInfoProto,
+};
+use prost::Message;
+use std::collections::HashMap;
+
+fn external_tensor(name: &str, dims: &[i64], location: &str, offset: usize) -> TensorProto {
+    let entry = |key: &str, value: &str| StringStringEntryProto {
+        key: key.to_string(),
+        value: value.to_string(),
+    };
+    let length = dims.iter().product::<i64>() * 4;
+    TensorProto {
+        name: name.to_string(),
+        dims: dims.to_vec(),
+        data_type: 1,
+        data_location: DataLocation::External.into(),
+        external_data: vec![
+            entry("location", location),
+            entry("offset", &offset.to_string()),
+            entry("length", &length.to_string()),
+        ],
+        ..Default::default()
+    }
+}
+
+fn model(initializer: Vec<TensorProto>) -> ModelProto {
+    let value_info = |name: &str| ValueInfoProto {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    ModelProto {
+        graph: Some(GraphProto {
+            node: vec![NodeProto {
+                op_type: "Add".to_string(),
+                input: vec!["x".to_string(), "w".to_string()],
+                output: vec!["y".to_string()],
+                ..Default::default()
+            }],
+            input: vec![value_info("x")],
+            output: vec![value_info("y")],
+            initializer,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn read_external_data() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("candle-onnx-external-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("weights"))?;
+    // Some unrelated bytes come before the tensor data.
+    let mut data = vec![0u8; 8];
+    for v in [1f32, 2., 3.] {
+        data.extend_from_slice(&v.to_le_bytes())
+    }
+    std::fs::write(dir.join("weights/w.bin"), &data)?;
+    let w = external_tensor("w", &[3], "weights/w.bin", 8);
+    std::fs::write(dir.join("model.onnx"), model(vec![w]).encode_to_vec())?;
+
+    let model = candle_onnx::Model::read(dir.join("model.onnx"))?;
+    // The data stays in the memory mapped file rather than being copied to the proto.
+    let w = &model.proto.graph.as_ref().unwrap().initializer[0];
+    assert!(w.raw_data.is_empty());
+    assert_eq!(model.get_tensor(w, "w")?.to_vec1::<f32>()?, [1., 2., 3.]);
+    let inputs = HashMap::from([(
+        "x".to_string(),
+        Tensor::new(&[10f32, 20., 30.], &Device::Cpu)?,
+    )]);
+    let outputs = model.simple_eval(inputs.clone())?;
+    assert_eq!(outputs["y"].to_vec1::<f32>()?, [11., 22., 33.]);
+    let session = model.session(&Device::Cpu)?;
+    // The session keeps working once the model is dropped.
+    drop(model);
+    let outputs = session.run(inputs)?;
+    assert_eq!(outputs["y"].to_vec1::<f32>()?, [11., 22., 33.]);
+
+    // The data has to be loaded before evaluating a model read with `read_file`.
+    let proto = candle_onnx::read_file(dir.join("model.onnx"))?;
+    let inputs = HashMap::from([(
+        "x".to_string(),
+        Tensor::zeros(3, candle::DType::F32, &Device::Cpu)?,
+    )]);
+    assert!(candle_onnx::simple_eval(&proto, inputs.clone()).is_err());
+    let external_data = candle_onnx::load_external_data(&proto, &dir)?;
+    let model = candle_onnx::Model {
+        proto,
+        external_data,
+    };
+    let outputs = model.simple_eval(inputs)?;
+    assert_eq!(outputs["y"].to_vec1::<f32>()?, [1., 2., 3.]);
+
+    // Out of bounds data and locations outside of the model directory are rejected.
+    let model = self::model(vec![external_tensor("w", &[3], "weights/w.bin", 12)]);
+    assert!(candle_onnx::load_external_data(&model, &dir).is_err());
+    let model = self::model(vec![external_tensor("w", &[3], "../w.bin", 8)]);
+    assert!(candle_onnx::load_external_data(&model, dir.join("weights")).is_err());
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use candle::{DType, Result};
+use candle_onnx::inspect::{inspect, Dim};
+use candle_onnx::onnx::{
+    tensor_shape_proto::{dimension, Dimension},
+    type_proto, AttributeProto, GraphProto, ModelProto, NodeProto, TensorProto, TensorShapeProto,
+    TypeProto, ValueInfoProto,
+};
+
+fn node(op_type: &str, input: &[&str], output: &str) -> NodeProto {
+    NodeProto {
+        op_type: op_type.to_string(),
+        input: input.iter().map(|v| v.to_string()).collect(),
+        output: vec![output.to_string()],
+        name: output.to_string(),
+        ..Default::default()
+    }
+}
+
+fn f32_initializer(name: &str, dims: &[i64]) -> TensorProto {
+    TensorProto {
+        name: name.to_string(),
+        dims: dims.to_vec(),
+        data_type: 1,
+        float_data: vec![0.; dims.iter().product::<i64>() as usize],
+        ..Default::default()
+    }
+}
+
+fn i64_initializer(name: &str, data: &[i64]) -> TensorProto {
+    TensorProto {
+        name: name.to_string(),
+        dims: vec![data.len() as i64],
+        data_type: 7,
+        int64_data: data.to_vec(),
+        ..Default::default()
+    }
+}
+
+// A f32 graph input, the negative dimensions are unknown and the others symbolic.
+fn input(name: &str, dims: &[&str]) -> ValueInfoProto {
+    let dim = dims
+        .iter()
+        .map(|d| {
+            let value = match d.parse::<i64>() {
+                Ok(v) if v >= 0 => Some(dimension::Value::DimValue(v)),
+                Ok(_) => None,
+                Err(_) => Some(dimension::Value::DimParam(d.to_string())),
+            };
+            Dimension {
+                value,
+                ..Default::default()
+            }
+        })
+        .collect();
+    ValueInfoProto {
+        name: name.to_string(),
+        r#type: Some(TypeProto {
+            value: Some(type_proto::Value::TensorType(type_proto::Tensor {
+                elem_type: 1,
+                shape: Some(TensorShapeProto { dim }),
+            })),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn output(name: &str) -> ValueInfoProto {
+    ValueInfoProto {
+        name: name.to_string(),
+        ..Default::default()
+    }
+}
+
+fn model(
+    node: Vec<NodeProto>,
+    initializer: Vec<TensorProto>,
+    input: Vec<ValueInfoProto>,
+    output: Vec<ValueInfoProto>,
+) -> ModelProto {
+    let graph = GraphProto {
+        node,
+        initializer,
+        input,
+        output,
+        ..Default::default()
+    };
+    ModelProto {
+        graph: Some(graph),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn inspect_symbolic_batch() -> Result<()> {
+    // relu(x . w + b) > 0 with a symbolic batch size.
+    let model = model(
+        vec![
+            node("MatMul", &["x", "w"], "xw"),
+            node("Add", &["xw", "b"], "y"),
+            node("Relu", &["y"], "z"),
+            node("Greater", &["z", "b"], "mask"),
+        ],
+        vec![f32_initializer("w", &[3, 4]), f32_initializer("b", &[4])],
+        vec![input("x", &["batch", "3"])],
+        vec![output("z"), output("mask")],
+    );
+    let info = inspect(&model)?;
+    assert_eq!(info.num_parameters, 16);
+    assert_eq!(info.inputs.len(), 1);
+    let (name, z) = &info.outputs[0];
+    assert_eq!(name, "z");
+    assert_eq!(z.dtype, Some(DType::F32));
+    assert_eq!(
+        z.shape,
+        Some(vec![Dim::Symbolic("batch".to_string()), Dim::Known(4)])
+    );
+    assert_eq!(z.to_string(), "f32[batch, 4]");
+    assert_eq!(info.outputs[1].1.to_string(), "u8[batch, 4]");
+    assert_eq!(info.dynamic_inputs().len(), 1);
+    assert_eq!(info.dynamic_outputs().len(), 2);
+    assert!(info.unsupported_ops().is_empty());
+    assert_eq!(info.errors().count(), 0);
+    Ok(())
+}
+
+#[test]
+fn inspect_unsupported_ops() -> Result<()> {
+    let model = model(
+        vec![
+            node("Relu", &["x"], "y"),
+            node("NotAnOp", &["y"], "z"),
+            node("NotAnOp", &["z"], "t"),
+        ],
+        vec![],
+        vec![input("x", &["2", "3"])],
+        vec![output("t")],
+    );
+    let info = inspect(&model)?;
+    let unsupported = info.unsupported_ops();
+    assert_eq!(unsupported.len(), 1);
+    assert_eq!(unsupported["NotAnOp"], 2);
+    assert_eq!(info.errors().count(), 0);
+    assert_eq!(info.values["y"].to_string(), "f32[2, 3]");
+    assert_eq!(info.outputs[0].1.to_string(), "?[..]");
+    assert!(info.to_string().contains("unsupported ops:\n  NotAnOp: 2"));
+    Ok(())
+}
+
+#[test]
+fn inspect_shape_computation() -> Result<()> {
+    // Flattens all the dimensions but the batch one: reshape(x, concat(shape(x)[0], [-1])).
+    let gather = NodeProto {
+        attribute: vec![AttributeProto {
+            name: "axis".to_string(),
+            r#type: 2,
+            i: 0,
+            ..Default::default()
+        }],
+        ..node("Gather", &["shape", "zero"], "batch")
+    };
+    let concat = NodeProto {
+        attribute: vec![AttributeProto {
+            name: "axis".to_string(),
+            r#type: 2,
+            i: 0,
+            ..Default::default()
+        }],
+        ..node("Concat", &["batch", "minus_one"], "new_shape")
+    };
+    let model = model(
+        vec![
+            node("Shape", &["x"], "shape"),
+            gather,
+            concat,
+            node("Reshape", &["x", "new_shape"], "y"),
+        ],
+        vec![
+            i64_initializer("zero", &[0]),
+            i64_initializer("minus_one", &[-1]),
+        ],
+        vec![input("x", &["n", "2", "5"])],
+        vec![output("y")],
+    );
+    let info = inspect(&model)?;
+    assert_eq!(info.values["shape"].to_string(), "i64[3]");
+    assert_eq!(info.outputs[0].1.to_string(), "f32[n, 10]");
+
+    // With an unknown dimension, the flattened size cannot be computed.
+    let mut model = model;
+    if let Some(graph) = model.graph.as_mut() {
+        graph.input = vec![input("x", &["n", "-1", "5"])]
+    }
+    let info = inspect(&model)?;
+    assert_eq!(info.outputs[0].1.to_string(), "f32[n, ?]");
+    Ok(())
+}
+
+#[test]
+fn inspect_shape_mismatch() -> Result<()> {
+    let model = model(
+        vec![node("MatMul", &["x", "w"], "y"), node("Relu", &["y"], "z")],
+        vec![f32_initializer("w", &[4, 2])],
+        vec![input("x", &["batch", "3"])],
+        vec![output("z")],
+    );
+    let info = inspect(&model)?;
+    let errors = info.errors().collect::<Vec<_>>();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].op_type, "MatMul");
+    assert!(errors[0].error.as_ref().unwrap().contains("incompatible"));
+    assert!(info.unsupported_ops().is_empty());
+    Ok(())
+}
@@ -1,5 +1,5 @@
-use candle::test_utils::to_vec2_round;
-use candle::{DType, Device, NdArray, Result, Tensor};
+use candle::test_utils::{to_vec1_round, to_vec2_round};
+use candle::{DType, Device, IndexOp, NdArray, Result, Tensor};
 use candle_onnx::onnx::attribute_proto::AttributeType;
 use candle_onnx::onnx::tensor_proto::DataType;
 use candle_onnx::onnx::tensor_shape_proto::{dimension, Dimension};
@@ -7226,3 +7226,969 @@ fn test_one_hot() -> Result<()> {
 
     Ok(())
 }
+
+fn int_attr(name: &str, i: i64) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::Int.into(),
+        i,
+        ..AttributeProto::default()
+    }
+}
+
+fn float_attr(name: &str, f: f32) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::Float.into(),
+        f,
+        ..AttributeProto::default()
+    }
+}
+
+fn string_attr(name: &str, s: &str) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::String.into(),
+        s: s.as_bytes().to_vec(),
+        ..AttributeProto::default()
+    }
+}
+
+// Evaluates a graph made of a single node, the inputs are listed in order.
+fn eval_node_helper(
+    op_name: &str,
+    inputs: &[(&str, &Tensor)],
+    outputs: &[&str],
+    attribs: Vec<AttributeProto>,
+) -> Result<HashMap<String, Tensor>> {
+    let names = inputs.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    let mut graph = make_graph_helper(op_name, &names, outputs, attribs);
+    // The empty names are for the optional node outputs that are not computed.
+    if let Some(graph) = graph.graph.as_mut() {
+        graph.output.retain(|output| !output.name.is_empty())
+    }
+    let inputs = inputs
+        .iter()
+        .map(|(name, t)| (name.to_string(), (*t).clone()))
+        .collect();
+    simple_eval(&graph, inputs)
+}
+
+fn max_abs_diff(a: &Tensor, b: &Tensor) -> Result<f32> {
+    (a - b)?.abs()?.flatten_all()?.max(0)?.to_scalar::<f32>()
+}
+
+#[test]
+fn test_layer_normalization() -> Result<()> {
+    let dev = &Device::Cpu;
+    let x = Tensor::new(&[[1f32, 2., 3.], [2., 4., 6.]], dev)?;
+    let scale = Tensor::new(&[1f32, 2., 1.], dev)?;
+    let bias = Tensor::new(&[0f32, 0., 1.], dev)?;
+    let eval = eval_node_helper(
+        "LayerNormalization",
+        &[("x", &x), ("scale", &scale), ("bias", &bias)],
+        &["y", "mean", "inv_std"],
+        vec![],
+    )?;
+    assert_eq!(
+        to_vec2_round(&eval["y"], 4)?,
+        [[-1.2247, 0., 2.2247], [-1.2247, 0., 2.2247]]
+    );
+    assert_eq!(eval["mean"].to_vec2::<f32>()?, [[2.], [4.]]);
+
+    // Normalize over both dimensions.
+    let eval = eval_node_helper(
+        "LayerNormalization",
+        &[
+            ("x", &x),
+            ("scale", &Tensor::ones((2, 3), DType::F32, dev)?),
+        ],
+        &["y"],
+        vec![int_attr("axis", 0), float_attr("epsilon", 0.)],
+    )?;
+    let y = eval["y"].flatten_all()?;
+    assert!(y.mean_all()?.to_scalar::<f32>()?.abs() < 1e-6);
+    assert!((y.sqr()?.mean_all()?.to_scalar::<f32>()? - 1.).abs() < 1e-5);
+    Ok(())
+}
+
+#[test]
+fn test_instance_normalization_global_average_pool() -> Result<()> {
+    let dev = &Device::Cpu;
+    let x = Tensor::arange(0f32, 16., dev)?.reshape((1, 2, 2, 4))?;
+    let scale = Tensor::new(&[1f32, 2.], dev)?;
+    let bias = Tensor::new(&[0f32, 1.], dev)?;
+    let eval = eval_node_helper(
+        "InstanceNormalization",
+        &[("x", &x), ("scale", &scale), ("bias", &bias)],
+        &["y"],
+        vec![],
+    )?;
+    let y = &eval["y"];
+    assert_eq!(y.dims(), [1, 2, 2, 4]);
+    // Both channels contain 8 consecutive values so only the scale and bias differ.
+    let y0 = y.get(0)?.get(0)?;
+    let y1 = y.get(0)?.get(1)?;
+    assert!(max_abs_diff(&((&y0 * 2.)? + 1.)?, &y1)? < 1e-5);
+    assert!(y0.mean_all()?.to_scalar::<f32>()?.abs() < 1e-6);
+
+    let eval = eval_node_helper("GlobalAveragePool", &[("x", &x)], &["y"], vec![])?;
+    assert_eq!(eval["y"].dims(), [1, 2, 1, 1]);
+    assert_eq!(eval["y"].flatten_all()?.to_vec1::<f32>()?, [3.5, 11.5]);
+    Ok(())
+}
+
+#[test]
+fn test_einsum() -> Result<()> {
+    let dev = &Device::Cpu;
+    let a = Tensor::arange(0f32, 6., dev)?.reshape((2, 3))?;
+    let b = Tensor::arange(0f32, 12., dev)?.reshape((3, 4))?;
+    let c = Tensor::arange(0f32, 8., dev)?.reshape((4, 2))?;
+    let einsum = |equation: &str, inputs: &[&Tensor]| -> Result<Tensor> {
+        let names = ["a", "b", "c"];
+        let inputs = names
+            .iter()
+            .zip(inputs.iter())
+            .map(|(n, t)| (*n, *t))
+            .collect::<Vec<_>>();
+        let eval = eval_node_helper(
+            "Einsum",
+            &inputs,
+            &["y"],
+            vec![string_attr("equation", equation)],
+        )?;
+        Ok(eval["y"].clone())
+    };
+    let ab = a.matmul(&b)?;
+    assert_eq!(
+        einsum("ij,jk->ik", &[&a, &b])?.to_vec2::<f32>()?,
+        ab.to_vec2::<f32>()?
+    );
+    // Implicit output and spaces.
+    assert_eq!(
+        einsum("ij, jk", &[&a, &b])?.to_vec2::<f32>()?,
+        ab.to_vec2::<f32>()?
+    );
+    assert_eq!(
+        einsum("ij,jk->ki", &[&a, &b])?.to_vec2::<f32>()?,
+        ab.t()?.to_vec2::<f32>()?
+    );
+    assert_eq!(
+        einsum("ij,jk,kl->il", &[&a, &b, &c])?.to_vec2::<f32>()?,
+        ab.matmul(&c)?.to_vec2::<f32>()?
+    );
+    assert_eq!(
+        einsum("ij->ji", &[&a])?.to_vec2::<f32>()?,
+        a.t()?.to_vec2::<f32>()?
+    );
+    assert_eq!(einsum("ij->", &[&a])?.to_vec0::<f32>()?, 15.);
+    assert_eq!(
+        einsum("ij->j", &[&a])?.to_vec1::<f32>()?,
+        a.sum(0)?.to_vec1::<f32>()?
+    );
+    let u = Tensor::new(&[1f32, 2.], dev)?;
+    let v = Tensor::new(&[3f32, 4., 5.], dev)?;
+    assert_eq!(
+        einsum("i,j->ij", &[&u, &v])?.to_vec2::<f32>()?,
+        [[3., 4., 5.], [6., 8., 10.]]
+    );
+    assert_eq!(einsum("i,i", &[&u, &u])?.to_vec0::<f32>()?, 5.);
+
+    // Batched matmul with broadcasting over the ellipsis dimensions.
+    let x = Tensor::arange(0f32, 24., dev)?.reshape((2, 1, 3, 4))?;
+    let y = Tensor::arange(0f32, 24., dev)?.reshape((3, 4, 2))?;
+    let xy = einsum("...ij,...jk->...ik", &[&x, &y])?;
+    assert_eq!(xy.dims(), [2, 3, 3, 2]);
+    assert!(max_abs_diff(&xy, &x.broadcast_matmul(&y)?)? < 1e-4);
+    let xy = einsum("bhij,hjk->bhik", &[&x.broadcast_as((2, 3, 3, 4))?, &y])?;
+    assert!(max_abs_diff(&xy, &x.broadcast_matmul(&y)?)? < 1e-4);
+
+    assert!(einsum("ii->i", &[&a]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_topk() -> Result<()> {
+    let dev = &Device::Cpu;
+    let x = Tensor::new(&[[1f32, 5., 3., 5.], [4., 2., 6., 0.]], dev)?;
+    let k = Tensor::new(&[2i64], dev)?;
+    let eval = eval_node_helper(
+        "TopK",
+        &[("x", &x), ("k", &k)],
+        &["values", "indices"],
+        vec![],
+    )?;
+    assert_eq!(eval["values"].to_vec2::<f32>()?, [[5., 5.], [6., 4.]]);
+    assert_eq!(eval["indices"].to_vec2::<i64>()?, [[1, 3], [2, 0]]);
+
+    let eval = eval_node_helper(
+        "TopK",
+        &[("x", &x), ("k", &k)],
+        &["values", "indices"],
+        vec![int_attr("largest", 0)],
+    )?;
+    assert_eq!(eval["values"].to_vec2::<f32>()?, [[1., 3.], [0., 2.]]);
+    assert_eq!(eval["indices"].to_vec2::<i64>()?, [[0, 2], [3, 1]]);
+
+    let k = Tensor::new(&[1i64], dev)?;
+    let eval = eval_node_helper(
+        "TopK",
+        &[("x", &x), ("k", &k)],
+        &["values", "indices"],
+        vec![int_attr("axis", 0)],
+    )?;
+    assert_eq!(eval["values"].to_vec2::<f32>()?, [[4., 5., 6., 5.]]);
+    assert_eq!(eval["indices"].to_vec2::<i64>()?, [[1, 0, 1, 0]]);
+    Ok(())
+}
+
+#[test]
+fn test_nonzero() -> Result<()> {
+    let x = Tensor::new(&[[1f32, 0.], [1., 1.]], &Device::Cpu)?;
+    let eval = eval_node_helper("NonZero", &[("x", &x)], &["y"], vec![])?;
+    assert_eq!(eval["y"].to_vec2::<i64>()?, [[0, 1, 1], [0, 0, 1]]);
+
+    let x = Tensor::new(&[0u8, 3, 0, 1], &Device::Cpu)?;
+    let eval = eval_node_helper("NonZero", &[("x", &x)], &["y"], vec![])?;
+    assert_eq!(eval["y"].to_vec2::<i64>()?, [[1, 3]]);
+    Ok(())
+}
+
+#[test]
+fn test_scatter_elements() -> Result<()> {
+    let dev = &Device::Cpu;
+    let scatter = |data: &Tensor, indices: &Tensor, updates: &Tensor, attribs| {
+        let eval = eval_node_helper(
+            "ScatterElements",
+            &[("data", data), ("indices", indices), ("updates", updates)],
+            &["y"],
+            attribs,
+        )?;
+        Ok::<_, candle::Error>(eval["y"].clone())
+    };
+    let data = Tensor::zeros((3, 3), DType::F32, dev)?;
+    let indices = Tensor::new(&[[1i64, 0, 2], [0, 2, 1]], dev)?;
+    let updates = Tensor::new(&[[1f32, 1.1, 1.2], [2., 2.1, 2.2]], dev)?;
+    assert_eq!(
+        scatter(&data, &indices, &updates, vec![])?.to_vec2::<f32>()?,
+        [[2., 1.1, 0.], [1., 0., 2.2], [0., 2.1, 1.2]]
+    );
+
+    let data = Tensor::new(&[[1f32, 2., 3., 4., 5.]], dev)?;
+    let updates = Tensor::new(&[[1.1f32, 2.1]], dev)?;
+    let indices = Tensor::new(&[[1i64, -3]], dev)?;
+    assert_eq!(
+        scatter(&data, &indices, &updates, vec![int_attr("axis", 1)])?.to_vec2::<f32>()?,
+        [[1., 1.1, 2.1, 4., 5.]]
+    );
+    let indices = Tensor::new(&[[1i64, 1]], dev)?;
+    let y = scatter(
+        &data,
+        &indices,
+        &updates,
+        vec![int_attr("axis", 1), string_attr("reduction", "add")],
+    )?;
+    assert_eq!(to_vec2_round(&y, 4)?, [[1., 5.2, 3., 4., 5.]]);
+    let y = scatter(
+        &data,
+        &indices,
+        &updates,
+        vec![int_attr("axis", 1), string_attr("reduction", "max")],
+    )?;
+    assert_eq!(y.to_vec2::<f32>()?, [[1., 2.1, 3., 4., 5.]]);
+
+    let data = Tensor::new(&[[1i64, 2, 3]], dev)?;
+    let indices = Tensor::new(&[[2i64]], dev)?;
+    let updates = Tensor::new(&[[4i64]], dev)?;
+    let y = scatter(
+        &data,
+        &indices,
+        &updates,
+        vec![int_attr("axis", 1), string_attr("reduction", "mul")],
+    )?;
+    assert_eq!(y.to_vec2::<i64>()?, [[1, 2, 12]]);
+
+    let indices = Tensor::new(&[[3i64]], dev)?;
+    assert!(scatter(&data, &indices, &updates, vec![int_attr("axis", 1)]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_max_mean_mod_reciprocal() -> Result<()> {
+    let dev = &Device::Cpu;
+    let a = Tensor::new(&[3f32, 2., 1.], dev)?;
+    let b = Tensor::new(&[1f32, 4., 4.], dev)?;
+    let c = Tensor::new(&[2f32, 5., 3.], dev)?;
+    let inputs = [("a", &a), ("b", &b), ("c", &c)];
+    let eval = eval_node_helper("Max", &inputs, &["y"], vec![])?;
+    assert_eq!(eval["y"].to_vec1::<f32>()?, [3., 5., 4.]);
+    let eval = eval_node_helper("Mean", &inputs, &["y"], vec![])?;
+    assert_eq!(to_vec1_round(&eval["y"], 4)?, [2., 3.6667, 2.6667]);
+
+    let a = Tensor::new(&[-4i64, 7, 5, 4, -7, 8], dev)?;
+    let b = Tensor::new(&[2i64, -3, 8, -2, 3, 5], dev)?;
+    let eval = eval_node_helper("Mod", &[("a", &a), ("b", &b)], &["y"], vec![])?;
+    assert_eq!(eval["y"].to_vec1::<i64>()?, [0, -2, 5, 0, 2, 3]);
+    let eval = eval_node_helper(
+        "Mod",
+        &[("a", &a), ("b", &b)],
+        &["y"],
+        vec![int_attr("fmod", 1)],
+    )?;
+    assert_eq!(eval["y"].to_vec1::<i64>()?, [0, 1, 5, 0, -1, 3]);
+
+    let a = Tensor::new(&[-4.3f32, 7.2, 5.0, 4.3, -7.2, 8.0], dev)?;
+    let b = Tensor::new(&[2.1f32, -3.4, 8.0, -2.1, 3.4, 5.0], dev)?;
+    let eval = eval_node_helper(
+        "Mod",
+        &[("a", &a), ("b", &b)],
+        &["y"],
+        vec![int_attr("fmod", 1)],
+    )?;
+    assert_eq!(
+        to_vec1_round(&eval["y"], 4)?,
+        [-0.1, 0.4, 5., 0.1, -0.4, 3.]
+    );
+
+    let x = Tensor::new(&[-4f32, 2.], dev)?;
+    let eval = eval_node_helper("Reciprocal", &[("x", &x)], &["y"], vec![])?;
+    assert_eq!(eval["y"].to_vec1::<f32>()?, [-0.25, 0.5]);
+    Ok(())
+}
+
+#[test]
+fn test_softplus_hard_sigmoid_elu() -> Result<()> {
+    let x = Tensor::new(&[-3f32, -1., 0., 1., 3.], &Device::Cpu)?;
+    let eval = eval_node_helper("Softplus", &[("x", &(&x + 0.5)?)], &["y"], vec![])?;
+    assert_eq!(
+        to_vec1_round(&eval["y"], 4)?,
+        [0.0789, 0.4741, 0.9741, 1.7014, 3.5298]
+    );
+    let eval = eval_node_helper("HardSigmoid", &[("x", &x)], &["y"], vec![])?;
+    assert_eq!(to_vec1_round(&eval["y"], 4)?, [0., 0.3, 0.5, 0.7, 1.]);
+    let eval = eval_node_helper(
+        "HardSigmoid",
+        &[("x", &x)],
+        &["y"],
+        vec![float_attr("alpha", 0.5), float_attr("beta", 0.)],
+    )?;
+    assert_eq!(to_vec1_round(&eval["y"], 4)?, [0., 0., 0., 0.5, 1.]);
+    let eval = eval_node_helper("Elu", &[("x", &x)], &["y"], vec![])?;
+    assert_eq!(
+        to_vec1_round(&eval["y"], 4)?,
+        [-0.9502, -0.6321, 0., 1., 3.]
+    );
+    let eval = eval_node_helper("Elu", &[("x", &x)], &["y"], vec![float_attr("alpha", 2.)])?;
+    assert_eq!(
+        to_vec1_round(&eval["y"], 4)?,
+        [-1.9004, -1.2642, 0., 1., 3.]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_contrib_layer_norm_gelu() -> Result<()> {
+    let dev = &Device::Cpu;
+    let x = Tensor::new(&[[[1f32, -2., 3.], [0.5, 0., -1.]]], dev)?;
+    let skip = Tensor::new(&[[[0f32, 1., 1.], [2., -1., 0.]]], dev)?;
+    let gamma = Tensor::new(&[1f32, 0.5, 2.], dev)?;
+    let beta = Tensor::new(&[0f32, 1., -1.], dev)?;
+    let bias = Tensor::new(&[0.1f32, 0.2, 0.3], dev)?;
+    let eval = eval_node_helper(
+        "SkipLayerNormalization",
+        &[
+            ("x", &x),
+            ("skip", &skip),
+            ("gamma", &gamma),
+            ("beta", &beta),
+            ("bias", &bias),
+        ],
+        &["y", "", "", "sum"],
+        vec![float_attr("epsilon", 1e-5)],
+    )?;
+    let sum = ((&x + &skip)?.broadcast_add(&bias))?;
+    assert!(max_abs_diff(&eval["sum"], &sum)? < 1e-6);
+    let expected = eval_node_helper(
+        "LayerNormalization",
+        &[("x", &sum), ("scale", &gamma), ("bias", &beta)],
+        &["y"],
+        vec![],
+    )?;
+    assert!(max_abs_diff(&eval["y"], &expected["y"])? < 1e-6);
+
+    let eval = eval_node_helper("FastGelu", &[("x", &x), ("bias", &bias)], &["y"], vec![])?;
+    assert!(max_abs_diff(&eval["y"], &x.broadcast_add(&bias)?.gelu()?)? < 1e-6);
+    let eval = eval_node_helper("BiasGelu", &[("x", &x), ("bias", &bias)], &["y"], vec![])?;
+    assert!(max_abs_diff(&eval["y"], &x.broadcast_add(&bias)?.gelu_erf()?)? < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn test_contrib_attention() -> Result<()> {
+    let dev = &Device::Cpu;
+    // The query, key and value projections are all the identity.
+    let x = Tensor::new(&[[[1f32, 0.], [0., 1.]]], dev)?;
+    let eye = Tensor::eye(2, DType::F32, dev)?;
+    let weights = Tensor::cat(&[&eye, &eye, &eye], 1)?;
+    let bias = Tensor::zeros(6, DType::F32, dev)?;
+    let attention = |mask: Option<&Tensor>, attribs: Vec<AttributeProto>| {
+        let mut inputs = vec![("x", &x), ("weights", &weights), ("bias", &bias)];
+        if let Some(mask) = mask {
+            inputs.push(("mask", mask))
+        }
+        let mut attribs = attribs;
+        attribs.push(int_attr("num_heads", 1));
+        let eval = eval_node_helper("Attention", &inputs, &["y"], attribs)?;
+        eval["y"].i(0)
+    };
+    let y = attention(None, vec![])?;
+    assert_eq!(to_vec2_round(&y, 4)?, [[0.6698, 0.3302], [0.3302, 0.6698]]);
+    let y = attention(None, vec![int_attr("unidirectional", 1)])?;
+    assert_eq!(to_vec2_round(&y, 4)?, [[1., 0.], [0.3302, 0.6698]]);
+    // Only the first key is attended to.
+    let lens = Tensor::new(&[1i32], dev)?;
+    let y = attention(Some(&lens), vec![])?;
+    assert_eq!(to_vec2_round(&y, 4)?, [[1., 0.], [1., 0.]]);
+    let mask = Tensor::new(&[[1i32, 0]], dev)?;
+    let y = attention(Some(&mask), vec![])?;
+    assert_eq!(to_vec2_round(&y, 4)?, [[1., 0.], [1., 0.]]);
+
+    // Two heads of size 1 with a custom scale.
+    let eval = eval_node_helper(
+        "Attention",
+        &[("x", &x), ("weights", &weights)],
+        &["y"],
+        vec![int_attr("num_heads", 2), float_attr("scale", 1.)],
+    )?;
+    // Each head only sees its own dimension, the queries that are 0 attend uniformly.
+    let p = 1. / (1. + (-1f32).exp());
+    let expected = [[p, 0.5], [0.5, p]];
+    let y = eval["y"].i(0)?.to_vec2::<f32>()?;
+    for (y, e) in y.iter().flatten().zip(expected.iter().flatten()) {
+        assert!((y - e).abs() < 1e-5, "{y} {e}")
+    }
+    Ok(())
+}
+
+#[test]
+fn test_quantize_dequantize_linear() -> Result<()> {
+    let dev = &Device::Cpu;
+    let x = Tensor::new(&[0f32, 2., 3., 1000., -254., -1000.], dev)?;
+    let scale = Tensor::new(2f32, dev)?;
+    let zp = Tensor::new(128u8, dev)?;
+    let eval = eval_node_helper(
+        "QuantizeLinear",
+        &[("x", &x), ("scale", &scale), ("zp", &zp)],
+        &["y"],
+        vec![],
+    )?;
+    assert_eq!(eval["y"].dtype(), DType::U8);
+    assert_eq!(eval["y"].to_vec1::<u8>()?, [128, 129, 130, 255, 1, 0]);
+
+    let eval = eval_node_helper(
+        "DequantizeLinear",
+        &[("x", &eval["y"]), ("scale", &scale), ("zp", &zp)],
+        &["y"],
+        vec![],
+    )?;
+    assert_eq!(
+        eval["y"].to_vec1::<f32>()?,
+        [0., 2., 4., 254., -254., -256.]
+    );
+
+    // An int8 zero point provided as an initializer.
+    let mut graph = make_graph_helper("QuantizeLinear", &["x", "scale", "zp"], &["y"], vec![]);
+    if let Some(graph) = graph.graph.as_mut() {
+        graph.input.retain(|input| input.name != "zp");
+        graph.initializer.push(TensorProto {
+            name: "zp".to_string(),
+            data_type: DataType::Int8.into(),
+            int32_data: vec![1],
+            ..TensorProto::default()
+        })
+    }
+    let inputs = HashMap::from([
+        ("x".to_string(), x.clone()),
+        ("scale".to_string(), scale.clone()),
+    ]);
+    let eval = simple_eval(&graph, inputs)?;
+    assert_eq!(eval["y"].to_vec1::<i64>()?, [1, 2, 3, 127, -126, -128]);
+
+    // Per-axis quantization along the first dimension.
+    let x = Tensor::new(&[[1f32, 2., 3.], [-2., 4., 5.]], dev)?;
+    let scale = Tensor::new(&[1f32, 2.], dev)?;
+    let zp = Tensor::new(&[0u8, 10], dev)?;
+    let eval = eval_node_helper(
+        "QuantizeLinear",
+        &[("x", &x), ("scale", &scale), ("zp", &zp)],
+        &["y"],
+        vec![int_attr("axis", 0)],
+    )?;
+    assert_eq!(eval["y"].to_vec2::<u8>()?, [[1, 2, 3], [9, 12, 12]]);
+    let eval = eval_node_helper(
+        "DequantizeLinear",
+        &[("x", &eval["y"]), ("scale", &scale), ("zp", &zp)],
+        &["y"],
+        vec![int_attr("axis", 0)],
+    )?;
+    assert_eq!(eval["y"].to_vec2::<f32>()?, [[1., 2., 3.], [-2., 4., 4.]]);
+    Ok(())
+}
+
+#[test]
+fn test_dynamic_quantize_linear() -> Result<()> {
+    let x = Tensor::new(&[0f32, 2., -3., -2.5, 1.34, 0.5], &Device::Cpu)?;
+    let eval = eval_node_helper(
+        "DynamicQuantizeLinear",
+        &[("x", &x)],
+        &["y", "scale", "zp"],
+        vec![],
+    )?;
+    assert_eq!(eval["y"].to_vec1::<u8>()?, [153, 255, 0, 26, 221, 179]);
+    assert!((eval["scale"].to_scalar::<f32>()? - 0.019_607_844).abs() < 1e-7);
+    assert_eq!(eval["zp"].to_scalar::<u8>()?, 153);
+    Ok(())
+}
+
+#[test]
+fn test_matmul_integer() -> Result<()> {
+    let dev = &Device::Cpu;
+    let a = Tensor::new(&[[11u8, 7, 3], [10, 6, 2], [9, 5, 1], [8, 4, 0]], dev)?;
+    let a_zp = Tensor::new(12u8, dev)?;
+    let b = Tensor::new(&[[1u8, 4], [2, 5], [3, 6]], dev)?;
+    let b_zp = Tensor::new(0u8, dev)?;
+    let eval = eval_node_helper(
+        "MatMulInteger",
+        &[("a", &a), ("b", &b), ("a_zp", &a_zp), ("b_zp", &b_zp)],
+        &["y"],
+        vec![],
+    )?;
+    assert_eq!(
+        eval["y"].to_vec2::<i64>()?,
+        [[-38, -83], [-44, -98], [-50, -113], [-56, -128]]
+    );
+
+    let a = Tensor::new(&[[208u8, 236, 0, 238], [3, 214, 255, 29]], dev)?;
+    let b = Tensor::new(
+        &[
+            [152u8, 51, 244],
+            [60, 26, 255],
+            [0, 127, 246],
+            [127, 254, 247],
+        ],
+        dev,
+    )?;
+    let eval = eval_node_helper(
+        "QLinearMatMul",
+        &[
+            ("a", &a),
+            ("a_scale", &Tensor::new(0.0066f32, dev)?),
+            ("a_zp", &Tensor::new(113u8, dev)?),
+            ("b", &b),
+            ("b_scale", &Tensor::new(0.00705f32, dev)?),
+            ("b_zp", &Tensor::new(114u8, dev)?),
+            ("y_scale", &Tensor::new(0.0107f32, dev)?),
+            ("y_zp", &Tensor::new(118u8, dev)?),
+        ],
+        &["y"],
+        vec![],
+    )?;
+    assert_eq!(eval["y"].to_vec2::<u8>()?, [[168, 115, 255], [1, 66, 151]]);
+    Ok(())
+}
+
+#[test]
+fn test_conv_integer() -> Result<()> {
+    let dev = &Device::Cpu;
+    let x = Tensor::arange(2u8, 11, dev)?.reshape((1, 1, 3, 3))?;
+    let w = Tensor::ones((1, 1, 2, 2), DType::U8, dev)?;
+    let x_zp = Tensor::new(1u8, dev)?;
+    let eval = eval_node_helper(
+        "ConvInteger",
+        &[("x", &x), ("w", &w), ("x_zp", &x_zp)],
+        &["y"],
+        vec![],
+    )?;
+    assert_eq!(eval["y"].dtype(), DType::I64);
+    assert_eq!(eval["y"].flatten_all()?.to_vec1::<i64>()?, [12, 16, 24, 28]);
+
+    // The padding uses the zero point of the input.
+    let pads = AttributeProto {
+        name: "pads".to_string(),
+        r#type: AttributeType::Ints.into(),
+        ints: vec![1, 1, 1, 1],
+        ..AttributeProto::default()
+    };
+    let eval = eval_node_helper(
+        "ConvInteger",
+        &[("x", &x), ("w", &w), ("x_zp", &x_zp)],
+        &["y"],
+        vec![pads],
+    )?;
+    assert_eq!(
+        eval["y"].i((0, 0))?.to_vec2::<i64>()?,
+        [
+            [1, 3, 5, 3],
+            [5, 12, 16, 9],
+            [11, 24, 28, 15],
+            [7, 15, 17, 9]
+        ]
+    );
+    Ok(())
+}
+
+fn ints_attr(name: &str, ints: &[i64]) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::Ints.into(),
+        ints: ints.to_vec(),
+        ..AttributeProto::default()
+    }
+}
+
+fn graph_attr(name: &str, g: GraphProto) -> AttributeProto {
+    AttributeProto {
+        name: name.to_string(),
+        r#type: AttributeType::Graph.into(),
+        g: Some(g),
+        ..AttributeProto::default()
+    }
+}
+
+fn node_proto(
+    op_name: &str,
+    inputs: &[&str],
+    outputs: &[&str],
+    attribs: Vec<AttributeProto>,
+) -> NodeProto {
+    NodeProto {
+        op_type: op_name.to_string(),
+        input: inputs.iter().map(|s| s.to_string()).collect(),
+        output: outputs.iter().map(|s| s.to_string()).collect(),
+        attribute: attribs,
+        ..NodeProto::default()
+    }
+}
+
+fn value_infos(names: &[&str]) -> Vec<ValueInfoProto> {
+    names
+        .iter()
+        .map(|name| ValueInfoProto {
+            name: name.to_string(),
+            ..ValueInfoProto::default()
+        })
+        .collect()
+}
+
+#[test]
+fn test_loop() -> Result<()> {
+    let dev = &Device::Cpu;
+    // Adds x, captured from the outer scope, to the carried value and also outputs the
+    // iteration number. The loop stops when the iteration number reaches the limit.
+    let body = GraphProto {
+        node: vec![
+            node_proto("Add", &["sum_in", "x"], &["sum_out"], vec![]),
+            node_proto("Less", &["iter", "limit"], &["cond_out"], vec![]),
+            node_proto("Identity", &["iter"], &["iter_out"], vec![]),
+        ],
+        input: value_infos(&["iter", "cond_in", "sum_in"]),
+        output: value_infos(&["cond_out", "sum_out", "iter_out"]),
+        ..GraphProto::default()
+    };
+    let x = Tensor::new(&[1f32, 2.], dev)?;
+    let sum0 = Tensor::zeros(2, DType::F32, dev)?;
+    let run = |m: Option<i64>, limit: i64| -> Result<HashMap<String, Tensor>> {
+        let mut inputs = HashMap::new();
+        inputs.insert("x".to_string(), x.clone());
+        inputs.insert("sum0".to_string(), sum0.clone());
+        inputs.insert("limit".to_string(), Tensor::new(limit, dev)?);
+        inputs.insert("cond".to_string(), Tensor::new(1u8, dev)?);
+        let m_name = match m {
+            Some(m) => {
+                inputs.insert("m".to_string(), Tensor::new(m, dev)?);
+                "m"
+            }
+            None => "",
+        };
+        let mut graph = make_graph_helper(
+            "Loop",
+            &[m_name, "cond", "sum0"],
+            &["sum", "iters"],
+            vec![graph_attr("body", body.clone())],
+        );
+        if let Some(graph) = graph.graph.as_mut() {
+            graph.input.extend(value_infos(&["x", "limit"]))
+        }
+        simple_eval(&graph, inputs)
+    };
+    // The trip count stops the loop.
+    let eval = run(Some(3), 10)?;
+    assert_eq!(eval["sum"].to_vec1::<f32>()?, [3., 6.]);
+    assert_eq!(eval["iters"].to_vec1::<i64>()?, [0, 1, 2]);
+    // The condition stops the loop, it is checked after each iteration.
+    let eval = run(None, 1)?;
+    assert_eq!(eval["sum"].to_vec1::<f32>()?, [2., 4.]);
+    assert_eq!(eval["iters"].to_vec1::<i64>()?, [0, 1]);
+    Ok(())
+}
+
+#[test]
+fn test_scan() -> Result<()> {
+    let dev = &Device::Cpu;
+    // A cumulative sum over the first axis.
+    let body = GraphProto {
+        node: vec![
+            node_proto("Add", &["sum_in", "next"], &["sum_out"], vec![]),
+            node_proto("Identity", &["sum_out"], &["scan_out"], vec![]),
+        ],
+        input: value_infos(&["sum_in", "next"]),
+        output: value_infos(&["sum_out", "scan_out"]),
+        ..GraphProto::default()
+    };
+    let sum0 = Tensor::zeros(2, DType::F32, dev)?;
+    let xs = Tensor::new(&[[1f32, 2.], [3., 4.], [5., 6.]], dev)?;
+    let run = |attribs: Vec<AttributeProto>| {
+        let mut attribs = attribs;
+        attribs.push(graph_attr("body", body.clone()));
+        attribs.push(int_attr("num_scan_inputs", 1));
+        eval_node_helper(
+            "Scan",
+            &[("sum0", &sum0), ("xs", &xs)],
+            &["sum", "scan"],
+            attribs,
+        )
+    };
+    let eval = run(vec![])?;
+    assert_eq!(eval["sum"].to_vec1::<f32>()?, [9., 12.]);
+    assert_eq!(
+        eval["scan"].to_vec2::<f32>()?,
+        [[1., 2.], [4., 6.], [9., 12.]]
+    );
+
+    let eval = run(vec![ints_attr("scan_input_directions", &[1])])?;
+    assert_eq!(eval["sum"].to_vec1::<f32>()?, [9., 12.]);
+    assert_eq!(
+        eval["scan"].to_vec2::<f32>()?,
+        [[5., 6.], [8., 10.], [9., 12.]]
+    );
+
+    let eval = run(vec![
+        ints_attr("scan_input_directions", &[1]),
+        ints_attr("scan_output_directions", &[1]),
+        ints_attr("scan_output_axes", &[-1]),
+    ])?;
+    assert_eq!(
+        eval["scan"].to_vec2::<f32>()?,
+        [[9., 8., 5.], [12., 10., 6.]]
+    );
+
+    // Scanning over the columns.
+    let eval = eval_node_helper(
+        "Scan",
+        &[("sum0", &Tensor::zeros(3, DType::F32, dev)?), ("xs", &xs)],
+        &["sum", "scan"],
+        vec![
+            graph_attr("body", body.clone()),
+            int_attr("num_scan_inputs", 1),
+            ints_attr("scan_input_axes", &[1]),
+        ],
+    )?;
+    assert_eq!(eval["sum"].to_vec1::<f32>()?, [3., 7., 11.]);
+    assert_eq!(
+        eval["scan"].to_vec2::<f32>()?,
+        [[1., 3., 5.], [3., 7., 11.]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_sequence_ops() -> Result<()> {
+    let dev = &Device::Cpu;
+    let graph = create_model_proto_with_graph(Some(GraphProto {
+        node: vec![
+            node_proto("SequenceConstruct", &["a", "b"], &["s1"], vec![]),
+            node_proto("SequenceInsert", &["s1", "c", "zero"], &["s2"], vec![]),
+            node_proto("SequenceErase", &["s2"], &["s3"], vec![]),
+            node_proto("SequenceLength", &["s2"], &["len"], vec![]),
+            node_proto("SequenceAt", &["s2", "minus_one"], &["last"], vec![]),
+            node_proto(
+                "ConcatFromSequence",
+                &["s3"],
+                &["cat"],
+                vec![int_attr("axis", 0)],
+            ),
+            node_proto(
+                "ConcatFromSequence",
+                &["s2"],
+                &["stacked"],
+                vec![int_attr("axis", -1), int_attr("new_axis", 1)],
+            ),
+            node_proto("SequenceEmpty", &[], &["e"], vec![]),
+            node_proto("SequenceInsert", &["e", "a"], &["e1"], vec![]),
+            node_proto("SequenceLength", &["e1"], &["len_e1"], vec![]),
+        ],
+        input: value_infos(&["a", "b", "c", "zero", "minus_one"]),
+        output: value_infos(&["len", "last", "cat", "stacked", "len_e1"]),
+        ..GraphProto::default()
+    }));
+    let mut inputs = HashMap::new();
+    inputs.insert("a".to_string(), Tensor::new(&[1f32, 2.], dev)?);
+    inputs.insert("b".to_string(), Tensor::new(&[3f32, 4.], dev)?);
+    inputs.insert("c".to_string(), Tensor::new(&[5f32, 6.], dev)?);
+    inputs.insert("zero".to_string(), Tensor::new(0i64, dev)?);
+    inputs.insert("minus_one".to_string(), Tensor::new(-1i64, dev)?);
+    let eval = simple_eval(&graph, inputs)?;
+    // s2 is [c, a, b] and s3 is [c, a].
+    assert_eq!(eval["len"].to_scalar::<i64>()?, 3);
+    assert_eq!(eval["last"].to_vec1::<f32>()?, [3., 4.]);
+    assert_eq!(eval["cat"].to_vec1::<f32>()?, [5., 6., 1., 2.]);
+    assert_eq!(
+        eval["stacked"].to_vec2::<f32>()?,
+        [[5., 1., 3.], [6., 2., 4.]]
+    );
+    assert_eq!(eval["len_e1"].to_scalar::<i64>()?, 1);
+    Ok(())
+}
+
+#[test]
+fn test_gru() -> Result<()> {
+    let dev = &Device::Cpu;
+    // seq_length 3, batch_size 1, input_size 2, hidden_size 1.
+    let x = Tensor::new(&[[[0.5f32, -1.0]], [[1.0, 2.0]], [[-0.5, 0.25]]], dev)?;
+    let w = Tensor::new(&[[[0.1f32, 0.2], [-0.3, 0.4], [0.5, -0.6]]], dev)?;
+    let r = Tensor::new(&[[[0.7f32], [-0.8], [0.9]]], dev)?;
+    let b = Tensor::new(&[[0.01f32, 0.02, 0.03, 0.04, 0.05, 0.06]], dev)?;
+    let run = |attribs: Vec<AttributeProto>, w: &Tensor, r: &Tensor, b: &Tensor| {
+        let mut attribs = attribs;
+        attribs.push(int_attr("hidden_size", 1));
+        eval_node_helper(
+            "GRU",
+            &[("x", &x), ("w", w), ("r", r), ("b", b)],
+            &["y", "y_h"],
+            attribs,
+        )
+    };
+    let eval = run(vec![], &w, &r, &b)?;
+    assert_eq!(eval["y"].dims(), [3, 1, 1, 1]);
+    assert_eq!(
+        to_vec1_round(&eval["y"].flatten_all()?, 4)?,
+        [0.386, 0.1482, -0.0274]
+    );
+    assert_eq!(to_vec1_round(&eval["y_h"].flatten_all()?, 4)?, [-0.0274]);
+
+    let eval = run(vec![int_attr("linear_before_reset", 1)], &w, &r, &b)?;
+    assert_eq!(
+        to_vec1_round(&eval["y"].flatten_all()?, 4)?,
+        [0.3768, 0.1331, -0.0513]
+    );
+
+    let eval = run(vec![string_attr("direction", "reverse")], &w, &r, &b)?;
+    assert_eq!(
+        to_vec1_round(&eval["y"].flatten_all()?, 4)?,
+        [0.2531, -0.3245, -0.1465]
+    );
+    assert_eq!(to_vec1_round(&eval["y_h"].flatten_all()?, 4)?, [0.2531]);
+
+    // Both directions share the same weights here.
+    let w2 = Tensor::cat(&[&w, &w], 0)?;
+    let r2 = Tensor::cat(&[&r, &r], 0)?;
+    let b2 = Tensor::cat(&[&b, &b], 0)?;
+    let eval = run(
+        vec![string_attr("direction", "bidirectional")],
+        &w2,
+        &r2,
+        &b2,
+    )?;
+    assert_eq!(eval["y"].dims(), [3, 2, 1, 1]);
+    assert_eq!(eval["y_h"].dims(), [2, 1, 1]);
+    assert_eq!(
+        to_vec2_round(&eval["y"].flatten_from(1)?, 4)?,
+        [[0.386, 0.2531], [0.1482, -0.3245], [-0.0274, -0.1465]]
+    );
+    assert_eq!(
+        to_vec1_round(&eval["y_h"].flatten_all()?, 4)?,
+        [-0.0274, 0.2531]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_conv_transpose() -> Result<()> {
+    let dev = &Device::Cpu;
+    let x = Tensor::arange(0f32, 3., dev)?.reshape((1, 1, 3))?;
+    let w = Tensor::ones((1, 2, 3), DType::F32, dev)?;
+    let eval = eval_node_helper("ConvTranspose", &[("x", &x), ("w", &w)], &["y"], vec![])?;
+    assert_eq!(
+        eval["y"].i(0)?.to_vec2::<f32>()?,
+        [[0., 1., 3., 3., 2.], [0., 1., 3., 3., 2.]]
+    );
+
+    let x = Tensor::arange(0f32, 9., dev)?.reshape((1, 1, 3, 3))?;
+    let w = Tensor::ones((1, 2, 3, 3), DType::F32, dev)?;
+    let eval = eval_node_helper("ConvTranspose", &[("x", &x), ("w", &w)], &["y"], vec![])?;
+    let expected = [
+        [0., 1., 3., 3., 2.],
+        [3., 8., 15., 12., 7.],
+        [9., 21., 36., 27., 15.],
+        [9., 20., 33., 24., 13.],
+        [6., 13., 21., 15., 8.],
+    ];
+    assert_eq!(eval["y"].dims(), [1, 2, 5, 5]);
+    assert_eq!(eval["y"].i((0, 0))?.to_vec2::<f32>()?, expected);
+    assert_eq!(eval["y"].i((0, 1))?.to_vec2::<f32>()?, expected);
+
+    // The bias is added per output channel.
+    let bias = Tensor::new(&[1f32, -1.], dev)?;
+    let eval = eval_node_helper(
+        "ConvTranspose",
+        &[("x", &x), ("w", &w), ("b", &bias)],
+        &["y"],
+        vec![],
+    )?;
+    assert_eq!(
+        eval["y"].i((0, 1, 0))?.to_vec1::<f32>()?,
+        [-1., 0., 2., 2., 1.]
+    );
+    assert_eq!(
+        eval["y"].i((0, 0, 0))?.to_vec1::<f32>()?,
+        [1., 2., 4., 4., 3.]
+    );
+
+    // The padding crops the output, the output padding extends it.
+    let strided = |attribs: Vec<AttributeProto>| -> Result<Tensor> {
+        let mut attribs = attribs;
+        attribs.push(ints_attr("strides", &[2, 2]));
+        let eval = eval_node_helper("ConvTranspose", &[("x", &x), ("w", &w)], &["y"], attribs)?;
+        Ok(eval["y"].clone())
+    };
+    let full = strided(vec![])?;
+    assert_eq!(full.dims(), [1, 2, 7, 7]);
+    let ys = strided(vec![ints_attr("pads", &[1, 1, 1, 1])])?;
+    assert_eq!(ys.dims(), [1, 2, 5, 5]);
+    assert_eq!(max_abs_diff(&ys, &full.i((.., .., 1..6, 1..6))?)?, 0.);
+    let ys = strided(vec![ints_attr("output_padding", &[1, 1])])?;
+    assert_eq!(ys.dims(), [1, 2, 8, 8]);
+    assert_eq!(max_abs_diff(&ys.i((.., .., ..7, ..7))?, &full)?, 0.);
+    assert_eq!(ys.i((.., .., 7))?.abs()?.sum_all()?.to_scalar::<f32>()?, 0.);
+    let ys = strided(vec![string_attr("auto_pad", "SAME_UPPER")])?;
+    assert_eq!(ys.dims(), [1, 2, 6, 6]);
+    assert_eq!(max_abs_diff(&ys, &full.i((.., .., ..6, ..6))?)?, 0.);
+    let ys = strided(vec![ints_attr("output_shape", &[6, 6])])?;
+    assert_eq!(max_abs_diff(&ys, &full.i((.., .., 1..7, 1..7))?)?, 0.);
+
+    // Grouped transposed convolutions.
+    let x = Tensor::cat(&[&x, &(&x * 2.)?], 1)?;
+    let w = Tensor::ones((2, 1, 3, 3), DType::F32, dev)?;
+    let eval = eval_node_helper(
+        "ConvTranspose",
+        &[("x", &x), ("w", &w)],
+        &["y"],
+        vec![int_attr("group", 2)],
+    )?;
+    assert_eq!(eval["y"].i((0, 0))?.to_vec2::<f32>()?, expected);
+    assert_eq!(
+        eval["y"].i((0, 1))?.to_vec2::<f32>()?,
+        (Tensor::new(&expected, dev)? * 2.)?.to_vec2::<f32>()?
+    );
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use candle::{Device, Result, Tensor};
+use candle_onnx::onnx::{
+    type_proto, GraphProto, ModelProto, NodeProto, TensorProto, TypeProto, ValueInfoProto,
+};
+use candle_onnx::OnnxSession;
+use std::collections::HashMap;
+
+fn node(op_type: &str, input: &[&str], output: &str) -> NodeProto {
+    NodeProto {
+        op_type: op_type.to_string(),
+        input: input.iter().map(|v| v.to_string()).collect(),
+        output: vec![output.to_string()],
+        name: output.to_string(),
+        ..Default::default()
+    }
+}
+
+fn initializer(name: &str, dims: &[i64], data: &[f32]) -> TensorProto {
+    TensorProto {
+        name: name.to_string(),
+        dims: dims.to_vec(),
+        data_type: 1,
+        float_data: data.to_vec(),
+        ..Default::default()
+    }
+}
+
+fn value_info(name: &str) -> ValueInfoProto {
+    ValueInfoProto {
+        name: name.to_string(),
+        ..Default::default()
+    }
+}
+
+// relu(x . w + (b1 + b2)), the nodes are listed in a non topological order.
+fn model() -> ModelProto {
+    let graph = GraphProto {
+        node: vec![
+            node("Relu", &["y"], "z"),
+            node("Add", &["xw", "b"], "y"),
+            node("MatMul", &["x", "w"], "xw"),
+            node("Add", &["b1", "b2"], "b"),
+        ],
+        initializer: vec![
+            initializer("w", &[3, 2], &[1., -1., 2., 0.5, -3., 1.]),
+            initializer("b1", &[2], &[0.5, -0.5]),
+            initializer("b2", &[2], &[1., 0.]),
+        ],
+        input: vec![ValueInfoProto {
+            r#type: Some(TypeProto {
+                value: Some(type_proto::Value::TensorType(type_proto::Tensor {
+                    elem_type: 1,
+                    shape: None,
+                })),
+                ..Default::default()
+            }),
+            ..value_info("x")
+        }],
+        output: vec![value_info("z"), value_info("b")],
+        ..Default::default()
+    };
+    ModelProto {
+        graph: Some(graph),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn session_run() -> Result<()> {
+    let dev = Device::Cpu;
+    let model = model();
+    let session = OnnxSession::new(&model, &dev)?;
+    // The bias addition only depends on initializers and is folded.
+    assert_eq!(session.num_nodes(), 3);
+    assert_eq!(session.inputs().len(), 1);
+    assert_eq!(session.outputs(), ["z", "b"]);
+
+    // simple_eval requires the nodes to be topologically sorted.
+    let mut sorted = model.clone();
+    if let Some(graph) = sorted.graph.as_mut() {
+        graph.node.reverse()
+    }
+
+    for x in [[[1f32, 2., 3.]], [[-1., 0.5, 2.]]] {
+        let x = Tensor::new(&x, &dev)?;
+        let inputs = HashMap::from([("x".to_string(), x.clone())]);
+        let expected = candle_onnx::simple_eval(&sorted, inputs.clone())?;
+        let outputs = session.run(inputs)?;
+        assert_eq!(outputs.len(), 2);
+        for name in ["z", "b"] {
+            let diff = (&outputs[name] - &expected[name])?
+                .abs()?
+                .flatten_all()?
+                .max(0)?
+                .to_scalar::<f32>()?;
+            assert_eq!(diff, 0., "{name}");
+        }
+    }
+    let z = session.run(HashMap::from([(
+        "x".to_string(),
+        Tensor::new(&[[1f32, 2., 3.]], &dev)?,
+    )]))?;
+    assert_eq!(z["z"].to_vec2::<f32>()?, [[0., 2.5]]);
+
+    let err = session.run(HashMap::new()).unwrap_err();
+    assert!(err.to_string().contains("missing input x"), "{err}");
+    Ok(())
+}
+
+#[test]
+fn session_errors() -> Result<()> {
+    let mut model = model();
+    if let Some(graph) = model.graph.as_mut() {
+        graph.node.push(node("Neg", &["unknown"], "n"))
+    }
+    let err = OnnxSession::new(&model, &Device::Cpu).unwrap_err();
+    assert!(err.to_string().contains("cannot find unknown"), "{err}");
+
+    let mut model = self::model();
+    if let Some(graph) = model.graph.as_mut() {
+        graph.node.push(node("Neg", &["m"], "n"));
+        graph.node.push(node("Neg", &["n"], "m"));
+    }
+    let err = OnnxSession::new(&model, &Device::Cpu).unwrap_err();
+    assert!(err.to_string().contains("cycle"), "{err}");
+    Ok(())
+}
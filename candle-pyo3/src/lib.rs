@@ -1,6 +1,6 @@
 #![allow(clippy::redundant_closure_call)]
 #![allow(clippy::useless_conversion)]
-use float8::F8E4M3;
+use float8::{F8E4M3, F8E5M2};
 use half::{bf16, f16};
 use pyo3::exceptions::{PyTypeError, PyValueError};
 use pyo3::prelude::*;
@@ -166,6 +166,7 @@ pydtype!(bf16, f32::from);
 pydtype!(f32, |v| v);
 pydtype!(f64, |v| v);
 pydtype!(F8E4M3, f32::from);
+pydtype!(F8E5M2, f32::from);
 
 fn actual_index(t: &Tensor, dim: usize, index: i64) -> ::candle::Result<usize> {
     let dim = t.dim(dim)?;
@@ -222,6 +223,9 @@ trait MapDType {
             DType::F8E4M3 => Err(PyErr::new::<PyTypeError, _>(
                 "f8e4m3 dtype is not supported in Python interface",
             )),
+            DType::F8E5M2 => Err(PyErr::new::<PyTypeError, _>(
+                "f8e5m2 dtype is not supported in Python interface",
+            )),
             DType::F6E2M3 | DType::F6E3M2 | DType::F4 | DType::F8E8M0 => {
                 Err(PyErr::new::<PyTypeError, _>(format!(
                     "Dummy dtype {:?} is not supported",
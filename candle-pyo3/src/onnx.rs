@@ -2,11 +2,11 @@ use std::collections::HashMap;
 
 use crate::utils::wrap_err;
 use crate::{PyDType, PyTensor};
-use candle_onnx::eval::{dtype, get_tensor, simple_eval};
+use candle_onnx::eval::dtype;
 use candle_onnx::onnx::tensor_proto::DataType;
 use candle_onnx::onnx::tensor_shape_proto::dimension::Value;
 use candle_onnx::onnx::type_proto::{Tensor as ONNXTensor, Value as ONNXValue};
-use candle_onnx::onnx::{ModelProto, ValueInfoProto};
+use candle_onnx::onnx::ValueInfoProto;
 use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 use pyo3::types::{PyList, PyTuple};
@@ -82,7 +82,7 @@ impl PyONNXTensorDescriptor {
 #[derive(Clone, Debug)]
 #[pyclass(name = "ONNXModel")]
 /// A wrapper around an ONNX model.
-pub struct PyONNXModel(ModelProto);
+pub struct PyONNXModel(candle_onnx::Model);
 
 fn extract_tensor_descriptions(
     value_infos: &[ValueInfoProto],
@@ -116,7 +116,7 @@ impl PyONNXModel {
     #[pyo3(text_signature = "(self, path:str)")]
     /// Load an ONNX model from the given path.
     fn new(path: String) -> PyResult<Self> {
-        let model: ModelProto = candle_onnx::read_file(path).map_err(wrap_err)?;
+        let model = candle_onnx::Model::read(path).map_err(wrap_err)?;
         Ok(PyONNXModel(model))
     }
 
@@ -124,51 +124,53 @@ impl PyONNXModel {
     /// The version of the IR this model targets.
     /// &RETURNS&: int
     fn ir_version(&self) -> i64 {
-        self.0.ir_version
+        self.0.proto.ir_version
     }
 
     #[getter]
     /// The producer of the model.
     /// &RETURNS&: str
     fn producer_name(&self) -> String {
-        self.0.producer_name.clone()
+        self.0.proto.producer_name.clone()
     }
 
     #[getter]
     /// The version of the producer of the model.
     /// &RETURNS&: str
     fn producer_version(&self) -> String {
-        self.0.producer_version.clone()
+        self.0.proto.producer_version.clone()
     }
 
     #[getter]
     /// The domain of the operator set of the model.
     /// &RETURNS&: str
     fn domain(&self) -> String {
-        self.0.domain.clone()
+        self.0.proto.domain.clone()
     }
 
     #[getter]
     /// The version of the model.
     /// &RETURNS&: int
     fn model_version(&self) -> i64 {
-        self.0.model_version
+        self.0.proto.model_version
     }
 
     #[getter]
     /// The doc string of the model.
     /// &RETURNS&: str
     fn doc_string(&self) -> String {
-        self.0.doc_string.clone()
+        self.0.proto.doc_string.clone()
     }
 
     /// Get the weights of the model.
     /// &RETURNS&: Dict[str, Tensor]
     fn initializers(&self) -> PyResult<HashMap<String, PyTensor>> {
         let mut map = HashMap::new();
-        if let Some(graph) = self.0.graph.as_ref() {
+        if let Some(graph) = self.0.proto.graph.as_ref() {
             for tensor_description in graph.initializer.iter() {
-                let tensor = get_tensor(tensor_description, tensor_description.name.as_str())
+                let tensor = self
+                    .0
+                    .get_tensor(tensor_description, tensor_description.name.as_str())
                     .map_err(wrap_err)?;
                 map.insert(tensor_description.name.to_string(), PyTensor(tensor));
             }
@@ -180,7 +182,7 @@ impl PyONNXModel {
     /// The inputs of the model.
     /// &RETURNS&: Optional[Dict[str, ONNXTensorDescription]]
     fn inputs(&self) -> Option<HashMap<String, PyONNXTensorDescriptor>> {
-        if let Some(graph) = self.0.graph.as_ref() {
+        if let Some(graph) = self.0.proto.graph.as_ref() {
             return Some(extract_tensor_descriptions(&graph.input));
         }
         None
@@ -190,7 +192,7 @@ impl PyONNXModel {
     /// The outputs of the model.
     /// &RETURNS&: Optional[Dict[str, ONNXTensorDescription]]
     fn outputs(&self) -> Option<HashMap<String, PyONNXTensorDescriptor>> {
-        if let Some(graph) = self.0.graph.as_ref() {
+        if let Some(graph) = self.0.proto.graph.as_ref() {
             return Some(extract_tensor_descriptions(&graph.output));
         }
         None
@@ -202,7 +204,7 @@ impl PyONNXModel {
     fn run(&self, inputs: HashMap<String, PyTensor>) -> PyResult<HashMap<String, PyTensor>> {
         let unwrapped_tensors = inputs.into_iter().map(|(k, v)| (k.clone(), v.0)).collect();
 
-        let result = simple_eval(&self.0, unwrapped_tensors).map_err(wrap_err)?;
+        let result = self.0.simple_eval(unwrapped_tensors).map_err(wrap_err)?;
 
         Ok(result
             .into_iter()
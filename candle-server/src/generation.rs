@@ -0,0 +1,292 @@
+//! The token generation loop, with text streaming and stop sequences.
+use crate::model::Model;
+use anyhow::Result;
+use candle::{Device, Tensor};
+use candle_transformers::generation::engine::{self, EngineHandle, GenerationEvent};
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use tokenizers::Tokenizer;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationParams {
+    pub max_tokens: usize,
+    pub sampling: Sampling,
+    pub seed: u64,
+    /// Generation stops as soon as one of these strings appears in the output, the stop string
+    /// itself is not returned.
+    pub stop: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    Stop,
+    Length,
+}
+
+impl FinishReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Stop => "stop",
+            Self::Length => "length",
+        }
+    }
+}
+
+impl From<engine::FinishReason> for FinishReason {
+    fn from(reason: engine::FinishReason) -> Self {
+        match reason {
+            engine::FinishReason::Stop => Self::Stop,
+            engine::FinishReason::Length => Self::Length,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+}
+
+// Returns the length of the longest suffix of `text` that is a strict prefix of a stop sequence,
+// this text cannot be streamed yet as it may end up being part of a stop sequence.
+fn partial_stop_len(text: &str, stop: &[String]) -> usize {
+    stop.iter()
+        .flat_map(|s| {
+            s.char_indices()
+                .skip(1)
+                .map(|(i, _)| &s[..i])
+                .filter(|prefix| text.ends_with(prefix))
+                .map(|prefix| prefix.len())
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// Some new text, possibly empty.
+    Text(String),
+    /// A stop sequence has been generated, this holds the new text preceding it.
+    Stop(String),
+}
+
+/// Incrementally decodes the generated tokens and checks for the stop sequences.
+///
+/// Only the tokens since the previously returned text are decoded on each step. The new text is
+/// returned once the decoding of these tokens extends the previous one with complete utf-8
+/// characters, so tokenizers whose decoding is not prefix stable cannot garble the stream. The
+/// text that may be the start of a stop sequence is held back.
+pub struct TextStream<'a> {
+    tokenizer: &'a Tokenizer,
+    stop: &'a [String],
+    tokens: Vec<u32>,
+    // The text for `tokens[..current_index]` has been decoded, the tokens from `prev_index` are
+    // decoded again on the next step as the text of a token can depend on the previous ones.
+    prev_index: usize,
+    current_index: usize,
+    // The decoded text that has not been returned yet.
+    pending: String,
+}
+
+impl<'a> TextStream<'a> {
+    pub fn new(tokenizer: &'a Tokenizer, stop: &'a [String]) -> Self {
+        Self {
+            tokenizer,
+            stop,
+            tokens: vec![],
+            prev_index: 0,
+            current_index: 0,
+            pending: String::new(),
+        }
+    }
+
+    /// The number of tokens pushed to the stream.
+    pub fn num_tokens(&self) -> usize {
+        self.tokens.len()
+    }
+
+    fn decode(&self, tokens: &[u32]) -> Result<String> {
+        self.tokenizer
+            .decode(tokens, true)
+            .map_err(anyhow::Error::msg)
+    }
+
+    // Returns the previously decoded text and the text for all the tokens from `prev_index`.
+    fn decode_window(&self) -> Result<(String, String)> {
+        let prev_text = self.decode(&self.tokens[self.prev_index..self.current_index])?;
+        let text = self.decode(&self.tokens[self.prev_index..])?;
+        Ok((prev_text, text))
+    }
+
+    pub fn push(&mut self, token: u32) -> Result<StreamEvent> {
+        self.tokens.push(token);
+        let (prev_text, text) = self.decode_window()?;
+        match text.strip_prefix(prev_text.as_str()) {
+            Some(new) if !new.is_empty() && !new.ends_with('\u{FFFD}') => {
+                self.pending.push_str(new);
+                self.prev_index = self.current_index;
+                self.current_index = self.tokens.len();
+            }
+            _ => return Ok(StreamEvent::Text(String::new())),
+        }
+        Ok(self.take_pending(true))
+    }
+
+    /// Returns the text held back once generation is over.
+    pub fn finish(mut self) -> Result<StreamEvent> {
+        let (prev_text, text) = self.decode_window()?;
+        // When the decoding is not prefix stable, the text that has already been returned
+        // cannot be changed anymore and only the part after the common prefix is added.
+        let common = prev_text
+            .chars()
+            .zip(text.chars())
+            .take_while(|(p, c)| p == c)
+            .map(|(p, _)| p.len_utf8())
+            .sum::<usize>();
+        self.pending.push_str(&text[common..]);
+        Ok(self.take_pending(false))
+    }
+
+    fn take_pending(&mut self, hold_back: bool) -> StreamEvent {
+        let stop_pos = self
+            .stop
+            .iter()
+            .filter_map(|s| self.pending.find(s.as_str()))
+            .min();
+        if let Some(pos) = stop_pos {
+            self.pending.truncate(pos);
+            return StreamEvent::Stop(std::mem::take(&mut self.pending));
+        }
+        let end = if hold_back {
+            self.pending.len() - partial_stop_len(&self.pending, self.stop)
+        } else {
+            self.pending.len()
+        };
+        let held = self.pending.split_off(end);
+        StreamEvent::Text(std::mem::replace(&mut self.pending, held))
+    }
+}
+
+// Streams the text for the tokens returned by `next_event` until generation finishes, a stop
+// sequence matches or `on_text` returns false. Returns the finish reason and the number of
+// generated tokens.
+fn stream_events(
+    tokenizer: &Tokenizer,
+    stop: &[String],
+    mut next_event: impl FnMut() -> Result<GenerationEvent>,
+    mut on_text: impl FnMut(&str) -> bool,
+) -> Result<(FinishReason, usize)> {
+    let mut stream = TextStream::new(tokenizer, stop);
+    let finish_reason = loop {
+        let token = match next_event()? {
+            GenerationEvent::Token(token) => token,
+            GenerationEvent::Finished(reason) => break FinishReason::from(reason),
+        };
+        match stream.push(token)? {
+            StreamEvent::Stop(text) => {
+                if !text.is_empty() {
+                    on_text(&text);
+                }
+                return Ok((FinishReason::Stop, stream.num_tokens()));
+            }
+            // The remaining text is not sent when the client has disconnected.
+            StreamEvent::Text(text) => {
+                if !text.is_empty() && !on_text(&text) {
+                    return Ok((FinishReason::Length, stream.num_tokens()));
+                }
+            }
+        }
+    };
+    let num_tokens = stream.num_tokens();
+    // The text held back on the last step is not part of a stop sequence anymore, unless the
+    // last tokens could only be decoded together.
+    let (finish_reason, text) = match stream.finish()? {
+        StreamEvent::Stop(text) => (FinishReason::Stop, text),
+        StreamEvent::Text(text) => (finish_reason, text),
+    };
+    if !text.is_empty() {
+        on_text(&text);
+    }
+    Ok((finish_reason, num_tokens))
+}
+
+/// Generates a completion for `prompt_tokens`, `on_text` is called with each new chunk of text.
+/// Generation is interrupted if `on_text` returns false, e.g. when the client has disconnected.
+pub fn generate(
+    model: &mut Model,
+    tokenizer: &Tokenizer,
+    prompt_tokens: &[u32],
+    eos_tokens: &[u32],
+    params: &GenerationParams,
+    device: &Device,
+    on_text: impl FnMut(&str) -> bool,
+) -> Result<(FinishReason, Usage)> {
+    if prompt_tokens.is_empty() {
+        anyhow::bail!("empty prompt")
+    }
+    let mut logits_processor = LogitsProcessor::from_sampling(params.seed, params.sampling.clone());
+    model.clear_kv_cache();
+    let mut tokens = prompt_tokens.to_vec();
+    let next_event = || {
+        let index = tokens.len() - prompt_tokens.len();
+        if index >= params.max_tokens {
+            return Ok(GenerationEvent::Finished(engine::FinishReason::Length));
+        }
+        let context = if index == 0 {
+            &tokens[..]
+        } else {
+            &tokens[tokens.len() - 1..]
+        };
+        let offset = tokens.len() - context.len();
+        let input = Tensor::new(context, device)?.unsqueeze(0)?;
+        let logits = model.forward(&input, offset)?;
+        let next_token = logits_processor.sample(&logits)?;
+        tokens.push(next_token);
+        if eos_tokens.contains(&next_token) {
+            return Ok(GenerationEvent::Finished(engine::FinishReason::Stop));
+        }
+        Ok(GenerationEvent::Token(next_token))
+    };
+    let result = stream_events(tokenizer, &params.stop, next_event, on_text);
+    model.clear_kv_cache();
+    let (finish_reason, completion_tokens) = result?;
+    let usage = Usage {
+        prompt_tokens: prompt_tokens.len(),
+        completion_tokens,
+    };
+    Ok((finish_reason, usage))
+}
+
+/// Same as [`generate`] but the tokens are sampled by a continuous batching engine, so that the
+/// requests submitted concurrently are decoded together.
+pub fn generate_batched(
+    engine: &EngineHandle,
+    tokenizer: &Tokenizer,
+    prompt_tokens: &[u32],
+    eos_tokens: &[u32],
+    params: &GenerationParams,
+    on_text: impl FnMut(&str) -> bool,
+) -> Result<(FinishReason, Usage)> {
+    if prompt_tokens.is_empty() {
+        anyhow::bail!("empty prompt")
+    }
+    let engine_params = engine::GenerationParams {
+        sampling: params.sampling.clone(),
+        seed: params.seed,
+        max_new_tokens: params.max_tokens,
+        stop_tokens: eos_tokens.to_vec(),
+        ..Default::default()
+    };
+    // Dropping the receiver on return cancels the request in the engine.
+    let events = engine.submit(prompt_tokens.to_vec(), engine_params)?;
+    let next_event = || match events.recv() {
+        Ok(event) => Ok(event?),
+        Err(_) => anyhow::bail!("the generation engine has stopped"),
+    };
+    let (finish_reason, completion_tokens) =
+        stream_events(tokenizer, &params.stop, next_event, on_text)?;
+    let usage = Usage {
+        prompt_tokens: prompt_tokens.len(),
+        completion_tokens,
+    };
+    Ok((finish_reason, usage))
+}
@@ -0,0 +1,5 @@
+//! An OpenAI compatible http server for the models from candle-transformers.
+pub mod generation;
+pub mod model;
+pub mod openai;
+pub mod server;
@@ -0,0 +1,224 @@
+#[cfg(feature = "mkl")]
+extern crate intel_mkl_src;
+
+#[cfg(feature = "accelerate")]
+extern crate accelerate_src;
+
+use anyhow::{Context, Result};
+use candle::quantized::gguf_file;
+use candle::utils::{cuda_is_available, metal_is_available};
+use candle::{DType, Device};
+use candle_server::model::{Model, ModelFiles};
+use candle_server::server::{router, AppState};
+use candle_transformers::chat_template::ChatTemplate;
+use candle_transformers::generation::engine::EngineConfig;
+use candle_transformers::gguf_tokenizer::GgufTokenizer;
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// A .gguf file or a directory with a config.json and the safetensors weights.
+    #[arg(long)]
+    model: PathBuf,
+
+    /// The tokenizer.json file, defaults to the one in the model directory. For gguf models the
+    /// tokenizer embedded in the file is used when there is no tokenizer.json.
+    #[arg(long)]
+    tokenizer: Option<PathBuf>,
+
+    /// The tokenizer_config.json file holding the chat template, defaults to the one next to
+    /// the tokenizer.
+    #[arg(long)]
+    tokenizer_config: Option<PathBuf>,
+
+    /// The name under which the model is served.
+    #[arg(long)]
+    model_id: Option<String>,
+
+    /// The dtype used for safetensors weights, e.g. f32, f16 or bf16.
+    #[arg(long)]
+    dtype: Option<String>,
+
+    /// Run on CPU rather than on GPU.
+    #[arg(long)]
+    cpu: bool,
+
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+
+    /// The number of tokens to generate when a request does not specify `max_tokens`.
+    #[arg(long, default_value_t = 256)]
+    max_tokens: usize,
+
+    /// The number of requests decoded together by the continuous batching engine, for the
+    /// architectures that support it. With 0, the requests are processed one at a time.
+    #[arg(long, default_value_t = 8)]
+    max_batch_size: usize,
+
+    /// The maximum number of tokens per request, prompt included, when batching.
+    #[arg(long, default_value_t = 4096)]
+    context: usize,
+}
+
+fn device(cpu: bool) -> Result<Device> {
+    let device = if cpu {
+        Device::Cpu
+    } else if cuda_is_available() {
+        Device::new_cuda(0)?
+    } else if metal_is_available() {
+        Device::new_metal(0)?
+    } else {
+        Device::Cpu
+    };
+    Ok(device)
+}
+
+// Collects the end of sequence tokens from the tokenizer config, the generation config and the
+// gguf metadata, models often use more than one of these, e.g. for the end of a chat turn.
+fn eos_tokens(
+    tokenizer: &Tokenizer,
+    template: Option<&ChatTemplate>,
+    files: &ModelFiles,
+    model_dir: &Path,
+) -> Result<Vec<u32>> {
+    let mut eos_tokens = vec![];
+    if let Some(id) = template.and_then(|t| tokenizer.token_to_id(t.eos_token())) {
+        eos_tokens.push(id)
+    }
+    let generation_config = model_dir.join("generation_config.json");
+    if generation_config.exists() {
+        let config = std::fs::read_to_string(&generation_config)?;
+        let config: serde_json::Value = serde_json::from_str(&config)?;
+        match &config["eos_token_id"] {
+            serde_json::Value::Number(id) => eos_tokens.extend(id.as_u64().map(|id| id as u32)),
+            serde_json::Value::Array(ids) => {
+                eos_tokens.extend(ids.iter().filter_map(|id| id.as_u64().map(|id| id as u32)))
+            }
+            _ => {}
+        }
+    }
+    if let ModelFiles::Gguf(path) = files {
+        let mut file = std::fs::File::open(path)?;
+        let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(path))?;
+        if let Some(id) = content.metadata.get("tokenizer.ggml.eos_token_id") {
+            eos_tokens.push(id.to_u32()?)
+        }
+    }
+    if eos_tokens.is_empty() {
+        let fallbacks = [
+            "</s>",
+            "<|endoftext|>",
+            "<|im_end|>",
+            "<|eot_id|>",
+            "<|end|>",
+        ];
+        eos_tokens.extend(fallbacks.iter().find_map(|t| tokenizer.token_to_id(t)))
+    }
+    eos_tokens.sort();
+    eos_tokens.dedup();
+    Ok(eos_tokens)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+    let device = device(args.cpu)?;
+    let dtype = match args.dtype.as_deref() {
+        Some("f16") => DType::F16,
+        Some("bf16") => DType::BF16,
+        Some("f32") => DType::F32,
+        Some(dtype) => anyhow::bail!("unsupported dtype {dtype}"),
+        None if device.is_cuda() || device.is_metal() => DType::BF16,
+        None => DType::F32,
+    };
+    let files = ModelFiles::from_path(&args.model)?;
+    let model_dir = match &files {
+        ModelFiles::Gguf(path) => path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        ModelFiles::Safetensors { .. } => args.model.clone(),
+    };
+    let tokenizer_file = args
+        .tokenizer
+        .unwrap_or_else(|| model_dir.join("tokenizer.json"));
+    let tokenizer = match &files {
+        // Gguf files embed their tokenizer, use it when no tokenizer.json is available.
+        ModelFiles::Gguf(path) if !tokenizer_file.exists() => {
+            let mut file = std::fs::File::open(path)?;
+            let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(path))?;
+            GgufTokenizer::from_gguf(&content)?.into_tokenizer()
+        }
+        _ => Tokenizer::from_file(&tokenizer_file)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("cannot load the tokenizer from {tokenizer_file:?}"))?,
+    };
+    let tokenizer_config = args.tokenizer_config.unwrap_or_else(|| {
+        tokenizer_file
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join("tokenizer_config.json")
+    });
+    let chat_template = if tokenizer_config.exists() {
+        ChatTemplate::from_tokenizer_config(&tokenizer_config)
+    } else if let ModelFiles::Gguf(path) = &files {
+        let mut file = std::fs::File::open(path)?;
+        let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(path))?;
+        ChatTemplate::from_gguf(&content)
+    } else {
+        Err(candle::Error::Msg(format!(
+            "{tokenizer_config:?} not found"
+        )))
+    };
+    let chat_template = match chat_template {
+        Ok(template) => Some(template),
+        Err(e) => {
+            tracing::warn!("no chat template, /v1/chat/completions is disabled: {e}");
+            None
+        }
+    };
+    let eos_tokens = eos_tokens(&tokenizer, chat_template.as_ref(), &files, &model_dir)?;
+    let model_id = args.model_id.unwrap_or_else(|| {
+        args.model
+            .file_stem()
+            .map_or("model".to_string(), |s| s.to_string_lossy().to_string())
+    });
+
+    let start = std::time::Instant::now();
+    let model = Model::load(&files, dtype, &device)?;
+    tracing::info!("loaded {model_id} in {:.2}s", start.elapsed().as_secs_f32());
+
+    let engine = if args.max_batch_size > 0 {
+        let config = EngineConfig {
+            max_batch_size: args.max_batch_size,
+            context: args.context,
+        };
+        model.spawn_engine(config, dtype, &device)?
+    } else {
+        None
+    };
+    if engine.is_some() {
+        tracing::info!("batching up to {} requests", args.max_batch_size)
+    }
+
+    let mut state = AppState::new(
+        model,
+        tokenizer,
+        chat_template,
+        eos_tokens,
+        model_id,
+        device,
+    );
+    state.max_tokens = args.max_tokens;
+    state.engine = engine;
+    let app = router(Arc::new(state));
+    let listener = tokio::net::TcpListener::bind((args.host.as_str(), args.port)).await?;
+    tracing::info!("listening on {}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,222 @@
+//! Loading and running the supported model architectures.
+use anyhow::{bail, Context, Result};
+use candle::quantized::gguf_file;
+use candle::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::generation::engine::{Engine, EngineConfig, EngineHandle};
+use candle_transformers::models::{
+    llama, mistral, phi3, quantized_llama, quantized_phi3, quantized_qwen2, quantized_qwen3, qwen2,
+    qwen3,
+};
+use std::path::{Path, PathBuf};
+
+pub enum Model {
+    Llama {
+        model: llama::Llama,
+        cache: llama::Cache,
+        // A cache with no kv entries, cloned when starting a new sequence.
+        empty_cache: llama::Cache,
+    },
+    Mistral(mistral::Model),
+    Phi3(phi3::Model),
+    Qwen2(qwen2::ModelForCausalLM),
+    Qwen3(qwen3::ModelForCausalLM),
+    QuantizedLlama(quantized_llama::ModelWeights),
+    QuantizedPhi3(quantized_phi3::ModelWeights),
+    QuantizedQwen2(quantized_qwen2::ModelWeights),
+    QuantizedQwen3(quantized_qwen3::ModelWeights),
+}
+
+/// The files that make up a model, either a directory with a `config.json` and safetensors
+/// weights or a single gguf file.
+#[derive(Debug, Clone)]
+pub enum ModelFiles {
+    Safetensors {
+        config: PathBuf,
+        weights: Vec<PathBuf>,
+    },
+    Gguf(PathBuf),
+}
+
+impl ModelFiles {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        if path.is_file() {
+            if path.extension().is_some_and(|e| e == "gguf") {
+                return Ok(Self::Gguf(path.to_path_buf()));
+            }
+            bail!("unsupported model file {path:?}, expected a .gguf file or a directory")
+        }
+        let mut weights = std::fs::read_dir(path)
+            .with_context(|| format!("cannot read model directory {path:?}"))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "safetensors"))
+            .collect::<Vec<_>>();
+        if weights.is_empty() {
+            bail!("no safetensors files in {path:?}")
+        }
+        weights.sort();
+        Ok(Self::Safetensors {
+            config: path.join("config.json"),
+            weights,
+        })
+    }
+}
+
+impl Model {
+    pub fn load(files: &ModelFiles, dtype: DType, device: &Device) -> Result<Self> {
+        match files {
+            ModelFiles::Gguf(path) => {
+                let mut file = std::fs::File::open(path)?;
+                let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(path))?;
+                Self::from_gguf(content, &mut file, device)
+            }
+            ModelFiles::Safetensors { config, weights } => {
+                let config = std::fs::read_to_string(config)
+                    .with_context(|| format!("cannot read {config:?}"))?;
+                let vb = unsafe { VarBuilder::from_mmaped_safetensors(weights, dtype, device)? };
+                Self::from_config(&config, vb)
+            }
+        }
+    }
+
+    pub fn from_gguf<R: std::io::Seek + std::io::Read>(
+        content: gguf_file::Content,
+        reader: &mut R,
+        device: &Device,
+    ) -> Result<Self> {
+        let arch = match content.metadata.get("general.architecture") {
+            Some(arch) => arch.to_string()?.clone(),
+            None => bail!("missing general.architecture in gguf metadata"),
+        };
+        let model = match arch.as_str() {
+            // Mistral gguf files use the llama architecture.
+            "llama" => Self::QuantizedLlama(quantized_llama::ModelWeights::from_gguf(
+                content, reader, device,
+            )?),
+            "phi3" => Self::QuantizedPhi3(quantized_phi3::ModelWeights::from_gguf(
+                false, content, reader, device,
+            )?),
+            "qwen2" => Self::QuantizedQwen2(quantized_qwen2::ModelWeights::from_gguf(
+                content, reader, device,
+            )?),
+            "qwen3" => Self::QuantizedQwen3(quantized_qwen3::ModelWeights::from_gguf(
+                content, reader, device,
+            )?),
+            arch => bail!("unsupported gguf architecture {arch}"),
+        };
+        Ok(model)
+    }
+
+    /// Builds a model from the content of a `config.json` file, the architecture is read from
+    /// the `model_type` field.
+    pub fn from_config(config: &str, vb: VarBuilder) -> Result<Self> {
+        #[derive(serde::Deserialize)]
+        struct ModelType {
+            model_type: String,
+        }
+        let model_type: ModelType = serde_json::from_str(config)?;
+        let model = match model_type.model_type.as_str() {
+            "llama" => {
+                let config: llama::LlamaConfig = serde_json::from_str(config)?;
+                let config = config.into_config(false);
+                let cache = llama::Cache::new(true, vb.dtype(), &config, vb.device())?;
+                let model = llama::Llama::load(vb, &config)?;
+                Self::Llama {
+                    model,
+                    empty_cache: cache.clone(),
+                    cache,
+                }
+            }
+            "mistral" => {
+                let config: mistral::Config = serde_json::from_str(config)?;
+                Self::Mistral(mistral::Model::new(&config, vb)?)
+            }
+            "phi3" => {
+                let config: phi3::Config = serde_json::from_str(config)?;
+                Self::Phi3(phi3::Model::new(&config, vb)?)
+            }
+            "qwen2" => {
+                let config: qwen2::Config = serde_json::from_str(config)?;
+                Self::Qwen2(qwen2::ModelForCausalLM::new(&config, vb)?)
+            }
+            "qwen3" => {
+                let config: qwen3::Config = serde_json::from_str(config)?;
+                Self::Qwen3(qwen3::ModelForCausalLM::new(&config, vb)?)
+            }
+            model_type => bail!("unsupported model type {model_type}"),
+        };
+        Ok(model)
+    }
+
+    /// Starts a continuous batching engine on a background thread for the architectures that
+    /// support batched decoding, the engine shares the weights of the model. `dtype` is the dtype
+    /// of the engine kv-caches.
+    pub fn spawn_engine(
+        &self,
+        config: EngineConfig,
+        dtype: DType,
+        device: &Device,
+    ) -> Result<Option<EngineHandle>> {
+        let engine = match self {
+            Self::Qwen3(m) => Engine::new(m.clone(), config, dtype, device)?.spawn(),
+            _ => return Ok(None),
+        };
+        Ok(Some(engine))
+    }
+
+    /// Resets the kv-caches so that a new sequence can be processed.
+    pub fn clear_kv_cache(&mut self) {
+        match self {
+            Self::Llama {
+                model: _,
+                cache,
+                empty_cache,
+            } => *cache = empty_cache.clone(),
+            Self::Mistral(m) => m.clear_kv_cache(),
+            Self::Phi3(m) => m.clear_kv_cache(),
+            Self::Qwen2(m) => m.clear_kv_cache(),
+            Self::Qwen3(m) => m.clear_kv_cache(),
+            Self::QuantizedQwen3(m) => m.clear_kv_cache(),
+            // These models reset their kv-cache when called with a zero offset.
+            Self::QuantizedLlama(_) | Self::QuantizedPhi3(_) | Self::QuantizedQwen2(_) => {}
+        }
+    }
+
+    /// Returns the logits for the last position of `input`, shape `(1, seq_len)`, as a 1d
+    /// tensor.
+    pub fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        let logits = match self {
+            Self::Llama {
+                model,
+                cache,
+                empty_cache: _,
+            } => model.forward(input, offset, cache)?,
+            Self::Mistral(m) => m.forward(input, offset)?,
+            Self::Phi3(m) => m.forward(input, offset)?,
+            Self::Qwen2(m) => m.forward(input, offset)?,
+            Self::Qwen3(m) => m.forward(input, offset)?,
+            Self::QuantizedLlama(m) => m.forward(input, offset)?,
+            Self::QuantizedPhi3(m) => m.forward(input, offset)?,
+            Self::QuantizedQwen2(m) => m.forward(input, offset)?,
+            Self::QuantizedQwen3(m) => m.forward(input, offset)?,
+        };
+        Ok(logits.flatten_all()?.to_dtype(DType::F32)?)
+    }
+
+    /// Computes a normalized embedding from the hidden state of the last token, as done by the
+    /// Qwen3 embedding models.
+    pub fn embed(&mut self, input: &Tensor) -> Result<Vec<f32>> {
+        self.clear_kv_cache();
+        let hidden = match self {
+            Self::Qwen3(m) => m.forward_hidden(input, 0)?,
+            Self::QuantizedQwen3(m) => m.forward_hidden(input, 0)?,
+            _ => bail!("embeddings are only supported for qwen3 models"),
+        };
+        self.clear_kv_cache();
+        let (_, seq_len, _) = hidden.dims3()?;
+        let last = hidden.narrow(1, seq_len - 1, 1)?.flatten_all()?;
+        let last = last.to_dtype(DType::F32)?;
+        let norm = last.sqr()?.sum_all()?.sqrt()?;
+        Ok(last.broadcast_div(&norm)?.to_vec1()?)
+    }
+}
@@ -0,0 +1,440 @@
+//! The http routes implementing the OpenAI compatible api.
+use crate::generation::{generate, generate_batched, FinishReason, GenerationParams};
+use crate::model::Model;
+use crate::openai::*;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use candle::Device;
+use candle_transformers::chat_template::{ChatTemplate, Message};
+use candle_transformers::generation::engine::EngineHandle;
+use candle_transformers::generation::Sampling;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokenizers::Tokenizer;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+pub struct AppState {
+    /// The model used for the embeddings, and for the completions when there is no engine.
+    pub model: Mutex<Model>,
+    /// The continuous batching engine serving the completions, see [`Model::spawn_engine`].
+    pub engine: Option<EngineHandle>,
+    pub tokenizer: Tokenizer,
+    pub chat_template: Option<ChatTemplate>,
+    pub eos_tokens: Vec<u32>,
+    pub model_id: String,
+    pub device: Device,
+    /// The number of tokens to generate when the request does not specify `max_tokens`.
+    pub max_tokens: usize,
+    request_id: AtomicU64,
+}
+
+impl AppState {
+    pub fn new(
+        model: Model,
+        tokenizer: Tokenizer,
+        chat_template: Option<ChatTemplate>,
+        eos_tokens: Vec<u32>,
+        model_id: String,
+        device: Device,
+    ) -> Self {
+        Self {
+            model: Mutex::new(model),
+            engine: None,
+            tokenizer,
+            chat_template,
+            eos_tokens,
+            model_id,
+            device,
+            max_tokens: 256,
+            request_id: AtomicU64::new(0),
+        }
+    }
+
+    // A panic while generating poisons the mutex, the kv-cache may then hold a partial sequence
+    // so it is reset before the model gets used again.
+    fn lock_model(&self) -> MutexGuard<'_, Model> {
+        self.model.lock().unwrap_or_else(|poisoned| {
+            let mut model = poisoned.into_inner();
+            model.clear_kv_cache();
+            self.model.clear_poison();
+            model
+        })
+    }
+
+    fn next_id(&self, prefix: &str) -> String {
+        let id = self.request_id.fetch_add(1, Ordering::Relaxed);
+        format!("{prefix}-{id}")
+    }
+
+    fn encode(&self, text: &str, add_special_tokens: bool) -> anyhow::Result<Vec<u32>> {
+        let encoding = self
+            .tokenizer
+            .encode(text, add_special_tokens)
+            .map_err(anyhow::Error::msg)?;
+        Ok(encoding.get_ids().to_vec())
+    }
+
+    fn generation_params(&self, req: &SamplingRequest) -> GenerationParams {
+        let temperature = req.temperature.unwrap_or(1.0);
+        let sampling = if temperature <= 0. {
+            Sampling::ArgMax
+        } else {
+            match (req.top_k, req.top_p) {
+                (None, None) => Sampling::All { temperature },
+                (Some(k), None) => Sampling::TopK { k, temperature },
+                (None, Some(p)) => Sampling::TopP { p, temperature },
+                (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+            }
+        };
+        GenerationParams {
+            max_tokens: req.max_tokens.unwrap_or(self.max_tokens),
+            sampling,
+            seed: req.seed.unwrap_or(299792458),
+            stop: req
+                .stop
+                .clone()
+                .map(|s| s.into_vec())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .collect(),
+        }
+    }
+}
+
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/v1/models", get(models))
+        .route("/v1/completions", post(completions))
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/embeddings", post(embeddings))
+        .with_state(state)
+}
+
+pub struct AppError {
+    status: StatusCode,
+    message: String,
+}
+
+impl AppError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(e: anyhow::Error) -> Self {
+        Self {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let type_ = if self.status.is_client_error() {
+            "invalid_request_error"
+        } else {
+            "server_error"
+        };
+        let body = ErrorResponse {
+            error: ErrorDetail {
+                message: self.message,
+                type_,
+            },
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+type AppResult<T> = Result<T, AppError>;
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn sse_event<T: serde::Serialize>(value: &T) -> Event {
+    Event::default().data(serde_json::to_string(value).unwrap_or_default())
+}
+
+fn error_event(e: &anyhow::Error) -> Event {
+    sse_event(&ErrorResponse {
+        error: ErrorDetail {
+            message: e.to_string(),
+            type_: "server_error",
+        },
+    })
+}
+
+/// Runs the generation on a blocking thread, `on_text` gets called with the generated text.
+/// Without an engine, the requests are processed one at a time.
+async fn run_generation(
+    state: Arc<AppState>,
+    prompt_tokens: Vec<u32>,
+    params: GenerationParams,
+    on_text: impl FnMut(&str) -> bool + Send + 'static,
+) -> anyhow::Result<(FinishReason, crate::generation::Usage)> {
+    tokio::task::spawn_blocking(move || match &state.engine {
+        Some(engine) => generate_batched(
+            engine,
+            &state.tokenizer,
+            &prompt_tokens,
+            &state.eos_tokens,
+            &params,
+            on_text,
+        ),
+        None => {
+            let mut model = state.lock_model();
+            generate(
+                &mut model,
+                &state.tokenizer,
+                &prompt_tokens,
+                &state.eos_tokens,
+                &params,
+                &state.device,
+                on_text,
+            )
+        }
+    })
+    .await?
+}
+
+/// Starts the generation in the background and streams `first`, then the events produced by
+/// `chunk` for each piece of text and by `last` once generation is over.
+fn stream_generation(
+    state: Arc<AppState>,
+    prompt_tokens: Vec<u32>,
+    params: GenerationParams,
+    first: Option<Event>,
+    chunk: impl Fn(&str) -> Event + Send + 'static,
+    last: impl FnOnce(FinishReason) -> Event + Send + 'static,
+) -> Response {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    if let Some(first) = first {
+        let _ = tx.send(Ok(first));
+    }
+    tokio::spawn(async move {
+        let tx_text = tx.clone();
+        let on_text = move |text: &str| tx_text.send(Ok(chunk(text))).is_ok();
+        let event = match run_generation(state, prompt_tokens, params, on_text).await {
+            Ok((finish_reason, _usage)) => last(finish_reason),
+            Err(e) => error_event(&e),
+        };
+        let _ = tx.send(Ok(event));
+        let _ = tx.send(Ok(Event::default().data("[DONE]")));
+    });
+    let stream = UnboundedReceiverStream::<Result<Event, Infallible>>::new(rx);
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn models(State(state): State<Arc<AppState>>) -> Json<ModelList> {
+    Json(ModelList {
+        object: "list",
+        data: vec![ModelInfo {
+            id: state.model_id.clone(),
+            object: "model",
+            created: 0,
+            owned_by: "candle",
+        }],
+    })
+}
+
+async fn completions(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<CompletionRequest>,
+) -> AppResult<Response> {
+    let prompt_tokens = state.encode(&req.prompt, true)?;
+    if prompt_tokens.is_empty() {
+        return Err(AppError::bad_request("the prompt is empty"));
+    }
+    let params = state.generation_params(&req.sampling);
+    let id = state.next_id("cmpl");
+    let created = now();
+    let model = state.model_id.clone();
+    let completion = move |text: String, finish_reason: Option<FinishReason>, usage| Completion {
+        id: id.clone(),
+        object: "text_completion",
+        created,
+        model: model.clone(),
+        choices: vec![CompletionChoice {
+            index: 0,
+            text,
+            finish_reason: finish_reason.map(|f| f.as_str()),
+        }],
+        usage,
+    };
+    if req.sampling.stream {
+        let completion = Arc::new(completion);
+        let last_completion = completion.clone();
+        let stream = stream_generation(
+            state,
+            prompt_tokens,
+            params,
+            None,
+            move |text| sse_event(&completion(text.to_string(), None, None)),
+            move |finish_reason| {
+                sse_event(&last_completion(String::new(), Some(finish_reason), None))
+            },
+        );
+        return Ok(stream);
+    }
+    let text = Arc::new(Mutex::new(String::new()));
+    let out = text.clone();
+    let (finish_reason, usage) = run_generation(state, prompt_tokens, params, move |t| {
+        out.lock().map(|mut out| out.push_str(t)).is_ok()
+    })
+    .await?;
+    let text = text.lock().map(|t| t.clone()).unwrap_or_default();
+    let completion = completion(text, Some(finish_reason), Some(usage.into()));
+    Ok(Json(completion).into_response())
+}
+
+async fn chat_completions(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ChatCompletionRequest>,
+) -> AppResult<Response> {
+    let template = match &state.chat_template {
+        Some(template) => template,
+        None => {
+            return Err(AppError::bad_request(
+                "the model does not have a chat template",
+            ))
+        }
+    };
+    let messages = req
+        .messages
+        .into_iter()
+        .map(Message::from)
+        .collect::<Vec<_>>();
+    let prompt = if req.tools.is_empty() {
+        template.apply(&messages, true)
+    } else {
+        template.apply_with_tools(&messages, &req.tools, true)
+    };
+    let prompt = prompt
+        .map_err(|e| AppError::bad_request(format!("cannot render the chat template: {e}")))?;
+    // The template already includes the special tokens.
+    let prompt_tokens = state.encode(&prompt, false)?;
+    if prompt_tokens.is_empty() {
+        return Err(AppError::bad_request("the prompt is empty"));
+    }
+    let params = state.generation_params(&req.sampling);
+    let id = state.next_id("chatcmpl");
+    let created = now();
+    let model = state.model_id.clone();
+    if req.sampling.stream {
+        let chunk = move |delta: Delta, finish_reason: Option<FinishReason>| ChatCompletionChunk {
+            id: id.clone(),
+            object: "chat.completion.chunk",
+            created,
+            model: model.clone(),
+            choices: vec![ChatChunkChoice {
+                index: 0,
+                delta,
+                finish_reason: finish_reason.map(|f| f.as_str()),
+            }],
+        };
+        let chunk = Arc::new(chunk);
+        let last_chunk = chunk.clone();
+        // The role is sent in a separate chunk before any content.
+        let role = Delta {
+            role: Some("assistant"),
+            content: None,
+        };
+        let first = sse_event(&chunk(role, None));
+        let stream = stream_generation(
+            state,
+            prompt_tokens,
+            params,
+            Some(first),
+            move |text| {
+                let delta = Delta {
+                    role: None,
+                    content: Some(text.to_string()),
+                };
+                sse_event(&chunk(delta, None))
+            },
+            move |finish_reason| sse_event(&last_chunk(Delta::default(), Some(finish_reason))),
+        );
+        return Ok(stream);
+    }
+    let text = Arc::new(Mutex::new(String::new()));
+    let out = text.clone();
+    let (finish_reason, usage) = run_generation(state, prompt_tokens, params, move |t| {
+        out.lock().map(|mut out| out.push_str(t)).is_ok()
+    })
+    .await?;
+    let content = text.lock().map(|t| t.clone()).unwrap_or_default();
+    Ok(Json(ChatCompletion {
+        id,
+        object: "chat.completion",
+        created,
+        model,
+        choices: vec![ChatChoice {
+            index: 0,
+            message: ResponseMessage {
+                role: "assistant",
+                content,
+            },
+            finish_reason: finish_reason.as_str(),
+        }],
+        usage: usage.into(),
+    })
+    .into_response())
+}
+
+async fn embeddings(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<EmbeddingRequest>,
+) -> AppResult<Json<EmbeddingList>> {
+    let inputs = req
+        .input
+        .into_vec()
+        .iter()
+        .map(|input| state.encode(input, true))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if inputs.iter().any(|tokens| tokens.is_empty()) {
+        return Err(AppError::bad_request("empty input"));
+    }
+    let prompt_tokens = inputs.iter().map(|t| t.len()).sum();
+    let model_id = state.model_id.clone();
+    let data = tokio::task::spawn_blocking(move || {
+        let mut model = state.lock_model();
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(index, tokens)| {
+                let input = candle::Tensor::new(tokens.as_slice(), &state.device)?.unsqueeze(0)?;
+                Ok(Embedding {
+                    object: "embedding",
+                    index,
+                    embedding: model.embed(&input)?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    })
+    .await
+    .map_err(anyhow::Error::from)??;
+    Ok(Json(EmbeddingList {
+        object: "list",
+        data,
+        model: model_id,
+        usage: EmbeddingUsage {
+            prompt_tokens,
+            total_tokens: prompt_tokens,
+        },
+    }))
+}
@@ -0,0 +1,297 @@
+use anyhow::Result;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use candle::{DType, Device};
+use candle_server::model::Model;
+use candle_server::server::{router, AppState};
+use candle_transformers::chat_template::ChatTemplate;
+use candle_transformers::generation::engine::EngineConfig;
+use candle_transformers::models::qwen3;
+use http_body_util::BodyExt;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+const WORDS: [&str; 6] = [
+    "<unk>",
+    "user",
+    "assistant",
+    "system",
+    "hello",
+    "<|im_end|>",
+];
+
+fn tokenizer() -> Result<tokenizers::Tokenizer> {
+    let vocab = (0..32)
+        .map(|i| {
+            let word = WORDS.get(i).map_or(format!("w{i}"), |w| w.to_string());
+            (word, i)
+        })
+        .collect::<std::collections::HashMap<_, _>>();
+    let tokenizer = serde_json::json!({
+        "version": "1.0",
+        "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "<unk>" },
+        "pre_tokenizer": { "type": "WhitespaceSplit" },
+    });
+    let tokenizer = tokenizers::Tokenizer::from_bytes(serde_json::to_vec(&tokenizer)?);
+    tokenizer.map_err(anyhow::Error::msg)
+}
+
+fn tiny_qwen3() -> Result<qwen3::ModelForCausalLM> {
+    Ok(qwen3::ModelForCausalLM::tiny_random(
+        DType::F32,
+        &Device::Cpu,
+    )?)
+}
+
+// With `batched`, the completions are served by a continuous batching engine.
+fn app(batched: bool) -> Result<axum::Router> {
+    app_with(tiny_qwen3()?, batched)
+}
+
+fn app_with(model: qwen3::ModelForCausalLM, batched: bool) -> Result<axum::Router> {
+    let template = ChatTemplate::new(
+        "{% for m in messages %}{{ m.role }} {{ m.content }} <|im_end|> {% endfor %}\
+         {% if add_generation_prompt %}assistant{% endif %}",
+    )?
+    .with_special_tokens("", "<|im_end|>");
+    // Use a token that is not in the vocabulary so that generation never stops early.
+    let model = Model::Qwen3(model);
+    let engine = if batched {
+        let config = EngineConfig {
+            max_batch_size: 2,
+            context: 64,
+        };
+        model.spawn_engine(config, DType::F32, &Device::Cpu)?
+    } else {
+        None
+    };
+    let mut state = AppState::new(
+        model,
+        tokenizer()?,
+        Some(template),
+        vec![32],
+        "tiny".to_string(),
+        Device::Cpu,
+    );
+    state.engine = engine;
+    Ok(router(Arc::new(state)))
+}
+
+async fn post(
+    app: &axum::Router,
+    uri: &str,
+    body: serde_json::Value,
+) -> Result<(StatusCode, String)> {
+    let request = Request::post(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&body)?))?;
+    let response = app.clone().oneshot(request).await?;
+    let status = response.status();
+    let body = response.into_body().collect().await?.to_bytes();
+    Ok((status, String::from_utf8(body.to_vec())?))
+}
+
+#[tokio::test]
+async fn completions() -> Result<()> {
+    let app = app(true)?;
+    let request = serde_json::json!({ "prompt": "hello w7 w8", "max_tokens": 5, "temperature": 0 });
+    let (status, body) = post(&app, "/v1/completions", request).await?;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let body: serde_json::Value = serde_json::from_str(&body)?;
+    assert_eq!(body["object"], "text_completion");
+    assert_eq!(body["choices"][0]["finish_reason"], "length");
+    assert_eq!(body["usage"]["prompt_tokens"], 3);
+    assert_eq!(body["usage"]["completion_tokens"], 5);
+    let text = body["choices"][0]["text"].as_str().unwrap().to_string();
+    assert_eq!(text.split_whitespace().count(), 5);
+
+    // Stop on the third generated word, only the text before it should be returned.
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    let stop = words[2];
+    let request = serde_json::json!({
+        "prompt": "hello w7 w8", "max_tokens": 5, "temperature": 0, "stop": [stop]
+    });
+    let (status, body) = post(&app, "/v1/completions", request).await?;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let body: serde_json::Value = serde_json::from_str(&body)?;
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    let stopped = body["choices"][0]["text"].as_str().unwrap();
+    assert_eq!(stopped, &text[..text.find(stop).unwrap()]);
+
+    // The last word is a prefix of the stop sequence, it is held back while generating but
+    // has to be returned once the generation is over.
+    let request = serde_json::json!({
+        "prompt": "hello w7 w8", "max_tokens": 5, "temperature": 0,
+        "stop": [format!("{}zzz", words[4])]
+    });
+    let (status, body) = post(&app, "/v1/completions", request).await?;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let body: serde_json::Value = serde_json::from_str(&body)?;
+    assert_eq!(body["choices"][0]["finish_reason"], "length");
+    assert_eq!(body["choices"][0]["text"], text);
+    Ok(())
+}
+
+#[tokio::test]
+async fn concurrent_completions() -> Result<()> {
+    let prompts = ["hello w7 w8", "w9", "w10 w11 hello", "hello hello"];
+    let request =
+        |prompt: &str| serde_json::json!({ "prompt": prompt, "max_tokens": 6, "temperature": 0 });
+    let model = tiny_qwen3()?;
+    let sequential = app_with(model.clone(), false)?;
+    let mut expected = vec![];
+    for prompt in prompts {
+        let (status, body) = post(&sequential, "/v1/completions", request(prompt)).await?;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let body: serde_json::Value = serde_json::from_str(&body)?;
+        expected.push(body["choices"][0]["text"].clone())
+    }
+    // More requests than batch slots are sent at once, they are decoded together by the engine.
+    let batched = app_with(model, true)?;
+    let handles = prompts
+        .iter()
+        .map(|prompt| {
+            let app = batched.clone();
+            let request = request(prompt);
+            tokio::spawn(async move { post(&app, "/v1/completions", request).await })
+        })
+        .collect::<Vec<_>>();
+    for (handle, expected) in handles.into_iter().zip(expected) {
+        let (status, body) = handle.await??;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let body: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(body["choices"][0]["text"], expected);
+        assert_eq!(body["usage"]["completion_tokens"], 6);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn chat_completions_streaming() -> Result<()> {
+    let app = app(true)?;
+    let messages =
+        serde_json::json!([{ "role": "user", "content": [{ "type": "text", "text": "hello" }] }]);
+    let request = serde_json::json!({ "messages": messages, "max_tokens": 4, "temperature": 0 });
+    let (status, body) = post(&app, "/v1/chat/completions", request).await?;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let body: serde_json::Value = serde_json::from_str(&body)?;
+    assert_eq!(body["object"], "chat.completion");
+    assert_eq!(body["choices"][0]["message"]["role"], "assistant");
+    assert_eq!(body["usage"]["prompt_tokens"], 4);
+    let content = body["choices"][0]["message"]["content"].as_str().unwrap();
+
+    let request = serde_json::json!({
+        "messages": messages, "max_tokens": 4, "temperature": 0, "stream": true
+    });
+    let (status, body) = post(&app, "/v1/chat/completions", request).await?;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let events = body
+        .lines()
+        .filter_map(|l| l.strip_prefix("data: "))
+        .collect::<Vec<_>>();
+    assert_eq!(events.last(), Some(&"[DONE]"));
+    let chunks = events[..events.len() - 1]
+        .iter()
+        .map(|e| serde_json::from_str::<serde_json::Value>(e))
+        .collect::<serde_json::Result<Vec<_>>>()?;
+    assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+    let streamed = chunks
+        .iter()
+        .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+        .collect::<String>();
+    assert_eq!(streamed, content);
+    let last = chunks.last().unwrap();
+    assert_eq!(last["object"], "chat.completion.chunk");
+    assert_eq!(last["choices"][0]["finish_reason"], "length");
+    Ok(())
+}
+
+#[test]
+fn text_stream() -> Result<()> {
+    use candle_server::generation::{StreamEvent, TextStream};
+    // The euro sign is split over three byte tokens and "ab" decodes to "X" once fused, so the
+    // decoding is not prefix stable.
+    let vocab = ["<unk>", "a", "b", "c", "<0xE2>", "<0x82>", "<0xAC>"]
+        .iter()
+        .enumerate()
+        .map(|(i, w)| (w.to_string(), i))
+        .collect::<std::collections::HashMap<_, _>>();
+    let tokenizer = serde_json::json!({
+        "version": "1.0",
+        "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "<unk>" },
+        "decoder": { "type": "Sequence", "decoders": [
+            { "type": "ByteFallback" },
+            { "type": "Fuse" },
+            { "type": "Replace", "pattern": { "String": "ab" }, "content": "X" },
+        ]},
+    });
+    let tokenizer = tokenizers::Tokenizer::from_bytes(serde_json::to_vec(&tokenizer)?);
+    let tokenizer = tokenizer.map_err(anyhow::Error::msg)?;
+    let run = |tokens: &[u32], stop: &[String]| -> Result<Vec<StreamEvent>> {
+        let mut stream = TextStream::new(&tokenizer, stop);
+        let mut events = vec![];
+        for &token in tokens {
+            let event = stream.push(token)?;
+            if let StreamEvent::Stop(_) = event {
+                events.push(event);
+                return Ok(events);
+            }
+            events.push(event)
+        }
+        events.push(stream.finish()?);
+        Ok(events)
+    };
+    let text = |s: &str| StreamEvent::Text(s.to_string());
+
+    // The incomplete utf-8 sequences are held back.
+    let events = run(&[3, 4, 5, 6, 3], &[])?;
+    assert_eq!(
+        events,
+        [
+            text("c"),
+            text(""),
+            text(""),
+            text("€"),
+            text("c"),
+            text("")
+        ]
+    );
+
+    // The text that has been returned cannot change anymore.
+    let events = run(&[1, 2, 3], &[])?;
+    assert_eq!(events, [text("a"), text(""), text(""), text("Xc")]);
+
+    // Stop sequences can span several tokens and their prefixes are held back.
+    let stop = ["c€".to_string()];
+    let events = run(&[3, 1, 3, 4, 5, 6, 3], &stop)?;
+    let stopped = StreamEvent::Stop(String::new());
+    assert_eq!(
+        events,
+        [text(""), text("ca"), text(""), text(""), text(""), stopped]
+    );
+    let events = run(&[1, 3], &stop)?;
+    assert_eq!(events, [text("a"), text(""), text("c")]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn embeddings_and_errors() -> Result<()> {
+    let app = app(true)?;
+    let request = serde_json::json!({ "input": ["hello w7", "w9"] });
+    let (status, body) = post(&app, "/v1/embeddings", request).await?;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let body: serde_json::Value = serde_json::from_str(&body)?;
+    let data = body["data"].as_array().unwrap();
+    assert_eq!(data.len(), 2);
+    assert_eq!(body["usage"]["prompt_tokens"], 3);
+    let embedding = data[1]["embedding"].as_array().unwrap();
+    assert_eq!(embedding.len(), 16);
+    let norm: f64 = embedding.iter().map(|v| v.as_f64().unwrap().powi(2)).sum();
+    assert!((norm - 1.0).abs() < 1e-4, "{norm}");
+
+    let (status, body) = post(&app, "/v1/completions", serde_json::json!({ "prompt": "" })).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = serde_json::from_str(&body)?;
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    Ok(())
+}
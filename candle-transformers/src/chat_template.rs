@@ -0,0 +1,346 @@
+//! Chat templates, formatting conversations into model prompts.
+//!
+//! Models on the hub ship a jinja template, either as the `chat_template` field of
+//! `tokenizer_config.json` or as the `tokenizer.chat_template` gguf metadata key. Rendering this
+//! template rather than hardcoding the prompt format guarantees that the model is prompted the
+//! same way as during its fine-tuning.
+//!
+//! ```no_run
+//! use candle_transformers::chat_template::{ChatTemplate, Message};
+//! # fn main() -> candle::Result<()> {
+//! let template = ChatTemplate::from_tokenizer_config("tokenizer_config.json")?;
+//! let prompt = template.apply(
+//!     &[
+//!         Message::system("You are a helpful assistant."),
+//!         Message::user("What is the capital of France?"),
+//!     ],
+//!     true,
+//! )?;
+//! # Ok(())
+//! # }
+//! ```
+use candle::quantized::gguf_file;
+use candle::{bail, Error, Result};
+use minijinja::{Environment, ErrorKind};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A message in a conversation, roles are usually `system`, `user`, `assistant` or `tool`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+    /// The tool calls made by an assistant message, in the OpenAI format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<serde_json::Value>,
+    /// The id of the call that a tool message answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl Message {
+    pub fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: content.into(),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new("system", content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new("user", content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new("assistant", content)
+    }
+
+    pub fn tool(content: impl Into<String>, tool_call_id: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new("tool", content)
+        }
+    }
+}
+
+// Special tokens can be specified either as a string or as an `AddedToken` object.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Token {
+    String(String),
+    Added { content: String },
+}
+
+impl Token {
+    fn content(self) -> String {
+        match self {
+            Self::String(s) => s,
+            Self::Added { content } => content,
+        }
+    }
+}
+
+// The chat template is either a single template or a list of named templates.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Templates {
+    Single(String),
+    Named(Vec<NamedTemplate>),
+}
+
+#[derive(Debug, Deserialize)]
+struct NamedTemplate {
+    name: String,
+    template: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenizerConfig {
+    chat_template: Option<Templates>,
+    bos_token: Option<Token>,
+    eos_token: Option<Token>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatTemplate {
+    template: String,
+    // The template used when tools are provided, some models ship one in addition to the
+    // default template.
+    tool_use_template: Option<String>,
+    bos_token: String,
+    eos_token: String,
+}
+
+impl ChatTemplate {
+    /// Creates a chat template from its jinja source, returns an error if it fails to parse.
+    pub fn new(template: impl Into<String>) -> Result<Self> {
+        let template = template.into();
+        check_syntax(&template)?;
+        Ok(Self {
+            template,
+            tool_use_template: None,
+            bos_token: String::new(),
+            eos_token: String::new(),
+        })
+    }
+
+    pub fn with_tool_use_template(mut self, template: impl Into<String>) -> Result<Self> {
+        let template = template.into();
+        check_syntax(&template)?;
+        self.tool_use_template = Some(template);
+        Ok(self)
+    }
+
+    /// Sets the values of the `bos_token` and `eos_token` template variables.
+    pub fn with_special_tokens(
+        mut self,
+        bos_token: impl Into<String>,
+        eos_token: impl Into<String>,
+    ) -> Self {
+        self.bos_token = bos_token.into();
+        self.eos_token = eos_token.into();
+        self
+    }
+
+    pub fn bos_token(&self) -> &str {
+        &self.bos_token
+    }
+
+    pub fn eos_token(&self) -> &str {
+        &self.eos_token
+    }
+
+    /// Reads the template from the content of a `tokenizer_config.json` file.
+    pub fn from_tokenizer_config_str(config: &str) -> Result<Self> {
+        let config: TokenizerConfig = serde_json::from_str(config).map_err(Error::wrap)?;
+        let template = match config.chat_template {
+            None => bail!("no chat_template in tokenizer config"),
+            Some(Templates::Single(template)) => Self::new(template)?,
+            Some(Templates::Named(templates)) => {
+                let get = |name: &str| {
+                    templates
+                        .iter()
+                        .find(|t| t.name == name)
+                        .map(|t| t.template.as_str())
+                };
+                let template = match get("default") {
+                    None => bail!("no default template in the tokenizer config chat_template"),
+                    Some(template) => Self::new(template)?,
+                };
+                match get("tool_use") {
+                    None => template,
+                    Some(tool_use) => template.with_tool_use_template(tool_use)?,
+                }
+            }
+        };
+        let bos_token = config.bos_token.map(Token::content).unwrap_or_default();
+        let eos_token = config.eos_token.map(Token::content).unwrap_or_default();
+        Ok(template.with_special_tokens(bos_token, eos_token))
+    }
+
+    /// Reads the template from a `tokenizer_config.json` file.
+    pub fn from_tokenizer_config<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path).map_err(|e| Error::from(e).with_path(path))?;
+        Self::from_tokenizer_config_str(&config).map_err(|e| e.with_path(path))
+    }
+
+    /// Reads the template from the `tokenizer.chat_template` gguf metadata, the special tokens
+    /// are looked up in `tokenizer.ggml.tokens`.
+    pub fn from_gguf(content: &gguf_file::Content) -> Result<Self> {
+        let metadata = &content.metadata;
+        let template = match metadata.get("tokenizer.chat_template") {
+            None => bail!("no tokenizer.chat_template in gguf metadata"),
+            Some(template) => Self::new(template.to_string()?.as_str())?,
+        };
+        let template = match metadata.get("tokenizer.chat_template.tool_use") {
+            None => template,
+            Some(tool_use) => template.with_tool_use_template(tool_use.to_string()?.as_str())?,
+        };
+        let token = |key: &str| -> Result<String> {
+            let (id, tokens) = match (metadata.get(key), metadata.get("tokenizer.ggml.tokens")) {
+                (Some(id), Some(tokens)) => (id.to_u32()? as usize, tokens.to_vec()?),
+                _ => return Ok(String::new()),
+            };
+            match tokens.get(id) {
+                None => bail!("{key} {id} is out of the vocabulary"),
+                Some(token) => Ok(token.to_string()?.clone()),
+            }
+        };
+        let bos_token = token("tokenizer.ggml.bos_token_id")?;
+        let eos_token = token("tokenizer.ggml.eos_token_id")?;
+        Ok(template.with_special_tokens(bos_token, eos_token))
+    }
+
+    /// Renders the conversation. When `add_generation_prompt` is set, the returned text ends with
+    /// the prompt for the next assistant message.
+    pub fn apply(&self, messages: &[Message], add_generation_prompt: bool) -> Result<String> {
+        self.render(&self.template, messages, None, add_generation_prompt)
+    }
+
+    /// Renders the conversation, making the tools available to the model. Tools are json schemas
+    /// in the OpenAI function calling format.
+    pub fn apply_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[serde_json::Value],
+        add_generation_prompt: bool,
+    ) -> Result<String> {
+        let template = self.tool_use_template.as_ref().unwrap_or(&self.template);
+        self.render(template, messages, Some(tools), add_generation_prompt)
+    }
+
+    fn render(
+        &self,
+        template: &str,
+        messages: &[Message],
+        tools: Option<&[serde_json::Value]>,
+        add_generation_prompt: bool,
+    ) -> Result<String> {
+        let env = environment();
+        let template = env.template_from_str(template).map_err(Error::wrap)?;
+        let text = template
+            .render(minijinja::context! {
+                messages => messages,
+                tools => tools,
+                add_generation_prompt => add_generation_prompt,
+                bos_token => self.bos_token,
+                eos_token => self.eos_token,
+            })
+            .map_err(Error::wrap)?;
+        Ok(text)
+    }
+}
+
+fn check_syntax(template: &str) -> Result<()> {
+    environment()
+        .template_from_str(template)
+        .map_err(Error::wrap)?;
+    Ok(())
+}
+
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    // These settings match the ones used by the transformers library.
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    // Templates are written for python jinja and call string methods such as `strip`.
+    env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+    env.add_function(
+        "raise_exception",
+        |msg: String| -> std::result::Result<(), minijinja::Error> {
+            Err(minijinja::Error::new(ErrorKind::InvalidOperation, msg))
+        },
+    );
+    env.add_function("strftime_now", strftime_now);
+    env
+}
+
+// The current UTC date and time, only the most common format specifiers are supported.
+fn strftime_now(format: String) -> String {
+    const MONTHS: [&str; 12] = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let month_name = MONTHS[month as usize - 1];
+
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&year.to_string()),
+            Some('y') => out.push_str(&format!("{:02}", year % 100)),
+            Some('m') => out.push_str(&format!("{month:02}")),
+            Some('d') => out.push_str(&format!("{day:02}")),
+            Some('B') => out.push_str(month_name),
+            Some('b') => out.push_str(&month_name[..3]),
+            Some('H') => out.push_str(&format!("{:02}", secs / 3600)),
+            Some('M') => out.push_str(&format!("{:02}", secs / 60 % 60)),
+            Some('S') => out.push_str(&format!("{:02}", secs % 60)),
+            Some('%') => out.push('%'),
+            Some(c) => {
+                out.push('%');
+                out.push(c)
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
@@ -0,0 +1,439 @@
+//! Continuous Batching
+//!
+//! An [`Engine`] serves many generation requests concurrently from a single loaded model. The
+//! model runs on a fixed number of batch slots, each slot having its own region of a
+//! [`ScatteredKvCache`] and its own position in the stream. On every step the engine:
+//! - retires the sequences that hit a stop condition or whose receiver has been dropped,
+//! - admits waiting requests into the free slots and prefills their prompts,
+//! - decodes one token for all the running sequences in a single batched forward pass.
+//!
+//! Each sequence has its own [`LogitsProcessor`] and stop conditions, generated tokens are
+//! streamed back over a channel as soon as they are sampled.
+//!
+//! ```ignore
+//! use candle_transformers::generation::engine::{Engine, EngineConfig, GenerationParams};
+//!
+//! let config = EngineConfig { max_batch_size: 8, context: 4096 };
+//! let handle = Engine::new(model, config, DType::F32, &device)?.spawn();
+//! let params = GenerationParams { stop_tokens: vec![eos_token], ..Default::default() };
+//! for event in handle.submit(prompt_tokens, params)? {
+//!     match event? {
+//!         GenerationEvent::Token(token) => print!("{}", tokenizer.decode(&[token], true)?),
+//!         GenerationEvent::Finished(reason) => println!("\n{reason:?}"),
+//!     }
+//! }
+//! ```
+use super::{LogitsProcessor, Sampling};
+use candle::{DType, Device, IndexOp, Result, Tensor};
+use candle_nn::kv_cache::{IndicesAndMask, ScatteredCacheBuilder, ScatteredKvCache};
+use std::collections::VecDeque;
+use std::sync::mpsc;
+
+/// A causal language model that can process a batch of sequences at different positions.
+pub trait BatchedModel {
+    /// Allocates the kv-caches for all the layers, the batch size and context size are the ones
+    /// of `builder`.
+    fn make_caches(&self, builder: &ScatteredCacheBuilder) -> Result<Vec<ScatteredKvCache>>;
+
+    /// Processes `input`, shape `(batch_size, seq_len)`, `positions` being the position of the
+    /// first token for each batch element. Returns the logits for the last position of each
+    /// batch element, shape `(batch_size, vocab_size)`.
+    fn forward_batch(
+        &mut self,
+        input: &Tensor,
+        positions: &[usize],
+        iam: &IndicesAndMask,
+        caches: &mut [ScatteredKvCache],
+    ) -> Result<Tensor>;
+
+    /// The number of positions supported by the model, the sequences reaching it are stopped
+    /// with [`FinishReason::Length`].
+    fn max_seq_len(&self) -> usize {
+        usize::MAX
+    }
+}
+
+impl BatchedModel for crate::models::qwen3::ModelForCausalLM {
+    fn make_caches(&self, builder: &ScatteredCacheBuilder) -> Result<Vec<ScatteredKvCache>> {
+        self.make_scattered_caches(builder)
+    }
+
+    fn forward_batch(
+        &mut self,
+        input: &Tensor,
+        positions: &[usize],
+        iam: &IndicesAndMask,
+        caches: &mut [ScatteredKvCache],
+    ) -> Result<Tensor> {
+        self.forward_scattered(input, positions, iam, caches)
+    }
+
+    fn max_seq_len(&self) -> usize {
+        self.max_position_embeddings()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineConfig {
+    /// The number of sequences that can be decoded together.
+    pub max_batch_size: usize,
+    /// The maximum number of tokens per sequence, prompt included. The sequences are also limited
+    /// by the number of positions supported by the model.
+    pub context: usize,
+}
+
+/// The per-request sampling parameters and stop conditions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerationParams {
+    pub sampling: Sampling,
+    pub seed: u64,
+    pub max_new_tokens: usize,
+    /// Generation stops when one of these tokens is sampled, the stop token is not streamed.
+    pub stop_tokens: Vec<u32>,
+    /// Penalty to apply to repeated tokens, 1. means no penalty.
+    pub repeat_penalty: f32,
+    /// The context size to consider for the repeat penalty.
+    pub repeat_last_n: usize,
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        Self {
+            sampling: Sampling::ArgMax,
+            seed: 299792458,
+            max_new_tokens: 256,
+            stop_tokens: vec![],
+            repeat_penalty: 1.,
+            repeat_last_n: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    /// A stop token has been sampled.
+    Stop,
+    /// `max_new_tokens` has been reached or the sequence does not fit in the context anymore.
+    Length,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenerationEvent {
+    Token(u32),
+    Finished(FinishReason),
+}
+
+pub type EventSender = mpsc::Sender<Result<GenerationEvent>>;
+pub type EventReceiver = mpsc::Receiver<Result<GenerationEvent>>;
+
+/// A generation request as sent to a running engine, see [`EngineHandle`].
+pub struct Request {
+    pub prompt: Vec<u32>,
+    pub params: GenerationParams,
+    pub events: EventSender,
+}
+
+struct Sequence {
+    tokens: Vec<u32>,
+    prompt_len: usize,
+    // The number of tokens that have been processed by the model.
+    processed: usize,
+    params: GenerationParams,
+    logits_processor: LogitsProcessor,
+    events: EventSender,
+}
+
+impl Sequence {
+    fn new(request: Request) -> Self {
+        let Request {
+            prompt,
+            params,
+            events,
+        } = request;
+        let logits_processor = LogitsProcessor::from_sampling(params.seed, params.sampling.clone());
+        Self {
+            prompt_len: prompt.len(),
+            tokens: prompt,
+            processed: 0,
+            params,
+            logits_processor,
+            events,
+        }
+    }
+
+    // Returns false if the receiving end has been dropped.
+    fn send(&self, event: Result<GenerationEvent>) -> bool {
+        self.events.send(event).is_ok()
+    }
+
+    // Samples the next token and streams it, returns true if the sequence is done.
+    fn sample(&mut self, logits: &Tensor, context: usize) -> Result<bool> {
+        let logits = if self.params.repeat_penalty == 1. {
+            logits.clone()
+        } else {
+            let start_at = self.tokens.len().saturating_sub(self.params.repeat_last_n);
+            crate::utils::apply_repeat_penalty(
+                logits,
+                self.params.repeat_penalty,
+                &self.tokens[start_at..],
+            )?
+        };
+        let token = self.logits_processor.sample(&logits)?;
+        self.tokens.push(token);
+        if self.params.stop_tokens.contains(&token) {
+            self.send(Ok(GenerationEvent::Finished(FinishReason::Stop)));
+            return Ok(true);
+        }
+        if !self.send(Ok(GenerationEvent::Token(token))) {
+            return Ok(true);
+        }
+        let generated = self.tokens.len() - self.prompt_len;
+        if generated >= self.params.max_new_tokens || self.tokens.len() >= context {
+            self.send(Ok(GenerationEvent::Finished(FinishReason::Length)));
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
+
+pub struct Engine<M> {
+    model: M,
+    config: EngineConfig,
+    builder: ScatteredCacheBuilder,
+    caches: Vec<ScatteredKvCache>,
+    // The maximum number of tokens per sequence, for both the context and the model.
+    max_seq_len: usize,
+    slots: Vec<Option<Sequence>>,
+    waiting: VecDeque<Sequence>,
+    device: Device,
+}
+
+impl<M: BatchedModel> Engine<M> {
+    /// `dtype` is the dtype of the kv-caches, it should match the one used by the model.
+    pub fn new(model: M, config: EngineConfig, dtype: DType, device: &Device) -> Result<Self> {
+        if config.max_batch_size == 0 || config.context == 0 {
+            candle::bail!("invalid engine config {config:?}")
+        }
+        let builder =
+            ScatteredCacheBuilder::new(config.max_batch_size, config.context, dtype, device)?;
+        let caches = model.make_caches(&builder)?;
+        let max_seq_len = usize::min(config.context, model.max_seq_len());
+        let slots = (0..config.max_batch_size).map(|_| None).collect();
+        Ok(Self {
+            model,
+            config,
+            builder,
+            caches,
+            max_seq_len,
+            slots,
+            waiting: VecDeque::new(),
+            device: device.clone(),
+        })
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Queues a new request, the events are streamed to the returned receiver once the request
+    /// is admitted. Dropping the receiver cancels the request.
+    pub fn add_request(&mut self, prompt: Vec<u32>, params: GenerationParams) -> EventReceiver {
+        let (events, receiver) = mpsc::channel();
+        self.push_request(Request {
+            prompt,
+            params,
+            events,
+        });
+        receiver
+    }
+
+    pub fn push_request(&mut self, request: Request) {
+        let seq = Sequence::new(request);
+        if seq.tokens.is_empty() {
+            seq.send(Err(candle::Error::Msg("empty prompt".to_string())));
+        } else if seq.tokens.len() >= self.max_seq_len {
+            seq.send(Err(candle::Error::Msg(format!(
+                "prompt of {} tokens exceeds the context size {}",
+                seq.tokens.len(),
+                self.max_seq_len
+            ))));
+        } else if seq.params.max_new_tokens == 0 {
+            seq.send(Ok(GenerationEvent::Finished(FinishReason::Length)));
+        } else {
+            self.waiting.push_back(seq)
+        }
+    }
+
+    pub fn num_running(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_some()).count()
+    }
+
+    pub fn num_waiting(&self) -> usize {
+        self.waiting.len()
+    }
+
+    pub fn has_work(&self) -> bool {
+        !self.waiting.is_empty() || self.slots.iter().any(|s| s.is_some())
+    }
+
+    /// Runs one scheduling step: admits waiting requests, prefills their prompts and decodes one
+    /// token for every running sequence.
+    ///
+    /// On a model error, all the running sequences receive the error and are retired.
+    pub fn step(&mut self) -> Result<()> {
+        self.admit();
+        let result = self.prefill().and_then(|()| self.decode());
+        if let Err(err) = &result {
+            for slot in self.slots.iter_mut() {
+                if let Some(seq) = slot.take() {
+                    seq.send(Err(candle::Error::Msg(err.to_string())));
+                }
+            }
+        }
+        result
+    }
+
+    fn admit(&mut self) {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.is_some() {
+                continue;
+            }
+            match self.waiting.pop_front() {
+                None => break,
+                Some(seq) => {
+                    self.builder.reset_batch_index(index);
+                    *slot = Some(seq)
+                }
+            }
+        }
+    }
+
+    // Runs the model on a batch where only the rows in `batch_mask` are active, the inactive rows
+    // are padded and do not update the cache.
+    fn forward(&mut self, input: Vec<u32>, seq_len: usize, batch_mask: &[bool]) -> Result<Tensor> {
+        let positions = self.builder.positions().to_vec();
+        let iam = self.builder.indices_and_mask(seq_len, batch_mask)?;
+        let input = Tensor::from_vec(input, (batch_mask.len(), seq_len), &self.device)?;
+        self.model
+            .forward_batch(&input, &positions, &iam, &mut self.caches)
+    }
+
+    // Prompts have different lengths so each of them is processed in its own forward pass, with
+    // a batch of size one using the cache of its slot.
+    fn prefill(&mut self) -> Result<()> {
+        for index in 0..self.slots.len() {
+            let prompt = match &self.slots[index] {
+                Some(seq) if seq.processed == 0 => seq.tokens.clone(),
+                _ => continue,
+            };
+            let seq_len = prompt.len();
+            let positions = [self.builder.positions()[index]];
+            let iam = self.builder.indices_and_mask_for(index, seq_len)?;
+            let mut caches = self
+                .caches
+                .iter()
+                .map(|c| c.batch_element(index))
+                .collect::<Result<Vec<_>>>()?;
+            let input = Tensor::from_vec(prompt, (1, seq_len), &self.device)?;
+            let logits = self
+                .model
+                .forward_batch(&input, &positions, &iam, &mut caches)?
+                .i(0)?;
+            if let Some(seq) = self.slots[index].as_mut() {
+                seq.processed = seq_len;
+                if seq.sample(&logits, self.max_seq_len)? {
+                    self.slots[index] = None
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decode(&mut self) -> Result<()> {
+        let batch_mask = self
+            .slots
+            .iter()
+            .map(|s| matches!(s, Some(seq) if seq.processed > 0))
+            .collect::<Vec<_>>();
+        if !batch_mask.iter().any(|&b| b) {
+            return Ok(());
+        }
+        let input = self
+            .slots
+            .iter()
+            .map(|s| match s {
+                Some(seq) if seq.processed > 0 => *seq.tokens.last().unwrap_or(&0),
+                _ => 0,
+            })
+            .collect::<Vec<_>>();
+        let logits = self.forward(input, 1, &batch_mask)?;
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if !batch_mask[index] {
+                continue;
+            }
+            if let Some(seq) = slot.as_mut() {
+                seq.processed += 1;
+                if seq.sample(&logits.i(index)?, self.max_seq_len)? {
+                    *slot = None
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Serves the requests received on `requests` until the channel is closed and all the
+    /// pending requests have completed.
+    pub fn run(&mut self, requests: mpsc::Receiver<Request>) {
+        loop {
+            if !self.has_work() {
+                // Block until some work arrives.
+                match requests.recv() {
+                    Ok(request) => self.push_request(request),
+                    Err(mpsc::RecvError) => return,
+                }
+            }
+            while let Ok(request) = requests.try_recv() {
+                self.push_request(request)
+            }
+            // Errors have already been forwarded to the affected requests.
+            if let Err(err) = self.step() {
+                tracing::error!("generation step failed: {err}")
+            }
+        }
+    }
+}
+
+impl<M: BatchedModel + Send + 'static> Engine<M> {
+    /// Runs the engine on a background thread, requests can then be submitted from any thread
+    /// through the returned handle.
+    pub fn spawn(mut self) -> EngineHandle {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || self.run(receiver));
+        EngineHandle { sender }
+    }
+}
+
+/// A cloneable handle to an engine running on a background thread.
+#[derive(Clone)]
+pub struct EngineHandle {
+    sender: mpsc::Sender<Request>,
+}
+
+impl EngineHandle {
+    pub fn submit(&self, prompt: Vec<u32>, params: GenerationParams) -> Result<EventReceiver> {
+        let (events, receiver) = mpsc::channel();
+        let request = Request {
+            prompt,
+            params,
+            events,
+        };
+        self.sender
+            .send(request)
+            .map_err(|_| candle::Error::Msg("the engine has stopped".to_string()))?;
+        Ok(receiver)
+    }
+}
@@ -6,6 +6,9 @@
 use candle::{DType, Error, Result, Tensor};
 use rand::{distr::Distribution, SeedableRng};
 
+pub mod engine;
+pub mod speculative;
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum Sampling {
     ArgMax,
@@ -111,6 +114,58 @@ impl LogitsProcessor {
         }
     }
 
+    pub fn sampling(&self) -> &Sampling {
+        &self.sampling
+    }
+
+    /// Returns the normalized probability distribution that `sample` draws from. Tokens that are
+    /// filtered out by top-k or top-p get a zero probability and `ArgMax` results in a one-hot
+    /// vector on the most likely token.
+    pub fn distribution(&self, logits: &Tensor) -> Result<Vec<f32>> {
+        let logits = logits.to_dtype(DType::F32)?;
+        let prs = |temperature: f64| -> Result<Vec<f32>> {
+            let logits = (&logits / temperature)?;
+            candle_nn::ops::softmax_last_dim(&logits)?.to_vec1()
+        };
+        let mut prs = match &self.sampling {
+            Sampling::ArgMax => {
+                let argmax = logits.argmax(candle::D::Minus1)?.to_scalar::<u32>()?;
+                let mut prs = vec![0f32; logits.dim(candle::D::Minus1)?];
+                prs[argmax as usize] = 1.0;
+                return Ok(prs);
+            }
+            // Sampling with the Gumbel-max trick is equivalent to sampling from the softmax.
+            Sampling::GumbelSoftmax { temperature } | Sampling::All { temperature } => {
+                return prs(*temperature)
+            }
+            Sampling::TopP { p, temperature } => {
+                let mut prs = prs(*temperature)?;
+                if *p > 0.0 && *p < 1.0 {
+                    mask_topp(&mut prs, *p as f32)
+                }
+                prs
+            }
+            Sampling::TopK { k, temperature } => {
+                let mut prs = prs(*temperature)?;
+                mask_topk(&mut prs, *k);
+                prs
+            }
+            Sampling::TopKThenTopP { k, p, temperature } => {
+                let mut prs = prs(*temperature)?;
+                mask_topk(&mut prs, *k);
+                let sum_p = prs.iter().sum::<f32>();
+                let p = *p as f32;
+                if p > 0.0 && p < sum_p {
+                    mask_topp(&mut prs, p)
+                }
+                prs
+            }
+        };
+        let sum_p = prs.iter().sum::<f32>();
+        prs.iter_mut().for_each(|v| *v /= sum_p);
+        Ok(prs)
+    }
+
     pub fn sample(&mut self, logits: &Tensor) -> Result<u32> {
         self.sample_f(logits, |_| {})
     }
@@ -156,3 +211,30 @@ impl LogitsProcessor {
         Ok(next_token)
     }
 }
+
+// Zero out the probabilities of the tokens outside of the top-p nucleus, this uses the same
+// cut-off as `sample_topp` but leaves the probabilities unnormalized.
+fn mask_topp(prs: &mut [f32], top_p: f32) {
+    let mut argsort_indices = (0..prs.len()).collect::<Vec<_>>();
+    argsort_indices.sort_by(|&i, &j| prs[j].total_cmp(&prs[i]));
+    let mut cumsum = 0.;
+    for index in argsort_indices {
+        if cumsum >= top_p {
+            prs[index] = 0.0;
+        } else {
+            cumsum += prs[index];
+        }
+    }
+}
+
+// Zero out the probabilities of the tokens outside of the top-k.
+fn mask_topk(prs: &mut [f32], top_k: usize) {
+    if top_k >= prs.len() {
+        return;
+    }
+    let mut argsort_indices = (0..prs.len()).collect::<Vec<_>>();
+    argsort_indices.select_nth_unstable_by(top_k, |&i, &j| prs[j].total_cmp(&prs[i]));
+    for &index in &argsort_indices[top_k..] {
+        prs[index] = 0.0;
+    }
+}
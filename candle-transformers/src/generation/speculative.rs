@@ -0,0 +1,372 @@
+//! Speculative Decoding
+//!
+//! A cheap drafter proposes a few tokens that the target model then verifies in a single forward
+//! pass. Draft tokens are accepted or rejected using the rejection sampling scheme from
+//! [Fast Inference from Transformers via Speculative Decoding](https://arxiv.org/abs/2211.17192)
+//! so that the generated tokens follow the same distribution as sampling from the target model
+//! alone, whatever the quality of the drafts.
+//!
+//! Two drafters are available:
+//! - [`DraftModel`] runs a smaller model that shares the target tokenizer, e.g. qwen3 0.6B in
+//!   front of a larger qwen3.
+//! - [`PromptLookup`] proposes the continuation of an n-gram that already appears in the context
+//!   (prompt lookup decoding), this does not require any model and works well for summarization
+//!   or code editing where the output copies large chunks of the input.
+//!
+//! ```ignore
+//! use candle_transformers::generation::speculative::{DraftModel, SpeculativeDecoder};
+//!
+//! let drafter = DraftModel::new(draft_model, LogitsProcessor::new(1, temp, top_p), &device);
+//! let lp = LogitsProcessor::new(299792458, temp, top_p);
+//! let mut decoder = SpeculativeDecoder::new(target_model, drafter, lp, 4, &device);
+//! let mut tokens = prompt_tokens;
+//! while tokens.len() < max_len {
+//!     let next_tokens = decoder.next_tokens(&tokens)?;
+//!     tokens.extend_from_slice(&next_tokens);
+//! }
+//! ```
+use super::LogitsProcessor;
+use candle::{Device, IndexOp, Result, Tensor};
+use rand::Rng;
+
+/// A causal language model that can be used as the target or the draft model.
+///
+/// The kv-cache of the model is expected to hold exactly the positions that have been processed
+/// so far, `truncate_kv_cache` is used to roll back the positions of rejected draft tokens.
+pub trait SpeculativeModel {
+    /// Processes `input`, shape `(1, seq_len)`, on top of the first `offset` cached positions and
+    /// returns the logits for every input position, shape `(seq_len, vocab_size)`.
+    fn forward_logits(&mut self, input: &Tensor, offset: usize) -> Result<Tensor>;
+
+    /// Drops the kv-cache entries past the first `seq_len` positions.
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()>;
+}
+
+impl SpeculativeModel for crate::models::qwen3::ModelForCausalLM {
+    fn forward_logits(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        self.forward_all(input, offset)?.squeeze(0)
+    }
+
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.truncate_kv_cache(seq_len)
+    }
+}
+
+impl SpeculativeModel for crate::models::quantized_qwen3::ModelWeights {
+    fn forward_logits(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        self.forward_all(input, offset)?.squeeze(0)
+    }
+
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.truncate_kv_cache(seq_len)
+    }
+}
+
+/// The tokens proposed by a [`Drafter`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Draft {
+    pub tokens: Vec<u32>,
+    /// The distributions that each token has been sampled from. This is `None` for deterministic
+    /// drafters, in which case each proposal is treated as a point mass.
+    pub distributions: Option<Vec<Vec<f32>>>,
+}
+
+pub trait Drafter {
+    /// Proposes up to `num_tokens` tokens to follow `context`, the whole token sequence so far
+    /// including the prompt.
+    ///
+    /// Some of the previously proposed tokens may have been rejected, so stateful drafters have to
+    /// reconcile their state with `context` on each call.
+    fn draft(&mut self, context: &[u32], num_tokens: usize) -> Result<Draft>;
+}
+
+// The length of the longest common prefix, used to find which kv-cache entries are still valid.
+fn common_prefix_len(lhs: &[u32], rhs: &[u32]) -> usize {
+    lhs.iter()
+        .zip(rhs.iter())
+        .take_while(|(l, r)| l == r)
+        .count()
+}
+
+/// Drafts tokens by sampling autoregressively from a smaller model.
+pub struct DraftModel<M> {
+    model: M,
+    logits_processor: LogitsProcessor,
+    // The tokens for which the model kv-cache currently holds keys and values.
+    cached: Vec<u32>,
+    device: Device,
+}
+
+impl<M: SpeculativeModel> DraftModel<M> {
+    pub fn new(model: M, logits_processor: LogitsProcessor, device: &Device) -> Self {
+        Self {
+            model,
+            logits_processor,
+            cached: vec![],
+            device: device.clone(),
+        }
+    }
+
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    pub fn model_mut(&mut self) -> &mut M {
+        &mut self.model
+    }
+}
+
+impl<M: SpeculativeModel> Drafter for DraftModel<M> {
+    fn draft(&mut self, context: &[u32], num_tokens: usize) -> Result<Draft> {
+        if num_tokens == 0 || context.is_empty() {
+            return Ok(Draft::default());
+        }
+        // The last context token is always processed again so as to get the logits for the
+        // first draft token.
+        let keep = common_prefix_len(&self.cached, context).min(context.len() - 1);
+        self.model.truncate_kv_cache(keep)?;
+        self.cached.truncate(keep);
+
+        let mut input = context[keep..].to_vec();
+        let mut tokens = Vec::with_capacity(num_tokens);
+        let mut distributions = Vec::with_capacity(num_tokens);
+        for _ in 0..num_tokens {
+            let offset = self.cached.len();
+            let input_t = Tensor::new(input.as_slice(), &self.device)?.unsqueeze(0)?;
+            let logits = self.model.forward_logits(&input_t, offset)?;
+            self.cached.extend_from_slice(&input);
+            let logits = logits.i(input.len() - 1)?;
+            let prs = self.logits_processor.distribution(&logits)?;
+            let token = self.logits_processor.sample_multinomial(&prs)?;
+            tokens.push(token);
+            distributions.push(prs);
+            input = vec![token];
+        }
+        Ok(Draft {
+            tokens,
+            distributions: Some(distributions),
+        })
+    }
+}
+
+/// Model-free drafter that looks up the last n-gram of the context in the earlier tokens and
+/// proposes whatever followed its most recent occurrence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptLookup {
+    min_ngram: usize,
+    max_ngram: usize,
+}
+
+impl PromptLookup {
+    /// Longer n-grams are tried first, down to `min_ngram` tokens.
+    pub fn new(min_ngram: usize, max_ngram: usize) -> Result<Self> {
+        if min_ngram == 0 || min_ngram > max_ngram {
+            candle::bail!("invalid n-gram range for prompt lookup {min_ngram}..={max_ngram}")
+        }
+        Ok(Self {
+            min_ngram,
+            max_ngram,
+        })
+    }
+}
+
+impl Default for PromptLookup {
+    fn default() -> Self {
+        Self {
+            min_ngram: 1,
+            max_ngram: 3,
+        }
+    }
+}
+
+impl Drafter for PromptLookup {
+    fn draft(&mut self, context: &[u32], num_tokens: usize) -> Result<Draft> {
+        let len = context.len();
+        for ngram in (self.min_ngram..=self.max_ngram).rev() {
+            if ngram >= len {
+                continue;
+            }
+            let suffix = &context[len - ngram..];
+            let found = (0..len - ngram)
+                .rev()
+                .find(|&start| &context[start..start + ngram] == suffix);
+            if let Some(start) = found {
+                let from = start + ngram;
+                let to = usize::min(from + num_tokens, len);
+                return Ok(Draft {
+                    tokens: context[from..to].to_vec(),
+                    distributions: None,
+                });
+            }
+        }
+        Ok(Draft::default())
+    }
+}
+
+/// Generates tokens from a target model, verifying the tokens proposed by a [`Drafter`].
+pub struct SpeculativeDecoder<M, D> {
+    target: M,
+    drafter: D,
+    logits_processor: LogitsProcessor,
+    num_draft_tokens: usize,
+    // The tokens for which the target kv-cache currently holds keys and values.
+    cached: Vec<u32>,
+    device: Device,
+    num_drafted: usize,
+    num_accepted: usize,
+}
+
+impl<M: SpeculativeModel, D: Drafter> SpeculativeDecoder<M, D> {
+    /// Creates a decoder that requests `num_draft_tokens` tokens from the drafter at each step.
+    /// `logits_processor` defines the target distribution, all the `Sampling` modes are supported.
+    pub fn new(
+        target: M,
+        drafter: D,
+        logits_processor: LogitsProcessor,
+        num_draft_tokens: usize,
+        device: &Device,
+    ) -> Self {
+        Self {
+            target,
+            drafter,
+            logits_processor,
+            num_draft_tokens,
+            cached: vec![],
+            device: device.clone(),
+            num_drafted: 0,
+            num_accepted: 0,
+        }
+    }
+
+    pub fn target(&self) -> &M {
+        &self.target
+    }
+
+    pub fn target_mut(&mut self) -> &mut M {
+        &mut self.target
+    }
+
+    pub fn drafter(&self) -> &D {
+        &self.drafter
+    }
+
+    pub fn drafter_mut(&mut self) -> &mut D {
+        &mut self.drafter
+    }
+
+    /// The total number of draft tokens that have been verified.
+    pub fn num_drafted(&self) -> usize {
+        self.num_drafted
+    }
+
+    /// The total number of draft tokens that have been accepted by the target model.
+    pub fn num_accepted(&self) -> usize {
+        self.num_accepted
+    }
+
+    pub fn acceptance_rate(&self) -> f64 {
+        if self.num_drafted == 0 {
+            0.
+        } else {
+            self.num_accepted as f64 / self.num_drafted as f64
+        }
+    }
+
+    /// Returns between one and `num_draft_tokens + 1` tokens to append to `context`, the whole
+    /// token sequence so far including the prompt.
+    ///
+    /// The target kv-cache is reused for the longest prefix that it shares with `context`, so
+    /// successive calls only process the new tokens and a different prompt can be used at any
+    /// time.
+    pub fn next_tokens(&mut self, context: &[u32]) -> Result<Vec<u32>> {
+        if context.is_empty() {
+            candle::bail!("speculative decoding requires a non-empty context")
+        }
+        let Draft {
+            mut tokens,
+            distributions,
+        } = self.drafter.draft(context, self.num_draft_tokens)?;
+        tokens.truncate(self.num_draft_tokens);
+        if let Some(distributions) = distributions.as_ref() {
+            if distributions.len() < tokens.len() {
+                candle::bail!(
+                    "drafter returned {} distributions for {} tokens",
+                    distributions.len(),
+                    tokens.len()
+                )
+            }
+        }
+
+        // Run the target model on the new context tokens followed by all the draft tokens.
+        let keep = common_prefix_len(&self.cached, context).min(context.len() - 1);
+        self.target.truncate_kv_cache(keep)?;
+        self.cached.truncate(keep);
+        let input = context[keep..]
+            .iter()
+            .chain(tokens.iter())
+            .copied()
+            .collect::<Vec<_>>();
+        let input_t = Tensor::new(input.as_slice(), &self.device)?.unsqueeze(0)?;
+        let logits = self.target.forward_logits(&input_t, keep)?;
+        self.cached.extend_from_slice(&input);
+        // The logits predicting the first draft token are the ones of the last context token.
+        let logits = logits.narrow(0, context.len() - 1 - keep, tokens.len() + 1)?;
+
+        let mut next_tokens = Vec::with_capacity(tokens.len() + 1);
+        for (index, &token) in tokens.iter().enumerate() {
+            let p = self.logits_processor.distribution(&logits.i(index)?)?;
+            let q = distributions.as_ref().map(|d| d[index].as_slice());
+            let p_token = p.get(token as usize).copied().unwrap_or(0.);
+            let q_token = q.map_or(1., |q| q.get(token as usize).copied().unwrap_or(0.));
+            // Accept the draft token with probability min(1, p / q).
+            let accept = p_token >= q_token || {
+                let u: f32 = self.logits_processor.rng.random();
+                u * q_token < p_token
+            };
+            if accept {
+                next_tokens.push(token);
+                continue;
+            }
+            // On rejection, sample from the residual distribution max(0, p - q) instead.
+            let residual = match q {
+                Some(q) => p
+                    .iter()
+                    .zip(q.iter())
+                    .map(|(p, q)| (p - q).max(0.))
+                    .collect::<Vec<_>>(),
+                None => {
+                    let mut residual = p.clone();
+                    if let Some(v) = residual.get_mut(token as usize) {
+                        *v = 0.
+                    }
+                    residual
+                }
+            };
+            let next_token = if residual.iter().any(|&v| v > 0.) {
+                self.logits_processor.sample_multinomial(&residual)?
+            } else {
+                self.logits_processor.sample_multinomial(&p)?
+            };
+            next_tokens.push(next_token);
+            break;
+        }
+        let num_accepted = if next_tokens.len() == tokens.len() {
+            // All the draft tokens have been accepted, sample one more from the target.
+            let p = self
+                .logits_processor
+                .distribution(&logits.i(tokens.len())?)?;
+            next_tokens.push(self.logits_processor.sample_multinomial(&p)?);
+            tokens.len()
+        } else {
+            next_tokens.len() - 1
+        };
+        self.num_drafted += tokens.len();
+        self.num_accepted += num_accepted;
+
+        // Roll back the cache entries for the rejected draft tokens.
+        let valid_len = context.len() + num_accepted;
+        self.target.truncate_kv_cache(valid_len)?;
+        self.cached.truncate(valid_len);
+        Ok(next_tokens)
+    }
+}
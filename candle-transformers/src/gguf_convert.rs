@@ -0,0 +1,786 @@
+//! Conversion of hugging face checkpoints to gguf files.
+//!
+//! A model directory with a `config.json`, some safetensors weights and optionally the
+//! `tokenizer.json`/`tokenizer_config.json` files is converted to a single gguf file that can
+//! be loaded by the quantized models, e.g. `quantized_llama`, `quantized_qwen3` or
+//! `quantized_gemma3`. This covers:
+//! - the tensor names, mapped to the gguf naming scheme of each architecture,
+//! - the query and key projections of llama models, permuted for the interleaved rope layout,
+//! - the `general.*`, `{arch}.*` hyper-parameters and the `tokenizer.ggml.*` metadata.
+//!
+//! [`validate_gguf`] checks that a gguf file has the metadata and tensors expected by the
+//! quantized model of its architecture.
+use candle::quantized::gguf_file::{self, Value};
+use candle::quantized::{GgmlDType, QTensor};
+use candle::{bail, DType, Device, Error, Result, Tensor};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// The `tokenizer.ggml.token_type` values.
+const TOKEN_TYPE_NORMAL: i32 = 1;
+const TOKEN_TYPE_UNKNOWN: i32 = 2;
+const TOKEN_TYPE_CONTROL: i32 = 3;
+const TOKEN_TYPE_USER_DEFINED: i32 = 4;
+const TOKEN_TYPE_UNUSED: i32 = 5;
+const TOKEN_TYPE_BYTE: i32 = 6;
+
+/// The architectures that can be converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+    /// Llama and mistral models.
+    Llama,
+    Qwen2,
+    Qwen3,
+    Phi3,
+    Gemma,
+    Gemma2,
+    Gemma3,
+}
+
+impl Architecture {
+    /// The architecture for a `model_type` from a `config.json` file.
+    pub fn from_model_type(model_type: &str) -> Result<Self> {
+        let arch = match model_type {
+            "llama" | "mistral" => Self::Llama,
+            "qwen2" => Self::Qwen2,
+            "qwen3" => Self::Qwen3,
+            "phi3" => Self::Phi3,
+            "gemma" => Self::Gemma,
+            "gemma2" => Self::Gemma2,
+            "gemma3" | "gemma3_text" => Self::Gemma3,
+            model_type => bail!("unsupported model type {model_type}"),
+        };
+        Ok(arch)
+    }
+
+    /// The `general.architecture` value, also used as the prefix of the hyper-parameters.
+    pub fn gguf_name(&self) -> &'static str {
+        match self {
+            Self::Llama => "llama",
+            Self::Qwen2 => "qwen2",
+            Self::Qwen3 => "qwen3",
+            Self::Phi3 => "phi3",
+            Self::Gemma => "gemma",
+            Self::Gemma2 => "gemma2",
+            Self::Gemma3 => "gemma3",
+        }
+    }
+
+    /// The architecture for a `general.architecture` value.
+    pub fn from_gguf_name(name: &str) -> Result<Self> {
+        let arch = match name {
+            "llama" => Self::Llama,
+            "qwen2" => Self::Qwen2,
+            "qwen3" => Self::Qwen3,
+            "phi3" => Self::Phi3,
+            "gemma" => Self::Gemma,
+            "gemma2" => Self::Gemma2,
+            "gemma3" => Self::Gemma3,
+            name => bail!("unsupported gguf architecture {name}"),
+        };
+        Ok(arch)
+    }
+
+    /// The `{arch}.*` hyper-parameters that the quantized model requires, without the prefix.
+    pub fn required_metadata(&self) -> &'static [&'static str] {
+        match self {
+            Self::Llama => &[
+                "attention.head_count",
+                "attention.head_count_kv",
+                "attention.layer_norm_rms_epsilon",
+                "block_count",
+                "embedding_length",
+                "rope.dimension_count",
+            ],
+            Self::Qwen2 => &[
+                "attention.head_count",
+                "attention.head_count_kv",
+                "attention.layer_norm_rms_epsilon",
+                "block_count",
+                "context_length",
+                "embedding_length",
+            ],
+            Self::Qwen3 => &[
+                "attention.head_count",
+                "attention.head_count_kv",
+                "attention.key_length",
+                "attention.layer_norm_rms_epsilon",
+                "block_count",
+                "context_length",
+                "embedding_length",
+                "rope.freq_base",
+            ],
+            Self::Phi3 => &[
+                "attention.head_count",
+                "attention.head_count_kv",
+                "attention.layer_norm_rms_epsilon",
+                "block_count",
+                "context_length",
+                "embedding_length",
+                "feed_forward_length",
+                "rope.dimension_count",
+            ],
+            Self::Gemma | Self::Gemma2 => &[
+                "attention.head_count",
+                "attention.head_count_kv",
+                "attention.layer_norm_rms_epsilon",
+                "block_count",
+                "embedding_length",
+            ],
+            Self::Gemma3 => &[
+                "attention.head_count",
+                "attention.head_count_kv",
+                "attention.key_length",
+                "attention.layer_norm_rms_epsilon",
+                "attention.sliding_window",
+                "attention.value_length",
+                "block_count",
+                "embedding_length",
+            ],
+        }
+    }
+
+    /// The tensors of each layer, without the `blk.{i}.` prefix.
+    pub fn layer_tensors(&self) -> Vec<&'static str> {
+        let mut tensors = match self {
+            Self::Phi3 => vec![
+                "attn_norm.weight",
+                "attn_qkv.weight",
+                "attn_output.weight",
+                "ffn_norm.weight",
+                "ffn_up.weight",
+                "ffn_down.weight",
+            ],
+            _ => vec![
+                "attn_norm.weight",
+                "attn_q.weight",
+                "attn_k.weight",
+                "attn_v.weight",
+                "attn_output.weight",
+                "ffn_norm.weight",
+                "ffn_gate.weight",
+                "ffn_up.weight",
+                "ffn_down.weight",
+            ],
+        };
+        match self {
+            Self::Qwen2 => tensors.extend(["attn_q.bias", "attn_k.bias", "attn_v.bias"]),
+            Self::Qwen3 => tensors.extend(["attn_q_norm.weight", "attn_k_norm.weight"]),
+            Self::Gemma2 => tensors.extend(["post_attention_norm.weight", "post_ffw_norm.weight"]),
+            Self::Gemma3 => tensors.extend([
+                "attn_q_norm.weight",
+                "attn_k_norm.weight",
+                "post_attention_norm.weight",
+                "post_ffw_norm.weight",
+            ]),
+            Self::Llama | Self::Phi3 | Self::Gemma => {}
+        }
+        tensors
+    }
+
+    fn is_gemma(&self) -> bool {
+        matches!(self, Self::Gemma | Self::Gemma2 | Self::Gemma3)
+    }
+
+    /// The gguf name for a hugging face tensor name, `None` for the tensors that are not part of
+    /// the converted model such as the vision tower of multimodal models.
+    pub fn tensor_name(&self, name: &str) -> Option<String> {
+        let name = name.strip_prefix("language_model.").unwrap_or(name);
+        let (base, suffix) = name.rsplit_once('.')?;
+        if !matches!(suffix, "weight" | "bias") {
+            return None;
+        }
+        let gguf = match base {
+            "model.embed_tokens" => "token_embd".to_string(),
+            "model.norm" => "output_norm".to_string(),
+            "lm_head" => "output".to_string(),
+            base => {
+                let rest = base.strip_prefix("model.layers.")?;
+                let (layer_idx, rest) = rest.split_once('.')?;
+                let layer_idx: usize = layer_idx.parse().ok()?;
+                let gemma2 = matches!(self, Self::Gemma2 | Self::Gemma3);
+                let tensor = match rest {
+                    "input_layernorm" => "attn_norm",
+                    "post_attention_layernorm" if gemma2 => "post_attention_norm",
+                    "post_attention_layernorm" => "ffn_norm",
+                    "pre_feedforward_layernorm" => "ffn_norm",
+                    "post_feedforward_layernorm" => "post_ffw_norm",
+                    "self_attn.q_proj" => "attn_q",
+                    "self_attn.k_proj" => "attn_k",
+                    "self_attn.v_proj" => "attn_v",
+                    "self_attn.qkv_proj" => "attn_qkv",
+                    "self_attn.o_proj" => "attn_output",
+                    "self_attn.q_norm" => "attn_q_norm",
+                    "self_attn.k_norm" => "attn_k_norm",
+                    "mlp.gate_proj" => "ffn_gate",
+                    "mlp.up_proj" => "ffn_up",
+                    // Phi3 fuses the gate and up projections.
+                    "mlp.gate_up_proj" => "ffn_up",
+                    "mlp.down_proj" => "ffn_down",
+                    _ => return None,
+                };
+                format!("blk.{layer_idx}.{tensor}")
+            }
+        };
+        Some(format!("{gguf}.{suffix}"))
+    }
+}
+
+// Reorders the rows of a query or key projection from the half-split rope layout used by the
+// hugging face llama implementation to the interleaved layout used in gguf files.
+fn permute_rope(xs: &Tensor, n_head: usize) -> Result<Tensor> {
+    let dims = xs.dims();
+    let head_dim = dims[0] / n_head;
+    let rest: usize = dims[1..].iter().product();
+    xs.reshape((n_head, 2, head_dim / 2, rest))?
+        .transpose(1, 2)?
+        .reshape(dims)
+}
+
+fn config_value<'a>(config: &'a serde_json::Value, key: &str) -> Option<&'a serde_json::Value> {
+    config.get(key).filter(|v| !v.is_null())
+}
+
+fn config_u32(config: &serde_json::Value, key: &str) -> Result<Option<u32>> {
+    match config_value(config, key) {
+        None => Ok(None),
+        Some(v) => match v.as_u64() {
+            Some(v) => Ok(Some(v as u32)),
+            None => bail!("unexpected value for {key} in config.json: {v}"),
+        },
+    }
+}
+
+fn config_f32(config: &serde_json::Value, key: &str) -> Result<Option<f32>> {
+    match config_value(config, key) {
+        None => Ok(None),
+        Some(v) => match v.as_f64() {
+            Some(v) => Ok(Some(v as f32)),
+            None => bail!("unexpected value for {key} in config.json: {v}"),
+        },
+    }
+}
+
+fn required_u32(config: &serde_json::Value, key: &str) -> Result<u32> {
+    match config_u32(config, key)? {
+        Some(v) => Ok(v),
+        None => bail!("missing {key} in config.json"),
+    }
+}
+
+// Multimodal models such as gemma3 nest the language model parameters in a text config.
+fn text_config(config: &serde_json::Value) -> &serde_json::Value {
+    config_value(config, "text_config").unwrap_or(config)
+}
+
+/// The `general.*` and `{arch}.*` metadata for a model described by a hugging face
+/// `config.json` file.
+pub fn metadata_from_config(config: &serde_json::Value) -> Result<Vec<(String, Value)>> {
+    let model_type = match config_value(config, "model_type").and_then(|v| v.as_str()) {
+        Some(model_type) => model_type,
+        None => bail!("missing model_type in config.json"),
+    };
+    let arch = Architecture::from_model_type(model_type)?;
+    let text = text_config(config);
+    let a = arch.gguf_name();
+    let n_head = required_u32(text, "num_attention_heads")?;
+    let hidden_size = required_u32(text, "hidden_size")?;
+    let head_dim = config_u32(text, "head_dim")?.unwrap_or(hidden_size / n_head);
+    let rope_dim = match config_f32(text, "partial_rotary_factor")? {
+        Some(factor) => (head_dim as f32 * factor) as u32,
+        None => head_dim,
+    };
+    let u32 = |k: &str, v: u32| (format!("{a}.{k}"), Value::U32(v));
+    let f32 = |k: &str, v: f32| (format!("{a}.{k}"), Value::F32(v));
+    let mut metadata = vec![
+        (
+            "general.architecture".to_string(),
+            Value::String(a.to_string()),
+        ),
+        u32("block_count", required_u32(text, "num_hidden_layers")?),
+        u32("embedding_length", hidden_size),
+        u32(
+            "feed_forward_length",
+            required_u32(text, "intermediate_size")?,
+        ),
+        u32("attention.head_count", n_head),
+        u32(
+            "attention.head_count_kv",
+            config_u32(text, "num_key_value_heads")?.unwrap_or(n_head),
+        ),
+        u32("attention.key_length", head_dim),
+        u32("attention.value_length", head_dim),
+        u32("rope.dimension_count", rope_dim),
+        f32(
+            "rope.freq_base",
+            config_f32(text, "rope_theta")?.unwrap_or(10000.),
+        ),
+    ];
+    if let Some(name) = config_value(config, "_name_or_path").and_then(|v| v.as_str()) {
+        metadata.push(("general.name".to_string(), Value::String(name.to_string())))
+    }
+    if let Some(context_length) = config_u32(text, "max_position_embeddings")? {
+        metadata.push(u32("context_length", context_length))
+    }
+    if let Some(eps) = config_f32(text, "rms_norm_eps")? {
+        metadata.push(f32("attention.layer_norm_rms_epsilon", eps))
+    }
+    if let Some(eps) = config_f32(text, "layer_norm_eps")? {
+        metadata.push(f32("attention.layer_norm_epsilon", eps))
+    }
+    if let Some(window) = config_u32(text, "sliding_window")? {
+        metadata.push(u32("attention.sliding_window", window))
+    }
+    if let Some(pattern) = config_u32(text, "sliding_window_pattern")? {
+        metadata.push(u32("attention.sliding_window_type", pattern))
+    }
+    if let Some(freq_base) = config_f32(text, "rope_local_base_freq")? {
+        metadata.push(f32("rope.local_freq_base", freq_base))
+    }
+    for key in ["attn_logit_softcapping", "final_logit_softcapping"] {
+        if let Some(softcapping) = config_f32(text, key)? {
+            metadata.push(f32(key, softcapping))
+        }
+    }
+    Ok(metadata)
+}
+
+fn special_token(config: &serde_json::Value, key: &str) -> Option<String> {
+    match config_value(config, key)? {
+        serde_json::Value::String(s) => Some(s.clone()),
+        v => v.get("content")?.as_str().map(|s| s.to_string()),
+    }
+}
+
+fn is_byte_token(token: &str) -> bool {
+    token.len() == 6 && token.starts_with("<0x") && token.ends_with('>')
+}
+
+/// The `tokenizer.ggml.*` metadata for a BPE tokenizer in the hugging face `tokenizer.json`
+/// format. `tokenizer_config` provides the special tokens and the chat template, `pre` is the
+/// name of the pre-tokenizer regex for byte-level tokenizers, e.g. `llama-bpe` or `qwen2`.
+pub fn tokenizer_metadata(
+    tokenizer: &serde_json::Value,
+    tokenizer_config: Option<&serde_json::Value>,
+    pre: &str,
+) -> Result<Vec<(String, Value)>> {
+    let model = match tokenizer.get("model") {
+        Some(model) => model,
+        None => bail!("missing model in tokenizer.json"),
+    };
+    if model.get("type").and_then(|t| t.as_str()) != Some("BPE") {
+        bail!("only BPE tokenizers can be converted")
+    }
+    let vocab = match model.get("vocab").and_then(|v| v.as_object()) {
+        Some(vocab) => vocab,
+        None => bail!("missing model.vocab in tokenizer.json"),
+    };
+    let mut ids = HashMap::new();
+    for (token, id) in vocab.iter() {
+        match id.as_u64() {
+            Some(id) => ids.insert(id as usize, (token.clone(), TOKEN_TYPE_NORMAL)),
+            None => bail!("invalid id for token {token}"),
+        };
+    }
+    let added_tokens = tokenizer.get("added_tokens").and_then(|v| v.as_array());
+    for added in added_tokens.into_iter().flatten() {
+        let (id, content) = match (added.get("id"), added.get("content")) {
+            (Some(id), Some(content)) => match (id.as_u64(), content.as_str()) {
+                (Some(id), Some(content)) => (id as usize, content),
+                _ => bail!("invalid added token {added}"),
+            },
+            _ => bail!("invalid added token {added}"),
+        };
+        let special = added.get("special").and_then(|v| v.as_bool()) == Some(true);
+        let token_type = if special {
+            TOKEN_TYPE_CONTROL
+        } else {
+            TOKEN_TYPE_USER_DEFINED
+        };
+        ids.insert(id, (content.to_string(), token_type));
+    }
+    let unk_token = model.get("unk_token").and_then(|v| v.as_str());
+    let byte_fallback = model.get("byte_fallback").and_then(|v| v.as_bool()) == Some(true);
+    let n_tokens = ids.keys().max().map_or(0, |id| id + 1);
+    let mut tokens = Vec::with_capacity(n_tokens);
+    let mut token_types = Vec::with_capacity(n_tokens);
+    for id in 0..n_tokens {
+        let (token, token_type) = match ids.remove(&id) {
+            None => (format!("[PAD{id}]"), TOKEN_TYPE_UNUSED),
+            Some((token, _)) if Some(token.as_str()) == unk_token => (token, TOKEN_TYPE_UNKNOWN),
+            Some((token, TOKEN_TYPE_NORMAL)) if byte_fallback && is_byte_token(&token) => {
+                (token, TOKEN_TYPE_BYTE)
+            }
+            Some(v) => v,
+        };
+        tokens.push(Value::String(token));
+        token_types.push(Value::I32(token_type));
+    }
+    let merges = match model.get("merges").and_then(|v| v.as_array()) {
+        None => vec![],
+        Some(merges) => merges
+            .iter()
+            .map(|m| match m {
+                serde_json::Value::String(m) => Ok(Value::String(m.clone())),
+                serde_json::Value::Array(pair) => match pair.as_slice() {
+                    [serde_json::Value::String(l), serde_json::Value::String(r)] => {
+                        Ok(Value::String(format!("{l} {r}")))
+                    }
+                    _ => bail!("invalid merge {m}"),
+                },
+                _ => bail!("invalid merge {m}"),
+            })
+            .collect::<Result<Vec<_>>>()?,
+    };
+    // Byte-level tokenizers use the gpt2 scheme, the others are SentencePiece like.
+    let byte_level = serde_json::to_string(&tokenizer.get("pre_tokenizer"))
+        .map_err(Error::wrap)?
+        .contains("ByteLevel");
+    let key = |k: &str| format!("tokenizer.ggml.{k}");
+    let mut metadata = vec![];
+    if byte_level {
+        metadata.push((key("model"), Value::String("gpt2".to_string())));
+        metadata.push((key("pre"), Value::String(pre.to_string())));
+    } else {
+        metadata.push((key("model"), Value::String("llama".to_string())));
+        // The SentencePiece normalizer prepends a space to the input.
+        let normalizer =
+            serde_json::to_string(&tokenizer.get("normalizer")).map_err(Error::wrap)?;
+        let add_space_prefix = normalizer.contains("Prepend") || normalizer.contains("Metaspace");
+        metadata.push((key("add_space_prefix"), Value::Bool(add_space_prefix)));
+    }
+    let token_id = |token: &str| {
+        tokens
+            .iter()
+            .position(|t| matches!(t, Value::String(t) if t == token))
+    };
+    if let Some(config) = tokenizer_config {
+        for (name, gguf_key) in [
+            ("bos_token", "bos_token_id"),
+            ("eos_token", "eos_token_id"),
+            ("unk_token", "unknown_token_id"),
+            ("pad_token", "padding_token_id"),
+        ] {
+            if let Some(id) = special_token(config, name).and_then(|t| token_id(&t)) {
+                metadata.push((key(gguf_key), Value::U32(id as u32)))
+            }
+        }
+        for name in ["add_bos_token", "add_eos_token"] {
+            if let Some(v) = config_value(config, name).and_then(|v| v.as_bool()) {
+                metadata.push((key(name), Value::Bool(v)))
+            }
+        }
+        if let Some(template) = config_value(config, "chat_template").and_then(|v| v.as_str()) {
+            metadata.push((
+                "tokenizer.chat_template".to_string(),
+                Value::String(template.to_string()),
+            ))
+        }
+    }
+    metadata.push((key("tokens"), Value::Array(tokens)));
+    metadata.push((key("token_type"), Value::Array(token_types)));
+    if !merges.is_empty() {
+        metadata.push((key("merges"), Value::Array(merges)));
+    }
+    Ok(metadata)
+}
+
+/// A hugging face model directory to convert.
+pub struct HfModel {
+    arch: Architecture,
+    config: serde_json::Value,
+    metadata: Vec<(String, Value)>,
+    safetensors: candle::safetensors::MmapedSafetensors,
+    // The gguf names of the converted tensors together with their hugging face names.
+    tensor_names: Vec<(String, String)>,
+}
+
+impl HfModel {
+    /// Opens a model directory, the weights are memory mapped.
+    ///
+    /// # Safety
+    ///
+    /// The safetensors files must not be modified while the model is in use, see
+    /// [`candle::safetensors::MmapedSafetensors`].
+    pub unsafe fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let read_json = |name: &str| -> Result<Option<serde_json::Value>> {
+            let path = dir.join(name);
+            if !path.exists() {
+                return Ok(None);
+            }
+            let content = std::fs::read_to_string(&path)?;
+            let value =
+                serde_json::from_str(&content).map_err(|e| Error::wrap(e).with_path(path))?;
+            Ok(Some(value))
+        };
+        let config = match read_json("config.json")? {
+            Some(config) => config,
+            None => bail!("no config.json in {dir:?}"),
+        };
+        let mut weights = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "safetensors"))
+            .collect::<Vec<PathBuf>>();
+        if weights.is_empty() {
+            bail!("no safetensors files in {dir:?}")
+        }
+        weights.sort();
+        let safetensors = candle::safetensors::MmapedSafetensors::multi(&weights)?;
+
+        let mut metadata = metadata_from_config(&config)?;
+        let arch = match metadata.first() {
+            Some((_, Value::String(arch))) => Architecture::from_model_type(arch)?,
+            _ => bail!("missing architecture"),
+        };
+        if let Some(tokenizer) = read_json("tokenizer.json")? {
+            let tokenizer_config = read_json("tokenizer_config.json")?;
+            let pre = match arch {
+                Architecture::Llama => "llama-bpe",
+                Architecture::Qwen2 | Architecture::Qwen3 => "qwen2",
+                _ => "default",
+            };
+            metadata.extend(tokenizer_metadata(
+                &tokenizer,
+                tokenizer_config.as_ref(),
+                pre,
+            )?)
+        }
+        let mut tensor_names = safetensors
+            .tensors()
+            .into_iter()
+            .filter_map(|(name, _)| arch.tensor_name(&name).map(|gguf| (gguf, name)))
+            .collect::<Vec<_>>();
+        tensor_names.sort();
+        Ok(Self {
+            arch,
+            config,
+            metadata,
+            safetensors,
+            tensor_names,
+        })
+    }
+
+    pub fn architecture(&self) -> Architecture {
+        self.arch
+    }
+
+    /// The gguf metadata, hyper-parameters and tokenizer.
+    pub fn metadata(&self) -> &[(String, Value)] {
+        &self.metadata
+    }
+
+    /// The gguf names of the tensors of the converted model.
+    pub fn tensor_names(&self) -> impl Iterator<Item = &str> {
+        self.tensor_names.iter().map(|(gguf, _)| gguf.as_str())
+    }
+
+    /// The hugging face name of a tensor using its gguf name.
+    pub fn hf_name(&self, name: &str) -> Result<&str> {
+        match self.tensor_names.iter().find(|(gguf, _)| gguf == name) {
+            Some((_, hf_name)) => Ok(hf_name),
+            None => bail!("cannot find tensor {name}"),
+        }
+    }
+
+    /// The memory mapped weights, the tensors use their hugging face names and layout.
+    pub fn safetensors(&self) -> &candle::safetensors::MmapedSafetensors {
+        &self.safetensors
+    }
+
+    fn n_heads(&self) -> Result<(usize, usize)> {
+        let text = text_config(&self.config);
+        let n_head = required_u32(text, "num_attention_heads")?;
+        let n_kv_head = config_u32(text, "num_key_value_heads")?.unwrap_or(n_head);
+        Ok((n_head as usize, n_kv_head as usize))
+    }
+
+    /// Loads a tensor using its gguf name, as a f32 tensor on the cpu with the gguf layout.
+    pub fn tensor(&self, name: &str) -> Result<Tensor> {
+        let xs = self
+            .safetensors
+            .load(self.hf_name(name)?, &Device::Cpu)?
+            .to_dtype(DType::F32)?;
+        let (n_head, n_kv_head) = self.n_heads()?;
+        let xs = match self.arch {
+            Architecture::Llama if name.contains(".attn_q.") => permute_rope(&xs, n_head)?,
+            Architecture::Llama if name.contains(".attn_k.") => permute_rope(&xs, n_kv_head)?,
+            // The gguf gemma norms include the +1 offset applied by the hugging face models.
+            arch if arch.is_gemma() && name.ends_with("norm.weight") => (xs + 1.)?,
+            _ => xs,
+        };
+        Ok(xs)
+    }
+
+    /// Converts all the tensors using `quantize` and writes the resulting gguf file.
+    pub fn write_gguf<W, F>(&self, w: &mut W, quantize: F) -> Result<()>
+    where
+        W: std::io::Seek + std::io::Write,
+        F: Fn(&str, Tensor) -> Result<QTensor> + Sync,
+    {
+        let qtensors = self
+            .tensor_names
+            .par_iter()
+            .map(|(name, _)| {
+                let tensor = self.tensor(name)?;
+                Ok((name.as_str(), quantize(name, tensor)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let qtensors = qtensors.iter().map(|(k, v)| (*k, v)).collect::<Vec<_>>();
+        let metadata = self
+            .metadata
+            .iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect::<Vec<_>>();
+        gguf_file::write(w, &metadata, &qtensors)
+    }
+}
+
+/// The outcome of [`validate_gguf`].
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// The problems that prevent the quantized model from loading the file.
+    pub errors: Vec<String>,
+    /// The unexpected content that the quantized model ignores.
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Checks the metadata and tensors of a gguf file against the expectations of the quantized
+/// model for `arch`, this includes the hyper-parameters, the tensor names of each layer and the
+/// shapes of the embeddings and norms.
+pub fn validate_gguf(content: &gguf_file::Content, arch: Architecture) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let prefix = arch.gguf_name();
+    match content.metadata.get("general.architecture") {
+        Some(Value::String(name)) if name == prefix => {}
+        Some(name) => report.errors.push(format!(
+            "general.architecture is {name:?}, expected {prefix}"
+        )),
+        None => report
+            .errors
+            .push("missing general.architecture".to_string()),
+    }
+    for key in arch.required_metadata() {
+        // The quantized models read the epsilons and rope frequencies as f32, the other
+        // hyper-parameters as u32.
+        let is_f32 = key.ends_with("epsilon") || key.ends_with("freq_base");
+        let key = format!("{prefix}.{key}");
+        match content.metadata.get(&key) {
+            None => report.errors.push(format!("missing metadata {key}")),
+            Some(v) if is_f32 && v.to_f32().is_err() => report
+                .errors
+                .push(format!("expected a f32 for {key}, got {v:?}")),
+            Some(v) if !is_f32 && v.to_u32().is_err() => report
+                .errors
+                .push(format!("expected a u32 for {key}, got {v:?}")),
+            Some(_) => {}
+        }
+    }
+    let md_u32 = |key: &str| {
+        content
+            .metadata
+            .get(&format!("{prefix}.{key}"))
+            .and_then(|v| v.to_u32().ok())
+            .map(|v| v as usize)
+    };
+
+    let mut expected = vec![
+        "token_embd.weight".to_string(),
+        "output_norm.weight".to_string(),
+    ];
+    // The output projection is optional except for phi3, the other models tie it with the token
+    // embeddings.
+    if arch == Architecture::Phi3 {
+        expected.push("output.weight".to_string())
+    }
+    let block_count = md_u32("block_count").unwrap_or(0);
+    // The llama mixture of experts models use one feed-forward network per expert.
+    let n_expert = match arch {
+        Architecture::Llama => md_u32("expert_count").unwrap_or(0),
+        _ => 0,
+    };
+    for layer_idx in 0..block_count {
+        for tensor in arch.layer_tensors() {
+            match tensor.strip_suffix(".weight") {
+                Some(ffn @ ("ffn_gate" | "ffn_up" | "ffn_down")) if n_expert > 0 => {
+                    for i in 0..n_expert {
+                        expected.push(format!("blk.{layer_idx}.{ffn}.{i}.weight"))
+                    }
+                }
+                _ => expected.push(format!("blk.{layer_idx}.{tensor}")),
+            }
+        }
+        if n_expert > 0 {
+            expected.push(format!("blk.{layer_idx}.ffn_gate_inp.weight"))
+        }
+    }
+    for name in expected.iter() {
+        if !content.tensor_infos.contains_key(name) {
+            report.errors.push(format!("missing tensor {name}"))
+        }
+    }
+    let mut unexpected = content
+        .tensor_infos
+        .keys()
+        .filter(|name| !expected.contains(name) && name.as_str() != "output.weight")
+        .collect::<Vec<_>>();
+    unexpected.sort();
+    for name in unexpected {
+        report.warnings.push(format!("unexpected tensor {name}"))
+    }
+
+    if let Some(embedding_length) = md_u32("embedding_length") {
+        // The query and key norms apply to each head.
+        let norms = content.tensor_infos.iter().filter(|(name, _)| {
+            name.ends_with("_norm.weight")
+                && !name.ends_with("attn_q_norm.weight")
+                && !name.ends_with("attn_k_norm.weight")
+        });
+        for (name, info) in norms {
+            if info.shape.dims() != [embedding_length] {
+                report.errors.push(format!(
+                    "unexpected shape {:?} for {name}, expected [{embedding_length}]",
+                    info.shape
+                ))
+            }
+        }
+        if let Some(info) = content.tensor_infos.get("token_embd.weight") {
+            match info.shape.dims() {
+                &[vocab_size, hidden] if hidden == embedding_length => {
+                    let n_tokens = content
+                        .metadata
+                        .get("tokenizer.ggml.tokens")
+                        .and_then(|v| v.to_vec().ok())
+                        .map(|v| v.len());
+                    if let Some(n_tokens) = n_tokens.filter(|&n| n > vocab_size) {
+                        report.errors.push(format!(
+                            "the tokenizer has {n_tokens} tokens for a vocabulary of {vocab_size}"
+                        ))
+                    }
+                }
+                _ => report.errors.push(format!(
+                    "unexpected shape {:?} for token_embd.weight, expected [_, {embedding_length}]",
+                    info.shape
+                )),
+            }
+        }
+    }
+    report
+}
+
+/// The default conversion, `f16` for the matrixes and `f32` for the other tensors.
+pub fn default_quantize(_name: &str, tensor: Tensor) -> Result<QTensor> {
+    let dtype = if tensor.rank() == 2 {
+        GgmlDType::F16
+    } else {
+        GgmlDType::F32
+    };
+    QTensor::quantize(&tensor, dtype)
+}
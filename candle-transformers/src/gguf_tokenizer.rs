@@ -0,0 +1,298 @@
+//! Tokenizers reconstructed from the `tokenizer.ggml.*` gguf metadata.
+//!
+//! This makes a single gguf file enough to run a model, without downloading a separate
+//! `tokenizer.json`. Both byte-level BPE tokenizers (`tokenizer.ggml.model = gpt2`, as used by
+//! llama-3, qwen, mistral-nemo, ...) and SentencePiece BPE tokenizers
+//! (`tokenizer.ggml.model = llama`, as used by llama-2, mistral, gemma, ...) are supported.
+use candle::quantized::gguf_file;
+use candle::{bail, Error, Result};
+use std::collections::HashMap;
+use tokenizers::decoders::byte_fallback::ByteFallback;
+use tokenizers::decoders::byte_level::ByteLevel;
+use tokenizers::decoders::fuse::Fuse;
+use tokenizers::decoders::sequence::Sequence as DecoderSequence;
+use tokenizers::decoders::strip::Strip;
+use tokenizers::models::bpe::{Vocab, BPE};
+use tokenizers::normalizers::{Prepend, Replace, Sequence as NormalizerSequence};
+use tokenizers::pre_tokenizers::sequence::Sequence as PreTokenizerSequence;
+use tokenizers::pre_tokenizers::split::Split;
+use tokenizers::processors::template::TemplateProcessing;
+use tokenizers::{AddedToken, DecoderWrapper, NormalizerWrapper, PreTokenizerWrapper};
+use tokenizers::{SplitDelimiterBehavior, Tokenizer};
+
+// The values of `tokenizer.ggml.token_type`.
+const TOKEN_TYPE_UNKNOWN: i32 = 2;
+const TOKEN_TYPE_CONTROL: i32 = 3;
+const TOKEN_TYPE_USER_DEFINED: i32 = 4;
+const TOKEN_TYPE_UNUSED: i32 = 5;
+const TOKEN_TYPE_BYTE: i32 = 6;
+
+// The splitting regexes for the `tokenizer.ggml.pre` values, gpt2 is used by default.
+const GPT2_PATTERN: &str =
+    r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+const LLAMA3_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const QWEN2_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+/// A tokenizer together with the special tokens from the gguf metadata.
+#[derive(Debug, Clone)]
+pub struct GgufTokenizer {
+    pub tokenizer: Tokenizer,
+    pub bos_token_id: Option<u32>,
+    pub eos_token_id: Option<u32>,
+    /// Whether a bos token is prepended when encoding with `add_special_tokens`.
+    pub add_bos_token: bool,
+}
+
+struct Metadata<'a>(&'a HashMap<String, gguf_file::Value>);
+
+impl Metadata<'_> {
+    fn get(&self, key: &str) -> Option<&gguf_file::Value> {
+        self.0.get(&format!("tokenizer.ggml.{key}"))
+    }
+
+    fn u32(&self, key: &str) -> Result<Option<u32>> {
+        self.get(key).map(|v| v.to_u32()).transpose()
+    }
+
+    fn bool(&self, key: &str) -> Result<Option<bool>> {
+        self.get(key).map(|v| v.to_bool()).transpose()
+    }
+
+    fn strings(&self, key: &str) -> Result<Option<Vec<String>>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(v) => {
+                let v = v.to_vec()?;
+                let v = v.iter().map(|v| v.to_string().cloned());
+                Ok(Some(v.collect::<Result<Vec<_>>>()?))
+            }
+        }
+    }
+}
+
+impl GgufTokenizer {
+    pub fn from_gguf(content: &gguf_file::Content) -> Result<Self> {
+        let metadata = Metadata(&content.metadata);
+        let model = match metadata.get("model") {
+            None => bail!("missing tokenizer.ggml.model in gguf metadata"),
+            Some(model) => model.to_string()?.clone(),
+        };
+        let tokens = match metadata.strings("tokens")? {
+            None => bail!("missing tokenizer.ggml.tokens in gguf metadata"),
+            Some(tokens) => tokens,
+        };
+        let token_types = match metadata.get("token_type") {
+            None => vec![1; tokens.len()],
+            Some(v) => v
+                .to_vec()?
+                .iter()
+                .map(|v| v.to_i32())
+                .collect::<Result<_>>()?,
+        };
+        if token_types.len() != tokens.len() {
+            bail!(
+                "tokenizer.ggml.token_type has {} entries, expected {}",
+                token_types.len(),
+                tokens.len()
+            )
+        }
+        let bos_token_id = metadata.u32("bos_token_id")?;
+        let eos_token_id = metadata.u32("eos_token_id")?;
+        let unk_token_id = metadata.u32("unknown_token_id")?;
+        let token = |id: Option<u32>| -> Result<Option<&str>> {
+            match id {
+                None => Ok(None),
+                Some(id) => match tokens.get(id as usize) {
+                    None => bail!("special token {id} is out of the vocabulary"),
+                    Some(token) => Ok(Some(token.as_str())),
+                },
+            }
+        };
+        let bos_token = token(bos_token_id)?;
+
+        let vocab: Vocab = tokens
+            .iter()
+            .enumerate()
+            .map(|(id, token)| (token.clone(), id as u32))
+            .collect();
+        let (mut tokenizer, default_add_bos) = match model.as_str() {
+            "gpt2" => {
+                let merges = match metadata.strings("merges")? {
+                    None => bail!("missing tokenizer.ggml.merges for a gpt2 tokenizer"),
+                    Some(merges) => parse_merges(&merges)?,
+                };
+                let bpe = BPE::builder()
+                    .vocab_and_merges(vocab, merges)
+                    .build()
+                    .map_err(Error::wrap)?;
+                let pre = metadata.get("pre").map(|v| v.to_string()).transpose()?;
+                let pattern = match pre.map(|s| s.as_str()) {
+                    Some("llama3" | "llama-bpe" | "smaug-bpe" | "falcon3" | "pixtral") => {
+                        LLAMA3_PATTERN
+                    }
+                    Some("qwen2" | "deepseek-r1-qwen") => QWEN2_PATTERN,
+                    _ => GPT2_PATTERN,
+                };
+                let split = Split::new(pattern, SplitDelimiterBehavior::Isolated, false)
+                    .map_err(Error::wrap)?;
+                let pre_tokenizer = PreTokenizerSequence::new(vec![
+                    PreTokenizerWrapper::Split(split),
+                    PreTokenizerWrapper::ByteLevel(ByteLevel::new(false, false, false)),
+                ]);
+                let mut tokenizer = Tokenizer::new(bpe);
+                tokenizer
+                    .with_pre_tokenizer(Some(pre_tokenizer))
+                    .with_decoder(Some(ByteLevel::default()));
+                (tokenizer, false)
+            }
+            "llama" => {
+                let merges = match metadata.strings("merges")? {
+                    Some(merges) => parse_merges(&merges)?,
+                    None => {
+                        let scores = match metadata.get("scores") {
+                            None => bail!("missing tokenizer.ggml.scores for a llama tokenizer"),
+                            Some(v) => v.to_vec()?.iter().map(|v| v.to_f32()),
+                        };
+                        let scores = scores.collect::<Result<Vec<_>>>()?;
+                        sentencepiece_merges(&tokens, &scores, &token_types)
+                    }
+                };
+                let mut bpe = BPE::builder()
+                    .vocab_and_merges(vocab, merges)
+                    .byte_fallback(true)
+                    .fuse_unk(true);
+                if let Some(unk) = token(unk_token_id)? {
+                    bpe = bpe.unk_token(unk.to_string())
+                }
+                let bpe = bpe.build().map_err(Error::wrap)?;
+                let mut normalizers = vec![];
+                if metadata.bool("add_space_prefix")?.unwrap_or(true) {
+                    normalizers.push(NormalizerWrapper::Prepend(Prepend::new("▁".to_string())))
+                }
+                let replace = Replace::new(" ", "▁").map_err(Error::wrap)?;
+                normalizers.push(NormalizerWrapper::Replace(replace));
+                let replace = Replace::new("▁", " ").map_err(Error::wrap)?;
+                let mut decoders = vec![
+                    DecoderWrapper::Replace(replace),
+                    DecoderWrapper::ByteFallback(ByteFallback::new()),
+                    DecoderWrapper::Fuse(Fuse::new()),
+                ];
+                if metadata.bool("add_space_prefix")?.unwrap_or(true) {
+                    decoders.push(DecoderWrapper::Strip(Strip::new(' ', 1, 0)))
+                }
+                let mut tokenizer = Tokenizer::new(bpe);
+                tokenizer
+                    .with_normalizer(Some(NormalizerSequence::new(normalizers)))
+                    .with_decoder(Some(DecoderSequence::new(decoders)));
+                (tokenizer, true)
+            }
+            model => bail!("unsupported gguf tokenizer model {model}"),
+        };
+
+        // Control tokens are matched in the input text before the tokenization proper.
+        let added_tokens = tokens
+            .iter()
+            .zip(token_types.iter())
+            .filter_map(|(token, &token_type)| match token_type {
+                TOKEN_TYPE_CONTROL | TOKEN_TYPE_UNKNOWN => Some(AddedToken::from(token, true)),
+                TOKEN_TYPE_USER_DEFINED => Some(AddedToken::from(token, false).normalized(false)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        tokenizer.add_special_tokens(&added_tokens);
+
+        let add_bos_token = metadata.bool("add_bos_token")?.unwrap_or(default_add_bos);
+        let add_eos_token = metadata.bool("add_eos_token")?.unwrap_or(false);
+        let add_bos = bos_token.filter(|_| add_bos_token).zip(bos_token_id);
+        let add_eos = token(eos_token_id)?
+            .filter(|_| add_eos_token)
+            .zip(eos_token_id);
+        if add_bos.is_some() || add_eos.is_some() {
+            let (mut single, mut pair) = (vec!["$A:0".to_string()], vec!["$B:1".to_string()]);
+            let mut special_tokens = vec![];
+            if let Some((bos, id)) = add_bos {
+                single.insert(0, bos.to_string());
+                pair.insert(0, format!("{bos}:1"));
+                special_tokens.push((bos.to_string(), id));
+            }
+            if let Some((eos, id)) = add_eos {
+                single.push(eos.to_string());
+                pair.push(format!("{eos}:1"));
+                special_tokens.push((eos.to_string(), id));
+            }
+            let mut pair_template = single.clone();
+            pair_template.extend(pair);
+            let post_processor = TemplateProcessing::builder()
+                .try_single(single.join(" "))
+                .map_err(Error::wrap)?
+                .try_pair(pair_template.join(" "))
+                .map_err(Error::wrap)?
+                .special_tokens(special_tokens)
+                .build()
+                .map_err(Error::wrap)?;
+            tokenizer.with_post_processor(Some(post_processor));
+        }
+        Ok(Self {
+            tokenizer,
+            bos_token_id,
+            eos_token_id,
+            add_bos_token: add_bos.is_some(),
+        })
+    }
+
+    pub fn into_tokenizer(self) -> Tokenizer {
+        self.tokenizer
+    }
+}
+
+fn parse_merges(merges: &[String]) -> Result<Vec<(String, String)>> {
+    merges
+        .iter()
+        .map(|merge| match merge.split_once(' ') {
+            None => bail!("invalid merge {merge:?}"),
+            Some((left, right)) => Ok((left.to_string(), right.to_string())),
+        })
+        .collect()
+}
+
+// SentencePiece models only store scores, the merges are recovered from the vocabulary: any
+// token that can be split in two tokens of the vocabulary results in a merge, merges that
+// produce the tokens with the highest scores are applied first.
+fn sentencepiece_merges(
+    tokens: &[String],
+    scores: &[f32],
+    token_types: &[i32],
+) -> Vec<(String, String)> {
+    let is_piece = |id: usize| {
+        !matches!(
+            token_types[id],
+            TOKEN_TYPE_CONTROL | TOKEN_TYPE_UNKNOWN | TOKEN_TYPE_UNUSED | TOKEN_TYPE_BYTE
+        )
+    };
+    let ids = tokens
+        .iter()
+        .enumerate()
+        .filter(|(id, _)| is_piece(*id))
+        .map(|(id, token)| (token.as_str(), id))
+        .collect::<HashMap<_, _>>();
+    let mut merges = vec![];
+    for (token, &id) in ids.iter() {
+        for (split, _) in token.char_indices().skip(1) {
+            let (left, right) = token.split_at(split);
+            if let (Some(&l), Some(&r)) = (ids.get(left), ids.get(right)) {
+                let score = scores.get(id).copied().unwrap_or(0.);
+                merges.push((score, id, l, r))
+            }
+        }
+    }
+    merges.sort_by(|a, b| {
+        b.0.total_cmp(&a.0)
+            .then(a.1.cmp(&b.1))
+            .then(a.2.cmp(&b.2))
+            .then(a.3.cmp(&b.3))
+    });
+    merges
+        .into_iter()
+        .map(|(_, _, l, r)| (tokens[l].clone(), tokens[r].clone()))
+        .collect()
+}
@@ -1,8 +1,13 @@
+pub mod chat_template;
 pub mod fused_moe;
 pub mod generation;
+pub mod gguf_convert;
+#[cfg(feature = "tokenizers")]
+pub mod gguf_tokenizer;
 pub mod models;
 pub mod object_detection;
 pub mod pipelines;
 pub mod quantized_nn;
+pub mod quantized_safetensors;
 pub mod quantized_var_builder;
 pub mod utils;
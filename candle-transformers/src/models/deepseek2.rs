@@ -53,6 +53,7 @@ impl CustomOp1 for NonZero {
             candle::CpuStorage::F32(vs) => self.nonzero(vs, layout),
             candle::CpuStorage::F64(vs) => self.nonzero(vs, layout),
             candle::CpuStorage::F8E4M3(vs) => self.nonzero(vs, layout),
+            candle::CpuStorage::F8E5M2(vs) => self.nonzero(vs, layout),
             // Dummy types don't support nonzero operation
             candle::CpuStorage::F6E2M3(_) => {
                 return Err(
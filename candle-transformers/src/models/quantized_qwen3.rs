@@ -237,6 +237,10 @@ impl AttentionWeights {
     fn clear_kv_cache(&mut self) {
         self.kv_cache.reset();
     }
+
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.kv_cache.truncate(seq_len)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -291,6 +295,10 @@ impl LayerWeights {
     fn clear_kv_cache(&mut self) {
         self.self_attn.clear_kv_cache();
     }
+
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.self_attn.truncate_kv_cache(seq_len)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -407,7 +415,9 @@ impl ModelWeights {
         Tensor::from_slice(&mask, (b, 1, tgt, tgt + offset), &self.device)?.to_dtype(self.dtype)
     }
 
-    pub fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+    /// Returns the final hidden states for every position, shape `(b, l, hidden_size)`, e.g. to
+    /// compute embeddings.
+    pub fn forward_hidden(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
         let _enter = self.span.enter();
         let (b, l) = input.dims2()?;
         let mut h = self.embed_tokens.forward(input)?;
@@ -419,15 +429,35 @@ impl ModelWeights {
         for layer in &mut self.layers {
             h = layer.forward(&h, causal_mask.as_ref(), offset)?;
         }
-        let h = self.norm.forward(&h)?;
+        self.norm.forward(&h)
+    }
+
+    pub fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        let (_, l) = input.dims2()?;
+        let h = self.forward_hidden(input, offset)?;
         let _enter = self.span_output.enter();
         let last_hidden = h.narrow(1, l - 1, 1)?;
         self.lm_head.forward(&last_hidden)?.squeeze(1)
     }
 
+    /// Same as `forward` but returns the logits for every position, shape `(b, l, vocab_size)`.
+    pub fn forward_all(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        let h = self.forward_hidden(input, offset)?;
+        let _enter = self.span_output.enter();
+        self.lm_head.forward(&h)
+    }
+
     pub fn clear_kv_cache(&mut self) {
         for layer in &mut self.layers {
             layer.clear_kv_cache();
         }
     }
+
+    /// Drops the cached keys and values past `seq_len`, e.g. after rejected draft tokens.
+    pub fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        for layer in &mut self.layers {
+            layer.truncate_kv_cache(seq_len)?;
+        }
+        Ok(())
+    }
 }
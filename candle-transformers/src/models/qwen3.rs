@@ -3,7 +3,10 @@ use crate::{
     utils::repeat_kv,
 };
 use candle::{DType, Device, Module, Result, Tensor};
-use candle_nn::{kv_cache::ConcatKvCache, Activation, VarBuilder};
+use candle_nn::{
+    kv_cache::{ConcatKvCache, IndicesAndMask, ScatteredCacheBuilder, ScatteredKvCache},
+    Activation, VarBuilder,
+};
 use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq, serde::Deserialize)]
@@ -61,6 +64,42 @@ impl Qwen3RotaryEmbedding {
         let k_embed = candle_nn::rotary_emb::rope(&k.contiguous()?, &cos, &sin)?;
         Ok((q_embed, k_embed))
     }
+
+    /// Apply RoPE with a different start position for each batch element.
+    pub(crate) fn apply_positions(
+        &self,
+        q: &Tensor,
+        k: &Tensor,
+        positions: &[usize],
+    ) -> Result<(Tensor, Tensor)> {
+        let (b, _, seq_len, _) = q.dims4()?;
+        let max_position_embeddings = self.cos.dim(0)?;
+        if let Some(p) = positions
+            .iter()
+            .find(|&&p| p + seq_len > max_position_embeddings)
+        {
+            candle::bail!(
+                "positions {p}..{} exceed max_position_embeddings {max_position_embeddings}",
+                p + seq_len
+            )
+        }
+        let indices = positions
+            .iter()
+            .flat_map(|&p| (p..p + seq_len).map(|p| p as u32))
+            .collect::<Vec<_>>();
+        let indices = Tensor::new(indices, q.device())?;
+        let cos = self
+            .cos
+            .index_select(&indices, 0)?
+            .reshape((b, seq_len, ()))?;
+        let sin = self
+            .sin
+            .index_select(&indices, 0)?
+            .reshape((b, seq_len, ()))?;
+        let q_embed = candle_nn::rotary_emb::rope(&q.contiguous()?, &cos, &sin)?;
+        let k_embed = candle_nn::rotary_emb::rope(&k.contiguous()?, &cos, &sin)?;
+        Ok((q_embed, k_embed))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -178,12 +217,8 @@ impl Qwen3Attention {
         })
     }
 
-    pub(crate) fn forward(
-        &mut self,
-        x: &Tensor,
-        attn_mask: Option<&Tensor>,
-        offset: usize,
-    ) -> Result<Tensor> {
+    // Projects the input and applies the per-head norms, returns q, k, v with shape (B, H, L, D).
+    fn qkv(&self, x: &Tensor) -> Result<(Tensor, Tensor, Tensor)> {
         let (b, l, _) = x.dims3()?;
 
         // 1. Proj
@@ -209,12 +244,18 @@ impl Qwen3Attention {
         let k_flat = self.k_norm.forward(&k_flat)?;
         let q = q_flat.reshape((b, self.num_heads, l, self.head_dim))?;
         let k = k_flat.reshape((b, self.num_kv_heads, l, self.head_dim))?;
+        Ok((q, k, v))
+    }
 
-        // 4. RoPE
-        let (q, k) = self.rotary_emb.apply(&q, &k, offset)?;
-
-        // 5. Accumulate KV cache
-        let (k, v) = self.kv_cache.append(&k, &v)?;
+    // Attention over the full keys and values followed by the output projection.
+    fn attend(
+        &self,
+        q: &Tensor,
+        k: Tensor,
+        v: Tensor,
+        attn_mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        let (b, _, l, _) = q.dims4()?;
 
         // 6. GQA repeat_kv
         let k = repeat_kv(k, self.num_kv_groups)?.contiguous()?;
@@ -235,9 +276,45 @@ impl Qwen3Attention {
             .apply(&self.o_proj)
     }
 
+    pub(crate) fn forward(
+        &mut self,
+        x: &Tensor,
+        attn_mask: Option<&Tensor>,
+        offset: usize,
+    ) -> Result<Tensor> {
+        let (q, k, v) = self.qkv(x)?;
+
+        // 4. RoPE
+        let (q, k) = self.rotary_emb.apply(&q, &k, offset)?;
+
+        // 5. Accumulate KV cache
+        let (k, v) = self.kv_cache.append(&k, &v)?;
+
+        self.attend(&q, k, v, attn_mask)
+    }
+
+    // Same as `forward` but using an external scattered cache, each batch element having its
+    // own start position.
+    fn forward_scattered(
+        &self,
+        x: &Tensor,
+        positions: &[usize],
+        iam: &IndicesAndMask,
+        kv_cache: &mut ScatteredKvCache,
+    ) -> Result<Tensor> {
+        let (q, k, v) = self.qkv(x)?;
+        let (q, k) = self.rotary_emb.apply_positions(&q, &k, positions)?;
+        let (k, v) = kv_cache.append(&k.contiguous()?, &v.contiguous()?, iam)?;
+        self.attend(&q, k, v, Some(iam.mask()))
+    }
+
     pub(crate) fn clear_kv_cache(&mut self) {
         self.kv_cache.reset();
     }
+
+    pub(crate) fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.kv_cache.truncate(seq_len)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -278,6 +355,27 @@ impl DecoderLayer {
     fn clear_kv_cache(&mut self) {
         self.self_attn.clear_kv_cache();
     }
+
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.self_attn.truncate_kv_cache(seq_len)
+    }
+
+    fn forward_scattered(
+        &self,
+        x: &Tensor,
+        positions: &[usize],
+        iam: &IndicesAndMask,
+        kv_cache: &mut ScatteredKvCache,
+    ) -> Result<Tensor> {
+        let h = self.ln1.forward(x)?;
+        let h = self
+            .self_attn
+            .forward_scattered(&h, positions, iam, kv_cache)?;
+        let x = (x + h)?;
+        let h2 = self.ln2.forward(&x)?;
+        let h2 = h2.apply(&self.mlp)?;
+        x + h2
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -285,6 +383,7 @@ pub struct Model {
     embed_tokens: candle_nn::Embedding,
     layers: Vec<DecoderLayer>,
     norm: RmsNorm,
+    max_position_embeddings: usize,
     device: Device,
     dtype: DType,
 }
@@ -303,6 +402,7 @@ impl Model {
             embed_tokens,
             layers,
             norm: RmsNorm::new(cfg.hidden_size, cfg.rms_norm_eps, vb.pp("model.norm"))?,
+            max_position_embeddings: cfg.max_position_embeddings,
             device: vb.device().clone(),
             dtype: vb.dtype(),
         })
@@ -314,6 +414,13 @@ impl Model {
         }
     }
 
+    fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        for l in &mut self.layers {
+            l.truncate_kv_cache(seq_len)?;
+        }
+        Ok(())
+    }
+
     fn causal_mask(
         &self,
         b: usize,
@@ -356,6 +463,45 @@ impl Model {
         }
         self.norm.forward(&h)
     }
+
+    pub fn max_position_embeddings(&self) -> usize {
+        self.max_position_embeddings
+    }
+
+    /// Allocates one scattered kv-cache per layer, to be used with `forward_scattered`.
+    pub fn make_scattered_caches(
+        &self,
+        builder: &ScatteredCacheBuilder,
+    ) -> Result<Vec<ScatteredKvCache>> {
+        self.layers
+            .iter()
+            .map(|l| builder.make_cache(l.self_attn.num_kv_heads, l.self_attn.head_dim))
+            .collect()
+    }
+
+    /// Runs a batch where each element has its own position in the stream, `positions` being the
+    /// position of the first token of each element. The indices and mask are obtained from the
+    /// `ScatteredCacheBuilder` that created `kv_caches`.
+    pub fn forward_scattered(
+        &self,
+        input: &Tensor,
+        positions: &[usize],
+        iam: &IndicesAndMask,
+        kv_caches: &mut [ScatteredKvCache],
+    ) -> Result<Tensor> {
+        if kv_caches.len() != self.layers.len() {
+            candle::bail!(
+                "expected {} kv-caches, got {}",
+                self.layers.len(),
+                kv_caches.len()
+            )
+        }
+        let mut h = self.embed_tokens.forward(input)?;
+        for (layer, kv_cache) in self.layers.iter().zip(kv_caches.iter_mut()) {
+            h = layer.forward_scattered(&h, positions, iam, kv_cache)?;
+        }
+        self.norm.forward(&h)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -375,6 +521,32 @@ impl ModelForCausalLM {
         Ok(Self { base, lm_head })
     }
 
+    /// A tiny model with random weights and a vocabulary of 32 tokens, used to test the code
+    /// built on top of the models.
+    #[doc(hidden)]
+    pub fn tiny_random(dtype: DType, device: &Device) -> Result<Self> {
+        let cfg = Config {
+            vocab_size: 32,
+            hidden_size: 16,
+            intermediate_size: 32,
+            num_hidden_layers: 2,
+            num_attention_heads: 4,
+            head_dim: 8,
+            attention_bias: false,
+            num_key_value_heads: 2,
+            max_position_embeddings: 64,
+            sliding_window: None,
+            max_window_layers: 2,
+            tie_word_embeddings: true,
+            rope_theta: 10000.,
+            rms_norm_eps: 1e-6,
+            use_sliding_window: false,
+            hidden_act: Activation::Silu,
+        };
+        let varmap = candle_nn::VarMap::new();
+        Self::new(&cfg, VarBuilder::from_varmap(&varmap, dtype, device))
+    }
+
     pub fn forward(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
         let (_, l) = input.dims2()?;
         self.base
@@ -383,7 +555,51 @@ impl ModelForCausalLM {
             .apply(&self.lm_head)
     }
 
+    /// Returns the final hidden states for every position, shape `(b, l, hidden_size)`, e.g. to
+    /// compute embeddings.
+    pub fn forward_hidden(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        self.base.forward(input, offset)
+    }
+
+    /// Same as `forward` but returns the logits for every position, shape `(b, l, vocab_size)`.
+    pub fn forward_all(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+        self.base.forward(input, offset)?.apply(&self.lm_head)
+    }
+
+    pub fn max_position_embeddings(&self) -> usize {
+        self.base.max_position_embeddings()
+    }
+
+    pub fn make_scattered_caches(
+        &self,
+        builder: &ScatteredCacheBuilder,
+    ) -> Result<Vec<ScatteredKvCache>> {
+        self.base.make_scattered_caches(builder)
+    }
+
+    /// Batched forward pass using scattered kv-caches, returns the logits for the last position
+    /// of each batch element, shape `(b, vocab_size)`.
+    pub fn forward_scattered(
+        &self,
+        input: &Tensor,
+        positions: &[usize],
+        iam: &IndicesAndMask,
+        kv_caches: &mut [ScatteredKvCache],
+    ) -> Result<Tensor> {
+        let (_, l) = input.dims2()?;
+        self.base
+            .forward_scattered(input, positions, iam, kv_caches)?
+            .narrow(1, l - 1, 1)?
+            .apply(&self.lm_head)?
+            .squeeze(1)
+    }
+
     pub fn clear_kv_cache(&mut self) {
         self.base.clear_kv_cache();
     }
+
+    /// Drops the cached keys and values past `seq_len`, e.g. after rejected draft tokens.
+    pub fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+        self.base.truncate_kv_cache(seq_len)
+    }
 }
@@ -0,0 +1,322 @@
+//! Loading of GPTQ and AWQ quantized safetensors checkpoints.
+//!
+//! Most quantized checkpoints on the hub store their linear layers as packed integer tensors,
+//! `qweight`, `qzeros` and `scales` (plus `g_idx` for GPTQ), with a `quantization_config`
+//! section in `config.json`. This module unpacks these tensors and repacks them into a
+//! [`QTensor`] so that they can be used through [`QMatMul`] without any conversion step.
+//!
+//! Weights that use at most 4 bits and whose quantization groups are aligned on 32 input
+//! features are repacked into `Q4_1` blocks, this is exact up to the `f16` rounding of the block
+//! minimum. Other weights, e.g. 8 bits GPTQ or GPTQ with activation ordering (`desc_act`), are
+//! dequantized and requantized to `Q8_0`.
+use candle::quantized::{GgmlDType, QMatMul, QStorage, QTensor};
+use candle::{bail, DType, Device, Result, Tensor};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+// The order in which AWQ packs the 8 nibbles of an `i32`, nibble `i` holds output `AWQ_ORDER[i]`.
+const AWQ_ORDER: [usize; 8] = [0, 2, 4, 6, 1, 3, 5, 7];
+
+/// The quantization method, the `quant_method` field of the quantization config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantMethod {
+    Gptq,
+    Awq,
+}
+
+/// The `quantization_config` section of a `config.json` file.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct QuantizationConfig {
+    pub quant_method: QuantMethod,
+    #[serde(alias = "w_bit")]
+    pub bits: usize,
+    /// The number of input features sharing a scale and a zero point, -1 for a single group.
+    #[serde(alias = "q_group_size")]
+    pub group_size: i64,
+    /// GPTQ activation ordering, the groups are then given by `g_idx`.
+    #[serde(default)]
+    pub desc_act: bool,
+    /// `gptq_v2` checkpoints do not offset the stored zero points by one.
+    #[serde(default)]
+    pub checkpoint_format: Option<String>,
+    /// The AWQ kernel version, only the `gemm` packing is supported.
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+impl QuantizationConfig {
+    /// Extracts the quantization config from the content of a `config.json` file.
+    pub fn from_config_json(config: &serde_json::Value) -> Result<Self> {
+        match config.get("quantization_config") {
+            None => bail!("no quantization_config in config.json"),
+            Some(cfg) => serde_json::from_value(cfg.clone()).map_err(candle::Error::wrap),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self.quant_method {
+            QuantMethod::Gptq => {
+                if !matches!(self.bits, 2 | 4 | 8) {
+                    bail!("unsupported number of bits for gptq {}", self.bits)
+                }
+            }
+            QuantMethod::Awq => {
+                if self.bits != 4 {
+                    bail!("unsupported number of bits for awq {}", self.bits)
+                }
+                if let Some(version) = self.version.as_deref() {
+                    if !version.eq_ignore_ascii_case("gemm") {
+                        bail!("unsupported awq version {version}")
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn zero_offset(&self) -> u32 {
+        match (self.quant_method, self.checkpoint_format.as_deref()) {
+            (QuantMethod::Gptq, Some("gptq_v2")) | (QuantMethod::Awq, _) => 0,
+            (QuantMethod::Gptq, _) => 1,
+        }
+    }
+}
+
+/// A linear weight unpacked from its GPTQ or AWQ representation.
+///
+/// The dequantized weight is `scales[g][o] * (q[o][i] - zeros[g][o])` with `g = groups[i]`.
+#[derive(Debug, Clone)]
+pub struct PackedWeight {
+    out_features: usize,
+    in_features: usize,
+    n_groups: usize,
+    /// The quantized levels, `(out_features, in_features)`.
+    q: Vec<u8>,
+    /// The zero points, `(n_groups, out_features)`.
+    zeros: Vec<u8>,
+    /// The scales, `(n_groups, out_features)`.
+    scales: Vec<f32>,
+    /// The group of each input feature.
+    groups: Vec<usize>,
+}
+
+fn unpack_words(words: &[i32], bits: usize) -> impl Iterator<Item = u8> + '_ {
+    let mask = (1u32 << bits) - 1;
+    words
+        .iter()
+        .flat_map(move |&w| (0..32 / bits).map(move |j| ((w as u32 >> (bits * j)) & mask) as u8))
+}
+
+impl PackedWeight {
+    /// Unpacks the `qweight`, `qzeros`, `scales` and optional `g_idx` tensors of a linear layer.
+    pub fn new(
+        cfg: &QuantizationConfig,
+        qweight: &Tensor,
+        qzeros: &Tensor,
+        scales: &Tensor,
+        g_idx: Option<&Tensor>,
+    ) -> Result<Self> {
+        cfg.validate()?;
+        let bits = cfg.bits;
+        let pack = 32 / bits;
+        let (n_groups, out_features) = scales.dims2()?;
+        let scales = scales
+            .to_dtype(DType::F32)?
+            .flatten_all()?
+            .to_vec1::<f32>()?;
+        let qweight = qweight.to_dtype(DType::I32)?;
+        let qzeros = qzeros.to_dtype(DType::I32)?;
+        if qzeros.dims2()? != (n_groups, out_features / pack) {
+            bail!(
+                "unexpected qzeros shape {:?} for scales ({n_groups}, {out_features})",
+                qzeros.shape()
+            )
+        }
+        let offset = cfg.zero_offset();
+        let qzeros = qzeros.flatten_all()?.to_vec1::<i32>()?;
+        let (q, in_features, zeros) = match cfg.quant_method {
+            QuantMethod::Gptq => {
+                // qweight is (in / pack, out), packed along the input features.
+                let (rows, cols) = qweight.dims2()?;
+                if cols != out_features {
+                    bail!("unexpected qweight shape {:?}", qweight.shape())
+                }
+                let in_features = rows * pack;
+                let words = qweight.t()?.contiguous()?.flatten_all()?.to_vec1::<i32>()?;
+                let q: Vec<u8> = unpack_words(&words, bits).collect();
+                // Like the reference kernels, the offset wraps around within the bit width.
+                let mask = (1u32 << bits) - 1;
+                let zeros = unpack_words(&qzeros, bits)
+                    .map(|z| ((z as u32 + offset) & mask) as u8)
+                    .collect();
+                (q, in_features, zeros)
+            }
+            QuantMethod::Awq => {
+                // qweight is (in, out / pack), packed along the output features.
+                let (in_features, cols) = qweight.dims2()?;
+                if cols * pack != out_features {
+                    bail!("unexpected qweight shape {:?}", qweight.shape())
+                }
+                let unpack_awq = |words: &[i32]| {
+                    let mut out = vec![0u8; words.len() * pack];
+                    for (c, &w) in words.iter().enumerate() {
+                        for (i, &o) in AWQ_ORDER.iter().enumerate() {
+                            out[c * pack + o] = ((w as u32 >> (4 * i)) & 0xf) as u8
+                        }
+                    }
+                    out
+                };
+                let words = qweight.flatten_all()?.to_vec1::<i32>()?;
+                let q_in_out = unpack_awq(&words);
+                let mut q = vec![0u8; q_in_out.len()];
+                for i in 0..in_features {
+                    for o in 0..out_features {
+                        q[o * in_features + i] = q_in_out[i * out_features + o]
+                    }
+                }
+                (q, in_features, unpack_awq(&qzeros))
+            }
+        };
+        let groups = match g_idx {
+            Some(g_idx) => g_idx
+                .to_dtype(DType::I64)?
+                .to_vec1::<i64>()?
+                .into_iter()
+                .map(|g| g as usize)
+                .collect::<Vec<_>>(),
+            None => {
+                let group_size = in_features.div_ceil(n_groups);
+                (0..in_features).map(|i| i / group_size).collect()
+            }
+        };
+        if groups.len() != in_features || groups.iter().any(|&g| g >= n_groups) {
+            bail!("invalid group indexes for {in_features} input features and {n_groups} groups")
+        }
+        Ok(Self {
+            out_features,
+            in_features,
+            n_groups,
+            q,
+            zeros,
+            scales,
+            groups,
+        })
+    }
+
+    /// The `(out_features, in_features)` dimensions of the weight.
+    pub fn dims(&self) -> (usize, usize) {
+        (self.out_features, self.in_features)
+    }
+
+    /// The number of quantization groups along the input features.
+    pub fn n_groups(&self) -> usize {
+        self.n_groups
+    }
+
+    /// Returns true if the weight can be repacked exactly into `Q4_1` blocks.
+    pub fn is_q4_1_compatible(&self) -> bool {
+        let block_size = GgmlDType::Q4_1.block_size();
+        self.in_features.is_multiple_of(block_size)
+            && self.q.iter().all(|&q| q < 16)
+            && self
+                .groups
+                .chunks_exact(block_size)
+                .all(|b| b.iter().all(|&g| g == b[0]))
+    }
+
+    /// The dequantized weight as a `(out_features, in_features)` tensor.
+    pub fn dequantize(&self, device: &Device) -> Result<Tensor> {
+        let mut ws = Vec::with_capacity(self.q.len());
+        for (o, q) in self.q.chunks_exact(self.in_features).enumerate() {
+            ws.extend(q.iter().zip(self.groups.iter()).map(|(&q, &g)| {
+                let idx = g * self.out_features + o;
+                self.scales[idx] * (q as f32 - self.zeros[idx] as f32)
+            }))
+        }
+        Tensor::from_vec(ws, (self.out_features, self.in_features), device)
+    }
+
+    fn q4_1_data(&self) -> Vec<u8> {
+        let block_size = GgmlDType::Q4_1.block_size();
+        let n_blocks = self.q.len() / block_size;
+        let mut data = Vec::with_capacity(n_blocks * GgmlDType::Q4_1.type_size());
+        for (o, q) in self.q.chunks_exact(self.in_features).enumerate() {
+            for (b, q) in q.chunks_exact(block_size).enumerate() {
+                let idx = self.groups[b * block_size] * self.out_features + o;
+                let d = self.scales[idx];
+                let m = -d * self.zeros[idx] as f32;
+                data.extend_from_slice(&half::f16::from_f32(d).to_le_bytes());
+                data.extend_from_slice(&half::f16::from_f32(m).to_le_bytes());
+                let (lo, hi) = q.split_at(block_size / 2);
+                data.extend(lo.iter().zip(hi.iter()).map(|(&lo, &hi)| lo | (hi << 4)))
+            }
+        }
+        data
+    }
+
+    /// Converts the weight to a quantized tensor on `device`, using `Q4_1` when this is exact
+    /// and `Q8_0` otherwise.
+    pub fn to_qtensor(&self, device: &Device) -> Result<QTensor> {
+        let shape = (self.out_features, self.in_features);
+        if self.is_q4_1_compatible() {
+            let data = self.q4_1_data();
+            let storage = QStorage::from_data(Cow::Owned(data), device, GgmlDType::Q4_1)?;
+            QTensor::new(storage, shape)
+        } else {
+            let dtype = if self
+                .in_features
+                .is_multiple_of(GgmlDType::Q8_0.block_size())
+            {
+                GgmlDType::Q8_0
+            } else {
+                GgmlDType::F16
+            };
+            QTensor::quantize_onto(&self.dequantize(&Device::Cpu)?, dtype, device)
+        }
+    }
+
+    /// Converts the weight to a [`QMatMul`] on `device`, see [`Self::to_qtensor`].
+    pub fn to_qmatmul(&self, device: &Device) -> Result<QMatMul> {
+        QMatMul::from_qtensor(self.to_qtensor(device)?)
+    }
+}
+
+/// Converts the tensors of a GPTQ or AWQ checkpoint to quantized tensors.
+///
+/// Each `<prefix>.qweight`, `<prefix>.qzeros`, `<prefix>.scales`, `<prefix>.g_idx` set is
+/// replaced by a single `<prefix>.weight` quantized tensor, the other tensors are kept as `f16`,
+/// `bf16` or `f32` tensors.
+pub fn convert(
+    cfg: &QuantizationConfig,
+    tensors: &HashMap<String, Tensor>,
+    device: &Device,
+) -> Result<HashMap<String, QTensor>> {
+    const PACKED_SUFFIXES: [&str; 4] = [".qweight", ".qzeros", ".scales", ".g_idx"];
+    let mut qtensors = HashMap::new();
+    for (name, tensor) in tensors.iter() {
+        if let Some(prefix) = name.strip_suffix(".qweight") {
+            let get = |suffix: &str| {
+                let name = format!("{prefix}.{suffix}");
+                match tensors.get(&name) {
+                    None => bail!("cannot find tensor {name}"),
+                    Some(tensor) => Ok(tensor),
+                }
+            };
+            let g_idx = tensors.get(&format!("{prefix}.g_idx"));
+            let weight = PackedWeight::new(cfg, tensor, get("qzeros")?, get("scales")?, g_idx)?;
+            qtensors.insert(format!("{prefix}.weight"), weight.to_qtensor(device)?);
+        } else if !PACKED_SUFFIXES.iter().any(|s| name.ends_with(s)) {
+            let (tensor, dtype) = match tensor.dtype() {
+                DType::F16 => (tensor.clone(), GgmlDType::F16),
+                DType::BF16 => (tensor.clone(), GgmlDType::BF16),
+                _ => (tensor.to_dtype(DType::F32)?, GgmlDType::F32),
+            };
+            qtensors.insert(
+                name.clone(),
+                QTensor::quantize_onto(&tensor, dtype, device)?,
+            );
+        }
+    }
+    Ok(qtensors)
+}
@@ -1,8 +1,10 @@
 //! Varbuilder for Loading gguf files
 //!
 //! VarBuilder is a utility to store quantized tensors from a [GGUF model file](https://huggingface.co/docs/hub/gguf).
-//! These tensors can be loaded from disk using `from_gguf` or from an in-memory
-//! buffer using `from_gguf_buffer`.
+//! These tensors can be loaded from disk using `from_gguf`, memory mapped using
+//! `from_mmaped_gguf`, or from an in-memory buffer using `from_gguf_buffer`. GPTQ and AWQ
+//! safetensors checkpoints can be loaded using `from_packed_safetensors`, see
+//! [`crate::quantized_safetensors`].
 
 use candle::quantized::QTensor;
 use candle::{Device, Result, Shape};
@@ -32,6 +34,29 @@ impl VarBuilder {
         })
     }
 
+    /// Loads a gguf file using a memory mapping, the tensors on the cpu borrow their data from
+    /// the mapping.
+    ///
+    /// # Safety
+    ///
+    /// The unsafe is inherited from [`candle::quantized::gguf_file::MmapedFile::new`].
+    pub unsafe fn from_mmaped_gguf<P: AsRef<std::path::Path>>(
+        p: P,
+        device: &Device,
+    ) -> Result<Self> {
+        let file = candle::quantized::gguf_file::MmapedFile::new(p)?;
+        let mut data = std::collections::HashMap::new();
+        for tensor_name in file.content().tensor_infos.keys() {
+            let tensor = file.tensor(tensor_name, device)?;
+            data.insert(tensor_name.to_string(), Arc::new(tensor));
+        }
+        Ok(Self {
+            data: Arc::new(data),
+            path: Vec::new(),
+            device: device.clone(),
+        })
+    }
+
     pub fn from_gguf_buffer(buffer: &[u8], device: &Device) -> Result<Self> {
         let mut cursor = std::io::Cursor::new(buffer);
         let content = candle::quantized::gguf_file::Content::read(&mut cursor)?;
@@ -47,6 +72,26 @@ impl VarBuilder {
         })
     }
 
+    pub fn from_packed_safetensors<P: AsRef<std::path::Path>>(
+        paths: &[P],
+        cfg: &crate::quantized_safetensors::QuantizationConfig,
+        device: &Device,
+    ) -> Result<Self> {
+        let mut tensors = std::collections::HashMap::new();
+        for p in paths {
+            tensors.extend(candle::safetensors::load(p, &Device::Cpu)?);
+        }
+        let data = crate::quantized_safetensors::convert(cfg, &tensors, device)?
+            .into_iter()
+            .map(|(name, qtensor)| (name, Arc::new(qtensor)))
+            .collect();
+        Ok(Self {
+            data: Arc::new(data),
+            path: Vec::new(),
+            device: device.clone(),
+        })
+    }
+
     pub fn pp<S: ToString>(&self, s: S) -> Self {
         let mut path = self.path.clone();
         path.push(s.to_string());
@@ -0,0 +1,139 @@
+use candle::quantized::gguf_file;
+use candle::Result;
+use candle_transformers::chat_template::{ChatTemplate, Message};
+
+const CHATML: &str = "{% for message in messages %}\
+{{ '<|im_start|>' + message.role + '\n' + message.content + '<|im_end|>' + '\n' }}\
+{% endfor %}\
+{% if add_generation_prompt %}{{ '<|im_start|>assistant\n' }}{% endif %}";
+
+#[test]
+fn chatml() -> Result<()> {
+    let template = ChatTemplate::new(CHATML)?;
+    let messages = [
+        Message::system("You are a helpful assistant."),
+        Message::user("Hello"),
+        Message::assistant("Hi!"),
+        Message::user("How are you?"),
+    ];
+    let prompt = template.apply(&messages, true)?;
+    assert_eq!(
+        prompt,
+        "<|im_start|>system\nYou are a helpful assistant.<|im_end|>\n\
+         <|im_start|>user\nHello<|im_end|>\n\
+         <|im_start|>assistant\nHi!<|im_end|>\n\
+         <|im_start|>user\nHow are you?<|im_end|>\n\
+         <|im_start|>assistant\n"
+    );
+    let prompt = template.apply(&messages[..2], false)?;
+    assert_eq!(
+        prompt,
+        "<|im_start|>system\nYou are a helpful assistant.<|im_end|>\n\
+         <|im_start|>user\nHello<|im_end|>\n"
+    );
+    assert!(ChatTemplate::new("{% for m in messages %}").is_err());
+    Ok(())
+}
+
+#[test]
+fn tokenizer_config() -> Result<()> {
+    // A llama-2 style template, using python string methods and `raise_exception`.
+    let template = "{{ bos_token }}{% for m in messages %}\
+        {% if m.role == 'system' %}{{ raise_exception('no system') }}{% endif %}\
+        {% if m.role == 'user' %}[INST] {{ m.content.strip() }} [/INST]\
+        {% else %} {{ m.content.strip() }} {{ eos_token }}{% endif %}{% endfor %}";
+    let config = serde_json::json!({
+        "bos_token": { "content": "<s>", "lstrip": false },
+        "eos_token": "</s>",
+        "chat_template": [
+            { "name": "default", "template": template },
+            { "name": "tool_use", "template": "{{ tools | length }} tools" },
+        ],
+    });
+    let template = ChatTemplate::from_tokenizer_config_str(&config.to_string())?;
+    assert_eq!(template.bos_token(), "<s>");
+    assert_eq!(template.eos_token(), "</s>");
+    let messages = [Message::user(" hi "), Message::assistant("hello ")];
+    let prompt = template.apply(&messages, true)?;
+    assert_eq!(prompt, "<s>[INST] hi [/INST] hello </s>");
+    let err = template.apply(&[Message::system("")], true).unwrap_err();
+    assert!(err.to_string().contains("no system"), "{err}");
+    let tools = [serde_json::json!({ "type": "function" })];
+    assert_eq!(
+        template.apply_with_tools(&messages, &tools, true)?,
+        "1 tools"
+    );
+
+    let config = serde_json::json!({ "eos_token": "</s>" });
+    assert!(ChatTemplate::from_tokenizer_config_str(&config.to_string()).is_err());
+    Ok(())
+}
+
+#[test]
+fn tool_messages() -> Result<()> {
+    let template = "{% for m in messages %}{{ m.role }}:\
+        {% if m.tool_calls %}{% for call in m.tool_calls %}\
+        {{ call.function.name }}({{ call.function.arguments | tojson }}){% endfor %}\
+        {% elif m.role == 'tool' %}[{{ m.tool_call_id }}] {{ m.content }}\
+        {% else %}{{ m.content }}{% endif %}{{ '\n' }}{% endfor %}\
+        {% if tools %}{% for tool in tools %}{{ tool.function.name }}\n{% endfor %}{% endif %}";
+    let template = ChatTemplate::new(template)?;
+    let mut call = Message::assistant("");
+    call.tool_calls = Some(serde_json::json!([
+        { "id": "0", "type": "function",
+          "function": { "name": "weather", "arguments": { "city": "Paris" } } }
+    ]));
+    let messages = [
+        Message::user("Weather in Paris?"),
+        call,
+        Message::tool("sunny", "0"),
+    ];
+    let tools = [serde_json::json!({ "type": "function", "function": { "name": "weather" } })];
+    let prompt = template.apply_with_tools(&messages, &tools, false)?;
+    assert_eq!(
+        prompt,
+        "user:Weather in Paris?\nassistant:weather({\"city\":\"Paris\"})\ntool:[0] sunny\nweather\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn gguf() -> Result<()> {
+    use gguf_file::Value;
+    let tokens = ["<unk>", "<s>", "</s>"].map(|t| Value::String(t.to_string()));
+    let metadata = [
+        ("tokenizer.chat_template", Value::String(CHATML.to_string())),
+        ("tokenizer.ggml.tokens", Value::Array(tokens.to_vec())),
+        ("tokenizer.ggml.bos_token_id", Value::U32(1)),
+        ("tokenizer.ggml.eos_token_id", Value::U32(2)),
+    ];
+    let content = gguf_file::Content {
+        magic: gguf_file::VersionedMagic::GgufV3,
+        metadata: metadata
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+        tensor_infos: Default::default(),
+        tensor_data_offset: 0,
+    };
+    let template = ChatTemplate::from_gguf(&content)?;
+    assert_eq!(template.bos_token(), "<s>");
+    assert_eq!(template.eos_token(), "</s>");
+    let prompt = template.apply(&[Message::user("Hi")], true)?;
+    assert_eq!(
+        prompt,
+        "<|im_start|>user\nHi<|im_end|>\n<|im_start|>assistant\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn strftime_now() -> Result<()> {
+    let template = ChatTemplate::new("{{ strftime_now('%Y-%m-%d %b') }}")?;
+    let date = template.apply(&[], true)?;
+    let (ymd, month) = date.split_once(' ').unwrap();
+    assert_eq!(ymd.len(), 10, "{date}");
+    assert!(ymd[..4].parse::<u32>().unwrap() >= 2024, "{date}");
+    assert_eq!(month.len(), 3, "{date}");
+    Ok(())
+}
@@ -76,3 +76,299 @@ fn sample_gumbel() -> Result<()> {
     }
     Ok(())
 }
+
+mod speculative {
+    use candle::{Device, Result, Tensor};
+    use candle_transformers::generation::speculative::{
+        DraftModel, Drafter, PromptLookup, SpeculativeDecoder, SpeculativeModel,
+    };
+    use candle_transformers::generation::{LogitsProcessor, Sampling};
+
+    // A bigram model, the logits only depend on the last token. The kv-cache is simulated so as
+    // to check that the positions are rolled back properly.
+    struct Bigram {
+        logits: Tensor,
+        cache: Vec<u32>,
+    }
+
+    impl Bigram {
+        fn new(logits: &[[f32; 4]; 4]) -> Result<Self> {
+            let logits = Tensor::new(logits, &Device::Cpu)?;
+            Ok(Self {
+                logits,
+                cache: vec![],
+            })
+        }
+    }
+
+    impl SpeculativeModel for Bigram {
+        fn forward_logits(&mut self, input: &Tensor, offset: usize) -> Result<Tensor> {
+            assert_eq!(offset, self.cache.len());
+            let input = input.squeeze(0)?;
+            self.cache.extend(input.to_vec1::<u32>()?);
+            self.logits.index_select(&input, 0)
+        }
+
+        fn truncate_kv_cache(&mut self, seq_len: usize) -> Result<()> {
+            self.cache.truncate(seq_len);
+            Ok(())
+        }
+    }
+
+    const TARGET: [[f32; 4]; 4] = [
+        [0., 3., 1., 0.],
+        [0., 0., 3., 1.],
+        [1., 0., 0., 3.],
+        [3., 1., 0., 0.],
+    ];
+
+    const DRAFT: [[f32; 4]; 4] = [
+        [0., 3., 1., 0.],
+        [0., 0., 3., 1.],
+        [3., 0., 0., 1.],
+        [3., 1., 0., 0.],
+    ];
+
+    fn greedy(context: &[u32], len: usize) -> Vec<u32> {
+        let mut tokens = context.to_vec();
+        while tokens.len() < len {
+            let last = *tokens.last().unwrap() as usize;
+            let next = (0..4).max_by(|&i, &j| TARGET[last][i].total_cmp(&TARGET[last][j]));
+            tokens.push(next.unwrap() as u32)
+        }
+        tokens
+    }
+
+    fn generate<D: Drafter>(
+        decoder: &mut SpeculativeDecoder<Bigram, D>,
+        context: &[u32],
+        len: usize,
+    ) -> Result<Vec<u32>> {
+        let mut tokens = context.to_vec();
+        while tokens.len() < len {
+            let next_tokens = decoder.next_tokens(&tokens)?;
+            assert!(!next_tokens.is_empty());
+            tokens.extend_from_slice(&next_tokens);
+            assert_eq!(decoder.target().cache.len(), tokens.len() - 1);
+        }
+        tokens.truncate(len);
+        Ok(tokens)
+    }
+
+    #[test]
+    fn greedy_draft_model() -> Result<()> {
+        let dev = Device::Cpu;
+        let lp = || LogitsProcessor::from_sampling(42, Sampling::ArgMax);
+        let drafter = DraftModel::new(Bigram::new(&DRAFT)?, lp(), &dev);
+        let mut decoder = SpeculativeDecoder::new(Bigram::new(&TARGET)?, drafter, lp(), 3, &dev);
+        let tokens = generate(&mut decoder, &[0, 1], 20)?;
+        assert_eq!(tokens, greedy(&[0, 1], 20));
+        assert!(decoder.num_accepted() > 0);
+        assert!(decoder.num_accepted() < decoder.num_drafted());
+
+        // With an identical draft model, every draft token gets accepted.
+        let drafter = DraftModel::new(Bigram::new(&TARGET)?, lp(), &dev);
+        let mut decoder = SpeculativeDecoder::new(Bigram::new(&TARGET)?, drafter, lp(), 3, &dev);
+        let tokens = generate(&mut decoder, &[2], 17)?;
+        assert_eq!(tokens, greedy(&[2], 17));
+        assert_eq!(decoder.acceptance_rate(), 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn prompt_lookup() -> Result<()> {
+        let mut drafter = PromptLookup::new(1, 2)?;
+        let draft = drafter.draft(&[1, 2, 3, 1, 2, 4, 5, 1, 2], 3)?;
+        assert_eq!(draft.tokens, [4, 5, 1]);
+        assert_eq!(draft.distributions, None);
+        let draft = drafter.draft(&[1, 2, 3, 3], 5)?;
+        assert_eq!(draft.tokens, [3]);
+        let draft = drafter.draft(&[1, 2, 3], 5)?;
+        assert!(draft.tokens.is_empty());
+
+        let dev = Device::Cpu;
+        let lp = LogitsProcessor::from_sampling(42, Sampling::ArgMax);
+        let mut decoder = SpeculativeDecoder::new(Bigram::new(&TARGET)?, drafter, lp, 4, &dev);
+        let tokens = generate(&mut decoder, &[0, 1], 20)?;
+        assert_eq!(tokens, greedy(&[0, 1], 20));
+        assert!(decoder.num_accepted() > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn rejection_sampling_distribution() -> Result<()> {
+        let dev = Device::Cpu;
+        let sampling = Sampling::TopK {
+            k: 3,
+            temperature: 1.0,
+        };
+        let drafter = DraftModel::new(
+            Bigram::new(&DRAFT)?,
+            LogitsProcessor::from_sampling(1, sampling.clone()),
+            &dev,
+        );
+        let lp = LogitsProcessor::from_sampling(42, sampling);
+        let expected = lp.distribution(&Tensor::new(&TARGET[2], &dev)?)?;
+        let mut decoder = SpeculativeDecoder::new(Bigram::new(&TARGET)?, drafter, lp, 2, &dev);
+        let mut counts = [0f32; 4];
+        let samples = 20000;
+        for _ in 0..samples {
+            let next_tokens = decoder.next_tokens(&[0, 2])?;
+            counts[next_tokens[0] as usize] += 1. / samples as f32;
+        }
+        for i in 0..4 {
+            if (counts[i] - expected[i]).abs() > 0.02 {
+                panic!("pr mismatch {counts:?} {expected:?}");
+            }
+        }
+        Ok(())
+    }
+}
+
+mod engine {
+    use candle::{DType, Device, IndexOp, Result, Tensor};
+    use candle_transformers::generation::engine::{
+        Engine, EngineConfig, FinishReason, GenerationEvent, GenerationParams,
+    };
+    use candle_transformers::models::qwen3::ModelForCausalLM;
+
+    fn tiny_qwen3() -> Result<ModelForCausalLM> {
+        ModelForCausalLM::tiny_random(DType::F32, &Device::Cpu)
+    }
+
+    fn greedy(model: &mut ModelForCausalLM, prompt: &[u32], len: usize) -> Result<Vec<u32>> {
+        model.clear_kv_cache();
+        let mut tokens = prompt.to_vec();
+        let mut offset = 0;
+        while tokens.len() < prompt.len() + len {
+            let input = Tensor::new(&tokens[offset..], &Device::Cpu)?.unsqueeze(0)?;
+            let logits = model.forward(&input, offset)?.i((0, 0))?;
+            offset = tokens.len();
+            tokens.push(logits.argmax(0)?.to_scalar::<u32>()?);
+        }
+        Ok(tokens[prompt.len()..].to_vec())
+    }
+
+    fn collect(events: &std::sync::mpsc::Receiver<Result<GenerationEvent>>) -> Result<Vec<u32>> {
+        let mut tokens = vec![];
+        for event in events.iter() {
+            match event? {
+                GenerationEvent::Token(token) => tokens.push(token),
+                GenerationEvent::Finished(reason) => {
+                    assert_eq!(reason, FinishReason::Length);
+                    break;
+                }
+            }
+        }
+        Ok(tokens)
+    }
+
+    #[test]
+    fn continuous_batching_matches_sequential() -> Result<()> {
+        let mut model = tiny_qwen3()?;
+        let prompts: [&[u32]; 4] = [&[1, 2, 3], &[4, 5], &[6, 7, 8, 9, 10], &[11]];
+        let lens = [6, 9, 4, 7];
+        let mut expected = vec![];
+        for (prompt, &len) in prompts.iter().zip(lens.iter()) {
+            expected.push(greedy(&mut model, prompt, len)?);
+        }
+
+        // Only two slots so that requests get admitted as others retire.
+        let config = EngineConfig {
+            max_batch_size: 2,
+            context: 32,
+        };
+        let mut engine = Engine::new(model, config, DType::F32, &Device::Cpu)?;
+        let receivers = prompts
+            .iter()
+            .zip(lens.iter())
+            .map(|(prompt, &max_new_tokens)| {
+                let params = GenerationParams {
+                    max_new_tokens,
+                    ..Default::default()
+                };
+                engine.add_request(prompt.to_vec(), params)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(engine.num_waiting(), 4);
+        engine.step()?;
+        assert_eq!(engine.num_running(), 2);
+        while engine.has_work() {
+            engine.step()?;
+        }
+        for (receiver, expected) in receivers.iter().zip(expected.iter()) {
+            assert_eq!(&collect(receiver)?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn engine_handle_and_stop_tokens() -> Result<()> {
+        let mut model = tiny_qwen3()?;
+        let expected = greedy(&mut model, &[3, 1, 4], 8)?;
+        let config = EngineConfig {
+            max_batch_size: 4,
+            context: 32,
+        };
+        let handle = Engine::new(model, config, DType::F32, &Device::Cpu)?.spawn();
+        let params = GenerationParams {
+            max_new_tokens: 8,
+            ..Default::default()
+        };
+        let events = handle.submit(vec![3, 1, 4], params.clone())?;
+        assert_eq!(collect(&events)?, expected);
+
+        let params = GenerationParams {
+            stop_tokens: vec![expected[2]],
+            ..params
+        };
+        let events = handle.submit(vec![3, 1, 4], params)?;
+        let events = events.iter().collect::<Result<Vec<_>>>()?;
+        let stop_at = expected.iter().position(|&t| t == expected[2]).unwrap();
+        let mut expected_events = expected[..stop_at]
+            .iter()
+            .map(|&t| GenerationEvent::Token(t))
+            .collect::<Vec<_>>();
+        expected_events.push(GenerationEvent::Finished(FinishReason::Stop));
+        assert_eq!(events, expected_events);
+
+        let events = handle.submit(vec![], GenerationParams::default())?;
+        assert!(events.recv().unwrap().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn engine_max_position_embeddings() -> Result<()> {
+        // The tiny model supports 64 positions, less than the engine context.
+        let model = tiny_qwen3()?;
+        let config = EngineConfig {
+            max_batch_size: 2,
+            context: 128,
+        };
+        let mut engine = Engine::new(model.clone(), config, DType::F32, &Device::Cpu)?;
+        let params = GenerationParams {
+            max_new_tokens: 100,
+            ..Default::default()
+        };
+        let receiver = engine.add_request(vec![1; 60], params.clone());
+        let too_long = engine.add_request(vec![1; 64], params);
+        while engine.has_work() {
+            engine.step()?;
+        }
+        let events = receiver.iter().collect::<Result<Vec<_>>>()?;
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[4], GenerationEvent::Finished(FinishReason::Length));
+        assert!(too_long.recv().unwrap().is_err());
+
+        // Running the model past its positions is an error rather than a silent clamp.
+        let mut builder =
+            candle_nn::kv_cache::ScatteredCacheBuilder::new(1, 128, DType::F32, &Device::Cpu)?;
+        let mut caches = model.make_scattered_caches(&builder)?;
+        let iam = builder.indices_and_mask(2, &[true])?;
+        let input = Tensor::new(&[[1u32, 2]], &Device::Cpu)?;
+        assert!(model
+            .forward_scattered(&input, &[63], &iam, &mut caches)
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use candle::quantized::gguf_file::{self, Value};
+use candle::quantized::{GgmlDType, QTensor};
+use candle::{DType, Device, Result, Tensor};
+use candle_transformers::gguf_convert::{self, Architecture, HfModel};
+use candle_transformers::models::{llama, quantized_llama};
+use std::collections::HashMap;
+
+const HIDDEN: usize = 64;
+const N_HEAD: usize = 4;
+const N_KV_HEAD: usize = 2;
+const N_LAYER: usize = 2;
+const INTERMEDIATE: usize = 128;
+const VOCAB: usize = 32;
+
+fn config() -> serde_json::Value {
+    serde_json::json!({
+        "model_type": "llama",
+        "hidden_size": HIDDEN,
+        "intermediate_size": INTERMEDIATE,
+        "vocab_size": VOCAB,
+        "num_hidden_layers": N_LAYER,
+        "num_attention_heads": N_HEAD,
+        "num_key_value_heads": N_KV_HEAD,
+        "rms_norm_eps": 1e-5,
+        "rope_theta": 10000.0,
+        "max_position_embeddings": 128,
+        "tie_word_embeddings": false,
+    })
+}
+
+fn tokenizer() -> serde_json::Value {
+    let mut vocab = serde_json::Map::new();
+    for (i, token) in ["a", "b", "c", "ab", "abc"].iter().enumerate() {
+        vocab.insert(token.to_string(), (i + 2).into());
+    }
+    serde_json::json!({
+        "model": {"type": "BPE", "vocab": vocab, "merges": ["a b", ["ab", "c"]]},
+        "pre_tokenizer": {"type": "ByteLevel", "add_prefix_space": false},
+        "added_tokens": [
+            {"id": 0, "content": "<s>", "special": true},
+            {"id": 1, "content": "</s>", "special": true},
+            {"id": 8, "content": "<extra>", "special": false},
+        ],
+    })
+}
+
+// Deterministic weights with the hugging face names and layout.
+fn weights(dev: &Device) -> Result<HashMap<String, Tensor>> {
+    let mut shapes = vec![
+        ("model.embed_tokens.weight".to_string(), vec![VOCAB, HIDDEN]),
+        ("model.norm.weight".to_string(), vec![HIDDEN]),
+        ("lm_head.weight".to_string(), vec![VOCAB, HIDDEN]),
+    ];
+    let kv = HIDDEN / N_HEAD * N_KV_HEAD;
+    for i in 0..N_LAYER {
+        let p = format!("model.layers.{i}");
+        shapes.extend([
+            (format!("{p}.input_layernorm.weight"), vec![HIDDEN]),
+            (format!("{p}.post_attention_layernorm.weight"), vec![HIDDEN]),
+            (format!("{p}.self_attn.q_proj.weight"), vec![HIDDEN, HIDDEN]),
+            (format!("{p}.self_attn.k_proj.weight"), vec![kv, HIDDEN]),
+            (format!("{p}.self_attn.v_proj.weight"), vec![kv, HIDDEN]),
+            (format!("{p}.self_attn.o_proj.weight"), vec![HIDDEN, HIDDEN]),
+            (
+                format!("{p}.mlp.gate_proj.weight"),
+                vec![INTERMEDIATE, HIDDEN],
+            ),
+            (
+                format!("{p}.mlp.up_proj.weight"),
+                vec![INTERMEDIATE, HIDDEN],
+            ),
+            (
+                format!("{p}.mlp.down_proj.weight"),
+                vec![HIDDEN, INTERMEDIATE],
+            ),
+        ])
+    }
+    shapes
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, shape))| {
+            let n = shape.iter().product::<usize>();
+            let xs = Tensor::arange(0u32, n as u32, dev)?
+                .to_dtype(DType::F32)?
+                .affine(0.37 + i as f64 * 0.11, i as f64)?
+                .sin()?;
+            let xs = if shape.len() == 1 {
+                ((xs * 0.1)? + 1.)?
+            } else {
+                (xs * 0.2)?
+            };
+            Ok((name, xs.reshape(shape)?))
+        })
+        .collect()
+}
+
+fn metadata_value<'a>(metadata: &'a [(String, Value)], key: &str) -> &'a Value {
+    match metadata.iter().find(|(k, _)| k == key) {
+        Some((_, v)) => v,
+        None => panic!("missing {key}"),
+    }
+}
+
+#[test]
+fn tensor_names() {
+    let llama = Architecture::Llama;
+    let names = [
+        ("model.embed_tokens.weight", "token_embd.weight"),
+        ("lm_head.weight", "output.weight"),
+        (
+            "model.layers.3.post_attention_layernorm.weight",
+            "blk.3.ffn_norm.weight",
+        ),
+        ("model.layers.0.self_attn.k_proj.bias", "blk.0.attn_k.bias"),
+    ];
+    for (hf, gguf) in names {
+        assert_eq!(llama.tensor_name(hf).as_deref(), Some(gguf))
+    }
+    assert_eq!(llama.tensor_name("model.rotary_emb.inv_freq"), None);
+    let gemma3 = Architecture::from_model_type("gemma3").unwrap();
+    assert_eq!(
+        gemma3
+            .tensor_name("language_model.model.layers.1.post_attention_layernorm.weight")
+            .as_deref(),
+        Some("blk.1.post_attention_norm.weight")
+    );
+    assert_eq!(
+        gemma3.tensor_name("vision_tower.vision_model.post_layernorm.weight"),
+        None
+    );
+}
+
+#[test]
+fn tokenizer_metadata() -> Result<()> {
+    let tokenizer_config = serde_json::json!({
+        "bos_token": "<s>",
+        "eos_token": {"content": "</s>"},
+        "add_bos_token": true,
+        "chat_template": "{{ messages }}",
+    });
+    let metadata = gguf_convert::tokenizer_metadata(&tokenizer(), Some(&tokenizer_config), "gpt2")?;
+    let get = |key: &str| metadata_value(&metadata, key);
+    assert_eq!(get("tokenizer.ggml.model").to_string()?, "gpt2");
+    assert_eq!(get("tokenizer.ggml.bos_token_id").to_u32()?, 0);
+    assert_eq!(get("tokenizer.ggml.eos_token_id").to_u32()?, 1);
+    assert!(get("tokenizer.ggml.add_bos_token").to_bool()?);
+    assert_eq!(
+        get("tokenizer.chat_template").to_string()?,
+        "{{ messages }}"
+    );
+    let tokens = get("tokenizer.ggml.tokens")
+        .to_vec()?
+        .iter()
+        .map(|v| v.to_string().cloned())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        tokens,
+        ["<s>", "</s>", "a", "b", "c", "ab", "abc", "[PAD7]", "<extra>"]
+    );
+    let token_types = get("tokenizer.ggml.token_type")
+        .to_vec()?
+        .iter()
+        .map(|v| v.to_i32())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(token_types, [3, 3, 1, 1, 1, 1, 1, 5, 4]);
+    let merges = get("tokenizer.ggml.merges")
+        .to_vec()?
+        .iter()
+        .map(|v| v.to_string().cloned())
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(merges, ["a b", "ab c"]);
+    Ok(())
+}
+
+#[test]
+fn convert_llama() -> Result<()> {
+    let dev = Device::Cpu;
+    let dir = std::env::temp_dir().join(format!("candle-convert-hf-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let weights = weights(&dev)?;
+    candle::safetensors::save(&weights, dir.join("model.safetensors"))?;
+    std::fs::write(dir.join("config.json"), config().to_string())?;
+    std::fs::write(dir.join("tokenizer.json"), tokenizer().to_string())?;
+    let model = unsafe { HfModel::from_dir(&dir) };
+    std::fs::remove_dir_all(&dir)?;
+    let model = model?;
+    assert_eq!(model.architecture(), Architecture::Llama);
+    assert_eq!(model.tensor_names().count(), 3 + 9 * N_LAYER);
+    let hf_name = model.hf_name("blk.1.attn_k.weight")?;
+    assert_eq!(hf_name, "model.layers.1.self_attn.k_proj.weight");
+    let view = model.safetensors().get(hf_name)?;
+    assert_eq!(view.shape(), [HIDDEN / N_HEAD * N_KV_HEAD, HIDDEN]);
+    assert!(model.hf_name("blk.1.attn_v.bias").is_err());
+
+    let mut buffer = std::io::Cursor::new(Vec::new());
+    model.write_gguf(&mut buffer, |_, tensor| {
+        QTensor::quantize(&tensor, GgmlDType::F32)
+    })?;
+    let mut reader = std::io::Cursor::new(buffer.into_inner());
+    let content = gguf_file::Content::read(&mut reader)?;
+    let report = gguf_convert::validate_gguf(&content, Architecture::Llama);
+    assert!(report.is_ok(), "{report:?}");
+    assert!(report.warnings.is_empty(), "{report:?}");
+    let report = gguf_convert::validate_gguf(&content, Architecture::Qwen3);
+    assert!(report
+        .errors
+        .contains(&"missing metadata qwen3.block_count".to_string()));
+    let get = |key: &str| content.metadata.get(key).unwrap();
+    assert_eq!(get("general.architecture").to_string()?, "llama");
+    assert_eq!(get("llama.attention.head_count_kv").to_u32()?, 2);
+    assert_eq!(get("llama.rope.dimension_count").to_u32()?, 16);
+    assert_eq!(get("tokenizer.ggml.pre").to_string()?, "llama-bpe");
+    let quantized = quantized_llama::ModelWeights::from_gguf(content, &mut reader, &dev)?;
+
+    reader.set_position(0);
+    let mut content = gguf_file::Content::read(&mut reader)?;
+    content.tensor_infos.remove("blk.0.ffn_up.weight");
+    content.metadata.remove("llama.rope.dimension_count");
+    let report = gguf_convert::validate_gguf(&content, Architecture::Llama);
+    assert_eq!(
+        report.errors,
+        [
+            "missing metadata llama.rope.dimension_count",
+            "missing tensor blk.0.ffn_up.weight"
+        ]
+    );
+
+    // The converted model matches the hugging face implementation.
+    let config: llama::LlamaConfig = serde_json::from_value(config()).unwrap();
+    let config = config.into_config(false);
+    let vb = candle_nn::VarBuilder::from_tensors(weights, DType::F32, &dev);
+    let reference = llama::Llama::load(vb, &config)?;
+    let mut cache = llama::Cache::new(true, DType::F32, &config, &dev)?;
+    let input = Tensor::new(&[[1u32, 5, 9, 3, 7, 2]], &dev)?;
+    let expected = reference.forward(&input, 0, &mut cache)?;
+    let mut quantized = quantized;
+    let logits = quantized.forward(&input, 0)?;
+    let diff = (logits - expected)?.abs()?.flatten_all()?.max(0)?;
+    assert!(diff.to_scalar::<f32>()? < 1e-4, "{diff}");
+    Ok(())
+}
@@ -0,0 +1,141 @@
+#![cfg(feature = "tokenizers")]
+use candle::quantized::gguf_file::{Content, Value, VersionedMagic};
+use candle::Result;
+use candle_transformers::gguf_tokenizer::GgufTokenizer;
+
+fn content(metadata: Vec<(&str, Value)>) -> Content {
+    Content {
+        magic: VersionedMagic::GgufV3,
+        metadata: metadata
+            .into_iter()
+            .map(|(k, v)| (format!("tokenizer.ggml.{k}"), v))
+            .collect(),
+        tensor_infos: Default::default(),
+        tensor_data_offset: 0,
+    }
+}
+
+fn strings(values: &[&str]) -> Value {
+    Value::Array(
+        values
+            .iter()
+            .map(|v| Value::String(v.to_string()))
+            .collect(),
+    )
+}
+
+fn encode(tokenizer: &GgufTokenizer, text: &str) -> Result<Vec<String>> {
+    let encoding = tokenizer
+        .tokenizer
+        .encode(text, true)
+        .map_err(candle::Error::wrap)?;
+    Ok(encoding.get_tokens().to_vec())
+}
+
+#[test]
+fn gpt2() -> Result<()> {
+    let tokens = [
+        "<|endoftext|>",
+        "h",
+        "e",
+        "l",
+        "o",
+        "Ġ",
+        "w",
+        "r",
+        "d",
+        "he",
+        "ll",
+        "hell",
+        "hello",
+        "Ġw",
+        "or",
+        "Ġwor",
+        "Ġworl",
+        "Ġworld",
+    ];
+    let merges = [
+        "h e", "l l", "he ll", "hell o", "Ġ w", "o r", "Ġw or", "Ġwor l", "Ġworl d",
+    ];
+    let mut token_types = vec![Value::I32(1); tokens.len()];
+    token_types[0] = Value::I32(3);
+    let metadata = vec![
+        ("model", Value::String("gpt2".to_string())),
+        ("pre", Value::String("qwen2".to_string())),
+        ("tokens", strings(&tokens)),
+        ("token_type", Value::Array(token_types)),
+        ("merges", strings(&merges)),
+        ("eos_token_id", Value::U32(0)),
+        ("bos_token_id", Value::U32(0)),
+    ];
+    let tokenizer = GgufTokenizer::from_gguf(&content(metadata.clone()))?;
+    assert_eq!(tokenizer.eos_token_id, Some(0));
+    assert!(!tokenizer.add_bos_token);
+    let encoded = encode(&tokenizer, "hello world<|endoftext|>")?;
+    assert_eq!(encoded, ["hello", "Ġworld", "<|endoftext|>"]);
+    let decoded = tokenizer
+        .tokenizer
+        .decode(&[12, 17, 0], true)
+        .map_err(candle::Error::wrap)?;
+    assert_eq!(decoded, "hello world");
+
+    let mut metadata = metadata;
+    metadata.push(("add_bos_token", Value::Bool(true)));
+    let tokenizer = GgufTokenizer::from_gguf(&content(metadata))?;
+    assert!(tokenizer.add_bos_token);
+    let encoded = encode(&tokenizer, "hello")?;
+    assert_eq!(encoded, ["<|endoftext|>", "hello"]);
+    Ok(())
+}
+
+#[test]
+fn sentencepiece() -> Result<()> {
+    let tokens = [
+        "<unk>", "<s>", "</s>", "<0x21>", "▁", "h", "e", "l", "o", "▁h", "el", "▁hel", "lo",
+        "▁hello", "ll",
+    ];
+    let scores = [
+        0., 0., 0., 0., -10., -11., -12., -13., -14., -1., -2., -3., -4., -5., -6.,
+    ];
+    let mut token_types = vec![Value::I32(1); tokens.len()];
+    token_types[0] = Value::I32(2);
+    token_types[1] = Value::I32(3);
+    token_types[2] = Value::I32(3);
+    token_types[3] = Value::I32(6);
+    let metadata = vec![
+        ("model", Value::String("llama".to_string())),
+        ("tokens", strings(&tokens)),
+        (
+            "scores",
+            Value::Array(scores.iter().map(|&s| Value::F32(s)).collect()),
+        ),
+        ("token_type", Value::Array(token_types)),
+        ("unknown_token_id", Value::U32(0)),
+        ("bos_token_id", Value::U32(1)),
+        ("eos_token_id", Value::U32(2)),
+    ];
+    let tokenizer = GgufTokenizer::from_gguf(&content(metadata))?;
+    assert_eq!(tokenizer.bos_token_id, Some(1));
+    assert!(tokenizer.add_bos_token);
+    let encoded = encode(&tokenizer, "hello!")?;
+    assert_eq!(encoded, ["<s>", "▁hello", "<0x21>"]);
+    // Characters that are neither in the vocabulary nor byte tokens map to unk.
+    let encoded = encode(&tokenizer, "hel?")?;
+    assert_eq!(encoded, ["<s>", "▁hel", "<unk>"]);
+    let decoded = tokenizer
+        .tokenizer
+        .decode(&[1, 13, 3, 11], true)
+        .map_err(candle::Error::wrap)?;
+    assert_eq!(decoded, "hello! hel");
+    Ok(())
+}
+
+#[test]
+fn unsupported() {
+    let metadata = vec![
+        ("model", Value::String("bert".to_string())),
+        ("tokens", strings(&["a"])),
+    ];
+    assert!(GgufTokenizer::from_gguf(&content(metadata)).is_err());
+    assert!(GgufTokenizer::from_gguf(&content(vec![])).is_err());
+}
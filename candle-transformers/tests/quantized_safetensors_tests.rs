@@ -0,0 +1,265 @@
+use candle::quantized::GgmlDType;
+use candle::{Device, Module, Result, Tensor};
+use candle_transformers::quantized_safetensors::{PackedWeight, QuantMethod, QuantizationConfig};
+
+const OUT: usize = 16;
+const IN: usize = 128;
+
+fn config(quant_method: QuantMethod, bits: usize, group_size: i64) -> QuantizationConfig {
+    QuantizationConfig {
+        quant_method,
+        bits,
+        group_size,
+        desc_act: false,
+        checkpoint_format: None,
+        version: None,
+    }
+}
+
+// Deterministic levels, zero points and scales, the scales are exactly representable as f16 so
+// that the Q4_1 repacking is lossless.
+fn reference(bits: usize, n_groups: usize) -> (Vec<u8>, Vec<u8>, Vec<f32>) {
+    let max = (1usize << bits) - 1;
+    let q = (0..OUT * IN)
+        .map(|i| ((i * 7 + i / 5) % (max + 1)) as u8)
+        .collect();
+    let zeros = (0..n_groups * OUT)
+        .map(|i| (1 + (i * 3) % max) as u8)
+        .collect();
+    let scales = (0..n_groups * OUT)
+        .map(|i| (1 + i % 11) as f32 / 64.)
+        .collect();
+    (q, zeros, scales)
+}
+
+fn dequantize(q: &[u8], zeros: &[u8], scales: &[f32], groups: &[usize]) -> Vec<f32> {
+    let mut ws = vec![0f32; OUT * IN];
+    for o in 0..OUT {
+        for i in 0..IN {
+            let idx = groups[i] * OUT + o;
+            ws[o * IN + i] = scales[idx] * (q[o * IN + i] as f32 - zeros[idx] as f32)
+        }
+    }
+    ws
+}
+
+fn pack_gptq(bits: usize, q: &[u8], zeros: &[u8], n_groups: usize) -> Result<(Tensor, Tensor)> {
+    let pack = 32 / bits;
+    let mut qweight = vec![0i32; IN / pack * OUT];
+    for o in 0..OUT {
+        for i in 0..IN {
+            let v = (q[o * IN + i] as u32) << (bits * (i % pack));
+            qweight[(i / pack) * OUT + o] |= v as i32
+        }
+    }
+    let mut qzeros = vec![0i32; n_groups * OUT / pack];
+    for (idx, &z) in zeros.iter().enumerate() {
+        // GPTQ stores the zero points minus one, a zero point of 0 wraps around to the max.
+        let z = z.wrapping_sub(1) as u32 & ((1 << bits) - 1);
+        qzeros[idx / pack] |= (z << (bits * (idx % pack))) as i32
+    }
+    let qweight = Tensor::from_vec(qweight, (IN / pack, OUT), &Device::Cpu)?;
+    let qzeros = Tensor::from_vec(qzeros, (n_groups, OUT / pack), &Device::Cpu)?;
+    Ok((qweight, qzeros))
+}
+
+fn pack_awq(q: &[u8], zeros: &[u8], n_groups: usize) -> Result<(Tensor, Tensor)> {
+    const ORDER: [usize; 8] = [0, 2, 4, 6, 1, 3, 5, 7];
+    let pack_rows = |values: &[u8], rows: usize| {
+        let mut words = vec![0i32; rows * OUT / 8];
+        for r in 0..rows {
+            for c in 0..OUT / 8 {
+                for (j, &o) in ORDER.iter().enumerate() {
+                    let v = values[r * OUT + c * 8 + o] as u32;
+                    words[r * OUT / 8 + c] |= (v << (4 * j)) as i32
+                }
+            }
+        }
+        words
+    };
+    let mut q_in_out = vec![0u8; IN * OUT];
+    for o in 0..OUT {
+        for i in 0..IN {
+            q_in_out[i * OUT + o] = q[o * IN + i]
+        }
+    }
+    let qweight = Tensor::from_vec(pack_rows(&q_in_out, IN), (IN, OUT / 8), &Device::Cpu)?;
+    let qzeros = Tensor::from_vec(
+        pack_rows(zeros, n_groups),
+        (n_groups, OUT / 8),
+        &Device::Cpu,
+    )?;
+    Ok((qweight, qzeros))
+}
+
+fn flat(t: &Tensor) -> Result<Vec<f32>> {
+    t.flatten_all()?.to_vec1::<f32>()
+}
+
+fn max_diff(xs: &[f32], ys: &[f32]) -> f32 {
+    xs.iter()
+        .zip(ys.iter())
+        .map(|(x, y)| (x - y).abs())
+        .fold(0f32, f32::max)
+}
+
+#[test]
+fn gptq_q4_1_repack() -> Result<()> {
+    for (bits, group_size) in [(4, 64), (4, -1), (2, 32)] {
+        let n_groups = if group_size < 0 {
+            1
+        } else {
+            IN / group_size as usize
+        };
+        let (q, zeros, scales) = reference(bits, n_groups);
+        let (qweight, qzeros) = pack_gptq(bits, &q, &zeros, n_groups)?;
+        let scales_t = Tensor::from_vec(scales.clone(), (n_groups, OUT), &Device::Cpu)?
+            .to_dtype(candle::DType::F16)?;
+        let cfg = config(QuantMethod::Gptq, bits, group_size);
+        let weight = PackedWeight::new(&cfg, &qweight, &qzeros, &scales_t, None)?;
+        assert_eq!(weight.dims(), (OUT, IN));
+        assert!(weight.is_q4_1_compatible());
+        let groups: Vec<usize> = (0..IN).map(|i| i * n_groups / IN).collect();
+        let expected = dequantize(&q, &zeros, &scales, &groups);
+        assert_eq!(flat(&weight.dequantize(&Device::Cpu)?)?, expected);
+        let qtensor = weight.to_qtensor(&Device::Cpu)?;
+        assert_eq!(qtensor.dtype(), GgmlDType::Q4_1);
+        assert_eq!(flat(&qtensor.dequantize(&Device::Cpu)?)?, expected);
+    }
+    Ok(())
+}
+
+#[test]
+fn gptq_zero_wraparound() -> Result<()> {
+    for bits in [4, 8] {
+        let n_groups = 2;
+        let (q, mut zeros, scales) = reference(bits, n_groups);
+        // These are stored as 2^bits - 1 in qzeros.
+        for z in zeros.iter_mut().step_by(3) {
+            *z = 0
+        }
+        let (qweight, qzeros) = pack_gptq(bits, &q, &zeros, n_groups)?;
+        let scales_t = Tensor::from_vec(scales.clone(), (n_groups, OUT), &Device::Cpu)?;
+        let cfg = config(QuantMethod::Gptq, bits, 64);
+        let weight = PackedWeight::new(&cfg, &qweight, &qzeros, &scales_t, None)?;
+        let groups: Vec<usize> = (0..IN).map(|i| i / 64).collect();
+        let expected = dequantize(&q, &zeros, &scales, &groups);
+        assert_eq!(flat(&weight.dequantize(&Device::Cpu)?)?, expected, "{bits}");
+    }
+    Ok(())
+}
+
+#[test]
+fn gptq_act_order() -> Result<()> {
+    let n_groups = 4;
+    let (q, zeros, scales) = reference(4, n_groups);
+    let (qweight, qzeros) = pack_gptq(4, &q, &zeros, n_groups)?;
+    // Activation ordering interleaves the groups so they are not aligned on the Q4_1 blocks.
+    let groups: Vec<usize> = (0..IN).map(|i| i % n_groups).collect();
+    let g_idx = Tensor::from_vec(
+        groups.iter().map(|&g| g as i32).collect::<Vec<_>>(),
+        IN,
+        &Device::Cpu,
+    )?;
+    let scales_t = Tensor::from_vec(scales.clone(), (n_groups, OUT), &Device::Cpu)?;
+    let mut cfg = config(QuantMethod::Gptq, 4, 32);
+    cfg.desc_act = true;
+    let weight = PackedWeight::new(&cfg, &qweight, &qzeros, &scales_t, Some(&g_idx))?;
+    assert!(!weight.is_q4_1_compatible());
+    let expected = dequantize(&q, &zeros, &scales, &groups);
+    assert_eq!(flat(&weight.dequantize(&Device::Cpu)?)?, expected);
+    let qtensor = weight.to_qtensor(&Device::Cpu)?;
+    assert_eq!(qtensor.dtype(), GgmlDType::Q8_0);
+    let diff = max_diff(&flat(&qtensor.dequantize(&Device::Cpu)?)?, &expected);
+    assert!(diff < 0.05, "{diff}");
+    Ok(())
+}
+
+#[test]
+fn awq_repack() -> Result<()> {
+    let n_groups = 2;
+    let (q, zeros, scales) = reference(4, n_groups);
+    let (qweight, qzeros) = pack_awq(&q, &zeros, n_groups)?;
+    let scales_t = Tensor::from_vec(scales.clone(), (n_groups, OUT), &Device::Cpu)?;
+    let cfg = config(QuantMethod::Awq, 4, 64);
+    let weight = PackedWeight::new(&cfg, &qweight, &qzeros, &scales_t, None)?;
+    assert_eq!(weight.dims(), (OUT, IN));
+    let groups: Vec<usize> = (0..IN).map(|i| i / 64).collect();
+    let expected = dequantize(&q, &zeros, &scales, &groups);
+    let qtensor = weight.to_qtensor(&Device::Cpu)?;
+    assert_eq!(qtensor.dtype(), GgmlDType::Q4_1);
+    assert_eq!(flat(&qtensor.dequantize(&Device::Cpu)?)?, expected);
+    Ok(())
+}
+
+#[test]
+fn quantization_config() -> Result<()> {
+    let config: serde_json::Value = serde_json::from_str(
+        r#"{"hidden_size": 4096, "quantization_config": {"quant_method": "awq", "w_bit": 4,
+            "q_group_size": 128, "zero_point": true, "version": "GEMM"}}"#,
+    )
+    .unwrap();
+    let cfg = QuantizationConfig::from_config_json(&config)?;
+    assert_eq!(cfg.quant_method, QuantMethod::Awq);
+    assert_eq!((cfg.bits, cfg.group_size), (4, 128));
+    assert_eq!(cfg.version.as_deref(), Some("GEMM"));
+
+    let config: serde_json::Value = serde_json::from_str(
+        r#"{"quantization_config": {"quant_method": "gptq", "bits": 8, "group_size": -1,
+            "desc_act": true, "sym": true}}"#,
+    )
+    .unwrap();
+    let cfg = QuantizationConfig::from_config_json(&config)?;
+    assert_eq!(cfg.quant_method, QuantMethod::Gptq);
+    assert_eq!((cfg.bits, cfg.group_size, cfg.desc_act), (8, -1, true));
+    Ok(())
+}
+
+#[test]
+fn packed_var_builder() -> Result<()> {
+    let n_groups = 2;
+    let (q, zeros, scales) = reference(4, n_groups);
+    let (qweight, qzeros) = pack_gptq(4, &q, &zeros, n_groups)?;
+    let scales_t = Tensor::from_vec(scales.clone(), (n_groups, OUT), &Device::Cpu)?;
+    let bias = Tensor::arange(0f32, OUT as f32, &Device::Cpu)?;
+    let tensors = [
+        ("proj.qweight", qweight),
+        ("proj.qzeros", qzeros),
+        ("proj.scales", scales_t),
+        ("proj.bias", bias.clone()),
+    ];
+    let file = std::env::temp_dir().join(format!("candle-gptq-{}.safetensors", std::process::id()));
+    candle::safetensors::save(
+        &tensors
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+        &file,
+    )?;
+    let cfg = config(QuantMethod::Gptq, 4, 64);
+    let vb = candle_transformers::quantized_var_builder::VarBuilder::from_packed_safetensors(
+        &[&file],
+        &cfg,
+        &Device::Cpu,
+    );
+    std::fs::remove_file(&file)?;
+    let vb = vb?;
+    assert!(!vb.contains_key("proj.qweight"));
+    let linear = candle_transformers::quantized_nn::linear(IN, OUT, vb.pp("proj"))?;
+
+    let groups: Vec<usize> = (0..IN).map(|i| i / 64).collect();
+    let w = Tensor::from_vec(
+        dequantize(&q, &zeros, &scales, &groups),
+        (OUT, IN),
+        &Device::Cpu,
+    )?;
+    let xs = Tensor::arange(0f32, (3 * IN) as f32, &Device::Cpu)?
+        .affine(1. / IN as f64, -1.)?
+        .reshape((3, IN))?;
+    let expected = xs.matmul(&w.t()?)?.broadcast_add(&bias)?;
+    let ys = linear.forward(&xs)?;
+    let diff = (ys - expected)?.abs()?.max_all()?.to_scalar::<f32>()?;
+    // The activations are quantized to Q8_1 in the matmul.
+    assert!(diff < 0.5, "{diff}");
+    Ok(())
+}
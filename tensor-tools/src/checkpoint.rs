@@ -0,0 +1,125 @@
+//! Uniform access to the tensors of a checkpoint, whatever its format.
+use crate::Format;
+use candle::quantized::{ggml_file, gguf_file, QTensor};
+use candle::{DType, Device, Result, Shape, Tensor};
+use candle_transformers::gguf_convert::HfModel;
+use std::collections::HashMap;
+
+fn view_shape_and_dtype(view: &safetensors::tensor::TensorView) -> (Shape, String) {
+    let dtype = match DType::try_from(view.dtype()) {
+        Ok(dtype) => format!("{dtype:?}"),
+        Err(_) => format!("{:?}", view.dtype()),
+    };
+    (view.shape().into(), dtype)
+}
+
+pub enum Checkpoint {
+    Safetensors(candle::safetensors::MmapedSafetensors),
+    Npz(candle::npy::NpzTensors),
+    Pth(candle::pickle::PthTensors),
+    Ggml(HashMap<String, QTensor>),
+    Gguf(gguf_file::Content, std::fs::File),
+    /// A hugging face model directory, the tensors use the gguf names and layout so that they
+    /// can be compared with a converted model.
+    HfModel(Box<HfModel>),
+}
+
+impl Checkpoint {
+    pub fn open(path: &std::path::Path, format: Option<Format>) -> Result<Self> {
+        if path.is_dir() {
+            let model = unsafe { HfModel::from_dir(path)? };
+            return Ok(Self::HfModel(Box::new(model)));
+        }
+        let format = match format.or_else(|| Format::infer(path)) {
+            Some(format) => format,
+            None => candle::bail!(
+                "{path:?}: cannot infer format from file extension, use the --format flag"
+            ),
+        };
+        let checkpoint = match format {
+            Format::Safetensors => {
+                Self::Safetensors(unsafe { candle::safetensors::MmapedSafetensors::new(path)? })
+            }
+            Format::Npz => Self::Npz(candle::npy::NpzTensors::new(path)?),
+            Format::Pth => Self::Pth(candle::pickle::PthTensors::new(path, None)?),
+            Format::Ggml => {
+                let mut file = std::fs::File::open(path)?;
+                let content = ggml_file::Content::read(&mut file, &Device::Cpu)?;
+                Self::Ggml(content.tensors.into_iter().collect())
+            }
+            Format::Gguf => {
+                let mut file = std::fs::File::open(path)?;
+                let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(path))?;
+                Self::Gguf(content, file)
+            }
+            Format::Pickle => candle::bail!("pickle files do not contain named tensors"),
+        };
+        Ok(checkpoint)
+    }
+
+    /// The tensor names, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = match self {
+            Self::Safetensors(st) => st.tensors().into_iter().map(|(name, _)| name).collect(),
+            Self::Npz(npz) => npz.names().into_iter().cloned().collect(),
+            Self::Pth(pth) => pth.tensor_infos().keys().cloned().collect(),
+            Self::Ggml(tensors) => tensors.keys().cloned().collect(),
+            Self::Gguf(content, _) => content.tensor_infos.keys().cloned().collect(),
+            Self::HfModel(model) => model.tensor_names().map(|v| v.to_string()).collect(),
+        };
+        names.sort();
+        names
+    }
+
+    /// The shape of a tensor and a description of its storage type.
+    pub fn shape_and_dtype(&self, name: &str) -> Result<(Shape, String)> {
+        let shape_and_dtype = match self {
+            Self::Safetensors(st) => view_shape_and_dtype(&st.get(name)?),
+            Self::Npz(npz) => {
+                let (shape, dtype) = npz.get_shape_and_dtype(name)?;
+                (shape, format!("{dtype:?}"))
+            }
+            Self::Pth(pth) => match pth.tensor_infos().get(name) {
+                Some(info) => (info.layout.shape().clone(), format!("{:?}", info.dtype)),
+                None => candle::bail!("cannot find tensor {name}"),
+            },
+            Self::Ggml(tensors) => match tensors.get(name) {
+                Some(t) => (t.shape().clone(), format!("{:?}", t.dtype())),
+                None => candle::bail!("cannot find tensor {name}"),
+            },
+            Self::Gguf(content, _) => match content.tensor_infos.get(name) {
+                Some(info) => (info.shape.clone(), format!("{:?}", info.ggml_dtype)),
+                None => candle::bail!("cannot find tensor {name}"),
+            },
+            // The conversion to the gguf layout does not change the shapes, the dtype is the one
+            // stored in the safetensors files.
+            Self::HfModel(model) => {
+                view_shape_and_dtype(&model.safetensors().get(model.hf_name(name)?)?)
+            }
+        };
+        Ok(shape_and_dtype)
+    }
+
+    /// Loads a tensor as f32 on the cpu, the quantized tensors are dequantized.
+    pub fn tensor(&mut self, name: &str) -> Result<Tensor> {
+        let device = &Device::Cpu;
+        let tensor = match self {
+            Self::Safetensors(st) => st.load(name, device)?,
+            Self::Npz(npz) => match npz.get(name)? {
+                Some(tensor) => tensor,
+                None => candle::bail!("cannot find tensor {name}"),
+            },
+            Self::Pth(pth) => match pth.get(name)? {
+                Some(tensor) => tensor,
+                None => candle::bail!("cannot find tensor {name}"),
+            },
+            Self::Ggml(tensors) => match tensors.get(name) {
+                Some(tensor) => tensor.dequantize(device)?,
+                None => candle::bail!("cannot find tensor {name}"),
+            },
+            Self::Gguf(content, file) => content.tensor(file, name, device)?.dequantize(device)?,
+            Self::HfModel(model) => model.tensor(name)?,
+        };
+        tensor.to_dtype(DType::F32)
+    }
+}
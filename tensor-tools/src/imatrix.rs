@@ -0,0 +1,130 @@
+//! Computes an importance matrix by running a gguf model over a calibration text.
+use candle::quantized::{calibration, gguf_file, imatrix_file};
+use candle::{Device, Result, Tensor};
+use candle_transformers::gguf_tokenizer::GgufTokenizer;
+use candle_transformers::models::{
+    quantized_llama, quantized_phi3, quantized_qwen2, quantized_qwen3,
+};
+
+enum Model {
+    Llama(quantized_llama::ModelWeights),
+    Phi3(quantized_phi3::ModelWeights),
+    Qwen2(quantized_qwen2::ModelWeights),
+    Qwen3(quantized_qwen3::ModelWeights),
+}
+
+impl Model {
+    fn from_gguf(content: gguf_file::Content, file: &mut std::fs::File) -> Result<Self> {
+        let arch = match content.metadata.get("general.architecture") {
+            Some(arch) => arch.to_string()?.clone(),
+            None => candle::bail!("missing general.architecture in gguf metadata"),
+        };
+        let device = &Device::Cpu;
+        let model = match arch.as_str() {
+            "llama" => Self::Llama(quantized_llama::ModelWeights::from_gguf(
+                content, file, device,
+            )?),
+            "phi3" => Self::Phi3(quantized_phi3::ModelWeights::from_gguf(
+                false, content, file, device,
+            )?),
+            "qwen2" => Self::Qwen2(quantized_qwen2::ModelWeights::from_gguf(
+                content, file, device,
+            )?),
+            "qwen3" => Self::Qwen3(quantized_qwen3::ModelWeights::from_gguf(
+                content, file, device,
+            )?),
+            arch => candle::bail!("unsupported gguf architecture {arch}"),
+        };
+        Ok(model)
+    }
+
+    // Processes a chunk from a fresh kv-cache, the outputs are not used.
+    fn forward(&mut self, input: &Tensor) -> Result<()> {
+        match self {
+            Self::Llama(m) => m.forward(input, 0)?,
+            Self::Phi3(m) => m.forward(input, 0)?,
+            Self::Qwen2(m) => m.forward(input, 0)?,
+            Self::Qwen3(m) => {
+                m.clear_kv_cache();
+                m.forward(input, 0)?
+            }
+        };
+        Ok(())
+    }
+}
+
+fn process_chunks(
+    calibration: &calibration::Calibration,
+    content: gguf_file::Content,
+    file: &mut std::fs::File,
+    tokens: &[u32],
+    bos_token_id: Option<u32>,
+    chunk_size: usize,
+    n_chunks: usize,
+) -> Result<()> {
+    // The weights are hooked when loaded within the calibration.
+    let mut model = calibration.load(|| Model::from_gguf(content, file))?;
+    for (i, chunk) in tokens.chunks_exact(chunk_size).take(n_chunks).enumerate() {
+        let mut chunk = chunk.to_vec();
+        // Each chunk starts with a bos token, as when running the model on a new prompt.
+        if let Some(bos) = bos_token_id {
+            chunk[0] = bos
+        }
+        let start = std::time::Instant::now();
+        let input = Tensor::new(chunk, &Device::Cpu)?.unsqueeze(0)?;
+        model.forward(&input)?;
+        println!(
+            "  chunk {}/{n_chunks} processed in {:.2}s",
+            i + 1,
+            start.elapsed().as_secs_f32()
+        );
+    }
+    Ok(())
+}
+
+pub fn run(
+    model: &std::path::Path,
+    text_file: &std::path::Path,
+    out_file: &std::path::Path,
+    chunk_size: usize,
+    n_chunks: Option<usize>,
+) -> Result<()> {
+    if chunk_size < 2 {
+        candle::bail!("the chunk size must be at least 2, got {chunk_size}")
+    }
+    let mut file = std::fs::File::open(model)?;
+    let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(model))?;
+    let tokenizer = GgufTokenizer::from_gguf(&content)?;
+    let text = std::fs::read_to_string(text_file)?;
+    let tokens = tokenizer
+        .tokenizer
+        .encode(text, false)
+        .map_err(candle::Error::msg)?
+        .get_ids()
+        .to_vec();
+    let available = tokens.len() / chunk_size;
+    let n_chunks = n_chunks.map_or(available, |n| n.min(available));
+    if n_chunks == 0 {
+        candle::bail!(
+            "{text_file:?} has {} tokens, at least {chunk_size} are needed",
+            tokens.len()
+        )
+    }
+    println!("tokens: {}, chunks: {n_chunks}", tokens.len());
+
+    let calibration = calibration::Calibration::new();
+    process_chunks(
+        &calibration,
+        content,
+        &mut file,
+        &tokens,
+        tokenizer.bos_token_id,
+        chunk_size,
+        n_chunks,
+    )?;
+    let entries = calibration.imatrix();
+    let dataset = text_file.to_string_lossy();
+    imatrix_file::save_imatrix(out_file, &entries, n_chunks, &dataset)?;
+    println!("wrote {} entries to {out_file:?}", entries.len());
+    Ok(())
+}
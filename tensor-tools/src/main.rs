@@ -1,30 +1,58 @@
 use candle::quantized::{gguf_file, GgmlDType, QTensor};
-use candle::{Device, Result};
+use candle::{DType, Device, Result};
 use clap::{Parser, Subcommand, ValueEnum};
 use rayon::prelude::*;
 
+mod checkpoint;
+mod imatrix;
+mod metadata;
+mod recipe;
+mod stats;
+
+type Imatrix = std::collections::HashMap<String, Vec<f32>>;
+
 #[derive(ValueEnum, Debug, Clone)]
 enum QuantizationMode {
-    /// The default quantization includes all 2d tensors, except the output tensor which always
-    /// uses Q6_K.
+    /// The default quantization includes all 2d tensors, except the output tensor which uses
+    /// Q6_K unless the target type is more precise. The 1d tensors are kept in f32.
     Llama,
 }
 
 impl QuantizationMode {
-    fn quantize(&self, name: &str, tensor: QTensor, dtype: GgmlDType) -> Result<QTensor> {
+    fn quantize(
+        &self,
+        name: &str,
+        tensor: QTensor,
+        recipe: &recipe::Recipe,
+        imatrix: Option<&Imatrix>,
+    ) -> Result<QTensor> {
         match self {
             Self::Llama => {
                 // Same behavior as the llama.cpp quantization.
-                let should_quantize = name.ends_with(".weight") && tensor.rank() == 2;
-                if should_quantize {
+                if tensor.rank() == 1 && tensor.dtype() != GgmlDType::F32 {
                     let tensor = tensor.dequantize(&Device::Cpu)?;
-                    if name == "output.weight" {
-                        QTensor::quantize(&tensor, GgmlDType::Q6K)
+                    return QTensor::quantize(&tensor, GgmlDType::F32);
+                }
+                let should_quantize = name.ends_with(".weight") && tensor.rank() == 2;
+                if !should_quantize {
+                    return Ok(tensor);
+                }
+                let tensor = tensor.dequantize(&Device::Cpu)?;
+                let mut dtype = recipe.dtype(name)?;
+                let n_per_row = tensor.dim(1)?;
+                if !n_per_row.is_multiple_of(dtype.block_size()) {
+                    // Fallback for the rows that do not fit the k-quants super-blocks.
+                    dtype = if n_per_row.is_multiple_of(GgmlDType::Q8_0.block_size()) {
+                        GgmlDType::Q8_0
                     } else {
-                        QTensor::quantize(&tensor, dtype)
+                        GgmlDType::F16
+                    }
+                }
+                match imatrix.and_then(|imatrix| imatrix.get(name)) {
+                    Some(weights) if dtype.supports_imatrix() => {
+                        QTensor::quantize_imatrix(&tensor, weights, dtype)
                     }
-                } else {
-                    Ok(tensor)
+                    _ => QTensor::quantize(&tensor, dtype),
                 }
             }
         }
@@ -51,8 +79,42 @@ enum Quantization {
     Q5k,
     Q6k,
     Q8k,
+    Iq2xxs,
+    Iq2xs,
+    Iq2s,
+    Iq3xxs,
+    Iq3s,
+    Iq4nl,
+    Iq4xs,
+    Iq1s,
+    Iq1m,
+    #[value(name = "tq1_0")]
+    Tq1_0,
+    #[value(name = "tq2_0")]
+    Tq2_0,
     F16,
     F32,
+    /// Mostly Q3_K, the llama.cpp Q3_K_S mixture.
+    #[value(name = "q3_k_s")]
+    Q3kS,
+    /// Mostly Q3_K with Q4_K/Q5_K for attn_v, attn_output and ffn_down.
+    #[value(name = "q3_k_m")]
+    Q3kM,
+    /// Mostly Q3_K with Q5_K for attn_v, attn_output and ffn_down.
+    #[value(name = "q3_k_l")]
+    Q3kL,
+    /// Mostly Q4_K with Q5_K for attn_v and ffn_down in the first layers.
+    #[value(name = "q4_k_s")]
+    Q4kS,
+    /// Mostly Q4_K with Q6_K for half of the attn_v and ffn_down tensors.
+    #[value(name = "q4_k_m")]
+    Q4kM,
+    /// Mostly Q5_K, the llama.cpp Q5_K_S mixture.
+    #[value(name = "q5_k_s")]
+    Q5kS,
+    /// Mostly Q5_K with Q6_K for half of the attn_v and ffn_down tensors.
+    #[value(name = "q5_k_m")]
+    Q5kM,
 }
 
 impl Quantization {
@@ -70,8 +132,22 @@ impl Quantization {
             Quantization::Q5k => GgmlDType::Q5K,
             Quantization::Q6k => GgmlDType::Q6K,
             Quantization::Q8k => GgmlDType::Q8K,
+            Quantization::Iq2xxs => GgmlDType::IQ2XXS,
+            Quantization::Iq2xs => GgmlDType::IQ2XS,
+            Quantization::Iq2s => GgmlDType::IQ2S,
+            Quantization::Iq3xxs => GgmlDType::IQ3XXS,
+            Quantization::Iq3s => GgmlDType::IQ3S,
+            Quantization::Iq4nl => GgmlDType::IQ4NL,
+            Quantization::Iq4xs => GgmlDType::IQ4XS,
+            Quantization::Iq1s => GgmlDType::IQ1S,
+            Quantization::Iq1m => GgmlDType::IQ1M,
+            Quantization::Tq1_0 => GgmlDType::TQ1_0,
+            Quantization::Tq2_0 => GgmlDType::TQ2_0,
             Quantization::F16 => GgmlDType::F16,
             Quantization::F32 => GgmlDType::F32,
+            Quantization::Q3kS | Quantization::Q3kM | Quantization::Q3kL => GgmlDType::Q3K,
+            Quantization::Q4kS | Quantization::Q4kM => GgmlDType::Q4K,
+            Quantization::Q5kS | Quantization::Q5kM => GgmlDType::Q5K,
         }
     }
 }
@@ -150,6 +226,20 @@ enum Command {
         /// Which tensor to quantize.
         #[arg(long, value_enum, default_value_t = QuantizationMode::Llama)]
         mode: QuantizationMode,
+
+        /// Override the quantization of the tensors matching a regex, e.g.
+        /// `--override 'ffn_down=q6k'`, can be repeated. The first matching override is used.
+        #[arg(long = "override")]
+        overrides: Vec<String>,
+
+        /// An importance matrix in the llama.cpp format, used for the tensors it covers.
+        #[arg(long)]
+        imatrix: Option<std::path::PathBuf>,
+
+        /// The metadata to write in the output file, either from a gguf file or derived from a
+        /// hugging face config.json. By default the metadata of the input gguf file is kept.
+        #[arg(long)]
+        metadata: Option<std::path::PathBuf>,
     },
 
     Dequantize {
@@ -160,6 +250,102 @@ enum Command {
         #[arg(long)]
         out_file: std::path::PathBuf,
     },
+
+    /// Convert a hugging face model directory, with a config.json and safetensors weights, to a
+    /// gguf file including the hyper-parameters and tokenizer metadata.
+    ConvertHf {
+        /// The model directory.
+        model_dir: std::path::PathBuf,
+
+        /// The output file, in gguf format.
+        #[arg(long)]
+        out_file: std::path::PathBuf,
+
+        /// The quantization schema to apply.
+        #[arg(long, value_enum, default_value_t = Quantization::F16)]
+        quantization: Quantization,
+
+        /// Override the quantization of the tensors matching a regex, e.g.
+        /// `--override 'ffn_down=q6k'`, can be repeated. The first matching override is used.
+        #[arg(long = "override")]
+        overrides: Vec<String>,
+
+        /// An importance matrix in the llama.cpp format, used for the tensors it covers.
+        #[arg(long)]
+        imatrix: Option<std::path::PathBuf>,
+    },
+
+    /// Compute an importance matrix by running a gguf model over some calibration text, the
+    /// result uses the llama.cpp imatrix format.
+    Imatrix {
+        /// The model, in gguf format.
+        model: std::path::PathBuf,
+
+        /// The calibration text.
+        #[arg(long)]
+        text_file: std::path::PathBuf,
+
+        /// The output file.
+        #[arg(long, default_value = "imatrix.dat")]
+        out_file: std::path::PathBuf,
+
+        /// The number of tokens in each chunk processed by the model.
+        #[arg(long, default_value_t = 512)]
+        chunk_size: usize,
+
+        /// The maximum number of chunks to process, all the text is used by default.
+        #[arg(long)]
+        n_chunks: Option<usize>,
+    },
+
+    /// Compare the tensors of two checkpoints, possibly in different formats, reporting the
+    /// errors and cosine similarity of the tensors with the same name as well as the shape
+    /// mismatches. A hugging face model directory uses the gguf names and layout.
+    Diff {
+        lhs: std::path::PathBuf,
+
+        rhs: std::path::PathBuf,
+
+        /// The format of the first file, if unspecified infer from the file extension.
+        #[arg(long, value_enum)]
+        lhs_format: Option<Format>,
+
+        /// The format of the second file, if unspecified infer from the file extension.
+        #[arg(long, value_enum)]
+        rhs_format: Option<Format>,
+
+        /// Count the tensors with a larger max absolute error as mismatches.
+        #[arg(long)]
+        tolerance: Option<f64>,
+    },
+
+    /// Print the min, max, mean, standard deviation and the number of NaN and infinite values
+    /// of each tensor.
+    Stats {
+        file: std::path::PathBuf,
+
+        /// The tensors to report, all of them by default.
+        names: Vec<String>,
+
+        /// The file format to use, if unspecified infer from the file extension.
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+
+        /// The number of histogram bins, zero to disable the histograms.
+        #[arg(long, default_value_t = 10)]
+        bins: usize,
+    },
+
+    /// Check that the metadata and tensors of a gguf file match what the quantized model of
+    /// its architecture expects.
+    Validate {
+        /// The input file, in gguf format.
+        file: std::path::PathBuf,
+
+        /// The architecture to check against, by default the one from the gguf metadata.
+        #[arg(long)]
+        arch: Option<String>,
+    },
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -399,6 +585,7 @@ fn run_quantize_safetensors(
     in_files: &[std::path::PathBuf],
     out_file: std::path::PathBuf,
     q: Quantization,
+    options: &QuantizeOptions,
 ) -> Result<()> {
     let mut out_file = std::fs::File::create(out_file)?;
     let mut tensors = std::collections::HashMap::new();
@@ -408,19 +595,14 @@ fn run_quantize_safetensors(
     }
     println!("tensors: {}", tensors.len());
 
-    let dtype = q.dtype();
-    let block_size = dtype.block_size();
-
+    let recipe = recipe::Recipe::new(q, tensors.keys().map(|k| k.as_str()), &options.overrides)?;
+    let imatrix = options.imatrix_for(tensors.keys().map(|k| k.as_str()))?;
     let qtensors = tensors
         .into_par_iter()
         .map(|(name, tensor)| {
-            let should_quantize = tensor.rank() == 2 && tensor.dim(1)? % block_size == 0;
-            println!("  quantizing {name} {tensor:?} {should_quantize}");
-            let tensor = if should_quantize {
-                QTensor::quantize(&tensor, dtype)?
-            } else {
-                QTensor::quantize(&tensor, GgmlDType::F32)?
-            };
+            println!("  quantizing {name} {tensor:?}");
+            let tensor = QTensor::quantize(&tensor.to_dtype(DType::F32)?, GgmlDType::F32)?;
+            let tensor = options.quantize(&name, tensor, &recipe, imatrix.as_ref())?;
             Ok((name, tensor))
         })
         .collect::<Result<Vec<_>>>()?;
@@ -428,7 +610,11 @@ fn run_quantize_safetensors(
         .iter()
         .map(|(k, v)| (k.as_str(), v))
         .collect::<Vec<_>>();
-    gguf_file::write(&mut out_file, &[], &qtensors)?;
+    let metadata = match options.metadata.as_ref() {
+        None => vec![],
+        Some(metadata) => metadata.iter().map(|(k, v)| (k.as_str(), v)).collect(),
+    };
+    gguf_file::write(&mut out_file, &metadata, &qtensors)?;
     Ok(())
 }
 
@@ -449,11 +635,56 @@ fn run_dequantize(
     Ok(())
 }
 
+struct QuantizeOptions {
+    mode: QuantizationMode,
+    overrides: Vec<String>,
+    imatrix: Option<Imatrix>,
+    metadata: Option<Vec<(String, gguf_file::Value)>>,
+}
+
+impl QuantizeOptions {
+    fn quantize(
+        &self,
+        name: &str,
+        tensor: QTensor,
+        recipe: &recipe::Recipe,
+        imatrix: Option<&Imatrix>,
+    ) -> Result<QTensor> {
+        self.mode.quantize(name, tensor, recipe, imatrix)
+    }
+
+    /// The imatrix entries indexed by the names of the tensors to quantize. Imatrix files use the
+    /// gguf names so the hugging face names are mapped to these, an imatrix that does not cover
+    /// any of the tensors is an error rather than being silently ignored.
+    fn imatrix_for<'a>(&self, names: impl Iterator<Item = &'a str>) -> Result<Option<Imatrix>> {
+        use candle_transformers::gguf_convert::Architecture;
+        let imatrix = match self.imatrix.as_ref() {
+            None => return Ok(None),
+            Some(imatrix) => imatrix,
+        };
+        let mut entries = Imatrix::new();
+        for name in names {
+            // The linear weights have the same gguf names for all the architectures.
+            let gguf_name = Architecture::Llama.tensor_name(name);
+            let weights = imatrix
+                .get(name)
+                .or_else(|| gguf_name.and_then(|n| imatrix.get(&n)));
+            if let Some(weights) = weights {
+                entries.insert(name.to_string(), weights.clone());
+            }
+        }
+        if entries.is_empty() {
+            candle::bail!("the imatrix does not cover any of the tensors to quantize")
+        }
+        Ok(Some(entries))
+    }
+}
+
 fn run_quantize(
     in_files: &[std::path::PathBuf],
     out_file: std::path::PathBuf,
     q: Quantization,
-    qmode: QuantizationMode,
+    options: &QuantizeOptions,
     device: &Device,
 ) -> Result<()> {
     if in_files.is_empty() {
@@ -466,7 +697,7 @@ fn run_quantize(
     }
     if let Some(extension) = in_files[0].extension() {
         if extension == "safetensors" {
-            return run_quantize_safetensors(in_files, out_file, q);
+            return run_quantize_safetensors(in_files, out_file, q, options);
         }
     }
 
@@ -480,7 +711,9 @@ fn run_quantize(
     let content = gguf_file::Content::read(&mut in_)?;
     println!("tensors: {}", content.tensor_infos.len());
 
-    let dtype = q.dtype();
+    let names = content.tensor_infos.keys().map(|k| k.as_str());
+    let recipe = recipe::Recipe::new(q, names, &options.overrides)?;
+    let imatrix = options.imatrix_for(content.tensor_infos.keys().map(|k| k.as_str()))?;
     let qtensors = content
         .tensor_infos
         .par_iter()
@@ -488,7 +721,7 @@ fn run_quantize(
             println!("  quantizing {name}");
             let mut in_file = std::fs::File::open(&in_files[0])?;
             let tensor = content.tensor(&mut in_file, name, device)?;
-            let tensor = qmode.quantize(name, tensor, dtype)?;
+            let tensor = options.quantize(name, tensor, &recipe, imatrix.as_ref())?;
             Ok((name, tensor))
         })
         .collect::<Result<Vec<_>>>()?;
@@ -497,15 +730,66 @@ fn run_quantize(
         .map(|(k, v)| (k.as_str(), v))
         .collect::<Vec<_>>();
 
-    let metadata = content
-        .metadata
-        .iter()
-        .map(|(k, v)| (k.as_str(), v))
-        .collect::<Vec<_>>();
+    let metadata = match options.metadata.as_ref() {
+        None => content
+            .metadata
+            .iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .collect::<Vec<_>>(),
+        Some(metadata) => metadata.iter().map(|(k, v)| (k.as_str(), v)).collect(),
+    };
     gguf_file::write(&mut out_file, metadata.as_slice(), &qtensors)?;
     Ok(())
 }
 
+fn run_convert_hf(
+    model_dir: &std::path::Path,
+    out_file: std::path::PathBuf,
+    q: Quantization,
+    options: &QuantizeOptions,
+) -> Result<()> {
+    let model = unsafe { candle_transformers::gguf_convert::HfModel::from_dir(model_dir)? };
+    println!(
+        "architecture: {}, tensors: {}",
+        model.architecture().gguf_name(),
+        model.tensor_names().count()
+    );
+    let mut out_file = std::fs::File::create(out_file)?;
+    let recipe = recipe::Recipe::new(q, model.tensor_names(), &options.overrides)?;
+    let imatrix = options.imatrix_for(model.tensor_names())?;
+    model.write_gguf(&mut out_file, |name, tensor| {
+        println!("  converting {name} {tensor:?}");
+        let tensor = QTensor::quantize(&tensor, GgmlDType::F32)?;
+        options.quantize(name, tensor, &recipe, imatrix.as_ref())
+    })
+}
+
+fn run_validate(file: &std::path::Path, arch: Option<String>) -> Result<()> {
+    use candle_transformers::gguf_convert::{self, Architecture};
+    let mut reader = std::fs::File::open(file)?;
+    let content = gguf_file::Content::read(&mut reader).map_err(|e| e.with_path(file))?;
+    let arch = match arch {
+        Some(arch) => arch,
+        None => match content.metadata.get("general.architecture") {
+            Some(arch) => arch.to_string()?.clone(),
+            None => candle::bail!("missing general.architecture, use the --arch flag"),
+        },
+    };
+    let arch = Architecture::from_gguf_name(&arch)?;
+    let report = gguf_convert::validate_gguf(&content, arch);
+    for warning in report.warnings.iter() {
+        println!("warning: {warning}")
+    }
+    for error in report.errors.iter() {
+        println!("error: {error}")
+    }
+    if !report.is_ok() {
+        candle::bail!("{file:?} is not a valid {} model", arch.gguf_name())
+    }
+    println!("{file:?} is a valid {} model", arch.gguf_name());
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     let device = Device::Cpu;
@@ -535,8 +819,143 @@ fn main() -> anyhow::Result<()> {
             out_file,
             quantization,
             mode,
-        } => run_quantize(&in_file, out_file, quantization, mode, &device)?,
+            overrides,
+            imatrix,
+            metadata,
+        } => {
+            let imatrix = match imatrix {
+                None => None,
+                Some(imatrix) => Some(candle::quantized::imatrix_file::load_imatrix(imatrix)?),
+            };
+            let metadata = match metadata {
+                None => None,
+                Some(metadata) => Some(metadata::load(&metadata)?),
+            };
+            let options = QuantizeOptions {
+                mode,
+                overrides,
+                imatrix,
+                metadata,
+            };
+            run_quantize(&in_file, out_file, quantization, &options, &device)?
+        }
         Command::Dequantize { in_file, out_file } => run_dequantize(in_file, out_file, &device)?,
+        Command::ConvertHf {
+            model_dir,
+            out_file,
+            quantization,
+            overrides,
+            imatrix,
+        } => {
+            let imatrix = match imatrix {
+                None => None,
+                Some(imatrix) => Some(candle::quantized::imatrix_file::load_imatrix(imatrix)?),
+            };
+            let options = QuantizeOptions {
+                mode: QuantizationMode::Llama,
+                overrides,
+                imatrix,
+                metadata: None,
+            };
+            run_convert_hf(&model_dir, out_file, quantization, &options)?
+        }
+        Command::Imatrix {
+            model,
+            text_file,
+            out_file,
+            chunk_size,
+            n_chunks,
+        } => imatrix::run(&model, &text_file, &out_file, chunk_size, n_chunks)?,
+        Command::Diff {
+            lhs,
+            rhs,
+            lhs_format,
+            rhs_format,
+            tolerance,
+        } => {
+            let mut lhs = checkpoint::Checkpoint::open(&lhs, lhs_format)?;
+            let mut rhs = checkpoint::Checkpoint::open(&rhs, rhs_format)?;
+            let mismatches = stats::run_diff(&mut lhs, &mut rhs, tolerance)?;
+            if mismatches > 0 {
+                anyhow::bail!("the checkpoints differ")
+            }
+        }
+        Command::Stats {
+            file,
+            names,
+            format,
+            bins,
+        } => {
+            let mut checkpoint = checkpoint::Checkpoint::open(&file, format)?;
+            stats::run_stats(&mut checkpoint, &names, bins)?
+        }
+        Command::Validate { file, arch } => run_validate(&file, arch)?,
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle::Tensor;
+
+    #[test]
+    fn quantize_with_imatrix() -> Result<()> {
+        let names = ["blk.0.ffn_down.weight", "blk.0.attn_q.weight"];
+        for (n_cols, quantization, overrides, expected) in [
+            (256, Quantization::Q4k, vec![], GgmlDType::Q4K),
+            (96, Quantization::Q4_0, vec![], GgmlDType::Q4_0),
+            (96, Quantization::Q8_0, vec![], GgmlDType::Q8_0),
+            // The rows do not fit the k-quants super-blocks so these fall back to Q8_0.
+            (96, Quantization::Q4k, vec![], GgmlDType::Q8_0),
+            (
+                256,
+                Quantization::Q4kM,
+                vec![".*=q8_0".to_string()],
+                GgmlDType::Q8_0,
+            ),
+        ] {
+            let w = Tensor::arange(0f32, (16 * n_cols) as f32, &Device::Cpu)?
+                .affine(1e-3, -1.)?
+                .reshape((16, n_cols))?;
+            let imatrix: Imatrix = names
+                .iter()
+                .map(|name| (name.to_string(), vec![1f32; n_cols]))
+                .collect();
+            let recipe = recipe::Recipe::new(quantization, names.into_iter(), &overrides)?;
+            for name in names {
+                let tensor = QTensor::quantize(&w, GgmlDType::F32)?;
+                let tensor =
+                    QuantizationMode::Llama.quantize(name, tensor, &recipe, Some(&imatrix))?;
+                assert_eq!(tensor.dtype(), expected, "{name} {n_cols}");
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn imatrix_names() -> Result<()> {
+        let imatrix: Imatrix = [("blk.3.ffn_down.weight".to_string(), vec![1f32; 4])]
+            .into_iter()
+            .collect();
+        let options = QuantizeOptions {
+            mode: QuantizationMode::Llama,
+            overrides: vec![],
+            imatrix: Some(imatrix),
+            metadata: None,
+        };
+        for name in [
+            "blk.3.ffn_down.weight",
+            "model.layers.3.mlp.down_proj.weight",
+        ] {
+            let entries = options
+                .imatrix_for([name, "model.layers.3.mlp.up_proj.weight"].into_iter())?
+                .unwrap();
+            assert_eq!(entries.keys().collect::<Vec<_>>(), [name]);
+        }
+        assert!(options
+            .imatrix_for(["model.layers.0.mlp.down_proj.weight"].into_iter())
+            .is_err());
+        Ok(())
+    }
+}
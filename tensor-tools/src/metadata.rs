@@ -0,0 +1,21 @@
+//! The gguf metadata written alongside the quantized tensors.
+use candle::quantized::gguf_file::{self, Value};
+use candle::Result;
+use candle_transformers::gguf_convert;
+
+/// Reads the metadata from a gguf file, or builds it from the content of a hugging face
+/// `config.json` file.
+pub fn load(path: &std::path::Path) -> Result<Vec<(String, Value)>> {
+    if path.extension().is_some_and(|e| e == "json") {
+        let config = std::fs::read_to_string(path)?;
+        let config: serde_json::Value =
+            serde_json::from_str(&config).map_err(candle::Error::wrap)?;
+        gguf_convert::metadata_from_config(&config)
+    } else {
+        let mut file = std::fs::File::open(path)?;
+        let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(path))?;
+        let mut metadata = content.metadata.into_iter().collect::<Vec<_>>();
+        metadata.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(metadata)
+    }
+}
@@ -0,0 +1,244 @@
+//! The quantization type of each tensor, following the llama.cpp quantization mixtures.
+//!
+//! The mixtures such as `q4_k_m` use more bits for the tensors that are the most sensitive to
+//! quantization: the value projection of the attention and the down projection of the mlp, in
+//! particular in the first and last layers. Both the gguf and the hugging face tensor names are
+//! recognized.
+use crate::Quantization;
+use candle::quantized::GgmlDType;
+use candle::Result;
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TensorKind {
+    Output,
+    TokenEmbd,
+    AttnV,
+    AttnOutput,
+    FfnDown,
+    Other,
+}
+
+impl TensorKind {
+    fn of(name: &str) -> Self {
+        let name = name.strip_suffix(".weight").unwrap_or(name);
+        let last = name.rsplit('.').next().unwrap_or(name);
+        match last {
+            "output" | "lm_head" if !name.contains("blk.") && !name.contains("layers.") => {
+                Self::Output
+            }
+            "token_embd" | "embed_tokens" | "wte" => Self::TokenEmbd,
+            "attn_v" | "v_proj" => Self::AttnV,
+            "attn_output" | "o_proj" => Self::AttnOutput,
+            "ffn_down" | "down_proj" => Self::FfnDown,
+            _ => Self::Other,
+        }
+    }
+}
+
+fn layer_index(name: &str) -> Option<usize> {
+    ["blk.", "layers."].iter().find_map(|prefix| {
+        let (_, rest) = name.split_once(prefix)?;
+        rest.split('.').next()?.parse().ok()
+    })
+}
+
+// The layers that get more bits in the _M mixtures: the first and last eighth of the layers and
+// one layer out of three in between.
+fn use_more_bits(i_layer: usize, n_layers: usize) -> bool {
+    i_layer < n_layers / 8 || i_layer >= 7 * n_layers / 8 || (i_layer - n_layers / 8) % 3 == 2
+}
+
+pub struct Recipe {
+    quantization: Quantization,
+    n_layers: usize,
+    tied_embeddings: bool,
+    overrides: Vec<(fancy_regex::Regex, GgmlDType)>,
+}
+
+impl Recipe {
+    /// Creates the recipe for a model with the given tensor names, `overrides` are
+    /// `regex=dtype` strings that take precedence over the mixture, e.g. `ffn_down=q6k`.
+    pub fn new<'a>(
+        quantization: Quantization,
+        names: impl Iterator<Item = &'a str>,
+        overrides: &[String],
+    ) -> Result<Self> {
+        let mut n_layers = 0;
+        let mut tied_embeddings = true;
+        for name in names {
+            if let Some(i) = layer_index(name) {
+                n_layers = usize::max(n_layers, i + 1)
+            }
+            if TensorKind::of(name) == TensorKind::Output {
+                tied_embeddings = false
+            }
+        }
+        let overrides = overrides
+            .iter()
+            .map(|o| {
+                let (re, dtype) = match o.rsplit_once('=') {
+                    Some(v) => v,
+                    None => candle::bail!("invalid override {o}, expected regex=dtype"),
+                };
+                let re = fancy_regex::Regex::new(re).map_err(candle::Error::wrap)?;
+                let dtype = match Quantization::from_str(dtype, true) {
+                    Ok(q) => q.dtype(),
+                    Err(_) => candle::bail!("invalid dtype in override {o}"),
+                };
+                Ok((re, dtype))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            quantization,
+            n_layers,
+            tied_embeddings,
+            overrides,
+        })
+    }
+
+    /// The quantization type to use for the 2d weight `name`.
+    pub fn dtype(&self, name: &str) -> Result<GgmlDType> {
+        for (re, dtype) in self.overrides.iter() {
+            if re.is_match(name).map_err(candle::Error::wrap)? {
+                return Ok(*dtype);
+            }
+        }
+        Ok(self.mixture_dtype(name))
+    }
+
+    fn mixture_dtype(&self, name: &str) -> GgmlDType {
+        use GgmlDType as T;
+        use Quantization as Q;
+        let base = self.quantization.dtype();
+        let n = self.n_layers;
+        let i = layer_index(name).unwrap_or(0);
+        // The output is kept as is when converting to a type at least as precise as Q6_K.
+        let output = match base {
+            T::F32 | T::F16 | T::BF16 | T::Q8_0 => base,
+            _ => T::Q6K,
+        };
+        match TensorKind::of(name) {
+            TensorKind::Output => output,
+            // Tied embeddings are also used as the output projection.
+            TensorKind::TokenEmbd if self.tied_embeddings => output,
+            TensorKind::TokenEmbd | TensorKind::Other => base,
+            TensorKind::AttnV => match self.quantization {
+                Q::Q3kM if i < 2 => T::Q5K,
+                Q::Q3kM => T::Q4K,
+                Q::Q3kL => T::Q5K,
+                Q::Q4kS if i < 4 => T::Q5K,
+                Q::Q4kM | Q::Q5kM if use_more_bits(i, n) => T::Q6K,
+                _ => base,
+            },
+            TensorKind::FfnDown => match self.quantization {
+                Q::Q3kM if i < n / 16 => T::Q5K,
+                Q::Q3kM if use_more_bits(i, n) => T::Q4K,
+                Q::Q3kL => T::Q5K,
+                Q::Q4kS if i < n / 8 => T::Q5K,
+                Q::Q4kM | Q::Q5kM if use_more_bits(i, n) => T::Q6K,
+                _ => base,
+            },
+            TensorKind::AttnOutput => match self.quantization {
+                Q::Q3kM => T::Q4K,
+                Q::Q3kL => T::Q5K,
+                _ => base,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(quantization: Quantization, overrides: &[&str]) -> Result<Recipe> {
+        let mut names = vec!["token_embd.weight".to_string(), "output.weight".to_string()];
+        for i in 0..32 {
+            for tensor in ["attn_v", "attn_output", "ffn_down", "ffn_up"] {
+                names.push(format!("blk.{i}.{tensor}.weight"))
+            }
+        }
+        let overrides = overrides.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        Recipe::new(quantization, names.iter().map(|n| n.as_str()), &overrides)
+    }
+
+    #[test]
+    fn mixture_dtype() -> Result<()> {
+        use GgmlDType as T;
+        use Quantization as Q;
+        let table = [
+            (Q::Q3kM, "blk.0.attn_v.weight", T::Q5K),
+            (Q::Q3kM, "blk.1.attn_v.weight", T::Q5K),
+            (Q::Q3kM, "blk.2.attn_v.weight", T::Q4K),
+            (Q::Q3kM, "blk.31.attn_v.weight", T::Q4K),
+            (Q::Q3kM, "blk.1.ffn_down.weight", T::Q5K),
+            (Q::Q3kM, "blk.2.ffn_down.weight", T::Q4K),
+            (Q::Q3kM, "blk.5.ffn_down.weight", T::Q3K),
+            (Q::Q3kM, "blk.6.ffn_down.weight", T::Q4K),
+            (Q::Q3kM, "blk.28.ffn_down.weight", T::Q4K),
+            (Q::Q3kM, "blk.7.attn_output.weight", T::Q4K),
+            (Q::Q3kM, "blk.7.ffn_up.weight", T::Q3K),
+            (Q::Q3kM, "output.weight", T::Q6K),
+            (Q::Q4kM, "blk.0.attn_v.weight", T::Q6K),
+            (Q::Q4kM, "blk.3.attn_v.weight", T::Q6K),
+            (Q::Q4kM, "blk.4.attn_v.weight", T::Q4K),
+            (Q::Q4kM, "blk.6.attn_v.weight", T::Q6K),
+            (Q::Q4kM, "blk.7.attn_v.weight", T::Q4K),
+            (Q::Q4kM, "blk.27.attn_v.weight", T::Q6K),
+            (Q::Q4kM, "blk.28.attn_v.weight", T::Q6K),
+            (Q::Q4kM, "blk.0.ffn_down.weight", T::Q6K),
+            (Q::Q4kM, "blk.5.ffn_down.weight", T::Q4K),
+            (Q::Q4kM, "blk.9.ffn_down.weight", T::Q6K),
+            (Q::Q4kM, "blk.31.ffn_down.weight", T::Q6K),
+            (Q::Q4kM, "blk.7.attn_output.weight", T::Q4K),
+            (Q::Q4kM, "output.weight", T::Q6K),
+            (Q::Q4kM, "token_embd.weight", T::Q4K),
+            // The hugging face names get the same types.
+            (Q::Q4kM, "model.layers.6.self_attn.v_proj.weight", T::Q6K),
+            (Q::Q4kM, "model.layers.7.mlp.down_proj.weight", T::Q4K),
+            (Q::Q4kM, "lm_head.weight", T::Q6K),
+        ];
+        for (quantization, name, expected) in table {
+            let dtype = recipe(quantization.clone(), &[])?.dtype(name)?;
+            assert_eq!(dtype, expected, "{quantization:?} {name}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tied_embeddings() -> Result<()> {
+        let names = ["token_embd.weight", "blk.0.ffn_down.weight"];
+        let recipe = Recipe::new(Quantization::Q4kM, names.into_iter(), &[])?;
+        assert_eq!(recipe.dtype("token_embd.weight")?, GgmlDType::Q6K);
+        let recipe = Recipe::new(Quantization::Q8_0, names.into_iter(), &[])?;
+        assert_eq!(recipe.dtype("token_embd.weight")?, GgmlDType::Q8_0);
+        Ok(())
+    }
+
+    #[test]
+    fn override_precedence() -> Result<()> {
+        let recipe = recipe(Quantization::Q4kM, &[r"blk\.0\..*=q8_0", "ffn_down=q6k"])?;
+        let table = [
+            // The first matching override is used.
+            ("blk.0.ffn_down.weight", GgmlDType::Q8_0),
+            ("blk.0.ffn_up.weight", GgmlDType::Q8_0),
+            ("blk.5.ffn_down.weight", GgmlDType::Q6K),
+            // The other tensors use the mixture.
+            ("blk.5.attn_v.weight", GgmlDType::Q4K),
+            ("blk.6.attn_v.weight", GgmlDType::Q6K),
+            ("output.weight", GgmlDType::Q6K),
+        ];
+        for (name, expected) in table {
+            assert_eq!(recipe.dtype(name)?, expected, "{name}");
+        }
+        assert!(Recipe::new(
+            Quantization::Q4kM,
+            [].into_iter(),
+            &["ffn_down".to_string()]
+        )
+        .is_err());
+        assert!(Recipe::new(Quantization::Q4kM, [].into_iter(), &["x=q9".to_string()]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//! Per-tensor statistics and comparisons between checkpoints.
+use crate::checkpoint::Checkpoint;
+use candle::{Result, Tensor};
+use std::collections::BTreeSet;
+
+/// The statistics of the finite values of a tensor.
+struct Stats {
+    min: f64,
+    max: f64,
+    mean: f64,
+    std: f64,
+    n_nan: usize,
+    n_inf: usize,
+    histogram: Vec<usize>,
+}
+
+impl Stats {
+    fn new(values: &[f32], bins: usize) -> Self {
+        let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+        let (mut sum, mut sum2, mut n) = (0f64, 0f64, 0usize);
+        let (mut n_nan, mut n_inf) = (0, 0);
+        for &v in values.iter() {
+            if v.is_nan() {
+                n_nan += 1
+            } else if v.is_infinite() {
+                n_inf += 1
+            } else {
+                let v = v as f64;
+                min = min.min(v);
+                max = max.max(v);
+                sum += v;
+                sum2 += v * v;
+                n += 1
+            }
+        }
+        let mean = sum / n.max(1) as f64;
+        let std = (sum2 / n.max(1) as f64 - mean * mean).max(0.).sqrt();
+        let mut histogram = vec![0; bins];
+        if n > 0 && bins > 0 {
+            let width = (max - min) / bins as f64;
+            for &v in values.iter().filter(|v| v.is_finite()) {
+                let bin = if width == 0. {
+                    0
+                } else {
+                    ((v as f64 - min) / width) as usize
+                };
+                histogram[bin.min(bins - 1)] += 1
+            }
+        }
+        Self {
+            min,
+            max,
+            mean,
+            std,
+            n_nan,
+            n_inf,
+            histogram,
+        }
+    }
+}
+
+pub fn run_stats(checkpoint: &mut Checkpoint, names: &[String], bins: usize) -> Result<()> {
+    let names = if names.is_empty() {
+        checkpoint.names()
+    } else {
+        names.to_vec()
+    };
+    for name in names.iter() {
+        let (shape, dtype) = checkpoint.shape_and_dtype(name)?;
+        let values = checkpoint.tensor(name)?.flatten_all()?.to_vec1::<f32>()?;
+        let stats = Stats::new(&values, bins);
+        println!(
+            "{name} [{shape:?}; {dtype}] min {:.6e} max {:.6e} mean {:.6e} std {:.6e} nan {} inf {}",
+            stats.min, stats.max, stats.mean, stats.std, stats.n_nan, stats.n_inf
+        );
+        if bins > 0 && stats.histogram.iter().any(|&c| c > 0) {
+            let width = (stats.max - stats.min) / bins as f64;
+            for (i, count) in stats.histogram.iter().enumerate() {
+                let lo = stats.min + width * i as f64;
+                println!("  [{lo:>12.4e}, {:>12.4e}) {count}", lo + width)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The errors between two tensors with the same shape.
+struct Diff {
+    max_abs: f64,
+    mean_abs: f64,
+    cosine: f64,
+}
+
+impl Diff {
+    fn new(lhs: &Tensor, rhs: &Tensor) -> Result<Self> {
+        let lhs = lhs.flatten_all()?.to_vec1::<f32>()?;
+        let rhs = rhs.flatten_all()?.to_vec1::<f32>()?;
+        let (mut max_abs, mut sum_abs) = (0f64, 0f64);
+        let (mut dot, mut norm_l, mut norm_r) = (0f64, 0f64, 0f64);
+        for (&l, &r) in lhs.iter().zip(rhs.iter()) {
+            let (l, r) = (l as f64, r as f64);
+            let d = (l - r).abs();
+            // NaN values propagate to the reported errors.
+            max_abs = if d.is_nan() || max_abs.is_nan() {
+                f64::NAN
+            } else {
+                max_abs.max(d)
+            };
+            sum_abs += d;
+            dot += l * r;
+            norm_l += l * l;
+            norm_r += r * r;
+        }
+        let cosine = if norm_l == 0. && norm_r == 0. {
+            1.
+        } else {
+            dot / (norm_l.sqrt() * norm_r.sqrt())
+        };
+        Ok(Self {
+            max_abs,
+            mean_abs: sum_abs / lhs.len().max(1) as f64,
+            cosine,
+        })
+    }
+}
+
+/// Compares the tensors with the same names, returns the number of mismatches.
+pub fn run_diff(
+    lhs: &mut Checkpoint,
+    rhs: &mut Checkpoint,
+    tolerance: Option<f64>,
+) -> Result<usize> {
+    let lhs_names = lhs.names().into_iter().collect::<BTreeSet<_>>();
+    let rhs_names = rhs.names().into_iter().collect::<BTreeSet<_>>();
+    let mut mismatches = 0;
+    for name in lhs_names.iter() {
+        if !rhs_names.contains(name) {
+            println!("{name}: only in the first checkpoint");
+            mismatches += 1;
+            continue;
+        }
+        let (lhs_shape, lhs_dtype) = lhs.shape_and_dtype(name)?;
+        let (rhs_shape, rhs_dtype) = rhs.shape_and_dtype(name)?;
+        let dtypes = if lhs_dtype == rhs_dtype {
+            lhs_dtype
+        } else {
+            format!("{lhs_dtype} vs {rhs_dtype}")
+        };
+        if lhs_shape != rhs_shape {
+            println!("{name}: shape mismatch {lhs_shape:?} vs {rhs_shape:?} [{dtypes}]");
+            mismatches += 1;
+            continue;
+        }
+        let diff = Diff::new(&lhs.tensor(name)?, &rhs.tensor(name)?)?;
+        let exceeds = match tolerance {
+            Some(tolerance) => diff.max_abs.is_nan() || diff.max_abs > tolerance,
+            None => false,
+        };
+        if exceeds {
+            mismatches += 1
+        }
+        println!(
+            "{name}: [{lhs_shape:?}; {dtypes}] max abs {:.4e} mean abs {:.4e} cosine {:.6}{}",
+            diff.max_abs,
+            diff.mean_abs,
+            diff.cosine,
+            if exceeds { " EXCEEDS TOLERANCE" } else { "" }
+        );
+    }
+    for name in rhs_names.iter() {
+        if !lhs_names.contains(name) {
+            println!("{name}: only in the second checkpoint");
+            mismatches += 1
+        }
+    }
+    println!("{mismatches} mismatches");
+    Ok(mismatches)
+}